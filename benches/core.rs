@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    hint::black_box,
+    sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}},
+    thread,
+    time::{Duration, Instant},
+};
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+
+use paxos_from_scratch::{
+    Ballot, ProposalId,
+    acceptor::Acceptor,
+    kv::{Command, Kv},
+    ledger::SharedLedger,
+    proposer::Round,
+    wire::Promise,
+};
+
+fn ballot(instance: u64, round: u64, node_id: u64) -> Ballot {
+    let id = ProposalId { round, node_id };
+    Ballot { instance, id, value: Some(format!("value-{}-{}", round, node_id)) }
+}
+
+fn ballots(c: &mut Criterion) {
+    let ballots: Vec<Ballot> = (0..64).map(|i| ballot(1, i % 7, i % 5 + 1)).collect();
+
+    c.bench_function("proposal_id/cmp", |b| {
+        let (x, y) = (ProposalId { round: 7, node_id: 2 }, ProposalId { round: 7, node_id: 3 });
+        b.iter(|| black_box(&x).cmp(black_box(&y)))
+    });
+    c.bench_function("ballot/highest_of_64", |b| {
+        b.iter(|| black_box(&ballots).iter().max_by_key(|ballot| ballot.id))
+    });
+}
+
+fn value_selection(c: &mut Criterion) {
+    let id = ProposalId { round: 9, node_id: 1 };
+    let promises: Vec<Option<Ballot>> = (0..5).map(|i| (i % 2 == 0).then(|| ballot(1, i, i + 1))).collect();
+
+    c.bench_function("round/proposal_5_voters", |b| {
+        b.iter(|| {
+            let mut round = Round::new(1, id, String::from("own"), promises.len());
+            for (from, accepted) in promises.iter().enumerate() {
+                round.promise(from as u64 + 1, accepted.clone(), None);
+            }
+            round.proposal()
+        })
+    });
+}
+
+fn serialization(c: &mut Criterion) {
+    let ballot = ballot(42, 3, 2);
+    let encoded = serde_json::to_string(&ballot).unwrap();
+    let payload = Promise::granted(Some(ballot.clone()));
+    let encoded_payload = serde_json::to_string(&payload).unwrap();
+
+    c.bench_function("ballot/encode", |b| b.iter(|| serde_json::to_string(black_box(&ballot)).unwrap()));
+    c.bench_function("ballot/decode", |b| b.iter(|| serde_json::from_str::<Ballot>(black_box(&encoded)).unwrap()));
+    c.bench_function("promise/decode", |b| {
+        b.iter(|| serde_json::from_str::<Promise>(black_box(&encoded_payload)).unwrap())
+    });
+}
+
+fn acceptor(c: &mut Criterion) {
+    let mut acceptor = Acceptor::default();
+    for instance in 1..=1024 {
+        let ballot = ballot(instance, 1, 1);
+        acceptor.prepare(&ballot).unwrap();
+        acceptor.accept(&ballot).unwrap();
+    }
+
+    c.bench_function("acceptor/prepare_and_accept", |b| {
+        b.iter_batched(
+            || acceptor.clone(),
+            |mut acceptor| {
+                let ballot = ballot(512, 2, 1);
+                acceptor.prepare(&ballot).unwrap();
+                acceptor.accept(&ballot).unwrap();
+                acceptor
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn apply(c: &mut Criterion) {
+    let commands: Vec<String> = (0..256)
+        .map(|i| match i % 4 {
+            3 => Command::Delete { key: format!("key-{}", i % 32) },
+            _ => Command::Put { key: format!("key-{}", i % 32), value: format!("value-{}", i) },
+        }.encode())
+        .collect();
+
+    c.bench_function("kv/apply_256", |b| {
+        b.iter(|| {
+            let mut kv = Kv::default();
+            for command in &commands {
+                kv.apply(black_box(command));
+            }
+            kv
+        })
+    });
+}
+
+/// How long `readers` threads take to `read` `iters` times between them.
+/// Reported per read, this is the wall time a read costs while the others
+/// run alongside it.
+fn contended(readers: u64, iters: u64, read: impl Fn() + Sync) -> Duration {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..readers {
+            scope.spawn(|| (0..iters.div_ceil(readers)).for_each(|_| read()));
+        }
+    });
+    start.elapsed()
+}
+
+/// Reads of a learned instance by a thread per core, while another thread
+/// keeps learning, each learn applying a batch of commands the way
+/// `learn_value` does: once behind the single ledger lock it took before,
+/// once with the shards.
+fn ledger_reads(c: &mut Criterion) {
+    let readers = thread::available_parallelism().map_or(4, |cores| cores.get() as u64).max(4);
+    let commands: Vec<String> = (0..64).map(|i| Command::Put { key: format!("key-{}", i), value: String::from("v") }.encode()).collect();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let locked = Arc::new(Mutex::new(HashMap::from([(1, String::from("a"))])));
+    let learner = thread::spawn({
+        let (locked, commands, stop) = (locked.clone(), commands.clone(), stop.clone());
+        move || {
+            let (mut kv, mut instance) = (Kv::default(), 2);
+            while !stop.load(Ordering::Relaxed) {
+                let mut ledger = locked.lock().unwrap();
+                ledger.insert(instance, String::from("b"));
+                commands.iter().for_each(|command| kv.apply(command));
+                std::mem::drop(ledger);
+                instance += 1;
+            }
+        }
+    });
+    c.bench_function("ledger/get_while_learning_locked", |b| {
+        b.iter_custom(|iters| contended(readers, iters, || {
+            black_box(locked.lock().unwrap().get(black_box(&1)).cloned());
+        }))
+    });
+    stop.store(true, Ordering::Relaxed);
+    learner.join().unwrap();
+    stop.store(false, Ordering::Relaxed);
+
+    let shared = Arc::new(SharedLedger::default());
+    shared.blocking_write().insert(1, String::from("a"));
+    let learner = thread::spawn({
+        let (shared, stop) = (shared.clone(), stop.clone());
+        move || {
+            let (mut kv, mut instance) = (Kv::default(), 2);
+            while !stop.load(Ordering::Relaxed) {
+                let mut ledger = shared.blocking_write();
+                ledger.insert(instance, String::from("b"));
+                commands.iter().for_each(|command| kv.apply(command));
+                std::mem::drop(ledger);
+                instance += 1;
+            }
+        }
+    });
+    c.bench_function("ledger/get_while_learning_sharded", |b| {
+        b.iter_custom(|iters| contended(readers, iters, || {
+            black_box(shared.get(black_box(1)));
+        }))
+    });
+    stop.store(true, Ordering::Relaxed);
+    learner.join().unwrap();
+}
+
+criterion_group!(benches, ballots, value_selection, serialization, acceptor, apply, ledger_reads);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+//! Arbitrary bytes as the body of every peer-facing route.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use paxos_from_scratch_fuzz::{ENDPOINTS, node, post, runtime};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, body)) = data.split_first() else {
+        return;
+    };
+    let path = ENDPOINTS[selector as usize % ENDPOINTS.len()];
+
+    runtime().block_on(async {
+        post(&node(), path, body.to_vec()).await;
+    });
+});
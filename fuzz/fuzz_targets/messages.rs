@@ -0,0 +1,54 @@
+//! Sequences of well-formed but adversarial messages against one node:
+//! arbitrary ballots for a handful of instances, learns that contradict
+//! each other, and joins with made-up ids and addresses.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use paxos_from_scratch::{Ballot, ProposalId};
+use paxos_from_scratch_fuzz::{node, post, runtime};
+use serde_json::json;
+
+#[derive(Arbitrary, Debug)]
+struct Proposal {
+    /// Few instances, so messages keep colliding on the same ones.
+    instance: u8,
+    round: u64,
+    node_id: u64,
+    value: Option<String>,
+}
+
+impl Proposal {
+    fn ballot(&self) -> Ballot {
+        Ballot {
+            instance: u64::from(self.instance % 4),
+            id: ProposalId { round: self.round, node_id: self.node_id },
+            value: self.value.clone(),
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+enum Message {
+    Prepare(Proposal),
+    Accept(Proposal),
+    Learn(Proposal),
+    Join { id: String, addr: String },
+}
+
+fuzz_target!(|messages: Vec<Message>| {
+    let node = node();
+
+    runtime().block_on(async {
+        for message in messages {
+            let (path, body) = match message {
+                Message::Prepare(proposal) => ("/handle-prepare", serde_json::to_vec(&proposal.ballot())),
+                Message::Accept(proposal) => ("/handle-accept", serde_json::to_vec(&proposal.ballot())),
+                Message::Learn(proposal) => ("/handle-learn", serde_json::to_vec(&proposal.ballot())),
+                Message::Join { id, addr } => ("/ping", serde_json::to_vec(&json!({ "id": id, "addr": addr }))),
+            };
+            post(&node, path, body.unwrap()).await;
+        }
+    });
+});
@@ -0,0 +1,53 @@
+//! Shared setup for the fuzz targets: a fresh node with no network, and a
+//! way to hand it a raw request.
+
+use std::{net::SocketAddr, sync::{Arc, OnceLock}};
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode, header::CONTENT_TYPE},
+};
+use futures::future::BoxFuture;
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+use paxos_from_scratch::{
+    AppState, Node, router,
+    transport::{Reply, Transport},
+};
+
+/// Every route a peer can reach with a payload.
+pub const ENDPOINTS: [&str; 4] = ["/handle-prepare", "/handle-accept", "/handle-learn", "/ping"];
+
+#[derive(Debug)]
+struct Unreachable;
+
+impl Transport for Unreachable {
+    fn post(&self, _addr: SocketAddr, path: &str, _body: String) -> BoxFuture<'static, Result<Reply, String>> {
+        let error = format!("fuzz: {} has nowhere to go", path);
+        Box::pin(async move { Err(error) })
+    }
+}
+
+pub fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap())
+}
+
+pub fn node() -> Router {
+    let node = Node::new(1, SocketAddr::from(([127, 0, 0, 1], 3000)));
+    router(AppState::new(node, Arc::new(Unreachable)))
+}
+
+pub async fn post(router: &Router, path: &str, body: Vec<u8>) -> StatusCode {
+    let request = Request::builder()
+        .method("POST")
+        .uri(path)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap();
+
+    let status = router.clone().oneshot(request).await.unwrap().status();
+    assert!(!status.is_server_error(), "{} answered {}", path, status);
+    status
+}
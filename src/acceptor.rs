@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+
+use crate::{Ballot, ProposalId};
+
+/// Promise and accepted proposal for a single, not yet learned, instance.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AcceptorSlot {
+    pub last_ballot_number: ProposalId,
+    pub accepted_proposal: Option<Ballot>,
+}
+
+/// A promise for every instance from `from` up to, not including, `to`,
+/// given once to a leader preparing them ahead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RangePromise {
+    pub from: u64,
+    pub to: u64,
+    pub id: ProposalId,
+}
+
+impl RangePromise {
+    pub fn covers(&self, instance: u64) -> bool {
+        (self.from..self.to).contains(&instance)
+    }
+}
+
+/// Slots are kept ordered so that the whole acceptor can be hashed, which
+/// the model checker needs to tell states apart.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Acceptor {
+    pub slots: BTreeMap<u64, AcceptorSlot>,
+    /// Promises for ranges of instances, which count for every slot in
+    /// them without one being kept per instance.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ranges: Vec<RangePromise>,
+}
+
+impl Acceptor {
+    /// Phase 1b. Promises to ignore anything lower than `ballot.id` and hands
+    /// back whatever was already accepted for the instance, or returns the
+    /// higher promise that made us refuse.
+    pub fn prepare(&mut self, ballot: &Ballot) -> Result<Option<Ballot>, ProposalId> {
+        let promised = self.promised(ballot.instance);
+        if ballot.id <= promised {
+            return Err(promised);
+        }
+
+        let slot = self.slots.entry(ballot.instance).or_default();
+        slot.last_ballot_number = ballot.id;
+        Ok(slot.accepted_proposal.clone())
+    }
+
+    /// Phase 1b for every instance in `range` at once. Hands back what was
+    /// accepted in it, or the highest promise in it that made us refuse.
+    pub fn prepare_range(&mut self, range: RangePromise) -> Result<Vec<Ballot>, ProposalId> {
+        let highest = self.slots.range(range.from..range.to).map(|(_, slot)| slot.last_ballot_number)
+            .chain(self.ranges.iter().filter(|other| other.from < range.to && range.from < other.to).map(|other| other.id))
+            .max();
+        if let Some(highest) = highest.filter(|&highest| range.id <= highest) {
+            return Err(highest);
+        }
+
+        self.promise_range(range);
+        Ok(self.slots.range(range.from..range.to).filter_map(|(_, slot)| slot.accepted_proposal.clone()).collect())
+    }
+
+    /// Records `range` as promised, dropping the ranges it outbids all of.
+    pub fn promise_range(&mut self, range: RangePromise) {
+        self.ranges.retain(|other| !(range.from <= other.from && other.to <= range.to && other.id <= range.id));
+        self.ranges.push(range);
+    }
+
+    /// The highest promise that counts for `instance`.
+    pub fn promised(&self, instance: u64) -> ProposalId {
+        let slot = self.slots.get(&instance).map(|slot| slot.last_ballot_number).unwrap_or_default();
+        self.ranges.iter().filter(|range| range.covers(instance)).map(|range| range.id).fold(slot, ProposalId::max)
+    }
+
+    /// The highest promise it made, in any instance.
+    pub fn highest(&self) -> ProposalId {
+        self.slots.values().map(|slot| slot.last_ballot_number)
+            .chain(self.ranges.iter().map(|range| range.id))
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Phase 2b. Accepts unless we already promised a higher proposal.
+    pub fn accept(&mut self, ballot: &Ballot) -> Result<(), ProposalId> {
+        let promised = self.promised(ballot.instance);
+        if ballot.id < promised {
+            return Err(promised);
+        }
+
+        let slot = self.slots.entry(ballot.instance).or_default();
+        slot.last_ballot_number = ballot.id;
+        slot.accepted_proposal = Some(ballot.clone());
+        Ok(())
+    }
+
+    /// Once an instance is learned the ledger answers for it, so the
+    /// acceptor state can go.
+    pub fn forget(&mut self, instance: u64) {
+        self.slots.remove(&instance);
+    }
+
+    /// Drops the range promises that end at `instance`, once `learned` says
+    /// every instance in them is, as the ledger then answers for them all.
+    pub fn forget_ranges(&mut self, instance: u64, learned: impl Fn(u64) -> bool) {
+        self.ranges.retain(|range| range.to != instance + 1 || !(range.from..range.to).all(&learned));
+    }
+}
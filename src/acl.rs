@@ -0,0 +1,263 @@
+//! Access control lists on the key space.
+//!
+//! With `--acl`, every client request names its client with an
+//! `Authorization: Bearer <token>` header, and the node checks what that
+//! client may do before it proposes anything: a [`Grant`] lists key
+//! prefixes and the operations allowed under each. A request without a
+//! known token gets a `401`, one the grant doesn't cover a `403`.
+//!
+//! The table is replicated like everything else: grants are KV entries
+//! under [`PREFIX`], keyed by the SHA-256 of their token, so they are
+//! chosen, logged, snapshotted and backed up with the data they guard, and
+//! every node checks a token with a single lookup. Only `/admin/acl` writes
+//! them; the KV API refuses keys under the prefix whether ACLs are on or
+//! not, so no one can plant a grant before they are. The first grant can
+//! be made by anyone, and every change after that needs an admin's token.
+//!
+//! Views that show values whatever their key, such as `/ledger`, `/events`
+//! or the audit chain, go through [`admins`]: with ACLs on, only an admin's
+//! token reads them.
+//!
+//! Peers are trusted to have checked what they forward; that's what
+//! `--signing-key` is for.
+
+use axum::{
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    AppState, Value,
+    barrier,
+    hex,
+    history::Function,
+    input::{Json, Path},
+    kv::{self, Command},
+    lease,
+    namespace,
+    quota::Quota,
+    schema,
+    secrets,
+    topic,
+    txn,
+};
+
+/// Where the table is kept in the KV store.
+pub const PREFIX: &str = "__acl/";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Read,
+    Write,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rule {
+    /// Keys starting with this; empty for every key.
+    pub prefix: String,
+    pub ops: Vec<Op>,
+}
+
+/// What the holder of a token may do.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Grant {
+    pub client: String,
+    /// Whether it may change the table.
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// What it may commit; see `quota`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<Quota>,
+}
+
+impl Grant {
+    pub fn allows(&self, op: Op, key: &str) -> bool {
+        self.rules.iter().any(|rule| key.starts_with(&rule.prefix) && rule.ops.contains(&op))
+    }
+}
+
+/// A grant as `POST /admin/acl` takes it, with the token in the clear.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewGrant {
+    pub token: String,
+    #[serde(flatten)]
+    pub grant: Grant,
+}
+
+/// The key the grant for `token` is kept under.
+pub fn token_key(token: &str) -> String {
+    format!("{}{}", PREFIX, hex::encode(&Sha256::digest(token.as_bytes())))
+}
+
+/// Whether `key` is only written through `/admin/acl`, `namespace`,
+/// `schema`, `txn`, `lease`, `barrier` or `topic`.
+pub fn is_reserved(key: &str) -> bool {
+    [PREFIX, namespace::PREFIX, schema::PREFIX, txn::PREFIX, lease::PREFIX, barrier::PREFIX, topic::PREFIX].iter().any(|prefix| key.starts_with(prefix))
+}
+
+type Refusal = (StatusCode, String);
+
+pub(crate) fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers.get(AUTHORIZATION)?.to_str().ok()?.strip_prefix("Bearer ").map(str::trim)
+}
+
+async fn lookup(state: &AppState, headers: &HeaderMap) -> Option<Grant> {
+    let key = token_key(bearer(headers)?);
+    serde_json::from_str(state.kv.lock().await.get(&key)?).ok()
+}
+
+/// The grant of whoever sent `headers`, or none with ACLs off.
+pub async fn authenticate(state: &AppState, headers: &HeaderMap) -> Result<Option<Grant>, Refusal> {
+    if !state.acl {
+        return Ok(None);
+    }
+    match lookup(state, headers).await {
+        Some(grant) => Ok(Some(grant)),
+        None => Err((StatusCode::UNAUTHORIZED, String::from("Node needs the token of a known client!"))),
+    }
+}
+
+/// Whoever sent `headers` may do `op` on `key`; their grant, with ACLs on.
+pub async fn check(state: &AppState, headers: &HeaderMap, op: Op, key: &str) -> Result<Option<Grant>, Refusal> {
+    if is_reserved(key) {
+        return Err(reserved());
+    }
+    authorize(state, headers, op, key).await
+}
+
+fn reserved() -> Refusal {
+    (StatusCode::BAD_REQUEST, format!("Keys under {}, {}, {}, {}, {}, {} and {} are reserved!", PREFIX, namespace::PREFIX, schema::PREFIX, txn::PREFIX, lease::PREFIX, barrier::PREFIX, topic::PREFIX))
+}
+
+/// Whoever sent `headers` may do `op` on what the grants know as `key`,
+/// reserved or not.
+pub async fn authorize(state: &AppState, headers: &HeaderMap, op: Op, key: &str) -> Result<Option<Grant>, Refusal> {
+    match authenticate(state, headers).await? {
+        Some(grant) if !grant.allows(op, key) => {
+            println!("[acl] Node {} refused {:?} on {} to client {}", state.node.id, op, key, grant.client);
+            Err((StatusCode::FORBIDDEN, format!("Client {} may not {:?} {}!", grant.client, op, key)))
+        },
+        grant => Ok(grant),
+    }
+}
+
+/// Whoever sent `headers` may propose `value`: a KV command is checked
+/// like the KV API would, anything else only needs a known client. Expiry
+/// is `namespace`'s alone, votes `txn`'s, and leases, barriers and topics
+/// their modules'.
+pub async fn check_value(state: &AppState, headers: &HeaderMap, value: &Value) -> Result<Option<Grant>, Refusal> {
+    match Command::parse(value) {
+        Some(Command::Put { key, .. }) => check(state, headers, Op::Write, &key).await,
+        Some(Command::Delete { key }) => check(state, headers, Op::Delete, &key).await,
+        Some(Command::Expire { .. } | Command::Prepare { .. } | Command::Refuse { .. } | Command::Decide { .. } | Command::Lease { .. } | Command::Enter { .. } | Command::Publish { .. }) => Err(reserved()),
+        None => authenticate(state, headers).await,
+    }
+}
+
+/// Every grant in the table, by the key it is kept under.
+pub async fn grants(state: &AppState) -> Vec<(String, Grant)> {
+    let kv = state.kv.lock().await;
+    let mut grants: Vec<(String, Grant)> = kv.data.iter()
+        .filter(|(key, _)| is_reserved(key))
+        .filter_map(|(key, grant)| Some((key.clone(), serde_json::from_str(grant).ok()?)))
+        .collect();
+    grants.sort_by(|a, b| a.1.client.cmp(&b.1.client).then_with(|| a.0.cmp(&b.0)));
+    grants
+}
+
+/// Whoever sent `headers` may change the table: anyone while it is empty,
+/// an admin or whoever has an admin token after that.
+pub(crate) async fn check_admin(state: &AppState, headers: &HeaderMap) -> Result<(), Refusal> {
+    if secrets::from_admin(state, headers) || grants(state).await.is_empty() {
+        return Ok(());
+    }
+    require_admin(state, headers).await
+}
+
+/// With ACLs on, lets only admins through: what's behind it shows values
+/// under every key, the table's own included. An admin token, see
+/// `secrets`, does as well.
+pub async fn admins(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if secrets::from_admin(&state, request.headers()) {
+        return next.run(request).await;
+    }
+    let refusal = if state.acl {
+        require_admin(&state, request.headers()).await.err()
+    } else {
+        state.admin_token.is_on().then(|| needs_admin(&state))
+    };
+    match refusal {
+        Some(refusal) => refusal.into_response(),
+        None => next.run(request).await,
+    }
+}
+
+pub(crate) fn needs_admin(state: &AppState) -> Refusal {
+    (StatusCode::UNAUTHORIZED, format!("Node {} needs an admin's token!", state.node.id))
+}
+
+async fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<(), Refusal> {
+    match lookup(state, headers).await {
+        Some(grant) if grant.admin => Ok(()),
+        Some(grant) => Err((StatusCode::FORBIDDEN, format!("Client {} isn't an admin!", grant.client))),
+        None => Err(needs_admin(state)),
+    }
+}
+
+pub async fn get_acl(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<Vec<Grant>>, Refusal> {
+    check_admin(&state, &headers).await?;
+    Ok(Json(grants(&state).await.into_iter().map(|(_, grant)| grant).collect()))
+}
+
+pub async fn add_grant(State(state): State<AppState>, headers: HeaderMap, Json(new): Json<NewGrant>) -> (StatusCode, String) {
+    if let Err(refusal) = check_admin(&state, &headers).await {
+        return refusal;
+    }
+    if !new.grant.admin && grants(&state).await.is_empty() {
+        return (StatusCode::BAD_REQUEST, String::from("The first grant must be an admin's, or no one could change the table!"));
+    }
+    if new.token.trim().is_empty() || new.token.trim() != new.token {
+        return (StatusCode::BAD_REQUEST, String::from("The token must be non-empty, without spaces around it!"));
+    }
+
+    let key = token_key(&new.token);
+    let grant = serde_json::to_string(&new.grant).unwrap();
+    let command = Command::Put { key: key.clone(), value: grant.clone() };
+    let (status, body) = kv::write(&state, None, Function::Write, key, Some(grant), command).await;
+    if status.is_success() {
+        println!("[/admin/acl] Node {} granted client {} {:?}", state.node.id, new.grant.client, new.grant.rules);
+    }
+    (status, body)
+}
+
+pub async fn delete_client(State(state): State<AppState>, headers: HeaderMap, Path(client): Path<String>) -> (StatusCode, String) {
+    if let Err(refusal) = check_admin(&state, &headers).await {
+        return refusal;
+    }
+
+    let (revoked, kept): (Vec<_>, Vec<_>) = grants(&state).await.into_iter().partition(|(_, grant)| grant.client == client);
+    if revoked.is_empty() {
+        return (StatusCode::NOT_FOUND, format!("Client {} has no grant!", client));
+    }
+    if !kept.is_empty() && !kept.iter().any(|(_, grant)| grant.admin) {
+        return (StatusCode::CONFLICT, format!("Client {} is the last admin!", client));
+    }
+    let keys: Vec<String> = revoked.into_iter().map(|(key, _)| key).collect();
+    for key in &keys {
+        let (status, body) = kv::write(&state, None, Function::Delete, key.clone(), None, Command::Delete { key: key.clone() }).await;
+        if !status.is_success() {
+            return (status, body);
+        }
+    }
+
+    println!("[/admin/acl] Node {} revoked the {} tokens of client {}", state.node.id, keys.len(), client);
+    (StatusCode::OK, format!("Revoked {} tokens of client {}!", keys.len(), client))
+}
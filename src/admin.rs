@@ -0,0 +1,187 @@
+//! The operator's endpoints under `/admin`.
+//!
+//! Every one of them goes through [`require`]: with `--admin-token` or
+//! `--acl`, only an operator's token gets through; see `secrets`. The ones
+//! that break the node on purpose, injecting faults, partitions and clock
+//! skew, also go through [`debug_only`], and are only served with
+//! `--debug-admin`. `/admin/step` is only served on a node started with
+//! `--step`.
+//!
+//! The endpoints peers call each other on under `/admin` take the cluster
+//! token instead.
+
+use std::{collections::{HashMap, HashSet}, sync::atomic::Ordering};
+use axum::{
+    http::{HeaderMap, StatusCode},
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{AppState, Id, acl, fanout, faults::FaultRule, input::{Json, Path}, secrets, transport::NODE_ID_HEADER};
+
+/// Operator endpoints a node forwards to its peers, with the cluster token.
+const FORWARDED: [&str; 2] = ["/admin/partition", "/admin/heal"];
+
+/// Lets operators through: whoever shows an admin token, or with ACLs on,
+/// an admin's grant (anyone while there is none). A node with neither lets
+/// everyone through.
+pub async fn require(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let forwarded = FORWARDED.contains(&request.uri().path()) && headers.contains_key(NODE_ID_HEADER) && secrets::from_peer(&state, headers);
+    if forwarded || secrets::from_admin(&state, headers) {
+        return next.run(request).await;
+    }
+    let refusal = if state.acl {
+        acl::check_admin(&state, headers).await.err()
+    } else {
+        state.admin_token.is_on().then(|| acl::needs_admin(&state))
+    };
+    match refusal {
+        Some(refusal) => refusal.into_response(),
+        None => next.run(request).await,
+    }
+}
+
+/// Turns away requests to the endpoints that break or hold the node on
+/// purpose, unless it runs with `--debug-admin`.
+pub async fn debug_only(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if state.debug_admin {
+        return next.run(request).await;
+    }
+    let refusal = format!("Node {} only serves {} with --debug-admin!", state.node.id, request.uri().path());
+    (StatusCode::FORBIDDEN, refusal).into_response()
+}
+
+pub async fn get_faults(State(state): State<AppState>) -> (StatusCode, Json<Vec<FaultRule>>) {
+    (StatusCode::OK, Json(state.faults.rules()))
+}
+
+pub async fn add_fault(
+    State(state): State<AppState>,
+    Json(rule): Json<FaultRule>
+) -> Result<(StatusCode, Json<FaultRule>), (StatusCode, Json<HashMap<&'static str, String>>)> {
+    if let Err(e) = rule.validate() {
+        let mut payload = HashMap::new();
+        payload.insert("error", e);
+        return Err((StatusCode::BAD_REQUEST, Json(payload)));
+    }
+
+    let rule = state.faults.add(rule);
+    println!("[/admin/faults] Node {} installed fault rule: {:?}", state.node.id, rule);
+
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+pub async fn delete_fault(State(state): State<AppState>, Path(id): Path<u64>) -> StatusCode {
+    if !state.faults.remove(id) {
+        return StatusCode::NOT_FOUND;
+    }
+
+    println!("[/admin/faults] Node {} removed fault rule {}", state.node.id, id);
+    StatusCode::NO_CONTENT
+}
+
+pub async fn clear_faults(State(state): State<AppState>) -> StatusCode {
+    state.faults.clear();
+    println!("[/admin/faults] Node {} cleared every fault rule", state.node.id);
+    StatusCode::NO_CONTENT
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PartitionPayload {
+    pub groups: Option<Vec<Vec<Id>>>,
+}
+
+pub async fn get_partition(State(state): State<AppState>) -> (StatusCode, Json<PartitionPayload>) {
+    (StatusCode::OK, Json(PartitionPayload { groups: state.faults.partition() }))
+}
+
+pub async fn partition(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<PartitionPayload>
+) -> (StatusCode, Json<HashMap<&'static str, String>>) {
+    let Some(groups) = payload.groups else {
+        let mut payload = HashMap::new();
+        payload.insert("error", String::from("`groups` is required, use /admin/heal to remove a partition"));
+        return (StatusCode::BAD_REQUEST, Json(payload));
+    };
+
+    let mut seen = HashSet::new();
+    if let Some(id) = groups.iter().flatten().find(|&&id| !seen.insert(id)) {
+        let mut payload = HashMap::new();
+        payload.insert("error", format!("Node {} appears in more than one group!", id));
+        return (StatusCode::BAD_REQUEST, Json(payload));
+    }
+
+    apply_partition(&state, &headers, "/admin/partition", Some(groups)).await
+}
+
+pub async fn heal(State(state): State<AppState>, headers: HeaderMap) -> (StatusCode, Json<HashMap<&'static str, String>>) {
+    apply_partition(&state, &headers, "/admin/heal", None).await
+}
+
+/// Applies the partition locally and, when the call came from an operator
+/// rather than a peer, forwards it so one request reshapes the whole cluster.
+async fn apply_partition(
+    state: &AppState,
+    headers: &HeaderMap,
+    path: &str,
+    groups: Option<Vec<Vec<Id>>>,
+) -> (StatusCode, Json<HashMap<&'static str, String>>) {
+    let mut payload = HashMap::new();
+
+    if !headers.contains_key(NODE_ID_HEADER) {
+        let body = PartitionPayload { groups: groups.clone() };
+        let nodes = state.nodes.snapshot();
+        let responses = fanout::post_all(state, &nodes, path, &body).await;
+
+        let unreachable: Vec<String> = nodes.iter()
+            .zip(responses)
+            .filter(|(_, res)| res.as_ref().map_or(true, |res| res.is_error()))
+            .map(|(node, _)| node.id.to_string())
+            .collect();
+
+        if !unreachable.is_empty() {
+            payload.insert("unreachable", unreachable.join(","));
+        }
+    }
+
+    match &groups {
+        None => println!("[{}] Node {} healed the partition", path, state.node.id),
+        Some(groups) => println!("[{}] Node {} partitioned the cluster: {:?}", path, state.node.id, groups),
+    }
+
+    state.faults.set_partition(groups);
+
+    payload.insert("status", String::from("ok"));
+    (StatusCode::OK, Json(payload))
+}
+
+pub const PAUSED: &str = "Node is paused!";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PauseStatus {
+    pub paused: bool,
+}
+
+/// Stops the node from voting and proposing, e.g. for disk maintenance.
+/// It still learns values chosen without it and answers everything else.
+pub async fn pause(State(state): State<AppState>) -> (StatusCode, Json<PauseStatus>) {
+    state.paused.store(true, Ordering::SeqCst);
+    println!("[/admin/pause] Node {} stopped taking part in consensus", state.node.id);
+    (StatusCode::OK, Json(PauseStatus { paused: true }))
+}
+
+pub async fn resume(State(state): State<AppState>) -> (StatusCode, Json<PauseStatus>) {
+    state.paused.store(false, Ordering::SeqCst);
+    println!("[/admin/resume] Node {} is taking part in consensus again", state.node.id);
+    (StatusCode::OK, Json(PauseStatus { paused: false }))
+}
+
+/// The 503 a paused node answers proposals and votes with.
+pub fn refuse_paused() -> (StatusCode, String) {
+    (StatusCode::SERVICE_UNAVAILABLE, String::from(PAUSED))
+}
@@ -0,0 +1,254 @@
+//! Applying learned values to the KV store, off the learn path, in instance
+//! order.
+//!
+//! Learning a value only records it in the ledger and the log, and hands it
+//! to a task of the node's own that applies values one after the other. A
+//! slow apply, a big value or, some day, a state machine on disk, holds up
+//! that task but never an accept or the next learn. `paxos_apply_lag` in
+//! `GET /metrics` is how many learned values it still has to apply.
+//!
+//! Values don't always arrive in order: a learn can overtake the one before
+//! it, or get lost on the way. The task holds a value that arrives ahead of
+//! an instance not learned yet until that one is learned too, so the KV
+//! store only ever shows a prefix of the log, the same on every node. When
+//! [`fill_gaps`] finds the same gap twice in a row, a second apart, the node
+//! asks the voters for the log from there, as a learner does; see `replica`.
+//!
+//! A library embedding the node can register an [`ApplyHook`] with each
+//! group's [`Hooks`], to keep a secondary index, send notifications or
+//! whatever else should follow the log, without forking the crate. The task
+//! calls it after each value is applied, in instance order, with what the
+//! value changed in the KV store, and only then counts the value applied,
+//! so a slow hook holds up applying. A hook that panics is skipped for that
+//! value. Values put in place by a recovery, a transfer or a repair skip
+//! the hooks, which only see what the task applies.
+//!
+//! What needs the KV store to have caught up waits for it: a snapshot, so
+//! it never stamps a log position the KV store hasn't reached, and a KV
+//! write, so a client reads its own writes from the node it wrote to; and
+//! `GET /wait`, which holds a client until a given instance is applied. A
+//! value held behind a gap keeps none of them waiting but `GET /wait`: a
+//! write whose instance lies past a gap shows once the gap is filled.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, RwLock, atomic::{AtomicBool, AtomicU64, Ordering}},
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+use crate::{AppState, Value, kv::Change, replica};
+
+/// How often [`fill_gaps`] looks for values held behind a gap.
+const GAP_EVERY: Duration = Duration::from_secs(1);
+
+/// Something to do after each value is applied.
+pub trait ApplyHook: Send + Sync {
+    /// `value`, learned in `instance`, was just applied, and changed
+    /// `changes` in the KV store, none if it isn't a KV command.
+    fn applied(&self, instance: u64, value: &Value, changes: &[Change]);
+}
+
+/// The hooks a group calls after each value is applied.
+#[derive(Default)]
+pub struct Hooks {
+    hooks: RwLock<Vec<Arc<dyn ApplyHook>>>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks").field("hooks", &self.len()).finish()
+    }
+}
+
+impl Hooks {
+    /// Calls `hook` after every value applied from now on.
+    pub fn register(&self, hook: impl ApplyHook + 'static) {
+        self.hooks.write().unwrap().push(Arc::new(hook));
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn run(&self, state: &AppState, instance: u64, value: &Value, changes: &[Change]) {
+        let hooks = self.hooks.read().unwrap().clone();
+        for hook in hooks {
+            if panic::catch_unwind(AssertUnwindSafe(|| hook.applied(instance, value, changes))).is_err() {
+                println!("[apply] Node {} had a hook panic on instance {}", state.node.id, instance);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// Learned values the task hasn't taken yet, by instance.
+    held: BTreeMap<u64, Value>,
+    /// The task took every instance up to this one.
+    taken: u64,
+}
+
+impl Queue {
+    /// The last instance the task can take without a gap.
+    fn frontier(&self) -> u64 {
+        let mut frontier = self.taken;
+        while self.held.contains_key(&(frontier + 1)) {
+            frontier += 1;
+        }
+        frontier
+    }
+}
+
+/// The node's apply task, which starts with the first value.
+#[derive(Debug, Default)]
+pub struct Applier {
+    queue: Arc<Mutex<Queue>>,
+    started: AtomicBool,
+    wake: Arc<Notify>,
+    learned: AtomicU64,
+    applied: Arc<AtomicU64>,
+    /// Every instance up to this one is applied.
+    index: Arc<AtomicU64>,
+    progress: Arc<Notify>,
+}
+
+impl Applier {
+    /// Queues a value newly learned in `instance`.
+    pub fn push(&self, state: &AppState, instance: u64, value: Value) {
+        self.start(state);
+        self.learned.fetch_add(1, Ordering::SeqCst);
+        self.queue.lock().unwrap().held.insert(instance, value);
+        self.wake.notify_one();
+    }
+
+    fn start(&self, state: &AppState) {
+        if !self.started.swap(true, Ordering::SeqCst) {
+            let task = Task {
+                state: state.clone(),
+                queue: self.queue.clone(),
+                wake: self.wake.clone(),
+                applied: self.applied.clone(),
+                index: self.index.clone(),
+                progress: self.progress.clone(),
+            };
+            tokio::spawn(task.run());
+        }
+    }
+
+    /// Starts over from a KV store that has everything the ledger holds up
+    /// to its first gap applied, and holds what lies past it. Callers hold
+    /// the ledger's writer, and waited for the task to catch up.
+    pub fn restart(&self, state: &AppState) {
+        let chained = state.ledger.chained();
+        let held: BTreeMap<u64, Value> = state.ledger.to_map().into_iter().filter(|(instance, _)| *instance > chained).collect();
+
+        self.start(state);
+        self.learned.store(self.applied.load(Ordering::SeqCst) + held.len() as u64, Ordering::SeqCst);
+        self.index.store(chained, Ordering::SeqCst);
+        *self.queue.lock().unwrap() = Queue { held, taken: chained };
+        self.wake.notify_one();
+        self.progress.notify_waiters();
+    }
+
+    /// Learned values not applied yet, those held behind a gap included.
+    pub fn lag(&self) -> u64 {
+        self.learned.load(Ordering::SeqCst).saturating_sub(self.applied.load(Ordering::SeqCst))
+    }
+
+    /// Every instance up to this one is applied.
+    pub fn index(&self) -> u64 {
+        self.index.load(Ordering::SeqCst)
+    }
+
+    /// The first instance missing before a value the task holds.
+    pub fn gap(&self) -> Option<u64> {
+        let queue = self.queue.lock().unwrap();
+        (!queue.held.is_empty()).then_some(queue.frontier() + 1)
+    }
+
+    /// Waits until everything learned so far is applied, but what is held
+    /// behind a gap. Values learned meanwhile don't keep it waiting.
+    pub async fn caught_up(&self) {
+        let target = self.queue.lock().unwrap().frontier();
+        self.applied(target).await;
+    }
+
+    /// Waits until every instance up to `instance` is learned and applied.
+    pub async fn applied(&self, instance: u64) {
+        loop {
+            let progress = self.progress.notified();
+            tokio::pin!(progress);
+            progress.as_mut().enable();
+
+            if self.index() >= instance {
+                return;
+            }
+            progress.await;
+        }
+    }
+}
+
+struct Task {
+    state: AppState,
+    queue: Arc<Mutex<Queue>>,
+    wake: Arc<Notify>,
+    applied: Arc<AtomicU64>,
+    index: Arc<AtomicU64>,
+    progress: Arc<Notify>,
+}
+
+impl Task {
+    async fn run(self) {
+        loop {
+            let (from, values) = {
+                let mut queue = self.queue.lock().unwrap();
+                let from = queue.taken + 1;
+                let mut values = Vec::new();
+                while let Some(value) = queue.held.remove(&(from + values.len() as u64)) {
+                    values.push(value);
+                }
+                queue.taken += values.len() as u64;
+                (from, values)
+            };
+            if values.is_empty() {
+                self.wake.notified().await;
+                continue;
+            }
+
+            let hooks = &self.state.hooks;
+            for (instance, value) in (from..).zip(&values) {
+                // Hooks run with the store unlocked, so they may read it.
+                let changes = self.state.kv.lock().await.apply_changes(value);
+                self.state.watches.record(instance, &changes);
+                if !hooks.is_empty() {
+                    hooks.run(&self.state, instance, value, &changes);
+                }
+                self.applied.fetch_add(1, Ordering::SeqCst);
+                self.index.store(instance, Ordering::SeqCst);
+            }
+            self.progress.notify_waiters();
+        }
+    }
+}
+
+/// Asks the voters for the instance values are held behind, whenever a gap
+/// outlasts a check.
+pub async fn fill_gaps(state: AppState) {
+    let mut seen = None;
+    loop {
+        tokio::time::sleep(GAP_EVERY).await;
+        let gap = state.applier.gap();
+        if let Some(instance) = gap.filter(|_| gap == seen) {
+            println!("[apply] Node {} is still missing instance {}, asking the voters for it", state.node.id, instance);
+            replica::catch_up(&state).await;
+        }
+        seen = gap;
+    }
+}
@@ -0,0 +1,112 @@
+//! A bound on the client proposals a node takes on at once.
+//!
+//! Every proposal holds its request, its value and a connection for as long
+//! as it runs, and they all wait for the one proposer, so a load spike
+//! would pile up without end. Past `max_in_flight` running proposals new
+//! ones wait in a queue of at most `max_queued`, and past that they get a
+//! `503` straight away. Both are [`Settings`](crate::config::Settings);
+//! a `max_in_flight` of 0 takes any number.
+//!
+//! A proposal in the urgent lane takes a free slot even with others queued
+//! for one; see `priority`.
+
+use std::sync::Mutex;
+use axum::http::StatusCode;
+use tokio::sync::Notify;
+
+use crate::priority::{self, Lane};
+
+pub const OVERLOADED: &str = "Too many proposals in flight, try again later!";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    /// 0 for no limit, and then nothing ever queues.
+    pub max_in_flight: usize,
+    pub max_queued: usize,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    running: usize,
+    queued: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Backpressure {
+    counts: Mutex<Counts>,
+    freed: Notify,
+}
+
+/// A running proposal; dropping it lets the next one in.
+pub struct Slot<'a> {
+    backpressure: &'a Backpressure,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.backpressure.counts.lock().unwrap().running -= 1;
+        self.backpressure.freed.notify_one();
+    }
+}
+
+/// A proposal waiting for a slot, which the queue forgets if its request
+/// goes away.
+struct Waiting<'a> {
+    backpressure: &'a Backpressure,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.backpressure.counts.lock().unwrap().queued -= 1;
+        // It may have taken the wakeup meant for the next one in line.
+        self.backpressure.freed.notify_one();
+    }
+}
+
+fn has_room(counts: &Counts, limits: Limits) -> bool {
+    limits.max_in_flight == 0 || counts.running < limits.max_in_flight
+}
+
+impl Backpressure {
+    /// Waits for a slot under `limits`, or returns `None` with the queue full.
+    pub async fn admit(&self, limits: Limits) -> Option<Slot<'_>> {
+        let urgent = priority::current() == Lane::Urgent;
+        let waiting = {
+            let mut counts = self.counts.lock().unwrap();
+            if (counts.queued == 0 || urgent) && has_room(&counts, limits) {
+                counts.running += 1;
+                return Some(Slot { backpressure: self });
+            }
+            if counts.queued >= limits.max_queued {
+                return None;
+            }
+            counts.queued += 1;
+            Waiting { backpressure: self }
+        };
+
+        loop {
+            self.freed.notified().await;
+
+            let mut counts = self.counts.lock().unwrap();
+            if has_room(&counts, limits) {
+                counts.running += 1;
+                drop(counts);
+                drop(waiting);
+                return Some(Slot { backpressure: self });
+            }
+        }
+    }
+
+    pub fn running(&self) -> usize {
+        self.counts.lock().unwrap().running
+    }
+
+    pub fn queued(&self) -> usize {
+        self.counts.lock().unwrap().queued
+    }
+}
+
+/// The 503 a node answers proposals with once its queue is full.
+pub fn refuse() -> (StatusCode, String) {
+    (StatusCode::SERVICE_UNAVAILABLE, String::from(OVERLOADED))
+}
@@ -0,0 +1,195 @@
+//! Barriers, for a set number of clients to wait for one another.
+//!
+//! `POST /barriers/<name>/enter` with a participant and a count enters the
+//! barrier, and answers once `count` participants have entered it, or
+//! with a `504` after `timeout_ms`, which leaves the participant waiting
+//! for the others. The first participant to enter sets the count: one
+//! that comes with another is turned away with a `409`. Once it is full,
+//! the barrier lets them all through and opens again, empty, for the next
+//! round; `generation` counts the rounds. A participant that enters again
+//! while it waits, say after a `504`, just goes on waiting.
+//!
+//! A participant can enter with the name of a lease it holds, see `lease`,
+//! as its session: it then only counts for as long as the lease lives.
+//! Whichever command comes to the barrier next drops it once the lease
+//! ended, so a client that died doesn't hold the others up.
+//!
+//! Each entry is a [`Command::Enter`] in the node's own log, stamped with
+//! the time the proposer's clock read, and the barrier is kept in the KV
+//! store under [`PREFIX`], so every node comes to the same rounds.
+
+use std::{collections::{BTreeMap, BTreeSet}, time::Duration};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState,
+    acl::{self, Op},
+    input::{Json, Path},
+    intake,
+    kv::{self, Change, Command, Kv},
+    lease,
+    shutdown,
+};
+
+/// Where barriers are kept in the KV store.
+pub const PREFIX: &str = "__barrier/";
+
+/// How long `enter` waits for the others, unless told otherwise.
+const ENTER_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_ENTER_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Barrier {
+    pub name: String,
+    pub count: usize,
+    /// The rounds the barrier let through so far.
+    pub generation: u64,
+    /// Who entered this round.
+    pub waiting: BTreeMap<String, Entry>,
+    /// Who the last round let through.
+    pub passed: BTreeMap<String, Entry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// When the participant entered, in microseconds.
+    pub at: u64,
+    /// The lease it entered with, if any.
+    pub lease: Option<String>,
+}
+
+fn key(name: &str) -> String {
+    format!("{}{}", PREFIX, name)
+}
+
+/// Barrier `name` as `kv` has it.
+pub fn get(kv: &Kv, name: &str) -> Option<Barrier> {
+    serde_json::from_str(kv.get(&key(name))?).ok()
+}
+
+/// Applies `participant` entering barrier `name` for `count` at `at` to
+/// `kv`; this is how every node fills and opens barriers. Answers what it
+/// changed.
+pub(crate) fn apply(kv: &mut Kv, name: &str, participant: String, count: usize, lease: Option<String>, at: u64) -> Vec<Change> {
+    let live = |lease: &Option<String>, participant: &str| match lease {
+        Some(lease) => lease::get(kv, lease).is_some_and(|lease| lease.holder == participant && lease.is_live(at)),
+        None => true,
+    };
+    let mut barrier = get(kv, name).unwrap_or_else(|| Barrier {
+        name: name.to_string(),
+        count,
+        generation: 0,
+        waiting: BTreeMap::new(),
+        passed: BTreeMap::new(),
+    });
+    barrier.waiting.retain(|participant, entry| live(&entry.lease, participant));
+    if barrier.waiting.is_empty() {
+        barrier.count = count;
+    }
+    if count == barrier.count && live(&lease, &participant) {
+        barrier.waiting.entry(participant).or_insert(Entry { at, lease });
+    }
+    if barrier.waiting.len() >= barrier.count {
+        barrier.passed = std::mem::take(&mut barrier.waiting);
+        barrier.generation += 1;
+    }
+
+    let key = key(name);
+    let after = serde_json::to_string(&barrier).unwrap();
+    let before = kv.data.insert(key.clone(), after.clone());
+    if before.as_ref() == Some(&after) {
+        return Vec::new();
+    }
+    vec![Change { key, before, after: Some(after) }]
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Enter {
+    pub participant: String,
+    pub count: usize,
+    /// A lease `participant` holds, to count only while it lives.
+    pub lease: Option<String>,
+    pub timeout_ms: Option<u64>,
+}
+
+/// What `enter` answers once the barrier let the participant through.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Passed {
+    pub name: String,
+    pub generation: u64,
+    pub participants: BTreeSet<String>,
+}
+
+pub async fn enter(State(state): State<AppState>, Path(name): Path<String>, headers: HeaderMap, Json(request): Json<Enter>) -> Response {
+    if request.count == 0 {
+        return (StatusCode::BAD_REQUEST, String::from("A barrier needs a count above 0!")).into_response();
+    }
+    if let Err(refusal) = acl::authorize(&state, &headers, Op::Write, &key(&name)).await {
+        return refusal.into_response();
+    }
+    if let Some(refusal) = kv::refusal(&state) {
+        return refusal.into_response();
+    }
+    let Some(proposal) = state.shutdown.enter() else {
+        return shutdown::refuse().into_response();
+    };
+
+    let participant = request.participant;
+    let at = state.clock.now().wall;
+    let command = Command::Enter { barrier: name.clone(), participant: participant.clone(), count: request.count, lease: request.lease, at };
+    let instance = match intake::submit(&state, command.encode()).await {
+        Ok(instance) => instance,
+        Err(e) => return e.into_response(),
+    };
+    drop(proposal);
+    state.applier.applied(instance).await;
+
+    let Some(entered) = get(&*state.kv.lock().await, &name) else {
+        return (StatusCode::NOT_FOUND, format!("Barrier {} not found", name)).into_response();
+    };
+    let generation = if entered.waiting.contains_key(&participant) {
+        entered.generation
+    } else if entered.passed.get(&participant).is_some_and(|entry| entry.at == at) {
+        entered.generation - 1
+    } else if entered.count != request.count {
+        return (StatusCode::CONFLICT, format!("Barrier {} is for {} participants!", name, entered.count)).into_response();
+    } else {
+        return (StatusCode::CONFLICT, format!("{} doesn't hold the lease it entered barrier {} with!", participant, name)).into_response();
+    };
+
+    let timeout = request.timeout_ms.map_or(ENTER_TIMEOUT, Duration::from_millis).min(MAX_ENTER_TIMEOUT);
+    let passed = tokio::time::timeout(timeout, async {
+        loop {
+            if let Some(barrier) = get(&*state.kv.lock().await, &name).filter(|barrier| barrier.generation > generation) {
+                return barrier;
+            }
+            state.applier.applied(state.applier.index() + 1).await;
+        }
+    });
+    match passed.await {
+        Ok(barrier) if barrier.generation == generation + 1 && !barrier.passed.contains_key(&participant) => {
+            (StatusCode::CONFLICT, format!("{} left barrier {} with its lease!", participant, name)).into_response()
+        },
+        Ok(barrier) if barrier.generation == generation + 1 => {
+            Json(Passed { name, generation: barrier.generation, participants: barrier.passed.into_keys().collect() }).into_response()
+        },
+        // Others went through since; all this one knows is it did too.
+        Ok(_) => Json(Passed { name, generation: generation + 1, participants: BTreeSet::from([participant]) }).into_response(),
+        Err(_) => (StatusCode::GATEWAY_TIMEOUT, format!("Barrier {} didn't fill within {} ms, {} is still waiting!", name, timeout.as_millis(), participant)).into_response(),
+    }
+}
+
+pub async fn get_barrier(State(state): State<AppState>, Path(name): Path<String>, headers: HeaderMap) -> Response {
+    if let Err(refusal) = acl::authorize(&state, &headers, Op::Read, &key(&name)).await {
+        return refusal.into_response();
+    }
+    match get(&*state.kv.lock().await, &name) {
+        Some(barrier) => Json(barrier).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Barrier {} not found", name)).into_response(),
+    }
+}
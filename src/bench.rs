@@ -0,0 +1,152 @@
+//! A load generator: `concurrency` clients send proposals to the targets
+//! round-robin for a fixed duration, and the run is summarized as
+//! throughput, latency percentiles and errors.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug)]
+pub struct BenchConfig {
+    /// `host:port` of every node to send proposals to.
+    pub targets: Vec<String>,
+    pub concurrency: usize,
+    pub duration: Duration,
+}
+
+/// Accepts `500ms`, `30s`, `2m` or a bare number of seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration: {:?}", s))?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        _ => Err(format!("invalid duration unit {:?}, use ms, s or m", unit)),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BenchReport {
+    pub elapsed: Duration,
+    /// Latencies of the successful proposals, sorted.
+    pub latencies: Vec<Duration>,
+    /// Failed proposals, by HTTP status or transport error.
+    pub errors: BTreeMap<String, usize>,
+}
+
+impl BenchReport {
+    pub fn ok(&self) -> usize {
+        self.latencies.len()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.errors.values().sum()
+    }
+
+    pub fn throughput(&self) -> f64 {
+        self.ok() as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        let total = self.ok() + self.failed();
+        if total == 0 {
+            return 0.0;
+        }
+        self.failed() as f64 / total as f64
+    }
+
+    /// Nearest-rank percentile of the successful proposals.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let rank = ((p / 100.0) * self.latencies.len() as f64).ceil() as usize;
+        Some(self.latencies[rank.clamp(1, self.latencies.len()) - 1])
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |d: Option<Duration>| d.map_or(String::from("-"), |d| format!("{:.2}ms", d.as_secs_f64() * 1000.0));
+
+        writeln!(f, "Duration:    {:.2}s", self.elapsed.as_secs_f64())?;
+        writeln!(f, "Proposals:   {} ok, {} failed ({:.2}% errors)", self.ok(), self.failed(), self.error_rate() * 100.0)?;
+        writeln!(f, "Throughput:  {:.1} proposals/s", self.throughput())?;
+        writeln!(
+            f,
+            "Latency:     p50 {}  p90 {}  p99 {}  max {}",
+            ms(self.percentile(50.0)), ms(self.percentile(90.0)), ms(self.percentile(99.0)), ms(self.latencies.last().copied()),
+        )?;
+        for (error, count) in &self.errors {
+            writeln!(f, "  {}: {}", error, count)?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn run(config: &BenchConfig) -> BenchReport {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap();
+    let start = Instant::now();
+    let deadline = start + config.duration;
+
+    let clients = (0..config.concurrency).map(|i| {
+        let client = client.clone();
+        let config = config.clone();
+        tokio::spawn(async move { run_client(i, &client, &config, deadline).await })
+    });
+
+    let mut report = BenchReport::default();
+    for (latencies, errors) in futures::future::join_all(clients).await.into_iter().flatten() {
+        report.latencies.extend(latencies);
+        for (error, count) in errors {
+            *report.errors.entry(error).or_default() += count;
+        }
+    }
+
+    report.elapsed = start.elapsed();
+    report.latencies.sort();
+    report
+}
+
+async fn run_client(
+    id: usize,
+    client: &reqwest::Client,
+    config: &BenchConfig,
+    deadline: Instant,
+) -> (Vec<Duration>, BTreeMap<String, usize>) {
+    let mut latencies = Vec::new();
+    let mut errors = BTreeMap::new();
+    let mut op: usize = 0;
+
+    while Instant::now() < deadline && !config.targets.is_empty() {
+        let target = &config.targets[(id + op) % config.targets.len()];
+        let value = format!("bench-{}-{}", id, op);
+        op += 1;
+
+        let started = Instant::now();
+        let result = client.post(format!("http://{}/prepare", target)).body(value).send().await;
+
+        match result {
+            Ok(res) if res.status().is_success() => latencies.push(started.elapsed()),
+            Ok(res) => {
+                let status = res.status().as_u16();
+                let body = res.text().await.unwrap_or_default();
+                let reason: String = body.chars().take(80).collect();
+                *errors.entry(format!("HTTP {}: {}", status, reason)).or_default() += 1;
+            },
+            Err(e) if e.is_timeout() => *errors.entry(String::from("timeout")).or_default() += 1,
+            Err(_) => *errors.entry(String::from("connection error")).or_default() += 1,
+        }
+    }
+
+    (latencies, errors)
+}
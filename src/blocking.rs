@@ -0,0 +1,96 @@
+//! A client for a cluster that blocks, for programs that don't run tokio.
+//!
+//! [`Client`] is `client::Client` on a single-threaded runtime of its own:
+//! each call runs the request to completion on it before returning. Calling
+//! it from inside a runtime panics, as blocking there would; async code
+//! uses `client::Client` instead.
+//!
+//! ```no_run
+//! use paxos_from_scratch::blocking::Client;
+//!
+//! let client = Client::new("localhost:3000");
+//! client.put("x", "1").unwrap();
+//! assert_eq!(client.get("x").unwrap().as_deref(), Some("1"));
+//! ```
+
+use std::{sync::Arc, time::Duration};
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    chain::Link,
+    client::{self, ClientError},
+    status::{NodeState, Status},
+    watch::Page,
+};
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: client::Client,
+    runtime: Arc<Runtime>,
+}
+
+impl Client {
+    /// A client of the node at `node`, a URL such as
+    /// `http://localhost:3000` or just `localhost:3000`.
+    pub fn new(node: &str) -> Self {
+        Self::from(client::Client::new(node))
+    }
+
+    /// The node's URL.
+    pub fn node(&self) -> &str {
+        self.inner.node()
+    }
+
+    /// Proposes `value`, and answers what the node said once it was chosen.
+    pub fn propose(&self, value: &str) -> Result<String, ClientError> {
+        self.runtime.block_on(self.inner.propose(value))
+    }
+
+    /// The value of `key`, if it has one.
+    pub fn get(&self, key: &str) -> Result<Option<String>, ClientError> {
+        self.runtime.block_on(self.inner.get(key))
+    }
+
+    pub fn put(&self, key: &str, value: &str) -> Result<(), ClientError> {
+        self.runtime.block_on(self.inner.put(key, value))
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), ClientError> {
+        self.runtime.block_on(self.inner.delete(key))
+    }
+
+    /// Has the node add the one listening on `port` of the same host to its
+    /// peers, and answers what it said.
+    pub fn connect(&self, port: u16) -> Result<String, ClientError> {
+        self.runtime.block_on(self.inner.connect(port))
+    }
+
+    /// The node's epoch and commit index.
+    pub fn status(&self) -> Result<Status, ClientError> {
+        self.runtime.block_on(self.inner.status())
+    }
+
+    /// Everything the node knows.
+    pub fn state(&self) -> Result<NodeState, ClientError> {
+        self.runtime.block_on(self.inner.state())
+    }
+
+    /// The changes to keys under `prefix` after instance `since`, or after
+    /// what the node applied now; waits up to `timeout` for one.
+    pub fn watch(&self, prefix: &str, since: Option<u64>, timeout: Duration) -> Result<Page, ClientError> {
+        self.runtime.block_on(self.inner.watch(prefix, since, timeout))
+    }
+
+    /// The node's link in its audit chain for `instance`, if it chained
+    /// that far.
+    pub fn link(&self, instance: u64) -> Result<Option<Link>, ClientError> {
+        self.runtime.block_on(self.inner.link(instance))
+    }
+}
+
+impl From<client::Client> for Client {
+    fn from(inner: client::Client) -> Self {
+        let runtime = Builder::new_current_thread().enable_all().build().expect("failed to start the client's runtime");
+        Self { inner, runtime: Arc::new(runtime) }
+    }
+}
@@ -0,0 +1,98 @@
+//! Totally ordered delivery, for libraries that don't want the KV store.
+//!
+//! [`AtomicBroadcast`] is the consensus core seen as atomic broadcast: a
+//! message [`broadcast`](AtomicBroadcast::broadcast) through any node is
+//! [delivered](AtomicBroadcast::deliveries) on every node, once, in the
+//! same order as every other message. Nothing about it is HTTP or the KV
+//! store; a library embedding the node only needs an [`AppState`], and the
+//! messages are whatever strings it likes.
+//!
+//! [`Broadcast`] implements it over a group: a broadcast is a proposal
+//! like any client write, and a delivery is a value the group applied,
+//! handed over by an [`ApplyHook`](crate::apply::ApplyHook). Empty values,
+//! which only fill holes in the log, aren't delivered. A stream delivers
+//! what is applied after it was asked for, so a library that must not miss
+//! any message asks for it before it broadcasts, or reads the ledger for
+//! what came before.
+
+use std::sync::{Arc, Mutex};
+use futures::{future::BoxFuture, stream::{self, BoxStream, StreamExt}};
+use serde::{Serialize, Deserialize};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::{AppState, Value, apply::ApplyHook, intake, kv::Change};
+
+/// A message in the order every node delivers it in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delivery {
+    /// Where it is in the log; deliveries come in increasing instances.
+    pub instance: u64,
+    pub message: Value,
+}
+
+pub trait AtomicBroadcast: Send + Sync {
+    /// Orders `message` with every other one, and answers the instance it
+    /// was delivered in once it is chosen.
+    fn broadcast(&self, message: Value) -> BoxFuture<'_, Result<u64, String>>;
+
+    /// Every message delivered from now on, in order.
+    fn deliveries(&self) -> BoxStream<'static, Delivery>;
+}
+
+/// The open streams of deliveries.
+#[derive(Default)]
+struct Subscribers {
+    senders: Mutex<Vec<UnboundedSender<Delivery>>>,
+}
+
+impl ApplyHook for Subscribers {
+    fn applied(&self, instance: u64, value: &Value, _changes: &[Change]) {
+        if value.is_empty() {
+            return;
+        }
+        let delivery = Delivery { instance, message: value.clone() };
+        self.senders.lock().unwrap().retain(|sender| sender.send(delivery.clone()).is_ok());
+    }
+}
+
+impl ApplyHook for Arc<Subscribers> {
+    fn applied(&self, instance: u64, value: &Value, changes: &[Change]) {
+        self.as_ref().applied(instance, value, changes)
+    }
+}
+
+/// Atomic broadcast over a group's log.
+pub struct Broadcast {
+    state: AppState,
+    subscribers: Arc<Subscribers>,
+}
+
+impl Broadcast {
+    /// Broadcasts through `state`, which is a node or one of its groups.
+    pub fn new(state: AppState) -> Self {
+        let subscribers = Arc::new(Subscribers::default());
+        state.hooks.register(subscribers.clone());
+        Self { state, subscribers }
+    }
+}
+
+impl AtomicBroadcast for Broadcast {
+    fn broadcast(&self, message: Value) -> BoxFuture<'_, Result<u64, String>> {
+        Box::pin(async move {
+            if message.is_empty() {
+                return Err(String::from("Can't broadcast an empty message!"));
+            }
+            let instance = intake::submit(&self.state, message).await?;
+            self.state.applier.applied(instance).await;
+            Ok(instance)
+        })
+    }
+
+    fn deliveries(&self) -> BoxStream<'static, Delivery> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers.senders.lock().unwrap().push(sender);
+        stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|delivery| (delivery, receiver))
+        }).boxed()
+    }
+}
@@ -0,0 +1,469 @@
+//! A tamper-evident ledger, and who voted for each of its values.
+//!
+//! Every learned value is chained to the one before it: for each instance
+//! from 1 up to the first one it hasn't learned, the ledger keeps a SHA-256
+//! over the hash of the instance before and the value itself (see [`link`]).
+//! Changing, dropping or reordering a value changes every hash after it, so
+//! two nodes with the same hash for an instance agree on all of history up
+//! to it, and a node can hash its values again to find out whether any of
+//! them was touched.
+//!
+//! The proposer that got a value chosen also keeps a [`Certificate`] for
+//! it: its proposal and the acceptors that acknowledged it. With
+//! `--signing-key`, each acceptor signs what it accepted (see
+//! [`ack_message`]) in its reply, so a certificate holds up without trusting
+//! the node that kept it. Certificates are logged and snapshotted along with
+//! the values. Peers that only learned the value have none, and ask the
+//! others for theirs when they are audited.
+//!
+//! A repair that replaces a learned value chains everything after it again,
+//! and keeps a [`Rewrite`] saying what the instance was before and who
+//! repaired it; see `repair`.
+//!
+//! `GET /admin/chain` lists the links of a range of instances with their
+//! certificates and rewrites. `GET /admin/chain/verify` hashes the ledger again from
+//! instance 1, and checks the certificate of every instance in the range it
+//! is given; it answers 409 if anything doesn't hold up. Values PBFT orders
+//! with `--byzantine` are chained but not certified.
+//!
+//! A client that trusts no single node can ask for a [`Proof`] of what it
+//! reads, with `GET /kv/<key>?proof=true`, or of any instance, with
+//! `GET /proof/<instance>`: the value chosen, where it sits in the chain and
+//! its certificate. [`Proof::verify`] checks it against the public keys of
+//! the voters, which every node prints as it starts, so a node can only hand
+//! out a proof that a quorum of them signed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::RwLock;
+use axum::{
+    http::{HeaderMap, StatusCode},
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    AppState, Ballot, Id, ProposalId,
+    acl,
+    ed25519::{self, PUBLIC_KEY_BYTES, SIGNATURE_BYTES},
+    hex,
+    fanout,
+    input::{Json, Path, Query},
+    kv::Command,
+    quorum::Quorum,
+    signing::Keys,
+    storage::{self, Record},
+};
+
+pub type Hash = [u8; 32];
+
+/// What the first instance is chained to.
+pub const GENESIS: Hash = [0; 32];
+
+/// The most links `GET /admin/chain` lists at once.
+const MAX_LINKS: u64 = 1000;
+
+/// The hash of `value` learned in `instance`, after an instance hashed to
+/// `prev`.
+pub fn link(prev: &Hash, instance: u64, value: &str) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(prev);
+    hasher.update(instance.to_le_bytes());
+    hasher.update((value.len() as u64).to_le_bytes());
+    hasher.update(value.as_bytes());
+    hasher.finalize().into()
+}
+
+/// The SHA-256 of a value, in hex, which is what certificates name.
+pub fn value_hash(value: &str) -> String {
+    hex::encode(&Sha256::digest(value.as_bytes()))
+}
+
+/// What an acceptor signs when it accepts `value` in `instance` under `id`.
+pub fn ack_message(instance: u64, id: ProposalId, value: &str) -> Vec<u8> {
+    format!("paxos-ack\n{}\n{}.{}\n{}", instance, id.round, id.node_id, value_hash(value)).into_bytes()
+}
+
+/// This node's signature over accepting `ballot`, if it has a key.
+pub fn sign(state: &AppState, ballot: &Ballot) -> Option<String> {
+    let (_, keypair) = state.keys.own()?;
+    let message = ack_message(ballot.instance, ballot.id, ballot.value.as_deref().unwrap_or_default());
+    Some(hex::encode(&keypair.sign(&message)))
+}
+
+/// An acceptor acknowledging a proposal.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Ack {
+    pub from: Id,
+    /// Over [`ack_message`], from an acceptor with a key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// The acceptors that accepted the value chosen in an instance.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Certificate {
+    pub instance: u64,
+    pub id: ProposalId,
+    /// The SHA-256 of the value, in hex.
+    pub value: String,
+    /// How many voters there were when it was chosen.
+    pub voters: usize,
+    /// Their ids by zone, when the quorum was zoned; see `quorum`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<Vec<Id>>,
+    /// What the voters that don't weigh 1 weighed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub weights: BTreeMap<Id, u32>,
+    pub acks: Vec<Ack>,
+}
+
+impl Ack {
+    fn signed_by(&self, key: &[u8; PUBLIC_KEY_BYTES], message: &[u8]) -> bool {
+        self.signature.as_deref()
+            .and_then(hex::decode::<SIGNATURE_BYTES>)
+            .is_some_and(|signature| ed25519::verify(key, message, &signature))
+    }
+}
+
+impl Certificate {
+    /// The quorum it was chosen by.
+    fn chosen_by(&self) -> Quorum {
+        match self.zones.is_empty() {
+            true => Quorum::Majority(self.voters),
+            false => Quorum::Zones(self.zones.clone(), self.weights.clone()),
+        }
+    }
+
+    /// The acceptors whose acks `counts`, if they are a `quorum`.
+    fn quorum(&self, value: &str, quorum: &Quorum, counts: impl Fn(&Ack, &[u8]) -> bool) -> Result<Vec<Id>, String> {
+        if self.value != value_hash(value) {
+            return Err(format!("Instance {} is certified for another value!", self.instance));
+        }
+
+        let message = ack_message(self.instance, self.id, value);
+        let counted: BTreeSet<Id> = self.acks.iter().filter(|ack| counts(ack, &message)).map(|ack| ack.from).collect();
+        if !quorum.is_met(&counted) {
+            return Err(format!("Instance {} has {} good acks, not the {} it needs!", self.instance, counted.len(), quorum));
+        }
+        Ok(counted.into_iter().collect())
+    }
+
+    /// Checks that a quorum accepted `value`. An ack from an acceptor whose
+    /// key `keys` knows counts only with a good signature, and one from any
+    /// other acceptor only while this node doesn't sign either.
+    pub fn check(&self, value: &str, keys: &Keys) -> Result<(), String> {
+        let signing = keys.own().is_some();
+        self.quorum(value, &self.chosen_by(), |ack, message| match keys.of(ack.from) {
+            Some(key) => ack.signed_by(&key, message),
+            None => !signing,
+        })?;
+        Ok(())
+    }
+}
+
+/// The certificates this node kept, by instance.
+#[derive(Debug, Default)]
+pub struct Certificates {
+    by_instance: RwLock<BTreeMap<u64, Certificate>>,
+}
+
+impl Certificates {
+    pub fn get(&self, instance: u64) -> Option<Certificate> {
+        self.by_instance.read().unwrap().get(&instance).cloned()
+    }
+
+    pub fn insert(&self, certificate: Certificate) {
+        self.by_instance.write().unwrap().insert(certificate.instance, certificate);
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_instance.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The certificates of the instances in `from..=to`.
+    pub fn range(&self, from: u64, to: u64) -> Vec<Certificate> {
+        if from > to {
+            return Vec::new();
+        }
+        self.by_instance.read().unwrap().range(from..=to).map(|(_, certificate)| certificate.clone()).collect()
+    }
+
+    pub fn to_map(&self) -> BTreeMap<u64, Certificate> {
+        self.by_instance.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, certificates: BTreeMap<u64, Certificate>) {
+        *self.by_instance.write().unwrap() = certificates;
+    }
+}
+
+/// Keeps `certificate` for its instance, which this node just got chosen.
+pub async fn certify(state: &AppState, certificate: Certificate) {
+    let logged = storage::log(state, Record::Certified { certificate: certificate.clone() });
+    state.certificates.insert(certificate);
+    let _ = storage::durable(state, logged).await;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Range {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl Range {
+    /// The instances asked for, of the `chained` ones.
+    pub(crate) fn bounds(&self, chained: u64) -> (u64, u64) {
+        (self.from.unwrap_or(1).max(1), self.to.unwrap_or(chained).min(chained))
+    }
+}
+
+/// A value a repair replaced, and the link it had.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rewrite {
+    /// The [`value_hash`] of the value replaced.
+    pub was: String,
+    /// The instance's hash before, in hex.
+    pub hash: String,
+    /// The node that sent the repair.
+    pub by: Id,
+}
+
+/// An instance in the chain.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Link {
+    pub instance: u64,
+    /// The hash of the instance before it, in hex.
+    pub prev: String,
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<Certificate>,
+    /// What repairs replaced, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rewrites: Vec<Rewrite>,
+}
+
+impl Link {
+    pub fn of(state: &AppState, instance: u64) -> Option<Self> {
+        let hash = state.ledger.link(instance)?;
+        let prev = state.ledger.link(instance - 1).unwrap_or(GENESIS);
+        Some(Self {
+            instance,
+            prev: hex::encode(&prev),
+            hash: hex::encode(&hash),
+            certificate: state.certificates.get(instance),
+            rewrites: state.ledger.rewrites(instance),
+        })
+    }
+}
+
+pub async fn get_chain(State(state): State<AppState>, Query(range): Query<Range>) -> Json<Vec<Link>> {
+    let (from, to) = range.bounds(state.ledger.chained());
+    let to = to.min(from.saturating_add(MAX_LINKS - 1));
+    Json((from..=to).filter_map(|instance| Link::of(&state, instance)).collect())
+}
+
+/// The certificates a peer kept in a range, for one auditing its chain.
+pub async fn get_certificates(State(state): State<AppState>, Json(range): Json<Range>) -> Json<Vec<Certificate>> {
+    let (from, to) = range.bounds(u64::MAX);
+    Json(state.certificates.range(from, to))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Invalid {
+    pub instance: u64,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Audit {
+    /// The instances chained, from 1; a later one waits for the gap before
+    /// it to be learned.
+    pub chained: u64,
+    /// The hash of the last of them, in hex.
+    pub head: Option<String>,
+    /// The instances whose certificates were checked.
+    pub from: u64,
+    pub to: u64,
+    /// Instances in the range no one kept a certificate for.
+    pub uncertified: Vec<u64>,
+    pub invalid: Vec<Invalid>,
+}
+
+/// Adds the certificates this node lacks in `from..=to` from whichever
+/// peers have them. Each is checked on its own, so no peer is trusted.
+async fn gather(state: &AppState, from: u64, to: u64) -> BTreeMap<u64, Certificate> {
+    let mut certificates: BTreeMap<u64, Certificate> = state.certificates.range(from, to).into_iter()
+        .map(|certificate| (certificate.instance, certificate))
+        .collect();
+    if from > to || certificates.len() as u64 == to - from + 1 {
+        return certificates;
+    }
+
+    let nodes = state.nodes.snapshot();
+    let range = Range { from: Some(from), to: Some(to) };
+    for response in fanout::post_all(state, &nodes, "/admin/certificates", &range).await.into_iter().flatten() {
+        let Ok(theirs) = response.json::<Vec<Certificate>>() else {
+            continue;
+        };
+        for certificate in theirs.into_iter().filter(|certificate| (from..=to).contains(&certificate.instance)) {
+            certificates.entry(certificate.instance).or_insert(certificate);
+        }
+    }
+    certificates
+}
+
+/// Hashes the ledger again and checks the certificates of `range`.
+pub async fn audit(state: &AppState, range: Range) -> Audit {
+    let chained = state.ledger.chained();
+    let (from, to) = range.bounds(chained);
+    let mut invalid = Vec::new();
+
+    let mut prev = GENESIS;
+    for instance in 1..=chained {
+        let (Some(value), Some(hash)) = (state.ledger.get(instance), state.ledger.link(instance)) else {
+            break;
+        };
+        prev = link(&prev, instance, &value);
+        if prev != hash {
+            invalid.push(Invalid { instance, reason: String::from("The chain doesn't match the value learned!") });
+            prev = hash;
+        }
+    }
+
+    let mut certificates = gather(state, from, to).await;
+    let mut uncertified = Vec::new();
+    for instance in from..=to {
+        let Some(value) = state.ledger.get(instance) else {
+            continue;
+        };
+        match certificates.remove(&instance) {
+            None => uncertified.push(instance),
+            Some(certificate) => {
+                if let Err(reason) = certificate.check(&value, &state.keys) {
+                    invalid.push(Invalid { instance, reason });
+                }
+            },
+        }
+    }
+
+    let head = state.ledger.link(chained).map(|hash| hex::encode(&hash));
+    Audit { chained, head, from, to, uncertified, invalid }
+}
+
+/// A value chosen in an instance, and what shows it was.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Proof {
+    pub instance: u64,
+    pub value: String,
+    /// The hash of the instance before it, in hex.
+    pub prev: String,
+    /// The hash of this one, which every node that chained it agrees on.
+    pub hash: String,
+    pub certificate: Certificate,
+}
+
+impl Proof {
+    /// Checks that the value is chained where the proof says, and that a
+    /// quorum of the voters signed it, with `keys` the public keys of the
+    /// voters as the client knows them. Only acks signed with one of those
+    /// count, and a quorum is of the voters the certificate names or of the
+    /// keys, whichever is more, so a node can't make it smaller. That is a
+    /// plain majority even when the certificate names zones or weights, as
+    /// the client has no way to tell the ones a node claims are real.
+    /// Returns the voters that signed.
+    pub fn verify(&self, keys: &BTreeMap<Id, [u8; PUBLIC_KEY_BYTES]>) -> Result<Vec<Id>, String> {
+        let (Some(prev), Some(hash)) = (hex::decode::<32>(&self.prev), hex::decode::<32>(&self.hash)) else {
+            return Err(String::from("The proof's hashes aren't 32 bytes of hex!"));
+        };
+        if link(&prev, self.instance, &self.value) != hash {
+            return Err(format!("The hash of instance {} doesn't cover its value!", self.instance));
+        }
+        if self.certificate.instance != self.instance {
+            return Err(format!("The certificate is for instance {}, not {}!", self.certificate.instance, self.instance));
+        }
+
+        let voters = Quorum::Majority(self.certificate.voters.max(keys.len()));
+        self.certificate.quorum(&self.value, &voters, |ack, message| keys.get(&ack.from).is_some_and(|key| ack.signed_by(key, message)))
+    }
+
+    /// Whether the value chosen wrote `value` to `key`.
+    pub fn writes(&self, key: &str, value: &str) -> bool {
+        matches!(Command::parse(&self.value), Some(Command::Put { key: k, value: v }) if k == key && v == value)
+    }
+}
+
+/// The proof of what `instance` chose, with a certificate from this node
+/// or one of its peers.
+async fn prove(state: &AppState, instance: u64) -> Result<Proof, (StatusCode, String)> {
+    let Some(link) = Link::of(state, instance) else {
+        return Err((StatusCode::NOT_FOUND, format!("Node {} hasn't chained instance {}!", state.node.id, instance)));
+    };
+    let value = state.ledger.get(instance).unwrap_or_default();
+    let certificate = match link.certificate {
+        Some(certificate) => certificate,
+        None => gather(state, instance, instance).await.remove(&instance)
+            .ok_or_else(|| (StatusCode::NOT_FOUND, format!("No node kept a certificate for instance {}!", instance)))?,
+    };
+    Ok(Proof { instance, value, prev: link.prev, hash: link.hash, certificate })
+}
+
+/// Any instance may hold any key, so with ACLs only admins get these.
+pub async fn get_proof(State(state): State<AppState>, Path(instance): Path<u64>, headers: HeaderMap) -> Result<Json<Proof>, (StatusCode, String)> {
+    acl::check_admin(&state, &headers).await?;
+    prove(&state, instance).await.map(Json)
+}
+
+/// A read, and the proof of the write it returns.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProvedRead {
+    pub key: String,
+    pub value: String,
+    pub proof: Proof,
+}
+
+/// Answers a read of `key` with the proof of the last write of the value
+/// it has. Finding that write walks the ledger back from the end, so this
+/// is for checking a value now and then, not for every read.
+pub async fn read(state: &AppState, key: &str) -> Response {
+    // Under the writer, with everything learned applied, the write of the
+    // value read is in the ledger.
+    let (value, instance) = {
+        let ledger = state.ledger.write().await;
+        state.applier.caught_up().await;
+        let Some(value) = state.kv.lock().await.get(key).cloned() else {
+            return (StatusCode::NOT_FOUND, format!("Key {} not found", key)).into_response();
+        };
+        let written = (1..=ledger.last().unwrap_or(0)).rev().find(|&instance| {
+            ledger.get(instance).and_then(|command| Command::parse(&command)).is_some_and(|command| {
+                matches!(command, Command::Put { key: k, value: v } if k == key && v == value)
+            })
+        });
+        (value, written)
+    };
+
+    let Some(instance) = instance else {
+        return (StatusCode::NOT_FOUND, format!("Node {} has no write of key {} left to prove!", state.node.id, key)).into_response();
+    };
+    match prove(state, instance).await {
+        Ok(proof) => Json(ProvedRead { key: key.to_string(), value, proof }).into_response(),
+        Err(refusal) => refusal.into_response(),
+    }
+}
+
+pub async fn verify(State(state): State<AppState>, Query(range): Query<Range>) -> (StatusCode, Json<Audit>) {
+    let audit = audit(&state, range).await;
+
+    if audit.invalid.is_empty() {
+        println!("[/admin/chain/verify] Node {} checked its chain up to instance {}, {} uncertified", state.node.id, audit.chained, audit.uncertified.len());
+        (StatusCode::OK, Json(audit))
+    } else {
+        println!("[/admin/chain/verify] Node {} found instances that don't hold up: {:?}", state.node.id, audit.invalid);
+        (StatusCode::CONFLICT, Json(audit))
+    }
+}
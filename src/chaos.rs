@@ -0,0 +1,75 @@
+//! An opt-in chaos controller that keeps disturbing a running node.
+//!
+//! On every tick it rolls each action against its rate:
+//!
+//! - **pause**: hold the acceptor and ledger locks for a while, so the node
+//!   stops answering prepares, accepts and learns as if it had stalled.
+//! - **preempt**: there is no leader to step down, so the closest thing is
+//!   running a bare phase 1 on the open instance with a fresh ballot, which
+//!   knocks out whatever proposal is currently in flight there.
+//! - **restart**: throw away the proposer's volatile state, as a restarted
+//!   consensus loop would. Acceptor promises are kept; losing those would be
+//!   a crash without durable storage, not a restart.
+//!
+//! The rates come from the node's [`Settings`](crate::config::Settings), so
+//! a reload can turn chaos on, off, or up without a restart.
+
+use std::time::Duration;
+
+use crate::{AppState, rng::Rng};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChaosConfig {
+    pub interval: Duration,
+    /// Chance of each action per tick, as percentages.
+    pub pause: f64,
+    pub preempt: f64,
+    pub restart: f64,
+    pub max_pause: Duration,
+}
+
+/// How often to look at the settings again while chaos is off.
+const IDLE: Duration = Duration::from_secs(1);
+
+pub async fn run(state: AppState) {
+    let mut rng = Rng::from_entropy(state.node.id);
+    let mut running = None;
+
+    loop {
+        let config = state.settings.read().unwrap().chaos.clone();
+        if config != running {
+            match &config {
+                Some(config) => println!("[chaos] Node {} running chaos: {:?}", state.node.id, config),
+                None => println!("[chaos] Node {} stopped chaos", state.node.id),
+            }
+            running = config.clone();
+        }
+
+        let Some(config) = config else {
+            tokio::time::sleep(IDLE).await;
+            continue;
+        };
+        tokio::time::sleep(config.interval).await;
+
+        if rng.chance(config.pause / 100.0) {
+            let pause = Duration::from_millis(rng.below(config.max_pause.as_millis() as u64 + 1));
+            println!("[chaos] Node {} pausing event processing for {:?}", state.node.id, pause);
+
+            let acceptor = state.acceptor.lock().await;
+            let ledger = state.ledger.write().await;
+            tokio::time::sleep(pause).await;
+            std::mem::drop((ledger, acceptor));
+
+            println!("[chaos] Node {} resuming event processing", state.node.id);
+        }
+
+        if rng.chance(config.preempt / 100.0) && !state.is_paused() {
+            let result = state.proposer.preempt(&state).await;
+            println!("[chaos] Node {} preempted the open instance: {:?}", state.node.id, result.map(|ballot| (ballot.instance, ballot.id)));
+        }
+
+        if rng.chance(config.restart / 100.0) && state.proposer.restart(&state).is_ok() {
+            println!("[chaos] Node {} restarted its proposer", state.node.id);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Large values and snapshots sent in checksummed chunks.
+//!
+//! A value sent whole has to be buffered whole before anyone can tell it
+//! arrived intact, and a snapshot served whole has to be read into memory
+//! first. A body sent as [`CONTENT_TYPE`] is instead a run of chunks, each
+//! a header line with its length and FNV-1a checksum, in hex, and that many
+//! bytes; an empty chunk ends it:
+//!
+//! ```text
+//! 5 a430d84680aabd0b
+//! hello
+//! 0 cbf29ce484222325
+//! ```
+//!
+//! Chunks are checked as they arrive, so a corrupt one fails the transfer
+//! there, without waiting for the rest. `PUT /kv/:key` and `POST /prepare`
+//! take a value in chunks, `GET /kv/:key` answers in chunks when asked to
+//! with `Accept`, and `GET /admin/snapshot` always does, a chunk at a time
+//! out of a map of the snapshot.
+
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{FromRequest, Request},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::consistency::{FNV_OFFSET, fnv1a};
+
+pub const CONTENT_TYPE: &str = "application/vnd.paxos.chunked";
+
+/// How much a node sends per chunk.
+pub const CHUNK_BYTES: usize = 64 * 1024;
+
+/// The largest chunk a node takes, so a bad header can't make it buffer
+/// without end.
+pub const MAX_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+
+/// The largest value a node takes in chunks.
+pub const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+pub fn checksum(data: &[u8]) -> u64 {
+    fnv1a(FNV_OFFSET, data)
+}
+
+/// `data` as one chunk, header included.
+pub fn frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = format!("{:x} {:016x}\n", data.len(), checksum(data)).into_bytes();
+    frame.extend_from_slice(data);
+    frame
+}
+
+/// All of `data` in chunks of `chunk_bytes`, and the empty one that ends it.
+pub fn encode(data: &[u8], chunk_bytes: usize) -> Vec<u8> {
+    let mut body: Vec<u8> = data.chunks(chunk_bytes.max(1)).flat_map(frame).collect();
+    body.extend(frame(&[]));
+    body
+}
+
+/// Takes a chunked body in pieces of any size and checks each chunk once
+/// it has all of it.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    chunks: usize,
+    ended: bool,
+}
+
+impl Decoder {
+    /// Feeds in the next `bytes` of the body, and appends the data of every
+    /// chunk they complete to `out`.
+    pub fn push(&mut self, bytes: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        self.buffer.extend_from_slice(bytes);
+        loop {
+            if self.ended {
+                if !self.buffer.is_empty() {
+                    return Err(String::from("Data after the last chunk!"));
+                }
+                return Ok(());
+            }
+
+            let Some(newline) = self.buffer.iter().position(|&byte| byte == b'\n') else {
+                if self.buffer.len() > 64 {
+                    return Err(format!("Chunk {} has a malformed header!", self.chunks));
+                }
+                return Ok(());
+            };
+            let (len, sum) = std::str::from_utf8(&self.buffer[..newline]).ok()
+                .and_then(parse_header)
+                .ok_or_else(|| format!("Chunk {} has a malformed header!", self.chunks))?;
+            if len > MAX_CHUNK_BYTES {
+                return Err(format!("Chunk {} is {} bytes, more than the {} a node takes!", self.chunks, len, MAX_CHUNK_BYTES));
+            }
+
+            let end = newline + 1 + len;
+            if self.buffer.len() < end {
+                return Ok(());
+            }
+            let data = &self.buffer[newline + 1..end];
+            if checksum(data) != sum {
+                return Err(format!("Chunk {} doesn't match its checksum!", self.chunks));
+            }
+
+            out.extend_from_slice(data);
+            self.buffer.drain(..end);
+            self.chunks += 1;
+            self.ended = len == 0;
+        }
+    }
+
+    /// Whether the body ended where it should have.
+    pub fn finish(&self) -> Result<(), String> {
+        match self.ended {
+            true => Ok(()),
+            false => Err(format!("Body ended after {} chunks, without the last one!", self.chunks)),
+        }
+    }
+}
+
+fn parse_header(header: &str) -> Option<(usize, u64)> {
+    let (len, sum) = header.split_once(' ')?;
+    Some((usize::from_str_radix(len, 16).ok()?, u64::from_str_radix(sum, 16).ok()?))
+}
+
+/// Decodes a whole chunked `body`, at most `limit` bytes of data.
+pub async fn decode(body: Body, limit: usize) -> Result<Vec<u8>, String> {
+    let mut decoder = Decoder::default();
+    let mut data = Vec::new();
+    let mut body = body.into_data_stream();
+    while let Some(bytes) = body.next().await {
+        decoder.push(&bytes.map_err(|e| e.to_string())?, &mut data)?;
+        if data.len() > limit {
+            return Err(format!("Value is more than the {} bytes a node takes!", limit));
+        }
+    }
+    decoder.finish()?;
+    Ok(data)
+}
+
+/// Frames whatever `reader` reads as it is read, so none of it needs to be
+/// in memory at once.
+pub fn stream(reader: impl AsyncRead + Send + Unpin + 'static) -> Body {
+    let frames = futures::stream::unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        let mut chunk = vec![0; CHUNK_BYTES];
+        match reader.read(&mut chunk).await {
+            Err(e) => Some((Err(e), None)),
+            Ok(read) => {
+                chunk.truncate(read);
+                Some((Ok(frame(&chunk)), (read > 0).then_some(reader)))
+            },
+        }
+    });
+    Body::from_stream(frames)
+}
+
+/// A response of `reader`'s bytes in chunks.
+pub fn respond(reader: impl AsyncRead + Send + Unpin + 'static) -> Response {
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], stream(reader)).into_response()
+}
+
+/// Whether the client asked for a chunked answer.
+pub fn accepts(headers: &HeaderMap) -> bool {
+    headers.get(header::ACCEPT).and_then(|accept| accept.to_str().ok()).is_some_and(|accept| accept.contains(CONTENT_TYPE))
+}
+
+fn is_chunked(headers: &HeaderMap) -> bool {
+    headers.get(header::CONTENT_TYPE).is_some_and(|content_type| content_type == CONTENT_TYPE)
+}
+
+/// A value sent in chunks, or whole like before.
+pub struct Upload(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for Upload {
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Response> {
+        if !is_chunked(request.headers()) {
+            return String::from_request(request, state).await.map(Upload).map_err(IntoResponse::into_response);
+        }
+
+        let refuse = |e: String| (StatusCode::BAD_REQUEST, e).into_response();
+        let data = decode(request.into_body(), MAX_UPLOAD_BYTES).await.map_err(refuse)?;
+        String::from_utf8(data).map(Upload).map_err(|_| refuse(String::from("Value isn't UTF-8!")))
+    }
+}
@@ -0,0 +1,128 @@
+//! A client for a cluster's HTTP API.
+//!
+//! [`Client`] talks to one node: it proposes values, reads and writes keys
+//! and asks how far the node got, and turns what the node answers into
+//! types. The `client` subcommand is this from the command line, so no one
+//! has to craft requests against the node's routes by hand:
+//!
+//! ```sh
+//! cargo run -- client --node http://localhost:3000 propose hello
+//! cargo run -- client get x
+//! ```
+
+use std::time::Duration;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use crate::{chain::Link, status::{NodeState, Status}, watch::Page};
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Failed to reach {node}: {reason}")]
+    Unreachable { node: String, reason: String },
+    /// The node answered, and turned the request down.
+    #[error("{status}: {message}")]
+    Refused { status: StatusCode, message: String },
+    #[error("{node} answered with something else than expected: {reason}")]
+    Unexpected { node: String, reason: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    node: String,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// A client of the node at `node`, a URL such as
+    /// `http://localhost:3000` or just `localhost:3000`.
+    pub fn new(node: &str) -> Self {
+        let node = node.trim_end_matches('/');
+        let node = if node.contains("://") { node.to_string() } else { format!("http://{}", node) };
+        Self { node, http: reqwest::Client::new() }
+    }
+
+    /// The node's URL.
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// The host in the node's URL.
+    pub fn host(&self) -> String {
+        reqwest::Url::parse(&self.node).ok().and_then(|url| url.host_str().map(str::to_string)).unwrap_or_else(|| String::from("localhost"))
+    }
+
+    /// Proposes `value`, and answers what the node said once it was chosen.
+    pub async fn propose(&self, value: &str) -> Result<String, ClientError> {
+        self.send(self.http.post(self.url("/prepare")).body(value.to_string())).await
+    }
+
+    /// The value of `key`, if it has one.
+    pub async fn get(&self, key: &str) -> Result<Option<String>, ClientError> {
+        match self.send(self.http.get(self.url(&format!("/kv/{}", key)))).await {
+            Err(ClientError::Refused { status: StatusCode::NOT_FOUND, .. }) => Ok(None),
+            result => result.map(Some),
+        }
+    }
+
+    pub async fn put(&self, key: &str, value: &str) -> Result<(), ClientError> {
+        self.send(self.http.put(self.url(&format!("/kv/{}", key))).body(value.to_string())).await.map(drop)
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), ClientError> {
+        self.send(self.http.delete(self.url(&format!("/kv/{}", key)))).await.map(drop)
+    }
+
+    /// Has the node add the one listening on `port` of the same host to its
+    /// peers, and answers what it said; see `joins`.
+    pub async fn connect(&self, port: u16) -> Result<String, ClientError> {
+        self.send(self.http.post(self.url("/connect")).body(port.to_string())).await
+    }
+
+    /// The node's epoch and commit index; see `status`.
+    pub async fn status(&self) -> Result<Status, ClientError> {
+        self.json(self.http.get(self.url("/status"))).await
+    }
+
+    /// Everything the node knows; see `status`.
+    pub async fn state(&self) -> Result<NodeState, ClientError> {
+        self.json(self.http.get(self.url("/state"))).await
+    }
+
+    /// The changes to keys under `prefix` after instance `since`, or after
+    /// what the node applied now; waits up to `timeout` for one. See `watch`.
+    pub async fn watch(&self, prefix: &str, since: Option<u64>, timeout: Duration) -> Result<Page, ClientError> {
+        let mut query = vec![("prefix", prefix.to_string()), ("timeout_ms", timeout.as_millis().to_string())];
+        query.extend(since.map(|since| ("since", since.to_string())));
+        self.json(self.http.get(self.url("/watch")).query(&query)).await
+    }
+
+    /// The node's link in its audit chain for `instance`, if it chained
+    /// that far; see `chain`.
+    pub async fn link(&self, instance: u64) -> Result<Option<Link>, ClientError> {
+        let links: Vec<Link> = self.json(self.http.get(self.url(&format!("/admin/chain?from={}&to={}", instance, instance)))).await?;
+        Ok(links.into_iter().next())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.node, path)
+    }
+
+    /// Sends `request`, and answers the body of a successful reply.
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<String, ClientError> {
+        let unreachable = |e: reqwest::Error| ClientError::Unreachable { node: self.node.clone(), reason: e.to_string() };
+        let response = request.send().await.map_err(unreachable)?;
+        let status = response.status();
+        let body = response.text().await.map_err(unreachable)?;
+        if status.is_client_error() || status.is_server_error() {
+            return Err(ClientError::Refused { status, message: body });
+        }
+        Ok(body)
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> Result<T, ClientError> {
+        let body = self.send(request).await?;
+        serde_json::from_str(&body).map_err(|e| ClientError::Unexpected { node: self.node.clone(), reason: e.to_string() })
+    }
+}
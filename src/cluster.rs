@@ -0,0 +1,222 @@
+//! Whole clusters, for scripting scenarios against.
+//!
+//! [`ClusterBuilder`] starts a cluster of connected nodes inside the calling
+//! process and hands back a [`NodeHandle`] for each, which sends client
+//! requests to its node and reaches into its state:
+//!
+//! ```no_run
+//! # async fn example() -> std::io::Result<()> {
+//! use paxos_from_scratch::cluster::{ClusterBuilder, Network::InMemory};
+//!
+//! let cluster = ClusterBuilder::new().nodes(5).transport(InMemory).start().await?;
+//! cluster.node(0).put("x", "1").await;
+//! cluster.settle().await;
+//! assert_eq!(cluster.node(4).get("/kv/x").await.body, "1");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Over [`Network::InMemory`] the nodes talk through a [`Sim`], so a seed
+//! fixes what happens and [`Cluster::sim`] partitions or drops messages.
+//! Over [`Network::Http`] each node listens on a port of the loopback
+//! interface and talks to its peers over HTTP, as it does in production.
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+use axum::Router;
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::{
+    AppState, Id, router,
+    client::Client,
+    hlc::Hlc,
+    sim::{self, Sim, SimConfig},
+    transport::{HttpTransport, Reply},
+};
+
+/// How the nodes of a cluster reach each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Network {
+    /// Through a simulated network inside the process; see `sim`.
+    #[default]
+    InMemory,
+    /// Over HTTP, each node on a port of the loopback interface.
+    Http,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClusterBuilder {
+    config: SimConfig,
+    seed: u64,
+    network: Network,
+}
+
+impl ClusterBuilder {
+    /// Three voters talking in memory, with seed 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn nodes(self, nodes: usize) -> Self {
+        Self { config: SimConfig { nodes, ..self.config }, ..self }
+    }
+
+    /// How many of the last nodes only learn; see `replica`.
+    pub fn learners(self, learners: usize) -> Self {
+        Self { config: SimConfig { learners, ..self.config }, ..self }
+    }
+
+    /// Paxos groups each node splits the keys between; see `shards`.
+    pub fn shards(self, shards: usize) -> Self {
+        Self { config: SimConfig { shards, ..self.config }, ..self }
+    }
+
+    /// Shapes the cluster as `config` says, down to the simulated network's
+    /// faults; its `nodes`, `learners` and `shards` replace what was set.
+    pub fn config(self, config: SimConfig) -> Self {
+        Self { config, ..self }
+    }
+
+    /// The seed of the simulated network's faults.
+    pub fn seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+
+    pub fn transport(self, network: Network) -> Self {
+        Self { network, ..self }
+    }
+
+    /// Starts every node, each knowing all the others.
+    pub async fn start(self) -> io::Result<Cluster> {
+        match self.network {
+            Network::InMemory => {
+                let sim = Sim::new(self.seed, self.config);
+                let nodes = (0..sim.size()).map(|index| NodeHandle::new(sim.node(index).clone(), None)).collect();
+                Ok(Cluster { nodes, sim: Some(sim), servers: Vec::new() })
+            },
+            Network::Http => {
+                let mut listeners = Vec::new();
+                for _ in 0..self.config.nodes {
+                    listeners.push(TcpListener::bind("127.0.0.1:0").await?);
+                }
+                let members = listeners.iter().enumerate()
+                    .map(|(index, listener)| Ok(sim::member(&self.config, index, listener.local_addr()?)))
+                    .collect::<io::Result<Vec<_>>>()?;
+
+                let mut cluster = Cluster { nodes: Vec::new(), sim: None, servers: Vec::new() };
+                for (node, listener) in members.iter().zip(listeners) {
+                    let clock = Arc::new(Hlc::default());
+                    let mut state = AppState::new(node.clone(), Arc::new(HttpTransport::new(node.id).with_clock(clock.clone())));
+                    state.clock = clock;
+                    let state = sim::with_peers(state, &members, &self.config);
+                    let app = router(state.clone()).into_make_service_with_connect_info::<SocketAddr>();
+                    cluster.servers.push(tokio::spawn(async move {
+                        let _ = axum::serve(listener, app).await;
+                    }));
+                    cluster.nodes.push(NodeHandle::new(state, Some(node.addr)));
+                }
+                Ok(cluster)
+            },
+        }
+    }
+}
+
+/// A started cluster. Dropping it stops the nodes' servers.
+pub struct Cluster {
+    nodes: Vec<NodeHandle>,
+    sim: Option<Sim>,
+    servers: Vec<JoinHandle<()>>,
+}
+
+impl Cluster {
+    pub fn size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The node at `index`; node `index` has id `index + 1`.
+    pub fn node(&self, index: usize) -> &NodeHandle {
+        &self.nodes[index]
+    }
+
+    pub fn nodes(&self) -> &[NodeHandle] {
+        &self.nodes
+    }
+
+    /// The simulated network of a cluster in memory, to partition it or
+    /// lose its messages.
+    pub fn sim(&self) -> Option<&Sim> {
+        self.sim.as_ref()
+    }
+
+    /// Waits until the nodes sent their peers what they decided and
+    /// applied what they learned, as both happen in the background.
+    pub async fn settle(&self) {
+        if let Some(sim) = &self.sim {
+            return sim.settle().await;
+        }
+        let states: Vec<AppState> = self.nodes.iter().map(|node| node.state.clone()).collect();
+        for _ in 0..1_000 {
+            if sim::settled(&states) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        for server in &self.servers {
+            server.abort();
+        }
+    }
+}
+
+/// One node of a [`Cluster`].
+#[derive(Debug, Clone)]
+pub struct NodeHandle {
+    state: AppState,
+    route: Router,
+    addr: Option<SocketAddr>,
+}
+
+impl NodeHandle {
+    fn new(state: AppState, addr: Option<SocketAddr>) -> Self {
+        Self { route: router(state.clone()), state, addr }
+    }
+
+    pub fn id(&self) -> Id {
+        self.state.node.id
+    }
+
+    /// Everything the node holds, to look into or change behind its back.
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// Where the node listens, over [`Network::Http`].
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// A client of the node, over [`Network::Http`].
+    pub fn client(&self) -> Option<Client> {
+        self.addr.map(|addr| Client::new(&addr.to_string()))
+    }
+
+    /// A client request straight to the node.
+    pub async fn request(&self, path: &str, body: &str) -> Reply {
+        sim::send(self.route.clone(), "POST", None, path, body.to_string()).await
+    }
+
+    pub async fn get(&self, path: &str) -> Reply {
+        sim::send(self.route.clone(), "GET", None, path, String::new()).await
+    }
+
+    pub async fn propose(&self, value: &str) -> Reply {
+        self.request("/prepare", value).await
+    }
+
+    pub async fn put(&self, key: &str, value: &str) -> Reply {
+        sim::send(self.route.clone(), "PUT", None, &format!("/kv/{}", key), value.to_string()).await
+    }
+}
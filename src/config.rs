@@ -0,0 +1,433 @@
+//! Node settings from a file, and reloading them.
+//!
+//! Every option of a node can be given, from lowest to highest precedence,
+//! in a TOML file passed with `--config` (or `PAXOS_CONFIG`), in a `PAXOS_*`
+//! environment variable, or as a flag. Keys in the file are the flag names
+//! with underscores, e.g. `drain_timeout_ms = 5000` for `--drain-timeout-ms`.
+//!
+//! On SIGHUP or `POST /admin/reload` the node reads the file again and
+//! applies the [`Settings`] that can change while it runs; flags and
+//! variables still win over the file. Other options only take effect on a
+//! restart, and the reload says which of them changed. A reload also reads
+//! the cluster token file again; see `secrets`.
+
+use std::{fs, net::{Ipv4Addr, SocketAddrV4}, path::{Path, PathBuf}, sync::Arc, time::Duration};
+use axum::{
+    http::StatusCode,
+    extract::State
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState, Id,
+    backpressure,
+    chaos::ChaosConfig,
+    fanout,
+    input::Json,
+    learns,
+    ratelimit::{Limit, RateLimits},
+    readonly::ReadOnly,
+    secrets,
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    pub id: Option<Id>,
+    pub port: Option<String>,
+    pub history: Option<PathBuf>,
+    pub trace: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    pub signing_key: Option<PathBuf>,
+    pub peer_keys: Option<Vec<String>>,
+    pub encryption_key: Option<PathBuf>,
+    pub encryption_migrate: Option<bool>,
+    pub cluster_token: Option<PathBuf>,
+    pub token_grace_ms: Option<u64>,
+    pub admin_token: Option<PathBuf>,
+    pub debug_admin: Option<bool>,
+    pub byzantine: Option<bool>,
+    pub shards: Option<usize>,
+    pub streams: Option<u64>,
+    pub groups: Option<Vec<String>>,
+    pub learner: Option<bool>,
+    pub zone: Option<String>,
+    pub weight: Option<u32>,
+    pub acl: Option<bool>,
+    pub wal_segment_bytes: Option<u64>,
+    pub wal_group_delay_ms: Option<u64>,
+    pub min_free_bytes: Option<u64>,
+    pub step: Option<bool>,
+    pub read_only: Option<ReadOnly>,
+    pub drain_timeout_ms: Option<u64>,
+    pub snapshot_every: Option<u64>,
+    pub snapshot_interval_ms: Option<u64>,
+    pub max_in_flight: Option<usize>,
+    pub max_queued: Option<usize>,
+    pub max_requests_per_round: Option<usize>,
+    pub max_requests_per_peer: Option<usize>,
+    pub learn_batch_delay_ms: Option<u64>,
+    pub learn_batch_max: Option<usize>,
+    pub learn_batch_fixed: Option<bool>,
+    pub learn_gossip_fanout: Option<usize>,
+    pub log_shipping: Option<bool>,
+    pub learn_multicast: Option<SocketAddrV4>,
+    pub learn_multicast_interface: Option<Ipv4Addr>,
+    pub prepare_ahead: Option<u64>,
+    pub pre_vote_lease_ms: Option<u64>,
+    pub stuck_instance_timeout_ms: Option<u64>,
+    pub chaos: Option<bool>,
+    pub chaos_interval_ms: Option<u64>,
+    pub chaos_pause: Option<f64>,
+    pub chaos_max_pause_ms: Option<u64>,
+    pub chaos_preempt: Option<f64>,
+    pub chaos_restart: Option<f64>,
+    pub rate_limit_client: Option<f64>,
+    pub rate_limit_client_burst: Option<f64>,
+    pub rate_limit_global: Option<f64>,
+    pub rate_limit_global_burst: Option<f64>,
+    pub rate_limit_peer: Option<f64>,
+    pub rate_limit_peer_burst: Option<f64>,
+    #[cfg(feature = "s3")]
+    pub s3_endpoint: Option<String>,
+    #[cfg(feature = "s3")]
+    pub s3_bucket: Option<String>,
+    #[cfg(feature = "s3")]
+    pub s3_region: Option<String>,
+    #[cfg(feature = "s3")]
+    pub s3_prefix: Option<String>,
+    #[cfg(feature = "plugins")]
+    pub state_machine: Option<PathBuf>,
+}
+
+impl NodeConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// `self`, overridden by whatever `over` sets.
+    pub fn merge(self, over: NodeConfig) -> NodeConfig {
+        NodeConfig {
+            id: over.id.or(self.id),
+            port: over.port.or(self.port),
+            history: over.history.or(self.history),
+            trace: over.trace.or(self.trace),
+            data_dir: over.data_dir.or(self.data_dir),
+            signing_key: over.signing_key.or(self.signing_key),
+            peer_keys: over.peer_keys.or(self.peer_keys),
+            encryption_key: over.encryption_key.or(self.encryption_key),
+            encryption_migrate: over.encryption_migrate.or(self.encryption_migrate),
+            cluster_token: over.cluster_token.or(self.cluster_token),
+            token_grace_ms: over.token_grace_ms.or(self.token_grace_ms),
+            admin_token: over.admin_token.or(self.admin_token),
+            debug_admin: over.debug_admin.or(self.debug_admin),
+            byzantine: over.byzantine.or(self.byzantine),
+            shards: over.shards.or(self.shards),
+            streams: over.streams.or(self.streams),
+            groups: over.groups.or(self.groups),
+            learner: over.learner.or(self.learner),
+            zone: over.zone.or(self.zone),
+            weight: over.weight.or(self.weight),
+            acl: over.acl.or(self.acl),
+            wal_segment_bytes: over.wal_segment_bytes.or(self.wal_segment_bytes),
+            wal_group_delay_ms: over.wal_group_delay_ms.or(self.wal_group_delay_ms),
+            min_free_bytes: over.min_free_bytes.or(self.min_free_bytes),
+            step: over.step.or(self.step),
+            read_only: over.read_only.or(self.read_only),
+            drain_timeout_ms: over.drain_timeout_ms.or(self.drain_timeout_ms),
+            snapshot_every: over.snapshot_every.or(self.snapshot_every),
+            snapshot_interval_ms: over.snapshot_interval_ms.or(self.snapshot_interval_ms),
+            max_in_flight: over.max_in_flight.or(self.max_in_flight),
+            max_queued: over.max_queued.or(self.max_queued),
+            max_requests_per_round: over.max_requests_per_round.or(self.max_requests_per_round),
+            max_requests_per_peer: over.max_requests_per_peer.or(self.max_requests_per_peer),
+            learn_batch_delay_ms: over.learn_batch_delay_ms.or(self.learn_batch_delay_ms),
+            learn_batch_max: over.learn_batch_max.or(self.learn_batch_max),
+            learn_batch_fixed: over.learn_batch_fixed.or(self.learn_batch_fixed),
+            learn_gossip_fanout: over.learn_gossip_fanout.or(self.learn_gossip_fanout),
+            log_shipping: over.log_shipping.or(self.log_shipping),
+            learn_multicast: over.learn_multicast.or(self.learn_multicast),
+            learn_multicast_interface: over.learn_multicast_interface.or(self.learn_multicast_interface),
+            prepare_ahead: over.prepare_ahead.or(self.prepare_ahead),
+            pre_vote_lease_ms: over.pre_vote_lease_ms.or(self.pre_vote_lease_ms),
+            stuck_instance_timeout_ms: over.stuck_instance_timeout_ms.or(self.stuck_instance_timeout_ms),
+            chaos: over.chaos.or(self.chaos),
+            chaos_interval_ms: over.chaos_interval_ms.or(self.chaos_interval_ms),
+            chaos_pause: over.chaos_pause.or(self.chaos_pause),
+            chaos_max_pause_ms: over.chaos_max_pause_ms.or(self.chaos_max_pause_ms),
+            chaos_preempt: over.chaos_preempt.or(self.chaos_preempt),
+            chaos_restart: over.chaos_restart.or(self.chaos_restart),
+            rate_limit_client: over.rate_limit_client.or(self.rate_limit_client),
+            rate_limit_client_burst: over.rate_limit_client_burst.or(self.rate_limit_client_burst),
+            rate_limit_global: over.rate_limit_global.or(self.rate_limit_global),
+            rate_limit_global_burst: over.rate_limit_global_burst.or(self.rate_limit_global_burst),
+            rate_limit_peer: over.rate_limit_peer.or(self.rate_limit_peer),
+            rate_limit_peer_burst: over.rate_limit_peer_burst.or(self.rate_limit_peer_burst),
+            #[cfg(feature = "s3")]
+            s3_endpoint: over.s3_endpoint.or(self.s3_endpoint),
+            #[cfg(feature = "s3")]
+            s3_bucket: over.s3_bucket.or(self.s3_bucket),
+            #[cfg(feature = "s3")]
+            s3_region: over.s3_region.or(self.s3_region),
+            #[cfg(feature = "s3")]
+            s3_prefix: over.s3_prefix.or(self.s3_prefix),
+            #[cfg(feature = "plugins")]
+            state_machine: over.state_machine.or(self.state_machine),
+        }
+    }
+
+    /// Options that differ from `other` but can't change without a restart.
+    pub fn needs_restart(&self, other: &NodeConfig) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.id != other.id {
+            changed.push("id");
+        }
+        if self.port != other.port {
+            changed.push("port");
+        }
+        if self.history != other.history {
+            changed.push("history");
+        }
+        if self.trace != other.trace {
+            changed.push("trace");
+        }
+        if self.data_dir != other.data_dir {
+            changed.push("data_dir");
+        }
+        if self.signing_key != other.signing_key {
+            changed.push("signing_key");
+        }
+        if self.peer_keys != other.peer_keys {
+            changed.push("peer_keys");
+        }
+        if self.encryption_key != other.encryption_key {
+            changed.push("encryption_key");
+        }
+        if self.encryption_migrate != other.encryption_migrate {
+            changed.push("encryption_migrate");
+        }
+        if self.cluster_token != other.cluster_token {
+            changed.push("cluster_token");
+        }
+        if self.admin_token != other.admin_token {
+            changed.push("admin_token");
+        }
+        if self.debug_admin != other.debug_admin {
+            changed.push("debug_admin");
+        }
+        if self.byzantine != other.byzantine {
+            changed.push("byzantine");
+        }
+        if self.shards != other.shards {
+            changed.push("shards");
+        }
+        if self.streams != other.streams {
+            changed.push("streams");
+        }
+        if self.groups != other.groups {
+            changed.push("groups");
+        }
+        if self.learner != other.learner {
+            changed.push("learner");
+        }
+        if self.zone != other.zone {
+            changed.push("zone");
+        }
+        if self.weight != other.weight {
+            changed.push("weight");
+        }
+        if self.acl != other.acl {
+            changed.push("acl");
+        }
+        if self.wal_segment_bytes != other.wal_segment_bytes {
+            changed.push("wal_segment_bytes");
+        }
+        if (self.learn_multicast, self.learn_multicast_interface) != (other.learn_multicast, other.learn_multicast_interface) {
+            changed.push("learn_multicast");
+        }
+        if self.step != other.step {
+            changed.push("step");
+        }
+        if self.read_only != other.read_only {
+            changed.push("read_only");
+        }
+        #[cfg(feature = "s3")]
+        if (&self.s3_endpoint, &self.s3_bucket, &self.s3_region, &self.s3_prefix) != (&other.s3_endpoint, &other.s3_bucket, &other.s3_region, &other.s3_prefix) {
+            changed.push("s3");
+        }
+        #[cfg(feature = "plugins")]
+        if self.state_machine != other.state_machine {
+            changed.push("state_machine");
+        }
+        changed
+    }
+
+    pub fn settings(&self) -> Settings {
+        let chaos = self.chaos.unwrap_or(false).then(|| ChaosConfig {
+            interval: Duration::from_millis(self.chaos_interval_ms.unwrap_or_default()),
+            pause: self.chaos_pause.unwrap_or_default(),
+            preempt: self.chaos_preempt.unwrap_or_default(),
+            restart: self.chaos_restart.unwrap_or_default(),
+            max_pause: Duration::from_millis(self.chaos_max_pause_ms.unwrap_or_default()),
+        });
+        let rate_limits = RateLimits {
+            client: Limit { rate: self.rate_limit_client.unwrap_or_default(), burst: self.rate_limit_client_burst.unwrap_or_default() },
+            global: Limit { rate: self.rate_limit_global.unwrap_or_default(), burst: self.rate_limit_global_burst.unwrap_or_default() },
+        };
+        Settings {
+            drain_timeout: Duration::from_millis(self.drain_timeout_ms.unwrap_or_default()),
+            snapshot_every: self.snapshot_every.unwrap_or_default(),
+            snapshot_interval: Duration::from_millis(self.snapshot_interval_ms.unwrap_or_default()),
+            min_free_bytes: self.min_free_bytes.unwrap_or_default(),
+            wal_group_delay: Duration::from_millis(self.wal_group_delay_ms.unwrap_or_default()),
+            token_grace: Duration::from_millis(self.token_grace_ms.unwrap_or_default()),
+            backpressure: backpressure::Limits {
+                max_in_flight: self.max_in_flight.unwrap_or_default(),
+                max_queued: self.max_queued.unwrap_or_default(),
+            },
+            fan_out: fanout::Limits {
+                per_round: self.max_requests_per_round.unwrap_or_default(),
+                per_peer: self.max_requests_per_peer.unwrap_or_default(),
+            },
+            learn_batching: learns::Batching {
+                max_delay: Duration::from_millis(self.learn_batch_delay_ms.unwrap_or_default()),
+                max_batch: self.learn_batch_max.unwrap_or_default(),
+                adaptive: !self.learn_batch_fixed.unwrap_or_default(),
+            },
+            learn_gossip: self.learn_gossip_fanout.unwrap_or_default(),
+            log_shipping: self.log_shipping.unwrap_or_default(),
+            prepare_ahead: self.prepare_ahead.unwrap_or_default(),
+            pre_vote_lease: Duration::from_millis(self.pre_vote_lease_ms.unwrap_or_default()),
+            stuck_instance_timeout: Duration::from_millis(self.stuck_instance_timeout_ms.unwrap_or_default()),
+            chaos,
+            rate_limits,
+            peer_rate_limit: Limit { rate: self.rate_limit_peer.unwrap_or_default(), burst: self.rate_limit_peer_burst.unwrap_or_default() },
+        }
+    }
+}
+
+/// What a node can change while it runs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    pub drain_timeout: Duration,
+    /// Log entries between automatic snapshots, 0 for none.
+    pub snapshot_every: u64,
+    /// Longest time between automatic snapshots, zero for no limit.
+    pub snapshot_interval: Duration,
+    /// Below this much free space in the data directory the node stops
+    /// voting; 0 never does.
+    pub min_free_bytes: u64,
+    /// How long a log entry waits for others to be synced with it.
+    pub wal_group_delay: Duration,
+    /// How long a cluster token is still accepted after it left the file.
+    pub token_grace: Duration,
+    /// How many client proposals run and wait at once.
+    pub backpressure: backpressure::Limits,
+    /// How many requests a node has out at once.
+    pub fan_out: fanout::Limits,
+    /// How decisions are gathered up before they are sent to the peers.
+    pub learn_batching: learns::Batching,
+    /// Peers each batch of learns is gossiped to, 0 to send it to all.
+    pub learn_gossip: usize,
+    /// Whether decisions go out as shipments of the log, with each
+    /// follower's progress kept; see `shipping`.
+    pub log_shipping: bool,
+    /// Instances a leader prepares at once, 0 to prepare each on its own.
+    pub prepare_ahead: u64,
+    /// How long after an accept a node turns down other proposers'
+    /// pre-votes; zero runs none.
+    pub pre_vote_lease: Duration,
+    /// How long an instance stays undecided before this node takes it
+    /// over; zero never does.
+    pub stuck_instance_timeout: Duration,
+    pub chaos: Option<ChaosConfig>,
+    pub rate_limits: RateLimits,
+    /// Prepares and accepts each peer may send.
+    pub peer_rate_limit: Limit,
+}
+
+/// Where a node's options came from, so they can be put together again.
+#[derive(Clone, Debug, Default)]
+pub struct Layers {
+    /// Built-in defaults of whatever no flag or variable set.
+    pub defaults: NodeConfig,
+    pub file: Option<PathBuf>,
+    /// Flags and environment variables.
+    pub explicit: NodeConfig,
+}
+
+impl Layers {
+    pub fn resolve(&self) -> Result<NodeConfig, String> {
+        let file = match &self.file {
+            Some(path) => NodeConfig::load(path)?,
+            None => NodeConfig::default(),
+        };
+        Ok(self.defaults.clone().merge(file).merge(self.explicit.clone()))
+    }
+}
+
+/// Kept by a node started from the command line.
+#[derive(Debug)]
+pub struct Reloader {
+    pub layers: Layers,
+    pub current: std::sync::Mutex<NodeConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ReloadReport {
+    pub error: Option<String>,
+    /// Changed in the file, but only applied on a restart.
+    pub needs_restart: Vec<String>,
+}
+
+pub fn reload(state: &AppState, reloader: &Arc<Reloader>) -> Result<ReloadReport, String> {
+    let config = reloader.layers.resolve()?;
+
+    let mut current = reloader.current.lock().unwrap();
+    let needs_restart = current.needs_restart(&config).into_iter().map(String::from).collect();
+
+    let settings = config.settings();
+    *state.settings.write().unwrap() = settings.clone();
+    *current = config;
+    secrets::refresh(state);
+
+    println!("[reload] Node {} applied {:?}", state.node.id, settings);
+    Ok(ReloadReport { error: None, needs_restart })
+}
+
+pub async fn reload_config(State(state): State<AppState>) -> (StatusCode, Json<ReloadReport>) {
+    let Some(reloader) = &state.reloader else {
+        let report = ReloadReport { error: Some(String::from("Node wasn't started with options to reload!")), ..ReloadReport::default() };
+        return (StatusCode::CONFLICT, Json(report));
+    };
+
+    match reload(&state, reloader) {
+        Ok(report) => (StatusCode::OK, Json(report)),
+        Err(e) => {
+            println!("[/admin/reload] Node {} kept its settings: {}", state.node.id, e);
+            (StatusCode::BAD_REQUEST, Json(ReloadReport { error: Some(e), ..ReloadReport::default() }))
+        },
+    }
+}
+
+/// Reloads on every SIGHUP, where there is such a thing.
+pub async fn on_hangup(state: AppState, reloader: Arc<Reloader>) {
+    #[cfg(unix)]
+    {
+        let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .expect("failed to listen for SIGHUP");
+
+        while hangups.recv().await.is_some() {
+            match reload(&state, &reloader) {
+                Ok(report) if !report.needs_restart.is_empty() => {
+                    println!("[reload] Node {} needs a restart to apply {}", state.node.id, report.needs_restart.join(", "));
+                },
+                Ok(_) => {},
+                Err(e) => println!("[reload] Node {} kept its settings: {}", state.node.id, e),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    std::future::pending::<()>().await;
+}
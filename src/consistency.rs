@@ -0,0 +1,210 @@
+//! Cross-node ledger comparison.
+//!
+//! `GET /admin/consistency-check` asks every voter for digests of its ledger
+//! over chunks of instances, then asks again, instance by instance, for the
+//! chunks whose digests don't match. Nodes that simply haven't learned an
+//! instance yet are fine; two nodes that learned different values for the
+//! same instance are a safety violation, and make the check answer 409.
+//! The check also repairs them where it can; see `repair`.
+
+use std::collections::BTreeMap;
+use axum::{
+    http::StatusCode,
+    extract::State
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{AppState, Id, Value, fanout, input::{Json, Query}, ledger::SharedLedger, repair};
+
+/// Instances per digest in the first pass.
+const CHUNK: u64 = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct DigestRequest {
+    pub from: u64,
+    pub to: u64,
+    pub chunk: u64,
+}
+
+/// The learned instances in `from..=to`, and a digest of their values.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RangeDigest {
+    pub from: u64,
+    pub to: u64,
+    pub count: u64,
+    pub digest: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DigestReply {
+    /// Highest instance learned.
+    pub last: u64,
+    /// One entry per non-empty chunk.
+    pub digests: Vec<RangeDigest>,
+}
+
+/// FNV-1a, which unlike the std hasher is guaranteed to stay the same
+/// across builds, so nodes on different versions still agree.
+pub(crate) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn mix(hash: u64, instance: u64, value: &Value) -> u64 {
+    let hash = fnv1a(hash, &instance.to_le_bytes());
+    let hash = fnv1a(hash, &(value.len() as u64).to_le_bytes());
+    fnv1a(hash, value.as_bytes())
+}
+
+/// The digest of `value` learned in `instance`, as the check reports it.
+pub(crate) fn value_digest(instance: u64, value: &Value) -> String {
+    format!("{:016x}", mix(FNV_OFFSET, instance, value))
+}
+
+pub fn digest(ledger: &SharedLedger, request: DigestRequest) -> DigestReply {
+    let last = ledger.last().unwrap_or(0);
+    let chunk = request.chunk.max(1);
+    let to = request.to.min(last);
+
+    let mut digests = Vec::new();
+    let mut from = request.from.max(1);
+    while from <= to {
+        // Chunk bounds don't depend on how far this node got, so that every
+        // node reports the same ranges.
+        let end = from.saturating_add(chunk - 1);
+        let mut hash = FNV_OFFSET;
+        let mut count = 0;
+
+        for instance in from..=end {
+            if let Some(value) = ledger.get(instance) {
+                hash = mix(hash, instance, &value);
+                count += 1;
+            }
+        }
+
+        if count > 0 {
+            digests.push(RangeDigest { from, to: end, count, digest: format!("{:016x}", hash) });
+        }
+        if end >= to {
+            break;
+        }
+        from = end + 1;
+    }
+
+    DigestReply { last, digests }
+}
+
+pub async fn ledger_digest(State(state): State<AppState>, Json(request): Json<DigestRequest>) -> (StatusCode, Json<DigestReply>) {
+    (StatusCode::OK, Json(digest(&state.ledger, request)))
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CheckQuery {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Conflict {
+    pub instance: u64,
+    /// Digest of the value each node learned, for the nodes that learned it.
+    pub digests: BTreeMap<Id, String>,
+    /// The nodes that got the value a quorum holds instead.
+    #[serde(default)]
+    pub repaired: Vec<Id>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CheckReport {
+    pub from: u64,
+    pub to: u64,
+    pub nodes: Vec<Id>,
+    pub unreachable: Vec<Id>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Collects digests from every voter (us included) that answers.
+async fn collect(state: &AppState, request: DigestRequest) -> (BTreeMap<Id, DigestReply>, Vec<Id>) {
+    let nodes = state.nodes.snapshot();
+    let responses = fanout::post_all(state, &nodes, "/admin/ledger-digest", &request).await;
+
+    let mut replies = BTreeMap::new();
+    let mut unreachable = Vec::new();
+
+    for (node, response) in nodes.iter().zip(responses) {
+        match response.ok().filter(|res| !res.is_error()).and_then(|res| res.json::<DigestReply>().ok()) {
+            Some(reply) => {
+                replies.insert(node.id, reply);
+            },
+            None => unreachable.push(node.id),
+        }
+    }
+
+    let local = digest(&state.ledger, request);
+    replies.insert(state.node.id, local);
+
+    (replies, unreachable)
+}
+
+/// Ranges where some two nodes disagree. A node missing instances shows up
+/// here too; the per-instance pass sorts that out.
+fn mismatches(replies: &BTreeMap<Id, DigestReply>) -> Vec<(u64, u64)> {
+    let mut by_range: BTreeMap<(u64, u64), Vec<&RangeDigest>> = BTreeMap::new();
+    for reply in replies.values() {
+        for digest in &reply.digests {
+            by_range.entry((digest.from, digest.to)).or_default().push(digest);
+        }
+    }
+
+    by_range.into_iter()
+        .filter(|(_, digests)| {
+            digests.len() < replies.len() || digests.windows(2).any(|pair| pair[0] != pair[1])
+        })
+        .map(|(range, _)| range)
+        .collect()
+}
+
+pub async fn consistency_check(
+    State(state): State<AppState>,
+    Query(query): Query<CheckQuery>
+) -> (StatusCode, Json<CheckReport>) {
+    let from = query.from.unwrap_or(1);
+    let to = query.to.unwrap_or(u64::MAX);
+
+    let (replies, unreachable) = collect(&state, DigestRequest { from, to, chunk: CHUNK }).await;
+    let nodes: Vec<Id> = replies.keys().copied().collect();
+
+    let mut conflicts = Vec::new();
+    for (range_from, range_to) in mismatches(&replies) {
+        let request = DigestRequest { from: range_from, to: range_to, chunk: 1 };
+        let (replies, _) = collect(&state, request).await;
+
+        let mut by_instance: BTreeMap<u64, BTreeMap<Id, String>> = BTreeMap::new();
+        for (id, reply) in replies {
+            for digest in reply.digests {
+                by_instance.entry(digest.from).or_default().insert(id, digest.digest);
+            }
+        }
+
+        for (instance, digests) in by_instance {
+            let mut values = digests.values();
+            let first = values.next();
+            if values.any(|digest| Some(digest) != first) {
+                let repaired = repair::conflict(&state, instance, &digests).await;
+                conflicts.push(Conflict { instance, digests, repaired });
+            }
+        }
+    }
+
+    let last = replies.values().map(|reply| reply.last).max().unwrap_or(0);
+    let report = CheckReport { from, to: to.min(last), nodes, unreachable, conflicts };
+
+    if report.conflicts.is_empty() {
+        println!("[/admin/consistency-check] Node {} found no conflicts up to instance {}", state.node.id, report.to);
+        (StatusCode::OK, Json(report))
+    } else {
+        println!("[/admin/consistency-check] Node {} found conflicting instances: {:?}", state.node.id, report.conflicts);
+        (StatusCode::CONFLICT, Json(report))
+    }
+}
@@ -0,0 +1,93 @@
+//! Crash reports for post-mortems.
+//!
+//! A node with a data directory installs a panic hook that, before the
+//! process aborts, writes `crash-<unix millis>.txt` there: the panic message
+//! and where it happened, a backtrace, and what the node was doing, i.e.
+//! its proposer round, the instance it was on and a summary of its state.
+//! Aborting on any panic, even one in a request task, keeps a node from
+//! running on with a lock poisoned or a proposal half done.
+//!
+//! The hook runs before anything is unwound, so it can't wait for a lock
+//! the panicking code may hold: it only tries the state's locks, reporting
+//! whatever is taken as such, and skips anything behind a blocking one.
+
+use std::{
+    backtrace::Backtrace,
+    fmt::Write as _,
+    fs,
+    panic::PanicHookInfo,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::AppState;
+
+const LOCKED: &str = "locked, maybe by whatever panicked";
+
+/// What the node was doing, from whatever isn't locked.
+pub fn summary(state: &AppState) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "node: {} at {}", state.node.id, state.node.addr);
+
+    let _ = writeln!(out, "proposer round: {}", state.proposer.round());
+
+    let last = state.ledger.last().unwrap_or(0);
+    let _ = writeln!(out, "learned: {} instances, up to {}; next instance {}; {} not applied", state.ledger.len(), last, last + 1, state.applier.lag());
+
+    let acceptor = state.acceptor.try_lock().map_or_else(|_| LOCKED.to_string(), |acceptor| {
+        let mut slots = format!("{} open slots", acceptor.slots.len());
+        if let Some((instance, slot)) = acceptor.slots.iter().next_back() {
+            let _ = write!(slots, "; instance {} promised {:?}, accepted {:?}", instance, slot.last_ballot_number, slot.accepted_proposal);
+        }
+        slots
+    });
+    let _ = writeln!(out, "acceptor: {}", acceptor);
+
+    let kv = state.kv.try_lock().map_or_else(|_| LOCKED.to_string(), |kv| format!("{} keys", kv.data.len()));
+    let _ = writeln!(out, "kv: {}", kv);
+
+    let peers = state.nodes.try_snapshot().map_or_else(|| LOCKED.to_string(), |nodes| format!("{:?}", nodes.iter().map(|node| node.id).collect::<Vec<_>>()));
+    let _ = writeln!(out, "peers: {}", peers);
+
+    let _ = writeln!(
+        out,
+        "proposals: {} in flight; paused: {}, low on disk: {}, draining: {}",
+        state.shutdown.in_flight(), state.is_paused(), state.disk.is_low(), state.shutdown.is_draining(),
+    );
+    out
+}
+
+fn report(state: &AppState, info: &PanicHookInfo<'_>) -> String {
+    let message = info.payload().downcast_ref::<&str>().copied()
+        .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(no message)");
+    let location = info.location().map_or_else(|| String::from("unknown"), ToString::to_string);
+    let thread = std::thread::current().name().unwrap_or("unnamed").to_string();
+
+    format!(
+        "panicked at {} on thread {}:\n{}\n\n{}\nbacktrace:\n{}\n",
+        location, thread, message, summary(state), Backtrace::force_capture(),
+    )
+}
+
+fn write(dir: &Path, report: &str) -> std::io::Result<PathBuf> {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let path = dir.join(format!("crash-{}.txt", millis));
+    fs::write(&path, report)?;
+    Ok(path)
+}
+
+/// Writes a crash report into `dir` on any panic, then aborts.
+pub fn install(state: AppState, dir: PathBuf) {
+    let default = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        default(info);
+
+        match write(&dir, &report(&state, info)) {
+            Ok(path) => eprintln!("[crash] Node {} wrote a crash report to {}", state.node.id, path.display()),
+            Err(e) => eprintln!("[crash] Node {} failed to write a crash report: {}", state.node.id, e),
+        }
+        std::process::abort();
+    }));
+}
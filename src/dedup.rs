@@ -0,0 +1,132 @@
+//! Answering a message a peer sent twice the way it was answered the first
+//! time.
+//!
+//! A retransmission, whether a flaky network or a fault rule duplicated the
+//! message, would otherwise be handled twice: a second accept persisted
+//! again, a second batch of learns looked up again, and, with a reply lost
+//! in between, answered with something that no longer matches the first.
+//! Every node-to-node request carries an id in [`MESSAGE_ID_HEADER`],
+//! unique to its sender: when its transport started and how many messages
+//! it sent since. Each Paxos group keeps the last [`PER_PEER`] replies it
+//! gave to each peer's prepares, accepts, learns and shipments, and answers
+//! a message it already answered with that same reply instead of handling
+//! it again. Only successful replies are kept, so a peer trying again after
+//! an error is handled anew.
+//!
+//! `paxos_duplicates_suppressed` in `GET /metrics` counts the messages
+//! answered from the cache. Peers that send no id, older ones, are handled
+//! as before.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, atomic::{AtomicU64, Ordering}},
+};
+use axum::{
+    body::{Body, Bytes, to_bytes},
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode, header::CONTENT_TYPE},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{AppState, Id, history::now_micros, transport::NODE_ID_HEADER};
+
+/// Set on every node-to-node request, with an id unique to its sender.
+pub const MESSAGE_ID_HEADER: &str = "x-paxos-message-id";
+
+/// How many replies to each peer a group keeps.
+pub const PER_PEER: usize = 256;
+
+/// Hands out the ids of the messages a node sends.
+#[derive(Debug)]
+pub struct MessageIds {
+    /// When the transport started, so a node that restarts doesn't reuse
+    /// the ids it sent before.
+    boot: u64,
+    sent: AtomicU64,
+}
+
+impl Default for MessageIds {
+    fn default() -> Self {
+        Self { boot: now_micros(), sent: AtomicU64::new(0) }
+    }
+}
+
+impl MessageIds {
+    pub fn next(&self) -> String {
+        format!("{}.{}", self.boot, self.sent.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Cached {
+    status: StatusCode,
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+}
+
+impl IntoResponse for Cached {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, self.body).into_response();
+        if let Some(content_type) = self.content_type {
+            response.headers_mut().insert(CONTENT_TYPE, content_type);
+        }
+        response
+    }
+}
+
+/// The last replies a group gave each peer.
+#[derive(Debug, Default)]
+pub struct Dedup {
+    peers: Mutex<HashMap<Id, VecDeque<(String, Cached)>>>,
+    suppressed: AtomicU64,
+}
+
+impl Dedup {
+    fn get(&self, peer: Id, id: &str) -> Option<Cached> {
+        let peers = self.peers.lock().unwrap();
+        peers.get(&peer)?.iter().find(|(seen, _)| seen == id).map(|(_, reply)| reply.clone())
+    }
+
+    fn keep(&self, peer: Id, id: String, reply: Cached) {
+        let mut peers = self.peers.lock().unwrap();
+        let replies = peers.entry(peer).or_default();
+        if replies.len() >= PER_PEER {
+            replies.pop_front();
+        }
+        replies.push_back((id, reply));
+    }
+
+    /// Messages answered from the cache rather than handled again.
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed.load(Ordering::SeqCst)
+    }
+}
+
+/// Answers a message the group already answered with the reply it gave.
+pub async fn suppress(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let peer = headers.get(NODE_ID_HEADER).and_then(|id| id.to_str().ok()?.parse::<Id>().ok());
+    let id = headers.get(MESSAGE_ID_HEADER).and_then(|id| id.to_str().ok()).map(String::from);
+    let (Some(peer), Some(id)) = (peer, id) else {
+        return next.run(request).await;
+    };
+
+    if let Some(reply) = state.dedup.get(peer, &id) {
+        state.dedup.suppressed.fetch_add(1, Ordering::SeqCst);
+        println!("[dedup] Node {} already answered message {} from node {}, answering the same", state.node.id, id, peer);
+        return reply.into_response();
+    }
+
+    let response = next.run(request).await;
+    if !response.status().is_success() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let Ok(body) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let reply = Cached { status: parts.status, content_type: parts.headers.get(CONTENT_TYPE).cloned(), body: body.clone() };
+    state.dedup.keep(peer, id, reply);
+    Response::from_parts(parts, Body::from(body))
+}
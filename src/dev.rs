@@ -0,0 +1,156 @@
+//! A cluster of local nodes, for development.
+//!
+//! `cargo run -- dev-cluster --nodes 3` starts nodes 1 to 3 as child
+//! processes of itself on ports 3000 to 3002, waits until each answers,
+//! connects every pair, then prints every line they write, each marked
+//! with the node that wrote it. Ctrl-C, or any node exiting, stops them
+//! all, each drained as on its own SIGTERM. Arguments after `--` go to
+//! every node, and with `--data-dir` each node keeps its data in a
+//! directory of its own under it.
+
+use std::{path::{Path, PathBuf}, process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+    task::JoinHandle,
+    time::{Instant, sleep, timeout},
+};
+
+use crate::{Id, client::Client, shutdown};
+
+/// How long a node has to answer once it was started.
+pub const START_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a node has to drain once asked to stop, before it is killed.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct DevCluster {
+    pub nodes: usize,
+    /// The port of node 1; node `n` listens `n - 1` ports further.
+    pub base_port: u16,
+    pub data_dir: Option<PathBuf>,
+    /// Passed on to every node.
+    pub args: Vec<String>,
+}
+
+/// The nodes of a started cluster. Dropping it kills them.
+#[derive(Debug)]
+pub struct Running {
+    /// Each node's id and port.
+    pub nodes: Vec<(Id, u16)>,
+    children: Vec<Child>,
+    tails: Vec<JoinHandle<()>>,
+}
+
+impl DevCluster {
+    /// Each node's id and port.
+    pub fn members(&self) -> Vec<(Id, u16)> {
+        (1..=self.nodes as u16).map(|n| (n as Id, self.base_port + n - 1)).collect()
+    }
+
+    /// How `program`, this binary, is run as node `id`.
+    pub fn command(&self, program: &Path, id: Id, port: u16) -> Command {
+        let mut command = Command::new(program);
+        command.arg("--id").arg(id.to_string()).arg("--port").arg(port.to_string());
+        if let Some(data_dir) = &self.data_dir {
+            command.arg("--data-dir").arg(data_dir.join(id.to_string()));
+        }
+        command.args(&self.args);
+        command
+    }
+
+    /// Starts every node as a child running `program`, tails what they
+    /// write, waits until each answers, and connects every pair.
+    pub async fn start(&self, program: &Path) -> Result<Running, String> {
+        if self.nodes == 0 || self.base_port as usize + self.nodes - 1 > u16::MAX as usize {
+            return Err(format!("{} nodes don't fit on the ports from {}!", self.nodes, self.base_port));
+        }
+        let mut running = Running { nodes: self.members(), children: Vec::new(), tails: Vec::new() };
+        for &(id, port) in &running.nodes {
+            let mut child = self.command(program, id, port)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| format!("Failed to start node {}: {}", id, e))?;
+            running.tails.push(tokio::spawn(tail(id, child.stdout.take().unwrap())));
+            running.tails.push(tokio::spawn(tail(id, child.stderr.take().unwrap())));
+            running.children.push(child);
+        }
+
+        for index in 0..running.nodes.len() {
+            running.await_up(index).await?;
+        }
+        for (i, &(id, _)) in running.nodes.iter().enumerate() {
+            let client = running.client(i);
+            for &(peer, port) in &running.nodes[i + 1..] {
+                client.connect(port).await.map_err(|e| format!("Failed to connect node {} to node {}: {}", id, peer, e))?;
+            }
+        }
+        Ok(running)
+    }
+}
+
+impl Running {
+    /// A client of the node at `index`.
+    pub fn client(&self, index: usize) -> Client {
+        Client::new(&format!("localhost:{}", self.nodes[index].1))
+    }
+
+    async fn await_up(&mut self, index: usize) -> Result<(), String> {
+        let (id, port) = self.nodes[index];
+        let client = self.client(index);
+        let deadline = Instant::now() + START_TIMEOUT;
+        loop {
+            if client.status().await.is_ok() {
+                return Ok(());
+            }
+            if let Ok(Some(exit)) = self.children[index].try_wait() {
+                return Err(format!("Node {} exited before it answered: {}", id, exit));
+            }
+            if Instant::now() > deadline {
+                return Err(format!("Node {} didn't answer on port {} within {:?}", id, port, START_TIMEOUT));
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Waits for Ctrl-C or for a node to exit, then stops them all, and
+    /// answers which it was.
+    pub async fn wait(mut self) -> String {
+        let exited = futures::future::select_all(self.children.iter_mut().map(|child| Box::pin(child.wait())));
+        let reason = tokio::select! {
+            _ = shutdown::signal() => String::from("Interrupted"),
+            (exit, index, _) = exited => format!("Node {} exited: {}", self.nodes[index].0, exit.map_or_else(|e| e.to_string(), |exit| exit.to_string())),
+        };
+        self.stop().await;
+        reason
+    }
+
+    /// Asks every node to stop, kills those still running after
+    /// [`STOP_TIMEOUT`], and waits for the last of their lines.
+    pub async fn stop(&mut self) {
+        #[cfg(unix)]
+        for child in &self.children {
+            if let Some(pid) = child.id() {
+                unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+            }
+        }
+        for child in &mut self.children {
+            if timeout(STOP_TIMEOUT, child.wait()).await.is_err() {
+                let _ = child.kill().await;
+            }
+        }
+        for tail in self.tails.drain(..) {
+            let _ = tail.await;
+        }
+    }
+}
+
+/// Prints each line `output` of node `id` writes, marked with its id.
+async fn tail(id: Id, output: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        println!("[node {}] {}", id, line);
+    }
+}
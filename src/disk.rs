@@ -0,0 +1,92 @@
+//! Watching the free space under the data directory.
+//!
+//! A node that fills its disk fails in the middle of a write, so well before
+//! that, once free space drops under `--min-free-bytes`, it goes degraded: it
+//! answers prepares and accepts with a `507` NACK and turns client proposals
+//! away, so it stops promising what it may not manage to write down. It
+//! keeps learning, since a learned value is small and a node that misses
+//! them falls behind. Once space is freed it takes part again by itself.
+
+use std::{
+    io,
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+use axum::http::StatusCode;
+
+use crate::AppState;
+
+pub const LOW_ON_SPACE: &str = "Node is low on disk space!";
+
+/// How often [`run`] looks at the disk.
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+pub struct Disk {
+    low: AtomicBool,
+    free: AtomicU64,
+}
+
+impl Disk {
+    pub fn is_low(&self) -> bool {
+        self.low.load(Ordering::SeqCst)
+    }
+
+    pub fn free(&self) -> u64 {
+        self.free.load(Ordering::SeqCst)
+    }
+
+    /// Records `free` bytes against a threshold of `min`, 0 for none, and
+    /// returns whether the node went degraded or recovered.
+    pub fn update(&self, free: u64, min: u64) -> Option<bool> {
+        self.free.store(free, Ordering::SeqCst);
+        let low = free < min;
+        (self.low.swap(low, Ordering::SeqCst) != low).then_some(low)
+    }
+}
+
+/// Bytes an unprivileged process can still write on the filesystem holding `dir`.
+#[cfg(unix)]
+pub fn free_bytes(dir: &Path) -> io::Result<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(dir.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: `statvfs` only writes into `stat`, and `path` is NUL-terminated.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The field types differ between platforms.
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_bytes(_dir: &Path) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "free space is only known on unix"))
+}
+
+pub async fn run(state: AppState) {
+    let Some(storage) = state.storage.clone() else {
+        return;
+    };
+
+    loop {
+        let min = state.settings.read().unwrap().min_free_bytes;
+        match free_bytes(storage.dir()) {
+            Err(e) => println!("[disk] Node {} can't tell its free space: {}", state.node.id, e),
+            Ok(free) => match state.disk.update(free, min) {
+                Some(true) => println!("[disk] Node {} has {} bytes free, under {}: no longer promising or accepting", state.node.id, free, min),
+                Some(false) => println!("[disk] Node {} has {} bytes free again, taking part in consensus", state.node.id, free),
+                None => {},
+            },
+        }
+        tokio::time::sleep(CHECK_EVERY).await;
+    }
+}
+
+/// The 507 a node low on space answers proposals with.
+pub fn refuse() -> (StatusCode, String) {
+    (StatusCode::INSUFFICIENT_STORAGE, String::from(LOW_ON_SPACE))
+}
@@ -0,0 +1,41 @@
+//! Ed25519 signatures (RFC 8032), through `ed25519-dalek`.
+//!
+//! Keys and signatures go between nodes as plain byte arrays, so this only
+//! wraps the crate's types in the few calls the node makes.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+use crate::hex;
+
+pub const PUBLIC_KEY_BYTES: usize = 32;
+pub const SIGNATURE_BYTES: usize = 64;
+
+/// A node's signing key, from the 32-byte seed RFC 8032 calls the private key.
+#[derive(Clone)]
+pub struct Keypair(SigningKey);
+
+impl std::fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keypair").field("public", &hex::encode(&self.public())).finish()
+    }
+}
+
+impl Keypair {
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        Self(SigningKey::from_bytes(seed))
+    }
+
+    pub fn public(&self) -> [u8; PUBLIC_KEY_BYTES] {
+        self.0.verifying_key().to_bytes()
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_BYTES] {
+        self.0.sign(message).to_bytes()
+    }
+}
+
+/// Whether `signature` is `public`'s over `message`. Strict, so neither a
+/// non-canonical signature nor a weak key passes.
+pub fn verify(public: &[u8; PUBLIC_KEY_BYTES], message: &[u8], signature: &[u8; SIGNATURE_BYTES]) -> bool {
+    VerifyingKey::from_bytes(public).is_ok_and(|key| key.verify_strict(message, &Signature::from_bytes(signature)).is_ok())
+}
@@ -0,0 +1,218 @@
+//! Leader election for applications outside the cluster.
+//!
+//! `POST /elections/<name>/campaign` makes a candidate leader of election
+//! `name`, waiting up to `timeout_ms` for the current leader to go, and
+//! answers the leader once it is one. A candidate it elects holds the
+//! lease `election:<name>` for `ttl_ms`, see `lease`, with its `value`, say
+//! its address, as a key of the lease. The leader stays leader by
+//! campaigning again before the lease runs out, which renews it, and
+//! `POST /elections/<name>/resign` gives it up at once.
+//!
+//! `GET /elections/<name>/observe` answers the leader. With `?leader=<x>`
+//! it waits until the leader is no longer `x`, so a follower can watch for
+//! a new one without polling, and answers a `404` if there is none.
+//!
+//! With `?since=<index>` instead, it answers every change of leader or of
+//! its value committed after instance `since`, out of the changes the node
+//! keeps (see `watch`), and `index`, the instance to observe from next, so
+//! a follower that got disconnected misses none. A lease that ran out only
+//! shows once someone expires it or takes it over.
+
+use std::time::Duration;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+use tokio::time::Instant;
+
+use crate::{
+    AppState,
+    acl::{self, Op},
+    input::{Json, Path, Query},
+    lease::{self, Action, Lease},
+};
+
+/// The key of the lease where the leader keeps its value.
+pub const VALUE_KEY: &str = "value";
+
+/// How long `campaign` and `observe` wait, unless told otherwise.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_WAIT: Duration = Duration::from_secs(300);
+
+/// The lease the leader of election `name` holds.
+pub fn lease_name(name: &str) -> String {
+    format!("election:{}", name)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Leader {
+    pub name: String,
+    pub leader: String,
+    pub value: Option<String>,
+    /// Microseconds since the Unix epoch, when the lease runs out unless
+    /// the leader campaigns again.
+    pub expires: u64,
+}
+
+impl Leader {
+    fn of(name: &str, lease: Lease) -> Self {
+        let value = lease.keys.get(VALUE_KEY).cloned();
+        Self { name: name.to_string(), leader: lease.holder, value, expires: lease.expires }
+    }
+}
+
+/// The leader of election `name` as of now on this node, if any.
+async fn leader(state: &AppState, name: &str) -> Option<Lease> {
+    let now = state.clock.now().wall;
+    lease::get(&*state.kv.lock().await, &lease_name(name)).filter(|lease| lease.is_live(now))
+}
+
+/// Waits for the node to apply something, or for `lease` to run out, but
+/// no later than `deadline`.
+async fn changed(state: &AppState, lease: Option<&Lease>, deadline: Instant) {
+    let mut until = deadline;
+    if let Some(lease) = lease {
+        let left = Duration::from_micros(lease.expires.saturating_sub(state.clock.now().wall));
+        until = until.min(Instant::now() + left);
+    }
+    let _ = tokio::time::timeout_at(until, state.applier.applied(state.applier.index() + 1)).await;
+}
+
+fn timeout(timeout_ms: Option<u64>) -> Duration {
+    timeout_ms.map_or(WAIT_TIMEOUT, Duration::from_millis).min(MAX_WAIT)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Campaign {
+    pub candidate: String,
+    pub ttl_ms: u64,
+    pub value: Option<String>,
+    pub timeout_ms: Option<u64>,
+}
+
+pub async fn campaign(State(state): State<AppState>, Path(name): Path<String>, headers: HeaderMap, Json(request): Json<Campaign>) -> Response {
+    if request.ttl_ms == 0 {
+        return (StatusCode::BAD_REQUEST, String::from("A campaign needs a ttl_ms above 0!")).into_response();
+    }
+    let lease = lease_name(&name);
+    let timeout = timeout(request.timeout_ms);
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let acquire = Action::Acquire { holder: request.candidate.clone(), ttl_ms: request.ttl_ms };
+        match lease::act(&state, &headers, &lease, acquire).await {
+            Err(refusal) => return refusal.into_response(),
+            Ok(Some(held)) if held.holder == request.candidate => {
+                let Some(value) = request.value.clone() else {
+                    return Json(Leader::of(&name, held)).into_response();
+                };
+                let attach = Action::Attach { holder: request.candidate.clone(), key: VALUE_KEY.to_string(), value };
+                return match lease::act(&state, &headers, &lease, attach).await {
+                    Ok(Some(held)) if held.holder == request.candidate => Json(Leader::of(&name, held)).into_response(),
+                    Ok(_) => (StatusCode::CONFLICT, format!("{} lost election {} before it could set its value!", request.candidate, name)).into_response(),
+                    Err(refusal) => refusal.into_response(),
+                };
+            },
+            _ => {},
+        }
+
+        // Someone else leads: try again once they don't.
+        while let Some(current) = leader(&state, &name).await.filter(|lease| lease.holder != request.candidate) {
+            if Instant::now() >= deadline {
+                return (StatusCode::GATEWAY_TIMEOUT, format!("{} still leads election {} after {} ms!", current.holder, name, timeout.as_millis())).into_response();
+            }
+            changed(&state, Some(&current), deadline).await;
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Resign {
+    pub candidate: String,
+}
+
+pub async fn resign(State(state): State<AppState>, Path(name): Path<String>, headers: HeaderMap, Json(request): Json<Resign>) -> Response {
+    match lease::act(&state, &headers, &lease_name(&name), Action::Release { holder: request.candidate.clone() }).await {
+        Ok(None) => (StatusCode::OK, format!("{} resigned from election {}!", request.candidate, name)).into_response(),
+        Ok(Some(held)) => (StatusCode::CONFLICT, format!("{} leads election {}, not {}!", held.holder, name, request.candidate)).into_response(),
+        Err(refusal) => refusal.into_response(),
+    }
+}
+
+/// A change of leader, and the instance it was committed in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Succession {
+    pub instance: u64,
+    /// None once the leader resigned or its lease was expired.
+    pub leader: Option<Leader>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Successions {
+    /// The changes go up to this instance; observe from here next.
+    pub index: u64,
+    pub changes: Vec<Succession>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Observe {
+    /// Wait until the leader is no longer this one.
+    pub leader: Option<String>,
+    /// Answer the changes of leader after this instance instead.
+    pub since: Option<u64>,
+    pub timeout_ms: Option<u64>,
+}
+
+pub async fn observe(State(state): State<AppState>, Path(name): Path<String>, Query(query): Query<Observe>, headers: HeaderMap) -> Response {
+    if let Err(refusal) = acl::authorize(&state, &headers, Op::Read, &lease::key(&lease_name(&name))).await {
+        return refusal.into_response();
+    }
+    let timeout = timeout(query.timeout_ms);
+    let deadline = Instant::now() + timeout;
+    if let Some(since) = query.since {
+        return replay(&state, &name, since, deadline).await;
+    }
+
+    loop {
+        let current = leader(&state, &name).await;
+        if current.as_ref().map(|lease| &lease.holder) != query.leader.as_ref() {
+            return match current {
+                Some(current) => Json(Leader::of(&name, current)).into_response(),
+                None => (StatusCode::NOT_FOUND, format!("Election {} has no leader", name)).into_response(),
+            };
+        }
+        if Instant::now() >= deadline {
+            return (StatusCode::GATEWAY_TIMEOUT, format!("Election {} didn't change leader within {} ms!", name, timeout.as_millis())).into_response();
+        }
+        changed(&state, current.as_ref(), deadline).await;
+    }
+}
+
+/// The changes of leader of election `name` committed after instance
+/// `since`, waiting for one until `deadline`.
+async fn replay(state: &AppState, name: &str, since: u64, deadline: Instant) -> Response {
+    let key = lease::key(&lease_name(name));
+    let leader = |lease: Option<&String>| lease.and_then(|lease| serde_json::from_str::<Lease>(lease).ok()).map(|lease| Leader::of(name, lease));
+    loop {
+        let page = match state.watches.since(since, &key) {
+            Ok(page) => page,
+            Err(from) => {
+                let refusal = format!("Changes after instance {} are no longer kept, observe from {} on!", since, from);
+                return (StatusCode::GONE, refusal).into_response();
+            },
+        };
+        // Renewals only move the expiry, and aren't a change of leader.
+        let changes: Vec<Succession> = page.events.iter()
+            .filter(|event| event.change.key == key)
+            .map(|event| (event.instance, leader(event.change.before.as_ref()), leader(event.change.after.as_ref())))
+            .filter(|(_, before, after)| before.as_ref().map(|l| (&l.leader, &l.value)) != after.as_ref().map(|l| (&l.leader, &l.value)))
+            .map(|(instance, _, leader)| Succession { instance, leader })
+            .collect();
+        if !changes.is_empty() || Instant::now() >= deadline {
+            return Json(Successions { index: page.index, changes }).into_response();
+        }
+        let _ = tokio::time::timeout_at(deadline, state.applier.applied(page.index + 1)).await;
+    }
+}
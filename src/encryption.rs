@@ -0,0 +1,197 @@
+//! Encryption at rest for the log and snapshots.
+//!
+//! With `--encryption-key <file>`, a node seals every log entry and every
+//! snapshot it writes with ChaCha20-Poly1305 before it goes to disk. The
+//! file is a key ring: one `<id> <64 hex digits>` line per key, the last of
+//! which seals what is written from then on. Whatever is sealed names the
+//! id of its key, so putting a new key at the end and restarting rotates
+//! keys without rewriting anything: entries and snapshots sealed under an
+//! older key are still opened with it, and the older key can leave the
+//! file once a snapshot has replaced all of them.
+//!
+//! A sealed entry is a [`Sealed`] line in place of the entry's own, so the
+//! log still ends at the last complete line after a crash; a sealed
+//! snapshot, or backup, is one whole [`Sealed`]. Each is tied to what it was
+//! written as, so one can't be passed off for another.
+//!
+//! Once a node has keys, anything not sealed is refused, so no one can slip
+//! plain entries into its directory. A directory written without a key
+//! carries on sealed with `--encryption-migrate`, which reads what isn't
+//! sealed yet, until a snapshot has replaced all of it.
+
+use std::{
+    borrow::Cow,
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, aead::{Aead, Payload}};
+use serde::{Serialize, Deserialize};
+
+use crate::hex;
+
+pub const KEY_BYTES: usize = 32;
+const NONCE_BYTES: usize = 12;
+
+/// What sealed bytes start with; plain log entries and snapshots never do.
+const SEALED_PREFIX: &[u8] = br#"{"key":"#;
+
+/// What was sealed, which goes into its tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    Wal,
+    Snapshot,
+    Backup,
+}
+
+impl Purpose {
+    fn aad(self, key: &str) -> Vec<u8> {
+        let purpose = match self {
+            Purpose::Wal => "wal",
+            Purpose::Snapshot => "snapshot",
+            Purpose::Backup => "backup",
+        };
+        format!("paxos-{}\n{}", purpose, key).into_bytes()
+    }
+}
+
+/// Bytes sealed under the key with id `key`: the nonce, the ciphertext and
+/// the tag, in hex.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Sealed {
+    pub key: String,
+    pub sealed: String,
+}
+
+/// What [`Keyring::open`] got out of some bytes.
+#[derive(Debug)]
+pub struct Opened<'a> {
+    pub bytes: Cow<'a, [u8]>,
+    /// The key they were sealed with, or none if they weren't.
+    pub key: Option<String>,
+}
+
+/// The keys a node seals with and opens with; without any, it writes
+/// everything in the clear.
+#[derive(Default)]
+pub struct Keyring {
+    /// In the order of the file, the current key last.
+    keys: Vec<(String, [u8; KEY_BYTES])>,
+    /// Random for every ring, so nonces from two runs don't meet.
+    nonce_base: [u8; NONCE_BYTES],
+    sealed: AtomicU64,
+    /// Whether what isn't sealed is read too.
+    migrating: bool,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids: Vec<&str> = self.keys.iter().map(|(id, _)| id.as_str()).collect();
+        f.debug_struct("Keyring").field("keys", &ids).finish_non_exhaustive()
+    }
+}
+
+fn invalid(e: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.into())
+}
+
+impl Keyring {
+    /// Reads the key ring in `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// A key ring from lines of `<id> <64 hex digits>`; blank lines and
+    /// lines starting with `#` are skipped.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut keys: Vec<(String, [u8; KEY_BYTES])> = Vec::new();
+        for (n, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let (Some(id), Some(key), None) = (words.next(), words.next(), words.next()) else {
+                return Err(invalid(format!("line {}: expected `<id> <key>`", n)));
+            };
+            let key = hex::decode::<KEY_BYTES>(key).ok_or_else(|| invalid(format!("line {}: the key must be 32 bytes of hex", n)))?;
+            if keys.iter().any(|(known, _)| known == id) {
+                return Err(invalid(format!("line {}: key {} is given twice", n, id)));
+            }
+            keys.push((id.to_string(), key));
+        }
+        if keys.is_empty() {
+            return Err(invalid("no keys"));
+        }
+
+        let mut nonce_base = [0u8; NONCE_BYTES];
+        File::open("/dev/urandom")?.read_exact(&mut nonce_base)?;
+        Ok(Self { keys, nonce_base, sealed: AtomicU64::new(0), migrating: false })
+    }
+
+    /// Also reads what isn't sealed, if `migrating`, to carry on a
+    /// directory written without a key.
+    pub fn migrating(self, migrating: bool) -> Self {
+        Self { migrating, ..self }
+    }
+
+    /// The id of the key new data is sealed with.
+    pub fn current(&self) -> Option<&str> {
+        self.keys.last().map(|(id, _)| id.as_str())
+    }
+
+    fn key(&self, id: &str) -> Option<&[u8; KEY_BYTES]> {
+        self.keys.iter().find(|(known, _)| known == id).map(|(_, key)| key)
+    }
+
+    /// A nonce no other seal from this ring uses.
+    fn nonce(&self) -> [u8; NONCE_BYTES] {
+        let count = self.sealed.fetch_add(1, Ordering::SeqCst).to_le_bytes();
+        let mut nonce = self.nonce_base;
+        for (byte, count) in nonce.iter_mut().zip(count) {
+            *byte ^= count;
+        }
+        nonce
+    }
+
+    /// `plaintext` sealed under the current key, or as it is without one.
+    pub fn seal(&self, purpose: Purpose, plaintext: Vec<u8>) -> Vec<u8> {
+        let Some((id, key)) = self.keys.last() else {
+            return plaintext;
+        };
+        let nonce = self.nonce();
+        let payload = Payload { msg: &plaintext, aad: &purpose.aad(id) };
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(key)).encrypt(Nonce::from_slice(&nonce), payload).expect("anything fits in one message");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        serde_json::to_vec(&Sealed { key: id.clone(), sealed: hex::encode(&sealed) }).unwrap()
+    }
+
+    /// What [`Keyring::seal`] made of some bytes. Bytes that aren't sealed
+    /// pass as they are only without keys, or while migrating; sealed ones
+    /// that this ring has no key for, or that don't open, are an error.
+    pub fn open<'a>(&self, purpose: Purpose, bytes: &'a [u8]) -> Result<Opened<'a>, String> {
+        self.open_or_plain(purpose, bytes, self.keys.is_empty() || self.migrating)
+    }
+
+    /// Like [`Keyring::open`], but passes bytes that aren't sealed if
+    /// `plain` says so, whatever the ring.
+    pub fn open_or_plain<'a>(&self, purpose: Purpose, bytes: &'a [u8], plain: bool) -> Result<Opened<'a>, String> {
+        let sealed = bytes.starts_with(SEALED_PREFIX).then(|| serde_json::from_slice::<Sealed>(bytes).ok()).flatten();
+        let Some(sealed) = sealed else {
+            if !plain {
+                return Err(String::from("isn't sealed, which takes --encryption-migrate while a directory written without a key is sealed"));
+            }
+            return Ok(Opened { bytes: Cow::Borrowed(bytes), key: None });
+        };
+
+        let key = self.key(&sealed.key).ok_or_else(|| format!("sealed with key {}, which isn't in the key ring", sealed.key))?;
+        let raw = hex::decode_vec(&sealed.sealed).filter(|raw| raw.len() >= NONCE_BYTES).ok_or("the sealed bytes aren't hex")?;
+        let (nonce, ciphertext) = raw.split_at(NONCE_BYTES);
+        let payload = Payload { msg: ciphertext, aad: &purpose.aad(&sealed.key) };
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(key)).decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| format!("doesn't open with key {}, it was changed or sealed as something else", sealed.key))?;
+        Ok(Opened { bytes: Cow::Owned(plaintext), key: Some(sealed.key) })
+    }
+}
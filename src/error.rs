@@ -0,0 +1,76 @@
+//! Why a proposal, or anything else the node does for a client, failed.
+//!
+//! [`PaxosError`] is what the proposer, the ways a value gets to it and the
+//! log return. Each kind answers a client with a status of its own, see
+//! [`PaxosError::status`], so a client can tell a proposal that lost to
+//! another one, and is worth retrying, from a node that can't reach a
+//! quorum or failed to write its log.
+
+use std::{io, net::SocketAddr};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use thiserror::Error;
+
+use crate::{Id, ProposalId};
+
+#[derive(Debug, Error)]
+pub enum PaxosError {
+    /// An acceptor promised a higher ballot than the proposal's.
+    #[error("Proposal was preempted by ballot {}.{}!", .0.round, .0.node_id)]
+    Preempted(ProposalId),
+    /// Too few acceptors answered a phase, and none of them refused.
+    #[error("Proposal not {phase} by a quorum!")]
+    NoQuorum { phase: &'static str },
+    /// Other values were chosen in every instance the proposal tried.
+    #[error("Proposal lost all {attempts} instances it tried!")]
+    InstancesTaken { attempts: usize },
+    #[error("Node {id} at {addr} is unreachable: {reason}")]
+    PeerUnreachable { id: Id, addr: SocketAddr, reason: String },
+    /// A peer the proposal went through refused it.
+    #[error("{message}")]
+    Peer { status: StatusCode, message: String },
+    /// The node couldn't write its log or intake.
+    #[error("Failed to persist: {0}")]
+    Storage(#[from] io::Error),
+    #[error("Failed to (de)serialize: {0}")]
+    Serialization(#[from] serde_json::Error),
+    /// The node can't take the proposal right now.
+    #[error("{0}")]
+    Unavailable(String),
+    /// The proposal itself can't be decided, here or anywhere.
+    #[error("{0}")]
+    Rejected(String),
+}
+
+impl PaxosError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Preempted(_) | Self::InstancesTaken { .. } => StatusCode::CONFLICT,
+            Self::NoQuorum { .. } | Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::PeerUnreachable { .. } => StatusCode::BAD_GATEWAY,
+            Self::Peer { status, .. } => *status,
+            Self::Storage(_) | Self::Serialization(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Rejected(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// The status and message to answer a client with.
+    pub fn refusal(&self) -> (StatusCode, String) {
+        (self.status(), self.to_string())
+    }
+}
+
+impl IntoResponse for PaxosError {
+    fn into_response(self) -> Response {
+        self.refusal().into_response()
+    }
+}
+
+/// So code that still reports errors as strings can pass these on with `?`.
+impl From<PaxosError> for String {
+    fn from(error: PaxosError) -> Self {
+        error.to_string()
+    }
+}
@@ -0,0 +1,117 @@
+//! State transitions, for visualizers.
+//!
+//! Every node keeps its last [`CAPACITY`] transitions (promises given and
+//! refused, values adopted, quorums reached, values learned...) in a ring
+//! buffer. `GET /events?since=<seq>` returns the ones after `seq`, so a
+//! visualizer can poll each node and animate what the cluster is doing.
+//! Sequence numbers are per node and never reused; when `oldest` is past
+//! the `since` a client asked for, it fell behind and missed some.
+
+use std::{collections::VecDeque, sync::Mutex};
+use axum::{
+    http::StatusCode,
+    extract::State
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{AppState, Ballot, Id, ProposalId, Value, history::now_micros, input::{Json, Query}};
+
+pub const CAPACITY: usize = 4096;
+
+/// Most events a single `/events` call returns.
+const PAGE: usize = 1000;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Transition {
+    /// We started a round as proposer.
+    PrepareSent { instance: u64, id: ProposalId },
+    PromiseGiven { instance: u64, id: ProposalId, accepted: Option<Ballot> },
+    PromiseRefused { instance: u64, id: ProposalId, promised: ProposalId },
+    /// A quorum promised, and this is the value the round goes on with:
+    /// our own, or one some acceptor already accepted or learned.
+    ValueAdopted { instance: u64, id: ProposalId, value: Value, own: bool },
+    Accepted { instance: u64, id: ProposalId, value: Option<Value> },
+    AcceptRefused { instance: u64, id: ProposalId, promised: ProposalId },
+    /// A quorum accepted our proposal, so its value is chosen.
+    QuorumReached { instance: u64, id: ProposalId, accepted: usize },
+    Learned { instance: u64, value: Value },
+    /// We had learned another value for the instance than a quorum did,
+    /// which only a bug can do, and took theirs; see `repair`.
+    Repaired { instance: u64, value: Value, was: Value },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub seq: u64,
+    /// Microseconds since the Unix epoch, on this node's clock.
+    pub time: u64,
+    pub node: Id,
+    #[serde(flatten)]
+    pub transition: Transition,
+}
+
+#[derive(Debug, Default)]
+struct Buffer {
+    next: u64,
+    events: VecDeque<Event>,
+}
+
+#[derive(Debug)]
+pub struct Events {
+    node: Id,
+    buffer: Mutex<Buffer>,
+}
+
+impl Events {
+    pub fn new(node: Id) -> Self {
+        Self { node, buffer: Mutex::new(Buffer { next: 1, events: VecDeque::new() }) }
+    }
+
+    pub fn record(&self, transition: Transition) {
+        let mut buffer = self.buffer.lock().unwrap();
+        let event = Event { seq: buffer.next, time: now_micros(), node: self.node, transition };
+        buffer.next += 1;
+
+        if buffer.events.len() == CAPACITY {
+            buffer.events.pop_front();
+        }
+        buffer.events.push_back(event);
+    }
+
+    /// Up to `limit` events with a sequence number above `since`.
+    pub fn since(&self, since: u64, limit: usize) -> EventsPage {
+        let buffer = self.buffer.lock().unwrap();
+        let events = buffer.events.iter()
+            .filter(|event| event.seq > since)
+            .take(limit)
+            .cloned()
+            .collect();
+
+        EventsPage {
+            oldest: buffer.events.front().map(|event| event.seq),
+            latest: buffer.next - 1,
+            events,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EventsPage {
+    /// Oldest event still in the buffer.
+    pub oldest: Option<u64>,
+    /// Latest event recorded, 0 before the first one.
+    pub latest: u64,
+    pub events: Vec<Event>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct EventsQuery {
+    pub since: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+pub async fn get_events(State(state): State<AppState>, Query(query): Query<EventsQuery>) -> (StatusCode, Json<EventsPage>) {
+    let limit = query.limit.unwrap_or(PAGE).min(PAGE);
+    (StatusCode::OK, Json(state.events.since(query.since.unwrap_or(0), limit)))
+}
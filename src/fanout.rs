@@ -0,0 +1,95 @@
+//! Capping how many requests a node has out at once.
+//!
+//! A round sends to every voter at once, and the learn after it to every
+//! peer again, so a big cluster, or many proposals in a row to a slow peer,
+//! can open more sockets than the OS allows. [`post_all`] lets at most
+//! `--max-requests-per-round` of one fan-out out at a time, and at most
+//! `--max-requests-per-peer` to any one peer across all of them; the rest
+//! wait their turn, in order. 0 is no limit for either. With zones,
+//! [`post_quorum`] asks the voters nearby before the rest.
+
+use std::{collections::HashMap, net::SocketAddr, sync::{Arc, Mutex}};
+use serde::Serialize;
+use tokio::sync::Semaphore;
+
+use crate::{AppState, Node, quorum::Quorum, transport::Reply};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub per_round: usize,
+    pub per_peer: usize,
+}
+
+/// A semaphore per peer, sized for the limit it was made under.
+#[derive(Debug, Default)]
+pub struct FanOut {
+    peers: Mutex<HashMap<SocketAddr, (usize, Arc<Semaphore>)>>,
+}
+
+impl FanOut {
+    /// The semaphore for `addr`. A changed limit gets a new one; requests
+    /// already waiting on the old one finish under it.
+    fn peer(&self, addr: SocketAddr, limit: usize) -> Option<Arc<Semaphore>> {
+        if limit == 0 {
+            return None;
+        }
+        let mut peers = self.peers.lock().unwrap();
+        let (size, semaphore) = peers.entry(addr).or_insert_with(|| (limit, Arc::new(Semaphore::new(limit))));
+        if *size != limit {
+            (*size, *semaphore) = (limit, Arc::new(Semaphore::new(limit)));
+        }
+        Some(semaphore.clone())
+    }
+}
+
+/// Posts `payload` to every one of `nodes` and answers their replies in the
+/// same order, within the limits above.
+pub async fn post_all<T: Serialize>(state: &AppState, nodes: &[Node], path: &str, payload: &T) -> Vec<Result<Reply, String>> {
+    let body = match serde_json::to_string(payload) {
+        Ok(body) => body,
+        Err(e) => return nodes.iter().map(|_| Err(e.to_string())).collect(),
+    };
+    let limits = state.settings.read().unwrap().fan_out;
+    let round = (limits.per_round > 0).then(|| Semaphore::new(limits.per_round));
+
+    let reqs = nodes.iter().map(|node| {
+        let (round, body) = (round.as_ref(), body.clone());
+        let peer = state.fan_out.peer(node.addr, limits.per_peer);
+        async move {
+            let _round = match round {
+                Some(round) => Some(round.acquire().await.map_err(|e| e.to_string())?),
+                None => None,
+            };
+            let _peer = match peer {
+                Some(peer) => Some(peer.acquire_owned().await.map_err(|e| e.to_string())?),
+                None => None,
+            };
+            state.transport.post(node.addr, path, body).await
+        }
+    });
+    futures::future::join_all(reqs).await
+}
+
+/// Posts `payload` to the voters `quorum` asks first, and to the rest of
+/// `voters` only if those that took it don't make a quorum. Answers every
+/// voter asked with its reply; see `quorum`.
+pub async fn post_quorum<T: Serialize>(state: &AppState, voters: &[Node], quorum: &Quorum, path: &str, payload: &T) -> Vec<(Node, Result<Reply, String>)> {
+    let Some(nearby) = quorum.nearby(state.node.id) else {
+        let responses = post_all(state, voters, path, payload).await;
+        return voters.iter().cloned().zip(responses).collect();
+    };
+
+    let (first, rest): (Vec<Node>, Vec<Node>) = voters.iter().cloned().partition(|node| nearby.contains(&node.id));
+    let responses = post_all(state, &first, path, payload).await;
+    let mut replies: Vec<_> = first.into_iter().zip(responses).collect();
+
+    let took = replies.iter()
+        .filter(|(_, response)| response.as_ref().is_ok_and(|reply| !reply.is_error()))
+        .map(|(node, _)| node.id)
+        .collect();
+    if !quorum.is_met(&took) && !rest.is_empty() {
+        let responses = post_all(state, &rest, path, payload).await;
+        replies.extend(rest.into_iter().zip(responses));
+    }
+    replies
+}
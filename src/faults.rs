@@ -0,0 +1,307 @@
+//! Runtime fault injection for node-to-node traffic.
+//!
+//! Rules are installed through `/admin/faults` and apply to messages to
+//! (outbound) or from (inbound) specific peers. On top of them, a partition
+//! installed through `/admin/partition` refuses all traffic with peers outside
+//! our group. Outbound faults are applied by [`FaultyTransport`], inbound ones
+//! by the [`inbound`] middleware, which recognises peer traffic by the
+//! [`NODE_ID_HEADER`] every transport sets. Admin traffic is never faulted, so
+//! a partition can always be healed.
+
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use axum::{
+    body::{Body, to_bytes},
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState, Id,
+    groups,
+    membership::Membership,
+    rng::Rng,
+    transport::{NODE_ID_HEADER, Reply, Transport},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+    #[default]
+    Both,
+}
+
+impl Direction {
+    fn covers(self, direction: Direction) -> bool {
+        self == Direction::Both || self == direction
+    }
+}
+
+/// One fault rule. All rates are percentages of the matching messages.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FaultRule {
+    /// Assigned by the node when the rule is installed.
+    pub id: u64,
+    /// Peers the rule applies to; empty means every peer.
+    pub peers: Vec<Id>,
+    pub direction: Direction,
+    pub drop: f64,
+    pub delay: f64,
+    pub delay_ms: u64,
+    pub duplicate: f64,
+    pub corrupt: f64,
+}
+
+impl FaultRule {
+    pub fn validate(&self) -> Result<(), String> {
+        let rates = [("drop", self.drop), ("delay", self.delay), ("duplicate", self.duplicate), ("corrupt", self.corrupt)];
+        for (name, rate) in rates {
+            if !(0.0..=100.0).contains(&rate) {
+                return Err(format!("`{}` must be a percentage between 0 and 100", name));
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, peer: Id, direction: Direction) -> bool {
+        self.direction.covers(direction) && (self.peers.is_empty() || self.peers.contains(&peer))
+    }
+}
+
+/// What should happen to one particular message.
+#[derive(Debug, Default)]
+pub struct FaultAction {
+    pub drop: bool,
+    pub delay: Option<Duration>,
+    pub duplicate: bool,
+    pub corrupt: bool,
+}
+
+impl FaultAction {
+    pub fn is_noop(&self) -> bool {
+        !self.drop && self.delay.is_none() && !self.duplicate && !self.corrupt
+    }
+}
+
+struct FaultState {
+    rules: Vec<FaultRule>,
+    next_id: u64,
+    partition: Option<Vec<Vec<Id>>>,
+    rng: Rng,
+}
+
+pub struct Faults {
+    node_id: Id,
+    state: Mutex<FaultState>,
+}
+
+impl fmt::Debug for Faults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Faults").field("rules", &self.rules().len()).finish()
+    }
+}
+
+impl Faults {
+    pub fn new(node_id: Id, rng: Rng) -> Self {
+        let state = FaultState { rules: Vec::new(), next_id: 1, partition: None, rng };
+        Self { node_id, state: Mutex::new(state) }
+    }
+
+    pub fn reseed(&self, seed: u64) {
+        self.state.lock().unwrap().rng = Rng::new(seed);
+    }
+
+    pub fn rules(&self) -> Vec<FaultRule> {
+        self.state.lock().unwrap().rules.clone()
+    }
+
+    pub fn add(&self, mut rule: FaultRule) -> FaultRule {
+        let mut state = self.state.lock().unwrap();
+        rule.id = state.next_id;
+        state.next_id += 1;
+        state.rules.push(rule.clone());
+        rule
+    }
+
+    pub fn remove(&self, id: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let before = state.rules.len();
+        state.rules.retain(|rule| rule.id != id);
+        state.rules.len() != before
+    }
+
+    pub fn clear(&self) {
+        self.state.lock().unwrap().rules.clear();
+    }
+
+    pub fn partition(&self) -> Option<Vec<Vec<Id>>> {
+        self.state.lock().unwrap().partition.clone()
+    }
+
+    /// Installs (or with `None`, heals) a partition. Nodes missing from every
+    /// group are cut off from everyone.
+    pub fn set_partition(&self, groups: Option<Vec<Vec<Id>>>) {
+        self.state.lock().unwrap().partition = groups;
+    }
+
+    /// Rolls every matching rule for one message; effects of several rules add up.
+    pub fn roll(&self, peer: Id, direction: Direction) -> FaultAction {
+        let mut state = self.state.lock().unwrap();
+        let FaultState { rules, partition, rng, .. } = &mut *state;
+
+        let mut action = FaultAction::default();
+
+        if let Some(groups) = partition {
+            let group_of = |id| groups.iter().position(|group| group.contains(&id));
+            if group_of(self.node_id).is_none() || group_of(self.node_id) != group_of(peer) {
+                action.drop = true;
+                return action;
+            }
+        }
+
+        for rule in rules.iter().filter(|rule| rule.matches(peer, direction)) {
+            action.drop |= rng.chance(rule.drop / 100.0);
+            action.duplicate |= rng.chance(rule.duplicate / 100.0);
+            action.corrupt |= rng.chance(rule.corrupt / 100.0);
+            if rng.chance(rule.delay / 100.0) {
+                let delay = Duration::from_millis(rule.delay_ms);
+                action.delay = Some(action.delay.map_or(delay, |current| current + delay));
+            }
+        }
+        action
+    }
+
+    /// Overwrites a random byte with a random printable character.
+    pub fn corrupt(&self, bytes: &mut [u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let index = state.rng.below(bytes.len() as u64) as usize;
+        bytes[index] = b'!' + state.rng.below(94) as u8;
+    }
+}
+
+/// Applies outbound faults before handing the message to the real transport.
+pub struct FaultyTransport {
+    inner: Arc<dyn Transport>,
+    faults: Arc<Faults>,
+    nodes: Arc<Membership>,
+}
+
+impl FaultyTransport {
+    pub fn new(inner: Arc<dyn Transport>, faults: Arc<Faults>, nodes: Arc<Membership>) -> Self {
+        Self { inner, faults, nodes }
+    }
+}
+
+impl fmt::Debug for FaultyTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl Transport for FaultyTransport {
+    fn post(&self, addr: SocketAddr, path: &str, body: String) -> BoxFuture<'static, Result<Reply, String>> {
+        let inner = self.inner.clone();
+        let faults = self.faults.clone();
+        let nodes = self.nodes.clone();
+        let path = path.to_string();
+
+        Box::pin(async move {
+            if is_admin(&path) {
+                return inner.post(addr, &path, body).await;
+            }
+
+            let peer = nodes.at(addr);
+            let Some(action) = peer.map(|peer| faults.roll(peer, Direction::Outbound)).filter(|a| !a.is_noop()) else {
+                return inner.post(addr, &path, body).await;
+            };
+
+            if let Some(delay) = action.delay {
+                tokio::time::sleep(delay).await;
+            }
+
+            if action.drop {
+                println!("[faults] dropping {} to {}", path, addr);
+                return Err(format!("fault injected: {} to {} dropped", path, addr));
+            }
+
+            let mut bytes = body.into_bytes();
+            if action.corrupt {
+                println!("[faults] corrupting {} to {}", path, addr);
+                faults.corrupt(&mut bytes);
+            }
+            let body = String::from_utf8_lossy(&bytes).into_owned();
+
+            if action.duplicate {
+                println!("[faults] duplicating {} to {}", path, addr);
+                let _ = inner.post(addr, &path, body.clone()).await;
+            }
+
+            inner.post(addr, &path, body).await
+        })
+    }
+}
+
+fn is_admin(path: &str) -> bool {
+    groups::split_path(path).1.starts_with("/admin/")
+}
+
+/// Applies inbound faults to requests coming from other nodes.
+pub async fn inbound(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let peer = request.headers()
+        .get(NODE_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<Id>().ok());
+
+    let Some(peer) = peer.filter(|_| !is_admin(request.uri().path())) else {
+        return next.run(request).await;
+    };
+
+    let action = state.faults.roll(peer, Direction::Inbound);
+    if action.is_noop() {
+        return next.run(request).await;
+    }
+
+    let path = request.uri().path().to_string();
+
+    if let Some(delay) = action.delay {
+        tokio::time::sleep(delay).await;
+    }
+
+    if action.drop {
+        println!("[faults] dropping {} from node {}", path, peer);
+        return (StatusCode::SERVICE_UNAVAILABLE, "fault injected: message dropped").into_response();
+    }
+
+    let (parts, body) = request.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let mut bytes = bytes.to_vec();
+
+    if action.corrupt {
+        println!("[faults] corrupting {} from node {}", path, peer);
+        state.faults.corrupt(&mut bytes);
+    }
+
+    if action.duplicate {
+        println!("[faults] duplicating {} from node {}", path, peer);
+        let duplicate = Request::from_parts(parts.clone(), Body::from(bytes.clone()));
+        let _ = next.clone().run(duplicate).await;
+    }
+
+    next.run(Request::from_parts(parts, Body::from(bytes))).await
+}
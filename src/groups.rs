@@ -0,0 +1,206 @@
+//! Hosting many Paxos groups in one node.
+//!
+//! Next to its own log, a node can host groups of its own: the shards of
+//! `shards`, and named groups from `--group <id>`, each a logical cluster
+//! with its own key space, reached with `?group=<id>` on `/kv`. Every
+//! group has its own acceptor, proposer, ledger, learns, certificates and
+//! KV store; membership, limits, settings and signing keys are the node's,
+//! so all its groups run on the same peers.
+//!
+//! Each message of a hosted group carries the group's id in its path: a
+//! group's [`GroupTransport`] puts [`prefix`]`(id)` in front of everything
+//! it sends, and the router hands what comes in under it to that group on
+//! the peer. Signatures cover the whole path, so a message signed for one
+//! group doesn't pass in another. The node's own log keeps its paths as
+//! they were, so a node hosting no groups talks to older peers unchanged.
+//!
+//! The groups are fixed when the node starts. With a data directory a named
+//! group keeps its log and snapshots in `groups/<id>` there. `GET
+//! /admin/groups` lists them and what they hold, and `GET /metrics` has
+//! the same labelled by group.
+
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+use axum::{extract::State, http::StatusCode};
+use futures::future::BoxFuture;
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState,
+    acceptor::Acceptor,
+    apply::Applier,
+    chain::Certificates,
+    input::Json,
+    kv::Kv,
+    learns::Learns,
+    ledger::SharedLedger,
+    pbft::Pbft,
+    proposer::ProposerHandle,
+    streams::Streams,
+    transport::{Reply, Transport},
+};
+
+pub type GroupId = String;
+
+/// Where a data directory keeps the named groups, and where their messages go.
+pub const DIR: &str = "groups";
+
+/// What the ids of shards start with, which named groups can't.
+pub const SHARD: &str = "shard-";
+
+/// The path group `id` takes its messages under.
+pub fn prefix(id: &str) -> String {
+    format!("/{}/{}", DIR, id)
+}
+
+/// `path` as the group it is for sees it, and which group that is; none
+/// for the node's own.
+pub fn split_path(path: &str) -> (Option<&str>, &str) {
+    let Some(rest) = path.strip_prefix('/').and_then(|rest| rest.strip_prefix(DIR)).and_then(|rest| rest.strip_prefix('/')) else {
+        return (None, path);
+    };
+    let (id, inner) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if is_valid(id) || id.starts_with(SHARD) {
+        (Some(id), inner)
+    } else {
+        (None, path)
+    }
+}
+
+/// Whether `id` can name a group of `--group`.
+pub fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && !id.starts_with(SHARD) && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+/// Sends what a group sends to the same group on the peers.
+#[derive(Debug)]
+pub struct GroupTransport {
+    inner: Arc<dyn Transport>,
+    prefix: String,
+}
+
+impl Transport for GroupTransport {
+    fn post(&self, addr: SocketAddr, path: &str, body: String) -> BoxFuture<'static, Result<Reply, String>> {
+        self.inner.post(addr, &format!("{}{}", self.prefix, path), body)
+    }
+}
+
+/// Group `id` of the node `main`: consensus state of its own, and
+/// everything else shared with the node.
+pub fn group(main: &AppState, id: &str) -> AppState {
+    AppState {
+        acceptor: Arc::new(tokio::sync::Mutex::new(Acceptor::default())),
+        proposer: ProposerHandle::default(),
+        pbft: Arc::new(Pbft::default()),
+        ledger: Arc::new(SharedLedger::default()),
+        certificates: Arc::new(Certificates::default()),
+        learns: Arc::new(Learns::default()),
+        shipper: Arc::default(),
+        prevote: Arc::default(),
+        dedup: Arc::default(),
+        takeovers: Arc::default(),
+        streams: Arc::new(Streams::new(main.streams.count())),
+        epoch: Arc::default(),
+        rounds: Arc::default(),
+        kv: Arc::new(tokio::sync::Mutex::new(Kv::with_machine(main.machine.clone()))),
+        applier: Arc::new(Applier::default()),
+        hooks: Arc::default(),
+        watches: Arc::default(),
+        // A trace replays a single log, and the group joins no multicast
+        // group, so its learns go over HTTP.
+        trace: None,
+        storage: None,
+        #[cfg(feature = "s3")]
+        s3: None,
+        multicast: None,
+        groups: Arc::default(),
+        transport: Arc::new(GroupTransport { inner: main.transport.clone(), prefix: prefix(id) }),
+        ..main.clone()
+    }
+}
+
+/// The groups a node hosts besides its own.
+#[derive(Debug, Default)]
+pub struct Groups {
+    by_id: BTreeMap<GroupId, AppState>,
+    /// Ids of the shards, in order.
+    shards: Vec<GroupId>,
+}
+
+impl Groups {
+    /// Hosts `group` as `id`; a shard, if its id says so.
+    pub fn host(&mut self, id: GroupId, group: AppState) {
+        if id.starts_with(SHARD) {
+            self.shards.push(id.clone());
+        }
+        self.by_id.insert(id, group);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&AppState> {
+        self.by_id.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&GroupId, &AppState)> {
+        self.by_id.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    /// The shards the keys of `/kv` are split between, if they are.
+    pub fn shards(&self) -> impl Iterator<Item = &AppState> {
+        self.shards.iter().map(|id| &self.by_id[id])
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn shard(&self, index: usize) -> Option<&AppState> {
+        self.by_id.get(self.shards.get(index)?)
+    }
+}
+
+/// The group a client names, if it does, or the node's own.
+pub fn named<'a>(state: &'a AppState, id: Option<&str>) -> Result<&'a AppState, (StatusCode, String)> {
+    let Some(id) = id else {
+        return Ok(state);
+    };
+    match state.groups.get(id).filter(|_| !id.starts_with(SHARD)) {
+        Some(group) => Ok(group),
+        None => Err((StatusCode::NOT_FOUND, format!("Node {} hosts no group {}!", state.node.id, id))),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupStatus {
+    pub id: GroupId,
+    pub instances: usize,
+    pub keys: usize,
+    pub apply_lag: u64,
+    pub learns_pending: usize,
+}
+
+pub async fn status(state: &AppState) -> Vec<GroupStatus> {
+    let mut groups = Vec::with_capacity(state.groups.len());
+    for (id, group) in state.groups.iter() {
+        let keys = group.kv.lock().await.data.len();
+        groups.push(GroupStatus {
+            id: id.clone(),
+            instances: group.ledger.len(),
+            keys,
+            apply_lag: group.applier.lag(),
+            learns_pending: group.learns.pending(),
+        });
+    }
+    groups
+}
+
+/// What each group learned, and how many keys it holds.
+pub async fn get_groups(State(state): State<AppState>) -> Json<Vec<GroupStatus>> {
+    Json(status(&state).await)
+}
@@ -0,0 +1,523 @@
+use std::{collections::BTreeMap, net::SocketAddr};
+use axum::{
+    http::{HeaderMap, StatusCode},
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+use tokio::time::Instant;
+
+use crate::{
+    AppState, Ballot, Node, ProposalId, Value,
+    acceptor::RangePromise,
+    acl, admin,
+    backpressure, chain, disk,
+    chunked::Upload,
+    error::PaxosError,
+    events::Transition,
+    input::{Invalid, Json},
+    intake,
+    kv::Command,
+    learns::{self, Committed, Gossip},
+    pbft,
+    proposer::Proposer,
+    quota,
+    readonly::{self, ReadOnly},
+    schema,
+    shutdown,
+    status,
+    step::{self, Pending, Phase},
+    storage::{self, Record},
+    streams::Stream,
+    trace::{self, Step},
+    transfer,
+    transport::{NODE_ID_HEADER, post_json},
+    wire::{Accept, Accepted, Join, JoinAck, Learn, Nack, Prepare, Promise},
+};
+
+/// How many instances a single `/prepare` call walks through before giving up
+/// on finding one where its own value is chosen.
+const MAX_INSTANCE_ATTEMPTS: usize = 16;
+
+/// The most instances a single `/handle-prepare-range` may promise.
+pub const MAX_PREPARE_AHEAD: u64 = 1_000_000;
+
+pub async fn connect(State(state): State<AppState>, value: String) -> Response {
+    let Ok(port) = value.trim().parse::<u16>() else {
+        return Invalid::new(format!("`{}` is not a port!", value.trim())).into_response();
+    };
+
+    match join(&state, port).await {
+        Ok(answer) => {
+            state.joins.done(port);
+            answer.into_response()
+        },
+        Err(e) => {
+            let now = Instant::now();
+            let next = state.joins.failed(port, e.clone(), now);
+            println!("[/connect] Port {} is unreachable ({}), trying again in {:?}", port, e, next - now);
+            (StatusCode::ACCEPTED, format!("Nothing answers at port {} yet, trying again in the background!", port)).into_response()
+        },
+    }
+}
+
+/// Pings the node at `port` and adds it to the known peers. Answers what
+/// to tell the client once the peer answered, however it did, and why
+/// not if it didn't.
+pub async fn join(state: &AppState, port: u16) -> Result<(StatusCode, String), String> {
+    let peer = SocketAddr::from(([0, 0, 0, 0], port));
+    let res = post_json(state.transport.as_ref(), peer, "/ping", &Join::of(state)).await?;
+    if res.is_error() {
+        return Ok((StatusCode::BAD_REQUEST, res.body));
+    }
+
+    // The peer's reply is not the client's doing, hence a 502.
+    let Ok(ack) = serde_json::from_str::<JoinAck>(&res.body) else {
+        return Ok((StatusCode::BAD_GATEWAY, format!("Node at {} answered its ping with {}!", peer, res.body)));
+    };
+    let protocol = match state.versions.negotiate(ack.id, ack.protocol) {
+        Ok(protocol) => protocol,
+        Err(e) => return Ok((StatusCode::BAD_REQUEST, e)),
+    };
+    if let Err(e) = state.keys.heard(ack.id, ack.public_key.as_deref()) {
+        return Ok((StatusCode::BAD_REQUEST, e));
+    }
+    if let Some(multicast) = &state.multicast {
+        multicast.heard(ack.id, ack.multicast.as_deref());
+    }
+
+    let peer = ack.node();
+    state.nodes.update(|nodes| nodes.push(peer.clone()));
+
+    println!("[/connect] sync new node: {} - ID: {} (protocol {})", peer.addr, peer.id, protocol);
+
+    if !state.is_syncing() && transfer::is_fresh(state).await {
+        transfer::join(state, peer);
+    }
+
+    Ok((StatusCode::OK, format!("Conneted to new voter: {}!", port)))
+}
+
+pub async fn ping(State(state): State<AppState>, Json(join): Json<Join>) -> Result<Json<JoinAck>, (StatusCode, Json<Nack>)> {
+    let refuse = |status: StatusCode, error: String| (status, Json(Nack::new(error)));
+
+    if join.id == state.node.id {
+        return Err(refuse(StatusCode::BAD_REQUEST, String::from("You can't connect in the same node!")));
+    }
+
+    // Checked before anything else, so an incompatible node is turned away
+    // as if it never pinged. A compatible one that is already known still
+    // gets its version updated, as it may have been upgraded.
+    let protocol = state.versions.negotiate(join.id, join.protocol).map_err(|e| refuse(StatusCode::BAD_REQUEST, e))?;
+    state.keys.heard(join.id, join.public_key.as_deref()).map_err(|e| refuse(StatusCode::FORBIDDEN, e))?;
+    if let Some(multicast) = &state.multicast {
+        multicast.heard(join.id, join.multicast.as_deref());
+    }
+
+    // A peer that left is still a voter; it only needs its address updated.
+    if state.departed.lock().await.remove(&join.id) {
+        state.nodes.update(|nodes| {
+            if let Some(node) = nodes.iter_mut().find(|node| node.id == join.id) {
+                *node = join.node();
+            }
+        });
+        println!("[/ping] Node {} is back at {}, speaking protocol {}", join.id, join.addr, protocol);
+
+        return Ok(Json(Join::of(&state)));
+    }
+
+    let joined = state.nodes.update(|nodes| {
+        if nodes.iter().any(|node| node.id == join.id) {
+            return false;
+        }
+        nodes.push(join.node());
+        true
+    });
+    if !joined {
+        return Err(refuse(StatusCode::BAD_REQUEST, String::from("You're already connected in this node!")));
+    }
+
+    println!("[/ping] updated state: {:?}", state);
+
+    Ok(Json(Join::of(&state)))
+}
+
+pub async fn get_node_state(State(state): State<AppState>) -> Json<Node> {
+    println!("[/] State: {:?}", state);
+    Json(state.node)
+}
+
+pub async fn prepare(State(state): State<AppState>, headers: HeaderMap, Upload(value): Upload) -> Response {
+    let reply = propose_upload(&state, &headers, value).await;
+    status::stamped(&state, reply)
+}
+
+async fn propose_upload(state: &AppState, headers: &HeaderMap, value: Value) -> (StatusCode, String) {
+    let grant = match acl::check_value(state, headers, &value).await {
+        Ok(grant) => grant,
+        Err(refusal) => return refusal,
+    };
+    if let Some(Command::Put { key, value }) = Command::parse(&value) {
+        if let Err(refusal) = schema::admit(state, None, &key, &value).await {
+            return refusal;
+        }
+    }
+    if state.is_paused() {
+        return admin::refuse_paused();
+    }
+    if state.read_only() == Some(ReadOnly::Reject) {
+        return readonly::refuse();
+    }
+    if state.disk.is_low() {
+        return disk::refuse();
+    }
+    let Some(_proposal) = state.shutdown.enter() else {
+        return shutdown::refuse();
+    };
+    if let Err(refusal) = quota::admit(state, grant.as_ref(), value.len()).await {
+        return refusal;
+    }
+    let limits = state.settings.read().unwrap().backpressure;
+    let Some(_slot) = state.backpressure.admit(limits).await else {
+        println!("[/prepare] Node {} is turning a proposal away, {} are queued", state.node.id, state.backpressure.queued());
+        return backpressure::refuse();
+    };
+
+    let bytes = value.len();
+    let result = intake::submit(state, value).await;
+    quota::record(state, grant.as_ref(), bytes, &result);
+    match result {
+        Err(e) => e.refusal(),
+        Ok(instance) => (StatusCode::OK, format!("Proposal accepted by the majority at instance {}!", instance)),
+    }
+}
+
+/// Runs Paxos until `value` is chosen for some instance and returns it,
+/// or has PBFT decide it with `--byzantine`.
+///
+/// Only queues the proposal for the node's proposer task, so the caller
+/// holds nothing while the rounds go over the network.
+pub async fn propose_value(state: &AppState, value: Value) -> Result<u64, PaxosError> {
+    if state.pbft.is_enabled() {
+        return pbft::propose(state, value).await;
+    }
+    state.streams.route(state, &value).propose(state, value).await
+}
+
+/// The proposal itself, run by the proposer task, which alone owns `proposer`.
+pub(crate) async fn run_proposal(state: &AppState, proposer: &mut Proposer, stream: Stream, value: Value) -> Result<u64, PaxosError> {
+    // Losing an instance to an older accepted value is not a failure, it just
+    // means our value has to go into the next one.
+    for _ in 0..MAX_INSTANCE_ATTEMPTS {
+        let instance = stream.next_instance(state);
+
+        step::gate(state, Pending::prepare(instance, &value)).await;
+        let ahead = match stream.count {
+            1 => proposer.prepare_ahead(state, instance, &value).await,
+            _ => None,
+        };
+        let ballot = match ahead {
+            Some(ballot) => ballot,
+            None => proposer.prepare(state, instance, value.clone()).await?,
+        };
+
+        step::gate(state, Pending::ballot(Phase::Accept, &ballot)).await;
+        let certificate = proposer.propose(state, &ballot).await?;
+        // Learners get no accepts, so with any around the learn has to go out.
+        let everywhere = certificate.acks.len() == certificate.voters && state.nodes.snapshot().iter().all(|node| !node.learner);
+
+        step::gate(state, Pending::ballot(Phase::Learn, &ballot)).await;
+
+        learn(state, &ballot).await;
+        chain::certify(state, certificate).await;
+        state.learns.push(state, ballot.clone(), everywhere);
+
+        if ballot.value.as_ref() == Some(&value) {
+            return Ok(instance);
+        }
+
+        println!("[/prepare] Instance {} was already taken, retrying on the next one", instance);
+    }
+
+    Err(PaxosError::InstancesTaken { attempts: MAX_INSTANCE_ATTEMPTS })
+}
+
+pub async fn handle_prepare(State(state): State<AppState>, Json(ballot): Json<Prepare>) -> (StatusCode, Json<Promise>) {
+    if state.is_paused() {
+        let payload = Promise::from(Nack::new(admin::PAUSED));
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(payload));
+    }
+    if state.disk.is_low() {
+        let payload = Promise::from(Nack::new(disk::LOW_ON_SPACE));
+        return (StatusCode::INSUFFICIENT_STORAGE, Json(payload));
+    }
+
+    let mut trace = trace::begin(&state).await;
+    let (status, payload) = promise(&state, &ballot).await;
+
+    if let Some(trace) = &mut trace {
+        trace.record(&state, Step::Prepare { ballot, reply: payload.clone() }).await;
+    }
+
+    (status, Json(payload))
+}
+
+pub(crate) async fn promise(state: &AppState, ballot: &Ballot) -> (StatusCode, Promise) {
+    if let Some(decided) = state.ledger.get(ballot.instance) {
+        println!("[/handle-prepare] Node {} already learned instance {}", state.node.id, ballot.instance);
+        let payload = Promise::decided(decided);
+        return (StatusCode::OK, payload);
+    }
+
+    let mut acceptor = state.acceptor.lock().await;
+
+    match acceptor.prepare(ballot) {
+        Err(promised) => {
+            state.events.record(Transition::PromiseRefused { instance: ballot.instance, id: ballot.id, promised });
+            let payload = Promise::from(Nack::outbid("The proposal ID is lesser than the last accepted ballot number", promised));
+            (StatusCode::BAD_REQUEST, payload)
+        },
+        Ok(value) => {
+            // Syncing the log waits for others to share it, so not under the
+            // acceptor's lock; anyone answering from this promise meanwhile
+            // waits for its own, later, entry.
+            let logged = storage::log(state, Record::Promised { instance: ballot.instance, id: ballot.id });
+            std::mem::drop(acceptor);
+            if let Err(e) = storage::durable(state, logged).await {
+                let payload = Promise::from(Nack::new(e.to_string()));
+                return (StatusCode::INTERNAL_SERVER_ERROR, payload);
+            }
+
+            println!("[/handle-prepare] Node {} accepted a new proposal: {:?} (instance {})", state.node.id, ballot.id, ballot.instance);
+            state.epoch.observe(ballot.id);
+            state.events.record(Transition::PromiseGiven { instance: ballot.instance, id: ballot.id, accepted: value.clone() });
+
+            let payload = Promise::granted(value);
+            (StatusCode::OK, payload)
+        },
+    }
+}
+
+/// The reply to `/handle-prepare-range`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PrepareRangePayload {
+    pub error: Option<String>,
+    /// What this acceptor accepted in the range and hasn't learned yet.
+    #[serde(default)]
+    pub accepted: Vec<Ballot>,
+    /// On a NACK, the highest proposal promised somewhere in the range.
+    #[serde(default)]
+    pub promised: Option<ProposalId>,
+    /// What this acceptor already learned in the range.
+    #[serde(default)]
+    pub decided: BTreeMap<u64, Value>,
+}
+
+impl PrepareRangePayload {
+    fn refused(error: &str) -> Self {
+        Self { error: Some(String::from(error)), accepted: Vec::new(), promised: None, decided: BTreeMap::new() }
+    }
+}
+
+/// Phase 1 for a whole range of instances, from a leader preparing ahead.
+pub async fn handle_prepare_range(State(state): State<AppState>, Json(range): Json<RangePromise>) -> (StatusCode, Json<PrepareRangePayload>) {
+    if state.is_paused() {
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(PrepareRangePayload::refused(admin::PAUSED)));
+    }
+    if state.disk.is_low() {
+        return (StatusCode::INSUFFICIENT_STORAGE, Json(PrepareRangePayload::refused(disk::LOW_ON_SPACE)));
+    }
+    if range.to <= range.from || range.to - range.from > MAX_PREPARE_AHEAD {
+        return (StatusCode::BAD_REQUEST, Json(PrepareRangePayload::refused("The range of instances is empty or too long!")));
+    }
+
+    let mut acceptor = state.acceptor.lock().await;
+    let accepted = match acceptor.prepare_range(range) {
+        Ok(accepted) => accepted,
+        Err(promised) => {
+            println!("[/handle-prepare-range] Node {} already promised {:?} in instances {} to {}", state.node.id, promised, range.from, range.to - 1);
+            let payload = PrepareRangePayload {
+                promised: Some(promised),
+                ..PrepareRangePayload::refused("The proposal ID is lesser than a ballot number already promised in the range")
+            };
+            return (StatusCode::BAD_REQUEST, Json(payload));
+        },
+    };
+    let logged = storage::log(&state, Record::PromisedRange { range });
+    std::mem::drop(acceptor);
+    if let Err(e) = storage::durable(&state, logged).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(PrepareRangePayload::refused(&e.to_string())));
+    }
+    state.epoch.observe(range.id);
+
+    let decided = (range.from..range.to.min(state.next_instance()))
+        .filter_map(|instance| Some((instance, state.ledger.get(instance)?)))
+        .collect();
+
+    println!("[/handle-prepare-range] Node {} promised {:?} for instances {} to {}", state.node.id, range.id, range.from, range.to - 1);
+    let payload = PrepareRangePayload { error: None, accepted, promised: None, decided };
+    (StatusCode::OK, Json(payload))
+}
+
+pub async fn handle_accept(State(state): State<AppState>, Json(request): Json<Accept>) -> (StatusCode, Json<Accepted>) {
+    // Decisions are learned even by a node that won't vote, as learns are.
+    learn_committed(&state, &request.committed).await;
+    let propose = request.ballot;
+
+    if state.is_paused() {
+        let payload = Accepted::from(Nack::new(admin::PAUSED));
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(payload));
+    }
+    if state.disk.is_low() {
+        let payload = Accepted::from(Nack::new(disk::LOW_ON_SPACE));
+        return (StatusCode::INSUFFICIENT_STORAGE, Json(payload));
+    }
+
+    let mut trace = trace::begin(&state).await;
+    let (status, payload) = accept(&state, &propose).await;
+
+    if let Some(trace) = &mut trace {
+        trace.record(&state, Step::Accept { ballot: propose, reply: payload.clone() }).await;
+    }
+
+    (status, Json(payload))
+}
+
+pub(crate) async fn accept(state: &AppState, propose: &Ballot) -> (StatusCode, Accepted) {
+    println!("[/handle-accept] Node {} get new propose to be accepted: {:?}", state.node.id, propose);
+
+    let decided = state.ledger.get(propose.instance);
+    if let Some(decided) = decided {
+        if Some(&decided) != propose.value.as_ref() {
+            println!("[/handle-accept] Node {} got a different value for learned instance {}", state.node.id, propose.instance);
+            let payload = Accepted::from(Nack::new("Instance was already learned with a different value!"));
+            return (StatusCode::BAD_REQUEST, payload);
+        }
+
+        let payload = Accepted::granted(propose.clone(), chain::sign(state, propose));
+        return (StatusCode::OK, payload);
+    }
+
+    let mut acceptor = state.acceptor.lock().await;
+    if let Err(promised) = acceptor.accept(propose) {
+        println!("[/handle-accept] Node {} received a proposal with a lower ballot ID: {:?}", state.node.id, propose.id);
+        state.events.record(Transition::AcceptRefused { instance: propose.instance, id: propose.id, promised });
+        let payload = Accepted::from(Nack::outbid("Node already promised a higher ballot ID!", promised));
+        return (StatusCode::BAD_REQUEST, payload);
+    }
+
+    let logged = storage::log(state, Record::Accepted { ballot: propose.clone() });
+    std::mem::drop(acceptor);
+    if let Err(e) = storage::durable(state, logged).await {
+        let payload = Accepted::from(Nack::new(e.to_string()));
+        return (StatusCode::INTERNAL_SERVER_ERROR, payload);
+    }
+
+    println!("[/handle-accept] Node {} accepting new proposed value: {:?}", state.node.id, propose.value);
+    state.prevote.heard(propose.id.node_id);
+    state.events.record(Transition::Accepted { instance: propose.instance, id: propose.id, value: propose.value.clone() });
+
+    let payload = Accepted::granted(propose.clone(), chain::sign(state, propose));
+
+    (StatusCode::OK, payload)
+}
+
+/// Learns the value we accepted under each of `committed`. One accepted
+/// under another proposal, or not at all, can't be told from an id, and
+/// is left for a learn or a later prepare to bring.
+async fn learn_committed(state: &AppState, committed: &[Committed]) {
+    if committed.is_empty() {
+        return;
+    }
+
+    let acceptor = state.acceptor.lock().await;
+    let ballots: Vec<Ballot> = committed.iter()
+        .filter(|committed| state.ledger.get(committed.instance).is_none())
+        .filter_map(|committed| {
+            let accepted = acceptor.slots.get(&committed.instance)?.accepted_proposal.as_ref()?;
+            (accepted.id == committed.id).then(|| accepted.clone())
+        })
+        .collect();
+    std::mem::drop(acceptor);
+
+    for ballot in &ballots {
+        learn(state, ballot).await;
+    }
+}
+
+pub async fn handle_learn(State(state): State<AppState>, Json(payload): Json<Learn>) -> (StatusCode, ()) {
+    learn(&state, &payload).await;
+    (StatusCode::OK, ())
+}
+
+/// A batch of learns, in the order they were decided.
+pub async fn handle_learns(State(state): State<AppState>, Json(ballots): Json<Vec<Learn>>) -> (StatusCode, ()) {
+    for ballot in &ballots {
+        learn(&state, ballot).await;
+    }
+    (StatusCode::OK, ())
+}
+
+/// Learns passed on by a peer, which this node passes on in turn if they
+/// are news to it.
+pub async fn handle_gossip(State(state): State<AppState>, headers: HeaderMap, Json(gossip): Json<Gossip>) -> (StatusCode, ()) {
+    let mut news = Vec::new();
+    for ballot in &gossip.ballots {
+        if learn(&state, ballot).await {
+            news.push(ballot.clone());
+        }
+    }
+
+    let from = headers.get(NODE_ID_HEADER).and_then(|id| id.to_str().ok()?.parse().ok());
+    learns::pass_on(&state, Gossip { ballots: news, ttl: gossip.ttl.saturating_sub(1) }, from);
+    (StatusCode::OK, ())
+}
+
+/// Learns `ballot`, and returns whether it was new to this node.
+pub(crate) async fn learn(state: &AppState, ballot: &Ballot) -> bool {
+    let mut trace = trace::begin(state).await;
+    let is_new = learn_value(state, ballot).await;
+
+    if let Some(trace) = &mut trace {
+        trace.record(state, Step::Learn { ballot: ballot.clone() }).await;
+    }
+    is_new
+}
+
+pub(crate) async fn learn_value(state: &AppState, ballot: &Ballot) -> bool {
+    let value = ballot.value.clone().unwrap_or_default();
+
+    let mut ledger = state.ledger.write().await;
+    let is_new = ledger.insert(ballot.instance, value.clone());
+    if is_new {
+        ledger.stamp(ballot.instance, state.clock.now());
+    }
+
+    // Re-applying a duplicated learn could roll a key back to an older value.
+    // The apply task takes values in instance order, whatever order they
+    // are learned in; see `apply`.
+    let mut logged = Ok(None);
+    if is_new {
+        logged = storage::log(state, Record::Learned { instance: ballot.instance, value: value.clone() });
+        state.applier.push(state, ballot.instance, value.clone());
+    }
+    std::mem::drop(ledger);
+
+    if is_new {
+        let _ = storage::durable(state, logged).await;
+        state.events.record(Transition::Learned { instance: ballot.instance, value: value.clone() });
+    }
+
+    println!("[learn] Node {} learns a new value: {:?} (instance {})", state.node.id, ballot.value, ballot.instance);
+
+    reset_decision_point(state, ballot.instance).await;
+    is_new
+}
+
+async fn reset_decision_point(state: &AppState, instance: u64) {
+    let mut acceptor = state.acceptor.lock().await;
+    acceptor.forget(instance);
+    acceptor.forget_ranges(instance, |instance| state.ledger.get(instance).is_some());
+
+    println!("[reset_decision_point] Decision point reseted for instance {}!", instance);
+}
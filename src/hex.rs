@@ -0,0 +1,21 @@
+//! Bytes as lowercase hex, the way keys, hashes and signatures are written
+//! everywhere a person might read them.
+
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The bytes of `text`, however many, in hex; surrounding whitespace is
+/// ignored.
+pub fn decode_vec(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+/// The bytes of `text`, which must be exactly `N` of them in hex.
+pub fn decode<const N: usize>(text: &str) -> Option<[u8; N]> {
+    decode_vec(text)?.try_into().ok()
+}
@@ -0,0 +1,273 @@
+//! Client operation histories and a linearizability checker for them.
+//!
+//! A node started with `--history <file>` appends one JSON line per event:
+//! the invocation of a KV operation and then its completion (`ok`, or `info`
+//! when the outcome is unknown). Timestamps are taken on the node, so
+//! histories from several nodes can be merged as long as their clocks agree,
+//! which holds for the usual single-machine cluster.
+//!
+//! [`check`] then searches, key by key, for an order of the operations that
+//! respects real time and register semantics (a read returns the latest
+//! write, or nothing after a delete).
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::{Mutex, atomic::{AtomicU64, Ordering}},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use serde::{Serialize, Deserialize};
+
+use crate::Id;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventType {
+    Invoke,
+    Ok,
+    Fail,
+    Info,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Function {
+    Read,
+    Write,
+    Delete,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub node: Id,
+    pub op: u64,
+    #[serde(rename = "type")]
+    pub kind: EventType,
+    pub f: Function,
+    pub key: String,
+    /// The written value on a write, the observed value on a completed read.
+    pub value: Option<String>,
+    /// Microseconds since the Unix epoch.
+    pub time: u64,
+}
+
+#[derive(Debug)]
+pub struct History {
+    node: Id,
+    file: Mutex<File>,
+    next_op: AtomicU64,
+}
+
+impl History {
+    pub fn open(node: Id, path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { node, file: Mutex::new(file), next_op: AtomicU64::new(1) })
+    }
+
+    /// Makes sure everything recorded so far is on disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.lock().unwrap().sync_all()
+    }
+
+    pub fn invoke(&self, f: Function, key: &str, value: Option<&str>) -> u64 {
+        let op = self.next_op.fetch_add(1, Ordering::Relaxed);
+        self.record(op, EventType::Invoke, f, key, value);
+        op
+    }
+
+    pub fn ok(&self, op: u64, f: Function, key: &str, value: Option<&str>) {
+        self.record(op, EventType::Ok, f, key, value);
+    }
+
+    pub fn info(&self, op: u64, f: Function, key: &str, value: Option<&str>) {
+        self.record(op, EventType::Info, f, key, value);
+    }
+
+    fn record(&self, op: u64, kind: EventType, f: Function, key: &str, value: Option<&str>) {
+        let event = Event {
+            node: self.node,
+            op,
+            kind,
+            f,
+            key: key.to_string(),
+            value: value.map(str::to_string),
+            time: now_micros(),
+        };
+
+        let mut line = serde_json::to_string(&event).unwrap();
+        line.push('\n');
+
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            println!("[history] Node {} failed to record {:?}: {}", self.node, event, e);
+        }
+    }
+}
+
+pub(crate) fn now_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64)
+}
+
+pub fn read_events(path: &Path) -> io::Result<Vec<Event>> {
+    let mut events = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// An invocation paired with its completion. `ret` is `None` when the outcome
+/// is unknown: the operation may have taken effect at any point after `call`.
+#[derive(Clone, Debug)]
+pub struct Operation {
+    pub node: Id,
+    pub op: u64,
+    pub f: Function,
+    pub key: String,
+    pub value: Option<String>,
+    pub call: u64,
+    pub ret: Option<u64>,
+}
+
+/// Pairs up events. Failed operations are known not to have happened and
+/// are left out; so are unfinished reads, which can't affect anybody.
+pub fn operations(events: &[Event]) -> Vec<Operation> {
+    let mut completions = HashMap::new();
+    for event in events.iter().filter(|event| event.kind != EventType::Invoke) {
+        completions.insert((event.node, event.op), event);
+    }
+
+    let mut operations = Vec::new();
+    for invoke in events.iter().filter(|event| event.kind == EventType::Invoke) {
+        let completion = completions.get(&(invoke.node, invoke.op));
+        let (value, ret) = match completion.map(|event| event.kind) {
+            Some(EventType::Fail) => continue,
+            Some(EventType::Ok) if invoke.f == Function::Read => {
+                (completion.and_then(|event| event.value.clone()), completion.map(|event| event.time))
+            },
+            Some(EventType::Ok) => (invoke.value.clone(), completion.map(|event| event.time)),
+            _ if invoke.f == Function::Read => continue,
+            _ => (invoke.value.clone(), None),
+        };
+
+        operations.push(Operation {
+            node: invoke.node,
+            op: invoke.op,
+            f: invoke.f,
+            key: invoke.key.clone(),
+            value,
+            call: invoke.time,
+            ret,
+        });
+    }
+
+    operations.sort_by_key(|operation| operation.call);
+    operations
+}
+
+#[derive(Debug)]
+pub struct Violation {
+    pub key: String,
+    pub operations: Vec<Operation>,
+}
+
+/// Checks every key independently and returns the keys whose operations
+/// can't be linearized.
+pub fn check(events: &[Event]) -> Result<usize, Vec<Violation>> {
+    let mut by_key: BTreeMap<String, Vec<Operation>> = BTreeMap::new();
+    for operation in operations(events) {
+        by_key.entry(operation.key.clone()).or_default().push(operation);
+    }
+
+    let keys = by_key.len();
+    let violations: Vec<Violation> = by_key.into_iter()
+        .filter(|(_, operations)| !is_linearizable(operations))
+        .map(|(key, operations)| Violation { key, operations })
+        .collect();
+
+    if violations.is_empty() {
+        Ok(keys)
+    } else {
+        Err(violations)
+    }
+}
+
+/// Depth-first search over linearization orders of a single register,
+/// memoizing (linearized set, register value) pairs already known to fail.
+pub fn is_linearizable(operations: &[Operation]) -> bool {
+    let mut search = Search {
+        operations,
+        done: vec![0; operations.len().div_ceil(64)],
+        failed: HashSet::new(),
+    };
+    let completed = operations.iter().filter(|operation| operation.ret.is_some()).count();
+    search.run(None, completed)
+}
+
+struct Search<'a> {
+    operations: &'a [Operation],
+    done: Vec<u64>,
+    failed: HashSet<(Vec<u64>, Option<String>)>,
+}
+
+impl Search<'_> {
+    fn is_done(&self, i: usize) -> bool {
+        self.done[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn toggle(&mut self, i: usize) {
+        self.done[i / 64] ^= 1 << (i % 64);
+    }
+
+    /// `completed` counts the finished operations not yet linearized; once it
+    /// reaches zero the rest are unknown-outcome writes that can be dropped.
+    fn run(&mut self, register: Option<String>, completed: usize) -> bool {
+        if completed == 0 {
+            return true;
+        }
+
+        if self.failed.contains(&(self.done.clone(), register.clone())) {
+            return false;
+        }
+
+        // Only operations invoked before the earliest pending response can
+        // go next; anything later must follow that response.
+        let horizon = (0..self.operations.len())
+            .filter(|&i| !self.is_done(i))
+            .filter_map(|i| self.operations[i].ret)
+            .min()
+            .unwrap_or(u64::MAX);
+
+        for i in 0..self.operations.len() {
+            let operation = &self.operations[i];
+            if self.is_done(i) || operation.call > horizon {
+                continue;
+            }
+
+            let next = match operation.f {
+                Function::Write => operation.value.clone(),
+                Function::Delete => None,
+                Function::Read if operation.value != register => continue,
+                Function::Read => register.clone(),
+            };
+
+            let completed = completed - usize::from(operation.ret.is_some());
+            self.toggle(i);
+            let found = self.run(next, completed);
+            self.toggle(i);
+
+            if found {
+                return true;
+            }
+        }
+
+        self.failed.insert((self.done.clone(), register));
+        false
+    }
+}
@@ -0,0 +1,164 @@
+//! A hybrid logical clock on every node, to stamp what it learns with.
+//!
+//! Instance numbers order the entries of one log, but say nothing about
+//! entries of different Paxos groups, or about when anything happened. A
+//! hybrid logical clock reads like wall-clock time, in microseconds since the
+//! Unix epoch, and still never goes back: it ticks a logical counter when
+//! the wall clock stalls or lags. Every request a node sends its peers
+//! carries its clock in [`HLC_HEADER`], and the peer moves its own clock
+//! past it, so whatever happened after a message arrived is stamped later
+//! than whatever happened before it was sent, however far apart the two
+//! nodes' wall clocks are.
+//!
+//! A node stamps each entry as it learns it, and `GET /ledger` shows the
+//! stamps next to the values. Each node stamps its own copy, so two nodes
+//! stamp one instance differently, but a learn always comes after the
+//! proposer learned the value itself. Stamps are kept in memory: entries
+//! a node had before it restarted have none.
+//!
+//! A peer whose clock is more than [`MAX_DRIFT`] ahead of this node's wall
+//! clock is ignored, so one bad clock can't drag the cluster's along.
+//!
+//! The clock is also what leases and namespace TTLs read the time from,
+//! so it can be skewed on purpose, to see them through a bad clock:
+//! `POST /admin/clock-skew` with `{"skew_ms": <ms>}` sets the node's wall
+//! clock that far ahead of the system's, or behind it when negative, and
+//! `GET` shows it. Like the fault rules, it is only meant for tests. The
+//! clock still never goes back, so a skew set backwards holds it still
+//! until the system's catches up. Pre-vote leases only count the time
+//! elapsed on the node, which a skew doesn't change.
+
+use std::{fmt, str::FromStr, sync::{Mutex, atomic::{AtomicI64, Ordering}}, time::Duration};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{AppState, history::now_micros, input::Json};
+
+/// Set on every node-to-node request, with the sender's clock.
+pub const HLC_HEADER: &str = "x-paxos-hlc";
+
+/// How far ahead of this node's wall clock a peer's may be.
+pub const MAX_DRIFT: Duration = Duration::from_secs(60);
+
+/// A reading of a hybrid logical clock, ordered by wall time first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Timestamp {
+    /// Microseconds since the Unix epoch.
+    pub wall: u64,
+    /// Events within the same `wall`.
+    pub logical: u32,
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.wall, self.logical)
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (wall, logical) = s.split_once('.').ok_or_else(|| format!("{} is no timestamp", s))?;
+        Ok(Self {
+            wall: wall.parse().map_err(|_| format!("{} is no timestamp", s))?,
+            logical: logical.parse().map_err(|_| format!("{} is no timestamp", s))?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Hlc {
+    last: Mutex<Timestamp>,
+    /// Microseconds the wall clock is set ahead of the system's.
+    skew: AtomicI64,
+}
+
+impl Hlc {
+    /// The wall clock, skewed, in microseconds since the Unix epoch.
+    pub fn wall(&self) -> u64 {
+        now_micros().saturating_add_signed(self.skew())
+    }
+
+    pub fn skew(&self) -> i64 {
+        self.skew.load(Ordering::SeqCst)
+    }
+
+    /// Sets the wall clock `micros` ahead of the system's.
+    pub fn set_skew(&self, micros: i64) {
+        self.skew.store(micros, Ordering::SeqCst);
+    }
+
+    /// A timestamp for something happening on this node, a send included.
+    pub fn now(&self) -> Timestamp {
+        self.tick(self.wall())
+    }
+
+    /// Like [`Hlc::now`], with the wall clock reading `wall`.
+    pub fn tick(&self, wall: u64) -> Timestamp {
+        let mut last = self.last.lock().unwrap();
+        *last = match wall > last.wall {
+            true => Timestamp { wall, logical: 0 },
+            false => Timestamp { wall: last.wall, logical: last.logical + 1 },
+        };
+        *last
+    }
+
+    /// Moves the clock past `remote`, a peer's, unless that is too far
+    /// ahead to believe.
+    pub fn receive(&self, remote: Timestamp) -> Option<Timestamp> {
+        self.receive_at(self.wall(), remote)
+    }
+
+    /// Like [`Hlc::receive`], with the wall clock reading `wall`.
+    pub fn receive_at(&self, wall: u64, remote: Timestamp) -> Option<Timestamp> {
+        if remote.wall > wall.saturating_add(MAX_DRIFT.as_micros() as u64) {
+            return None;
+        }
+        let mut last = self.last.lock().unwrap();
+        let top = wall.max(last.wall).max(remote.wall);
+        let logical = match (top == last.wall, top == remote.wall) {
+            (true, true) => last.logical.max(remote.logical) + 1,
+            (true, false) => last.logical + 1,
+            (false, true) => remote.logical + 1,
+            (false, false) => 0,
+        };
+        *last = Timestamp { wall: top, logical };
+        Some(*last)
+    }
+
+    /// The last timestamp handed out.
+    pub fn last(&self) -> Timestamp {
+        *self.last.lock().unwrap()
+    }
+}
+
+/// Moves the node's clock past the one a peer sent along with its request.
+pub async fn receive(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let remote = request.headers().get(HLC_HEADER).and_then(|stamp| stamp.to_str().ok()?.parse::<Timestamp>().ok());
+    if let Some(remote) = remote {
+        if state.clock.receive(remote).is_none() {
+            println!("[hlc] Node {} ignored a clock at {}, too far ahead of its own", state.node.id, remote);
+        }
+    }
+    next.run(request).await
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Skew {
+    pub skew_ms: i64,
+}
+
+pub async fn get_skew(State(state): State<AppState>) -> Json<Skew> {
+    Json(Skew { skew_ms: state.clock.skew() / 1000 })
+}
+
+pub async fn set_skew(State(state): State<AppState>, Json(skew): Json<Skew>) -> Json<Skew> {
+    state.clock.set_skew(skew.skew_ms.saturating_mul(1000));
+    println!("[hlc] Node {} set its clock {} ms off the system's", state.node.id, skew.skew_ms);
+    Json(skew)
+}
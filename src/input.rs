@@ -0,0 +1,70 @@
+//! Extractors that turn malformed requests away with a 400 that says why.
+//!
+//! [`Json`], [`Query`] and [`Path`] are axum's own, except that a body,
+//! query string or path that doesn't parse into what the handler takes is
+//! answered with a `400` and `{"error": "<why>"}`, whatever was wrong with
+//! it, instead of axum's plain text and its mix of 400, 415 and 422. Every
+//! handler takes its input through these, so one can assume it parsed, and
+//! checks only what parsing can't. `Json` also answers like axum's.
+
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use axum_macros::{FromRequest, FromRequestParts};
+use serde::{Serialize, Deserialize};
+
+/// Why a request was turned away.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Invalid {
+    pub error: String,
+}
+
+impl Invalid {
+    pub fn new(error: impl Into<String>) -> Self {
+        Self { error: error.into() }
+    }
+}
+
+impl IntoResponse for Invalid {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, axum::Json(self)).into_response()
+    }
+}
+
+impl From<JsonRejection> for Invalid {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for Invalid {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::new(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for Invalid {
+    fn from(rejection: PathRejection) -> Self {
+        Self::new(rejection.body_text())
+    }
+}
+
+#[derive(FromRequest, Debug, Clone, Copy, Default)]
+#[from_request(via(axum::Json), rejection(Invalid))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+#[derive(FromRequestParts, Debug, Clone, Copy, Default)]
+#[from_request(via(axum::extract::Query), rejection(Invalid))]
+pub struct Query<T>(pub T);
+
+#[derive(FromRequestParts, Debug, Clone, Copy, Default)]
+#[from_request(via(axum::extract::Path), rejection(Invalid))]
+pub struct Path<T>(pub T);
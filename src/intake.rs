@@ -0,0 +1,214 @@
+//! Client commands a node took but hasn't finished, kept on disk.
+//!
+//! A client write is only answered once its round is over, so a node that
+//! crashed in the middle of one used to lose it without a word: the client
+//! saw the connection drop and couldn't tell whether to retry. With a data
+//! directory, every client command now goes into `intake.jsonl` there,
+//! synced, before its round starts, and is crossed off once the round is
+//! over, however it ended. A node that starts again with commands still on
+//! the list proposes them again once it knows its peers.
+//!
+//! One of those may have been chosen before the crash, and is then chosen
+//! twice. The node doesn't try to tell: a client that can't have a write
+//! applied twice makes its values unique and ignores repeats, as it already
+//! must for a write it retries after a timeout.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{AppState, Value, error::PaxosError, readonly};
+
+const INTAKE: &str = "intake.jsonl";
+
+/// How big the file gets before it is rewritten with just what's pending.
+const COMPACT_BYTES: u64 = 4 * 1024 * 1024;
+
+/// How often to look for peers, and to try a command again, on restart.
+const RESUBMIT_EVERY: Duration = Duration::from_millis(500);
+const RESUBMIT_ATTEMPTS: usize = 10;
+
+/// A line of the file: a command taken, or without a value, one finished.
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<Value>,
+}
+
+#[derive(Debug)]
+struct Queue {
+    file: File,
+    bytes: u64,
+    next_id: u64,
+    pending: BTreeMap<u64, Value>,
+}
+
+#[derive(Debug)]
+pub struct Intake {
+    path: PathBuf,
+    queue: Mutex<Queue>,
+    /// Commands pending from before the node started.
+    recovered: Mutex<Vec<(u64, Value)>>,
+}
+
+impl Intake {
+    /// Opens the list in `dir`, keeping what was still pending on it.
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(INTAKE);
+        let mut pending = BTreeMap::new();
+
+        match fs::read_to_string(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+            // A crash halfway through a line leaves it unreadable; that
+            // command was never answered, nor was its round started.
+            Ok(text) => for entry in text.lines().filter_map(|line| serde_json::from_str::<Entry>(line).ok()) {
+                match entry.value {
+                    Some(value) => pending.insert(entry.id, value),
+                    None => pending.remove(&entry.id),
+                };
+            },
+        }
+
+        let next_id = pending.keys().next_back().map_or(1, |id| id + 1);
+        let (file, bytes) = rewrite(&path, &pending)?;
+        let recovered = pending.iter().map(|(id, value)| (*id, value.clone())).collect();
+
+        Ok(Self { path, queue: Mutex::new(Queue { file, bytes, next_id, pending }), recovered: Mutex::new(recovered) })
+    }
+
+    /// Puts `value` on the list, on disk, and returns its id.
+    pub fn push(&self, value: &Value) -> io::Result<u64> {
+        let mut queue = self.queue.lock().unwrap();
+        let id = queue.next_id;
+        let line = line(&Entry { id, value: Some(value.clone()) });
+        queue.file.write_all(line.as_bytes())?;
+        queue.file.sync_data()?;
+
+        queue.next_id += 1;
+        queue.bytes += line.len() as u64;
+        queue.pending.insert(id, value.clone());
+        Ok(id)
+    }
+
+    /// Crosses `id` off. Not synced: losing that only means proposing it
+    /// again.
+    pub fn done(&self, id: u64) -> io::Result<()> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.pending.remove(&id).is_none() {
+            return Ok(());
+        }
+
+        if queue.bytes >= COMPACT_BYTES {
+            (queue.file, queue.bytes) = rewrite(&self.path, &queue.pending)?;
+            return Ok(());
+        }
+        let line = line(&Entry { id, value: None });
+        queue.file.write_all(line.as_bytes())?;
+        queue.bytes += line.len() as u64;
+        Ok(())
+    }
+
+    /// Commands taken and not finished yet, including those from before the
+    /// node started.
+    pub fn pending(&self) -> usize {
+        self.queue.lock().unwrap().pending.len()
+    }
+
+    /// The commands pending from before the node started, in the order they
+    /// were taken; only the first call gets them.
+    pub fn take_recovered(&self) -> Vec<(u64, Value)> {
+        std::mem::take(&mut self.recovered.lock().unwrap())
+    }
+}
+
+fn line(entry: &Entry) -> String {
+    let mut line = serde_json::to_string(entry).unwrap();
+    line.push('\n');
+    line
+}
+
+/// Writes a list of just `pending` into place, and opens it for appending.
+fn rewrite(path: &Path, pending: &BTreeMap<u64, Value>) -> io::Result<(File, u64)> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    let text: String = pending.iter().map(|(id, value)| line(&Entry { id: *id, value: Some(value.clone()) })).collect();
+    file.write_all(text.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+
+    let file = OpenOptions::new().append(true).open(path)?;
+    Ok((file, text.len() as u64))
+}
+
+/// Crosses its command off when the round is over, or the client gave up
+/// on it.
+struct Taken<'a> {
+    intake: &'a Intake,
+    id: u64,
+}
+
+impl Drop for Taken<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.intake.done(self.id) {
+            println!("[intake] Failed to cross off command {}: {}", self.id, e);
+        }
+    }
+}
+
+/// Runs a client command, on the list for as long as its round lasts.
+pub async fn submit(state: &AppState, value: Value) -> Result<u64, PaxosError> {
+    let Some(storage) = &state.storage else {
+        return readonly::submit(state, value).await;
+    };
+
+    let id = storage.intake().push(&value).map_err(|e| {
+        println!("[intake] Node {} failed to write its intake: {}", state.node.id, e);
+        PaxosError::Storage(e)
+    })?;
+    let _taken = Taken { intake: storage.intake(), id };
+    readonly::submit(state, value).await
+}
+
+/// Proposes again whatever was pending when the node stopped, once it has
+/// peers to propose to.
+pub async fn resubmit(state: AppState) {
+    let Some(storage) = state.storage.clone() else {
+        return;
+    };
+    let recovered = storage.intake().take_recovered();
+    if recovered.is_empty() {
+        return;
+    }
+
+    println!("[intake] Node {} has {} commands from before it stopped, proposing them once it has peers", state.node.id, recovered.len());
+    while state.nodes.snapshot().is_empty() {
+        tokio::time::sleep(RESUBMIT_EVERY).await;
+    }
+
+    for (id, value) in recovered {
+        for attempt in 1..=RESUBMIT_ATTEMPTS {
+            match readonly::submit(&state, value.clone()).await {
+                Ok(instance) => {
+                    println!("[intake] Node {} proposed command {} again, chosen at instance {}", state.node.id, id, instance);
+                    let _ = storage.intake().done(id);
+                    break;
+                },
+                Err(e) if attempt == RESUBMIT_ATTEMPTS => {
+                    println!("[intake] Node {} gave up on command {} until it next starts: {}", state.node.id, id, e);
+                },
+                Err(_) => tokio::time::sleep(RESUBMIT_EVERY).await,
+            }
+        }
+    }
+}
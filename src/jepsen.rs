@@ -0,0 +1,257 @@
+//! Interop with Jepsen-style test rigs.
+//!
+//! Histories, whether recorded by the nodes (`--history`) or by the
+//! [`Workload`] client below, are exported as the operation maps Jepsen,
+//! Knossos and Elle read: one map per event with `:index`, `:time` (in
+//! nanoseconds since the first event), `:type`, `:process`, `:f` and
+//! `:value`. Keys are independent registers, so values are `[key value]`
+//! tuples; a delete is a write of `nil`.
+//!
+//! Jepsen processes are single-threaded and never outlive an operation
+//! with an unknown outcome, while a node serves many operations at once.
+//! Each operation therefore gets the lowest process of its node that is
+//! idle, and a process whose operation ended in `:info` is retired.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{Arc, atomic::{AtomicU64, Ordering}},
+    time::{Duration, Instant},
+};
+use reqwest::StatusCode;
+use serde_json::json;
+
+use crate::{
+    Id,
+    history::{Event, EventType, Function, now_micros},
+    rng::Rng,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// One EDN map per line, as Jepsen writes `history.edn`.
+    Edn,
+    /// The same maps as JSON, one per line.
+    Json,
+    /// This crate's own history format, readable by `check-history`.
+    Events,
+}
+
+/// An event as a Jepsen operation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Op {
+    pub index: usize,
+    pub time: u64,
+    pub kind: EventType,
+    pub process: u64,
+    pub f: Function,
+    pub key: String,
+    pub value: Option<String>,
+}
+
+/// Orders events by time and assigns them indexes and processes.
+pub fn ops(events: &[Event]) -> Vec<Op> {
+    let mut events: Vec<&Event> = events.iter().collect();
+    events.sort_by_key(|event| event.time);
+
+    let start = events.first().map_or(0, |event| event.time);
+    let mut idle: BTreeMap<Id, Vec<u64>> = BTreeMap::new();
+    let mut running: HashMap<(Id, u64), u64> = HashMap::new();
+    let mut next_process = 0;
+
+    let mut ops = Vec::with_capacity(events.len());
+    for event in events {
+        let process = if event.kind == EventType::Invoke {
+            let idle = idle.entry(event.node).or_default();
+            let process = idle.pop().unwrap_or_else(|| {
+                next_process += 1;
+                next_process - 1
+            });
+            running.insert((event.node, event.op), process);
+            process
+        } else {
+            let Some(process) = running.remove(&(event.node, event.op)) else {
+                continue;
+            };
+            if event.kind != EventType::Info {
+                let idle = idle.entry(event.node).or_default();
+                idle.push(process);
+                idle.sort_by(|a, b| b.cmp(a));
+            }
+            process
+        };
+
+        ops.push(Op {
+            index: ops.len(),
+            time: (event.time - start) * 1000,
+            kind: event.kind,
+            process,
+            f: event.f,
+            key: event.key.clone(),
+            value: event.value.clone(),
+        });
+    }
+    ops
+}
+
+fn kind_name(kind: EventType) -> &'static str {
+    match kind {
+        EventType::Invoke => "invoke",
+        EventType::Ok => "ok",
+        EventType::Fail => "fail",
+        EventType::Info => "info",
+    }
+}
+
+/// Deletes are writes of nil as far as a register is concerned.
+fn f_name(f: Function) -> &'static str {
+    match f {
+        Function::Read => "read",
+        Function::Write | Function::Delete => "write",
+    }
+}
+
+fn edn_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub fn to_edn(ops: &[Op]) -> String {
+    let mut out = String::new();
+    for op in ops {
+        let value = op.value.as_deref().map_or(String::from("nil"), edn_string);
+        writeln!(
+            out,
+            "{{:index {}, :time {}, :type :{}, :process {}, :f :{}, :value [{} {}]}}",
+            op.index, op.time, kind_name(op.kind), op.process, f_name(op.f), edn_string(&op.key), value,
+        ).unwrap();
+    }
+    out
+}
+
+pub fn to_json(ops: &[Op]) -> String {
+    let mut out = String::new();
+    for op in ops {
+        let line = json!({
+            "index": op.index,
+            "time": op.time,
+            "type": kind_name(op.kind),
+            "process": op.process,
+            "f": f_name(op.f),
+            "value": [op.key, op.value],
+        });
+        writeln!(out, "{}", line).unwrap();
+    }
+    out
+}
+
+pub fn to_events(events: &[Event]) -> String {
+    let mut out = String::new();
+    for event in events {
+        writeln!(out, "{}", serde_json::to_string(event).unwrap()).unwrap();
+    }
+    out
+}
+
+pub fn export(events: &[Event], format: Format) -> String {
+    match format {
+        Format::Edn => to_edn(&ops(events)),
+        Format::Json => to_json(&ops(events)),
+        Format::Events => to_events(events),
+    }
+}
+
+/// A register workload against the KV API: `concurrency` clients issue
+/// reads, writes of unique values and deletes on random keys of random
+/// nodes until `time_limit` runs out.
+#[derive(Clone, Debug)]
+pub struct Workload {
+    /// `host:port` of every node.
+    pub nodes: Vec<String>,
+    pub concurrency: usize,
+    pub time_limit: Duration,
+    pub keys: usize,
+}
+
+/// Runs the workload and returns the history it observed.
+pub async fn run(workload: &Workload) -> Vec<Event> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .unwrap();
+    let deadline = Instant::now() + workload.time_limit;
+    let values = Arc::new(AtomicU64::new(1));
+
+    let clients = (0..workload.concurrency).map(|i| {
+        let client = client.clone();
+        let workload = workload.clone();
+        let values = values.clone();
+        tokio::spawn(async move {
+            run_client(i as Id + 1, &client, &workload, deadline, &values).await
+        })
+    });
+
+    let mut events: Vec<Event> = futures::future::join_all(clients).await
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect();
+    events.sort_by_key(|event| event.time);
+    events
+}
+
+async fn run_client(id: Id, client: &reqwest::Client, workload: &Workload, deadline: Instant, values: &AtomicU64) -> Vec<Event> {
+    let mut rng = Rng::from_entropy(id);
+    let mut events = Vec::new();
+    let mut op = 0;
+
+    while Instant::now() < deadline && !workload.nodes.is_empty() {
+        op += 1;
+        let node = &workload.nodes[rng.below(workload.nodes.len() as u64) as usize];
+        let key = rng.below(workload.keys.max(1) as u64).to_string();
+        let url = format!("http://{}/kv/{}", node, key);
+
+        let roll = rng.below(10);
+        let (f, value) = match roll {
+            0..=4 => (Function::Read, None),
+            5..=8 => (Function::Write, Some(values.fetch_add(1, Ordering::Relaxed).to_string())),
+            _ => (Function::Delete, None),
+        };
+
+        let event = |kind, value: Option<String>| Event { node: id, op, kind, f, key: key.clone(), value, time: now_micros() };
+        events.push(event(EventType::Invoke, value.clone()));
+
+        let request = match f {
+            Function::Read => client.get(&url),
+            Function::Write => client.put(&url).body(value.clone().unwrap_or_default()),
+            Function::Delete => client.delete(&url),
+        };
+
+        let completion = match (f, request.send().await) {
+            (Function::Read, Ok(res)) if res.status() == StatusCode::OK => {
+                event(EventType::Ok, res.text().await.ok())
+            },
+            (Function::Read, Ok(res)) if res.status() == StatusCode::NOT_FOUND => event(EventType::Ok, None),
+            // A read that didn't answer changed nothing.
+            (Function::Read, _) => event(EventType::Fail, None),
+            (_, Ok(res)) if res.status().is_success() => event(EventType::Ok, value),
+            // The round may have failed after the value was accepted.
+            (_, _) => event(EventType::Info, value),
+        };
+        events.push(completion);
+    }
+
+    events
+}
@@ -0,0 +1,113 @@
+//! Joins that wait for their peer to come up.
+//!
+//! `POST /connect` to a port nothing answers on doesn't fail the join: the
+//! node answers `202` and keeps the port here, then pings it again in the
+//! background, half a second later at first and twice as long after each
+//! attempt nothing answered, up to 30 seconds between attempts, until the
+//! peer does. A peer that answers but turns the join down, say for a
+//! protocol it doesn't speak, is given up on, as is any port the node
+//! connects to directly in the meantime. `GET /joins` lists the ports still
+//! waiting.
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+use axum::extract::State;
+use serde::{Serialize, Deserialize};
+use tokio::time::Instant;
+
+use crate::{AppState, handlers, input::Json};
+
+const CHECK_EVERY: Duration = Duration::from_millis(250);
+
+/// How long a join waits after its first attempt.
+pub const FIRST_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The longest a join waits between attempts.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A join waiting for its peer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Waiting {
+    pub port: u16,
+    /// Attempts nothing answered so far.
+    pub attempts: u32,
+    /// Why the last one failed.
+    pub error: String,
+}
+
+#[derive(Debug)]
+struct Pending {
+    waiting: Waiting,
+    next: Instant,
+}
+
+/// The joins a node keeps trying, by port.
+#[derive(Debug, Default)]
+pub struct Joins {
+    pending: Mutex<BTreeMap<u16, Pending>>,
+}
+
+/// How long to wait after `attempts` attempts nothing answered.
+pub fn backoff(attempts: u32) -> Duration {
+    FIRST_BACKOFF.saturating_mul(1 << attempts.saturating_sub(1).min(16)).min(MAX_BACKOFF)
+}
+
+impl Joins {
+    /// Notes that nothing answered at `port` at `now`, and answers when
+    /// the join is tried again.
+    pub fn failed(&self, port: u16, error: String, now: Instant) -> Instant {
+        let mut pending = self.pending.lock().unwrap();
+        let attempts = pending.get(&port).map_or(0, |pending| pending.waiting.attempts) + 1;
+        let next = now + backoff(attempts);
+        pending.insert(port, Pending { waiting: Waiting { port, attempts, error }, next });
+        next
+    }
+
+    /// Stops trying `port`.
+    pub fn done(&self, port: u16) {
+        self.pending.lock().unwrap().remove(&port);
+    }
+
+    /// The ports due for another attempt at `now`.
+    pub fn due(&self, now: Instant) -> Vec<u16> {
+        self.pending.lock().unwrap().iter()
+            .filter(|(_, pending)| pending.next <= now)
+            .map(|(port, _)| *port)
+            .collect()
+    }
+
+    pub fn waiting(&self) -> Vec<Waiting> {
+        self.pending.lock().unwrap().values().map(|pending| pending.waiting.clone()).collect()
+    }
+}
+
+/// Tries again the joins due at `now`, and answers the ports whose peer
+/// answered, whether it took the join or not.
+pub async fn retry(state: &AppState, now: Instant) -> Vec<u16> {
+    let mut answered = Vec::new();
+    for port in state.joins.due(now) {
+        match handlers::join(state, port).await {
+            Err(e) => {
+                let next = state.joins.failed(port, e, now);
+                println!("[joins] Node {} still can't reach port {}, trying again in {:?}", state.node.id, port, next - now);
+            },
+            Ok((status, message)) => {
+                state.joins.done(port);
+                println!("[joins] Node {} reached port {}: {} {}", state.node.id, port, status.as_u16(), message);
+                answered.push(port);
+            },
+        }
+    }
+    answered
+}
+
+/// Tries the waiting joins again as they come due, as long as the node runs.
+pub async fn run(state: AppState) {
+    loop {
+        tokio::time::sleep(CHECK_EVERY).await;
+        retry(&state, Instant::now()).await;
+    }
+}
+
+pub async fn list(State(state): State<AppState>) -> Json<Vec<Waiting>> {
+    Json(state.joins.waiting())
+}
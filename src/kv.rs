@@ -0,0 +1,332 @@
+//! A key-value state machine on top of the ledger.
+//!
+//! KV writes are ledger values holding a JSON [`Command`]; every learned value
+//! is applied in instance order, by the `apply` task, and values that aren't
+//! commands are ignored. Reads are served from the local copy, so
+//! they can be stale, except that a write only returns once the node it went
+//! to applied it.
+//!
+//! With `--shards`, each key lives in the KV store of the group it hashes
+//! to; see `shards`. `?group=<id>` reads and writes a key in a named group
+//! instead; see `groups`.
+//!
+//! Keys under `__acl/`, `__ns/` and `__schema/` are reserved: `acl`,
+//! `namespace` and `schema` keep what they need there, and the KV API
+//! refuses them. A write to a key with a schema must satisfy it; see
+//! `schema`.
+//!
+//! A [`StateMachine`] can take the place of the commands above, to give
+//! values a meaning of its own; see `plugin`.
+
+use std::{collections::HashMap, fmt, sync::Arc};
+use axum::{
+    http::{HeaderMap, StatusCode},
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState, admin, backpressure, chain, disk,
+    acl::{self, Grant, Op},
+    barrier,
+    chunked::{self, Upload},
+    groups,
+    history::Function,
+    input::{Path, Query},
+    intake,
+    lease,
+    namespace,
+    quota,
+    readonly::{self, ReadOnly},
+    schema,
+    shards,
+    shutdown,
+    status,
+    topic,
+    txn,
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Command {
+    Put { key: String, value: String },
+    Delete { key: String },
+    /// Drops the keys of a namespace written before `before`; see `namespace`.
+    Expire { namespace: String, before: u64 },
+    /// Votes on a transaction's writes to this group; see `txn`.
+    Prepare { txn: String, ops: Vec<Command>, groups: Vec<usize> },
+    /// Votes against a transaction, unless this group already voted.
+    Refuse { txn: String },
+    /// Ends a transaction this group voted prepared on.
+    Decide { txn: String, commit: bool },
+    /// Acts on lease `name` at `at`, in microseconds; see `lease`.
+    Lease { name: String, at: u64, action: lease::Action },
+    /// Enters `participant` in barrier `barrier` at `at`; see `barrier`.
+    Enter { barrier: String, participant: String, count: usize, lease: Option<String>, at: u64 },
+    /// Publishes `message` to `topic`; see `topic`.
+    Publish { topic: String, id: String, message: String },
+}
+
+impl Command {
+    pub fn parse(value: &str) -> Option<Self> {
+        serde_json::from_str(value).ok()
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// What learned values do to the KV store, if not the [`Command`]s.
+pub trait StateMachine: Send + Sync + fmt::Debug {
+    /// Applies `value` to `data`, and answers what it changed. It must
+    /// come to the same on every node, given the same `data` and `value`.
+    fn apply(&self, data: &mut HashMap<String, String>, value: &str) -> Vec<Change>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Kv {
+    pub data: HashMap<String, String>,
+    /// Applies values in place of the commands, but the commands on
+    /// reserved keys, which the node keeps for itself.
+    pub machine: Option<Arc<dyn StateMachine>>,
+}
+
+/// What applying a value did to one key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub key: String,
+    /// The value before, if the key was there.
+    pub before: Option<String>,
+    /// The value after, if the key is still there.
+    pub after: Option<String>,
+}
+
+/// Whether `value` is a command on a key the node keeps for itself, a
+/// transaction's, or one of a lease, a barrier or a topic.
+fn is_reserved(value: &str) -> bool {
+    match Command::parse(value) {
+        Some(Command::Put { key, .. } | Command::Delete { key }) => acl::is_reserved(&key),
+        Some(Command::Prepare { .. } | Command::Refuse { .. } | Command::Decide { .. } | Command::Lease { .. } | Command::Enter { .. } | Command::Publish { .. }) => true,
+        _ => false,
+    }
+}
+
+impl Kv {
+    pub fn with_machine(machine: Option<Arc<dyn StateMachine>>) -> Self {
+        Self { data: HashMap::new(), machine }
+    }
+
+    /// The state machine `value` goes to, if not the commands.
+    fn machine_for(&self, value: &str) -> Option<Arc<dyn StateMachine>> {
+        self.machine.clone().filter(|_| !is_reserved(value))
+    }
+
+    pub fn apply(&mut self, value: &str) {
+        if let Some(machine) = self.machine_for(value) {
+            machine.apply(&mut self.data, value);
+            return;
+        }
+        match Command::parse(value) {
+            None => {},
+            Some(Command::Put { key, value }) => {
+                self.data.insert(key, value);
+            },
+            Some(Command::Delete { key }) => {
+                self.data.remove(&key);
+            },
+            Some(Command::Expire { namespace, before }) => {
+                namespace::expire(&mut self.data, &namespace, before);
+            },
+            Some(Command::Lease { name, at, action }) => {
+                lease::apply(self, &name, at, action);
+            },
+            Some(Command::Enter { barrier, participant, count, lease, at }) => {
+                barrier::apply(self, &barrier, participant, count, lease, at);
+            },
+            Some(Command::Publish { topic, id, message }) => {
+                topic::apply(self, &topic, id, message);
+            },
+            Some(command) => {
+                txn::apply(self, command);
+            },
+        }
+    }
+
+    /// Like [`Kv::apply`], and answers what `value` changed, for the apply
+    /// hooks; see `apply`.
+    pub fn apply_changes(&mut self, value: &str) -> Vec<Change> {
+        if let Some(machine) = self.machine_for(value) {
+            return machine.apply(&mut self.data, value);
+        }
+        match Command::parse(value) {
+            None => Vec::new(),
+            Some(Command::Put { key, value }) => {
+                let before = self.data.insert(key.clone(), value.clone());
+                vec![Change { key, before, after: Some(value) }]
+            },
+            Some(Command::Delete { key }) => match self.data.remove(&key) {
+                Some(before) => vec![Change { key, before: Some(before), after: None }],
+                None => Vec::new(),
+            },
+            Some(Command::Expire { namespace, before }) => {
+                namespace::expire(&mut self.data, &namespace, before).into_iter()
+                    .map(|(key, value)| Change { key, before: Some(value), after: None })
+                    .collect()
+            },
+            Some(Command::Lease { name, at, action }) => lease::apply(self, &name, at, action),
+            Some(Command::Enter { barrier, participant, count, lease, at }) => barrier::apply(self, &barrier, participant, count, lease, at),
+            Some(Command::Publish { topic, id, message }) => topic::apply(self, &topic, id, message),
+            Some(command) => txn::apply(self, command),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.data.get(key)
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ReadQuery {
+    /// Answer with the value and a proof of its write; see `chain`.
+    #[serde(default)]
+    pub proof: bool,
+    /// The group the key is in, if not the node's own; see `groups`.
+    pub group: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct WriteQuery {
+    /// The group the key is in, if not the node's own; see `groups`.
+    pub group: Option<String>,
+}
+
+/// The group that holds `key`: the one the client named, or else its shard.
+fn group<'a>(state: &'a AppState, named: Option<&str>, key: &str) -> Result<&'a AppState, (StatusCode, String)> {
+    match named {
+        Some(_) => groups::named(state, named),
+        None => Ok(shards::route(state, key)),
+    }
+}
+
+pub async fn get_key(State(state): State<AppState>, Path(key): Path<String>, Query(query): Query<ReadQuery>, headers: HeaderMap) -> Response {
+    if let Err(refusal) = acl::check(&state, &headers, Op::Read, &key).await {
+        return refusal.into_response();
+    }
+    let group = match group(&state, query.group.as_deref(), &key) {
+        Ok(group) => group,
+        Err(refusal) => return refusal.into_response(),
+    };
+    if query.proof {
+        return chain::read(group, &key).await;
+    }
+    read(group, &key, &key, &headers, |value| Some(value.clone())).await
+}
+
+/// Answers a read of `key`, which the client knows as `shown`, with what
+/// `view` makes of its value.
+pub(crate) async fn read(state: &AppState, key: &str, shown: &str, headers: &HeaderMap, view: impl FnOnce(&String) -> Option<String>) -> Response {
+    let op = state.history.as_ref().map(|history| history.invoke(Function::Read, key, None));
+
+    let value = state.kv.lock().await.get(key).and_then(view);
+
+    if let (Some(history), Some(op)) = (&state.history, op) {
+        history.ok(op, Function::Read, key, value.as_deref());
+    }
+
+    match value {
+        None => (StatusCode::NOT_FOUND, format!("Key {} not found", shown)).into_response(),
+        Some(value) if chunked::accepts(headers) => chunked::respond(std::io::Cursor::new(value.into_bytes())),
+        Some(value) => (StatusCode::OK, value).into_response(),
+    }
+}
+
+pub async fn put_key(State(state): State<AppState>, Path(key): Path<String>, Query(query): Query<WriteQuery>, headers: HeaderMap, Upload(value): Upload) -> Response {
+    let grant = match acl::check(&state, &headers, Op::Write, &key).await {
+        Ok(grant) => grant,
+        Err(refusal) => return refusal.into_response(),
+    };
+    let group = match group(&state, query.group.as_deref(), &key) {
+        Ok(group) => group,
+        Err(refusal) => return refusal.into_response(),
+    };
+    if let Err(refusal) = schema::admit(&state, None, &key, &value).await {
+        return refusal.into_response();
+    }
+    let command = Command::Put { key: key.clone(), value: value.clone() };
+    status::stamped(group, write(group, grant.as_ref(), Function::Write, key, Some(value), command).await)
+}
+
+pub async fn delete_key(State(state): State<AppState>, Path(key): Path<String>, Query(query): Query<WriteQuery>, headers: HeaderMap) -> Response {
+    let grant = match acl::check(&state, &headers, Op::Delete, &key).await {
+        Ok(grant) => grant,
+        Err(refusal) => return refusal.into_response(),
+    };
+    let group = match group(&state, query.group.as_deref(), &key) {
+        Ok(group) => group,
+        Err(refusal) => return refusal.into_response(),
+    };
+    let command = Command::Delete { key: key.clone() };
+    status::stamped(group, write(group, grant.as_ref(), Function::Delete, key, None, command).await)
+}
+
+/// Why the node won't take a write now, if it won't.
+pub(crate) fn refusal(state: &AppState) -> Option<(StatusCode, String)> {
+    if state.is_paused() {
+        return Some(admin::refuse_paused());
+    }
+    if state.read_only() == Some(ReadOnly::Reject) {
+        return Some(readonly::refuse());
+    }
+    if state.disk.is_low() {
+        return Some(disk::refuse());
+    }
+    None
+}
+
+/// Proposes `command` for the holder of `grant`, or for the node itself
+/// without one.
+pub(crate) async fn write(state: &AppState, grant: Option<&Grant>, f: Function, key: String, value: Option<String>, command: Command) -> (StatusCode, String) {
+    if let Some(refusal) = refusal(state) {
+        return refusal;
+    }
+    let Some(_proposal) = state.shutdown.enter() else {
+        return shutdown::refuse();
+    };
+    let command = command.encode();
+    if let Err(refusal) = quota::admit(state, grant, command.len()).await {
+        return refusal;
+    }
+    let limits = state.settings.read().unwrap().backpressure;
+    let Some(_slot) = state.backpressure.admit(limits).await else {
+        println!("[/kv] Node {} is turning a proposal away, {} are queued", state.node.id, state.backpressure.queued());
+        return backpressure::refuse();
+    };
+
+    let op = state.history.as_ref().map(|history| history.invoke(f, &key, value.as_deref()));
+
+    let bytes = command.len();
+    let result = intake::submit(state, command).await;
+    quota::record(state, grant, bytes, &result);
+    match result {
+        // Other streams may leave holes below the instance for a while.
+        Ok(instance) if state.streams.count() > 1 => state.applier.applied(instance).await,
+        Ok(_) => state.applier.caught_up().await,
+        Err(_) => {},
+    }
+
+    if let (Some(history), Some(op)) = (&state.history, op) {
+        // A failed round may still get its value chosen later, so the
+        // outcome is unknown rather than failed.
+        match result {
+            Ok(_) => history.ok(op, f, &key, value.as_deref()),
+            Err(_) => history.info(op, f, &key, value.as_deref()),
+        }
+    }
+
+    match result {
+        Err(e) => e.refusal(),
+        Ok(instance) => (StatusCode::OK, format!("Stored {} at instance {}!", key, instance)),
+    }
+}
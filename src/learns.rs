@@ -0,0 +1,335 @@
+//! Telling the peers what was decided, a batch at a time.
+//!
+//! A decision used to cost one `/handle-learn` per peer. Now the proposer
+//! learns it locally and queues it, and a task of the node's own sends
+//! whatever is queued as one `POST /handle-learns` per peer. It waits up to
+//! `--learn-batch-delay-ms` after the first decision for more to come, and
+//! no longer once `--learn-batch-max` are queued; with no delay it still
+//! sends everything that queued up while the previous batch was out. Peers
+//! older than [`version::LEARN_BATCH`] get their learns one by one.
+//!
+//! Waiting only pays when more decisions are coming, so unless started with
+//! `--learn-batch-fixed` the node keeps an average of the time between them
+//! and waits about long enough to fill a batch at that pace, never longer
+//! than the delay: the whole delay under load, and nothing when a decision
+//! comes along rarely enough that none would join it. `GET /metrics` shows
+//! the current wait as `paxos_learn_batch_window_seconds`.
+//!
+//! A decision every voter accepted doesn't even need that: the next accept
+//! the proposer sends carries it as [`Committed`], just an instance and a
+//! proposal id, and each peer learns the value it accepted under that id.
+//! In the steady state, with proposals following each other within the
+//! delay, there is no learn traffic at all. Only peers that speak
+//! [`version::PIGGYBACK`] read it, so it waits until all of them do, and
+//! with a learner among the peers it isn't done at all, as learners get no
+//! accepts; see [`crate::replica`].
+//!
+//! In a big cluster even one batch per peer is a lot for the proposer to
+//! send. With `--learn-gossip-fanout k` it sends each batch to only `k`
+//! peers picked at random, as `POST /gossip-learns`, and every peer that
+//! learns something new from it passes that on to `k` more, so the batch
+//! spreads through the cluster while the proposer's own traffic stays the
+//! same however many peers it has. It only does once every peer speaks
+//! [`version::GOSSIP`], and with no more peers than `k` it sends directly.
+//!
+//! On a LAN, `--learn-multicast` sends each batch as one datagram to a
+//! multicast group instead, for the peers that joined it; see
+//! [`crate::multicast`].
+//!
+//! With `--log-shipping` neither is used: each batch goes out as part of
+//! the log, from where each follower stands; see [`crate::shipping`].
+//!
+//! A learn is only a shortcut, so one lost with its batch is no worse than
+//! before: the peer finds out when it next prepares that instance. The same
+//! goes for a peer the gossip happened to miss.
+
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}},
+    time::{Duration, Instant as StdInstant},
+};
+use tokio::{sync::Notify, time::Instant};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{AppState, Ballot, Id, Node, ProposalId, fanout, multicast, rng::Rng, shipping, version};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Batching {
+    /// How long a decision waits for others to go with it.
+    pub max_delay: Duration,
+    /// Decisions per batch; 0 for any number.
+    pub max_batch: usize,
+    /// Whether to wait less than `max_delay` when decisions are few.
+    pub adaptive: bool,
+}
+
+impl Batching {
+    fn limit(&self) -> usize {
+        if self.max_batch == 0 { usize::MAX } else { self.max_batch }
+    }
+}
+
+/// A decision every voter accepted, by the proposal it was accepted under.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Committed {
+    pub instance: u64,
+    pub id: ProposalId,
+}
+
+#[derive(Debug)]
+struct Queued {
+    ballot: Ballot,
+    /// Whether every voter accepted it.
+    everywhere: bool,
+}
+
+/// How much of the last gap between decisions goes into the average.
+const GAP_WEIGHT: f64 = 0.2;
+
+/// How often decisions come along.
+#[derive(Debug, Default)]
+struct Pace {
+    last: Option<StdInstant>,
+    /// Average time between decisions, in seconds.
+    gap: Option<f64>,
+}
+
+impl Pace {
+    fn record(&mut self, now: StdInstant) {
+        if let Some(last) = self.last {
+            let gap = now.duration_since(last).as_secs_f64();
+            self.gap = Some(self.gap.map_or(gap, |average| average + GAP_WEIGHT * (gap - average)));
+        }
+        self.last = Some(now);
+    }
+}
+
+/// The decisions waiting to be sent. The task sending them starts with the
+/// first one.
+#[derive(Debug, Default)]
+pub struct Learns {
+    queue: Mutex<VecDeque<Queued>>,
+    queued: Notify,
+    /// Decisions taken off the queue whose batch is still out.
+    sending: AtomicUsize,
+    /// Gossip this node is passing on.
+    gossiping: AtomicUsize,
+    started: AtomicBool,
+    pace: Mutex<Pace>,
+}
+
+impl Learns {
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decisions queued or on their way to the peers, including gossip
+    /// being passed on.
+    pub fn pending(&self) -> usize {
+        self.len() + self.sending.load(Ordering::SeqCst) + self.gossiping.load(Ordering::SeqCst)
+    }
+
+    /// Queues `ballot` to be sent to every peer; `everywhere` if every
+    /// voter accepted it, so it can go along with the next accept.
+    pub fn push(&self, state: &AppState, ballot: Ballot, everywhere: bool) {
+        self.pace.lock().unwrap().record(StdInstant::now());
+        self.queue.lock().unwrap().push_back(Queued { ballot, everywhere });
+        self.queued.notify_one();
+
+        if !self.started.swap(true, Ordering::SeqCst) {
+            tokio::spawn(run(state.clone()));
+        }
+    }
+
+    fn take(&self, limit: usize) -> Vec<Ballot> {
+        let mut queue = self.queue.lock().unwrap();
+        let count = queue.len().min(limit);
+        self.sending.fetch_add(count, Ordering::SeqCst);
+        queue.drain(..count).map(|queued| queued.ballot).collect()
+    }
+
+    /// Takes the queued decisions that can go along with an accept to
+    /// `peers`, if all of them would read it. Call [`Learns::sent`] once the
+    /// accept is out.
+    pub fn piggyback(&self, state: &AppState, peers: impl IntoIterator<Item = u64>) -> Vec<Committed> {
+        if state.versions.common(peers) < version::PIGGYBACK {
+            return Vec::new();
+        }
+
+        let mut queue = self.queue.lock().unwrap();
+        let mut committed = Vec::new();
+        queue.retain(|queued| {
+            if queued.everywhere {
+                committed.push(Committed { instance: queued.ballot.instance, id: queued.ballot.id });
+            }
+            !queued.everywhere
+        });
+        self.sending.fetch_add(committed.len(), Ordering::SeqCst);
+        committed
+    }
+
+    /// Marks `count` decisions taken off the queue as delivered.
+    pub fn sent(&self, count: usize) {
+        self.sending.fetch_sub(count, Ordering::SeqCst);
+    }
+
+    /// How long a decision now waits for others: `max_delay`, or with
+    /// `adaptive` about what it takes to fill a batch at the recent pace,
+    /// and nothing if another isn't due within `max_delay`.
+    pub fn window(&self, batching: Batching) -> Duration {
+        if !batching.adaptive {
+            return batching.max_delay;
+        }
+        let Some(gap) = self.pace.lock().unwrap().gap else {
+            return Duration::ZERO;
+        };
+
+        let max_delay = batching.max_delay.as_secs_f64();
+        if gap >= max_delay {
+            return Duration::ZERO;
+        }
+        let fill = gap * batching.limit().saturating_sub(1) as f64;
+        Duration::from_secs_f64(fill.min(max_delay))
+    }
+
+    /// Waits for a batch's worth of decisions, or for the oldest to have
+    /// waited long enough.
+    async fn next(&self, batching: Batching) -> Vec<Ballot> {
+        while self.is_empty() {
+            self.queued.notified().await;
+        }
+
+        // No delay needs no timer, which the simulator doesn't have.
+        let window = self.window(batching);
+        if window.is_zero() {
+            return self.take(batching.limit());
+        }
+
+        let deadline = Instant::now() + window;
+        while self.len() < batching.limit() {
+            if tokio::time::timeout_at(deadline, self.queued.notified()).await.is_err() {
+                break;
+            }
+        }
+        self.take(batching.limit())
+    }
+}
+
+/// Learns passed on from peer to peer, at most `ttl` more times.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gossip {
+    pub ballots: Vec<Ballot>,
+    pub ttl: u32,
+}
+
+/// `count` of `peers`, picked at random but the same for the same `seed`,
+/// so a simulation replays.
+fn pick(peers: &[Node], count: usize, seed: u64) -> Vec<Node> {
+    let mut peers = peers.to_vec();
+    let mut rng = Rng::new(seed);
+    let count = count.min(peers.len());
+    for i in 0..count {
+        let j = i + rng.below((peers.len() - i) as u64) as usize;
+        peers.swap(i, j);
+    }
+    peers.truncate(count);
+    peers
+}
+
+/// Sends `ballots` as gossip to some of the peers but `skip`.
+async fn spread(state: &AppState, ballots: &[Ballot], ttl: u32, skip: &[Id]) {
+    let fanout = state.settings.read().unwrap().learn_gossip;
+    let peers: Vec<Node> = state.nodes.snapshot().iter().filter(|node| !skip.contains(&node.id)).cloned().collect();
+    let seed = state.node.id ^ ballots[0].instance.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let targets = pick(&peers, fanout, seed);
+
+    let gossip = Gossip { ballots: ballots.to_vec(), ttl };
+    fanout::post_all(state, &targets, "/gossip-learns", &gossip).await;
+}
+
+/// Passes on what this node just learned from `from`'s gossip, unless it
+/// went far enough already.
+pub fn pass_on(state: &AppState, gossip: Gossip, from: Option<Id>) {
+    if gossip.ballots.is_empty() || gossip.ttl == 0 || state.settings.read().unwrap().learn_gossip == 0 {
+        return;
+    }
+
+    let state = state.clone();
+    state.learns.gossiping.fetch_add(1, Ordering::SeqCst);
+    tokio::spawn(async move {
+        let mut skip: Vec<Id> = gossip.ballots.iter().map(|ballot| ballot.id.node_id).collect();
+        skip.extend(from);
+        spread(&state, &gossip.ballots, gossip.ttl, &skip).await;
+        state.learns.gossiping.fetch_sub(1, Ordering::SeqCst);
+    });
+}
+
+/// Sends `batch` to every peer: as part of the log to those it is shipped
+/// to, at once to those on the multicast group, batched to those that take
+/// it whole, or to a few to gossip on.
+async fn send(state: &AppState, batch: &[Ballot]) {
+    if state.settings.read().unwrap().log_shipping {
+        let shipped = shipping::ship(state).await;
+        let rest: Vec<Node> = state.nodes.snapshot().iter().filter(|node| !shipped.contains(&node.id)).cloned().collect();
+        tell(state, &rest, batch).await;
+        state.learns.sent(batch.len());
+        println!("[learn] Node {} shipped {} decisions to {} followers", state.node.id, batch.len(), shipped.len());
+        return;
+    }
+
+    let multicast = multicast::send(state, batch).await;
+    let peers: Vec<Node> = state.nodes.snapshot().iter().filter(|node| !multicast.contains(&node.id)).cloned().collect();
+    if peers.is_empty() {
+        state.learns.sent(batch.len());
+        println!("[learn] Node {} multicast {} decisions", state.node.id, batch.len());
+        return;
+    }
+
+    let fanout = state.settings.read().unwrap().learn_gossip;
+    if fanout > 0 && peers.len() > fanout && state.versions.common(peers.iter().map(|node| node.id)) >= version::GOSSIP {
+        // Every hop reaches more peers, so as many hops as peers is plenty.
+        let skip: Vec<Id> = multicast.into_iter().collect();
+        spread(state, batch, peers.len() as u32, &skip).await;
+        state.learns.sent(batch.len());
+        println!("[learn] Node {} gossiped {} decisions to {} peers", state.node.id, batch.len(), fanout);
+        return;
+    }
+
+    tell(state, &peers, batch).await;
+    state.learns.sent(batch.len());
+    println!("[learn] Node {} told its peers about {} decisions", state.node.id, batch.len());
+}
+
+/// Sends `batch` to each of `peers`, as one request to those that take it
+/// whole.
+async fn tell(state: &AppState, peers: &[Node], batch: &[Ballot]) {
+    let (batched, single): (Vec<Node>, Vec<Node>) = peers.iter().cloned()
+        .partition(|node| state.versions.of(node.id) >= version::LEARN_BATCH);
+
+    let singles = batch.iter().map(|ballot| fanout::post_all(state, &single, "/handle-learn", ballot));
+    futures::join!(
+        fanout::post_all(state, &batched, "/handle-learns", &batch),
+        futures::future::join_all(singles),
+    );
+}
+
+async fn run(state: AppState) {
+    loop {
+        let batching = state.settings.read().unwrap().learn_batching;
+        let batch = state.learns.next(batching).await;
+        send(&state, &batch).await;
+    }
+}
+
+/// Sends everything still queued right away, e.g. before leaving.
+pub async fn flush(state: &AppState) {
+    let batch = state.learns.take(usize::MAX);
+    if !batch.is_empty() {
+        send(state, &batch).await;
+    }
+}
@@ -0,0 +1,248 @@
+//! Named leases with a TTL, for locks, leader recipes and ephemeral keys.
+//!
+//! `POST /leases/<name>` takes the lease `name` for a holder for `ttl_ms`,
+//! unless someone else holds it; `POST /leases/<name>/renew` extends it by
+//! the same again, and `DELETE /leases/<name>?holder=<holder>` gives it up.
+//! `PUT /leases/<name>/keys/<key>?holder=<holder>` sets a key that lives
+//! as long as the lease: it goes with the lease, whichever way that ends.
+//! Only the holder can renew, release or set keys, and a lease taken again
+//! by its holder keeps its keys.
+//!
+//! Each of these is a [`Command::Lease`] in the node's own log, stamped
+//! with the time the proposer's clock read, see `hlc`, and applied against
+//! that time, so every node comes to the same holder and expiry. A lease is
+//! kept in the KV store under [`PREFIX`], which makes it replicated,
+//! snapshotted and backed up with everything else. Reads stop showing a
+//! lease as soon as it has expired, and anyone can take it from then on.
+//! Every node also sweeps each second and proposes to expire the leases
+//! that ran out; like a release, that revocation is a value in the log,
+//! which apply hooks see as the lease's key going away.
+
+use std::{collections::BTreeMap, time::Duration};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState,
+    acl::{self, Op},
+    chunked::Upload,
+    input::{Json, Path, Query},
+    intake,
+    kv::{self, Change, Command, Kv},
+    shutdown,
+};
+
+/// Where leases are kept in the KV store.
+pub const PREFIX: &str = "__lease/";
+
+const SWEEP_EVERY: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub name: String,
+    pub holder: String,
+    pub ttl_ms: u64,
+    /// Microseconds since the Unix epoch, as the proposers' clocks go.
+    pub expires: u64,
+    /// The keys that live as long as the lease.
+    #[serde(default)]
+    pub keys: BTreeMap<String, String>,
+}
+
+/// When a lease of `ttl_ms` taken or renewed at `at` runs out.
+fn expiry(at: u64, ttl_ms: u64) -> u64 {
+    at.saturating_add(ttl_ms.saturating_mul(1000))
+}
+
+impl Lease {
+    pub fn is_live(&self, at: u64) -> bool {
+        at < self.expires
+    }
+}
+
+/// What a [`Command::Lease`] does.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Action {
+    Acquire { holder: String, ttl_ms: u64 },
+    Renew { holder: String },
+    Release { holder: String },
+    /// Ends the lease if it ran out by then.
+    Expire,
+    Attach { holder: String, key: String, value: String },
+}
+
+pub(crate) fn key(name: &str) -> String {
+    format!("{}{}", PREFIX, name)
+}
+
+/// Lease `name` as `kv` has it, expired or not.
+pub fn get(kv: &Kv, name: &str) -> Option<Lease> {
+    serde_json::from_str(kv.get(&key(name))?).ok()
+}
+
+/// Every lease in `kv`, expired or not.
+pub fn leases(kv: &Kv) -> Vec<Lease> {
+    let mut leases: Vec<Lease> = kv.data.iter()
+        .filter(|(key, _)| key.starts_with(PREFIX))
+        .filter_map(|(_, lease)| serde_json::from_str(lease).ok())
+        .collect();
+    leases.sort_by(|a, b| a.name.cmp(&b.name));
+    leases
+}
+
+/// Applies `action` on lease `name` at `at` to `kv`; this is how every
+/// node takes and ends leases. Answers what it changed.
+pub(crate) fn apply(kv: &mut Kv, name: &str, at: u64, action: Action) -> Vec<Change> {
+    let current = get(kv, name);
+    let held_by = |holder: &str| current.as_ref().filter(|lease| lease.holder == holder && lease.is_live(at)).cloned();
+    let after = match action {
+        Action::Acquire { holder, ttl_ms } => match held_by(&holder) {
+            Some(lease) => Some(Lease { ttl_ms, expires: expiry(at, ttl_ms), ..lease }),
+            None if current.as_ref().is_some_and(|lease| lease.is_live(at)) => return Vec::new(),
+            None => Some(Lease { name: name.to_string(), holder, ttl_ms, expires: expiry(at, ttl_ms), keys: BTreeMap::new() }),
+        },
+        Action::Renew { holder } => match held_by(&holder) {
+            Some(lease) => Some(Lease { expires: expiry(at, lease.ttl_ms), ..lease }),
+            None => return Vec::new(),
+        },
+        Action::Release { holder } if held_by(&holder).is_some() => None,
+        Action::Expire if current.as_ref().is_some_and(|lease| !lease.is_live(at)) => None,
+        Action::Attach { holder, key, value } => match held_by(&holder) {
+            Some(mut lease) => {
+                lease.keys.insert(key, value);
+                Some(lease)
+            },
+            None => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+
+    let key = key(name);
+    let before = match &after {
+        Some(lease) => kv.data.insert(key.clone(), serde_json::to_string(lease).unwrap()),
+        None => kv.data.remove(&key),
+    };
+    vec![Change { key, before, after: after.map(|lease| serde_json::to_string(&lease).unwrap()) }]
+}
+
+/// Proposes `action` on lease `name`, and answers the lease once the node
+/// applied it.
+pub(crate) async fn act(state: &AppState, headers: &HeaderMap, name: &str, action: Action) -> Result<Option<Lease>, (StatusCode, String)> {
+    acl::authorize(state, headers, Op::Write, &key(name)).await?;
+    if let Some(refusal) = kv::refusal(state) {
+        return Err(refusal);
+    }
+    let Some(_proposal) = state.shutdown.enter() else {
+        return Err(shutdown::refuse());
+    };
+
+    let at = state.clock.now().wall;
+    let command = Command::Lease { name: name.to_string(), at, action };
+    let instance = intake::submit(state, command.encode()).await.map_err(|e| e.refusal())?;
+    state.applier.applied(instance).await;
+    Ok(get(&*state.kv.lock().await, name).filter(|lease| lease.is_live(at)))
+}
+
+/// Answers `lease` if `holder` holds it, and why not otherwise.
+fn held(name: &str, holder: &str, lease: Option<Lease>) -> Response {
+    match lease {
+        Some(lease) if lease.holder == holder => Json(lease).into_response(),
+        Some(lease) => (StatusCode::CONFLICT, format!("Lease {} is held by {}!", name, lease.holder)).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("{} holds no lease {}!", holder, name)).into_response(),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Acquire {
+    pub holder: String,
+    pub ttl_ms: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Holder {
+    pub holder: String,
+}
+
+pub async fn acquire(State(state): State<AppState>, Path(name): Path<String>, headers: HeaderMap, Json(request): Json<Acquire>) -> Response {
+    if request.ttl_ms == 0 {
+        return (StatusCode::BAD_REQUEST, String::from("A lease needs a ttl_ms above 0!")).into_response();
+    }
+    let action = Action::Acquire { holder: request.holder.clone(), ttl_ms: request.ttl_ms };
+    match act(&state, &headers, &name, action).await {
+        Ok(lease) => held(&name, &request.holder, lease),
+        Err(refusal) => refusal.into_response(),
+    }
+}
+
+pub async fn renew(State(state): State<AppState>, Path(name): Path<String>, headers: HeaderMap, Json(request): Json<Holder>) -> Response {
+    match act(&state, &headers, &name, Action::Renew { holder: request.holder.clone() }).await {
+        Ok(lease) => held(&name, &request.holder, lease),
+        Err(refusal) => refusal.into_response(),
+    }
+}
+
+pub async fn release(State(state): State<AppState>, Path(name): Path<String>, Query(query): Query<Holder>, headers: HeaderMap) -> Response {
+    match act(&state, &headers, &name, Action::Release { holder: query.holder.clone() }).await {
+        Ok(None) => (StatusCode::OK, format!("Released lease {}!", name)).into_response(),
+        Ok(lease) => held(&name, &query.holder, lease),
+        Err(refusal) => refusal.into_response(),
+    }
+}
+
+pub async fn put_key(State(state): State<AppState>, Path((name, key)): Path<(String, String)>, Query(query): Query<Holder>, headers: HeaderMap, Upload(value): Upload) -> Response {
+    let action = Action::Attach { holder: query.holder.clone(), key: key.clone(), value: value.clone() };
+    match act(&state, &headers, &name, action).await {
+        Ok(lease) => held(&name, &query.holder, lease),
+        Err(refusal) => refusal.into_response(),
+    }
+}
+
+pub async fn get_lease(State(state): State<AppState>, Path(name): Path<String>, headers: HeaderMap) -> Response {
+    if let Err(refusal) = acl::authorize(&state, &headers, Op::Read, &key(&name)).await {
+        return refusal.into_response();
+    }
+    let now = state.clock.now().wall;
+    match get(&*state.kv.lock().await, &name).filter(|lease| lease.is_live(now)) {
+        Some(lease) => Json(lease).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Lease {} not found", name)).into_response(),
+    }
+}
+
+pub async fn get_leases(State(state): State<AppState>) -> Json<Vec<Lease>> {
+    let now = state.clock.now().wall;
+    Json(leases(&*state.kv.lock().await).into_iter().filter(|lease| lease.is_live(now)).collect())
+}
+
+/// Proposes to expire the leases that ran out by `now`, and returns how
+/// many it found.
+pub async fn sweep(state: &AppState, now: u64) -> usize {
+    if state.is_paused() || state.read_only().is_some() || state.disk.is_low() {
+        return 0;
+    }
+
+    let due: Vec<String> = leases(&*state.kv.lock().await).into_iter()
+        .filter(|lease| !lease.is_live(now))
+        .map(|lease| lease.name)
+        .collect();
+    for name in &due {
+        let command = Command::Lease { name: name.clone(), at: now, action: Action::Expire };
+        match intake::submit(state, command.encode()).await {
+            Ok(instance) => println!("[lease] Node {} expired lease {} at instance {}", state.node.id, name, instance),
+            Err(e) => println!("[lease] Node {} failed to expire lease {}: {}", state.node.id, name, e),
+        }
+    }
+    due.len()
+}
+
+/// Sweeps for expired leases as long as the node runs.
+pub async fn run(state: AppState) {
+    loop {
+        tokio::time::sleep(SWEEP_EVERY).await;
+        sweep(&state, state.clock.now().wall).await;
+    }
+}
@@ -0,0 +1,240 @@
+//! The learned values, shared between the learner and everyone reading them.
+//!
+//! Learning a value also persists it and applies it to the KV store, and
+//! used to hold the one ledger lock throughout, so every read of it, a
+//! decided instance for a prepare, a digest or the metrics, waited for the
+//! slowest apply. Values are now spread over shards keyed by instance, each
+//! locked only for the insert or lookup itself. Writers still go one at a
+//! time, through [`SharedLedger::write`], and hold that until the KV store
+//! caught up, so a snapshot taken under it sees one consistent with the
+//! other.
+//!
+//! A reader that doesn't hold the writer may see a value learned a moment
+//! before the KV store applied it.
+//!
+//! Alongside the values, the ledger keeps the hash chain over them that
+//! `chain` describes, extended as the instances after the last one chained
+//! are learned, and the timestamp this node learned each of them at; see
+//! `hlc`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{RwLock, atomic::{AtomicU64, AtomicUsize, Ordering}},
+};
+use axum::extract::State;
+use serde::{Serialize, Deserialize};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::{
+    AppState, Ledger, Value,
+    Id,
+    chain::{self, GENESIS, Hash, Range, Rewrite},
+    hex,
+    hlc::Timestamp,
+    input::{Json, Query},
+};
+
+const SHARDS: usize = 16;
+
+/// The most entries `GET /ledger` answers with at once.
+const MAX_ENTRIES: u64 = 1000;
+
+#[derive(Debug)]
+pub struct SharedLedger {
+    shards: Vec<RwLock<Ledger>>,
+    /// The highest instance learned, 0 for none.
+    last: AtomicU64,
+    len: AtomicUsize,
+    /// The hash of every instance from 1 up to the first not learned.
+    chain: RwLock<Vec<Hash>>,
+    /// When this node learned each instance, of those it learned since it
+    /// started.
+    stamps: RwLock<HashMap<u64, Timestamp>>,
+    /// What repairs replaced, by instance.
+    rewrites: RwLock<BTreeMap<u64, Vec<Rewrite>>>,
+    writer: Mutex<()>,
+}
+
+impl Default for SharedLedger {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| RwLock::new(HashMap::new())).collect(),
+            last: AtomicU64::new(0),
+            len: AtomicUsize::new(0),
+            chain: RwLock::new(Vec::new()),
+            stamps: RwLock::new(HashMap::new()),
+            rewrites: RwLock::new(BTreeMap::new()),
+            writer: Mutex::new(()),
+        }
+    }
+}
+
+impl SharedLedger {
+    fn shard(&self, instance: u64) -> &RwLock<Ledger> {
+        &self.shards[instance as usize % SHARDS]
+    }
+
+    pub fn get(&self, instance: u64) -> Option<Value> {
+        self.shard(instance).read().unwrap().get(&instance).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The highest instance learned, if any.
+    pub fn last(&self) -> Option<u64> {
+        Some(self.last.load(Ordering::SeqCst)).filter(|&last| last > 0)
+    }
+
+    /// How many instances are chained, all of them from 1.
+    pub fn chained(&self) -> u64 {
+        self.chain.read().unwrap().len() as u64
+    }
+
+    /// The hash `instance` is chained under, once every instance up to it
+    /// is learned.
+    pub fn link(&self, instance: u64) -> Option<Hash> {
+        let index = usize::try_from(instance.checked_sub(1)?).ok()?;
+        self.chain.read().unwrap().get(index).copied()
+    }
+
+    /// When this node learned `instance`.
+    pub fn stamp(&self, instance: u64) -> Option<Timestamp> {
+        self.stamps.read().unwrap().get(&instance).copied()
+    }
+
+    /// What repairs replaced in `instance`, oldest first.
+    pub fn rewrites(&self, instance: u64) -> Vec<Rewrite> {
+        self.rewrites.read().unwrap().get(&instance).cloned().unwrap_or_default()
+    }
+
+    /// A copy of every rewrite, for a snapshot.
+    pub fn all_rewrites(&self) -> BTreeMap<u64, Vec<Rewrite>> {
+        self.rewrites.read().unwrap().clone()
+    }
+
+    /// A copy of every learned value. Only consistent with the KV store
+    /// while holding the writer.
+    pub fn to_map(&self) -> Ledger {
+        let mut ledger = HashMap::with_capacity(self.len());
+        for shard in &self.shards {
+            ledger.extend(shard.read().unwrap().iter().map(|(instance, value)| (*instance, value.clone())));
+        }
+        ledger
+    }
+
+    /// Waits for the other writers, then keeps new values out until the
+    /// returned guard is dropped.
+    pub async fn write(&self) -> LedgerWriter<'_> {
+        LedgerWriter { ledger: self, _writer: self.writer.lock().await }
+    }
+
+    /// Like [`SharedLedger::write`], for callers off the runtime.
+    pub fn blocking_write(&self) -> LedgerWriter<'_> {
+        LedgerWriter { ledger: self, _writer: self.writer.blocking_lock() }
+    }
+}
+
+/// Held by whoever is learning values or needs them not to change.
+pub struct LedgerWriter<'a> {
+    ledger: &'a SharedLedger,
+    _writer: MutexGuard<'a, ()>,
+}
+
+impl LedgerWriter<'_> {
+    /// Records `value` as learned in `instance`, and returns whether it was
+    /// new there.
+    pub fn insert(&mut self, instance: u64, value: Value) -> bool {
+        let is_new = self.ledger.shard(instance).write().unwrap().insert(instance, value).is_none();
+        if is_new {
+            self.ledger.len.fetch_add(1, Ordering::SeqCst);
+            self.ledger.last.fetch_max(instance, Ordering::SeqCst);
+            self.extend_chain();
+        }
+        is_new
+    }
+
+    /// Replaces what was learned in `instance` with `value`, and chains
+    /// everything from there again, recording what it replaced on `by`'s
+    /// word. Only a repair does this; see `repair`.
+    pub fn overwrite(&mut self, instance: u64, value: Value, by: Id) {
+        let was = self.get(instance);
+        let hash = self.link(instance);
+        if self.insert(instance, value) {
+            return;
+        }
+        if let Some(was) = was {
+            let rewrite = Rewrite { was: chain::value_hash(&was), hash: hash.map(|hash| hex::encode(&hash)).unwrap_or_default(), by };
+            self.ledger.rewrites.write().unwrap().entry(instance).or_default().push(rewrite);
+        }
+        let mut chain = self.ledger.chain.write().unwrap();
+        chain.truncate(usize::try_from(instance.saturating_sub(1)).unwrap_or(usize::MAX));
+        std::mem::drop(chain);
+        self.extend_chain();
+    }
+
+    /// Records that this node learned `instance` at `at`.
+    pub fn stamp(&mut self, instance: u64, at: Timestamp) {
+        self.ledger.stamps.write().unwrap().insert(instance, at);
+    }
+
+    /// Chains whatever was learned after the last instance chained.
+    fn extend_chain(&self) {
+        let mut chain = self.ledger.chain.write().unwrap();
+        while let Some(value) = self.ledger.get(chain.len() as u64 + 1) {
+            let hash = chain::link(chain.last().unwrap_or(&GENESIS), chain.len() as u64 + 1, &value);
+            chain.push(hash);
+        }
+    }
+
+    /// Takes back the rewrites a snapshot kept.
+    pub fn restore_rewrites(&mut self, rewrites: BTreeMap<u64, Vec<Rewrite>>) {
+        *self.ledger.rewrites.write().unwrap() = rewrites;
+    }
+
+    /// Replaces everything learned with `ledger`.
+    pub fn replace(&mut self, ledger: Ledger) {
+        for shard in &self.ledger.shards {
+            shard.write().unwrap().clear();
+        }
+        self.ledger.len.store(0, Ordering::SeqCst);
+        self.ledger.last.store(0, Ordering::SeqCst);
+        self.ledger.chain.write().unwrap().clear();
+        self.ledger.stamps.write().unwrap().clear();
+
+        for (instance, value) in ledger {
+            self.insert(instance, value);
+        }
+    }
+}
+
+impl std::ops::Deref for LedgerWriter<'_> {
+    type Target = SharedLedger;
+
+    fn deref(&self) -> &SharedLedger {
+        self.ledger
+    }
+}
+
+/// A learned instance, and when this node learned it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub instance: u64,
+    pub value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hlc: Option<Timestamp>,
+}
+
+pub async fn get_ledger(State(state): State<AppState>, Query(range): Query<Range>) -> Json<Vec<Entry>> {
+    let (from, to) = range.bounds(state.ledger.last().unwrap_or(0));
+    let to = to.min(from.saturating_add(MAX_ENTRIES - 1));
+    let entries = (from..=to)
+        .filter_map(|instance| Some(Entry { instance, value: state.ledger.get(instance)?, hlc: state.ledger.stamp(instance) }))
+        .collect();
+    Json(entries)
+}
@@ -0,0 +1,575 @@
+//! Paxos from scratch.
+//!
+//! The protocol core (ballots, [`acceptor`], the bookkeeping of a
+//! [`proposer::Round`], and the in-memory [`playground`]) only needs `serde`,
+//! so it also builds for `wasm32-unknown-unknown`. Everything that talks to a
+//! network or a runtime, the node itself included, is behind the default
+//! `server` feature.
+
+use std::{collections::HashMap, net::SocketAddr};
+#[cfg(feature = "server")]
+use std::{collections::HashSet, sync::{Arc, atomic::{AtomicBool, Ordering}}};
+#[cfg(feature = "server")]
+use axum::{
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
+};
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "server")]
+use tokio::sync::Mutex;
+
+pub mod acceptor;
+#[cfg(feature = "server")]
+pub mod acl;
+#[cfg(feature = "server")]
+pub mod admin;
+#[cfg(feature = "server")]
+pub mod apply;
+#[cfg(feature = "server")]
+pub mod backpressure;
+#[cfg(feature = "server")]
+pub mod barrier;
+#[cfg(feature = "server")]
+pub mod bench;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "server")]
+pub mod broadcast;
+#[cfg(feature = "server")]
+pub mod chain;
+#[cfg(feature = "server")]
+pub mod chaos;
+#[cfg(feature = "server")]
+pub mod client;
+#[cfg(feature = "server")]
+pub mod cluster;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod chunked;
+#[cfg(feature = "server")]
+pub mod consistency;
+#[cfg(feature = "server")]
+pub mod crash;
+#[cfg(feature = "server")]
+pub mod dedup;
+#[cfg(feature = "server")]
+pub mod dev;
+#[cfg(feature = "server")]
+pub mod disk;
+#[cfg(feature = "server")]
+pub mod ed25519;
+#[cfg(feature = "server")]
+pub mod election;
+#[cfg(feature = "server")]
+pub mod encryption;
+#[cfg(feature = "server")]
+pub mod error;
+#[cfg(feature = "server")]
+pub mod events;
+#[cfg(feature = "server")]
+pub mod faults;
+#[cfg(feature = "server")]
+pub mod fanout;
+#[cfg(feature = "server")]
+pub mod groups;
+#[cfg(feature = "server")]
+pub mod handlers;
+#[cfg(feature = "server")]
+pub mod hex;
+#[cfg(feature = "server")]
+pub mod hlc;
+pub mod history;
+#[cfg(feature = "server")]
+pub mod jepsen;
+#[cfg(feature = "server")]
+pub mod input;
+#[cfg(feature = "server")]
+pub mod intake;
+#[cfg(feature = "server")]
+pub mod joins;
+#[cfg(feature = "server")]
+pub mod kv;
+#[cfg(feature = "server")]
+pub mod learns;
+#[cfg(feature = "server")]
+pub mod lease;
+#[cfg(feature = "server")]
+pub mod ledger;
+#[cfg(feature = "server")]
+pub mod membership;
+#[cfg(feature = "server")]
+pub mod metrics;
+#[cfg(feature = "server")]
+pub mod mmap;
+#[cfg(feature = "server")]
+pub mod multicast;
+#[cfg(feature = "model-check")]
+pub mod model;
+#[cfg(feature = "server")]
+pub mod namespace;
+#[cfg(feature = "server")]
+pub mod pbft;
+pub mod playground;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+#[cfg(feature = "server")]
+pub mod prevote;
+#[cfg(feature = "server")]
+pub mod priority;
+pub mod proposer;
+pub mod quorum;
+#[cfg(feature = "server")]
+pub mod quota;
+#[cfg(feature = "server")]
+pub mod ratelimit;
+#[cfg(feature = "server")]
+pub mod readonly;
+#[cfg(feature = "server")]
+pub mod repair;
+#[cfg(feature = "server")]
+pub mod repl;
+#[cfg(feature = "server")]
+pub mod replica;
+pub mod rng;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "server")]
+pub mod schema;
+#[cfg(feature = "server")]
+pub mod script;
+#[cfg(feature = "server")]
+pub mod secrets;
+#[cfg(feature = "server")]
+pub mod shards;
+#[cfg(feature = "server")]
+pub mod shipping;
+#[cfg(feature = "server")]
+pub mod shutdown;
+#[cfg(feature = "server")]
+pub mod status;
+#[cfg(feature = "server")]
+pub mod signing;
+#[cfg(feature = "server")]
+pub mod sim;
+#[cfg(feature = "server")]
+pub mod step;
+#[cfg(feature = "server")]
+pub mod storage;
+#[cfg(feature = "server")]
+pub mod streams;
+#[cfg(feature = "server")]
+pub mod survey;
+#[cfg(feature = "server")]
+pub mod systemd;
+#[cfg(feature = "server")]
+pub mod takeover;
+#[cfg(feature = "server")]
+pub mod topic;
+#[cfg(feature = "server")]
+pub mod trace;
+#[cfg(feature = "server")]
+pub mod transfer;
+#[cfg(feature = "server")]
+pub mod transport;
+#[cfg(feature = "server")]
+pub mod txn;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring;
+#[cfg(feature = "server")]
+pub mod version;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "server")]
+pub mod watch;
+#[cfg(feature = "server")]
+pub mod wire;
+
+#[cfg(feature = "server")]
+use {
+    acceptor::Acceptor,
+    apply::{Applier, Hooks},
+    backpressure::Backpressure,
+    chain::Certificates,
+    config::{Reloader, Settings},
+    dedup::Dedup,
+    disk::Disk,
+    events::Events,
+    fanout::FanOut,
+    faults::{Faults, FaultyTransport},
+    groups::Groups,
+    history::History,
+    hlc::Hlc,
+    joins::Joins,
+    kv::{Kv, StateMachine},
+    learns::Learns,
+    ledger::SharedLedger,
+    membership::Membership,
+    multicast::Multicast,
+    pbft::Pbft,
+    prevote::PreVote,
+    proposer::{ProposerHandle, Rounds},
+    quota::Quotas,
+    ratelimit::RateLimiter,
+    readonly::ReadOnly,
+    rng::Rng,
+    secrets::ClusterToken,
+    shipping::Shipper,
+    shutdown::Shutdown,
+    signing::{Keys, SigningTransport},
+    takeover::Takeovers,
+    status::Epoch,
+    step::Stepper,
+    storage::Storage,
+    streams::Streams,
+    trace::Trace,
+    transport::Transport,
+    version::Versions,
+    watch::Watches,
+};
+
+pub type Id = u64;
+pub type Value = String;
+
+/// Proposal numbers are ordered by round first and broken by node id, so two
+/// proposers can never issue the same one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ProposalId {
+    pub round: u64,
+    pub node_id: Id,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Ballot {
+    pub instance: u64,
+    pub id: ProposalId,
+    pub value: Option<Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Node {
+    pub id: u64,
+    pub addr: SocketAddr,
+    /// Only learns, and has no vote; see `replica`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub learner: bool,
+    /// The zone it runs in, for zoned quorums; see `quorum`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    /// How many votes it counts for; see `quorum`.
+    #[serde(default = "Node::one", skip_serializing_if = "Node::is_one")]
+    pub weight: u32,
+}
+
+impl Node {
+    pub fn new(id: u64, addr: SocketAddr) -> Self{
+        Self { id, addr, learner: false, zone: None, weight: 1 }
+    }
+
+    pub(crate) fn one() -> u32 {
+        1
+    }
+
+    pub(crate) fn is_one(weight: &u32) -> bool {
+        *weight == 1
+    }
+}
+
+/// Learned values, keyed by instance.
+pub type Ledger = HashMap<u64, Value>;
+
+#[cfg(feature = "server")]
+#[derive(Clone, Debug)]
+pub struct AppState {
+    pub node: Node,
+    /// The known peers; see `membership`.
+    pub nodes: Arc<Membership>,
+    /// The protocol each peer speaks; see `version`.
+    pub versions: Arc<Versions>,
+    /// Peers that told us they were shutting down.
+    pub departed: Arc<Mutex<HashSet<Id>>>,
+    /// `/connect`s waiting for their peer; see `joins`.
+    pub joins: Arc<Joins>,
+    pub acceptor: Arc<Mutex<Acceptor>>,
+    pub proposer: ProposerHandle,
+    /// Orders commands instead of `proposer` with `--byzantine`; see `pbft`.
+    pub pbft: Arc<Pbft>,
+    /// What was learned so far; see `ledger`.
+    pub ledger: Arc<SharedLedger>,
+    /// Who accepted the values this node got chosen; see `chain`.
+    pub certificates: Arc<Certificates>,
+    /// Decisions still to be sent to the peers; see `learns`.
+    pub learns: Arc<Learns>,
+    /// How far each follower got, with `--log-shipping`; see `shipping`.
+    pub shipper: Arc<Shipper>,
+    /// The proposer this node last took an accept from; see `prevote`.
+    pub prevote: Arc<PreVote>,
+    /// The replies this node last gave each peer; see `dedup`.
+    pub dedup: Arc<Dedup>,
+    /// Instances this node found undecided; see `takeover`.
+    pub takeovers: Arc<Takeovers>,
+    /// The group's command streams; see `streams`.
+    pub streams: Arc<Streams>,
+    /// The highest ballot this node promised; see `status`.
+    pub epoch: Arc<Epoch>,
+    /// The rounds this node's proposers reserved; see `proposer`.
+    pub rounds: Arc<Rounds>,
+    /// Stamps what this node learns; see `hlc`.
+    pub clock: Arc<Hlc>,
+    pub kv: Arc<Mutex<Kv>>,
+    /// Applies learned values to `kv`; see `apply`.
+    pub applier: Arc<Applier>,
+    /// What to do after each value is applied; see `apply`.
+    pub hooks: Arc<Hooks>,
+    /// What the last values applied changed; see `watch`.
+    pub watches: Arc<Watches>,
+    /// What learned values do to `kv`, if not the KV commands; see `kv`.
+    pub machine: Option<Arc<dyn StateMachine>>,
+    pub faults: Arc<Faults>,
+    pub events: Arc<Events>,
+    pub history: Option<Arc<History>>,
+    pub trace: Option<Arc<Trace>>,
+    pub storage: Option<Arc<Storage>>,
+    /// Free space under the data directory, if there is one.
+    pub disk: Arc<Disk>,
+    /// Where snapshots are uploaded to, if anywhere.
+    #[cfg(feature = "s3")]
+    pub s3: Option<Arc<s3::Bucket>>,
+    /// This node's signing key and its peers'; see `signing`.
+    pub keys: Arc<Keys>,
+    /// What peers have to show to be taken for peers; see `secrets`.
+    pub cluster_token: Arc<ClusterToken>,
+    /// What operators have to show for `/admin`; see `secrets`.
+    pub admin_token: Arc<ClusterToken>,
+    /// The group learns are multicast to, if any.
+    pub multicast: Option<Arc<Multicast>>,
+    pub stepper: Option<Arc<Stepper>>,
+    pub shutdown: Arc<Shutdown>,
+    pub backpressure: Arc<Backpressure>,
+    /// Set by `POST /admin/pause`, while the node neither votes nor proposes.
+    pub paused: Arc<AtomicBool>,
+    /// Set while a node that just joined takes in the cluster's state, and
+    /// doesn't vote yet; see `transfer`.
+    pub syncing: Arc<AtomicBool>,
+    /// Set with `--acl`, when clients need a token the table allows; see `acl`.
+    pub acl: bool,
+    /// Set with `--debug-admin`, to serve the endpoints that break the
+    /// node on purpose; see `admin`.
+    pub debug_admin: bool,
+    /// How the node handles writes in read-only mode, if it is in it.
+    pub read_only: Arc<std::sync::RwLock<Option<ReadOnly>>>,
+    /// What can change while the node runs; see `config`.
+    pub settings: Arc<std::sync::RwLock<Settings>>,
+    /// Set when the node was started with options it can read again.
+    pub reloader: Option<Arc<Reloader>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    /// What each client committed, against its quota; see `quota`.
+    pub quotas: Arc<Quotas>,
+    /// Requests out to each peer; see `fanout`.
+    pub fan_out: Arc<FanOut>,
+    /// The Paxos groups hosted besides this one; see `groups`.
+    pub groups: Arc<Groups>,
+    pub transport: Arc<dyn Transport>,
+}
+
+#[cfg(feature = "server")]
+impl AppState {
+    pub fn new(node: Node, transport: Arc<dyn Transport>) -> Self {
+        let nodes = Arc::new(Membership::default());
+        let faults = Arc::new(Faults::new(node.id, Rng::from_entropy(node.id)));
+        let keys = Arc::new(Keys::default());
+        let transport = Arc::new(SigningTransport::new(transport, node.clone(), keys.clone(), nodes.clone()));
+        let transport = Arc::new(FaultyTransport::new(transport, faults.clone(), nodes.clone()));
+        let events = Arc::new(Events::new(node.id));
+
+        Self {
+            node,
+            nodes,
+            versions: Arc::new(Versions::default()),
+            departed: Arc::new(Mutex::new(HashSet::new())),
+            joins: Arc::new(Joins::default()),
+            acceptor: Arc::new(Mutex::new(Acceptor::default())),
+            proposer: ProposerHandle::default(),
+            pbft: Arc::new(Pbft::default()),
+            ledger: Arc::new(SharedLedger::default()),
+            certificates: Arc::new(Certificates::default()),
+            learns: Arc::new(Learns::default()),
+            shipper: Arc::new(Shipper::default()),
+            prevote: Arc::new(PreVote::default()),
+            dedup: Arc::new(Dedup::default()),
+            takeovers: Arc::new(Takeovers::default()),
+            streams: Arc::new(Streams::default()),
+            epoch: Arc::new(Epoch::default()),
+            rounds: Arc::new(Rounds::default()),
+            clock: Arc::new(Hlc::default()),
+            kv: Arc::new(Mutex::new(Kv::default())),
+            applier: Arc::new(Applier::default()),
+            hooks: Arc::new(Hooks::default()),
+            watches: Arc::new(Watches::default()),
+            machine: None,
+            faults,
+            events,
+            history: None,
+            trace: None,
+            storage: None,
+            disk: Arc::new(Disk::default()),
+            #[cfg(feature = "s3")]
+            s3: None,
+            keys,
+            cluster_token: Arc::new(ClusterToken::default()),
+            admin_token: Arc::new(ClusterToken::default()),
+            multicast: None,
+            stepper: None,
+            shutdown: Arc::new(Shutdown::default()),
+            backpressure: Arc::new(Backpressure::default()),
+            paused: Arc::new(AtomicBool::new(false)),
+            syncing: Arc::new(AtomicBool::new(false)),
+            acl: false,
+            debug_admin: false,
+            read_only: Arc::new(std::sync::RwLock::new(None)),
+            settings: Arc::new(std::sync::RwLock::new(Settings::default())),
+            reloader: None,
+            rate_limiter: Arc::new(RateLimiter::default()),
+            quotas: Arc::new(Quotas::default()),
+            fan_out: Arc::new(FanOut::default()),
+            groups: Arc::default(),
+            transport,
+        }
+    }
+
+    /// Every acceptor taking part in a round: the known peers plus ourselves,
+    /// less the learners.
+    pub fn voters(&self) -> Vec<Node> {
+        let mut voters: Vec<Node> = self.nodes.snapshot().iter().filter(|node| !node.learner).cloned().collect();
+        if !self.node.learner {
+            voters.push(self.node.clone());
+        }
+        voters
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.syncing.load(Ordering::SeqCst)
+    }
+
+    pub fn read_only(&self) -> Option<ReadOnly> {
+        *self.read_only.read().unwrap()
+    }
+
+    pub fn next_instance(&self) -> u64 {
+        self.ledger.last().map_or(1, |instance| instance + 1)
+    }
+}
+
+#[cfg(feature = "server")]
+pub fn router(state: AppState) -> Router {
+    let limited = middleware::from_fn_with_state(state.clone(), ratelimit::limit);
+    let signed = middleware::from_fn_with_state(state.clone(), signing::verify);
+    let peers = middleware::from_fn_with_state(state.clone(), secrets::require);
+    let admins = middleware::from_fn_with_state(state.clone(), acl::admins);
+    let debug = middleware::from_fn_with_state(state.clone(), admin::debug_only);
+
+    let operators = Router::new()
+        .route("/admin/faults", get(admin::get_faults).post(admin::add_fault).delete(admin::clear_faults).layer(debug.clone()))
+        .route("/admin/faults/:id", delete(admin::delete_fault).layer(debug.clone()))
+        .route("/admin/partition", get(admin::get_partition).post(admin::partition).layer(debug.clone()))
+        .route("/admin/heal", post(admin::heal).layer(debug.clone()))
+        .route("/admin/clock-skew", get(hlc::get_skew).post(hlc::set_skew).layer(debug))
+        .route("/admin/consistency-check", get(consistency::consistency_check))
+        .route("/admin/read-only", get(readonly::get_read_only).post(readonly::set_read_only))
+        .route("/admin/pause", post(admin::pause))
+        .route("/admin/resume", post(admin::resume))
+        .route("/admin/step", get(step::get_step).post(step::step).layer(middleware::from_fn_with_state(state.clone(), step::stepped)))
+        .route("/admin/reload", post(config::reload_config))
+        .route("/admin/snapshot", get(storage::get_snapshot).post(storage::take_snapshot).layer(admins.clone()))
+        .route("/admin/acl", get(acl::get_acl).post(acl::add_grant))
+        .route("/admin/acl/:client", delete(acl::delete_client))
+        .route("/admin/quotas", get(quota::get_quotas))
+        .route("/admin/namespaces", get(namespace::get_namespaces).post(namespace::put_namespace))
+        .route("/admin/namespaces/:namespace", delete(namespace::delete_namespace))
+        .route("/admin/schemas", get(schema::get_schemas).post(schema::put_schema).delete(schema::delete_schema))
+        .route("/admin/groups", get(groups::get_groups))
+        .route_layer(middleware::from_fn_with_state(state.clone(), admin::require));
+
+    let mut router = Router::new()
+        .route("/", get(handlers::get_node_state))
+        .route("/state", get(status::get_state))
+        .route("/status", get(status::get_status))
+        .route("/wait", get(status::get_wait))
+        .route("/ping", post(handlers::ping).layer(peers.clone()))
+        .route("/connect", post(handlers::connect))
+        .route("/joins", get(joins::list))
+        .route("/leave", post(shutdown::leave).layer(peers.clone()))
+        .route("/prepare", post(handlers::prepare).layer(limited.clone()))
+        .route("/pbft/request", post(pbft::handle_request).layer(peers.clone()))
+        .route("/pbft/pre-prepare", post(pbft::handle_pre_prepare).layer(signed.clone()).layer(peers.clone()))
+        .route("/pbft/vote", post(pbft::handle_vote).layer(signed).layer(peers))
+        .route("/events", get(events::get_events).layer(admins.clone()))
+        .route("/watch", get(watch::get_watch))
+        .route("/metrics", get(metrics::get_metrics))
+        .route("/kv/:key", get(kv::get_key).merge(put(kv::put_key).delete(kv::delete_key).layer(limited.clone())))
+        .route("/txn", post(txn::post_txn).layer(limited.clone()))
+        .route("/leases", get(lease::get_leases))
+        .route("/leases/:name", get(lease::get_lease).merge(post(lease::acquire).delete(lease::release).layer(limited.clone())))
+        .route("/leases/:name/renew", post(lease::renew).layer(limited.clone()))
+        .route("/leases/:name/keys/:key", put(lease::put_key).layer(limited.clone()))
+        .route("/barriers/:name", get(barrier::get_barrier))
+        .route("/barriers/:name/enter", post(barrier::enter).layer(limited.clone()))
+        .route("/elections/:name/campaign", post(election::campaign).layer(limited.clone()))
+        .route("/elections/:name/resign", post(election::resign).layer(limited.clone()))
+        .route("/elections/:name/observe", get(election::observe))
+        .route("/topics/:topic", get(topic::get_topic).merge(post(topic::publish).layer(limited.clone())))
+        .route("/proof/:instance", get(chain::get_proof).layer(admins))
+        .route("/ns/:namespace/kv/:key", get(namespace::get_key).merge(put(namespace::put_key).delete(namespace::delete_key).layer(limited)))
+        .merge(operators)
+        .merge(group_routes(&state, &state));
+
+    for (id, group) in state.groups.iter() {
+        router = router.nest(&groups::prefix(id), group_routes(group, &state).with_state(group.clone()));
+    }
+
+    router
+        .layer(middleware::from_fn(priority::mark))
+        .layer(middleware::from_fn_with_state(state.clone(), hlc::receive))
+        .layer(middleware::from_fn_with_state(state.clone(), faults::inbound))
+        .with_state(state)
+}
+
+/// What each Paxos group on a node serves: its consensus messages, and the
+/// views of its own log. `main` is the node's own group, which keeps the
+/// ACL.
+#[cfg(feature = "server")]
+fn group_routes(state: &AppState, main: &AppState) -> Router<AppState> {
+    let admins = middleware::from_fn_with_state(main.clone(), acl::admins);
+    let operators = middleware::from_fn_with_state(main.clone(), admin::require);
+    let signed = middleware::from_fn_with_state(state.clone(), signing::verify);
+    let paxos = middleware::from_fn_with_state(state.clone(), pbft::paxos_only);
+    let peers = middleware::from_fn_with_state(state.clone(), secrets::require);
+    let throttled = middleware::from_fn_with_state(state.clone(), ratelimit::limit_peer);
+    let votes = middleware::from_fn_with_state(state.clone(), replica::votes);
+    let dedup = middleware::from_fn_with_state(state.clone(), dedup::suppress);
+
+    Router::new()
+        .route("/handle-prepare", post(handlers::handle_prepare).layer(dedup.clone()).layer(votes.clone()).layer(throttled.clone()).layer(signed.clone()).layer(paxos.clone()).layer(peers.clone()))
+        .route("/handle-prepare-range", post(handlers::handle_prepare_range).layer(dedup.clone()).layer(votes.clone()).layer(throttled.clone()).layer(signed.clone()).layer(paxos.clone()).layer(peers.clone()))
+        .route("/handle-accept", post(handlers::handle_accept).layer(dedup.clone()).layer(votes.clone()).layer(throttled).layer(signed.clone()).layer(paxos.clone()).layer(peers.clone()))
+        .route("/handle-pre-vote", post(prevote::handle_pre_vote).layer(votes).layer(paxos.clone()).layer(peers.clone()))
+        .route("/handle-learn", post(handlers::handle_learn).layer(dedup.clone()).layer(signed.clone()).layer(paxos.clone()).layer(peers.clone()))
+        .route("/handle-learns", post(handlers::handle_learns).layer(dedup.clone()).layer(signed.clone()).layer(paxos.clone()).layer(peers.clone()))
+        .route("/gossip-learns", post(handlers::handle_gossip).layer(dedup.clone()).layer(signed.clone()).layer(paxos.clone()).layer(peers.clone()))
+        .route("/handle-ship", post(shipping::handle_ship).layer(dedup.clone()).layer(signed.clone()).layer(paxos).layer(peers.clone()))
+        .route("/forward", post(readonly::forward).layer(peers.clone()))
+        .route("/admin/certificates", post(chain::get_certificates).layer(peers.clone()))
+        .route("/admin/ledger-digest", post(consistency::ledger_digest).layer(peers.clone()))
+        .route("/admin/log", post(replica::get_log).layer(peers.clone()))
+        .route("/admin/repair", post(repair::handle_repair).layer(signed).layer(peers.clone()))
+        .route("/admin/transfer", post(transfer::get_transfer).layer(peers))
+        .route("/ledger", get(ledger::get_ledger).layer(admins.clone()))
+        .route("/admin/shipping", get(shipping::get_shipping).layer(operators))
+        .route("/admin/chain", get(chain::get_chain).layer(admins.clone()))
+        .route("/admin/chain/verify", get(chain::verify).layer(admins))
+}
@@ -1,405 +1,1266 @@
-use std::{collections::HashMap, net::SocketAddr, sync::Arc, mem};
-use axum::{
-    routing::{get, post},
-    Router,
-    http::StatusCode,
-    extract::{State, Json}
+use std::{net::{Ipv4Addr, SocketAddr, SocketAddrV4}, path::{Path, PathBuf}, process::ExitCode, sync::Arc, time::Duration};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, error::ErrorKind, parser::ValueSource};
+use paxos_from_scratch::{
+    AppState, Node,
+    apply,
+    bench::{self, BenchConfig},
+    chain::{Proof, ProvedRead},
+    chaos,
+    client::Client,
+    config::{self, Layers, Reloader},
+    crash,
+    dev::DevCluster,
+    disk,
+    encryption::Keyring,
+    groups::{self, Groups},
+    hex,
+    history::{self, History},
+    hlc::Hlc,
+    intake,
+    jepsen::{self, Format, Workload},
+    joins,
+    lease,
+    multicast::{self, Multicast},
+    namespace,
+    readonly::ReadOnly,
+    repl,
+    replica,
+    router,
+    secrets::{self, ClusterToken},
+    shards,
+    shipping,
+    shutdown,
+    signing,
+    sim::{Sim, SimConfig},
+    step::Stepper,
+    storage::{self, Backup, DataDir, Record, Storage},
+    streams::{self, Streams},
+    survey,
+    systemd,
+    takeover,
+    trace::{self, Trace},
+    transport::HttpTransport,
+    txn,
 };
-use clap::Parser;
-use reqwest::Client;
-use serde::{Serialize, Deserialize};
-use tokio::sync::Mutex;
-
+#[cfg(feature = "plugins")]
+use paxos_from_scratch::{kv::Kv, plugin::Plugin};
+#[cfg(feature = "s3")]
+use paxos_from_scratch::s3;
+
+/// Runs a node, unless a subcommand is given.
+///
+/// Node options come from `--config`, then `PAXOS_*` variables, then flags,
+/// each overriding the one before.
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(long)]
-    id: u64,
-    #[arg(short, long)]
-    port: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML file with node options, keyed by flag name.
+    #[arg(long, env = "PAXOS_CONFIG")]
+    config: Option<PathBuf>,
+    /// Required, unless the config file sets it.
+    #[arg(long, env = "PAXOS_ID")]
+    id: Option<u64>,
+    /// Required, unless the config file sets it.
+    #[arg(short, long, env = "PAXOS_PORT")]
+    port: Option<String>,
+    /// Record every client operation this node serves into this file.
+    #[arg(long, env = "PAXOS_HISTORY")]
+    history: Option<PathBuf>,
+    /// Record every message the acceptor and learner handle into this file.
+    #[arg(long, env = "PAXOS_TRACE")]
+    trace: Option<PathBuf>,
+    /// Keep promises, accepts and learned values in this directory, and
+    /// recover them on start.
+    #[arg(long, env = "PAXOS_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// Sign consensus messages with the Ed25519 key in this file, made on
+    /// the first start, and only take signed ones from peers.
+    #[arg(long, env = "PAXOS_SIGNING_KEY")]
+    signing_key: Option<PathBuf>,
+    /// A peer's public key, as `<id>=<64 hex digits>`, once for every peer;
+    /// a node that signs takes no other keys.
+    #[arg(long = "peer-key", env = "PAXOS_PEER_KEYS", value_delimiter = ',')]
+    peer_keys: Vec<String>,
+    /// Seal the log and snapshots in the data directory with the last key
+    /// in this file, of `<id> <64 hex digits>` lines, and open them with any.
+    #[arg(long, env = "PAXOS_ENCRYPTION_KEY")]
+    encryption_key: Option<PathBuf>,
+    /// Also read what in the data directory isn't sealed yet, to start
+    /// sealing one written without a key; otherwise that is refused.
+    #[arg(long, env = "PAXOS_ENCRYPTION_MIGRATE")]
+    encryption_migrate: bool,
+    /// Send the first token in this file to peers, and only take peer
+    /// requests with one of them; the file is read again when it changes.
+    #[arg(long, env = "PAXOS_CLUSTER_TOKEN")]
+    cluster_token: Option<PathBuf>,
+    /// How long a token that left that file is still taken.
+    #[arg(long, env = "PAXOS_TOKEN_GRACE_MS", default_value_t = 300_000)]
+    token_grace_ms: u64,
+    /// Only serve `/admin` to requests with one of the tokens in this file
+    /// as a bearer token, or an admin's with `--acl`.
+    #[arg(long, env = "PAXOS_ADMIN_TOKEN")]
+    admin_token: Option<PathBuf>,
+    /// Serve the endpoints that break the node on purpose: faults,
+    /// partitions and clock skew.
+    #[arg(long, env = "PAXOS_DEBUG_ADMIN")]
+    debug_admin: bool,
+    /// Order commands with the experimental PBFT mode instead of Paxos;
+    /// every node of the cluster has to run it.
+    #[arg(long, env = "PAXOS_BYZANTINE")]
+    byzantine: bool,
+    /// Split the keys between this many Paxos groups, each with a log and
+    /// a leader of its own; every node of the cluster has to run the same.
+    #[arg(long, env = "PAXOS_SHARDS", default_value_t = 1)]
+    shards: usize,
+    /// Split each group's log into this many streams that propose side by
+    /// side, the keys hashed between them; every node of the cluster has
+    /// to run the same.
+    #[arg(long, env = "PAXOS_STREAMS", default_value_t = 1)]
+    streams: u64,
+    /// Also host the Paxos group with this id, a key space of its own
+    /// reached with `?group=<id>`; every node of the cluster has to.
+    #[arg(long = "group", env = "PAXOS_GROUPS", value_delimiter = ',')]
+    groups: Vec<String>,
+    /// Join as a read replica: learn the log and serve reads, but never
+    /// vote or propose.
+    #[arg(long, env = "PAXOS_LEARNER")]
+    learner: bool,
+    /// The zone this node runs in; once voters name theirs, a quorum is a
+    /// majority within each of a majority of the zones.
+    #[arg(long, env = "PAXOS_ZONE")]
+    zone: Option<String>,
+    /// How many votes this node counts for; a quorum is more than half of
+    /// the votes.
+    #[arg(long, env = "PAXOS_WEIGHT", default_value_t = 1)]
+    weight: u32,
+    /// Only serve clients with a token, and only on the keys `/admin/acl`
+    /// grants them.
+    #[arg(long, env = "PAXOS_ACL")]
+    acl: bool,
+    /// Start a new log segment once the current one is this big.
+    #[arg(long, env = "PAXOS_WAL_SEGMENT_BYTES", default_value_t = storage::SEGMENT_BYTES)]
+    wal_segment_bytes: u64,
+    /// How long a log entry waits for others to share its sync; 0 syncs at
+    /// once, along with whatever was written meanwhile.
+    #[arg(long, env = "PAXOS_WAL_GROUP_DELAY_MS", default_value_t = 0)]
+    wal_group_delay_ms: u64,
+    /// With a data directory, stop promising and accepting below this much
+    /// free space, until there is more; 0 never does.
+    #[arg(long, env = "PAXOS_MIN_FREE_BYTES", default_value_t = 256 * 1024 * 1024)]
+    min_free_bytes: u64,
+    /// Hold every phase of our proposals until `POST /admin/step`.
+    #[arg(long, env = "PAXOS_STEP")]
+    step: bool,
+    /// Start in read-only mode, rejecting or forwarding client writes; see
+    /// `/admin/read-only`.
+    #[arg(long, env = "PAXOS_READ_ONLY", value_enum)]
+    read_only: Option<ReadOnly>,
+    /// On shutdown, how long to wait for running proposals before giving up on them.
+    #[arg(long, env = "PAXOS_DRAIN_TIMEOUT_MS", default_value_t = 10_000)]
+    drain_timeout_ms: u64,
+    /// With a data directory, snapshot after this many log entries; 0 never does.
+    #[arg(long, env = "PAXOS_SNAPSHOT_EVERY", default_value_t = 10_000)]
+    snapshot_every: u64,
+    /// With a data directory, snapshot at least this often when the log grew; 0 never does.
+    #[arg(long, env = "PAXOS_SNAPSHOT_INTERVAL_MS", default_value_t = 300_000)]
+    snapshot_interval_ms: u64,
+    /// Client proposals to run at once; 0 runs any number.
+    #[arg(long, env = "PAXOS_MAX_IN_FLIGHT", default_value_t = 64)]
+    max_in_flight: usize,
+    /// Client proposals to keep waiting beyond those, before answering 503.
+    #[arg(long, env = "PAXOS_MAX_QUEUED", default_value_t = 1024)]
+    max_queued: usize,
+    /// Requests to send at once when a round fans out to the voters; 0 sends them all.
+    #[arg(long, env = "PAXOS_MAX_REQUESTS_PER_ROUND", default_value_t = 64)]
+    max_requests_per_round: usize,
+    /// Requests to have out to any one peer at once; 0 for no limit.
+    #[arg(long, env = "PAXOS_MAX_REQUESTS_PER_PEER", default_value_t = 16)]
+    max_requests_per_peer: usize,
+    /// How long a decision waits for others to be sent to the peers with it.
+    #[arg(long, env = "PAXOS_LEARN_BATCH_DELAY_MS", default_value_t = 2)]
+    learn_batch_delay_ms: u64,
+    /// Decisions to send to the peers in one batch at most; 0 for any number.
+    #[arg(long, env = "PAXOS_LEARN_BATCH_MAX", default_value_t = 64)]
+    learn_batch_max: usize,
+    /// Always wait the whole --learn-batch-delay-ms, rather than less when
+    /// decisions are few.
+    #[arg(long, env = "PAXOS_LEARN_BATCH_FIXED")]
+    learn_batch_fixed: bool,
+    /// Gossip learns to this many random peers, which pass them on, rather
+    /// than sending them to every peer; 0 sends to every peer.
+    #[arg(long, env = "PAXOS_LEARN_GOSSIP_FANOUT", default_value_t = 0)]
+    learn_gossip_fanout: usize,
+    /// Ship the log to every follower from where it stands, rather than
+    /// broadcasting learns.
+    #[arg(long, env = "PAXOS_LOG_SHIPPING")]
+    log_shipping: bool,
+    /// Run phase 1 once for this many instances ahead, so the commands
+    /// that follow only need phase 2; 0 prepares every instance on its own.
+    #[arg(long, env = "PAXOS_PREPARE_AHEAD", default_value_t = 0)]
+    prepare_ahead: u64,
+    /// Ask the voters before preparing, and leave client writes to the
+    /// proposer whose accepts they took within this long; 0 never asks.
+    #[arg(long, env = "PAXOS_PRE_VOTE_LEASE_MS", default_value_t = 0)]
+    pre_vote_lease_ms: u64,
+    /// Take over an instance that stays undecided this long, its proposer
+    /// gone mid-round; 0 never does.
+    #[arg(long, env = "PAXOS_STUCK_INSTANCE_TIMEOUT_MS", default_value_t = 5000)]
+    stuck_instance_timeout_ms: u64,
+    /// Also send learns as datagrams to this UDP multicast group, e.g.
+    /// 239.255.0.1:4500, to the peers that joined it too.
+    #[arg(long, env = "PAXOS_LEARN_MULTICAST")]
+    learn_multicast: Option<SocketAddrV4>,
+    /// The interface to join the group on; 0.0.0.0 lets the system pick.
+    #[arg(long, env = "PAXOS_LEARN_MULTICAST_INTERFACE", default_value_t = Ipv4Addr::UNSPECIFIED)]
+    learn_multicast_interface: Ipv4Addr,
+    #[command(flatten)]
+    chaos: ChaosArgs,
+    #[command(flatten)]
+    rate_limit: RateLimitArgs,
+    #[cfg(feature = "s3")]
+    #[command(flatten)]
+    s3: S3Args,
+    /// Apply learned values with the state machine in this WebAssembly
+    /// module, rather than as KV commands.
+    #[cfg(feature = "plugins")]
+    #[arg(long, env = "PAXOS_STATE_MACHINE")]
+    state_machine: Option<PathBuf>,
+    /// Where the options above came from, once the config file is read.
+    #[arg(skip)]
+    reloader: Option<Reloader>,
 }
 
-type Id = u64;
-type Value = String;
-
-#[derive(Clone, Debug, Default)]
-struct Acceptor {
-    pub last_ballot_number: u64,
-    pub accepted_proposal: Option<Propose>,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check that recorded client histories are linearizable.
+    CheckHistory {
+        /// History files, typically one per node.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Drive proposals against a cluster and report throughput and latency.
+    Bench {
+        /// Comma-separated `host:port` of the nodes to propose to.
+        #[arg(long, value_delimiter = ',', required = true)]
+        targets: Vec<String>,
+        #[arg(long, default_value_t = 8)]
+        concurrency: usize,
+        /// How long to run, e.g. `500ms`, `60s` or `2m`.
+        #[arg(long, default_value = "60s", value_parser = bench::parse_duration)]
+        duration: Duration,
+    },
+    /// Run a cluster inside this process and propose a few values to it.
+    Simulate {
+        #[arg(long, default_value_t = 3)]
+        nodes: usize,
+        /// Values to propose, one after the other, to nodes in turn.
+        #[arg(long, value_delimiter = ',', default_value = "a,b,c,d,e")]
+        values: Vec<String>,
+        /// Seeds the simulated network; the same seed gives the same run.
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Chance, between 0 and 1, of losing each request and each reply.
+        #[arg(long, default_value_t = 0.0)]
+        drop_rate: f64,
+    },
+    /// Print what a node's data directory holds, without starting it.
+    Inspect {
+        #[arg(long)]
+        data_dir: PathBuf,
+        /// The node's key ring, if it seals its directory.
+        #[arg(long, env = "PAXOS_ENCRYPTION_KEY")]
+        encryption_key: Option<PathBuf>,
+    },
+    /// Copy a data directory's snapshot and log into a single file, sealed
+    /// like the directory.
+    Backup {
+        #[arg(long)]
+        data_dir: PathBuf,
+        /// Write here instead of stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// The node's key ring, if it seals its directory; the backup is
+        /// sealed with its last key.
+        #[arg(long, env = "PAXOS_ENCRYPTION_KEY")]
+        encryption_key: Option<PathBuf>,
+        /// Also read what in the directory isn't sealed yet.
+        #[arg(long, env = "PAXOS_ENCRYPTION_MIGRATE")]
+        encryption_migrate: bool,
+    },
+    /// Seed an empty data directory from a backup.
+    Restore {
+        file: PathBuf,
+        #[arg(long)]
+        data_dir: PathBuf,
+        /// Seal the directory with the last key of this key ring.
+        #[arg(long, env = "PAXOS_ENCRYPTION_KEY")]
+        encryption_key: Option<PathBuf>,
+    },
+    /// Seed a new replica's data directory from a snapshot another node uploaded.
+    #[cfg(feature = "s3")]
+    RestoreS3 {
+        /// The node whose snapshot to start from.
+        #[arg(long)]
+        from: u64,
+        /// The new replica's id.
+        #[arg(long)]
+        id: u64,
+        #[arg(long)]
+        data_dir: PathBuf,
+        /// Opens the snapshot if it is sealed, and seals the new directory
+        /// with its last key.
+        #[arg(long, env = "PAXOS_ENCRYPTION_KEY")]
+        encryption_key: Option<PathBuf>,
+        #[command(flatten)]
+        s3: S3Args,
+    },
+    /// Replay a message trace on a fresh node and check it ends up the same.
+    Replay {
+        file: PathBuf,
+    },
+    /// Check a proof from `GET /kv/<key>?proof=true` or `GET /proof/<instance>`
+    /// against the voters' public keys, without trusting the node that sent it.
+    VerifyProof {
+        file: PathBuf,
+        /// `<id>=<public key>` of a voter, as it prints it on start; one per voter.
+        #[arg(long = "key", required = true)]
+        keys: Vec<String>,
+    },
+    /// Convert recorded histories to the operation format Jepsen tooling reads.
+    ExportHistory {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        #[arg(long, value_enum, default_value_t = Format::Edn)]
+        format: Format,
+        /// Write here instead of stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Run a register workload against a cluster and record what it observes.
+    Workload {
+        /// Comma-separated `host:port` of the nodes.
+        #[arg(long, value_delimiter = ',', required = true)]
+        nodes: Vec<String>,
+        #[arg(long, default_value_t = 5)]
+        concurrency: usize,
+        /// How long to run, in seconds.
+        #[arg(long, default_value_t = 10)]
+        time_limit: u64,
+        #[arg(long, default_value_t = 3)]
+        keys: usize,
+        #[arg(long, value_enum, default_value_t = Format::Edn)]
+        format: Format,
+        /// Write here instead of stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Talk to a running cluster through one of its nodes.
+    Client {
+        /// The node's URL.
+        #[arg(long, env = "PAXOS_NODE", default_value = "http://localhost:3000")]
+        node: String,
+        #[command(subcommand)]
+        request: ClientRequest,
+    },
+    /// Find every member through one node, and show how each is doing.
+    ClusterStatus {
+        /// The URL of the node to start from.
+        #[arg(long, env = "PAXOS_NODE", default_value = "http://localhost:3000")]
+        node: String,
+    },
+    /// Run commands against a running cluster one line at a time.
+    Repl {
+        /// The URL of the node to talk to.
+        #[arg(long, env = "PAXOS_NODE", default_value = "http://localhost:3000")]
+        node: String,
+        /// Keep the lines typed here across sessions; defaults to
+        /// ~/.paxos_history.
+        #[arg(long)]
+        history_file: Option<PathBuf>,
+    },
+    /// Run a cluster of local nodes, connected to each other, and print
+    /// what they write.
+    DevCluster {
+        #[arg(long, default_value_t = 3)]
+        nodes: usize,
+        /// The port of node 1; the others listen on the ports after it.
+        #[arg(long, default_value_t = 3000)]
+        base_port: u16,
+        /// Keep each node's data in a directory named after its id in here.
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+        /// Passed on to every node, after `--`.
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct Node {
-    pub id: u64,
-    pub addr: SocketAddr,
+#[derive(Subcommand, Debug)]
+enum ClientRequest {
+    /// Propose a value, and print what the node answered once it was chosen.
+    Propose { value: String },
+    /// Print a key's value.
+    Get { key: String },
+    /// Set a key's value.
+    Put { key: String, value: String },
+    /// Delete a key.
+    Delete { key: String },
+    /// Print the node's epoch and commit index.
+    Status,
 }
 
-impl Node {
-    pub fn new(id: u64, addr: SocketAddr) -> Self{
-        Self { id, addr }
-    }
+#[derive(clap::Args, Debug)]
+struct ChaosArgs {
+    /// Keep disturbing this node with random pauses, preemptions and restarts.
+    #[arg(long, env = "PAXOS_CHAOS")]
+    chaos: bool,
+    #[arg(long, env = "PAXOS_CHAOS_INTERVAL_MS", default_value_t = 1000)]
+    chaos_interval_ms: u64,
+    /// Chance per tick, in percent, of pausing event processing.
+    #[arg(long, env = "PAXOS_CHAOS_PAUSE", default_value_t = 10.0)]
+    chaos_pause: f64,
+    #[arg(long, env = "PAXOS_CHAOS_MAX_PAUSE_MS", default_value_t = 2000)]
+    chaos_max_pause_ms: u64,
+    /// Chance per tick, in percent, of preempting the open instance.
+    #[arg(long, env = "PAXOS_CHAOS_PREEMPT", default_value_t = 5.0)]
+    chaos_preempt: f64,
+    /// Chance per tick, in percent, of restarting the proposer.
+    #[arg(long, env = "PAXOS_CHAOS_RESTART", default_value_t = 5.0)]
+    chaos_restart: f64,
 }
 
-type Ledger = HashMap<Id, Value>;
+/// Limits on `POST /prepare` and KV writes, in requests per second.
+#[derive(clap::Args, Debug)]
+struct RateLimitArgs {
+    /// For each client IP; 0 for no limit.
+    #[arg(long, env = "PAXOS_RATE_LIMIT_CLIENT", default_value_t = 0.0)]
+    rate_limit_client: f64,
+    /// Requests a client can send at once before the rate applies.
+    #[arg(long, env = "PAXOS_RATE_LIMIT_CLIENT_BURST", default_value_t = 20.0)]
+    rate_limit_client_burst: f64,
+    /// For all clients together; 0 for no limit.
+    #[arg(long, env = "PAXOS_RATE_LIMIT_GLOBAL", default_value_t = 0.0)]
+    rate_limit_global: f64,
+    #[arg(long, env = "PAXOS_RATE_LIMIT_GLOBAL_BURST", default_value_t = 100.0)]
+    rate_limit_global_burst: f64,
+    /// Prepares and accepts from each peer; 0 for no limit.
+    #[arg(long, env = "PAXOS_RATE_LIMIT_PEER", default_value_t = 0.0)]
+    rate_limit_peer: f64,
+    #[arg(long, env = "PAXOS_RATE_LIMIT_PEER_BURST", default_value_t = 500.0)]
+    rate_limit_peer_burst: f64,
+}
 
-#[derive(Clone, Debug)]
-struct AppState {
-    node: Node,
-    nodes: Arc<Mutex<Vec<Node>>>,
-    acceptor: Arc<Mutex<Acceptor>>,
-    proposer: Arc<Mutex<Proposer>>,
-    ledger: Arc<Mutex<Ledger>>,
+/// Credentials come from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`.
+#[cfg(feature = "s3")]
+#[derive(clap::Args, Debug)]
+struct S3Args {
+    /// Upload every snapshot to this bucket.
+    #[arg(long, env = "PAXOS_S3_BUCKET")]
+    s3_bucket: Option<String>,
+    /// Defaults to AWS in `--s3-region`.
+    #[arg(long, env = "PAXOS_S3_ENDPOINT")]
+    s3_endpoint: Option<String>,
+    #[arg(long, env = "PAXOS_S3_REGION", default_value = "us-east-1")]
+    s3_region: String,
+    #[arg(long, env = "PAXOS_S3_PREFIX", default_value = "")]
+    s3_prefix: String,
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
-    let port = args.port;
-    let node_id = args.id;
+#[cfg(feature = "s3")]
+fn s3_bucket(bucket: String, endpoint: Option<String>, region: String, prefix: String) -> Result<s3::Bucket, String> {
+    let endpoint = endpoint.unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+    s3::S3Config::new(endpoint, bucket, region, prefix).map(s3::Bucket::new)
+}
 
-    let node_http_addr = format!("0.0.0.0:{}", port);
+impl Args {
+    /// Splits the node options into those a flag or a variable set, which
+    /// win over the config file, and the defaults of the rest, which don't.
+    fn layers(&self, matches: &ArgMatches) -> Layers {
+        let mut layers = Layers { file: self.config.clone(), ..Layers::default() };
+
+        macro_rules! split {
+            ($($field:ident: $value:expr),* $(,)?) => {$(
+                let explicit = matches!(matches.value_source(stringify!($field)), Some(ValueSource::CommandLine | ValueSource::EnvVariable));
+                let layer = if explicit { &mut layers.explicit } else { &mut layers.defaults };
+                layer.$field = $value;
+            )*};
+        }
 
-    println!("Starting new node: http://{}", node_http_addr);
+        let chaos = &self.chaos;
+        split! {
+            id: self.id,
+            port: self.port.clone(),
+            history: self.history.clone(),
+            trace: self.trace.clone(),
+            data_dir: self.data_dir.clone(),
+            signing_key: self.signing_key.clone(),
+            peer_keys: Some(self.peer_keys.clone()),
+            encryption_key: self.encryption_key.clone(),
+            encryption_migrate: Some(self.encryption_migrate),
+            cluster_token: self.cluster_token.clone(),
+            token_grace_ms: Some(self.token_grace_ms),
+            admin_token: self.admin_token.clone(),
+            debug_admin: Some(self.debug_admin),
+            byzantine: Some(self.byzantine),
+            shards: Some(self.shards),
+            streams: Some(self.streams),
+            groups: Some(self.groups.clone()),
+            learner: Some(self.learner),
+            zone: self.zone.clone(),
+            weight: Some(self.weight),
+            acl: Some(self.acl),
+            wal_segment_bytes: Some(self.wal_segment_bytes),
+            wal_group_delay_ms: Some(self.wal_group_delay_ms),
+            min_free_bytes: Some(self.min_free_bytes),
+            step: Some(self.step),
+            read_only: self.read_only,
+            drain_timeout_ms: Some(self.drain_timeout_ms),
+            snapshot_every: Some(self.snapshot_every),
+            snapshot_interval_ms: Some(self.snapshot_interval_ms),
+            max_in_flight: Some(self.max_in_flight),
+            max_queued: Some(self.max_queued),
+            max_requests_per_round: Some(self.max_requests_per_round),
+            max_requests_per_peer: Some(self.max_requests_per_peer),
+            learn_batch_delay_ms: Some(self.learn_batch_delay_ms),
+            learn_batch_max: Some(self.learn_batch_max),
+            learn_batch_fixed: Some(self.learn_batch_fixed),
+            learn_gossip_fanout: Some(self.learn_gossip_fanout),
+            log_shipping: Some(self.log_shipping),
+            prepare_ahead: Some(self.prepare_ahead),
+            pre_vote_lease_ms: Some(self.pre_vote_lease_ms),
+            stuck_instance_timeout_ms: Some(self.stuck_instance_timeout_ms),
+            learn_multicast: self.learn_multicast,
+            learn_multicast_interface: Some(self.learn_multicast_interface),
+            chaos: Some(chaos.chaos),
+            chaos_interval_ms: Some(chaos.chaos_interval_ms),
+            chaos_pause: Some(chaos.chaos_pause),
+            chaos_max_pause_ms: Some(chaos.chaos_max_pause_ms),
+            chaos_preempt: Some(chaos.chaos_preempt),
+            chaos_restart: Some(chaos.chaos_restart),
+            rate_limit_client: Some(self.rate_limit.rate_limit_client),
+            rate_limit_client_burst: Some(self.rate_limit.rate_limit_client_burst),
+            rate_limit_global: Some(self.rate_limit.rate_limit_global),
+            rate_limit_global_burst: Some(self.rate_limit.rate_limit_global_burst),
+            rate_limit_peer: Some(self.rate_limit.rate_limit_peer),
+            rate_limit_peer_burst: Some(self.rate_limit.rate_limit_peer_burst),
+        }
+        #[cfg(feature = "s3")]
+        split! {
+            s3_bucket: self.s3.s3_bucket.clone(),
+            s3_endpoint: self.s3.s3_endpoint.clone(),
+            s3_region: Some(self.s3.s3_region.clone()),
+            s3_prefix: Some(self.s3.s3_prefix.clone()),
+        }
+        #[cfg(feature = "plugins")]
+        split! {
+            state_machine: self.state_machine.clone(),
+        }
+        layers
+    }
 
-    let node = Node::new(node_id, node_http_addr.parse().unwrap());
-    let state = AppState {
-        node,
-        nodes: Arc::new(Mutex::new(Vec::new())),
-        acceptor: Arc::new(Mutex::new(Acceptor::default())),
-        proposer: Arc::new(Mutex::new(Proposer::new())),
-        ledger: Arc::new(Mutex::new(HashMap::new())),
-    };
+    fn parse_layered() -> Self {
+        let matches = Args::command().get_matches();
+        let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        if args.command.is_some() {
+            return args;
+        }
 
-    let app = Router::new()
-        .route("/", get(get_node_state))
-        .route("/state", get(get_state))
-        .route("/ping", post(ping))
-        .route("/connect", post(connect))
-        .route("/prepare", post(prepare))
-        .route("/handle-prepare", post(handle_prepare))
-        .route("/handle-accept", post(handle_accept))
-        .route("/handle-learn", post(handle_learn))
-        .with_state(state);
+        let layers = args.layers(&matches);
+        let node = layers.resolve().unwrap_or_else(|e| Args::command().error(ErrorKind::Io, e).exit());
 
-    let listener = tokio::net::TcpListener::bind(node_http_addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+        if node.id.is_none() || node.port.is_none() {
+            let message = "a node needs --id and --port, from flags, PAXOS_ID and PAXOS_PORT, or the config file";
+            Args::command().error(ErrorKind::MissingRequiredArgument, message).exit();
+        }
+        args.reloader = Some(Reloader { layers, current: std::sync::Mutex::new(node) });
+        args
+    }
 }
 
-async fn connect(State(state): State<AppState>, value: String) -> (StatusCode, String) {
-    let Node { id, addr } = state.node;
-
-    let mut payload = HashMap::new();
-    payload.insert("id", id.to_string());
-    payload.insert("addr", addr.to_string());
-
-    let client = Client::new();
-    let res = client.post(format!("http://0.0.0.0:{}/ping", value))
-        .json(&payload)
-        .send()
-        .await;
-
-    match res {
-        Err(_) => todo!(),
-        Ok(res) => {
-            let is_error = res.status().is_client_error() || res.status().is_server_error();
-            if is_error {
-                return (StatusCode::BAD_REQUEST, res.text().await.unwrap());
-            }
-
-            let body_text = res.text().await.unwrap();
-            let body: PingNode = serde_json::from_str(body_text.as_str()).unwrap();
-
-            let mut nodes = state.nodes.lock().await;
+fn main() -> ExitCode {
+    let args = Args::parse_layered();
 
-            let id: u64 = body.id.parse().unwrap();
-            let addr: SocketAddr = body.addr.parse().unwrap();
-            nodes.push(Node { id, addr });
-            std::mem::drop(nodes);
-
-            println!("[/connect] sync new node: {} - ID: {}", addr, id);
+    match args.command {
+        Some(Command::CheckHistory { files }) => check_history(&files),
+        Some(Command::Bench { targets, concurrency, duration }) => run_bench(BenchConfig { targets, concurrency, duration }),
+        Some(Command::Simulate { nodes, values, seed, drop_rate }) => {
+            simulate(SimConfig { nodes, drop_rate, ..SimConfig::default() }, seed, &values)
+        },
+        Some(Command::Inspect { data_dir, encryption_key }) => inspect(&data_dir, encryption_key.as_deref()),
+        Some(Command::Backup { data_dir, output, encryption_key, encryption_migrate }) => backup(&data_dir, output.as_deref(), encryption_key.as_deref(), encryption_migrate),
+        Some(Command::Restore { file, data_dir, encryption_key }) => restore(&file, &data_dir, encryption_key.as_deref()),
+        #[cfg(feature = "s3")]
+        Some(Command::RestoreS3 { from, id, data_dir, encryption_key, s3 }) => restore_s3(from, id, &data_dir, encryption_key.as_deref(), s3),
+        Some(Command::Replay { file }) => replay(&file),
+        Some(Command::VerifyProof { file, keys }) => verify_proof(&file, &keys),
+        Some(Command::ExportHistory { files, format, output }) => export_history(&files, format, output.as_deref()),
+        Some(Command::Workload { nodes, concurrency, time_limit, keys, format, output }) => {
+            let workload = Workload { nodes, concurrency, time_limit: Duration::from_secs(time_limit), keys };
+            run_workload(workload, format, output.as_deref())
+        },
+        Some(Command::Client { node, request }) => run_client(Client::new(&node), request),
+        Some(Command::ClusterStatus { node }) => cluster_status(Client::new(&node)),
+        Some(Command::Repl { node, history_file }) => {
+            let history = history_file.or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".paxos_history")));
+            run_repl(Client::new(&node), history.as_deref())
+        },
+        Some(Command::DevCluster { nodes, base_port, data_dir, args }) => dev_cluster(DevCluster { nodes, base_port, data_dir, args }),
+        None => {
+            run_node(args.reloader.unwrap());
+            ExitCode::SUCCESS
+        },
+    }
+}
 
-            (StatusCode::OK, format!("Conneted to new voter: {}!", value))
+fn read_histories(files: &[PathBuf]) -> Result<Vec<history::Event>, ExitCode> {
+    let mut events = Vec::new();
+    for file in files {
+        match history::read_events(file) {
+            Ok(mut read) => events.append(&mut read),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", file.display(), e);
+                return Err(ExitCode::FAILURE);
+            },
         }
     }
+    Ok(events)
 }
 
-#[derive(Deserialize, Debug)]
-struct PingNode {
-    pub id: String,
-    pub addr: String,
-}
+fn write_output(output: Option<&Path>, text: &str) -> ExitCode {
+    let Some(path) = output else {
+        print!("{}", text);
+        return ExitCode::SUCCESS;
+    };
 
-async fn ping(
-    State(state): State<AppState>,
-    Json(body): Json<PingNode>
-) -> (StatusCode, Json<HashMap<&'static str, String>>) {
-    let node_id: u64 = body.id.parse().unwrap();
-    if node_id == state.node.id {
-        let mut payload = HashMap::new();
-        payload.insert("error", String::from("You can't connect in the same node!"));
-        return (StatusCode::BAD_REQUEST, Json(payload));
+    match std::fs::write(path, text) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+            ExitCode::FAILURE
+        },
     }
+}
 
-    let mut nodes = state.nodes.lock().await;
-
-    if nodes.iter().any(|node| node.id == node_id) {
-        let mut payload = HashMap::new();
-        payload.insert("error", String::from("You're already connected in this node!"));
-        return (StatusCode::BAD_REQUEST, Json(payload));
+fn export_history(files: &[PathBuf], format: Format, output: Option<&Path>) -> ExitCode {
+    match read_histories(files) {
+        Err(code) => code,
+        Ok(events) => write_output(output, &jepsen::export(&events, format)),
     }
+}
 
-    nodes.push(Node { id: node_id, addr: body.addr.parse().unwrap() });
-    std::mem::drop(nodes);
+#[tokio::main]
+async fn run_workload(workload: Workload, format: Format, output: Option<&Path>) -> ExitCode {
+    let events = jepsen::run(&workload).await;
+    eprintln!("Recorded {} events", events.len());
+    write_output(output, &jepsen::export(&events, format))
+}
 
-    println!("[/ping] updated state: {:?}", state);
+#[tokio::main]
+async fn run_bench(config: BenchConfig) -> ExitCode {
+    println!("Benchmarking {} with {} clients for {:?}", config.targets.join(","), config.concurrency, config.duration);
+    let report = bench::run(&config).await;
+    print!("{}", report);
 
-    let mut payload = HashMap::new();
-    payload.insert("id", state.node.id.to_string());
-    payload.insert("addr", state.node.addr.to_string());
+    if report.ok() == 0 {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
 
-    (StatusCode::OK, Json(payload))
+#[tokio::main(flavor = "current_thread")]
+async fn run_client(client: Client, request: ClientRequest) -> ExitCode {
+    let result = match request {
+        ClientRequest::Propose { value } => client.propose(&value).await,
+        ClientRequest::Get { key } => match client.get(&key).await {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => {
+                eprintln!("Key {} not found", key);
+                return ExitCode::FAILURE;
+            },
+            Err(e) => Err(e),
+        },
+        ClientRequest::Put { key, value } => client.put(&key, &value).await.map(|()| String::new()),
+        ClientRequest::Delete { key } => client.delete(&key).await.map(|()| String::new()),
+        ClientRequest::Status => client.status().await.map(|status| serde_json::to_string_pretty(&status).unwrap()),
+    };
+
+    match result {
+        Ok(output) => {
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        },
+    }
 }
 
-async fn get_node_state(State(state): State<AppState>) -> (StatusCode, String) {
-    println!("[/] State: {:?}", state);
-    let state = serde_json::to_string(&state.node).unwrap();
-    (StatusCode::OK, state)
+#[tokio::main(flavor = "current_thread")]
+async fn cluster_status(client: Client) -> ExitCode {
+    let survey = match survey::run(&client).await {
+        Ok(survey) => survey,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        },
+    };
+    print!("{}", survey);
+
+    if !survey.is_healthy() {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
 }
 
-async fn get_state(State(state): State<AppState>) -> (StatusCode, ()) {
-    println!("State: {:?}", state);
-    (StatusCode::OK, ())
+#[tokio::main(flavor = "current_thread")]
+async fn run_repl(client: Client, history: Option<&Path>) -> ExitCode {
+    match repl::run(client, history).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        },
+    }
 }
 
-async fn prepare(State(state): State<AppState>, value: String) -> (StatusCode, String) {
-    let mut proposer = state.proposer.lock().await;
-    let ballot = match proposer.prepare(&state, value).await {
-        Err(e) => return (StatusCode::BAD_REQUEST, e.clone()),
-        Ok(ballot) => ballot,
+#[tokio::main]
+async fn dev_cluster(cluster: DevCluster) -> ExitCode {
+    let program = match std::env::current_exe() {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("Failed to find this program: {}", e);
+            return ExitCode::FAILURE;
+        },
+    };
+    let ports: Vec<String> = cluster.members().iter().map(|(_, port)| port.to_string()).collect();
+    println!("Starting {} nodes on ports {}", cluster.nodes, ports.join(", "));
+    let running = match cluster.start(&program).await {
+        Ok(running) => running,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        },
     };
+    println!("Every node is up and connected; Ctrl-C stops them");
+    println!("{}", running.wait().await);
+    ExitCode::SUCCESS
+}
 
-    match proposer.propose(&state, &ballot).await {
-        Err(e) => (StatusCode::BAD_REQUEST, e),
-        Ok(_) => {
-            let client = Client::new();
-            let mut ledger = state.ledger.lock().await;
-            let nodes = state.nodes.lock().await;
+#[tokio::main(flavor = "current_thread")]
+async fn simulate(config: SimConfig, seed: u64, values: &[String]) -> ExitCode {
+    if let Err(e) = config.check() {
+        eprintln!("{}", e);
+        return ExitCode::FAILURE;
+    }
+    let sim = Sim::new(seed, config);
+    let mut rounds = Vec::new();
+
+    for (i, value) in values.iter().enumerate() {
+        let node = i % sim.size();
+        let before = sim.messages();
+        let reply = sim.propose(node, value).await;
+
+        let sent: Vec<String> = sim.messages().into_iter()
+            .map(|(path, count)| (path.clone(), count - before.get(&path).copied().unwrap_or(0)))
+            .filter(|(_, count)| *count > 0)
+            .map(|(path, count)| format!("{} {}", path, count))
+            .collect();
+        rounds.push(format!("{:>3}. node {} proposed {:?}: {} ({})", i + 1, sim.node(node).node.id, value, reply.body, sent.join(", ")));
+    }
 
-            let reqs = nodes.iter().map(|node| {
-                client.post(format!("http://{}/handle-learn", node.addr))
-                    .json(&ballot)
-                    .send()
-            });
+    println!("\nRounds:");
+    for round in &rounds {
+        println!("{}", round);
+    }
 
-            futures::future::join_all(reqs).await;
+    let ledgers = sim.ledgers().await;
+    let mut log: std::collections::BTreeMap<u64, (String, usize)> = Default::default();
+    for ledger in &ledgers {
+        for (instance, value) in ledger {
+            log.entry(*instance).or_insert_with(|| (value.clone(), 0)).1 += 1;
+        }
+    }
 
-            ledger.insert(ballot.id, ballot.value.unwrap_or(String::from("")));
+    println!("\nDecided log:");
+    for (instance, (value, learned)) in &log {
+        println!("{:>5}  {:?}  (learned by {}/{})", instance, value, learned, ledgers.len());
+    }
 
-            (StatusCode::OK, String::from("Proposal accepted by the majority!"))
+    match sim.check_agreement().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Agreement violated: {}", e);
+            ExitCode::FAILURE
         },
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct HandleProposalPayload {
-    error: Option<String>,
-    value: Option<Ballot>,
+/// The key ring in `path`, or an empty one without a path.
+fn load_keys(path: Option<&Path>) -> Result<Keyring, ExitCode> {
+    let Some(path) = path else {
+        return Ok(Keyring::default());
+    };
+    Keyring::load(path).map_err(|e| {
+        eprintln!("Failed to read the key ring {}: {}", path.display(), e);
+        ExitCode::FAILURE
+    })
 }
 
-async fn handle_prepare(State(state): State<AppState>, proposal_id: String) -> (StatusCode, Json<HandleProposalPayload>) {
-    let proposal_id: u64 = proposal_id.parse().unwrap();
-    let mut acceptor = state.acceptor.lock().await;
+fn inspect(dir: &Path, encryption_key: Option<&Path>) -> ExitCode {
+    // Reads what isn't sealed too, to say so.
+    let keys = match load_keys(encryption_key) {
+        Ok(keys) => keys.migrating(true),
+        Err(code) => return code,
+    };
+    let data = match DataDir::read_with_keys(dir, &keys) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", dir.display(), e);
+            return ExitCode::FAILURE;
+        },
+    };
 
-    if proposal_id <= acceptor.last_ballot_number {
-        let payload = HandleProposalPayload {
-            error: Some(String::from("The proposal ID is lesser than the last accepted ballot number")),
-            value: None,
-        };
-        return (StatusCode::BAD_REQUEST, Json(payload));
+    match &data.identity {
+        None => println!("No node identity in {}", dir.display()),
+        Some(identity) => println!("Node {} (format {}, created at {})", identity.id, identity.format, identity.created),
     }
 
-    if acceptor.accepted_proposal.is_some() && acceptor.last_ballot_number == proposal_id {
-        println!("[/handle-prepare] Node {} already has a value: {:?}", state.node.id, acceptor.accepted_proposal);
-        let value = acceptor.accepted_proposal.clone();
-        let payload = HandleProposalPayload {
-            error: None,
-            value: Some(Ballot { id: proposal_id, value }),
-        };
-        return (StatusCode::OK, Json(payload));
+    match &data.snapshot {
+        None => println!("\nNo snapshot"),
+        Some(snapshot) => {
+            let meta = &snapshot.meta;
+            let last = meta.last_instance.map_or(String::from("-"), |instance| instance.to_string());
+            println!("\nSnapshot at lsn {}, taken at {}: {} instances up to {}, {} keys", meta.lsn, meta.taken, meta.instances, last, snapshot.state.kv.len());
+        },
     }
 
+    match (data.keys.is_empty(), data.plain) {
+        (true, _) => println!("\nNot encrypted"),
+        (false, plain) => {
+            let keys: Vec<&str> = data.keys.iter().map(String::as_str).collect();
+            println!("\nSealed with keys {}{}", keys.join(", "), if plain { ", some of it in the clear" } else { "" });
+        },
+    }
 
-    acceptor.last_ballot_number = proposal_id;
+    println!("\nLog: {} segments, {} entries after the snapshot, next lsn {}", data.segments.len(), data.wal.len(), data.next_lsn());
+    for segment in &data.segments {
+        println!("  {} from lsn {}, {} bytes", segment.path.display(), segment.first_lsn, segment.bytes);
+    }
+    if let Err(e) = data.check() {
+        println!("  Missing entries: {}", e);
+    }
+    for entry in &data.wal {
+        match &entry.record {
+            Record::Promised { instance, id } => println!("{:>6}  promised  instance {} to {}.{}", entry.lsn, instance, id.round, id.node_id),
+            Record::PromisedRange { range } => println!("{:>6}  promised  instances {} to {} to {}.{}", entry.lsn, range.from, range.to - 1, range.id.round, range.id.node_id),
+            Record::Accepted { ballot } => {
+                println!("{:>6}  accepted  instance {} from {}.{}: {:?}", entry.lsn, ballot.instance, ballot.id.round, ballot.id.node_id, ballot.value);
+            },
+            Record::Learned { instance, value } => println!("{:>6}  learned   instance {}: {:?}", entry.lsn, instance, value),
+            Record::Certified { certificate } => {
+                let acks: Vec<String> = certificate.acks.iter().map(|ack| ack.from.to_string()).collect();
+                println!("{:>6}  certified instance {} from {}.{}: accepted by {}", entry.lsn, certificate.instance, certificate.id.round, certificate.id.node_id, acks.join(", "));
+            },
+            Record::Reserved { round } => println!("{:>6}  reserved  rounds up to {}", entry.lsn, round),
+        }
+    }
+    if data.torn {
+        println!("        (an incomplete last entry, dropped on the next start)");
+    }
 
-    println!("[/handle-prepare] setting the new last ballot number as: {}", proposal_id);
+    let recovered = data.recover();
+    println!("\nRecovered: {} instances learned, {} keys", recovered.ledger.len(), recovered.kv.len());
+    for (instance, slot) in &recovered.acceptor.slots {
+        let id = slot.last_ballot_number;
+        let accepted = slot.accepted_proposal.as_ref()
+            .map_or(String::from("nothing accepted"), |ballot| format!("accepted {}.{} {:?}", ballot.id.round, ballot.id.node_id, ballot.value));
+        println!("  instance {}: promised {}.{}, {}", instance, id.round, id.node_id, accepted);
+    }
+    ExitCode::SUCCESS
+}
 
-    println!("[/handle-prepare] Node {} accepted a new proposal: {}", state.node.id, proposal_id);
+fn backup(dir: &Path, output: Option<&Path>, encryption_key: Option<&Path>, migrating: bool) -> ExitCode {
+    let keys = match load_keys(encryption_key) {
+        Ok(keys) => keys.migrating(migrating),
+        Err(code) => return code,
+    };
+    match Backup::take_with_keys(dir, &keys) {
+        Ok(backup) => {
+            eprintln!("Backed up node {}: {} log entries after the snapshot", backup.node, backup.wal.len());
+            write_output(output, &String::from_utf8(backup.to_bytes(&keys)).unwrap())
+        },
+        Err(e) => {
+            eprintln!("Failed to back up {}: {}", dir.display(), e);
+            ExitCode::FAILURE
+        },
+    }
+}
 
-    let payload = HandleProposalPayload {
-        error: None,
-        value: Some(Ballot { id: proposal_id, value: None }),
+fn restore(file: &Path, dir: &Path, encryption_key: Option<&Path>) -> ExitCode {
+    let keys = match load_keys(encryption_key) {
+        Ok(keys) => keys,
+        Err(code) => return code,
+    };
+    let backup = std::fs::read(file)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| Backup::from_bytes(&bytes, &keys));
+
+    let backup = match backup {
+        Ok(backup) => backup,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", file.display(), e);
+            return ExitCode::FAILURE;
+        },
     };
 
-    (StatusCode::OK, Json(payload))
+    match backup.seed_with_keys(dir, &keys) {
+        Ok(()) => {
+            println!("Restored node {} into {}; start it with --id {} --data-dir {}", backup.node, dir.display(), backup.node, dir.display());
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            eprintln!("Failed to restore into {}: {}", dir.display(), e);
+            ExitCode::FAILURE
+        },
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct HandleAcceptPayload {
-    error: Option<String>,
-    value: Option<Ballot>,
-}
+#[cfg(feature = "s3")]
+#[tokio::main]
+async fn restore_s3(from: u64, id: u64, dir: &Path, encryption_key: Option<&Path>, args: S3Args) -> ExitCode {
+    let keys = match load_keys(encryption_key) {
+        Ok(keys) => keys,
+        Err(code) => return code,
+    };
+    let Some(bucket) = args.s3_bucket else {
+        eprintln!("restore-s3 needs --s3-bucket");
+        return ExitCode::FAILURE;
+    };
+    let bucket = match s3_bucket(bucket, args.s3_endpoint, args.s3_region, args.s3_prefix) {
+        Ok(bucket) => bucket,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        },
+    };
 
-async fn handle_accept(State(state): State<AppState>, propose: Json<Ballot>) -> (StatusCode, Json<HandleAcceptPayload>) {
-    println!("[/handle-accept] Node {} get new propose to be accepted: {:?}", state.node.id, propose);
+    let snapshot = match bucket.download_snapshot(from, &keys).await {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("Failed to download {}: {}", bucket.config().snapshot_key(from), e);
+            return ExitCode::FAILURE;
+        },
+    };
 
-    let mut acceptor = state.acceptor.lock().await;
-    if acceptor.last_ballot_number != propose.id {
-        println!("[/handle-accept] Node {} received a proposal with a ballot ID different: {}", state.node.id, propose.id);
-        let payload = HandleAcceptPayload {
-            error: Some(String::from("Node received a proposal with a ballot ID different!")),
-            value: None,
-        };
-        return (StatusCode::BAD_REQUEST, Json(payload));
+    let instances = snapshot.meta.instances;
+    match s3::replica(id, snapshot).seed_with_keys(dir, &keys) {
+        Ok(()) => {
+            println!("Seeded node {} with the {} instances node {} learned; start it with --id {} --data-dir {}", id, instances, from, id, dir.display());
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            eprintln!("Failed to restore into {}: {}", dir.display(), e);
+            ExitCode::FAILURE
+        },
     }
+}
 
-    println!("[/handle-accept] Node {} accepting new proposed value: {:?}", state.node.id, propose.value);
-
-    acceptor.accepted_proposal = propose.value.clone();
-
-    let payload = HandleAcceptPayload {
-        error: None,
-        value: Some(Ballot { id: propose.id, value: propose.value.clone() }),
+#[tokio::main]
+async fn replay(file: &Path) -> ExitCode {
+    let entries = match trace::read_entries(file) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", file.display(), e);
+            return ExitCode::FAILURE;
+        },
     };
 
-    (StatusCode::OK, Json(payload))
+    match trace::replay(&entries).await {
+        Ok(summary) => {
+            let learned = summary.snapshot.map_or(0, |snapshot| snapshot.ledger.len());
+            println!("Replayed {} steps, {} checkpoints matched, {} instances learned", summary.steps, summary.checkpoints, learned);
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            println!("Replay diverged at {}", e);
+            ExitCode::FAILURE
+        },
+    }
 }
 
-async fn handle_learn(State(state): State<AppState>, payload: Json<Ballot>) -> (StatusCode, ()) {
-    let mut ledger = state.ledger.lock().await;
+fn verify_proof(file: &Path, keys: &[String]) -> ExitCode {
+    let mut known = std::collections::BTreeMap::new();
+    for key in keys {
+        match signing::parse_peer_key(key) {
+            Ok((id, key)) => known.insert(id, key),
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            },
+        };
+    }
 
-    // TODO: I'm not proud of it, but it works.
-    match &payload.value {
-        None => ledger.insert(payload.id, String::from("")),
-        Some(v) => ledger.insert(payload.id, v.clone()),
+    let text = match std::fs::read_to_string(file) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", file.display(), e);
+            return ExitCode::FAILURE;
+        },
+    };
+    let (proof, read) = match (serde_json::from_str::<ProvedRead>(&text), serde_json::from_str::<Proof>(&text)) {
+        (Ok(read), _) => (read.proof.clone(), Some(read)),
+        (_, Ok(proof)) => (proof, None),
+        (Err(e), _) => {
+            eprintln!("{} holds no proof: {}", file.display(), e);
+            return ExitCode::FAILURE;
+        },
     };
 
-    println!("[/handle-learn] Node {} learns a new value: {:?}", state.node.id, payload.value);
-
-    reset_decision_point(&state).await;
-
-    (StatusCode::OK, ())
+    if let Some(read) = read.filter(|read| !read.proof.writes(&read.key, &read.value)) {
+        println!("Instance {} didn't write {:?} to key {}", proof.instance, read.value, read.key);
+        return ExitCode::FAILURE;
+    }
+    match proof.verify(&known) {
+        Ok(signers) => {
+            let signers: Vec<String> = signers.iter().map(u64::to_string).collect();
+            println!("Instance {} was accepted by {} and chained as {}", proof.instance, signers.join(", "), proof.hash);
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            println!("{}", e);
+            ExitCode::FAILURE
+        },
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Ballot {
-    pub id: u64,
-    pub value: Option<String>,
-}
+fn check_history(files: &[PathBuf]) -> ExitCode {
+    let events = match read_histories(files) {
+        Ok(events) => events,
+        Err(code) => return code,
+    };
 
-#[derive(Clone, Debug)]
-struct Proposer {
-    pub id: u64,
+    match history::check(&events) {
+        Ok(keys) => {
+            println!("History is linearizable ({} events over {} keys)", events.len(), keys);
+            ExitCode::SUCCESS
+        },
+        Err(violations) => {
+            for violation in violations {
+                println!("Key {:?} is not linearizable:", violation.key);
+                for op in violation.operations {
+                    let ret = op.ret.map_or(String::from("?"), |ret| ret.to_string());
+                    println!("  node {} op {}: {:?} {:?} [{} .. {}]", op.node, op.op, op.f, op.value, op.call, ret);
+                }
+            }
+            ExitCode::FAILURE
+        },
+    }
 }
 
-type Propose = Value;
+#[tokio::main]
+async fn run_node(reloader: Reloader) {
+    let options = reloader.current.lock().unwrap().clone();
+    let port = options.port.clone().unwrap();
+    let node_id = options.id.unwrap();
 
-impl Proposer {
-    pub fn new() -> Self {
-        Self { id: 0 }
-    }
+    let node_http_addr = format!("0.0.0.0:{}", port);
 
-    pub async fn prepare(&mut self, state: &AppState, value: String) -> Result<Ballot, String> {
-        let client = Client::new();
+    println!("Starting new node: http://{}", node_http_addr);
 
-        self.id += 1;
+    let mut node = Node::new(node_id, node_http_addr.parse().unwrap());
+    node.learner = options.learner.unwrap_or(false);
+    node.zone = options.zone.clone();
+    node.weight = options.weight.unwrap_or(1);
+    if node.weight == 0 {
+        panic!("--weight has to be at least 1; a node that shouldn't vote can run with --learner");
+    }
+    if options.byzantine.unwrap_or(false) && options.signing_key.is_none() {
+        panic!("--byzantine needs --signing-key: without it, any host can vote in another's name");
+    }
+    if node.learner {
+        println!("Node {} is a learner: it serves reads and leaves the voting to its peers", node_id);
+    }
+    let cluster_token = match &options.cluster_token {
+        Some(path) => Arc::new(ClusterToken::load(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))),
+        None => Arc::default(),
+    };
+    let clock = Arc::new(Hlc::default());
+    let transport = HttpTransport::new(node_id).with_token(cluster_token.clone()).with_clock(clock.clone());
+    let mut state = AppState::new(node, Arc::new(transport));
+    state.cluster_token = cluster_token;
+    if let Some(path) = &options.admin_token {
+        state.admin_token = Arc::new(ClusterToken::load(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e)));
+    }
+    state.clock = clock;
 
-        let nodes = state.nodes.lock().await;
-        let reqs = nodes.iter().map(|node| {
-            client.post(format!("http://{}/handle-prepare", node.addr))
-                .json(&self.id)
-                .send()
-        });
+    if let Some(path) = &options.history {
+        let history = History::open(node_id, path).unwrap();
+        state.history = Some(Arc::new(history));
+    }
 
-        let responses = futures::future::join_all(reqs).await;
+    if let Some(path) = &options.trace {
+        let trace = Trace::open(node_id, path).unwrap();
+        state.trace = Some(Arc::new(trace));
+    }
 
-        let mut promises = Vec::with_capacity(responses.len());
+    #[cfg(feature = "plugins")]
+    if let Some(path) = &options.state_machine {
+        let plugin = Plugin::load(path).unwrap_or_else(|e| panic!("{}", e));
+        println!("Node {} applies learned values with the state machine in {}", node_id, path.display());
+        state.machine = Some(Arc::new(plugin));
+        state.kv = Arc::new(tokio::sync::Mutex::new(Kv::with_machine(state.machine.clone())));
+    }
 
-        for response in responses.into_iter().flatten() {
-            // TODO: Improve this to handle Err() stuffs.
-            promises.push(response.json::<HandleProposalPayload>().await.unwrap());
+    // Every group's storage seals with a ring of its own.
+    let keyring = || match &options.encryption_key {
+        Some(path) => Keyring::load(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e)).migrating(options.encryption_migrate.unwrap_or(false)),
+        None => Keyring::default(),
+    };
+    let keys = keyring();
+    if options.encryption_key.is_some() {
+        if options.data_dir.is_none() {
+            println!("Node {} has no --data-dir, so it has nothing to encrypt", node_id);
         }
+        println!("Node {} seals its data directory with key {}", node_id, keys.current().unwrap_or_default());
+    }
 
-        let quorum = (nodes.len() / 2) + 1;
+    let segment_bytes = options.wal_segment_bytes.unwrap_or(storage::SEGMENT_BYTES);
+    if let Some(dir) = &options.data_dir {
+        let (storage, recovered) = Storage::open_with(node_id, dir, segment_bytes, keys, state.machine.clone()).unwrap();
+        println!("Recovered {} learned instances and {} open slots from {}", recovered.ledger.len(), recovered.acceptor.slots.len(), dir.display());
+        storage::restore(&state, recovered).await;
+        state.storage = Some(Arc::new(storage));
+    }
 
-        if promises.len() < quorum {
-            return Err(String::from("Proposal does not receive promises of the entire quorum"));
+    #[cfg(feature = "s3")]
+    if let Some(bucket) = options.s3_bucket.clone() {
+        if options.data_dir.is_none() {
+            println!("Node {} has no --data-dir, so it has no snapshots to upload to {}", node_id, bucket);
         }
+        let bucket = s3_bucket(bucket, options.s3_endpoint.clone(), options.s3_region.clone().unwrap_or_default(), options.s3_prefix.clone().unwrap_or_default());
+        state.s3 = Some(Arc::new(bucket.unwrap()));
+    }
 
-        let accepted_promise = promises
-            .into_iter()
-            .filter(|promise| promise.value.is_some())
-            .max_by_key(|promise| match &promise.value {
-                None => 0,
-                Some(value) => value.id,
-            });
-
-        // TODO: I'm not proud of this horrible stuff.
-        let value = match accepted_promise {
-            None => Some(value),
-            Some(payload) => {
-                match payload.value {
-                    None => Some(value),
-                    Some(ballot) => {
-                        match ballot.value {
-                            None => Some(value),
-                            b => b,
-                        }
-                    },
-                }
-            }
-        };
-
-        let propose = Ballot { id: self.id, value };
+    if let Some(path) = &options.signing_key {
+        let keypair = signing::load_or_create(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        println!("Node {} signs with key {}", node_id, hex::encode(&keypair.public()));
+        state.keys.set_own(node_id, keypair);
+    }
+    for key in options.peer_keys.clone().unwrap_or_default() {
+        let (peer, key) = signing::parse_peer_key(&key).unwrap_or_else(|e| panic!("--peer-key: {}", e));
+        state.keys.trust(peer, key);
+    }
 
-        Ok(propose)
+    let byzantine = options.byzantine.unwrap_or(false);
+    if byzantine {
+        state.pbft.enable(state.ledger.last().unwrap_or(0));
     }
 
-    pub async fn propose(&self, state: &AppState, propose: &Ballot) -> Result<(), String> {
-        let client = Client::new();
+    if let Some(group) = options.learn_multicast.filter(|_| !byzantine) {
+        let interface = options.learn_multicast_interface.unwrap_or(Ipv4Addr::UNSPECIFIED);
+        match Multicast::join(group, interface) {
+            Ok(multicast) => state.multicast = Some(Arc::new(multicast)),
+            Err(e) => println!("Node {} couldn't join {}, sending learns over HTTP only: {}", node_id, group, e),
+        }
+    }
 
-        let nodes = state.nodes.lock().await;
+    state.acl = options.acl.unwrap_or(false);
+    state.debug_admin = options.debug_admin.unwrap_or(false);
+    if !state.admin_token.is_on() && !state.acl {
+        println!("Node {} serves /admin to anyone: start it with --admin-token or --acl to keep it to operators", node_id);
+    }
 
-        let reqs = nodes.iter().map(|node| {
-            client.post(format!("http://{}/handle-accept", node.addr))
-                .json(&propose)
-                .send()
-        });
+    if options.step.unwrap_or(false) {
+        state.stepper = Some(Arc::new(Stepper::default()));
+    }
+    *state.read_only.write().unwrap() = options.read_only;
 
-        let responses = futures::future::join_all(reqs).await;
+    if let Some(dir) = &options.data_dir {
+        crash::install(state.clone(), dir.clone());
+    }
 
-        let mut accepted_ballots = Vec::with_capacity(responses.len());
+    let reloader = Arc::new(reloader);
+    *state.settings.write().unwrap() = options.settings();
+    state.reloader = Some(reloader.clone());
 
-        for response in responses.into_iter().flatten() {
-            accepted_ballots.push(response.json::<HandleAcceptPayload>().await.unwrap());
+    state.streams = Arc::new(Streams::new(options.streams.unwrap_or(1)));
+    let count = options.shards.unwrap_or(1);
+    let named = options.groups.clone().unwrap_or_default();
+    if let Some(id) = named.iter().find(|id| !groups::is_valid(id)) {
+        panic!("--group {:?} isn't a valid group id", id);
+    }
+    if byzantine && (count > 1 || !named.is_empty()) {
+        println!("Node {} keeps every key in one PBFT log, --shards and --group only make Paxos groups", node_id);
+    } else {
+        if let Some(dir) = options.data_dir.as_ref().filter(|_| count > 1) {
+            shards::check_count(dir, count).unwrap_or_else(|e| panic!("{}: {}", dir.display(), e));
         }
-
-        let quorum = (nodes.len() / 2) + 1;
-
-        if accepted_ballots.len() + 1 < quorum {
-            return Err(String::from("Proposal not accepted by majority"));
+        let shards = (0..count).filter(|_| count > 1).map(|shard| (shards::id(shard), Path::new(shards::DIR).join(shard.to_string())));
+        let named = named.iter().map(|id| (id.clone(), Path::new(groups::DIR).join(id)));
+
+        let mut hosted = Groups::default();
+        for (id, subdir) in shards.chain(named) {
+            let mut group = groups::group(&state, &id);
+            if let Some(dir) = &options.data_dir {
+                let dir = dir.join(subdir);
+                let (storage, recovered) = Storage::open_with(node_id, &dir, segment_bytes, keyring(), group.machine.clone()).unwrap();
+                println!("Recovered {} learned instances and {} open slots from {}", recovered.ledger.len(), recovered.acceptor.slots.len(), dir.display());
+                storage::restore(&group, recovered).await;
+                group.storage = Some(Arc::new(storage));
+            }
+            hosted.host(id, group);
         }
+        if !hosted.is_empty() {
+            let ids: Vec<&str> = hosted.iter().map(|(id, _)| id.as_str()).collect();
+            println!("Node {} hosts Paxos groups {} besides its own", node_id, ids.join(", "));
+        }
+        state.groups = Arc::new(hosted);
+    }
 
-        Ok(())
+    tokio::spawn(chaos::run(state.clone()));
+    tokio::spawn(storage::run(state.clone()));
+    tokio::spawn(intake::resubmit(state.clone()));
+    tokio::spawn(replica::run(state.clone()));
+    tokio::spawn(apply::fill_gaps(state.clone()));
+    tokio::spawn(shipping::run(state.clone()));
+    tokio::spawn(takeover::run(state.clone()));
+    tokio::spawn(streams::run(state.clone()));
+    tokio::spawn(txn::run(state.clone()));
+    for (_, group) in state.groups.iter() {
+        tokio::spawn(storage::run(group.clone()));
+        tokio::spawn(intake::resubmit(group.clone()));
+        tokio::spawn(replica::run(group.clone()));
+        tokio::spawn(apply::fill_gaps(group.clone()));
+        tokio::spawn(shipping::run(group.clone()));
+        tokio::spawn(takeover::run(group.clone()));
+        tokio::spawn(streams::run(group.clone()));
     }
-}
+    tokio::spawn(disk::run(state.clone()));
+    tokio::spawn(namespace::run(state.clone()));
+    tokio::spawn(lease::run(state.clone()));
+    tokio::spawn(joins::run(state.clone()));
+    tokio::spawn(secrets::run(state.clone()));
+    tokio::spawn(multicast::listen(state.clone()));
+    tokio::spawn(config::on_hangup(state.clone(), reloader));
 
-async fn reset_decision_point(state: &AppState) {
-    let mut acceptor = state.acceptor.lock().await;
-    acceptor.last_ballot_number = 0;
-    acceptor.accepted_proposal = None;
+    // Client addresses are what the rate limits are kept by.
+    let app = router(state.clone()).into_make_service_with_connect_info::<SocketAddr>();
+
+    let listener = tokio::net::TcpListener::bind(node_http_addr).await.unwrap();
+    let server = tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
 
-    let mut proposer = state.proposer.lock().await;
-    proposer.id = 0;
+    systemd::notify(&format!("READY=1\nSTATUS=Node {} serving on port {}", node_id, port));
+    tokio::spawn(systemd::watchdog(state.clone()));
 
-    println!("[reset_decision_point] Decision point reseted!");
+    // Peers still need answers while we drain, so the server only stops
+    // once that is over.
+    shutdown::signal().await;
+    systemd::notify("STOPPING=1");
+    shutdown::run(state).await;
+    server.abort();
 }
@@ -0,0 +1,50 @@
+//! The peers a node knows, as an immutable snapshot.
+//!
+//! Every round reads the membership, to count its voters and fan out to
+//! them, while it only changes when a node joins or comes back. So readers
+//! take an `Arc` of the current list and let go of the lock at once, and a
+//! change copies the list, edits the copy and swaps it in: a round never
+//! waits on a join, and a round under way keeps the list it started with.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::{Id, Node};
+
+#[derive(Debug, Default)]
+pub struct Membership {
+    nodes: RwLock<Arc<Vec<Node>>>,
+    /// Held through a change, so two can't both edit the same copy.
+    changing: Mutex<()>,
+}
+
+impl Membership {
+    pub fn new(nodes: Vec<Node>) -> Self {
+        Self { nodes: RwLock::new(Arc::new(nodes)), changing: Mutex::new(()) }
+    }
+
+    /// The peers as of now. Later changes don't show up in it.
+    pub fn snapshot(&self) -> Arc<Vec<Node>> {
+        self.nodes.read().unwrap().clone()
+    }
+
+    /// Like [`Membership::snapshot`], but `None` rather than waiting for a
+    /// change in progress.
+    pub fn try_snapshot(&self) -> Option<Arc<Vec<Node>>> {
+        self.nodes.try_read().ok().map(|nodes| nodes.clone())
+    }
+
+    /// The peer at `addr`, if it is one.
+    pub fn at(&self, addr: std::net::SocketAddr) -> Option<Id> {
+        self.snapshot().iter().find(|node| node.addr == addr).map(|node| node.id)
+    }
+
+    /// Applies `change` to a copy of the peers and publishes it. Changes
+    /// wait for each other; readers only ever wait for the swap.
+    pub fn update<R>(&self, change: impl FnOnce(&mut Vec<Node>) -> R) -> R {
+        let _changing = self.changing.lock().unwrap();
+        let mut next = Vec::clone(&self.snapshot());
+        let result = change(&mut next);
+        *self.nodes.write().unwrap() = Arc::new(next);
+        result
+    }
+}
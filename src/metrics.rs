@@ -0,0 +1,95 @@
+//! Metrics in the Prometheus text format, on `GET /metrics`.
+//!
+//! Everything is a gauge read off the node's state when scraped, so nothing
+//! has to be counted on the hot path.
+
+use std::fmt::Write;
+use axum::{
+    http::StatusCode,
+    extract::State,
+};
+
+use crate::{AppState, groups, namespace, version};
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, value);
+}
+
+/// A gauge with a value for each of `label`.
+fn labelled<T: std::fmt::Display>(out: &mut String, name: &str, help: &str, label: &str, values: impl IntoIterator<Item = (String, T)>) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge", name, help, name);
+    for (key, value) in values {
+        let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, key, value);
+    }
+}
+
+pub async fn get_metrics(State(state): State<AppState>) -> (StatusCode, String) {
+    let mut out = String::new();
+
+    let learned = state.ledger.len();
+    gauge(&mut out, "paxos_learned_instances", "Instances this node has learned.", learned);
+    gauge(&mut out, "paxos_apply_lag", "Learned values not applied to the KV store yet.", state.applier.lag());
+    gauge(&mut out, "paxos_duplicates_suppressed", "Peer messages answered again rather than handled twice.", state.dedup.suppressed());
+    gauge(&mut out, "paxos_instances_taken_over", "Instances left undecided that this node took over.", state.takeovers.taken());
+    gauge(&mut out, "paxos_learns_pending", "Decisions queued or on their way to the peers.", state.learns.pending());
+    let batching = state.settings.read().unwrap().learn_batching;
+    gauge(&mut out, "paxos_learn_batch_window_seconds", "How long a decision waits for others to be sent with it.", state.learns.window(batching).as_secs_f64());
+    gauge(&mut out, "paxos_proposals_in_flight", "Client proposals this node is running.", state.backpressure.running());
+    gauge(&mut out, "paxos_proposals_queued", "Client proposals waiting for one of those to finish.", state.backpressure.queued());
+    gauge(&mut out, "paxos_paused", "1 while an operator has paused this node.", u8::from(state.is_paused()));
+    gauge(&mut out, "paxos_read_only", "1 while this node rejects or forwards writes.", u8::from(state.read_only().is_some()));
+    gauge(&mut out, "paxos_learner", "1 if this node only learns, and has no vote.", u8::from(state.node.learner));
+
+    let peers: Vec<_> = state.nodes.snapshot().iter().map(|node| node.id).collect();
+    gauge(&mut out, "paxos_protocol_version", "Newest protocol this node speaks.", version::PROTOCOL);
+    gauge(&mut out, "paxos_cluster_protocol_version", "Newest protocol every known peer speaks too.", state.versions.common(peers));
+
+    let (usage, retention) = {
+        let kv = state.kv.lock().await;
+        let retention: Vec<_> = namespace::namespaces(&kv).into_values()
+            .filter_map(|namespace| Some((namespace.name, namespace.retention_ms? as f64 / 1e3)))
+            .collect();
+        (namespace::usage(&kv), retention)
+    };
+    if !usage.is_empty() {
+        labelled(&mut out, "paxos_namespace_keys", "Keys in each namespace.", "namespace", usage.iter().map(|(name, (keys, _))| (name.clone(), keys)));
+        labelled(&mut out, "paxos_namespace_bytes", "Bytes the values of each namespace take, write times included.", "namespace", usage.iter().map(|(name, (_, bytes))| (name.clone(), bytes)));
+    }
+    if !retention.is_empty() {
+        labelled(&mut out, "paxos_namespace_retention_seconds", "How long each namespace keeps a key after its last write.", "namespace", retention);
+    }
+
+    let groups = groups::status(&state).await;
+    if !groups.is_empty() {
+        labelled(&mut out, "paxos_group_learned_instances", "Instances each hosted group has learned.", "group", groups.iter().map(|group| (group.id.clone(), group.instances)));
+        labelled(&mut out, "paxos_group_keys", "Keys in the KV store of each hosted group.", "group", groups.iter().map(|group| (group.id.clone(), group.keys)));
+        labelled(&mut out, "paxos_group_apply_lag", "Values each hosted group learned but didn't apply yet.", "group", groups.iter().map(|group| (group.id.clone(), group.apply_lag)));
+        labelled(&mut out, "paxos_group_learns_pending", "Decisions of each hosted group still to reach the peers.", "group", groups.iter().map(|group| (group.id.clone(), group.learns_pending)));
+    }
+
+    let throttled = state.rate_limiter.throttled_peers();
+    if !throttled.is_empty() {
+        let throttled = throttled.into_iter().map(|(peer, count)| (peer.map_or_else(|| String::from("unknown"), |id| id.to_string()), count));
+        labelled(&mut out, "paxos_peer_throttled", "Prepares and accepts from each peer turned away for its rate limit.", "peer", throttled);
+    }
+
+    if let Some(multicast) = &state.multicast {
+        gauge(&mut out, "paxos_learn_multicasts", "Batches of learns sent to the multicast group.", multicast.sent());
+        gauge(&mut out, "paxos_learn_multicast_peers", "Peers that joined the same group.", multicast.listeners().len());
+    }
+
+    if let Some(storage) = &state.storage {
+        gauge(&mut out, "paxos_disk_free_bytes", "Free space under the data directory.", state.disk.free());
+        gauge(&mut out, "paxos_disk_low", "1 while that is under --min-free-bytes and the node doesn't vote.", u8::from(state.disk.is_low()));
+        gauge(&mut out, "paxos_wal_segments", "Log segments on disk.", storage.segments());
+        gauge(&mut out, "paxos_intake_pending", "Client commands taken and not finished yet.", storage.intake().pending());
+        gauge(&mut out, "paxos_wal_syncs", "Syncs of the log since the node started.", storage.syncs());
+        gauge(&mut out, "paxos_wal_entries_since_snapshot", "Log entries the last snapshot doesn't cover.", storage.entries_since_snapshot());
+        gauge(&mut out, "paxos_snapshot_age_seconds", "Time since the last snapshot, or since start without one.", storage.snapshot_age() as f64 / 1e6);
+        if let Some(meta) = storage.last_snapshot() {
+            gauge(&mut out, "paxos_snapshot_lsn", "Last log entry the latest snapshot covers.", meta.lsn);
+        }
+    }
+
+    (StatusCode::OK, out)
+}
@@ -0,0 +1,96 @@
+//! Read-only memory maps of files in the data directory.
+//!
+//! A snapshot used to be read into a string and then parsed, so starting
+//! from a big one held the text and the ledger built from it in memory at
+//! once, and sending one to a peer read it all again. [`Mmap`] lets both
+//! read it straight out of the page cache instead: the parser borrows the
+//! mapped bytes, and the kernel drops them again once they are read.
+//!
+//! Files in the data directory are only ever replaced by a rename, never
+//! written in place, so a map keeps seeing the file as it was opened.
+
+use std::{fs::File, io, path::Path};
+
+/// A file mapped into memory, unmapped when dropped.
+#[derive(Debug)]
+pub struct Mmap {
+    #[cfg(unix)]
+    ptr: *mut libc::c_void,
+    #[cfg(not(unix))]
+    data: Vec<u8>,
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and private, so sharing it between
+// threads is no different from sharing a `&[u8]`.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps all of `path`. Elsewhere than on unix it is read like before.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::map(&File::open(path)?)
+    }
+
+    #[cfg(unix)]
+    pub fn map(file: &File) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let len = usize::try_from(file.metadata()?.len()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // Mapping nothing is an error, and nothing needs no mapping.
+        if len == 0 {
+            return Ok(Self { ptr: std::ptr::null_mut(), len });
+        }
+
+        // SAFETY: a fresh read-only private mapping of an open file, which
+        // no one else gets to see or unmap.
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+
+    #[cfg(not(unix))]
+    pub fn map(file: &File) -> io::Result<Self> {
+        use std::io::Read;
+
+        let mut data = Vec::new();
+        (&*file).read_to_end(&mut data)?;
+        Ok(Self { len: data.len(), data })
+    }
+}
+
+impl std::ops::Deref for Mmap {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: `ptr` maps `len` readable bytes until `self` is dropped.
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl AsRef<[u8]> for Mmap {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.len > 0 {
+            // SAFETY: unmaps exactly what `map` mapped, once.
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
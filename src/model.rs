@@ -0,0 +1,264 @@
+//! A stateright model of a single instance, built on the same acceptor and
+//! proposer transitions the nodes run over HTTP.
+//!
+//! Every actor is a full node: it accepts, and it may propose a value of its
+//! own and retry with a higher proposal a bounded number of times. The
+//! network delivers in any order; lost messages need no modelling of their
+//! own, since nothing is retransmitted and a message that is never delivered
+//! looks just like one that is still in flight. Every chosen value is
+//! recorded in the model history, so that the checker can assert that at
+//! most one value is ever chosen (agreement) and that it is one of the
+//! proposed ones (validity), across every reachable interleaving.
+//!
+//! Only built with `--features model-check`.
+
+use std::{borrow::Cow, collections::BTreeSet};
+use stateright::{
+    actor::{Actor, ActorModel, Envelope, Id as ActorId, Network, Out, model_timeout},
+    Expectation,
+};
+
+use crate::{
+    Ballot, Id, ProposalId, Value,
+    acceptor::Acceptor,
+    proposer::{Proposer, Round},
+};
+
+/// The model only ever runs this instance.
+const INSTANCE: u64 = 1;
+
+/// The HTTP requests between nodes, and their replies.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Msg {
+    Prepare(Ballot),
+    Promised { id: ProposalId, accepted: Option<Ballot>, decided: Option<Value> },
+    Accept(Ballot),
+    Accepted { id: ProposalId },
+    Rejected { id: ProposalId, promised: Option<ProposalId> },
+    Learn(Ballot),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Timer {
+    Retry,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct NodeState {
+    pub acceptor: Acceptor,
+    pub proposer: Proposer,
+    pub ledger: Option<Value>,
+    /// The attempt in flight, if any.
+    pub round: Option<Round>,
+    /// Set once the attempt in flight moved on to phase 2.
+    pub proposal: Option<Ballot>,
+    pub attempts: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct PaxosNode {
+    /// Every node, this one included.
+    pub voters: Vec<ActorId>,
+    /// The value this node tries to get chosen, if any.
+    pub value: Option<Value>,
+    /// How many prepare rounds it may start before giving up.
+    pub attempts: usize,
+}
+
+/// Model actors are numbered from 0, node ids from 1.
+fn node_id(id: ActorId) -> Id {
+    usize::from(id) as Id + 1
+}
+
+impl PaxosNode {
+    fn start_round(&self, id: ActorId, state: &mut Cow<NodeState>, o: &mut Out<Self>) {
+        let Some(value) = &self.value else {
+            return;
+        };
+        if state.ledger.is_some() || state.attempts >= self.attempts {
+            return;
+        }
+
+        let state_mut = state.to_mut();
+        let proposal_id = state_mut.proposer.next_proposal_id(node_id(id));
+        let round = Round::new(INSTANCE, proposal_id, value.clone(), self.voters.len());
+        let prepare = round.prepare();
+
+        state_mut.round = Some(round);
+        state_mut.proposal = None;
+        state_mut.attempts += 1;
+
+        o.set_timer(Timer::Retry, model_timeout());
+        self.broadcast(id, state, Msg::Prepare(prepare), o);
+    }
+
+    /// Messages a node sends itself are handled on the spot, as going
+    /// through the network would only multiply the interleavings to explore
+    /// without adding any that matter.
+    fn send(&self, id: ActorId, state: &mut Cow<NodeState>, dst: ActorId, msg: Msg, o: &mut Out<Self>) {
+        if dst == id {
+            self.on_msg(id, state, id, msg, o);
+        } else {
+            o.send(dst, msg);
+        }
+    }
+
+    fn broadcast(&self, id: ActorId, state: &mut Cow<NodeState>, msg: Msg, o: &mut Out<Self>) {
+        for &dst in &self.voters {
+            self.send(id, state, dst, msg.clone(), o);
+        }
+    }
+
+    /// The reply `/handle-prepare` would give.
+    fn handle_prepare(state: &mut NodeState, ballot: Ballot) -> Msg {
+        if let Some(decided) = &state.ledger {
+            return Msg::Promised { id: ballot.id, accepted: None, decided: Some(decided.clone()) };
+        }
+
+        match state.acceptor.prepare(&ballot) {
+            Err(promised) => Msg::Rejected { id: ballot.id, promised: Some(promised) },
+            Ok(accepted) => Msg::Promised { id: ballot.id, accepted, decided: None },
+        }
+    }
+
+    /// The reply `/handle-accept` would give.
+    fn handle_accept(state: &mut NodeState, ballot: Ballot) -> Msg {
+        if let Some(decided) = &state.ledger {
+            if Some(decided) != ballot.value.as_ref() {
+                return Msg::Rejected { id: ballot.id, promised: None };
+            }
+            return Msg::Accepted { id: ballot.id };
+        }
+
+        match state.acceptor.accept(&ballot) {
+            Err(promised) => Msg::Rejected { id: ballot.id, promised: Some(promised) },
+            Ok(()) => Msg::Accepted { id: ballot.id },
+        }
+    }
+}
+
+impl Actor for PaxosNode {
+    type Msg = Msg;
+    type State = NodeState;
+    type Timer = Timer;
+    type Random = ();
+    type Storage = ();
+
+    fn name(&self) -> String {
+        String::from("Paxos node")
+    }
+
+    fn on_start(&self, id: ActorId, _storage: &Option<Self::Storage>, o: &mut Out<Self>) -> Self::State {
+        let mut state = Cow::Owned(NodeState::default());
+        self.start_round(id, &mut state, o);
+        state.into_owned()
+    }
+
+    fn on_msg(&self, id: ActorId, state: &mut Cow<Self::State>, src: ActorId, msg: Self::Msg, o: &mut Out<Self>) {
+        match msg {
+            Msg::Prepare(ballot) => {
+                let reply = Self::handle_prepare(state.to_mut(), ballot);
+                self.send(id, state, src, reply, o);
+            },
+            Msg::Accept(ballot) => {
+                let reply = Self::handle_accept(state.to_mut(), ballot);
+                self.send(id, state, src, reply, o);
+            },
+            Msg::Promised { id: proposal_id, accepted, decided } => {
+                let state_mut = state.to_mut();
+                let Some(round) = state_mut.round.as_mut().filter(|round| round.id == proposal_id) else {
+                    return;
+                };
+                if state_mut.proposal.is_some() {
+                    return;
+                }
+
+                round.promise(node_id(src), accepted, decided);
+                if let Some(proposal) = round.proposal() {
+                    state_mut.proposal = Some(proposal.clone());
+                    self.broadcast(id, state, Msg::Accept(proposal), o);
+                }
+            },
+            Msg::Accepted { id: proposal_id } => {
+                let state_mut = state.to_mut();
+                let Some(proposal) = state_mut.proposal.clone() else {
+                    return;
+                };
+                let Some(round) = state_mut.round.as_mut().filter(|round| round.id == proposal_id) else {
+                    return;
+                };
+
+                round.accept(node_id(src));
+                if round.is_chosen() {
+                    state_mut.round = None;
+                    state_mut.proposal = None;
+                    o.cancel_timer(Timer::Retry);
+                    self.broadcast(id, state, Msg::Learn(proposal), o);
+                }
+            },
+            Msg::Rejected { promised, .. } => {
+                state.to_mut().proposer.observe(promised);
+            },
+            Msg::Learn(ballot) => {
+                let state = state.to_mut();
+                state.ledger = ballot.value;
+                state.acceptor.forget(ballot.instance);
+            },
+        }
+    }
+
+    fn on_timeout(&self, id: ActorId, state: &mut Cow<Self::State>, _timer: &Self::Timer, o: &mut Out<Self>) {
+        self.start_round(id, state, o);
+    }
+}
+
+/// The size of the cluster to check. The first `proposers` nodes propose
+/// distinct values, the rest only accept.
+#[derive(Clone, Debug)]
+pub struct ModelConfig {
+    pub nodes: usize,
+    pub proposers: usize,
+    /// Prepare rounds each proposer may start.
+    pub attempts: usize,
+}
+
+impl ModelConfig {
+    pub fn values(&self) -> Vec<Value> {
+        (0..self.proposers).map(|i| ((b'a' + i as u8) as char).to_string()).collect()
+    }
+
+    /// The history is the set of every value some proposer saw chosen.
+    pub fn into_model(self) -> ActorModel<PaxosNode, Self, BTreeSet<Value>> {
+        let voters: Vec<ActorId> = (0..self.nodes).map(ActorId::from).collect();
+        let values = self.values();
+
+        ActorModel::new(self.clone(), BTreeSet::new())
+            .actors((0..self.nodes).map(|i| PaxosNode {
+                voters: voters.clone(),
+                value: values.get(i).cloned(),
+                attempts: self.attempts,
+            }))
+            .init_network(Network::new_unordered_nonduplicating([]))
+            .record_msg_out(record_learn)
+            .property(Expectation::Always, "agreement", |_, state| {
+                state.history.len() <= 1
+            })
+            .property(Expectation::Always, "validity", |model, state| {
+                let values = model.cfg.values();
+                state.history.iter().all(|value| values.contains(value))
+            })
+            .property(Expectation::Sometimes, "value chosen", |_, state| {
+                !state.history.is_empty()
+            })
+    }
+}
+
+fn record_learn(_cfg: &ModelConfig, history: &BTreeSet<Value>, envelope: Envelope<&Msg>) -> Option<BTreeSet<Value>> {
+    let Msg::Learn(ballot) = envelope.msg else {
+        return None;
+    };
+
+    let mut history = history.clone();
+    history.insert(ballot.value.clone().unwrap_or_default());
+    Some(history)
+}
@@ -0,0 +1,208 @@
+//! Learns sent once to a UDP multicast group, on a LAN that has one.
+//!
+//! Batching and gossip cut how many learns a proposer sends, but it still
+//! sends at least one request per peer, or per gossip target. On a network
+//! that carries multicast, `--learn-multicast group:port` has every node
+//! join the group, and a proposer sends each batch there as one datagram
+//! that every peer in the group gets at once.
+//!
+//! Nodes say which group they joined when they `/connect` or `/ping`, so a
+//! proposer only counts on the datagram for peers that joined the same one.
+//! Everyone else, and everyone when a batch doesn't fit in a datagram or the
+//! send fails, is told over HTTP as before. A datagram can be lost without
+//! anyone noticing, which is no worse than a lost learn: the peer finds out
+//! when it next prepares that instance.
+//!
+//! Anyone on the network can send to the group, so a node only learns what
+//! comes from a peer it knows, about instances it hasn't learned, as with
+//! `/handle-learns` itself; with `--signing-key`, only what that peer
+//! signed.
+
+use std::{
+    collections::HashSet,
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{RwLock, atomic::{AtomicU64, Ordering}},
+};
+use serde::{Serialize, Deserialize};
+use tokio::net::UdpSocket;
+
+use crate::{AppState, Ballot, Id, handlers, signing};
+
+/// The most a datagram carries: what fits in one Ethernet frame, so it is
+/// never fragmented and lost a piece at a time.
+pub const MAX_DATAGRAM_BYTES: usize = 1472;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Datagram {
+    from: Id,
+    ballots: Vec<Ballot>,
+}
+
+#[derive(Debug)]
+pub struct Multicast {
+    socket: UdpSocket,
+    group: SocketAddrV4,
+    /// Peers that said they joined the same group.
+    listeners: RwLock<HashSet<Id>>,
+    sent: AtomicU64,
+}
+
+impl Multicast {
+    /// Joins `group` on `interface`, 0.0.0.0 for the one the system picks.
+    /// Must run on the runtime.
+    pub fn join(group: SocketAddrV4, interface: Ipv4Addr) -> io::Result<Self> {
+        if !group.ip().is_multicast() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a multicast address", group.ip())));
+        }
+
+        let socket = bind(group.port(), interface)?;
+        socket.join_multicast_v4(group.ip(), &interface)?;
+        // Several nodes on one host, as in a test, hear each other this way.
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_nonblocking(true)?;
+
+        let socket = UdpSocket::from_std(socket)?;
+        Ok(Self { socket, group, listeners: RwLock::new(HashSet::new()), sent: AtomicU64::new(0) })
+    }
+
+    pub fn group(&self) -> SocketAddrV4 {
+        self.group
+    }
+
+    /// Records which group `peer` said it joined, if any.
+    pub fn heard(&self, peer: Id, group: Option<&str>) {
+        let mut listeners = self.listeners.write().unwrap();
+        if group.and_then(|group| group.parse::<SocketAddrV4>().ok()) == Some(self.group) {
+            listeners.insert(peer);
+        } else {
+            listeners.remove(&peer);
+        }
+    }
+
+    pub fn listeners(&self) -> HashSet<Id> {
+        self.listeners.read().unwrap().clone()
+    }
+
+    /// Datagrams sent since the node started.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(unix)]
+fn bind(port: u16, interface: Ipv4Addr) -> io::Result<std::net::UdpSocket> {
+    use std::os::unix::io::FromRawFd;
+
+    // Every node on a host binds the group's port, so it has to be shared,
+    // which std can't ask for before binding.
+    // SAFETY: plain socket calls on a descriptor only we have, with
+    // arguments that outlive them.
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = std::net::UdpSocket::from_raw_fd(fd);
+
+        let on: libc::c_int = 1;
+        let size = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        if libc::setsockopt(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, &on as *const _ as *const libc::c_void, size) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Sent from `interface` too, not wherever the routes point.
+        let interface = libc::in_addr { s_addr: u32::from(interface).to_be() };
+        let size = std::mem::size_of::<libc::in_addr>() as libc::socklen_t;
+        if libc::setsockopt(fd, libc::IPPROTO_IP, libc::IP_MULTICAST_IF, &interface as *const _ as *const libc::c_void, size) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let addr = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: port.to_be(),
+            sin_addr: libc::in_addr { s_addr: u32::from(Ipv4Addr::UNSPECIFIED).to_be() },
+            ..std::mem::zeroed()
+        };
+        let size = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        if libc::bind(fd, &addr as *const _ as *const libc::sockaddr, size) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+}
+
+#[cfg(not(unix))]
+fn bind(port: u16, _interface: Ipv4Addr) -> io::Result<std::net::UdpSocket> {
+    std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))
+}
+
+/// Sends `batch` to the group, and returns the peers that get it from
+/// there; none if it didn't fit, or didn't go out.
+pub async fn send(state: &AppState, batch: &[Ballot]) -> HashSet<Id> {
+    let Some(multicast) = &state.multicast else {
+        return HashSet::new();
+    };
+    let listeners = multicast.listeners();
+    if listeners.is_empty() || batch.is_empty() {
+        return HashSet::new();
+    }
+
+    let mut datagram = serde_json::to_string(&Datagram { from: state.node.id, ballots: batch.to_vec() }).unwrap();
+    if let Some(sealed) = state.keys.seal_request(signing::MULTICAST_PATH, datagram.clone()) {
+        datagram = serde_json::to_string(&sealed).unwrap();
+    }
+    if datagram.len() > MAX_DATAGRAM_BYTES {
+        return HashSet::new();
+    }
+    if let Err(e) = multicast.socket.send_to(datagram.as_bytes(), SocketAddr::V4(multicast.group)).await {
+        println!("[multicast] Node {} failed to send to {}, telling its peers directly: {}", state.node.id, multicast.group, e);
+        return HashSet::new();
+    }
+
+    multicast.sent.fetch_add(1, Ordering::SeqCst);
+    listeners
+}
+
+/// The datagram in `bytes`, which has to be signed by the node it is from
+/// when this one signs too.
+fn open(state: &AppState, bytes: &[u8]) -> Option<Datagram> {
+    if state.keys.own().is_none() {
+        return serde_json::from_slice(bytes).ok();
+    }
+    let envelope = serde_json::from_slice::<signing::Envelope>(bytes).ok()?;
+    if !state.keys.check_request(&envelope, signing::MULTICAST_PATH) {
+        println!("[multicast] Node {} dropped a datagram that isn't signed by node {}, or was replayed", state.node.id, envelope.from);
+        return None;
+    }
+    serde_json::from_str::<Datagram>(&envelope.body).ok().filter(|datagram| datagram.from == envelope.from)
+}
+
+/// Learns whatever the node's peers send to the group, for as long as the
+/// node runs.
+pub async fn listen(state: AppState) {
+    let Some(multicast) = state.multicast.clone() else {
+        return;
+    };
+
+    let mut buf = vec![0; MAX_DATAGRAM_BYTES];
+    loop {
+        let len = match multicast.socket.recv_from(&mut buf).await {
+            Ok((len, _)) => len,
+            Err(e) => {
+                println!("[multicast] Node {} failed to read from {}: {}", state.node.id, multicast.group, e);
+                continue;
+            },
+        };
+        let Some(datagram) = open(&state, &buf[..len]) else {
+            continue;
+        };
+        if datagram.from == state.node.id || !state.nodes.snapshot().iter().any(|node| node.id == datagram.from) {
+            continue;
+        }
+
+        for ballot in &datagram.ballots {
+            handlers::learn(&state, ballot).await;
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Namespaces, so applications can share a cluster without sharing keys.
+//!
+//! `POST /admin/namespaces` makes a namespace, and `/ns/<namespace>/kv/<key>`
+//! reads and writes in it the way `/kv/<key>` does in the default one. Like
+//! the ACL table, namespaces are kept in the KV store under [`PREFIX`]: the
+//! settings of one at `__ns/<namespace>` and its keys under
+//! `__ns/<namespace>/`. That way they are replicated, snapshotted and backed
+//! up with everything else, and the KV API, which refuses reserved keys,
+//! can't reach them from the default namespace or from another.
+//!
+//! A namespace with a retention keeps a key only that long after its last
+//! write. Every value is stored with the time its write was proposed, so
+//! all nodes agree on which keys are too old. Reads stop returning a key as
+//! soon as it is too old. Every node also sweeps each second and proposes a
+//! [`Command::Expire`] for any namespace holding old keys, which drops them
+//! from the store for good. `GET /metrics` shows the keys and bytes each
+//! namespace holds.
+//!
+//! A namespace can also carry a script that checks or rewrites each write
+//! to it; see `script`.
+
+use std::{collections::{BTreeMap, HashMap}, time::Duration};
+use axum::{
+    http::{HeaderMap, StatusCode},
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState,
+    acl::{self, Op},
+    chunked::Upload,
+    history::Function,
+    input::{Json, Path},
+    intake,
+    kv::{self, Command, Kv},
+    schema,
+    script::{self, Op as ScriptOp},
+    status,
+};
+
+/// Where namespaces are kept in the KV store.
+pub const PREFIX: &str = "__ns/";
+
+const SWEEP_EVERY: Duration = Duration::from_secs(1);
+
+/// The settings of a namespace.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Namespace {
+    pub name: String,
+    /// How long a key is kept after its last write; forever without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_ms: Option<u64>,
+    /// Checks or rewrites each write before it is proposed; see `script`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+}
+
+impl Namespace {
+    /// Values written before this are expired at `now`, both in
+    /// microseconds since the Unix epoch.
+    pub fn horizon(&self, now: u64) -> Option<u64> {
+        self.retention_ms.map(|ms| now.saturating_sub(ms.saturating_mul(1000)))
+    }
+}
+
+/// A value as a namespace stores it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// When its write was proposed, in microseconds since the Unix epoch.
+    pub at: u64,
+    pub value: String,
+}
+
+/// The key the settings of `namespace` are kept under.
+pub fn settings_key(namespace: &str) -> String {
+    format!("{}{}", PREFIX, namespace)
+}
+
+/// The key `key` of `namespace` is kept under.
+pub fn key(namespace: &str, key: &str) -> String {
+    format!("{}{}/{}", PREFIX, namespace, key)
+}
+
+fn is_valid(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 64 && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+/// Drops what `namespace` holds from before `before`, and answers what it
+/// dropped; this is how every node applies a [`Command::Expire`].
+pub fn expire(data: &mut HashMap<String, String>, namespace: &str, before: u64) -> Vec<(String, String)> {
+    let prefix = key(namespace, "");
+    let mut expired: Vec<String> = data.iter()
+        .filter(|(key, value)| key.starts_with(&prefix) && !serde_json::from_str::<Entry>(value).is_ok_and(|entry| entry.at >= before))
+        .map(|(key, _)| key.clone())
+        .collect();
+    expired.sort_unstable();
+    expired.into_iter().filter_map(|key| data.remove_entry(&key)).collect()
+}
+
+/// Every namespace in `kv`, by name.
+pub fn namespaces(kv: &Kv) -> BTreeMap<String, Namespace> {
+    kv.data.iter()
+        .filter_map(|(key, settings)| key.strip_prefix(PREFIX).filter(|name| !name.contains('/')).map(|_| settings))
+        .filter_map(|settings| serde_json::from_str::<Namespace>(settings).ok())
+        .map(|namespace| (namespace.name.clone(), namespace))
+        .collect()
+}
+
+/// How many keys each namespace holds, and how many bytes their values
+/// take in the store.
+pub fn usage(kv: &Kv) -> BTreeMap<String, (usize, usize)> {
+    let mut usage: BTreeMap<String, (usize, usize)> = namespaces(kv).into_keys().map(|name| (name, (0, 0))).collect();
+    for (key, value) in &kv.data {
+        let Some((name, _)) = key.strip_prefix(PREFIX).and_then(|rest| rest.split_once('/')) else {
+            continue;
+        };
+        if let Some((keys, bytes)) = usage.get_mut(name) {
+            *keys += 1;
+            *bytes += value.len();
+        }
+    }
+    usage
+}
+
+async fn find(state: &AppState, namespace: &str) -> Result<Namespace, (StatusCode, String)> {
+    let kv = state.kv.lock().await;
+    kv.get(&settings_key(namespace))
+        .and_then(|settings| serde_json::from_str(settings).ok())
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Namespace {} doesn't exist!", namespace)))
+}
+
+/// What the ACL checks a key of `namespace` as: a grant on `<namespace>/`
+/// covers the whole namespace.
+fn scoped(namespace: &str, key: &str) -> String {
+    format!("{}/{}", namespace, key)
+}
+
+pub async fn get_key(State(state): State<AppState>, Path((namespace, key)): Path<(String, String)>, headers: HeaderMap) -> Response {
+    let settings = match find(&state, &namespace).await {
+        Ok(settings) => settings,
+        Err(refusal) => return refusal.into_response(),
+    };
+    if let Err(refusal) = acl::authorize(&state, &headers, Op::Read, &scoped(&namespace, &key)).await {
+        return refusal.into_response();
+    }
+
+    let horizon = settings.horizon(state.clock.wall());
+    kv::read(&state, &self::key(&namespace, &key), &key, &headers, |stored| {
+        let entry: Entry = serde_json::from_str(stored).ok()?;
+        horizon.is_none_or(|horizon| entry.at >= horizon).then_some(entry.value)
+    }).await
+}
+
+pub async fn put_key(State(state): State<AppState>, Path((namespace, key)): Path<(String, String)>, headers: HeaderMap, Upload(value): Upload) -> Response {
+    let settings = match find(&state, &namespace).await {
+        Ok(settings) => settings,
+        Err(refusal) => return refusal.into_response(),
+    };
+    let grant = match acl::authorize(&state, &headers, Op::Write, &scoped(&namespace, &key)).await {
+        Ok(grant) => grant,
+        Err(refusal) => return refusal.into_response(),
+    };
+
+    let value = match script::admit(&state, &settings, ScriptOp::Put, &key, Some(value)) {
+        Ok(value) => value.unwrap_or_default(),
+        Err(refusal) => return refusal.into_response(),
+    };
+    if let Err(refusal) = schema::admit(&state, Some(&namespace), &key, &value).await {
+        return refusal.into_response();
+    }
+
+    let key = self::key(&namespace, &key);
+    let entry = serde_json::to_string(&Entry { at: state.clock.wall(), value: value.clone() }).unwrap();
+    let command = Command::Put { key: key.clone(), value: entry };
+    status::stamped(&state, kv::write(&state, grant.as_ref(), Function::Write, key, Some(value), command).await)
+}
+
+pub async fn delete_key(State(state): State<AppState>, Path((namespace, key)): Path<(String, String)>, headers: HeaderMap) -> Response {
+    let settings = match find(&state, &namespace).await {
+        Ok(settings) => settings,
+        Err(refusal) => return refusal.into_response(),
+    };
+    let grant = match acl::authorize(&state, &headers, Op::Delete, &scoped(&namespace, &key)).await {
+        Ok(grant) => grant,
+        Err(refusal) => return refusal.into_response(),
+    };
+    if let Err(refusal) = script::admit(&state, &settings, ScriptOp::Delete, &key, None) {
+        return refusal.into_response();
+    }
+
+    let key = self::key(&namespace, &key);
+    let command = Command::Delete { key: key.clone() };
+    status::stamped(&state, kv::write(&state, grant.as_ref(), Function::Delete, key, None, command).await)
+}
+
+pub async fn get_namespaces(State(state): State<AppState>) -> Json<Vec<Namespace>> {
+    Json(namespaces(&*state.kv.lock().await).into_values().collect())
+}
+
+/// Makes a namespace, or changes the settings of one.
+pub async fn put_namespace(State(state): State<AppState>, Json(namespace): Json<Namespace>) -> (StatusCode, String) {
+    if !is_valid(&namespace.name) {
+        return (StatusCode::BAD_REQUEST, String::from("A namespace is named with up to 64 letters, digits, '-', '_' or '.'!"));
+    }
+    if let Some(Err(e)) = namespace.script.as_deref().map(script::check) {
+        return (StatusCode::BAD_REQUEST, format!("The script of namespace {} can't be used: {}!", namespace.name, e));
+    }
+
+    let key = settings_key(&namespace.name);
+    let settings = serde_json::to_string(&namespace).unwrap();
+    let command = Command::Put { key: key.clone(), value: settings.clone() };
+    let (status, body) = kv::write(&state, None, Function::Write, key, Some(settings), command).await;
+    if status.is_success() {
+        println!("[/admin/namespaces] Node {} set namespace {} to keep keys for {:?} ms", state.node.id, namespace.name, namespace.retention_ms);
+    }
+    (status, body)
+}
+
+/// Drops a namespace and every key in it.
+pub async fn delete_namespace(State(state): State<AppState>, Path(namespace): Path<String>) -> (StatusCode, String) {
+    if let Err(refusal) = find(&state, &namespace).await {
+        return refusal;
+    }
+
+    // Settings first, so the namespace takes no more writes while it empties.
+    let key = settings_key(&namespace);
+    let (status, body) = kv::write(&state, None, Function::Delete, key.clone(), None, Command::Delete { key }).await;
+    if !status.is_success() {
+        return (status, body);
+    }
+    let command = Command::Expire { namespace: namespace.clone(), before: u64::MAX };
+    let (status, body) = kv::write(&state, None, Function::Delete, self::key(&namespace, ""), None, command).await;
+    if !status.is_success() {
+        return (status, body);
+    }
+
+    println!("[/admin/namespaces] Node {} dropped namespace {}", state.node.id, namespace);
+    (StatusCode::OK, format!("Dropped namespace {}!", namespace))
+}
+
+/// Proposes to expire the keys any namespace kept past its retention at
+/// `now`, and returns in how many namespaces it found some.
+pub async fn sweep(state: &AppState, now: u64) -> usize {
+    if state.is_paused() || state.read_only().is_some() || state.disk.is_low() {
+        return 0;
+    }
+
+    let due: Vec<(String, u64)> = {
+        let kv = state.kv.lock().await;
+        namespaces(&kv).into_values().filter_map(|namespace| {
+            let before = namespace.horizon(now)?;
+            let prefix = key(&namespace.name, "");
+            let stale = kv.data.iter().any(|(key, value)| {
+                key.starts_with(&prefix) && serde_json::from_str::<Entry>(value).is_ok_and(|entry| entry.at < before)
+            });
+            stale.then_some((namespace.name, before))
+        }).collect()
+    };
+
+    for (namespace, before) in &due {
+        let command = Command::Expire { namespace: namespace.clone(), before: *before };
+        match intake::submit(state, command.encode()).await {
+            Ok(instance) => println!("[namespace] Node {} expired keys of {} at instance {}", state.node.id, namespace, instance),
+            Err(e) => println!("[namespace] Node {} failed to expire keys of {}: {}", state.node.id, namespace, e),
+        }
+    }
+    due.len()
+}
+
+/// Sweeps for expired keys as long as the node runs.
+pub async fn run(state: AppState) {
+    loop {
+        tokio::time::sleep(SWEEP_EVERY).await;
+        sweep(&state, state.clock.wall()).await;
+    }
+}
@@ -0,0 +1,355 @@
+//! An experimental Byzantine-tolerant mode, PBFT-style, beside Paxos.
+//!
+//! Paxos keeps going with f of its 2f + 1 acceptors stopped, but a single
+//! one that lies, promising two proposers or learning what was never
+//! chosen, can split it. With `--byzantine`, a node orders client commands
+//! with the three phases of PBFT instead, which hold with f of 3f + 1 nodes
+//! doing anything at all:
+//!
+//! - the primary, the lowest id in the cluster, gives a command the next
+//!   sequence number and sends it to every replica in a pre-prepare;
+//! - a replica that takes the first pre-prepare for a number from the
+//!   primary tells every replica it prepares that command;
+//! - once a quorum prepares the same command, a replica tells every one it
+//!   commits it, and a quorum of commits decides it.
+//!
+//! Any two quorums share more than f replicas, so at least one honest one,
+//! and no two honest replicas decide different commands for a number.
+//! Decided commands go into the ledger in order, through the same learn as
+//! Paxos, and are applied from there; the messages go over the node's
+//! transport. A vote is only as good as the id of its sender, so the mode
+//! belongs with `--signing-key`.
+//!
+//! It stays a demonstration. There is no view change, so a primary that
+//! stops stops the cluster, safely; messages lost are not sent again; and
+//! the Paxos endpoints are turned away while the mode is on, so no one can
+//! slip a value in through them.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Mutex, atomic::{AtomicBool, Ordering}},
+};
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::oneshot;
+
+use crate::{
+    AppState, Ballot, Id, Node, ProposalId, Value,
+    fanout, handlers, hex,
+    error::PaxosError,
+    input::Json,
+    transport::{NODE_ID_HEADER, post_json},
+};
+
+pub const PBFT_ONLY: &str = "Node orders commands with PBFT, not Paxos!";
+
+/// A command and the number the primary gave it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PrePrepare {
+    pub view: u64,
+    pub seq: u64,
+    pub value: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Prepare,
+    Commit,
+}
+
+/// A replica's vote for the command with `digest` at `seq`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Vote {
+    pub phase: Phase,
+    pub view: u64,
+    pub seq: u64,
+    pub digest: String,
+}
+
+/// What votes name a command by.
+pub fn digest(value: &Value) -> String {
+    hex::encode(&Sha256::digest(value.as_bytes()))
+}
+
+/// The most of `n` replicas that can be faulty.
+pub fn faulty(n: usize) -> usize {
+    n.saturating_sub(1) / 3
+}
+
+/// Votes that decide, out of `n`: any two sets this big share more than
+/// `faulty(n)` replicas. 2f + 1 when `n` is 3f + 1.
+pub fn quorum(n: usize) -> usize {
+    (n + faulty(n)) / 2 + 1
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    /// The digest and command of the first pre-prepare taken.
+    pre_prepared: Option<(String, Value)>,
+    votes: HashMap<(Phase, String), HashSet<Id>>,
+    prepared: bool,
+    committed: bool,
+}
+
+/// One replica's side of the protocol, without the network.
+#[derive(Debug, Default)]
+pub struct Replica {
+    pub view: u64,
+    /// The next number handed out, on the primary.
+    next_seq: u64,
+    slots: BTreeMap<u64, Slot>,
+    /// Decided, but behind a number that isn't yet.
+    decided: BTreeMap<u64, Value>,
+    /// Every number up to here went into the ledger.
+    executed: u64,
+}
+
+impl Replica {
+    /// A replica whose ledger ends at `executed`.
+    pub fn new(executed: u64) -> Self {
+        Self { next_seq: executed + 1, executed, ..Self::default() }
+    }
+
+    /// The number for the primary's next command.
+    pub fn assign(&mut self) -> u64 {
+        let seq = self.next_seq.max(self.executed + 1);
+        self.next_seq = seq + 1;
+        seq
+    }
+
+    /// Takes `message` from `from`, and answers the votes to send; only the
+    /// primary's first pre-prepare for a number is taken.
+    pub fn pre_prepare(&mut self, from: Id, primary: Id, message: &PrePrepare, n: usize) -> Result<Vec<Vote>, String> {
+        if from != primary {
+            return Err(format!("Node {} isn't the primary, node {} is!", from, primary));
+        }
+        if message.view != self.view {
+            return Err(format!("The pre-prepare is for view {}, this replica is in view {}!", message.view, self.view));
+        }
+        if message.seq <= self.executed {
+            return Err(format!("Number {} was already decided!", message.seq));
+        }
+
+        let digest = digest(&message.value);
+        let slot = self.slots.entry(message.seq).or_default();
+        match &slot.pre_prepared {
+            Some((taken, _)) if *taken != digest => return Err(format!("Number {} was already given another command!", message.seq)),
+            Some(_) => return Ok(Vec::new()),
+            None => slot.pre_prepared = Some((digest.clone(), message.value.clone())),
+        }
+
+        let mut votes = vec![Vote { phase: Phase::Prepare, view: self.view, seq: message.seq, digest }];
+        votes.extend(self.progress(message.seq, n));
+        Ok(votes)
+    }
+
+    /// Counts `vote` from `from`, and answers the votes to send because of it.
+    pub fn vote(&mut self, from: Id, vote: &Vote, n: usize) -> Vec<Vote> {
+        if vote.view != self.view || vote.seq <= self.executed {
+            return Vec::new();
+        }
+        let slot = self.slots.entry(vote.seq).or_default();
+        slot.votes.entry((vote.phase, vote.digest.clone())).or_default().insert(from);
+        self.progress(vote.seq, n).into_iter().collect()
+    }
+
+    /// Moves `seq` on as far as its votes allow.
+    fn progress(&mut self, seq: u64, n: usize) -> Option<Vote> {
+        let view = self.view;
+        let slot = self.slots.get_mut(&seq)?;
+        let (digest, value) = slot.pre_prepared.clone()?;
+        let votes = |slot: &Slot, phase| slot.votes.get(&(phase, digest.clone())).map_or(0, HashSet::len);
+
+        let mut commit = None;
+        if !slot.prepared && votes(slot, Phase::Prepare) >= quorum(n) {
+            slot.prepared = true;
+            commit = Some(Vote { phase: Phase::Commit, view, seq, digest: digest.clone() });
+        }
+        if slot.prepared && !slot.committed && votes(slot, Phase::Commit) >= quorum(n) {
+            slot.committed = true;
+            self.decided.insert(seq, value);
+        }
+        commit
+    }
+
+    /// What was decided and can go into the ledger now, in order.
+    pub fn take_ready(&mut self) -> Vec<(u64, Value)> {
+        let mut ready = Vec::new();
+        while let Some(value) = self.decided.remove(&(self.executed + 1)) {
+            self.executed += 1;
+            self.slots.remove(&self.executed);
+            ready.push((self.executed, value));
+        }
+        ready
+    }
+}
+
+/// The node's replica, once the mode is on.
+#[derive(Debug, Default)]
+pub struct Pbft {
+    enabled: AtomicBool,
+    replica: Mutex<Replica>,
+    /// Our own commands, by the number they wait on.
+    waiting: Mutex<HashMap<u64, oneshot::Sender<Value>>>,
+    /// Held while decided commands go into the ledger, so they go in order.
+    executing: tokio::sync::Mutex<()>,
+}
+
+impl Pbft {
+    /// Turns the mode on for a node whose ledger ends at `executed`.
+    pub fn enable(&self, executed: u64) {
+        *self.replica.lock().unwrap() = Replica::new(executed);
+        self.enabled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+}
+
+/// The replica commands are ordered by, in view 0.
+pub fn primary(state: &AppState) -> Node {
+    state.voters().into_iter().min_by_key(|node| node.id).expect("a node always votes")
+}
+
+/// Has `value` decided, through the primary, and answers its number.
+pub async fn propose(state: &AppState, value: Value) -> Result<u64, PaxosError> {
+    let primary = primary(state);
+    if primary.id != state.node.id {
+        let reply = post_json(state.transport.as_ref(), primary.addr, "/pbft/request", &value).await
+            .map_err(|reason| PaxosError::PeerUnreachable { id: primary.id, addr: primary.addr, reason })?;
+        if reply.is_error() {
+            return Err(PaxosError::Peer { status: reply.status, message: reply.body });
+        }
+        return Ok(serde_json::from_str(&reply.body)?);
+    }
+
+    let (message, decided) = {
+        let mut replica = state.pbft.replica.lock().unwrap();
+        let message = PrePrepare { view: replica.view, seq: replica.assign(), value: value.clone() };
+        let (tx, rx) = oneshot::channel();
+        state.pbft.waiting.lock().unwrap().insert(message.seq, tx);
+        (message, rx)
+    };
+
+    println!("[pbft] Node {} pre-prepares {:?} as number {}", state.node.id, value, message.seq);
+    fanout::post_all(state, &state.voters(), "/pbft/pre-prepare", &message).await;
+
+    match decided.await {
+        Ok(decided) if decided == value => Ok(message.seq),
+        Ok(_) => Err(PaxosError::Unavailable(format!("Number {} was decided for another command!", message.seq))),
+        Err(_) => Err(PaxosError::Unavailable(String::from("Node stopped waiting for the decision!"))),
+    }
+}
+
+fn sender(headers: &HeaderMap) -> Option<Id> {
+    headers.get(NODE_ID_HEADER).and_then(|id| id.to_str().ok()?.parse().ok())
+}
+
+/// Sends our votes to every replica, ourselves included.
+fn cast(state: &AppState, votes: Vec<Vote>) {
+    if votes.is_empty() {
+        return;
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        let voters = state.voters();
+        for vote in &votes {
+            fanout::post_all(&state, &voters, "/pbft/vote", vote).await;
+        }
+    });
+}
+
+/// Learns whatever the replica can now put into the ledger.
+async fn execute(state: &AppState) {
+    let _executing = state.pbft.executing.lock().await;
+    let (ready, view) = {
+        let mut replica = state.pbft.replica.lock().unwrap();
+        (replica.take_ready(), replica.view)
+    };
+    let primary = primary(state).id;
+
+    for (seq, value) in ready {
+        println!("[pbft] Node {} decided {:?} as number {}", state.node.id, value, seq);
+        let ballot = Ballot { instance: seq, id: ProposalId { round: view, node_id: primary }, value: Some(value.clone()) };
+        handlers::learn(state, &ballot).await;
+        if let Some(waiting) = state.pbft.waiting.lock().unwrap().remove(&seq) {
+            let _ = waiting.send(value);
+        }
+    }
+}
+
+fn refuse_disabled() -> (StatusCode, String) {
+    (StatusCode::CONFLICT, String::from("Node doesn't run PBFT!"))
+}
+
+/// A command forwarded by a replica that isn't the primary.
+pub async fn handle_request(State(state): State<AppState>, headers: HeaderMap, Json(value): Json<Value>) -> (StatusCode, String) {
+    if !state.pbft.is_enabled() {
+        return refuse_disabled();
+    }
+    if sender(&headers).is_none() {
+        return (StatusCode::BAD_REQUEST, String::from("Only a peer can forward a command!"));
+    }
+    if primary(&state).id != state.node.id {
+        return (StatusCode::CONFLICT, String::from("Node isn't the primary!"));
+    }
+
+    match propose(&state, value).await {
+        Ok(seq) => (StatusCode::OK, seq.to_string()),
+        Err(e) => e.refusal(),
+    }
+}
+
+pub async fn handle_pre_prepare(State(state): State<AppState>, headers: HeaderMap, Json(message): Json<PrePrepare>) -> (StatusCode, String) {
+    if !state.pbft.is_enabled() {
+        return refuse_disabled();
+    }
+    let Some(from) = sender(&headers) else {
+        return (StatusCode::BAD_REQUEST, String::from("Only a peer can send a pre-prepare!"));
+    };
+
+    let (primary, n) = (primary(&state).id, state.voters().len());
+    let votes = state.pbft.replica.lock().unwrap().pre_prepare(from, primary, &message, n);
+    match votes {
+        Err(e) => {
+            println!("[pbft] Node {} turned away a pre-prepare from node {}: {}", state.node.id, from, e);
+            (StatusCode::CONFLICT, e)
+        },
+        Ok(votes) => {
+            cast(&state, votes);
+            execute(&state).await;
+            (StatusCode::OK, String::new())
+        },
+    }
+}
+
+pub async fn handle_vote(State(state): State<AppState>, headers: HeaderMap, Json(vote): Json<Vote>) -> (StatusCode, String) {
+    if !state.pbft.is_enabled() {
+        return refuse_disabled();
+    }
+    let Some(from) = sender(&headers) else {
+        return (StatusCode::BAD_REQUEST, String::from("Only a peer can vote!"));
+    };
+
+    let n = state.voters().len();
+    let votes = state.pbft.replica.lock().unwrap().vote(from, &vote, n);
+    cast(&state, votes);
+    execute(&state).await;
+    (StatusCode::OK, String::new())
+}
+
+/// Turns away Paxos messages while the node runs PBFT.
+pub async fn paxos_only(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if state.pbft.is_enabled() {
+        return (StatusCode::CONFLICT, PBFT_ONLY).into_response();
+    }
+    next.run(request).await
+}
@@ -0,0 +1,256 @@
+//! A whole cluster as a plain value, for teaching and for the browser.
+//!
+//! Nodes run the same [`Acceptor`] and [`Round`] as the real ones, but their
+//! messages go into an in-memory queue that the caller delivers one at a
+//! time, in any order, or drops. Nothing here waits or does I/O, so it builds
+//! wherever the core does (see `wasm`), and any interleaving a visualizer
+//! wants to show can be produced by hand.
+//!
+//! Like `/prepare`, a proposer whose round ends up choosing someone else's
+//! value moves on to the next instance with its own. A round that too many
+//! acceptors rejected for a quorum to be left is abandoned; proposing again
+//! retries it with a higher ballot.
+
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    Ballot, Id, ProposalId, Value,
+    acceptor::Acceptor,
+    proposer::{Proposer, Round, quorum},
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Prepare { ballot: Ballot },
+    Promise { id: ProposalId, accepted: Option<Ballot>, decided: Option<Value> },
+    Accept { ballot: Ballot },
+    Accepted { id: ProposalId },
+    /// `promised` is missing when the instance was already learned with a
+    /// different value.
+    Rejected { id: ProposalId, promised: Option<ProposalId> },
+    Learn { ballot: Ballot },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub from: Id,
+    pub to: Id,
+    pub message: Message,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Outcome {
+    Chosen { node: Id, instance: u64, value: Value },
+    Rejected { node: Id, instance: u64, value: Value },
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PlaygroundNode {
+    pub id: Id,
+    pub acceptor: Acceptor,
+    pub proposer: Proposer,
+    pub ledger: BTreeMap<u64, Value>,
+    /// The value this node is trying to get chosen, and its round in flight.
+    pub value: Option<Value>,
+    pub round: Option<Round>,
+    /// Set once a quorum promised and the accepts are out.
+    pub proposal: Option<Ballot>,
+    /// Acceptors that turned down the current phase.
+    pub rejections: usize,
+}
+
+impl PlaygroundNode {
+    fn next_instance(&self) -> u64 {
+        self.ledger.keys().max().map_or(1, |instance| instance + 1)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Playground {
+    pub nodes: Vec<PlaygroundNode>,
+    pub in_flight: Vec<Envelope>,
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Playground {
+    /// A cluster of `size` nodes with ids `1..=size`.
+    pub fn new(size: usize) -> Self {
+        let nodes = (1..=size as Id).map(|id| PlaygroundNode { id, ..PlaygroundNode::default() }).collect();
+        Self { nodes, ..Self::default() }
+    }
+
+    fn node(&mut self, id: Id) -> Option<&mut PlaygroundNode> {
+        self.nodes.iter_mut().find(|node| node.id == id)
+    }
+
+    fn broadcast(&mut self, from: Id, message: Message) {
+        for to in self.nodes.iter().map(|node| node.id).collect::<Vec<_>>() {
+            self.in_flight.push(Envelope { from, to, message: message.clone() });
+        }
+    }
+
+    /// Has `node` start a round for `value` on its next free instance.
+    pub fn propose(&mut self, node: Id, value: Value) -> Result<(), String> {
+        let voters = self.nodes.len();
+        let Some(proposer) = self.node(node) else {
+            return Err(format!("There is no node {}!", node));
+        };
+        if proposer.round.is_some() {
+            return Err(format!("Node {} is already proposing {:?}", node, proposer.value));
+        }
+
+        let instance = proposer.next_instance();
+        self.start(node, instance, value, voters);
+        Ok(())
+    }
+
+    fn start(&mut self, node: Id, instance: u64, value: Value, voters: usize) {
+        let Some(proposer) = self.node(node) else {
+            return;
+        };
+
+        let id = proposer.proposer.next_proposal_id(node);
+        let round = Round::new(instance, id, value.clone(), voters);
+        let prepare = round.prepare();
+
+        proposer.value = Some(value);
+        proposer.round = Some(round);
+        proposer.proposal = None;
+        proposer.rejections = 0;
+
+        self.broadcast(node, Message::Prepare { ballot: prepare });
+    }
+
+    /// Delivers the message at `index` of [`Playground::in_flight`].
+    pub fn deliver(&mut self, index: usize) -> Option<Envelope> {
+        if index >= self.in_flight.len() {
+            return None;
+        }
+        let envelope = self.in_flight.remove(index);
+        self.handle(envelope.clone());
+        Some(envelope)
+    }
+
+    pub fn drop_message(&mut self, index: usize) -> Option<Envelope> {
+        (index < self.in_flight.len()).then(|| self.in_flight.remove(index))
+    }
+
+    /// Delivers the oldest message until none are left or `max_steps` ran
+    /// out, and returns how many were delivered.
+    pub fn run(&mut self, max_steps: usize) -> usize {
+        let mut steps = 0;
+        while steps < max_steps && self.deliver(0).is_some() {
+            steps += 1;
+        }
+        steps
+    }
+
+    /// No two nodes may have learned different values for the same instance.
+    pub fn check_agreement(&self) -> Result<(), String> {
+        let mut learned: BTreeMap<u64, (Id, &Value)> = BTreeMap::new();
+        for node in &self.nodes {
+            for (instance, value) in &node.ledger {
+                let (first, agreed) = *learned.entry(*instance).or_insert((node.id, value));
+                if agreed != value {
+                    return Err(format!("instance {}: node {} learned {:?}, node {} learned {:?}", instance, first, agreed, node.id, value));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn reply(&mut self, from: Id, to: Id, message: Message) {
+        self.in_flight.push(Envelope { from, to, message });
+    }
+
+    fn handle(&mut self, Envelope { from, to, message }: Envelope) {
+        let voters = self.nodes.len();
+        let Some(node) = self.node(to) else {
+            return;
+        };
+
+        match message {
+            Message::Prepare { ballot } => {
+                let reply = if let Some(decided) = node.ledger.get(&ballot.instance) {
+                    Message::Promise { id: ballot.id, accepted: None, decided: Some(decided.clone()) }
+                } else {
+                    match node.acceptor.prepare(&ballot) {
+                        Ok(accepted) => Message::Promise { id: ballot.id, accepted, decided: None },
+                        Err(promised) => Message::Rejected { id: ballot.id, promised: Some(promised) },
+                    }
+                };
+                self.reply(to, from, reply);
+            },
+            Message::Accept { ballot } => {
+                let reply = match node.ledger.get(&ballot.instance) {
+                    Some(decided) if Some(decided) == ballot.value.as_ref() => Message::Accepted { id: ballot.id },
+                    Some(_) => Message::Rejected { id: ballot.id, promised: None },
+                    None => match node.acceptor.accept(&ballot) {
+                        Ok(()) => Message::Accepted { id: ballot.id },
+                        Err(promised) => Message::Rejected { id: ballot.id, promised: Some(promised) },
+                    },
+                };
+                self.reply(to, from, reply);
+            },
+            Message::Learn { ballot } => {
+                node.ledger.insert(ballot.instance, ballot.value.unwrap_or_default());
+                node.acceptor.forget(ballot.instance);
+            },
+            Message::Promise { id, accepted, decided } => {
+                let Some(round) = node.round.as_mut().filter(|round| round.id == id && node.proposal.is_none()) else {
+                    return;
+                };
+                round.promise(from, accepted, decided);
+
+                if let Some(proposal) = round.proposal() {
+                    node.proposal = Some(proposal.clone());
+                    node.rejections = 0;
+                    self.broadcast(to, Message::Accept { ballot: proposal });
+                }
+            },
+            Message::Accepted { id } => {
+                let Some(round) = node.round.as_mut().filter(|round| round.id == id) else {
+                    return;
+                };
+                round.accept(from);
+                if !round.is_chosen() {
+                    return;
+                }
+
+                let (Some(ballot), Some(value)) = (node.proposal.take(), node.value.take()) else {
+                    return;
+                };
+                node.round = None;
+                let next = ballot.instance + 1;
+
+                self.broadcast(to, Message::Learn { ballot: ballot.clone() });
+                if ballot.value.as_ref() == Some(&value) {
+                    self.outcomes.push(Outcome::Chosen { node: to, instance: ballot.instance, value });
+                } else {
+                    // Our round pushed through an older value; ours goes next.
+                    self.start(to, next, value, voters);
+                }
+            },
+            Message::Rejected { id, promised } => {
+                if node.round.as_ref().is_none_or(|round| round.id != id) {
+                    return;
+                }
+                node.proposer.observe(promised);
+
+                node.rejections += 1;
+                if voters - node.rejections >= quorum(voters) {
+                    return;
+                }
+
+                let instance = node.round.take().map_or(0, |round| round.instance);
+                node.proposal = None;
+                if let Some(value) = node.value.take() {
+                    self.outcomes.push(Outcome::Rejected { node: to, instance, value });
+                }
+            },
+        }
+    }
+}
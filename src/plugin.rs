@@ -0,0 +1,191 @@
+//! State machines loaded from WebAssembly modules.
+//!
+//! With the `plugins` feature, `--state-machine <module>` hands every
+//! learned value to a WebAssembly module, which reads and writes the KV
+//! store through the node, in place of the KV commands: what the replicated
+//! commands mean can change without building the node again. Commands on
+//! reserved keys, the ACL, namespaces and schemas, stay the node's own; see
+//! `kv`.
+//!
+//! The module exports its `memory`, `alloc(len) -> ptr`, for the node to
+//! copy the value into, and `apply(ptr, len)`. It may import from `env`:
+//!
+//! - `get(key_ptr, key_len, out_ptr, out_cap) -> len` copies as much of the
+//!   key's value as fits at `out_ptr`, and answers its whole length, or -1
+//!   if the key isn't there;
+//! - `put(key_ptr, key_len, value_ptr, value_len)`;
+//! - `delete(key_ptr, key_len)`.
+//!
+//! Keys and values are UTF-8. Every node has to come to the same KV store,
+//! so the module runs deterministically: it can't import anything else, so
+//! it has no clock, randomness or I/O, NaNs are canonicalized and relaxed
+//! SIMD is off. Each value runs in an instance of its own, so nothing but
+//! the KV store carries over from one value to the next. A value gets
+//! [`FUEL`] units of fuel and [`MEMORY`] bytes of memory, which count the
+//! same on every node; one that runs out of either, traps, or writes a key
+//! or value that isn't UTF-8 changes nothing, on every node alike.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs, mem,
+    path::Path,
+};
+use wasmtime::{
+    Caller, Config, Engine, Error, Extern, Instance, InstancePre, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, TypedFunc,
+};
+
+use crate::kv::{Change, StateMachine};
+
+/// The fuel one value runs on, about an instruction a unit.
+pub const FUEL: u64 = 10_000_000;
+
+/// The memory one value runs in, in bytes.
+pub const MEMORY: usize = 64 << 20;
+
+/// What a module's host functions see.
+struct Host {
+    data: HashMap<String, String>,
+    /// What the value wrote so far, `None` for a delete, kept aside until
+    /// it has run through.
+    writes: BTreeMap<String, Option<String>>,
+    limits: StoreLimits,
+}
+
+impl Host {
+    fn get(&self, key: &str) -> Option<&str> {
+        match self.writes.get(key) {
+            Some(written) => written.as_deref(),
+            None => self.data.get(key).map(String::as_str),
+        }
+    }
+}
+
+struct Exports {
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    apply: TypedFunc<(i32, i32), ()>,
+}
+
+impl Exports {
+    fn of(instance: &Instance, store: &mut Store<Host>) -> wasmtime::Result<Self> {
+        Ok(Self {
+            memory: instance.get_memory(&mut *store, "memory").ok_or_else(|| Error::msg("the module exports no memory"))?,
+            alloc: instance.get_typed_func(&mut *store, "alloc")?,
+            apply: instance.get_typed_func(&mut *store, "apply")?,
+        })
+    }
+}
+
+fn memory(caller: &mut Caller<'_, Host>) -> wasmtime::Result<Memory> {
+    caller.get_export("memory").and_then(Extern::into_memory).ok_or_else(|| Error::msg("the module exports no memory"))
+}
+
+/// The text at `ptr` in the module's memory.
+fn read(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> wasmtime::Result<String> {
+    let memory = memory(caller)?;
+    let (ptr, len) = (ptr as u32 as usize, len as u32 as usize);
+    let bytes = memory.data(&*caller).get(ptr..ptr.saturating_add(len)).ok_or_else(|| Error::msg("out of bounds memory access"))?;
+    Ok(std::str::from_utf8(bytes)?.to_owned())
+}
+
+/// A state machine a WebAssembly module implements.
+pub struct Plugin {
+    name: String,
+    engine: Engine,
+    pre: InstancePre<Host>,
+}
+
+impl fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plugin").field("name", &self.name).finish()
+    }
+}
+
+impl Plugin {
+    /// Loads the module at `path`, in the binary or the text format.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let module = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::new(&path.display().to_string(), &module)
+    }
+
+    /// Compiles `module`, and checks that it has what a state machine needs.
+    pub fn new(name: &str, module: &[u8]) -> Result<Self, String> {
+        Self::compile(name, module).map_err(|e| format!("{}: {:#}", name, e))
+    }
+
+    fn compile(name: &str, module: &[u8]) -> wasmtime::Result<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true).cranelift_nan_canonicalization(true).wasm_relaxed_simd(false);
+        let engine = Engine::new(&config)?;
+        let module = Module::new(&engine, module)?;
+
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap("env", "get", |mut caller: Caller<'_, Host>, key: i32, key_len: i32, out: i32, cap: i32| -> wasmtime::Result<i32> {
+            let key = read(&mut caller, key, key_len)?;
+            let Some(value) = caller.data().get(&key).map(String::from) else {
+                return Ok(-1);
+            };
+            let copied = value.len().min(cap as u32 as usize);
+            memory(&mut caller)?.write(&mut caller, out as u32 as usize, &value.as_bytes()[..copied])?;
+            Ok(i32::try_from(value.len())?)
+        })?;
+        linker.func_wrap("env", "put", |mut caller: Caller<'_, Host>, key: i32, key_len: i32, value: i32, value_len: i32| -> wasmtime::Result<()> {
+            let key = read(&mut caller, key, key_len)?;
+            let value = read(&mut caller, value, value_len)?;
+            caller.data_mut().writes.insert(key, Some(value));
+            Ok(())
+        })?;
+        linker.func_wrap("env", "delete", |mut caller: Caller<'_, Host>, key: i32, key_len: i32| -> wasmtime::Result<()> {
+            let key = read(&mut caller, key, key_len)?;
+            caller.data_mut().writes.insert(key, None);
+            Ok(())
+        })?;
+
+        let plugin = Self { name: name.to_string(), pre: linker.instantiate_pre(&module)?, engine };
+        let mut store = plugin.store(HashMap::new());
+        let instance = plugin.pre.instantiate(&mut store)?;
+        Exports::of(&instance, &mut store)?;
+        Ok(plugin)
+    }
+
+    fn store(&self, data: HashMap<String, String>) -> Store<Host> {
+        let limits = StoreLimitsBuilder::new().memory_size(MEMORY).instances(1).build();
+        let mut store = Store::new(&self.engine, Host { data, writes: BTreeMap::new(), limits });
+        store.limiter(|host| &mut host.limits);
+        store.set_fuel(FUEL).expect("fuel is on");
+        store
+    }
+
+    fn run(&self, store: &mut Store<Host>, value: &str) -> wasmtime::Result<()> {
+        let instance = self.pre.instantiate(&mut *store)?;
+        let exports = Exports::of(&instance, store)?;
+        let len = i32::try_from(value.len())?;
+        let ptr = exports.alloc.call(&mut *store, len)?;
+        exports.memory.write(&mut *store, ptr as u32 as usize, value.as_bytes())?;
+        exports.apply.call(&mut *store, (ptr, len))
+    }
+}
+
+impl StateMachine for Plugin {
+    fn apply(&self, data: &mut HashMap<String, String>, value: &str) -> Vec<Change> {
+        let mut store = self.store(mem::take(data));
+        let result = self.run(&mut store, value);
+        let host = store.into_data();
+        *data = host.data;
+        if let Err(e) = result {
+            println!("[plugin] State machine {} couldn't apply a value, which changes nothing: {:#}", self.name, e);
+            return Vec::new();
+        }
+
+        host.writes.into_iter()
+            .filter_map(|(key, after)| {
+                let before = match &after {
+                    Some(value) => data.insert(key.clone(), value.clone()),
+                    None => data.remove(&key),
+                };
+                (before != after).then_some(Change { key, before, after })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,125 @@
+//! A pre-vote before phase 1, so a node coming back doesn't disrupt a
+//! proposer that is doing fine.
+//!
+//! Any node can propose, and phase 1 with a fresh ballot preempts whatever
+//! range the current proposer prepared ahead: a node rejoining after a
+//! partition would have it start over for nothing. With
+//! `--pre-vote-lease-ms` above 0, a node that would run phase 1 for a
+//! client write first asks the voters, through `POST /handle-pre-vote`,
+//! whether they'd go along. The question binds no one and changes nothing
+//! on the peer. A voter says no while it took an accept from another
+//! proposer within its lease, and names that proposer; unless a quorum says
+//! yes, the node hands the write to the proposer most of them named,
+//! through `/forward`, as a read-only node would, instead of preparing.
+//!
+//! A node that already prepared ahead skips the pre-vote, as does a write
+//! forwarded to it, so two nodes can't hand one back and forth. Peers older
+//! than [`version::PRE_VOTE`] don't know the question, and until every
+//! voter speaks it the node prepares as it always did.
+
+use std::{collections::{BTreeMap, BTreeSet}, sync::Mutex, time::Duration};
+use axum::extract::State;
+use serde::{Serialize, Deserialize};
+use tokio::time::Instant;
+
+use crate::{
+    AppState, Id, Node, Value,
+    error::PaxosError,
+    fanout,
+    input::Json,
+    quorum::Quorum,
+    transport::post_json,
+    version,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PreVoteRequest {
+    pub from: Id,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PreVoteReply {
+    pub granted: bool,
+    /// The proposer the voter heard from instead, when it says no.
+    #[serde(default)]
+    pub leader: Option<Id>,
+}
+
+/// The proposer whose accept this node took last, and when.
+#[derive(Debug, Default)]
+pub struct PreVote {
+    last: Mutex<Option<(Id, Instant)>>,
+}
+
+impl PreVote {
+    /// Records an accept taken from `proposer`.
+    pub fn heard(&self, proposer: Id) {
+        *self.last.lock().unwrap() = Some((proposer, Instant::now()));
+    }
+
+    /// The proposer this node took an accept from within `lease`, if any.
+    pub fn follows(&self, lease: Duration) -> Option<Id> {
+        self.last.lock().unwrap().filter(|(_, at)| at.elapsed() < lease).map(|(leader, _)| leader)
+    }
+
+    /// What this node answers `from` while its lease is `lease` long.
+    pub fn answer(&self, from: Id, lease: Duration) -> PreVoteReply {
+        match self.follows(lease) {
+            Some(leader) if leader != from => PreVoteReply { granted: false, leader: Some(leader) },
+            _ => PreVoteReply { granted: true, leader: None },
+        }
+    }
+}
+
+pub async fn handle_pre_vote(State(state): State<AppState>, Json(request): Json<PreVoteRequest>) -> Json<PreVoteReply> {
+    let lease = state.settings.read().unwrap().pre_vote_lease;
+    Json(state.prevote.answer(request.from, lease))
+}
+
+/// The proposer this node should leave a client write to instead of
+/// preparing, if the voters would rather it did. A node whose own accepts
+/// it took last asks no one, as it is the one the others follow.
+pub async fn leader(state: &AppState) -> Option<Node> {
+    let lease = state.settings.read().unwrap().pre_vote_lease;
+    if lease.is_zero() || state.pbft.is_enabled() || state.proposer.is_prepared() || state.prevote.follows(lease) == Some(state.node.id) {
+        return None;
+    }
+    let voters = state.voters();
+    if state.versions.common(voters.iter().map(|node| node.id).filter(|&id| id != state.node.id)) < version::PRE_VOTE {
+        return None;
+    }
+
+    let request = PreVoteRequest { from: state.node.id };
+    let responses = fanout::post_all(state, &voters, "/handle-pre-vote", &request).await;
+
+    let mut granted = BTreeSet::new();
+    let mut named: BTreeMap<Id, usize> = BTreeMap::new();
+    for (node, response) in voters.iter().zip(responses) {
+        let Some(reply) = response.ok().filter(|reply| !reply.is_error()).and_then(|reply| reply.json::<PreVoteReply>().ok()) else {
+            continue;
+        };
+        match reply.leader.filter(|_| !reply.granted) {
+            Some(leader) => *named.entry(leader).or_default() += 1,
+            None => {
+                granted.insert(node.id);
+            },
+        }
+    }
+    if Quorum::of(&voters).is_met(&granted) {
+        return None;
+    }
+
+    let (leader, _) = named.into_iter().max_by_key(|(_, votes)| *votes)?;
+    let leader = state.nodes.snapshot().iter().find(|node| node.id == leader).cloned()?;
+    println!("[pre-vote] Node {} lost the pre-vote, leaving its write to node {}", state.node.id, leader.id);
+    Some(leader)
+}
+
+/// Has `leader` propose `value`, and answers its instance.
+pub async fn forward(state: &AppState, leader: &Node, value: Value) -> Result<u64, PaxosError> {
+    match post_json(state.transport.as_ref(), leader.addr, "/forward", &value).await {
+        Ok(reply) if reply.is_error() => Err(PaxosError::Peer { status: reply.status, message: reply.body }),
+        Ok(reply) => Ok(serde_json::from_str(&reply.body)?),
+        Err(reason) => Err(PaxosError::PeerUnreachable { id: leader.id, addr: leader.addr, reason }),
+    }
+}
@@ -0,0 +1,139 @@
+//! Priority lanes, so urgent client commands don't wait behind the rest.
+//!
+//! Every proposal a node runs waits its turn at the one proposer, behind
+//! whatever load came in before it. A lock release or a membership change
+//! that others are waiting on shouldn't queue behind a burst of ordinary
+//! writes, so a client can send it with `X-Paxos-Priority: urgent`. The
+//! command then runs in the urgent lane: it takes a free proposal slot
+//! ahead of those already queued for one, see `backpressure`, and the
+//! proposer task takes it ahead of the normal commands waiting for it. A
+//! takeover, see `takeover`, always runs in the urgent lane.
+//!
+//! So that a steady stream of urgent commands can't starve the others, the
+//! proposer takes a normal command after [`URGENT_RUN`] urgent ones in a
+//! row, whenever one is waiting. The lane is the request's: it holds for
+//! everything the request proposes, and a write forwarded to another node
+//! runs in that node's normal lane.
+
+use std::{collections::VecDeque, future::Future};
+use axum::{
+    extract::Request,
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+use tokio::sync::mpsc;
+
+/// Set by a client on a write that should jump the queue.
+pub const PRIORITY_HEADER: &str = "x-paxos-priority";
+
+/// Urgent commands the proposer takes in a row while a normal one waits.
+pub const URGENT_RUN: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Lane {
+    #[default]
+    Normal,
+    Urgent,
+}
+
+tokio::task_local! {
+    static LANE: Lane;
+}
+
+/// The lane of the request being handled, normal outside of one.
+pub fn current() -> Lane {
+    LANE.try_with(|lane| *lane).unwrap_or_default()
+}
+
+/// Runs `task` in `lane`.
+pub async fn scope<F: Future>(lane: Lane, task: F) -> F::Output {
+    LANE.scope(lane, task).await
+}
+
+/// Handles a request in the lane its client asked for.
+pub async fn mark(request: Request, next: Next) -> Response {
+    let lane = match request.headers().get(PRIORITY_HEADER).map(|lane| lane.to_str()) {
+        None => Lane::Normal,
+        Some(Ok(lane)) if lane.eq_ignore_ascii_case("normal") => Lane::Normal,
+        Some(Ok(lane)) if lane.eq_ignore_ascii_case("urgent") => Lane::Urgent,
+        Some(_) => return (StatusCode::BAD_REQUEST, String::from("X-Paxos-Priority is either normal or urgent!")).into_response(),
+    };
+    scope(lane, next.run(request)).await
+}
+
+/// Where a task's commands come in, a queue per lane.
+#[derive(Debug)]
+pub struct Lanes<T> {
+    urgent: mpsc::UnboundedReceiver<T>,
+    normal: mpsc::UnboundedReceiver<T>,
+    waiting: (VecDeque<T>, VecDeque<T>),
+    /// Urgent commands taken in a row while a normal one waited.
+    run: usize,
+}
+
+/// The sending side of [`Lanes`].
+#[derive(Debug)]
+pub struct Senders<T> {
+    urgent: mpsc::UnboundedSender<T>,
+    normal: mpsc::UnboundedSender<T>,
+}
+
+impl<T> Clone for Senders<T> {
+    fn clone(&self) -> Self {
+        Self { urgent: self.urgent.clone(), normal: self.normal.clone() }
+    }
+}
+
+impl<T> Senders<T> {
+    pub fn send(&self, lane: Lane, command: T) -> Result<(), mpsc::error::SendError<T>> {
+        match lane {
+            Lane::Urgent => self.urgent.send(command),
+            Lane::Normal => self.normal.send(command),
+        }
+    }
+}
+
+pub fn lanes<T>() -> (Senders<T>, Lanes<T>) {
+    let (urgent, urgent_commands) = mpsc::unbounded_channel();
+    let (normal, normal_commands) = mpsc::unbounded_channel();
+    let lanes = Lanes { urgent: urgent_commands, normal: normal_commands, waiting: (VecDeque::new(), VecDeque::new()), run: 0 };
+    (Senders { urgent, normal }, lanes)
+}
+
+impl<T> Lanes<T> {
+    /// The next command to run, or `None` once every sender is gone.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            while let Ok(command) = self.urgent.try_recv() {
+                self.waiting.0.push_back(command);
+            }
+            while let Ok(command) = self.normal.try_recv() {
+                self.waiting.1.push_back(command);
+            }
+            if let Some(command) = self.take() {
+                return Some(command);
+            }
+
+            tokio::select! {
+                biased;
+                Some(command) = self.urgent.recv() => self.waiting.0.push_back(command),
+                Some(command) = self.normal.recv() => self.waiting.1.push_back(command),
+                else => return None,
+            }
+        }
+    }
+
+    fn take(&mut self) -> Option<T> {
+        let (urgent, normal) = &mut self.waiting;
+        if !normal.is_empty() && (urgent.is_empty() || self.run >= URGENT_RUN) {
+            self.run = 0;
+            return normal.pop_front();
+        }
+        let command = urgent.pop_front()?;
+        self.run = if normal.is_empty() { 0 } else { self.run + 1 };
+        Some(command)
+    }
+}
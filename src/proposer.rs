@@ -0,0 +1,507 @@
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(feature = "server")]
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}};
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "server")]
+use tokio::sync::oneshot;
+
+use crate::{Ballot, Id, ProposalId, Value, acceptor::RangePromise, quorum::Quorum};
+#[cfg(feature = "server")]
+use crate::{
+    AppState,
+    chain::{self, Ack, Certificate},
+    error::PaxosError,
+    events::Transition,
+    fanout,
+    handlers::{self, MAX_PREPARE_AHEAD, PrepareRangePayload},
+    priority::{self, Lane, Lanes, Senders},
+    storage::{self, Record},
+    streams::Stream,
+    takeover,
+    version,
+    wire::{Accept, Accepted, Promise},
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Proposer {
+    pub round: u64,
+    /// The range of instances phase 1 already ran for, while it holds.
+    pub prepared: Option<Prepared>,
+}
+
+impl Proposer {
+    pub fn new() -> Self {
+        Self { round: 0, prepared: None }
+    }
+
+    pub fn next_proposal_id(&mut self, node_id: u64) -> ProposalId {
+        self.round += 1;
+        ProposalId { round: self.round, node_id }
+    }
+
+    /// A NACK tells us which proposal beat us; jump past it so the next
+    /// attempt isn't refused for the same reason.
+    pub fn observe(&mut self, promised: Option<ProposalId>) {
+        if let Some(promised) = promised {
+            self.round = self.round.max(promised.round);
+        }
+    }
+}
+
+/// Rounds a proposer reserves at a time.
+#[cfg(feature = "server")]
+pub const RESERVE: u64 = 1000;
+
+/// The rounds this node's proposers may have sent ballots with. A proposer
+/// reserves them [`RESERVE`] at a time, and with a data directory the
+/// reservation is in the log before any ballot under it goes out. A node
+/// that restarts, or a proposer that does, picks up past all of them, so
+/// it never sends the same ballot twice, which an acceptor could take as
+/// the same proposal with another value. Only one ballot in so many waits
+/// for a sync.
+#[cfg(feature = "server")]
+#[derive(Debug, Default)]
+pub struct Rounds {
+    reserved: AtomicU64,
+}
+
+#[cfg(feature = "server")]
+impl Rounds {
+    pub fn reserved(&self) -> u64 {
+        self.reserved.load(Ordering::SeqCst)
+    }
+
+    /// Takes up the rounds a data directory says were reserved.
+    pub fn restore(&self, round: u64) {
+        self.reserved.fetch_max(round, Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "server")]
+impl Proposer {
+    /// A proposer past every round this node reserved.
+    fn resumed(state: &AppState) -> Self {
+        Self { round: state.rounds.reserved(), prepared: None }
+    }
+
+    /// The next ballot, reserving its round first if it isn't yet. Rounds
+    /// only count as reserved once they are on disk, so another of the
+    /// node's proposers never goes ahead on a reservation that may be lost.
+    async fn next_ballot(&mut self, state: &AppState) -> Result<ProposalId, PaxosError> {
+        let id = self.next_proposal_id(state.node.id);
+        if id.round > state.rounds.reserved() {
+            let round = id.round.saturating_add(RESERVE - 1);
+            storage::durable(state, storage::log(state, Record::Reserved { round })).await?;
+            state.rounds.restore(round);
+        }
+        Ok(id)
+    }
+
+    /// Phase 1 for `instance` out of the range prepared ahead, preparing a
+    /// new range from it first when it's past the last one. `None` leaves
+    /// the instance to a phase 1 of its own: with prepare-ahead off, a peer
+    /// too old for it, or no quorum for the range.
+    pub async fn prepare_ahead(&mut self, state: &AppState, instance: u64, value: &Value) -> Option<Ballot> {
+        let ahead = state.settings.read().unwrap().prepare_ahead.min(MAX_PREPARE_AHEAD);
+        if ahead == 0 {
+            self.prepared = None;
+            return None;
+        }
+        if !self.prepared.as_ref().is_some_and(|prepared| prepared.covers(instance)) {
+            self.prepared = self.prepare_range(state, instance, ahead).await;
+        }
+
+        let proposal = self.prepared.as_mut()?.proposal(instance, value);
+        let adopted = proposal.value.clone().unwrap_or_default();
+        let own = &adopted == value;
+        state.events.record(Transition::ValueAdopted { instance, id: proposal.id, value: adopted, own });
+        Some(proposal)
+    }
+
+    async fn prepare_range(&mut self, state: &AppState, from: u64, ahead: u64) -> Option<Prepared> {
+        let voters = state.voters();
+        if state.versions.common(voters.iter().map(|node| node.id).filter(|&id| id != state.node.id)) < version::PREPARE_AHEAD {
+            return None;
+        }
+
+        let id = self.next_ballot(state).await.ok()?;
+        let range = RangePromise { from, to: from.saturating_add(ahead), id };
+        state.events.record(Transition::PrepareSent { instance: from, id: range.id });
+        let quorum = Quorum::of(&voters);
+        let responses = fanout::post_quorum(state, &voters, &quorum, "/handle-prepare-range", &range).await;
+
+        let mut prepared = Prepared::new(range);
+        let mut promised = BTreeSet::new();
+        for (node, response) in responses {
+            let Ok(response) = response else {
+                continue;
+            };
+            let Ok(payload) = response.json::<PrepareRangePayload>() else {
+                continue;
+            };
+
+            if response.is_error() {
+                self.observe(payload.promised);
+                continue;
+            }
+
+            promised.insert(node.id);
+            prepared.promise(payload.accepted, payload.decided);
+        }
+
+        if !quorum.is_met(&promised) {
+            println!("[/prepare] Node {} got no quorum for instances {} to {}, preparing them one at a time", state.node.id, range.from, range.to - 1);
+            return None;
+        }
+        println!("[/prepare] Node {} prepared instances {} to {} ahead under {:?}", state.node.id, range.from, range.to - 1, range.id);
+        Some(prepared)
+    }
+
+    pub async fn prepare(&mut self, state: &AppState, instance: u64, value: Value) -> Result<Ballot, PaxosError> {
+        let voters = state.voters();
+
+        let id = self.next_ballot(state).await?;
+        let quorum = Quorum::of(&voters);
+        let mut round = Round::with_quorum(instance, id, value.clone(), quorum.clone());
+        let prepare = round.prepare();
+        state.events.record(Transition::PrepareSent { instance, id });
+
+        let responses = fanout::post_quorum(state, &voters, &quorum, "/handle-prepare", &prepare).await;
+
+        let mut refused = None;
+        for (node, response) in responses {
+            let Ok(response) = response else {
+                continue;
+            };
+            let Ok(payload) = response.json::<Promise>() else {
+                continue;
+            };
+
+            if response.is_error() {
+                self.observe(payload.promised);
+                refused = refused.max(payload.promised);
+                continue;
+            }
+
+            round.promise(node.id, payload.value, payload.decided);
+        }
+
+        let proposal = round.proposal().ok_or_else(|| failed("promised", refused))?;
+
+        let adopted = proposal.value.clone().unwrap_or_default();
+        let own = adopted == value;
+        state.events.record(Transition::ValueAdopted { instance, id, value: adopted, own });
+
+        Ok(proposal)
+    }
+
+    /// Phase 2, which also carries whatever decisions can go along. Answers
+    /// with the certificate of the voters that accepted.
+    pub async fn propose(&mut self, state: &AppState, propose: &Ballot) -> Result<Certificate, PaxosError> {
+        let voters = state.voters();
+        let peers = voters.iter().map(|node| node.id).filter(|&id| id != state.node.id);
+        let committed = state.learns.piggyback(state, peers);
+
+        let quorum = Quorum::of(&voters);
+        let request = Accept { ballot: propose.clone(), committed };
+        let responses = fanout::post_quorum(state, &voters, &quorum, "/handle-accept", &request).await;
+        state.learns.sent(request.committed.len());
+
+        let mut acks = Vec::new();
+        let mut refused = None;
+
+        for (node, response) in responses {
+            let Ok(response) = response else {
+                continue;
+            };
+            let Ok(payload) = response.json::<Accepted>() else {
+                continue;
+            };
+
+            if response.is_error() {
+                self.observe(payload.promised);
+                refused = refused.max(payload.promised);
+                // Someone prepared over us, so our range is only good for
+                // refusals now: prepare again with the next command.
+                self.prepared = None;
+                continue;
+            }
+
+            acks.push(Ack { from: node.id, signature: payload.ack });
+        }
+
+        if !quorum.is_met(&acks.iter().map(|ack| ack.from).collect()) {
+            self.prepared = None;
+            return Err(failed("accepted", refused));
+        }
+
+        state.events.record(Transition::QuorumReached { instance: propose.instance, id: propose.id, accepted: acks.len() });
+        Ok(Certificate {
+            instance: propose.instance,
+            id: propose.id,
+            value: chain::value_hash(propose.value.as_deref().unwrap_or_default()),
+            voters: voters.len(),
+            zones: quorum.zones(),
+            weights: quorum.weights(),
+            acks,
+        })
+    }
+}
+
+/// Why a phase got no quorum: a higher ballot, if an acceptor named one.
+#[cfg(feature = "server")]
+fn failed(phase: &'static str, refused: Option<ProposalId>) -> PaxosError {
+    refused.map_or(PaxosError::NoQuorum { phase }, PaxosError::Preempted)
+}
+
+/// What the proposer task can be asked to do.
+#[cfg(feature = "server")]
+#[derive(Debug)]
+pub enum Command {
+    /// Run Paxos until the value is chosen for some instance.
+    Propose { value: Value, reply: oneshot::Sender<Result<u64, PaxosError>> },
+    /// A bare phase 1 on the open instance, with a fresh ballot.
+    Preempt { reply: oneshot::Sender<Result<Ballot, PaxosError>> },
+    /// Both phases for an instance left undecided; see `takeover`.
+    TakeOver { instance: u64, reply: oneshot::Sender<Result<Ballot, PaxosError>> },
+    /// Forget the round, as a restarted proposer would, and pick up past
+    /// the rounds reserved.
+    Restart,
+}
+
+/// The node's way to its proposer, which runs as a task of its own and
+/// takes commands one at a time, so no lock is ever held across the network
+/// round trips of a proposal. Proposals still run one after the other, as
+/// two at once from the same node would only preempt each other, urgent
+/// ones first; see `priority`.
+///
+/// The task starts with the first command, on whatever runtime sends it.
+#[cfg(feature = "server")]
+#[derive(Clone, Debug)]
+pub struct ProposerHandle {
+    commands: Senders<Command>,
+    idle: Arc<std::sync::Mutex<Option<Lanes<Command>>>>,
+    /// The round as of the last finished command, readable without asking.
+    round: Arc<AtomicU64>,
+    /// Whether a range was prepared ahead, as of the same.
+    prepared: Arc<AtomicBool>,
+    /// The instances it proposes in; see `streams`.
+    stream: Stream,
+}
+
+#[cfg(feature = "server")]
+impl Default for ProposerHandle {
+    fn default() -> Self {
+        let (commands, idle) = priority::lanes();
+        Self {
+            commands,
+            idle: Arc::new(std::sync::Mutex::new(Some(idle))),
+            round: Arc::new(AtomicU64::new(0)),
+            prepared: Arc::new(AtomicBool::new(false)),
+            stream: Stream::default(),
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+impl ProposerHandle {
+    /// A proposer for the instances of `stream` only.
+    pub fn for_stream(stream: Stream) -> Self {
+        Self { stream, ..Self::default() }
+    }
+
+    pub fn round(&self) -> u64 {
+        self.round.load(Ordering::SeqCst)
+    }
+
+    pub fn is_prepared(&self) -> bool {
+        self.prepared.load(Ordering::SeqCst)
+    }
+
+    fn send(&self, state: &AppState, lane: Lane, command: Command) -> Result<(), PaxosError> {
+        if let Some(commands) = self.idle.lock().unwrap().take() {
+            tokio::spawn(run(state.clone(), commands, self.clone()));
+        }
+        self.commands.send(lane, command).map_err(|_| gone())
+    }
+
+    /// Has `value` chosen, in the lane of the request it came with.
+    pub async fn propose(&self, state: &AppState, value: Value) -> Result<u64, PaxosError> {
+        let (reply, result) = oneshot::channel();
+        self.send(state, priority::current(), Command::Propose { value, reply })?;
+        result.await.map_err(|_| gone())?
+    }
+
+    pub async fn preempt(&self, state: &AppState) -> Result<Ballot, PaxosError> {
+        let (reply, result) = oneshot::channel();
+        self.send(state, Lane::Normal, Command::Preempt { reply })?;
+        result.await.map_err(|_| gone())?
+    }
+
+    pub async fn take_over(&self, state: &AppState, instance: u64) -> Result<Ballot, PaxosError> {
+        let (reply, result) = oneshot::channel();
+        self.send(state, Lane::Urgent, Command::TakeOver { instance, reply })?;
+        result.await.map_err(|_| gone())?
+    }
+
+    pub fn restart(&self, state: &AppState) -> Result<(), PaxosError> {
+        self.send(state, Lane::Urgent, Command::Restart)
+    }
+}
+
+#[cfg(feature = "server")]
+fn gone() -> PaxosError {
+    PaxosError::Unavailable(String::from("Proposer is gone!"))
+}
+
+/// The proposer task: the only owner of the node's [`Proposer`].
+#[cfg(feature = "server")]
+async fn run(state: AppState, mut commands: Lanes<Command>, handle: ProposerHandle) {
+    let mut proposer = Proposer::resumed(&state);
+
+    while let Some(command) = commands.recv().await {
+        match command {
+            Command::Propose { value, reply } => {
+                let _ = reply.send(handlers::run_proposal(&state, &mut proposer, handle.stream, value).await);
+            },
+            Command::Preempt { reply } => {
+                let instance = state.next_instance();
+                let _ = reply.send(proposer.prepare(&state, instance, String::new()).await);
+            },
+            Command::TakeOver { instance, reply } => {
+                let _ = reply.send(takeover::decide(&state, &mut proposer, instance).await);
+            },
+            Command::Restart => proposer = Proposer::resumed(&state),
+        }
+        handle.round.store(proposer.round, Ordering::SeqCst);
+        handle.prepared.store(proposer.prepared.is_some(), Ordering::SeqCst);
+    }
+}
+
+/// Phase 1 run once for a range of instances, with what its quorum had
+/// accepted or learned in them. Instances are proposed from it in order,
+/// each once, so no ballot ever goes out twice with different values.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Prepared {
+    pub range: RangePromise,
+    /// The first instance not proposed from the range yet.
+    next: u64,
+    highest: BTreeMap<u64, Ballot>,
+    decided: BTreeMap<u64, Value>,
+}
+
+impl Prepared {
+    pub fn new(range: RangePromise) -> Self {
+        Self { range, next: range.from, highest: BTreeMap::new(), decided: BTreeMap::new() }
+    }
+
+    /// Whether `instance` can still be proposed from the range.
+    pub fn covers(&self, instance: u64) -> bool {
+        instance >= self.next && self.range.covers(instance)
+    }
+
+    /// Records the promise of one acceptor in the quorum.
+    pub fn promise(&mut self, accepted: Vec<Ballot>, decided: BTreeMap<u64, Value>) {
+        for ballot in accepted.into_iter().filter(|ballot| self.range.covers(ballot.instance)) {
+            let highest = self.highest.entry(ballot.instance).or_insert_with(|| ballot.clone());
+            if ballot.id > highest.id {
+                *highest = ballot;
+            }
+        }
+        self.decided.extend(decided.into_iter().filter(|(instance, _)| self.range.covers(*instance)));
+    }
+
+    /// Phase 2a for `instance`, forced as [`Round::proposal`] would be.
+    pub fn proposal(&mut self, instance: u64, value: &Value) -> Ballot {
+        self.next = instance + 1;
+        let value = self.decided.remove(&instance)
+            .or_else(|| self.highest.remove(&instance).and_then(|ballot| ballot.value))
+            .unwrap_or_else(|| value.clone());
+
+        Ballot { instance, id: self.range.id, value: Some(value) }
+    }
+}
+
+/// A majority of the voters.
+pub fn quorum(voters: usize) -> usize {
+    (voters / 2) + 1
+}
+
+/// The bookkeeping of a single prepare/accept attempt, without any I/O: the
+/// async proposer above feeds it the replies it gets over the network, the
+/// model checker feeds it simulated ones.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Round {
+    pub instance: u64,
+    pub id: ProposalId,
+    value: Value,
+    quorum: Quorum,
+    promised: BTreeSet<Id>,
+    highest: Option<Ballot>,
+    decided: Option<Value>,
+    accepted: BTreeSet<Id>,
+}
+
+impl Round {
+    pub fn new(instance: u64, id: ProposalId, value: Value, voters: usize) -> Self {
+        Self::with_quorum(instance, id, value, Quorum::Majority(voters))
+    }
+
+    pub fn with_quorum(instance: u64, id: ProposalId, value: Value, quorum: Quorum) -> Self {
+        Self {
+            instance,
+            id,
+            value,
+            quorum,
+            promised: BTreeSet::new(),
+            highest: None,
+            decided: None,
+            accepted: BTreeSet::new(),
+        }
+    }
+
+    /// Phase 1a.
+    pub fn prepare(&self) -> Ballot {
+        Ballot { instance: self.instance, id: self.id, value: None }
+    }
+
+    /// Records a promise from `from`, along with what it had accepted or
+    /// learned for the instance.
+    pub fn promise(&mut self, from: Id, accepted: Option<Ballot>, decided: Option<Value>) {
+        self.promised.insert(from);
+
+        if let Some(accepted) = accepted {
+            if self.highest.as_ref().is_none_or(|highest| accepted.id > highest.id) {
+                self.highest = Some(accepted);
+            }
+        }
+
+        if decided.is_some() {
+            self.decided = decided;
+        }
+    }
+
+    /// Phase 2a, once a quorum promised: the value is forced by whatever the
+    /// quorum already learned or accepted, and only free otherwise.
+    pub fn proposal(&self) -> Option<Ballot> {
+        if !self.quorum.is_met(&self.promised) {
+            return None;
+        }
+
+        // Someone already learned this instance, so there is nothing left to
+        // choose: push the decided value through and move on.
+        let value = self.decided.clone()
+            .or_else(|| self.highest.as_ref().and_then(|ballot| ballot.value.clone()))
+            .unwrap_or_else(|| self.value.clone());
+
+        Some(Ballot { instance: self.instance, id: self.id, value: Some(value) })
+    }
+
+    pub fn accept(&mut self, from: Id) {
+        self.accepted.insert(from);
+    }
+
+    pub fn is_chosen(&self) -> bool {
+        self.quorum.is_met(&self.accepted)
+    }
+}
@@ -0,0 +1,139 @@
+//! Which sets of voters decide.
+//!
+//! Without zones a quorum is a majority of the voters. Once voters say
+//! which zone they are in, with `--zone`, it is hierarchical instead: a
+//! majority of the voters in each of a majority of the zones, where a voter
+//! with no zone is a zone of its own. Any two such sets still share a
+//! voter, as they share a zone in which both hold a majority, so Paxos
+//! stays safe; but a whole zone can go down and the others still decide.
+//!
+//! A voter started with `--weight <n>` counts as `n` voters: a majority is
+//! then more than half the weight, of the whole cluster, or of each zone
+//! with zones. A cluster with weights but no zones is one zone as far as
+//! this goes. A voter with no zone in a zoned cluster is a zone of its own
+//! whatever it weighs.
+//!
+//! A zoned proposer also keeps its rounds close to home: it asks its own
+//! zone and a majority of just enough other zones first, and only the rest
+//! of the voters when those don't make a quorum.
+
+use std::collections::{BTreeMap, BTreeSet};
+use serde::{Serialize, Deserialize};
+
+use crate::{Id, Node};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Quorum {
+    /// A majority of this many voters.
+    Majority(usize),
+    /// A majority of the weight of each of a majority of these zones; a
+    /// voter weighs what the map says, 1 if it isn't there.
+    Zones(Vec<Vec<Id>>, BTreeMap<Id, u32>),
+}
+
+impl Quorum {
+    /// The quorum of `voters`, zoned if any of them is, and weighted if
+    /// any of them weighs more than 1.
+    pub fn of(voters: &[Node]) -> Self {
+        let weights: BTreeMap<Id, u32> = voters.iter().filter(|node| node.weight != 1).map(|node| (node.id, node.weight)).collect();
+        if voters.iter().all(|node| node.zone.is_none()) {
+            return match weights.is_empty() {
+                true => Self::Majority(voters.len()),
+                false => Self::Zones(vec![voters.iter().map(|node| node.id).collect()], weights),
+            };
+        }
+
+        let mut zoned: BTreeMap<&str, Vec<Id>> = BTreeMap::new();
+        let mut zones = Vec::new();
+        for node in voters {
+            match &node.zone {
+                Some(zone) => zoned.entry(zone.as_str()).or_default().push(node.id),
+                None => zones.push(vec![node.id]),
+            }
+        }
+        zones.splice(0..0, zoned.into_values());
+        Self::Zones(zones, weights)
+    }
+
+    /// How many voters there are.
+    pub fn voters(&self) -> usize {
+        match self {
+            Self::Majority(voters) => *voters,
+            Self::Zones(zones, _) => zones.iter().map(Vec::len).sum(),
+        }
+    }
+
+    /// The voters by zone, none for a plain majority.
+    pub fn zones(&self) -> Vec<Vec<Id>> {
+        match self {
+            Self::Majority(_) => Vec::new(),
+            Self::Zones(zones, _) => zones.clone(),
+        }
+    }
+
+    /// The voters that don't weigh 1, and what they weigh.
+    pub fn weights(&self) -> BTreeMap<Id, u32> {
+        match self {
+            Self::Majority(_) => BTreeMap::new(),
+            Self::Zones(_, weights) => weights.clone(),
+        }
+    }
+
+    fn weight(weights: &BTreeMap<Id, u32>, ids: impl IntoIterator<Item = Id>) -> u64 {
+        ids.into_iter().map(|id| u64::from(weights.get(&id).copied().unwrap_or(1))).sum()
+    }
+
+    /// Whether `ids` decide.
+    pub fn is_met(&self, ids: &BTreeSet<Id>) -> bool {
+        match self {
+            Self::Majority(voters) => ids.len() > voters / 2,
+            Self::Zones(zones, weights) => {
+                let held = zones.iter()
+                    .filter(|zone| {
+                        let ours = Self::weight(weights, zone.iter().copied().filter(|id| ids.contains(id)));
+                        ours * 2 > Self::weight(weights, zone.iter().copied())
+                    })
+                    .count();
+                held > zones.len() / 2
+            },
+        }
+    }
+
+    /// The voters `own` asks first: its whole zone, and a majority of each
+    /// of the next zones until they would make a quorum. `None` without
+    /// zones, where everyone is asked at once.
+    pub fn nearby(&self, own: Id) -> Option<BTreeSet<Id>> {
+        let Self::Zones(zones, weights) = self else {
+            return None;
+        };
+        if zones.len() < 2 {
+            return None;
+        }
+        let home = zones.iter().position(|zone| zone.contains(&own))?;
+
+        let mut ids: BTreeSet<Id> = zones[home].iter().copied().collect();
+        let others = zones.iter().enumerate().filter(|(i, _)| *i != home).map(|(_, zone)| zone);
+        for zone in others.take(zones.len() / 2) {
+            let total = Self::weight(weights, zone.iter().copied());
+            let mut taken = 0;
+            for &id in zone {
+                if taken * 2 > total {
+                    break;
+                }
+                taken += Self::weight(weights, [id]);
+                ids.insert(id);
+            }
+        }
+        Some(ids)
+    }
+}
+
+impl std::fmt::Display for Quorum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Majority(voters) => write!(f, "{} of {} voters", voters / 2 + 1, voters),
+            Self::Zones(zones, weights) if zones.len() == 1 => write!(f, "more than half of {} votes", Self::weight(weights, zones[0].iter().copied())),
+            Self::Zones(zones, _) => write!(f, "majorities in {} of {} zones", zones.len() / 2 + 1, zones.len()),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Quotas on what each client commits.
+//!
+//! With `--acl`, every write names its client, and a grant can set a
+//! [`Quota`] on it: writes and bytes per second, with a burst of a few
+//! seconds' worth. Each node keeps a token bucket per client and limit,
+//! the way `ratelimit` does per IP, and takes from it before proposing.
+//! A write over the quota gets a `429`, or with `"over": "throttle"` waits
+//! until it fits, for up to [`MAX_THROTTLE`]. The bytes counted are those
+//! of the proposed value, so a KV write and the same command sent to
+//! `POST /prepare` cost the same.
+//!
+//! Like rate limits, quotas are enforced by each node on what goes through
+//! it. `GET /admin/quotas` has what each client committed through this node
+//! since it started, and how often it was held back.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use axum::{
+    http::{HeaderMap, StatusCode},
+    extract::State,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState,
+    acl::{self, Grant},
+    input::Json,
+    ratelimit::{Bucket, Limit},
+};
+
+/// A throttled write waits this long at most, and is turned away if it
+/// would have to wait longer.
+pub const MAX_THROTTLE: Duration = Duration::from_secs(10);
+
+/// What happens to a write over its client's quota.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Over {
+    #[default]
+    Reject,
+    Throttle,
+}
+
+fn one_second() -> f64 {
+    1.0
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    /// Writes per second; 0 for no limit.
+    #[serde(default)]
+    pub ops_per_sec: f64,
+    /// Bytes of proposed values per second; 0 for no limit.
+    #[serde(default)]
+    pub bytes_per_sec: f64,
+    /// How many seconds' worth of either a client may spend at once.
+    #[serde(default = "one_second")]
+    pub burst_secs: f64,
+    #[serde(default)]
+    pub over: Over,
+}
+
+impl Quota {
+    fn limits(&self) -> [Limit; 2] {
+        [
+            Limit { rate: self.ops_per_sec, burst: self.ops_per_sec * self.burst_secs },
+            Limit { rate: self.bytes_per_sec, burst: self.bytes_per_sec * self.burst_secs },
+        ]
+    }
+}
+
+/// What a client did through this node since it started.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Writes committed.
+    pub ops: u64,
+    /// Bytes of the values those writes proposed.
+    pub bytes: u64,
+    /// Writes turned away for being over the quota.
+    pub rejected: u64,
+    /// Writes held back until they fit in it.
+    pub throttled: u64,
+}
+
+#[derive(Debug, Default)]
+struct Account {
+    /// For writes, then for bytes.
+    buckets: [Option<Bucket>; 2],
+    usage: Usage,
+}
+
+#[derive(Debug, Default)]
+pub struct Quotas {
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+impl Quotas {
+    /// Takes what a write of `bytes` costs `client` under `quota`, or says
+    /// how long until it can.
+    pub fn take(&self, client: &str, quota: &Quota, bytes: usize, now: Instant) -> Result<(), Duration> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let account = accounts.entry(client.to_string()).or_default();
+        let costs = [1.0, bytes as f64];
+
+        let mut wait = None;
+        for ((bucket, limit), cost) in account.buckets.iter_mut().zip(quota.limits()).zip(costs) {
+            if limit.is_off() {
+                continue;
+            }
+            let bucket = bucket.get_or_insert_with(|| Bucket::full(limit, now));
+            bucket.refill(limit, now);
+            wait = wait.max(bucket.wait_for(limit, cost));
+        }
+        if let Some(wait) = wait {
+            return Err(wait);
+        }
+
+        for ((bucket, limit), cost) in account.buckets.iter_mut().zip(quota.limits()).zip(costs) {
+            if let Some(bucket) = bucket.as_mut().filter(|_| !limit.is_off()) {
+                bucket.take(cost);
+            }
+        }
+        Ok(())
+    }
+
+    fn count(&self, client: &str, count: impl FnOnce(&mut Usage)) {
+        count(&mut self.accounts.lock().unwrap().entry(client.to_string()).or_default().usage);
+    }
+
+    /// Counts a write of `bytes` by `client` as committed.
+    pub fn committed(&self, client: &str, bytes: usize) {
+        self.count(client, |usage| {
+            usage.ops += 1;
+            usage.bytes += bytes as u64;
+        });
+    }
+
+    pub fn usage(&self, client: &str) -> Usage {
+        self.accounts.lock().unwrap().get(client).map_or_else(Usage::default, |account| account.usage)
+    }
+}
+
+/// Lets a write of `bytes` by the holder of `grant` through once its quota
+/// allows, or turns it away.
+pub async fn admit(state: &AppState, grant: Option<&Grant>, bytes: usize) -> Result<(), (StatusCode, String)> {
+    let Some((client, quota)) = grant.and_then(|grant| Some((&grant.client, grant.quota.as_ref()?))) else {
+        return Ok(());
+    };
+
+    let mut throttled = false;
+    loop {
+        let wait = match state.quotas.take(client, quota, bytes, Instant::now()) {
+            Ok(()) => return Ok(()),
+            Err(wait) => wait,
+        };
+        if quota.over == Over::Reject || wait > MAX_THROTTLE {
+            state.quotas.count(client, |usage| usage.rejected += 1);
+            println!("[quota] Node {} turned away a write of client {}, over its quota", state.node.id, client);
+            let secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+            return Err((StatusCode::TOO_MANY_REQUESTS, format!("Client {} is over its quota, retry in {}s!", client, secs)));
+        }
+        if !throttled {
+            state.quotas.count(client, |usage| usage.throttled += 1);
+            throttled = true;
+        }
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Counts a write `admit` let through, if it was committed.
+pub fn record<T>(state: &AppState, grant: Option<&Grant>, bytes: usize, result: &Result<u64, T>) {
+    if let (Some(grant), Ok(_)) = (grant, result) {
+        state.quotas.committed(&grant.client, bytes);
+    }
+}
+
+/// A client's quota and what it did through this node.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClientUsage {
+    pub client: String,
+    pub quota: Option<Quota>,
+    pub usage: Usage,
+}
+
+pub async fn get_quotas(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<Vec<ClientUsage>>, (StatusCode, String)> {
+    acl::check_admin(&state, &headers).await?;
+
+    let mut clients: BTreeMap<String, Option<Quota>> = BTreeMap::new();
+    for (_, grant) in acl::grants(&state).await {
+        let quota = clients.entry(grant.client).or_default();
+        *quota = quota.or(grant.quota);
+    }
+    Ok(Json(clients.into_iter().map(|(client, quota)| {
+        let usage = state.quotas.usage(&client);
+        ClientUsage { client, quota, usage }
+    }).collect()))
+}
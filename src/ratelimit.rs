@@ -0,0 +1,232 @@
+//! Token buckets in front of the endpoints clients drive consensus with.
+//!
+//! `POST /prepare` and KV writes each start a round of Paxos, so a client
+//! sending them as fast as it can would slow every other client down and
+//! keep peers busy with its rounds. Each client IP gets a bucket of its own,
+//! and the node one more for all of them together; a request takes a token
+//! from both or gets `429 Too Many Requests` with a `Retry-After`.
+//!
+//! Traffic between nodes goes through other endpoints, and isn't limited by
+//! those. A peer gone wrong can still flood acceptors with prepares and
+//! accepts, though, so each peer has a bucket for those too, kept by the id
+//! it sends (which `signing` vouches for, with keys) if it is a member's;
+//! any other id shares one bucket with messages that send none. Messages
+//! over it get a `429` NACK, which the proposer counts as no vote like any
+//! other, and `paxos_peer_throttled` in `GET /metrics` counts them for each
+//! peer.
+//!
+//! The rates are [`Settings`](crate::config::Settings), so a reload changes
+//! them; buckets keep the tokens they had.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{StatusCode, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{AppState, Id, input::Json, transport::NODE_ID_HEADER};
+
+/// Past this many clients, the ones whose buckets are full again are
+/// forgotten, then the least recently seen until half are left, so a sweep
+/// only comes every `MAX_CLIENTS / 2` new clients at most.
+const MAX_CLIENTS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limit {
+    /// Tokens per second, 0 for no limit.
+    pub rate: f64,
+    /// Most tokens a bucket holds, i.e. the longest burst let through.
+    pub burst: f64,
+}
+
+impl Limit {
+    pub fn is_off(&self) -> bool {
+        self.rate <= 0.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RateLimits {
+    pub client: Limit,
+    pub global: Limit,
+}
+
+/// A token bucket; `quota` keeps some too.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    pub(crate) fn full(limit: Limit, now: Instant) -> Self {
+        Self { tokens: limit.burst.max(1.0), last: now }
+    }
+
+    pub(crate) fn refill(&mut self, limit: Limit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst.max(1.0));
+        self.last = now;
+    }
+
+    /// How long until there is a token to take.
+    fn wait(&self, limit: Limit) -> Option<Duration> {
+        self.wait_for(limit, 1.0)
+    }
+
+    /// How long until there are `cost` tokens to take, or a full bucket if
+    /// it never holds that many.
+    pub(crate) fn wait_for(&self, limit: Limit, cost: f64) -> Option<Duration> {
+        let needed = cost.min(limit.burst.max(1.0));
+        if self.tokens >= needed {
+            return None;
+        }
+        Some(Duration::from_secs_f64((needed - self.tokens) / limit.rate))
+    }
+
+    /// Takes `cost` tokens, which may leave the bucket owing some.
+    pub(crate) fn take(&mut self, cost: f64) {
+        self.tokens -= cost;
+    }
+}
+
+/// A peer's bucket for consensus messages, and how many it had turned away.
+#[derive(Clone, Copy, Debug)]
+struct Peer {
+    bucket: Bucket,
+    throttled: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    global: Mutex<Option<Bucket>>,
+    clients: Mutex<HashMap<IpAddr, Bucket>>,
+    /// By the id peers send; messages without one share a bucket.
+    peers: Mutex<HashMap<Option<Id>, Peer>>,
+}
+
+impl RateLimiter {
+    /// Takes a token for `client`, or says how long to wait for one.
+    /// Requests with no known client only count against the global limit.
+    pub fn check(&self, limits: RateLimits, client: Option<IpAddr>, now: Instant) -> Result<(), Duration> {
+        let mut shared = self.global.lock().unwrap();
+        let mut clients = self.clients.lock().unwrap();
+
+        let global = (!limits.global.is_off()).then(|| {
+            let bucket = shared.get_or_insert_with(|| Bucket::full(limits.global, now));
+            bucket.refill(limits.global, now);
+            bucket
+        });
+
+        if clients.len() >= MAX_CLIENTS {
+            forget(&mut clients, limits.client, now);
+        }
+        let client = client.filter(|_| !limits.client.is_off()).map(|ip| {
+            let bucket = clients.entry(ip).or_insert_with(|| Bucket::full(limits.client, now));
+            bucket.refill(limits.client, now);
+            bucket
+        });
+
+        let waits = [
+            global.as_deref().and_then(|bucket| bucket.wait(limits.global)),
+            client.as_deref().and_then(|bucket| bucket.wait(limits.client)),
+        ];
+        if let Some(wait) = waits.into_iter().flatten().max() {
+            return Err(wait);
+        }
+
+        for bucket in [global, client].into_iter().flatten() {
+            bucket.take(1.0);
+        }
+        Ok(())
+    }
+
+    /// Takes a token for a consensus message from `peer`, or says how long
+    /// to wait for one.
+    pub fn check_peer(&self, limit: Limit, peer: Option<Id>, now: Instant) -> Result<(), Duration> {
+        if limit.is_off() {
+            return Ok(());
+        }
+        let mut peers = self.peers.lock().unwrap();
+        let peer = peers.entry(peer).or_insert_with(|| Peer { bucket: Bucket::full(limit, now), throttled: 0 });
+        peer.bucket.refill(limit, now);
+        if let Some(wait) = peer.bucket.wait(limit) {
+            peer.throttled += 1;
+            return Err(wait);
+        }
+        peer.bucket.take(1.0);
+        Ok(())
+    }
+
+    /// How many consensus messages each peer that sent any had turned away.
+    pub fn throttled_peers(&self) -> Vec<(Option<Id>, u64)> {
+        let mut peers: Vec<_> = self.peers.lock().unwrap().iter().map(|(&id, peer)| (id, peer.throttled)).collect();
+        peers.sort();
+        peers
+    }
+}
+
+/// Drops the clients whose buckets are full again, and if that's not half of
+/// them, the ones seen the longest ago: a new bucket starts full, so those
+/// lose the least by it.
+fn forget(clients: &mut HashMap<IpAddr, Bucket>, limit: Limit, now: Instant) {
+    clients.retain(|_, bucket| {
+        // On a copy, so `last` still says when the client was seen.
+        let mut refilled = *bucket;
+        refilled.refill(limit, now);
+        refilled.tokens < limit.burst.max(1.0)
+    });
+    let keep = MAX_CLIENTS / 2;
+    if clients.len() > keep {
+        let mut seen: Vec<_> = clients.iter().map(|(&ip, bucket)| (bucket.last, ip)).collect();
+        let (_, &mut (cutoff, _), _) = seen.select_nth_unstable_by(keep, |a, b| b.0.cmp(&a.0));
+        clients.retain(|_, bucket| bucket.last > cutoff);
+    }
+}
+
+/// Rejects requests over the limits with `429`, before they start a round.
+pub async fn limit(State(state): State<AppState>, client: Option<ConnectInfo<SocketAddr>>, request: Request, next: Next) -> Response {
+    let limits = state.settings.read().unwrap().rate_limits;
+    let ip = client.map(|ConnectInfo(addr)| addr.ip());
+
+    match state.rate_limiter.check(limits, ip, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => {
+            let client = ip.map_or_else(|| String::from("a client"), |ip| ip.to_string());
+            println!("[{}] Node {} is rate limiting {}", request.uri().path(), state.node.id, client);
+
+            let secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+            let message = format!("Too many requests, retry in {}s!", secs);
+            (StatusCode::TOO_MANY_REQUESTS, [(RETRY_AFTER, secs.to_string())], message).into_response()
+        },
+    }
+}
+
+/// Answers prepares and accepts from a peer over its limit with a `429`
+/// NACK before the acceptor sees them.
+pub async fn limit_peer(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let limit = state.settings.read().unwrap().peer_rate_limit;
+    // Ids the node doesn't know share a bucket, or anyone could make it
+    // keep one, and a metrics label, for every id there is.
+    let peer = request.headers().get(NODE_ID_HEADER).and_then(|id| id.to_str().ok()?.parse::<Id>().ok())
+        .filter(|&id| state.nodes.snapshot().iter().any(|node| node.id == id));
+
+    match state.rate_limiter.check_peer(limit, peer, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => {
+            let peer = peer.map_or_else(|| String::from("an unknown peer"), |id| format!("node {}", id));
+            println!("[{}] Node {} is throttling {}", request.uri().path(), state.node.id, peer);
+
+            let secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+            let nack = serde_json::json!({ "error": format!("Node {} is throttling {}, retry in {}s", state.node.id, peer, secs) });
+            (StatusCode::TOO_MANY_REQUESTS, [(RETRY_AFTER, secs.to_string())], Json(nack)).into_response()
+        },
+    }
+}
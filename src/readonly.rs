@@ -0,0 +1,133 @@
+//! Read-only maintenance mode.
+//!
+//! A read-only node keeps serving reads from its applied state and keeps
+//! voting, since the cluster's quorum may need it, but it stops proposing
+//! client writes: during a migration, or while its storage is degraded. It
+//! either answers them with a `503` or forwards them to a peer, which runs
+//! the proposal on its behalf through `POST /forward`.
+//!
+//! A forwarded write is never forwarded again, so two read-only nodes can't
+//! bounce one between them.
+
+use axum::{
+    http::{HeaderMap, StatusCode},
+    extract::State
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState, Node, Value,
+    admin, backpressure, disk,
+    error::PaxosError,
+    handlers::propose_value,
+    input::Json,
+    prevote,
+    shutdown,
+    transport::{NODE_ID_HEADER, post_json},
+    version,
+};
+
+pub const READ_ONLY: &str = "Node is read-only!";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadOnly {
+    /// Answer writes with a 503.
+    Reject,
+    /// Have a peer propose writes instead.
+    Forward,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ReadOnlyStatus {
+    /// `None` while the node takes writes.
+    pub mode: Option<ReadOnly>,
+}
+
+/// The 503 a read-only node rejects writes with.
+pub fn refuse() -> (StatusCode, String) {
+    (StatusCode::SERVICE_UNAVAILABLE, String::from(READ_ONLY))
+}
+
+/// Proposes `value` here, or has a peer propose it if the node forwards
+/// writes, is a learner or doesn't vote yet.
+pub async fn submit(state: &AppState, value: Value) -> Result<u64, PaxosError> {
+    if state.read_only() != Some(ReadOnly::Forward) && !state.node.learner && !state.is_syncing() {
+        return match prevote::leader(state).await {
+            Some(leader) => prevote::forward(state, &leader, value).await,
+            None => propose_value(state, value).await,
+        };
+    }
+
+    let departed = state.departed.lock().await.clone();
+    let nodes = state.nodes.snapshot();
+
+    // Peers that can't take it say so with a 503; any other error is the
+    // proposal's own and would be the same anywhere.
+    let able = |node: &&Node| !node.learner && !departed.contains(&node.id) && state.versions.of(node.id) >= version::FORWARD;
+    for node in nodes.iter().filter(able) {
+        match post_json(state.transport.as_ref(), node.addr, "/forward", &value).await {
+            Ok(reply) if reply.status == StatusCode::SERVICE_UNAVAILABLE => continue,
+            Ok(reply) if reply.is_error() => return Err(PaxosError::Peer { status: reply.status, message: reply.body }),
+            Ok(reply) => {
+                println!("[read-only] Node {} had node {} propose a write", state.node.id, node.id);
+                return Ok(serde_json::from_str(&reply.body)?);
+            },
+            Err(_) => continue,
+        }
+    }
+
+    if state.node.learner {
+        return Err(PaxosError::Unavailable(String::from("Node is a learner and no voter took the write!")));
+    }
+    if state.is_syncing() {
+        return Err(PaxosError::Unavailable(String::from("Node is taking in the cluster's state and no voter took the write!")));
+    }
+    Err(PaxosError::Unavailable(String::from("Node is read-only and no peer took the write!")))
+}
+
+/// Runs a proposal a read-only peer forwarded, and answers its instance.
+pub async fn forward(State(state): State<AppState>, headers: HeaderMap, Json(value): Json<Value>) -> (StatusCode, String) {
+    if !headers.contains_key(NODE_ID_HEADER) {
+        return (StatusCode::BAD_REQUEST, String::from("Only a peer can forward a write!"));
+    }
+    if state.is_paused() {
+        return admin::refuse_paused();
+    }
+    if state.read_only().is_some() || state.node.learner || state.is_syncing() {
+        return refuse();
+    }
+    if state.disk.is_low() {
+        return disk::refuse();
+    }
+
+    let Some(_proposal) = state.shutdown.enter() else {
+        return shutdown::refuse();
+    };
+    let limits = state.settings.read().unwrap().backpressure;
+    let Some(_slot) = state.backpressure.admit(limits).await else {
+        return backpressure::refuse();
+    };
+
+    match propose_value(&state, value).await {
+        // A 503 has the peer try another node, which is only right when
+        // this one never proposed.
+        Err(e) if e.status() == StatusCode::SERVICE_UNAVAILABLE => (StatusCode::BAD_GATEWAY, e.to_string()),
+        Err(e) => e.refusal(),
+        Ok(instance) => (StatusCode::OK, instance.to_string()),
+    }
+}
+
+pub async fn get_read_only(State(state): State<AppState>) -> (StatusCode, Json<ReadOnlyStatus>) {
+    (StatusCode::OK, Json(ReadOnlyStatus { mode: state.read_only() }))
+}
+
+pub async fn set_read_only(State(state): State<AppState>, Json(status): Json<ReadOnlyStatus>) -> (StatusCode, Json<ReadOnlyStatus>) {
+    *state.read_only.write().unwrap() = status.mode;
+
+    match status.mode {
+        Some(mode) => println!("[/admin/read-only] Node {} is read-only: {:?}", state.node.id, mode),
+        None => println!("[/admin/read-only] Node {} takes writes again", state.node.id),
+    }
+    (StatusCode::OK, Json(status))
+}
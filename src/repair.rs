@@ -0,0 +1,179 @@
+//! Repairing a node that learned another value than a quorum did.
+//!
+//! Paxos chooses one value per instance, so two nodes that learned
+//! different ones point at a bug, in this code or underneath it: a disk
+//! that lost a write, a log edited by hand. When `GET
+//! /admin/consistency-check` finds such an instance, it looks for the value
+//! a quorum of the voters learned, which is the one that was chosen, and
+//! has every node holding another take it through `POST /admin/repair`.
+//!
+//! A node only takes a repair signed by a peer whose key it has, see
+//! `signing`, and only once it asked the voters itself and a quorum of them
+//! learned that value; a node without `--signing-key` takes none from its
+//! peers. Taking one, it says so loudly, records a `repaired` event with the
+//! value it had and a rewrite in its chain (see `chain`), rebuilds its KV
+//! store from its ledger, and takes a snapshot if it has a data directory,
+//! so the bad value isn't replayed from its log on the next start.
+//!
+//! Where no quorum agrees on a value, nothing is repaired: there is no
+//! telling which one was chosen, and the check's 409 is all there is to go
+//! on.
+
+use std::collections::{BTreeMap, BTreeSet};
+use axum::{extract::{Extension, State}, http::StatusCode};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState, Ballot, Id, Value,
+    consistency::value_digest,
+    events::Transition,
+    input::Json,
+    kv::Kv,
+    quorum::Quorum,
+    replica::LogRequest,
+    signing::SignedBy,
+    storage,
+    transport::post_json,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RepairRequest {
+    pub instance: u64,
+    /// The value a quorum learned.
+    pub value: Value,
+}
+
+pub async fn handle_repair(
+    State(state): State<AppState>,
+    signer: Option<Extension<SignedBy>>,
+    Json(request): Json<RepairRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let Some(Extension(SignedBy(by))) = signer else {
+        return Err((StatusCode::FORBIDDEN, format!("Node {} only takes repairs signed by a peer whose key it has!", state.node.id)));
+    };
+    if !confirm(&state, request.instance, &request.value).await {
+        let message = format!("Node {} found no quorum that learned that value for instance {}!", state.node.id, request.instance);
+        return Err((StatusCode::CONFLICT, message));
+    }
+    overwrite(&state, request.instance, request.value, by).await;
+    Ok(StatusCode::OK)
+}
+
+/// Has every node in `digests` that learned another value for `instance`
+/// than a quorum of the voters take theirs, and answers which did.
+pub async fn conflict(state: &AppState, instance: u64, digests: &BTreeMap<Id, String>) -> Vec<Id> {
+    let voters = state.voters();
+    let quorum = Quorum::of(&voters);
+    let mut holders: BTreeMap<&String, BTreeSet<Id>> = BTreeMap::new();
+    for (id, digest) in digests.iter().filter(|(id, _)| voters.iter().any(|node| node.id == **id)) {
+        holders.entry(digest).or_default().insert(*id);
+    }
+
+    let Some((chosen, holders)) = holders.into_iter().find(|(_, ids)| quorum.is_met(ids)) else {
+        println!("[repair] Node {} found no quorum that learned the same value for instance {}, and can't repair it", state.node.id, instance);
+        return Vec::new();
+    };
+    let Some(value) = fetch(state, instance, chosen, &holders).await else {
+        println!("[repair] Node {} couldn't get the value a quorum learned for instance {}", state.node.id, instance);
+        return Vec::new();
+    };
+
+    let nodes = state.nodes.snapshot();
+    let mut repaired = Vec::new();
+    for (&id, _) in digests.iter().filter(|(_, digest)| *digest != chosen) {
+        if id == state.node.id {
+            if overwrite(state, instance, value.clone(), state.node.id).await {
+                repaired.push(id);
+            }
+            continue;
+        }
+        let Some(node) = nodes.iter().find(|node| node.id == id) else {
+            continue;
+        };
+        let request = RepairRequest { instance, value: value.clone() };
+        match post_json(state.transport.as_ref(), node.addr, "/admin/repair", &request).await {
+            Ok(reply) if !reply.is_error() => repaired.push(id),
+            _ => println!("[repair] Node {} couldn't repair instance {} on node {}", state.node.id, instance, id),
+        }
+    }
+    repaired
+}
+
+/// The value of `instance` whose digest is `chosen`, from this node or one
+/// of the `holders`.
+async fn fetch(state: &AppState, instance: u64, chosen: &str, holders: &BTreeSet<Id>) -> Option<Value> {
+    let matches = |value: &Value| value_digest(instance, value) == chosen;
+    if holders.contains(&state.node.id) {
+        return state.ledger.get(instance).filter(matches);
+    }
+
+    let request = LogRequest { from: instance };
+    for node in state.nodes.snapshot().iter().filter(|node| holders.contains(&node.id)) {
+        let Ok(reply) = post_json(state.transport.as_ref(), node.addr, "/admin/log", &request).await else {
+            continue;
+        };
+        let value = reply.json::<Vec<Ballot>>().unwrap_or_default().into_iter()
+            .find(|ballot| ballot.instance == instance)
+            .and_then(|ballot| ballot.value)
+            .filter(matches);
+        if value.is_some() {
+            return value;
+        }
+    }
+    None
+}
+
+/// Whether a quorum of the voters told this node they learned `value` for
+/// `instance`.
+async fn confirm(state: &AppState, instance: u64, value: &Value) -> bool {
+    let voters = state.voters();
+    let quorum = Quorum::of(&voters);
+    let request = LogRequest { from: instance };
+    let mut holders = BTreeSet::new();
+    for node in voters.iter().filter(|node| node.id != state.node.id) {
+        let Ok(reply) = post_json(state.transport.as_ref(), node.addr, "/admin/log", &request).await else {
+            continue;
+        };
+        let learned = reply.json::<Vec<Ballot>>().unwrap_or_default().into_iter()
+            .find(|ballot| ballot.instance == instance)
+            .and_then(|ballot| ballot.value);
+        if learned.as_ref() == Some(value) {
+            holders.insert(node.id);
+        }
+    }
+    quorum.is_met(&holders)
+}
+
+/// Replaces what this node learned in `instance` with `value` on `by`'s
+/// word, and answers whether it had learned something else.
+async fn overwrite(state: &AppState, instance: u64, value: Value, by: Id) -> bool {
+    let mut ledger = state.ledger.write().await;
+    let Some(was) = ledger.get(instance).filter(|was| *was != value) else {
+        return false;
+    };
+    println!(
+        "[repair] SAFETY VIOLATION: Node {} learned {:?} for instance {}, but a quorum learned {:?}; taking theirs",
+        state.node.id, was, instance, value,
+    );
+    ledger.overwrite(instance, value.clone(), by);
+
+    // What the bad value did to the KV store can't be undone on its own, so
+    // the KV store is rebuilt from the ledger, in instance order, as far as
+    // it was applied.
+    state.applier.caught_up().await;
+    let applied = state.applier.index();
+    let mut entries: Vec<(u64, Value)> = ledger.to_map().into_iter().filter(|(instance, _)| *instance <= applied).collect();
+    entries.sort_unstable_by_key(|(instance, _)| *instance);
+    let mut kv = Kv::with_machine(state.machine.clone());
+    for (_, value) in &entries {
+        kv.apply(value);
+    }
+    state.kv.lock().await.data = kv.data;
+    std::mem::drop(ledger);
+
+    state.events.record(Transition::Repaired { instance, value, was });
+    if let Some(Err(e)) = storage::snapshot(state).await {
+        println!("[repair] Node {} couldn't snapshot the repaired instance {}: {}", state.node.id, instance, e);
+    }
+    true
+}
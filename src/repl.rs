@@ -0,0 +1,224 @@
+//! An interactive shell on a cluster, for trying it out and for teaching.
+//!
+//! `cargo run -- repl --node <url>` reads commands one line at a time and
+//! runs them against the node through a [`Client`]: `put`, `get`, `delete`
+//! and `propose` write and read, `watch <prefix>` waits for the next
+//! changes under a prefix and picks up where the last `watch` of it left
+//! off, and `members`, `leader` and `status` show what the node knows.
+//! `node <url>` moves to another node. Lines are kept in a history file
+//! across sessions, `~/.paxos_history` unless told otherwise, and tab
+//! completes command names.
+
+use std::{collections::BTreeMap, path::Path, time::Duration};
+use rustyline::{
+    Context, Editor, Helper,
+    completion::Completer,
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+};
+
+use crate::client::Client;
+
+/// Every command, with its arguments and what it does, as `help` lists them.
+pub const COMMANDS: &[(&str, &str, &str)] = &[
+    ("put", "<key> <value>", "set a key"),
+    ("get", "<key>", "print a key's value"),
+    ("delete", "<key>", "delete a key"),
+    ("propose", "<value>", "propose a value, and print what the node answered"),
+    ("watch", "<prefix>", "wait for the next changes to keys under a prefix"),
+    ("members", "", "list the members the node knows"),
+    ("leader", "", "print the node the node takes for the leader"),
+    ("status", "", "print the node's epoch and commit index"),
+    ("node", "[<url>]", "print the node talked to, or talk to another one"),
+    ("help", "", "list the commands"),
+    ("exit", "", "leave"),
+];
+
+/// How long a `watch` waits for a change, unless the session says otherwise.
+pub const WATCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What to do after a line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Print(String),
+    Exit,
+}
+
+/// The node talked to, and where each prefix was watched up to.
+#[derive(Debug)]
+pub struct Session {
+    pub client: Client,
+    /// How long a `watch` waits for a change.
+    pub watch_timeout: Duration,
+    watched: BTreeMap<String, u64>,
+}
+
+impl Session {
+    pub fn new(client: Client) -> Self {
+        Self { client, watch_timeout: WATCH_TIMEOUT, watched: BTreeMap::new() }
+    }
+
+    /// Runs one line, and answers what to print, or why it failed.
+    pub async fn eval(&mut self, line: &str) -> Result<Step, String> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let print = |text: String| Ok(Step::Print(text));
+
+        match (command, rest) {
+            ("", _) => print(String::new()),
+            ("exit" | "quit", _) => Ok(Step::Exit),
+            ("help", _) => print(help()),
+            ("put", rest) => {
+                let Some((key, value)) = rest.split_once(char::is_whitespace) else {
+                    return Err(String::from("Usage: put <key> <value>"));
+                };
+                self.client.put(key, value.trim_start()).await.map_err(|e| e.to_string())?;
+                print(String::from("OK"))
+            },
+            ("get", key) if !key.is_empty() => match self.client.get(key).await.map_err(|e| e.to_string())? {
+                Some(value) => print(value),
+                None => print(format!("Key {} not found", key)),
+            },
+            ("delete", key) if !key.is_empty() => {
+                self.client.delete(key).await.map_err(|e| e.to_string())?;
+                print(String::from("OK"))
+            },
+            ("propose", value) if !value.is_empty() => self.client.propose(value).await.map(Step::Print).map_err(|e| e.to_string()),
+            ("watch", prefix) => {
+                let since = self.watched.get(prefix).copied();
+                let page = self.client.watch(prefix, since, self.watch_timeout).await.map_err(|e| e.to_string())?;
+                self.watched.insert(prefix.to_string(), page.index);
+                if page.events.is_empty() {
+                    return print(format!("No changes up to instance {}", page.index));
+                }
+                let changes: Vec<String> = page.events.iter()
+                    .map(|event| {
+                        let (before, after) = (&event.change.before, &event.change.after);
+                        format!("{:>6}  {}: {} -> {}", event.instance, event.change.key, shown(before.as_deref()), shown(after.as_deref()))
+                    })
+                    .collect();
+                print(changes.join("\n"))
+            },
+            ("members", _) => {
+                let state = self.client.state().await.map_err(|e| e.to_string())?;
+                let mut members = vec![format!("{:>4}  {}  (this node)", state.node.id, state.node.addr)];
+                members.extend(state.members.iter().map(|member| {
+                    let mut notes = Vec::new();
+                    if member.node.learner {
+                        notes.push(String::from("learner"));
+                    }
+                    if let Some(zone) = &member.node.zone {
+                        notes.push(format!("zone {}", zone));
+                    }
+                    if member.departed {
+                        notes.push(String::from("left"));
+                    }
+                    let notes = if notes.is_empty() { String::new() } else { format!("  ({})", notes.join(", ")) };
+                    format!("{:>4}  {}{}", member.node.id, member.node.addr, notes)
+                }));
+                print(members.join("\n"))
+            },
+            ("leader", _) => match self.client.state().await.map_err(|e| e.to_string())?.leader {
+                Some(leader) => print(format!("Node {}", leader)),
+                None => print(String::from("No leader yet")),
+            },
+            ("status", _) => {
+                let status = self.client.status().await.map_err(|e| e.to_string())?;
+                print(serde_json::to_string_pretty(&status).unwrap())
+            },
+            ("node", "") => print(self.client.node().to_string()),
+            ("node", url) => {
+                self.client = Client::new(url);
+                self.watched.clear();
+                print(format!("Talking to {}", self.client.node()))
+            },
+            (command, _) => match COMMANDS.iter().find(|(name, ..)| *name == command) {
+                Some((name, args, _)) => Err(format!("Usage: {} {}", name, args)),
+                None => Err(format!("No command {}; try help", command)),
+            },
+        }
+    }
+}
+
+fn shown(value: Option<&str>) -> &str {
+    value.unwrap_or("(none)")
+}
+
+fn help() -> String {
+    COMMANDS.iter()
+        .map(|(name, args, about)| format!("{:<24} {}", format!("{} {}", name, args), about))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The commands that start with what was typed before `pos`, if that is
+/// the first word, and where the word starts.
+pub fn complete(line: &str, pos: usize) -> (usize, Vec<String>) {
+    let typed = &line[..pos];
+    if typed.contains(char::is_whitespace) {
+        return (pos, Vec::new());
+    }
+    let names = COMMANDS.iter().map(|(name, ..)| *name).filter(|name| name.starts_with(typed)).map(str::to_string).collect();
+    (0, names)
+}
+
+/// Completes command names as the shell's line editor asks.
+struct Commands;
+
+impl Completer for Commands {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(line, pos))
+    }
+}
+
+impl Hinter for Commands {
+    type Hint = String;
+}
+
+impl Highlighter for Commands {}
+
+impl Validator for Commands {}
+
+impl Helper for Commands {}
+
+/// Reads lines and runs them until `exit` or the end of the input, keeping
+/// them in `history`.
+pub async fn run(client: Client, history: Option<&Path>) -> rustyline::Result<()> {
+    let mut editor: Editor<Commands, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(Commands));
+    if let Some(history) = history {
+        // There is none the first time.
+        let _ = editor.load_history(history);
+    }
+
+    let mut session = Session::new(client);
+    println!("Talking to {}; help lists the commands.", session.client.node());
+    loop {
+        let line = match editor.readline("paxos> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+        match session.eval(&line).await {
+            Ok(Step::Exit) => break,
+            Ok(Step::Print(text)) if text.is_empty() => {},
+            Ok(Step::Print(text)) => println!("{}", text),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    if let Some(history) = history {
+        editor.save_history(history)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Learner-only read replicas.
+//!
+//! A node started with `--learner` joins the cluster like any other, but
+//! doesn't vote: it says so in its handshake, its peers leave it out of
+//! `voters`, and it turns away prepares and accepts with a `403` should one
+//! reach it anyway. Quorums stay the size they were, and no write waits on
+//! a learner, however many of them there are.
+//!
+//! A learner still gets every learn the proposer sends. Since it never sees
+//! an accept, a decision isn't piggybacked on the next one while learners
+//! are around, and goes out as a learn instead. One it missed it fetches on
+//! its own: every second it asks a voter for the log after the last
+//! instance it has chained, through `POST /admin/log`, and learns what it
+//! gets. Its reads are those of any node answering from its applied state,
+//! so a learner may be behind by the time it takes the log to reach it.
+//!
+//! A learner proposes nothing itself: writes sent to it are forwarded to a
+//! voter as a read-only node forwards them, see `readonly`.
+
+use std::time::Duration;
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState, Ballot, ProposalId,
+    handlers, transfer,
+    input::Json,
+    transport::post_json,
+};
+
+/// The most instances one `/admin/log` answers with.
+pub const MAX_ENTRIES: u64 = 1000;
+
+pub(crate) const CATCH_UP_EVERY: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LogRequest {
+    /// The first instance wanted.
+    pub from: u64,
+}
+
+/// Turns away prepares and accepts on a learner, and on a node still taking
+/// in the cluster's state; see `transfer`.
+pub async fn votes(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if state.node.learner {
+        return (StatusCode::FORBIDDEN, format!("Node {} is a learner, it doesn't vote!", state.node.id)).into_response();
+    }
+    if state.is_syncing() {
+        return transfer::refuse(&state).into_response();
+    }
+    next.run(request).await
+}
+
+/// The instances this node learned from `from` on, as long as they follow
+/// each other, and no more than [`MAX_ENTRIES`].
+pub async fn get_log(State(state): State<AppState>, Json(request): Json<LogRequest>) -> Json<Vec<Ballot>> {
+    let from = request.from.max(1);
+    let entries = (from..from.saturating_add(MAX_ENTRIES))
+        .map_while(|instance| {
+            let value = state.ledger.get(instance)?;
+            Some(Ballot { instance, id: ProposalId::default(), value: Some(value) })
+        })
+        .collect();
+    Json(entries)
+}
+
+/// Asks the voters for what this node hasn't chained yet, and learns it
+/// from the first that has some. Answers how many values were new.
+pub async fn catch_up(state: &AppState) -> usize {
+    let request = LogRequest { from: state.ledger.chained() + 1 };
+    let departed = state.departed.lock().await.clone();
+    let nodes = state.nodes.snapshot();
+
+    for node in nodes.iter().filter(|node| !node.learner && !departed.contains(&node.id)) {
+        let entries = match post_json(state.transport.as_ref(), node.addr, "/admin/log", &request).await {
+            Ok(reply) if !reply.is_error() => reply.json::<Vec<Ballot>>().unwrap_or_default(),
+            _ => continue,
+        };
+        if entries.is_empty() {
+            continue;
+        }
+
+        let mut learned = 0;
+        for ballot in entries.iter().filter(|ballot| ballot.instance >= request.from) {
+            if handlers::learn(state, ballot).await {
+                learned += 1;
+            }
+        }
+        if learned > 0 {
+            println!("[replica] Node {} caught up on {} instances from node {}", state.node.id, learned, node.id);
+        }
+        return learned;
+    }
+    0
+}
+
+/// Keeps a learner caught up with the voters. Does nothing on a voter.
+pub async fn run(state: AppState) {
+    if !state.node.learner {
+        return;
+    }
+
+    loop {
+        // A full batch means there is likely more to fetch right away.
+        if catch_up(&state).await < MAX_ENTRIES as usize {
+            tokio::time::sleep(CATCH_UP_EVERY).await;
+        }
+    }
+}
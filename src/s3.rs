@@ -0,0 +1,206 @@
+//! Snapshots in S3-compatible object storage, for disaster recovery.
+//!
+//! A node built with the `s3` feature and started with `--s3-bucket` uploads
+//! every snapshot it takes to `<prefix>node-<id>/snapshot.json`, replacing
+//! the previous one. `paxos restore-s3` bootstraps a new replica from the
+//! snapshot some node uploaded: it keeps the learned values and the KV
+//! store but not the acceptor state, whose promises were made by the other
+//! node and aren't the new one's to keep.
+//!
+//! Requests are signed with AWS Signature Version 4, using path-style URLs
+//! so that MinIO and other stand-ins work too. Credentials come from
+//! `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` only, never the config
+//! file.
+
+use std::{path::Path, time::{SystemTime, UNIX_EPOCH}};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, Url};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    AppState, Id,
+    acceptor::Acceptor,
+    encryption::{Keyring, Purpose},
+    hex,
+    storage::{Backup, FORMAT, SnapshotFile, SnapshotMeta},
+    trace::Snapshot,
+};
+
+#[derive(Clone, Debug)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-west-1.amazonaws.com` or `http://localhost:9000`.
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    /// Put in front of every key, e.g. `prod/`.
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl S3Config {
+    /// Reads the credentials from the environment.
+    pub fn new(endpoint: String, bucket: String, region: String, prefix: String) -> Result<Self, String> {
+        let var = |name: &str| std::env::var(name).map_err(|_| format!("{} must be set to use S3", name));
+        Ok(Self { endpoint, bucket, region, prefix, access_key: var("AWS_ACCESS_KEY_ID")?, secret_key: var("AWS_SECRET_ACCESS_KEY")? })
+    }
+
+    pub fn snapshot_key(&self, node: Id) -> String {
+        format!("{}node-{}/snapshot.json", self.prefix, node)
+    }
+}
+
+#[derive(Debug)]
+pub struct Bucket {
+    config: S3Config,
+    client: Client,
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex::encode(&Sha256::digest(data))
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters and `/`.
+fn uri_encode(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// `YYYYMMDDTHHMMSSZ`, in UTC.
+pub fn amz_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Days since the epoch to a civil date, after Howard Hinnant.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+/// The `Authorization` header for a request, given its headers other than
+/// `host`, lowercased and sorted by name.
+pub fn authorization(config: &S3Config, method: &str, host: &str, path: &str, headers: &[(&str, &str)], payload_hash: &str, amz_date: &str) -> String {
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, config.region);
+
+    let mut all = vec![("host", host)];
+    all.extend_from_slice(headers);
+    all.sort();
+
+    let canonical_headers: String = all.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+    let signed_headers = all.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+    let canonical_request = format!("{}\n{}\n\n{}\n{}\n{}", method, uri_encode(path), canonical_headers, signed_headers, payload_hash);
+
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, sha256(canonical_request.as_bytes()));
+
+    let key = ["s3", "aws4_request"].iter().fold(
+        hmac(&hmac(format!("AWS4{}", config.secret_key).as_bytes(), date), &config.region),
+        |key, part| hmac(&key, part),
+    );
+    let signature = hex::encode(&hmac(&key, &string_to_sign));
+
+    format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", config.access_key, scope, signed_headers, signature)
+}
+
+impl Bucket {
+    pub fn new(config: S3Config) -> Self {
+        Self { config, client: Client::new() }
+    }
+
+    pub fn config(&self) -> &S3Config {
+        &self.config
+    }
+
+    async fn request(&self, method: Method, key: &str, body: Vec<u8>) -> Result<Vec<u8>, String> {
+        let path = format!("/{}/{}", self.config.bucket, key);
+        let url = Url::parse(&self.config.endpoint).and_then(|endpoint| endpoint.join(&uri_encode(&path))).map_err(|e| e.to_string())?;
+
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(format!("{} has no host", self.config.endpoint)),
+        };
+
+        let payload_hash = sha256(&body);
+        let date = amz_date(SystemTime::now());
+        let headers = [("x-amz-content-sha256", payload_hash.as_str()), ("x-amz-date", date.as_str())];
+        let authorization = authorization(&self.config, method.as_str(), &host, &path, &headers, &payload_hash, &date);
+
+        let res = self.client.request(method, url)
+            .header("x-amz-content-sha256", &payload_hash)
+            .header("x-amz-date", &date)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let status = res.status();
+        let bytes = res.bytes().await.map_err(|e| e.to_string())?.to_vec();
+        if !status.is_success() {
+            return Err(format!("HTTP {}: {}", status, String::from_utf8_lossy(&bytes)));
+        }
+        Ok(bytes)
+    }
+
+    pub async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), String> {
+        self.request(Method::PUT, key, body).await.map(|_| ())
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        self.request(Method::GET, key, Vec::new()).await
+    }
+
+    /// Downloads the snapshot `node` uploaded, opening it with `keys` if
+    /// it is sealed.
+    pub async fn download_snapshot(&self, node: Id, keys: &Keyring) -> Result<SnapshotFile, String> {
+        let bytes = self.get(&self.config.snapshot_key(node)).await?;
+        let opened = keys.open(Purpose::Snapshot, &bytes)?;
+        serde_json::from_slice(&opened.bytes).map_err(|e| e.to_string())
+    }
+}
+
+/// Uploads the snapshot just written to `path`.
+pub async fn upload_snapshot(state: AppState, path: &Path) {
+    let Some(bucket) = &state.s3 else {
+        return;
+    };
+
+    let key = bucket.config.snapshot_key(state.node.id);
+    let result = match tokio::fs::read(path).await {
+        Ok(body) => bucket.put(&key, body).await,
+        Err(e) => Err(e.to_string()),
+    };
+
+    match result {
+        Ok(()) => println!("[s3] Node {} uploaded its snapshot to {}/{}", state.node.id, bucket.config.bucket, key),
+        Err(e) => println!("[s3] Node {} failed to upload its snapshot: {}", state.node.id, e),
+    }
+}
+
+/// A backup to seed node `id` with another node's snapshot: everything it
+/// learned, none of what it promised.
+pub fn replica(id: Id, snapshot: SnapshotFile) -> Backup {
+    let SnapshotFile { meta, state } = snapshot;
+    let state = Snapshot { acceptor: Acceptor::default(), ..state };
+    let meta = SnapshotMeta { lsn: 0, ..meta };
+    Backup { format: FORMAT, node: id, taken: meta.taken, snapshot: Some(SnapshotFile { meta, state }), wal: Vec::new() }
+}
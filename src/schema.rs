@@ -0,0 +1,310 @@
+//! JSON Schemas values must satisfy before they are proposed.
+//!
+//! `POST /admin/schemas` registers a schema for the keys starting with a
+//! prefix, in the default namespace or in a named one. A write to such a
+//! key, through `/kv` or `/ns`, must then be a JSON document the schema
+//! accepts, or it gets a `422` without anything entering consensus. Where
+//! several prefixes match a key, the longest one's schema applies.
+//!
+//! Like namespaces, schemas are kept in the KV store, under [`PREFIX`], so
+//! they are replicated, snapshotted and backed up with the data they guard,
+//! and each write finds its schema with one lookup per prefix of its key.
+//!
+//! Only a part of JSON Schema is understood: `type`, `enum`, `const`,
+//! `properties`, `required`, `additionalProperties`, `items`, `minItems`,
+//! `maxItems`, `minLength`, `maxLength`, `minimum`, `maximum`,
+//! `exclusiveMinimum`, `exclusiveMaximum`, `allOf`, `anyOf` and `oneOf`,
+//! with annotations such as `title` or `description` ignored. A schema with
+//! any other keyword is refused when it is registered, rather than have it
+//! quietly not checked.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+};
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::{
+    AppState,
+    history::Function,
+    input::{Json, Query},
+    kv::{self, Command, Kv},
+};
+
+/// Where schemas are kept in the KV store.
+pub const PREFIX: &str = "__schema/";
+
+/// Keywords that say something about a schema but check nothing.
+const ANNOTATIONS: [&str; 8] = ["$schema", "$id", "$comment", "title", "description", "default", "examples", "deprecated"];
+
+const TYPES: [&str; 7] = ["null", "boolean", "object", "array", "number", "integer", "string"];
+
+/// A schema for the keys starting with `prefix`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Schema {
+    /// The namespace the keys are in; the default one without.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Keys starting with this; empty for every key.
+    #[serde(default)]
+    pub prefix: String,
+    pub schema: JsonValue,
+}
+
+/// Names a schema for `DELETE /admin/schemas`.
+#[derive(Deserialize, Debug, Default)]
+pub struct SchemaQuery {
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub prefix: String,
+}
+
+/// The key the schema for `prefix` in `namespace` is kept under. Namespace
+/// names have no `:`, so no two pairs share one.
+pub fn key(namespace: Option<&str>, prefix: &str) -> String {
+    format!("{}{}:{}", PREFIX, namespace.unwrap_or_default(), prefix)
+}
+
+/// The schema that applies to `key` of `namespace`, if any.
+pub fn find(kv: &Kv, namespace: Option<&str>, key: &str) -> Option<Schema> {
+    let ends = key.char_indices().map(|(at, _)| at).chain(std::iter::once(key.len()));
+    let mut prefixes: Vec<&str> = ends.map(|end| &key[..end]).collect();
+    prefixes.reverse();
+    prefixes.into_iter().find_map(|prefix| serde_json::from_str(kv.get(&self::key(namespace, prefix))?).ok())
+}
+
+/// `value`, written to `key` of `namespace`, satisfies the schema for it,
+/// if there is one.
+pub async fn admit(state: &AppState, namespace: Option<&str>, key: &str, value: &str) -> Result<(), (StatusCode, String)> {
+    let Some(schema) = find(&*state.kv.lock().await, namespace, key) else {
+        return Ok(());
+    };
+    let refuse = |reason: String| {
+        println!("[schema] Node {} refused a value for {}: {}", state.node.id, key, reason);
+        (StatusCode::UNPROCESSABLE_ENTITY, format!("The value for {} doesn't match the schema for {:?}: {}!", key, schema.prefix, reason))
+    };
+    let document: JsonValue = serde_json::from_str(value).map_err(|e| refuse(format!("it isn't JSON ({})", e)))?;
+    validate(&schema.schema, &document).map_err(refuse)
+}
+
+/// Whether `schema` only uses keywords [`validate`] understands, the way it
+/// understands them.
+pub fn check(schema: &JsonValue) -> Result<(), String> {
+    let schema = match schema {
+        JsonValue::Bool(_) => return Ok(()),
+        JsonValue::Object(schema) => schema,
+        _ => return Err(String::from("a schema is an object or a boolean")),
+    };
+
+    for (keyword, value) in schema {
+        let valid = match keyword.as_str() {
+            keyword if ANNOTATIONS.contains(&keyword) => true,
+            "type" => match value {
+                JsonValue::String(name) => TYPES.contains(&name.as_str()),
+                JsonValue::Array(names) => names.iter().all(|name| name.as_str().is_some_and(|name| TYPES.contains(&name))),
+                _ => false,
+            },
+            "enum" => value.is_array(),
+            "const" => true,
+            "required" => value.as_array().is_some_and(|names| names.iter().all(JsonValue::is_string)),
+            "properties" => match value.as_object() {
+                Some(properties) => {
+                    properties.values().try_for_each(check)?;
+                    true
+                },
+                None => false,
+            },
+            "additionalProperties" | "items" => {
+                check(value)?;
+                true
+            },
+            "allOf" | "anyOf" | "oneOf" => match value.as_array() {
+                Some(schemas) if !schemas.is_empty() => {
+                    schemas.iter().try_for_each(check)?;
+                    true
+                },
+                _ => false,
+            },
+            "minItems" | "maxItems" | "minLength" | "maxLength" => value.is_u64(),
+            "minimum" | "maximum" | "exclusiveMinimum" | "exclusiveMaximum" => value.is_number(),
+            keyword => return Err(format!("the keyword {} isn't supported", keyword)),
+        };
+        if !valid {
+            return Err(format!("{} has a value it can't have", keyword));
+        }
+    }
+    Ok(())
+}
+
+fn type_of(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Object(_) => "object",
+        JsonValue::Array(_) => "array",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+    }
+}
+
+fn is_type(value: &JsonValue, name: &str) -> bool {
+    match name {
+        "integer" => value.as_f64().is_some_and(|number| number.fract() == 0.0),
+        name => type_of(value) == name,
+    }
+}
+
+/// Whether `value` satisfies `schema`, which passed [`check`]; the first
+/// thing it doesn't satisfy, with where in `value` that is, if not.
+pub fn validate(schema: &JsonValue, value: &JsonValue) -> Result<(), String> {
+    validate_at(schema, value, "")
+}
+
+fn validate_at(schema: &JsonValue, value: &JsonValue, at: &str) -> Result<(), String> {
+    let schema = match schema {
+        JsonValue::Bool(true) => return Ok(()),
+        JsonValue::Bool(false) => return Err(format!("{} isn't allowed at all", shown(at))),
+        JsonValue::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+    let fail = |what: String| Err(format!("{} {}", shown(at), what));
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            JsonValue::Array(names) => names.iter().filter_map(JsonValue::as_str).collect(),
+            name => name.as_str().into_iter().collect(),
+        };
+        if !types.iter().any(|name| is_type(value, name)) {
+            return fail(format!("is {}, not {}", type_of(value), types.join(" or ")));
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(JsonValue::as_array) {
+        if !allowed.contains(value) {
+            return fail(String::from("isn't one of the values allowed"));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            return fail(format!("isn't {}", expected));
+        }
+    }
+
+    match value {
+        JsonValue::Object(object) => validate_object(schema, object, at)?,
+        JsonValue::Array(items) => {
+            if let Some(min) = schema.get("minItems").and_then(JsonValue::as_u64).filter(|&min| (items.len() as u64) < min) {
+                return fail(format!("has fewer than {} items", min));
+            }
+            if let Some(max) = schema.get("maxItems").and_then(JsonValue::as_u64).filter(|&max| items.len() as u64 > max) {
+                return fail(format!("has more than {} items", max));
+            }
+            if let Some(item) = schema.get("items") {
+                for (index, value) in items.iter().enumerate() {
+                    validate_at(item, value, &format!("{}/{}", at, index))?;
+                }
+            }
+        },
+        JsonValue::String(string) => {
+            let length = string.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(JsonValue::as_u64).filter(|&min| length < min) {
+                return fail(format!("is shorter than {} characters", min));
+            }
+            if let Some(max) = schema.get("maxLength").and_then(JsonValue::as_u64).filter(|&max| length > max) {
+                return fail(format!("is longer than {} characters", max));
+            }
+        },
+        JsonValue::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            let bound = |keyword: &str| schema.get(keyword).and_then(JsonValue::as_f64);
+            if bound("minimum").is_some_and(|min| number < min) || bound("exclusiveMinimum").is_some_and(|min| number <= min) {
+                return fail(String::from("is too small"));
+            }
+            if bound("maximum").is_some_and(|max| number > max) || bound("exclusiveMaximum").is_some_and(|max| number >= max) {
+                return fail(String::from("is too large"));
+            }
+        },
+        _ => {},
+    }
+
+    if let Some(schemas) = schema.get("allOf").and_then(JsonValue::as_array) {
+        schemas.iter().try_for_each(|schema| validate_at(schema, value, at))?;
+    }
+    if let Some(schemas) = schema.get("anyOf").and_then(JsonValue::as_array) {
+        if !schemas.iter().any(|schema| validate_at(schema, value, at).is_ok()) {
+            return fail(String::from("matches none of anyOf"));
+        }
+    }
+    if let Some(schemas) = schema.get("oneOf").and_then(JsonValue::as_array) {
+        let matches = schemas.iter().filter(|schema| validate_at(schema, value, at).is_ok()).count();
+        if matches != 1 {
+            return fail(format!("matches {} of oneOf rather than one", matches));
+        }
+    }
+    Ok(())
+}
+
+fn validate_object(schema: &Map<String, JsonValue>, object: &Map<String, JsonValue>, at: &str) -> Result<(), String> {
+    for name in schema.get("required").and_then(JsonValue::as_array).into_iter().flatten().filter_map(JsonValue::as_str) {
+        if !object.contains_key(name) {
+            return Err(format!("{} lacks {}", shown(at), name));
+        }
+    }
+    let properties = schema.get("properties").and_then(JsonValue::as_object);
+    for (name, value) in object {
+        let at = format!("{}/{}", at, name);
+        match (properties.and_then(|properties| properties.get(name)), schema.get("additionalProperties")) {
+            (Some(property), _) => validate_at(property, value, &at)?,
+            (None, Some(additional)) => validate_at(additional, value, &at)?,
+            (None, None) => {},
+        }
+    }
+    Ok(())
+}
+
+/// Where in a value `at` points, for a message.
+fn shown(at: &str) -> String {
+    match at {
+        "" => String::from("the value"),
+        at => at.to_string(),
+    }
+}
+
+pub async fn get_schemas(State(state): State<AppState>) -> Json<Vec<Schema>> {
+    let kv = state.kv.lock().await;
+    let mut schemas: Vec<Schema> = kv.data.iter()
+        .filter(|(key, _)| key.starts_with(PREFIX))
+        .filter_map(|(_, schema)| serde_json::from_str(schema).ok())
+        .collect();
+    schemas.sort_by(|a, b| (&a.namespace, &a.prefix).cmp(&(&b.namespace, &b.prefix)));
+    Json(schemas)
+}
+
+/// Registers a schema, or replaces the one for the same prefix.
+pub async fn put_schema(State(state): State<AppState>, Json(schema): Json<Schema>) -> (StatusCode, String) {
+    if let Err(e) = check(&schema.schema) {
+        return (StatusCode::BAD_REQUEST, format!("The schema can't be used: {}!", e));
+    }
+
+    let key = key(schema.namespace.as_deref(), &schema.prefix);
+    let value = serde_json::to_string(&schema).unwrap();
+    let command = Command::Put { key: key.clone(), value: value.clone() };
+    let (status, body) = kv::write(&state, None, Function::Write, key, Some(value), command).await;
+    if status.is_success() {
+        println!("[/admin/schemas] Node {} set a schema for {:?} in namespace {:?}", state.node.id, schema.prefix, schema.namespace);
+    }
+    (status, body)
+}
+
+pub async fn delete_schema(State(state): State<AppState>, Query(query): Query<SchemaQuery>) -> (StatusCode, String) {
+    let key = key(query.namespace.as_deref(), &query.prefix);
+    if state.kv.lock().await.get(&key).is_none() {
+        return (StatusCode::NOT_FOUND, format!("No schema is set for {:?}!", query.prefix));
+    }
+
+    let (status, body) = kv::write(&state, None, Function::Delete, key.clone(), None, Command::Delete { key }).await;
+    if status.is_success() {
+        println!("[/admin/schemas] Node {} dropped the schema for {:?} in namespace {:?}", state.node.id, query.prefix, query.namespace);
+    }
+    (status, body)
+}
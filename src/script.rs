@@ -0,0 +1,117 @@
+//! Scripts a namespace runs on each write before it is proposed.
+//!
+//! Built with the `scripting` feature, a namespace can carry a [Rhai]
+//! script in its settings, which every `PUT` and `DELETE` to one of its
+//! keys runs through, on the node the client sent it to, before anything
+//! enters consensus. The script sees `op`, `"put"` or `"delete"`, `key`,
+//! without the namespace, and `value`, `()` for a delete. What it answers
+//! decides what happens to the write:
+//!
+//! - `()` or `true` lets it through as it is;
+//! - a string is the value to write instead, for a put;
+//! - `false` or a `throw` turns it away with a `422`, the thrown message
+//!   saying why.
+//!
+//! That is enough for light policies, such as refusing keys that don't
+//! follow a naming scheme, or trimming and lowercasing values, without a
+//! proxy in front of the cluster. The script runs before the namespace's
+//! schema, which checks the rewritten value; see `schema`. A script gets
+//! [`OPERATIONS`] operations at most, so one that loops forever turns the
+//! write away rather than hold it up. It runs where the write is made, not
+//! where it is applied, so it may look at the clock or anything else that
+//! differs between nodes.
+//!
+//! [Rhai]: https://rhai.rs
+
+use axum::http::StatusCode;
+
+use crate::{AppState, namespace::Namespace};
+
+/// Operations a script may run for one write.
+pub const OPERATIONS: u64 = 100_000;
+
+/// What a write is, for a script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Put,
+    Delete,
+}
+
+impl Op {
+    fn name(self) -> &'static str {
+        match self {
+            Op::Put => "put",
+            Op::Delete => "delete",
+        }
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn engine() -> rhai::Engine {
+    let mut engine = rhai::Engine::new();
+    engine.set_max_operations(OPERATIONS);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(1 << 20);
+    engine
+}
+
+/// Whether `script` compiles.
+#[cfg(feature = "scripting")]
+pub fn check(script: &str) -> Result<(), String> {
+    engine().compile(script).map(|_| ()).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn check(_script: &str) -> Result<(), String> {
+    Err(String::from("this node was built without the scripting feature"))
+}
+
+/// Runs `script` on a write, and answers the value to write instead, if
+/// any, or why the script turned the write away.
+#[cfg(feature = "scripting")]
+pub fn run(script: &str, op: Op, key: &str, value: Option<&str>) -> Result<Option<String>, String> {
+    use rhai::{Dynamic, EvalAltResult, Scope};
+
+    let engine = engine();
+    let ast = engine.compile(script).map_err(|e| e.to_string())?;
+    let mut scope = Scope::new();
+    scope.push_constant("op", op.name().to_string());
+    scope.push_constant("key", key.to_string());
+    scope.push_constant("value", value.map_or(Dynamic::UNIT, |value| Dynamic::from(value.to_string())));
+
+    let answer: Dynamic = engine.eval_ast_with_scope(&mut scope, &ast).map_err(|e| match *e {
+        EvalAltResult::ErrorRuntime(reason, _) => reason.to_string(),
+        e => e.to_string(),
+    })?;
+    if answer.is_unit() || answer.as_bool() == Ok(true) {
+        Ok(None)
+    } else if answer.as_bool() == Ok(false) {
+        Err(String::from("the script refused it"))
+    } else if answer.is_string() && op == Op::Put {
+        Ok(answer.into_string().ok())
+    } else {
+        Err(format!("the script answered a {}, not ()", answer.type_name()))
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn run(script: &str, _op: Op, _key: &str, _value: Option<&str>) -> Result<Option<String>, String> {
+    check(script).map(|_| None)
+}
+
+/// Runs the script of `namespace`, if it has one, on a write to `key`, and
+/// answers the value to write: `value`, or what the script made of it.
+pub fn admit(state: &AppState, namespace: &Namespace, op: Op, key: &str, value: Option<String>) -> Result<Option<String>, (StatusCode, String)> {
+    let Some(script) = &namespace.script else {
+        return Ok(value);
+    };
+    match run(script, op, key, value.as_deref()) {
+        Ok(None) => Ok(value),
+        Ok(Some(rewritten)) => Ok(Some(rewritten)),
+        Err(reason) => {
+            println!("[script] Node {} refused a {} of {} in namespace {}: {}", state.node.id, op.name(), key, namespace.name, reason);
+            Err((StatusCode::UNPROCESSABLE_ENTITY, format!("The script of namespace {} refused the {} of {}: {}!", namespace.name, op.name(), key, reason)))
+        },
+    }
+}
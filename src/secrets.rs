@@ -0,0 +1,170 @@
+//! A cluster token, so only nodes that know it are taken for peers.
+//!
+//! With `--cluster-token <file>`, a node sends the first token in that file
+//! in [`TOKEN_HEADER`] with everything it sends its peers, and takes what
+//! peers send (consensus messages, pings, forwarded writes, ledger digests)
+//! only with a token it accepts: any in the file. The file is read again
+//! every second, and on SIGHUP or `POST /admin/reload`, so a token can be
+//! rotated without a restart:
+//!
+//! 1. add the new token as a second line on every node, which then accepts
+//!    it as well as the old one;
+//! 2. make it the only line everywhere, so every node sends it.
+//!
+//! A token that leaves the file is still accepted for `--token-grace-ms`,
+//! which covers the nodes still sending it until they read their file
+//! again. Without the first step the nodes that read the new file first
+//! are turned away by the rest for a moment, which Paxos retries through.
+//!
+//! The token only says that a request comes from inside the cluster, not
+//! from which node; that is what `--signing-key` is for.
+//!
+//! `--admin-token <file>` is a file of the same kind, for operators: with
+//! it, `/admin` only serves requests with `Authorization: Bearer <token>`
+//! for one of its tokens, or an admin's grant with ACLs on; see `admin`. It
+//! is read again along with the cluster token's, and rotated the same way.
+
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{AppState, acl};
+
+/// Carries the cluster token on every node-to-node request.
+pub const TOKEN_HEADER: &str = "x-paxos-cluster-token";
+
+const WATCH_EVERY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+struct Tokens {
+    /// As the file lists them, the one sent first.
+    listed: Vec<String>,
+    /// Tokens that left the file, and until when they are still accepted.
+    retiring: Vec<(String, Instant)>,
+}
+
+/// The tokens a node sends and accepts; without a file, it does neither.
+#[derive(Debug, Default)]
+pub struct ClusterToken {
+    path: Option<PathBuf>,
+    tokens: RwLock<Tokens>,
+}
+
+fn parse(text: &str) -> io::Result<Vec<String>> {
+    let listed: Vec<String> = text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+    if listed.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no token"));
+    }
+    Ok(listed)
+}
+
+/// Compares without stopping at the first byte that differs.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+impl ClusterToken {
+    /// Reads the tokens in `path`, one per line; blank lines and lines
+    /// starting with `#` are skipped.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let listed = parse(&fs::read_to_string(path)?)?;
+        Ok(Self { path: Some(path.to_path_buf()), tokens: RwLock::new(Tokens { listed, retiring: Vec::new() }) })
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// The token sent to peers.
+    pub fn current(&self) -> Option<String> {
+        self.tokens.read().unwrap().listed.first().cloned()
+    }
+
+    /// Whether a peer sending `token` is let in at `now`.
+    pub fn accepts(&self, token: &str, now: Instant) -> bool {
+        let tokens = self.tokens.read().unwrap();
+        tokens.listed.iter().any(|known| same(known, token))
+            || tokens.retiring.iter().any(|(known, until)| *until > now && same(known, token))
+    }
+
+    /// Reads the file again, and says whether it changed. Tokens no longer
+    /// in it are still accepted for `grace`.
+    pub fn reload(&self, grace: Duration, now: Instant) -> io::Result<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let listed = parse(&fs::read_to_string(path)?)?;
+
+        let mut tokens = self.tokens.write().unwrap();
+        tokens.retiring.retain(|(token, until)| *until > now && !listed.contains(token));
+        if tokens.listed == listed {
+            return Ok(false);
+        }
+        let gone: Vec<String> = tokens.listed.iter().filter(|token| !listed.contains(token)).cloned().collect();
+        tokens.retiring.extend(gone.into_iter().map(|token| (token, now + grace)));
+        tokens.listed = listed;
+        Ok(true)
+    }
+}
+
+/// Reads the node's token files again, if it has any, and says what changed.
+pub fn refresh(state: &AppState) {
+    let grace = state.settings.read().unwrap().token_grace;
+    for (tokens, kind) in [(&state.cluster_token, "cluster"), (&state.admin_token, "admin")] {
+        match tokens.reload(grace, Instant::now()) {
+            Ok(true) => println!("[secrets] Node {} read a new {} token, the old ones are accepted for {:?}", state.node.id, kind, grace),
+            Ok(false) => {},
+            Err(e) => println!("[secrets] Node {} kept its {} tokens, it can't read the file: {}", state.node.id, kind, e),
+        }
+    }
+}
+
+/// Reads the token files again every so often, as long as the node runs.
+pub async fn run(state: AppState) {
+    if !state.cluster_token.is_on() && !state.admin_token.is_on() {
+        return;
+    }
+    loop {
+        tokio::time::sleep(WATCH_EVERY).await;
+        refresh(&state);
+    }
+}
+
+/// Whether `headers` carry the cluster token, as a peer's requests do.
+pub fn from_peer(state: &AppState, headers: &HeaderMap) -> bool {
+    let token = headers.get(TOKEN_HEADER).and_then(|token| token.to_str().ok());
+    token.is_some_and(|token| state.cluster_token.accepts(token, Instant::now()))
+}
+
+/// Whether `headers` carry one of the node's admin tokens.
+pub fn from_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    acl::bearer(headers).is_some_and(|token| state.admin_token.accepts(token, Instant::now()))
+}
+
+/// Turns away requests to the endpoints peers use that don't carry a
+/// token the node accepts. A node without a token file lets them through.
+pub async fn require(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !state.cluster_token.is_on() {
+        return next.run(request).await;
+    }
+    if from_peer(&state, request.headers()) {
+        return next.run(request).await;
+    }
+
+    println!("[{}] Node {} turned away a request without the cluster token", request.uri().path(), state.node.id);
+    (StatusCode::UNAUTHORIZED, format!("Node {} only takes this from a peer with the cluster token!", state.node.id)).into_response()
+}
@@ -0,0 +1,76 @@
+//! Sharding the key space over independent Paxos groups.
+//!
+//! With `--shards <n>` above 1, every node hosts `n` groups next to its
+//! own, `shard-0` to `shard-<n-1>`, and the keys of `/kv` are split between
+//! them by an FNV-1a hash of the key. A write runs a round in the group of
+//! its key only, so writes to keys in different groups neither share a log
+//! nor wait on one another, and the rounds of `n` groups run side by side
+//! on the same nodes; see `groups` for what a group has of its own. The
+//! node's own log keeps everything that isn't a `/kv` key: the ACL table,
+//! namespaces, and raw values from `/prepare`.
+//!
+//! Which group a key is in depends on `n`, so every node of a cluster has
+//! to run the same number, and it can't change once they hold data: a node
+//! with a data directory keeps each group's log and snapshots in
+//! `shards/<i>` there and refuses to start with another count.
+
+use std::{fs, io, path::Path};
+
+use crate::{
+    AppState,
+    consistency::{FNV_OFFSET, fnv1a},
+    groups::{self, GroupId, Groups},
+};
+
+/// Where a data directory keeps the groups, and the count it was made with.
+pub const DIR: &str = "shards";
+const COUNT: &str = "count";
+
+/// The group `key` belongs to, of `count`.
+pub fn of(key: &str, count: usize) -> usize {
+    (fnv1a(FNV_OFFSET, key.as_bytes()) % count.max(1) as u64) as usize
+}
+
+/// The id of shard `shard` among the node's groups.
+pub fn id(shard: usize) -> GroupId {
+    format!("{}{}", groups::SHARD, shard)
+}
+
+/// `state` hosting `count` shards for its keys, and `named` groups besides,
+/// none of them with storage.
+pub fn split(mut state: AppState, count: usize, named: &[GroupId]) -> AppState {
+    let mut hosted = Groups::default();
+    if count > 1 {
+        for shard in 0..count {
+            hosted.host(id(shard), groups::group(&state, &id(shard)));
+        }
+    }
+    for id in named {
+        hosted.host(id.clone(), groups::group(&state, id));
+    }
+    state.groups = std::sync::Arc::new(hosted);
+    state
+}
+
+/// The group that holds `key`.
+pub fn route<'a>(state: &'a AppState, key: &str) -> &'a AppState {
+    match state.groups.shard_count() {
+        0 => state,
+        count => state.groups.shard(of(key, count)).unwrap_or(state),
+    }
+}
+
+/// Records the count the groups in `dir` are made with, the first time,
+/// and refuses any other after that.
+pub fn check_count(dir: &Path, count: usize) -> io::Result<()> {
+    let path = dir.join(DIR).join(COUNT);
+    match fs::read_to_string(&path) {
+        Ok(text) if text.trim() == count.to_string() => Ok(()),
+        Ok(text) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("the keys were split into {} groups, not {}", text.trim(), count))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            fs::create_dir_all(dir.join(DIR))?;
+            fs::write(&path, format!("{}\n", count))
+        },
+        Err(e) => Err(e),
+    }
+}
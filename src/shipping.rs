@@ -0,0 +1,149 @@
+//! Shipping the log to every follower, rather than broadcasting learns.
+//!
+//! A learn is sent once and forgotten: a peer that misses one finds out
+//! when it next prepares that instance, or, if it's a learner, when it next
+//! asks for the log. With `--log-shipping`, the node that gets values
+//! chosen keeps, for each follower, the next instance to send it and the
+//! last one it is known to have everything up to, as a Raft leader keeps
+//! next and match indexes. Each batch of decisions then goes out as `POST
+//! /handle-ship`, carrying the log from the follower's next instance on, as
+//! far as the node has it without a gap. The follower learns it and answers
+//! with how far it has everything, which is where the next shipment starts:
+//! a follower that missed a shipment gets it again with the next one, and
+//! one that fell behind is walked forward a batch at a time. Every second
+//! the node also ships to the followers it knows are behind, decisions or
+//! not. `GET /admin/shipping` shows where each follower stands.
+//!
+//! Shipping takes the place of multicast and gossip. Peers older than
+//! [`version::SHIP`] still get their learns as before.
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+use axum::extract::State;
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState, Ballot, Id, Node, ProposalId,
+    handlers,
+    input::Json,
+    replica::MAX_ENTRIES,
+    transport::post_json,
+    version,
+};
+
+const SHIP_EVERY: Duration = Duration::from_secs(1);
+
+/// Shipments to one follower in a row before the next batch has its turn.
+const MAX_ROUNDS: usize = 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Shipment {
+    pub ballots: Vec<Ballot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ShipReply {
+    /// The follower has every instance up to this one.
+    pub chained: u64,
+}
+
+/// Where a follower stands.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Progress {
+    /// The first instance the next shipment carries.
+    pub next: u64,
+    /// The follower has every instance up to this one.
+    pub matched: u64,
+}
+
+/// The followers this node ships to.
+#[derive(Debug, Default)]
+pub struct Shipper {
+    followers: Mutex<BTreeMap<Id, Progress>>,
+}
+
+impl Shipper {
+    pub fn progress(&self) -> BTreeMap<Id, Progress> {
+        self.followers.lock().unwrap().clone()
+    }
+
+    /// Where the next shipment to `follower` starts; a follower not shipped
+    /// to yet is taken to have everything but the last instance this node
+    /// has in order.
+    fn next(&self, follower: Id, chained: u64) -> u64 {
+        let mut followers = self.followers.lock().unwrap();
+        followers.entry(follower).or_insert(Progress { next: chained.max(1), matched: 0 }).next
+    }
+
+    fn shipped(&self, follower: Id, chained: u64) {
+        self.followers.lock().unwrap().insert(follower, Progress { next: chained + 1, matched: chained });
+    }
+}
+
+/// Whether the node ships its log to `node`.
+pub fn ships_to(state: &AppState, node: &Node) -> bool {
+    state.settings.read().unwrap().log_shipping && state.versions.of(node.id) >= version::SHIP
+}
+
+pub async fn handle_ship(State(state): State<AppState>, Json(shipment): Json<Shipment>) -> Json<ShipReply> {
+    for ballot in &shipment.ballots {
+        handlers::learn(&state, ballot).await;
+    }
+    Json(ShipReply { chained: state.ledger.chained() })
+}
+
+pub async fn get_shipping(State(state): State<AppState>) -> Json<BTreeMap<Id, Progress>> {
+    Json(state.shipper.progress())
+}
+
+/// The log from `from` on, as far as it goes without a gap.
+fn entries(state: &AppState, from: u64) -> Vec<Ballot> {
+    (from..from.saturating_add(MAX_ENTRIES))
+        .map_while(|instance| Some(Ballot { instance, id: ProposalId::default(), value: Some(state.ledger.get(instance)?) }))
+        .collect()
+}
+
+/// Ships `follower` the log it doesn't have yet, and answers whether it has
+/// all of what this node has in order.
+async fn ship_to(state: &AppState, follower: &Node) -> bool {
+    for _ in 0..MAX_ROUNDS {
+        let chained = state.ledger.chained();
+        let next = state.shipper.next(follower.id, chained);
+        let shipment = Shipment { ballots: entries(state, next) };
+        if shipment.ballots.is_empty() {
+            return true;
+        }
+
+        let reply = match post_json(state.transport.as_ref(), follower.addr, "/handle-ship", &shipment).await {
+            Ok(reply) if !reply.is_error() => reply.json::<ShipReply>(),
+            _ => return false,
+        };
+        let Ok(reply) = reply else {
+            return false;
+        };
+        state.shipper.shipped(follower.id, reply.chained);
+        if reply.chained >= state.ledger.chained() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Ships every follower that takes shipments the log it doesn't have yet,
+/// and answers which followers those are.
+pub async fn ship(state: &AppState) -> Vec<Id> {
+    let followers: Vec<Node> = state.nodes.snapshot().iter().filter(|node| ships_to(state, node)).cloned().collect();
+    futures::future::join_all(followers.iter().map(|follower| ship_to(state, follower))).await;
+    followers.iter().map(|follower| follower.id).collect()
+}
+
+/// Ships to the followers that are behind, every second, whether or not
+/// there are new decisions.
+pub async fn run(state: AppState) {
+    loop {
+        tokio::time::sleep(SHIP_EVERY).await;
+        let chained = state.ledger.chained();
+        if state.shipper.progress().values().any(|progress| progress.matched < chained) {
+            ship(&state).await;
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Graceful shutdown.
+//!
+//! On SIGINT or SIGTERM a node stops taking new proposals (they get a 503)
+//! but keeps answering its peers, waits for the proposals it is running to
+//! finish, abandoning whatever is left after the drain timeout, syncs its
+//! log, history and trace files to disk and tells its peers it is leaving.
+//! Only then does the server stop.
+//!
+//! Peers keep a node that left among their voters, since dropping it would
+//! shrink the quorum without the rest of the cluster agreeing to it, but
+//! they let it `/connect` again when it comes back.
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use axum::{
+    http::{HeaderMap, StatusCode},
+    extract::State,
+};
+use tokio::sync::Notify;
+
+use crate::{AppState, Id, fanout, learns, transport::NODE_ID_HEADER};
+
+pub const SHUTTING_DOWN: &str = "Node is shutting down!";
+
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+/// A proposal the node has to finish before it can stop.
+pub struct Proposal<'a> {
+    shutdown: &'a Shutdown,
+}
+
+impl Drop for Proposal<'_> {
+    fn drop(&mut self) {
+        if self.shutdown.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shutdown.idle.notify_waiters();
+        }
+    }
+}
+
+impl Shutdown {
+    /// Registers a new proposal, unless the node is draining.
+    pub fn enter(&self) -> Option<Proposal<'_>> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let proposal = Proposal { shutdown: self };
+
+        if self.is_draining() {
+            return None;
+        }
+        Some(proposal)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn refuse_new(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Refuses new proposals and waits for the running ones, for at most
+    /// `timeout`. Returns how many were still running.
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        self.refuse_new();
+
+        let wait = async {
+            loop {
+                let idle = self.idle.notified();
+                if self.in_flight() == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        };
+
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(()) => 0,
+            Err(_) => self.in_flight(),
+        }
+    }
+}
+
+/// Resolves on SIGINT, or SIGTERM where there is such a thing.
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Everything between the signal and the server stopping.
+pub async fn run(state: AppState) {
+    let timeout = state.settings.read().unwrap().drain_timeout;
+    println!("[shutdown] Node {} draining {} proposals", state.node.id, state.shutdown.in_flight());
+
+    let abandoned = state.shutdown.drain(timeout).await;
+    if abandoned > 0 {
+        println!("[shutdown] Node {} abandoning {} proposals after {:?}", state.node.id, abandoned, timeout);
+    }
+
+    if let Some(storage) = &state.storage {
+        if let Err(e) = storage.sync(storage.position(), Duration::ZERO).await {
+            println!("[shutdown] Node {} failed to sync its log: {}", state.node.id, e);
+        }
+    }
+    for (id, group) in state.groups.iter() {
+        let Some(storage) = &group.storage else {
+            continue;
+        };
+        if let Err(e) = storage.sync(storage.position(), Duration::ZERO).await {
+            println!("[shutdown] Node {} failed to sync the log of group {}: {}", state.node.id, id, e);
+        }
+    }
+
+    if let Some(history) = &state.history {
+        if let Err(e) = history.sync() {
+            println!("[shutdown] Node {} failed to sync its history: {}", state.node.id, e);
+        }
+    }
+    if let Some(trace) = &state.trace {
+        if let Err(e) = trace.sync().await {
+            println!("[shutdown] Node {} failed to sync its trace: {}", state.node.id, e);
+        }
+    }
+
+    learns::flush(&state).await;
+    for (_, group) in state.groups.iter() {
+        learns::flush(group).await;
+    }
+    fanout::post_all(&state, &state.nodes.snapshot(), "/leave", &()).await;
+
+    println!("[shutdown] Node {} stopped", state.node.id);
+}
+
+pub async fn leave(State(state): State<AppState>, headers: HeaderMap) -> (StatusCode, String) {
+    let from = headers.get(NODE_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse::<Id>().ok());
+
+    let Some(from) = from else {
+        return (StatusCode::BAD_REQUEST, String::from("Only a peer can leave!"));
+    };
+
+    println!("[/leave] Node {} heard that node {} is shutting down", state.node.id, from);
+    state.departed.lock().await.insert(from);
+
+    (StatusCode::OK, String::new())
+}
+
+/// The 503 a draining node answers new proposals with.
+pub fn refuse() -> (StatusCode, String) {
+    (StatusCode::SERVICE_UNAVAILABLE, String::from(SHUTTING_DOWN))
+}
@@ -0,0 +1,303 @@
+//! Signed consensus messages, so no one but an acceptor can vote as it.
+//!
+//! With `--signing-key`, a node keeps an Ed25519 key in that file, made on
+//! the first start. It learns its peers' public keys out of band, from
+//! `--peer-key <id>=<hex>` once for each, and never from the network: a
+//! peer that `/connect`s or `/ping`s with a key other than the one given for
+//! its id, or with one when none was given, is turned away.
+//!
+//! Prepares, accepts and learns, PBFT's votes and repairs, to a peer whose
+//! key it knows then go out wrapped in an [`Envelope`] signed over the path,
+//! the time it was sent, a nonce and the body, and the reply comes back
+//! wrapped in one signed over the request too. A node takes a request only
+//! within [`MAX_AGE`] of when it was sent, and only once, so neither a
+//! forged request, nor one replayed, nor a reply replayed from another
+//! request passes. A node with a key takes those messages only in an
+//! envelope from a peer whose key it has, so a cluster turns signing on
+//! everywhere at once.
+//!
+//! [`SigningTransport`] does the wrapping for whatever transport the node
+//! uses, and [`verify`] the unwrapping, in front of the handlers.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{self, Read},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex, RwLock, atomic::{AtomicU64, Ordering}},
+    time::Duration,
+};
+use axum::{
+    body::{Body, to_bytes},
+    extract::{OriginalUri, Request, State},
+    http::{StatusCode, header::CONTENT_TYPE},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState, Id, Node,
+    chunked,
+    ed25519::{self, Keypair, PUBLIC_KEY_BYTES},
+    groups,
+    hex,
+    history::now_micros,
+    membership::Membership,
+    transport::{NODE_ID_HEADER, Reply, Transport},
+};
+
+/// The endpoints whose messages are signed.
+pub const SIGNED_PATHS: [&str; 10] = [
+    "/handle-prepare", "/handle-prepare-range", "/handle-accept", "/handle-learn", "/handle-learns", "/gossip-learns", "/handle-ship",
+    "/pbft/pre-prepare", "/pbft/vote", "/admin/repair",
+];
+
+/// The path multicast learns are signed under.
+pub const MULTICAST_PATH: &str = "multicast";
+
+/// The biggest signed message read: a proposal of the biggest upload,
+/// escaped into JSON twice.
+const MAX_SIGNED_BYTES: usize = 4 * chunked::MAX_UPLOAD_BYTES;
+
+/// How far from now a signed request may say it was sent; older ones, or
+/// ones from further ahead, are refused as replayed.
+pub const MAX_AGE: Duration = Duration::from_secs(30);
+
+/// A message and who signed it. Requests also carry when they were sent, in
+/// microseconds since the Unix epoch, and a nonce, which replies leave 0.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub from: Id,
+    #[serde(default)]
+    pub sent: u64,
+    #[serde(default)]
+    pub nonce: u64,
+    pub body: String,
+    pub signature: String,
+}
+
+/// What a request is signed over.
+pub fn request_message(path: &str, sent: u64, nonce: u64, body: &str) -> Vec<u8> {
+    format!("paxos-request\n{}\n{}\n{}\n{}", path, sent, nonce, body).into_bytes()
+}
+
+/// What the reply to `request` is signed over.
+pub fn reply_message(path: &str, request: &str, status: StatusCode, body: &str) -> Vec<u8> {
+    format!("paxos-reply\n{}\n{}\n{}\n{}\n{}", path, request.len(), request, status.as_u16(), body).into_bytes()
+}
+
+/// This node's key, the ones its peers were given, and the requests taken
+/// within [`MAX_AGE`].
+#[derive(Debug, Default)]
+pub struct Keys {
+    own: RwLock<Option<(Id, Arc<Keypair>)>>,
+    peers: RwLock<HashMap<Id, [u8; PUBLIC_KEY_BYTES]>>,
+    nonce: AtomicU64,
+    /// When each was sent, who sent it and its nonce.
+    seen: Mutex<BTreeSet<(u64, Id, u64)>>,
+}
+
+impl Keys {
+    pub fn set_own(&self, id: Id, keypair: Keypair) {
+        self.peers.write().unwrap().insert(id, keypair.public());
+        *self.own.write().unwrap() = Some((id, Arc::new(keypair)));
+    }
+
+    pub fn own(&self) -> Option<(Id, Arc<Keypair>)> {
+        self.own.read().unwrap().clone()
+    }
+
+    /// Our public key, in hex, to hand to peers.
+    pub fn public_hex(&self) -> Option<String> {
+        self.own().map(|(_, keypair)| hex::encode(&keypair.public()))
+    }
+
+    /// Takes `key` as the one `peer` signs with, as given out of band.
+    pub fn trust(&self, peer: Id, key: [u8; PUBLIC_KEY_BYTES]) {
+        self.peers.write().unwrap().insert(peer, key);
+    }
+
+    /// The key `peer` signs with, if we were given it.
+    pub fn of(&self, peer: Id) -> Option<[u8; PUBLIC_KEY_BYTES]> {
+        self.peers.read().unwrap().get(&peer).copied()
+    }
+
+    /// Checks the key `peer` says it has against the one it was given; a
+    /// key is never taken from the peer itself. A node that doesn't sign
+    /// takes any.
+    pub fn heard(&self, peer: Id, key: Option<&str>) -> Result<(), String> {
+        let Some(key) = key.filter(|_| self.own().is_some()) else {
+            return Ok(());
+        };
+        let key = hex::decode::<PUBLIC_KEY_BYTES>(key).ok_or_else(|| format!("Node {} sent a public key that isn't 32 bytes of hex!", peer))?;
+        match self.of(peer) {
+            Some(known) if known == key => Ok(()),
+            Some(_) => Err(format!("Node {} signs with another key than the one it was given!", peer)),
+            None => Err(format!("Node {} signs with a key it wasn't given with --peer-key!", peer)),
+        }
+    }
+
+    /// `body` wrapped and signed by this node as a request to `path`.
+    pub fn seal_request(&self, path: &str, body: String) -> Option<Envelope> {
+        let (id, keypair) = self.own()?;
+        let (sent, nonce) = (now_micros(), self.nonce.fetch_add(1, Ordering::SeqCst));
+        let signature = hex::encode(&keypair.sign(&request_message(path, sent, nonce, &body)));
+        Some(Envelope { from: id, sent, nonce, body, signature })
+    }
+
+    /// Whether `envelope` is a request to `path` signed by the node it says,
+    /// sent within [`MAX_AGE`] and not taken before.
+    pub fn check_request(&self, envelope: &Envelope, path: &str) -> bool {
+        if !self.check(envelope, &request_message(path, envelope.sent, envelope.nonce, &envelope.body)) {
+            return false;
+        }
+        let (now, max_age) = (now_micros(), MAX_AGE.as_micros() as u64);
+        if envelope.sent.abs_diff(now) > max_age {
+            return false;
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        // Anything older is refused for its age, so it needn't be kept.
+        *seen = seen.split_off(&(now.saturating_sub(max_age), 0, 0));
+        seen.insert((envelope.sent, envelope.from, envelope.nonce))
+    }
+
+    /// `body` wrapped and signed by this node.
+    pub fn seal(&self, message: &[u8], body: String) -> Option<Envelope> {
+        let (id, keypair) = self.own()?;
+        let signature = hex::encode(&keypair.sign(message));
+        Some(Envelope { from: id, sent: 0, nonce: 0, body, signature })
+    }
+
+    /// Whether `envelope` is signed by the node it says, over `message`.
+    pub fn check(&self, envelope: &Envelope, message: &[u8]) -> bool {
+        let (Some(key), Some(signature)) = (self.of(envelope.from), hex::decode(&envelope.signature)) else {
+            return false;
+        };
+        ed25519::verify(&key, message, &signature)
+    }
+}
+
+/// A peer's key, given as `<id>=<64 hex digits>`.
+pub fn parse_peer_key(text: &str) -> Result<(Id, [u8; PUBLIC_KEY_BYTES]), String> {
+    text.split_once('=')
+        .and_then(|(id, key)| Some((id.trim().parse().ok()?, hex::decode(key)?)))
+        .ok_or_else(|| format!("{:?} isn't <id>=<64 hex digits>", text))
+}
+
+/// Loads the key in `path`, or makes one there if there's nothing yet.
+pub fn load_or_create(path: &Path) -> io::Result<Keypair> {
+    match fs::read_to_string(path) {
+        Ok(text) => {
+            let seed = hex::decode::<32>(&text).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the key file must hold 32 bytes of hex"))?;
+            Ok(Keypair::from_seed(&seed))
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut seed = [0u8; 32];
+            fs::File::open("/dev/urandom")?.read_exact(&mut seed)?;
+
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            io::Write::write_all(&mut options.open(path)?, format!("{}\n", hex::encode(&seed)).as_bytes())?;
+            Ok(Keypair::from_seed(&seed))
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Signs what goes out to peers that have keys, and checks what they answer.
+#[derive(Debug)]
+pub struct SigningTransport {
+    inner: Arc<dyn Transport>,
+    node: Node,
+    keys: Arc<Keys>,
+    nodes: Arc<Membership>,
+}
+
+impl SigningTransport {
+    pub fn new(inner: Arc<dyn Transport>, node: Node, keys: Arc<Keys>, nodes: Arc<Membership>) -> Self {
+        Self { inner, node, keys, nodes }
+    }
+}
+
+impl Transport for SigningTransport {
+    fn post(&self, addr: SocketAddr, path: &str, body: String) -> BoxFuture<'static, Result<Reply, String>> {
+        let peer = if addr == self.node.addr { Some(self.node.id) } else { self.nodes.at(addr) };
+        let peer = peer.filter(|&peer| SIGNED_PATHS.contains(&groups::split_path(path).1) && self.keys.of(peer).is_some());
+        let (Some(peer), Some(sealed)) = (peer, self.keys.seal_request(path, body.clone())) else {
+            return self.inner.post(addr, path, body);
+        };
+
+        // The reply is signed over the request as sent, nonce and all.
+        let request = serde_json::to_string(&sealed).unwrap();
+        let reply = self.inner.post(addr, path, request.clone());
+        let (keys, path) = (self.keys.clone(), path.to_string());
+        Box::pin(async move {
+            let reply = reply.await?;
+            let envelope = serde_json::from_str::<Envelope>(&reply.body)
+                .map_err(|_| format!("Node {} answered {} without signing it", peer, path))?;
+            if envelope.from != peer || !keys.check(&envelope, &reply_message(&path, &request, reply.status, &envelope.body)) {
+                return Err(format!("Node {} answered {} with a bad signature", peer, path));
+            }
+            Ok(Reply { status: reply.status, body: envelope.body })
+        })
+    }
+}
+
+fn refuse(path: &str, message: &str) -> Response {
+    println!("[{}] {}", path, message);
+    (StatusCode::UNAUTHORIZED, format!("{}!", message)).into_response()
+}
+
+/// Who signed the message a handler got, for handlers that only take
+/// signed ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SignedBy(pub Id);
+
+/// Unwraps a signed message for the handler, and signs its answer. A node
+/// without a key lets everything through as it is.
+pub async fn verify(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if state.keys.own().is_none() {
+        return next.run(request).await;
+    }
+    // Signed over the path as sent, so a message for one group's log
+    // doesn't pass for another's.
+    let path = match request.extensions().get::<OriginalUri>() {
+        Some(uri) => uri.path().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    let (parts, body) = request.into_parts();
+
+    let Ok(bytes) = to_bytes(body, MAX_SIGNED_BYTES).await else {
+        return refuse(&path, "The message is too big to check");
+    };
+    let Ok(envelope) = serde_json::from_slice::<Envelope>(&bytes) else {
+        return refuse(&path, &format!("Node {} only takes signed consensus messages", state.node.id));
+    };
+    let claimed = parts.headers.get(NODE_ID_HEADER).and_then(|id| id.to_str().ok()?.parse::<Id>().ok());
+    if claimed.is_some_and(|claimed| claimed != envelope.from) || !state.keys.check_request(&envelope, &path) {
+        return refuse(&path, &format!("Node {} got a message that isn't signed by node {}, or was replayed", state.node.id, envelope.from));
+    }
+    let sealed_request = String::from_utf8_lossy(&bytes).into_owned();
+
+    let mut request = Request::from_parts(parts, Body::from(envelope.body.clone()));
+    request.extensions_mut().insert(SignedBy(envelope.from));
+    let response = next.run(request).await;
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(reply) = to_bytes(body, MAX_SIGNED_BYTES).await else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "The answer is too big to sign!").into_response();
+    };
+    let reply = String::from_utf8_lossy(&reply).into_owned();
+    let message = reply_message(&path, &sealed_request, parts.status, &reply);
+    let sealed = state.keys.seal(&message, reply).expect("the node has a key");
+
+    parts.headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    parts.headers.remove(axum::http::header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(serde_json::to_string(&sealed).unwrap()))
+}
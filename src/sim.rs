@@ -0,0 +1,400 @@
+//! A deterministic, in-process network for running whole clusters.
+//!
+//! Every simulated node runs its real router, but requests between nodes go
+//! through a [`SimNetwork`] that drops, delays and partitions them using a
+//! seeded RNG. Everything runs on a single-threaded runtime and delays are
+//! counted in scheduler yields rather than wall-clock time, so a seed fully
+//! determines the interleaving and any failure can be replayed with
+//! `PAXOS_SIM_SEED=<seed>`. A plain `cargo test` runs each scenario over a
+//! few seeds; `PAXOS_SIM_SEEDS=<n>` sweeps the first `n`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use axum::{
+    body::Body,
+    extract::Request,
+    http::header::CONTENT_TYPE,
+    Router,
+};
+use futures::future::BoxFuture;
+use tower::ServiceExt;
+
+use crate::{
+    AppState, Id, Ledger, Node, Value, groups::GroupId, rng::Rng, router, shards,
+    dedup::{MESSAGE_ID_HEADER, MessageIds},
+    hlc::{HLC_HEADER, Hlc, Timestamp},
+    streams::Streams,
+    transport::{NODE_ID_HEADER, Reply, Transport},
+    version,
+};
+
+/// Number of seeds in a scenario's full sweep.
+pub const DEFAULT_SEEDS: u64 = 64;
+/// Number of seeds each scenario runs unless `PAXOS_SIM_SEEDS` asks for more.
+pub const QUICK_SEEDS: u64 = 4;
+
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    pub nodes: usize,
+    /// Probability that a request, or independently its reply, is lost.
+    pub drop_rate: f64,
+    /// Upper bound, in scheduler yields, for each leg of a delivery. Random
+    /// delays are what reorder messages.
+    pub max_delay: u64,
+    /// Paxos groups each node splits the keys between; see `shards`.
+    pub shards: usize,
+    /// Named groups each node hosts besides; see `groups`.
+    pub groups: Vec<GroupId>,
+    /// How many of the last nodes only learn; see `replica`.
+    pub learners: usize,
+    /// The zone of each node, in order, for as many as it names; see
+    /// `quorum`.
+    pub zones: Vec<String>,
+    /// The weight of each node, in order, for as many as it names.
+    pub weights: Vec<u32>,
+    /// Command streams of each group's log; see `streams`.
+    pub streams: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self { nodes: 3, drop_rate: 0.0, max_delay: 8, shards: 1, groups: Vec::new(), learners: 0, zones: Vec::new(), weights: Vec::new(), streams: 1 }
+    }
+}
+
+impl SimConfig {
+    /// Whether there is a cluster to simulate.
+    pub fn check(&self) -> Result<(), String> {
+        if self.nodes == 0 {
+            return Err(String::from("A simulated cluster needs at least one node!"));
+        }
+        Ok(())
+    }
+}
+
+/// Where simulated node `index` is reached: 10.0.0.1 for the first, and on
+/// through as many addresses as there are nodes.
+fn addr(index: usize) -> SocketAddr {
+    let [.., a, b, c] = (index as u32 + 1).to_be_bytes();
+    SocketAddr::from(([10, a, b, c], 3000))
+}
+
+struct NetworkState {
+    rng: Rng,
+    routes: HashMap<SocketAddr, Router>,
+    groups: Option<Vec<Vec<SocketAddr>>>,
+    drop_rate: f64,
+    max_delay: u64,
+    /// Requests sent, lost or not, by path.
+    sent: BTreeMap<String, u64>,
+}
+
+impl NetworkState {
+    fn is_cut(&self, from: SocketAddr, to: SocketAddr) -> bool {
+        let Some(groups) = &self.groups else {
+            return false;
+        };
+
+        let group_of = |addr| groups.iter().position(|group| group.contains(&addr));
+        group_of(from) != group_of(to)
+    }
+}
+
+pub struct SimNetwork {
+    state: Mutex<NetworkState>,
+}
+
+impl SimNetwork {
+    fn new(seed: u64, config: &SimConfig) -> Self {
+        let state = NetworkState {
+            rng: Rng::new(seed),
+            routes: HashMap::new(),
+            groups: None,
+            drop_rate: config.drop_rate,
+            max_delay: config.max_delay,
+            sent: BTreeMap::new(),
+        };
+        Self { state: Mutex::new(state) }
+    }
+
+    async fn deliver(&self, from: &Node, stamp: Timestamp, message: String, to: SocketAddr, path: String, body: String) -> Result<Reply, String> {
+        let (id, from) = (from.id, from.addr);
+        let (route, request_delay, reply_delay, lose_request, lose_reply) = {
+            let mut state = self.state.lock().unwrap();
+            *state.sent.entry(path.clone()).or_default() += 1;
+            let (drop_rate, max_delay) = (state.drop_rate, state.max_delay);
+            let lose_request = state.is_cut(from, to) || state.rng.chance(drop_rate);
+            let lose_reply = state.rng.chance(drop_rate);
+            let request_delay = state.rng.below(max_delay + 1);
+            let reply_delay = state.rng.below(max_delay + 1);
+            (state.routes.get(&to).cloned(), request_delay, reply_delay, lose_request, lose_reply)
+        };
+
+        pause(request_delay).await;
+
+        let Some(route) = route.filter(|_| !lose_request) else {
+            return Err(format!("simulated: request {} from {} to {} was lost", path, from, to));
+        };
+
+        let reply = send(route, "POST", Some((id, stamp, message)), &path, body).await;
+
+        pause(reply_delay).await;
+
+        // A partition raised while the request was in flight eats the reply too.
+        if lose_reply || self.state.lock().unwrap().is_cut(from, to) {
+            return Err(format!("simulated: reply to {} from {} to {} was lost", path, from, to));
+        }
+
+        Ok(reply)
+    }
+}
+
+async fn pause(yields: u64) {
+    for _ in 0..yields {
+        tokio::task::yield_now().await;
+    }
+}
+
+pub(crate) async fn send(route: Router, method: &str, from: Option<(Id, Timestamp, String)>, path: &str, body: String) -> Reply {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(path)
+        .header(CONTENT_TYPE, "application/json");
+
+    if let Some((from, stamp, message)) = from {
+        request = request.header(NODE_ID_HEADER, from).header(HLC_HEADER, stamp.to_string()).header(MESSAGE_ID_HEADER, message);
+    }
+
+    let request = request.body(Body::from(body)).unwrap();
+
+    let response = route.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    Reply { status, body: String::from_utf8_lossy(&bytes).into_owned() }
+}
+
+#[derive(Clone)]
+pub struct SimTransport {
+    node: Node,
+    network: Arc<SimNetwork>,
+    clock: Arc<Hlc>,
+    ids: Arc<MessageIds>,
+}
+
+impl fmt::Debug for SimTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimTransport").field("node", &self.node).finish()
+    }
+}
+
+impl Transport for SimTransport {
+    fn post(&self, addr: SocketAddr, path: &str, body: String) -> BoxFuture<'static, Result<Reply, String>> {
+        let network = self.network.clone();
+        let from = self.node.clone();
+        let stamp = self.clock.now();
+        let message = self.ids.next();
+        let path = path.to_string();
+        Box::pin(async move { network.deliver(&from, stamp, message, addr, path, body).await })
+    }
+}
+
+/// Node `index` of a cluster shaped by `config`, reached at `addr`.
+pub(crate) fn member(config: &SimConfig, index: usize, addr: SocketAddr) -> Node {
+    Node {
+        learner: index + config.learners >= config.nodes,
+        zone: config.zones.get(index).cloned(),
+        weight: config.weights.get(index).copied().unwrap_or(1),
+        ..Node::new(index as u64 + 1, addr)
+    }
+}
+
+/// `state`, knowing the rest of `members` as its peers, and split into the
+/// groups and streams `config` names.
+pub(crate) fn with_peers(mut state: AppState, members: &[Node], config: &SimConfig) -> AppState {
+    state.streams = Arc::new(Streams::new(config.streams));
+    // A cluster in one process is for tests, which break it on purpose.
+    state.debug_admin = true;
+    let peers: Vec<Node> = members.iter().filter(|peer| peer.id != state.node.id).cloned().collect();
+    for peer in &peers {
+        state.versions.negotiate(peer.id, Some(version::PROTOCOL)).unwrap();
+    }
+    state.nodes.update(|nodes| *nodes = peers);
+    shards::split(state, config.shards, &config.groups)
+}
+
+/// Whether no node, nor any of its groups, has learns to send or commands
+/// to apply.
+pub(crate) fn settled(nodes: &[AppState]) -> bool {
+    let mut groups = nodes.iter().flat_map(|state| std::iter::once(state).chain(state.groups.iter().map(|(_, group)| group)));
+    groups.all(|state| state.learns.pending() == 0 && state.applier.lag() == 0)
+}
+
+/// A fully connected cluster living inside one process.
+#[derive(Clone)]
+pub struct Sim {
+    pub seed: u64,
+    network: Arc<SimNetwork>,
+    nodes: Vec<AppState>,
+    routes: Vec<Router>,
+}
+
+impl Sim {
+    pub fn new(seed: u64, config: SimConfig) -> Self {
+        let network = Arc::new(SimNetwork::new(seed, &config));
+
+        let members: Vec<Node> = (0..config.nodes)
+            .map(|i| member(&config, i, addr(i)))
+            .collect();
+
+        let nodes: Vec<AppState> = members.iter()
+            .map(|node| {
+                let clock = Arc::new(Hlc::default());
+                let transport = SimTransport { node: node.clone(), network: network.clone(), clock: clock.clone(), ids: Arc::default() };
+                let mut state = AppState::new(node.clone(), Arc::new(transport));
+                state.clock = clock;
+                state.faults.reseed(seed ^ node.id);
+                with_peers(state, &members, &config)
+            })
+            .collect();
+
+        let routes: Vec<Router> = nodes.iter().map(|state| router(state.clone())).collect();
+
+        {
+            let mut state = network.state.lock().unwrap();
+            for (node, route) in nodes.iter().zip(&routes) {
+                state.routes.insert(node.node.addr, route.clone());
+            }
+        }
+
+        Self { seed, network, nodes, routes }
+    }
+
+    pub fn size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn node(&self, index: usize) -> &AppState {
+        &self.nodes[index]
+    }
+
+    /// A client request straight to a node; this hop is never faulted.
+    pub async fn request(&self, index: usize, path: &str, body: &str) -> Reply {
+        send(self.routes[index].clone(), "POST", None, path, body.to_string()).await
+    }
+
+    pub async fn get(&self, index: usize, path: &str) -> Reply {
+        send(self.routes[index].clone(), "GET", None, path, String::new()).await
+    }
+
+    pub async fn propose(&self, index: usize, value: &str) -> Reply {
+        self.request(index, "/prepare", value).await
+    }
+
+    pub async fn put(&self, index: usize, key: &str, value: &str) -> Reply {
+        send(self.routes[index].clone(), "PUT", None, &format!("/kv/{}", key), value.to_string()).await
+    }
+
+    /// Splits the cluster; nodes left out of every group are isolated.
+    pub fn partition(&self, groups: &[&[usize]]) {
+        let groups = groups.iter()
+            .map(|group| group.iter().map(|&i| self.nodes[i].node.addr).collect())
+            .collect();
+        self.network.state.lock().unwrap().groups = Some(groups);
+    }
+
+    pub fn heal(&self) {
+        self.network.state.lock().unwrap().groups = None;
+    }
+
+    pub fn set_drop_rate(&self, drop_rate: f64) {
+        self.network.state.lock().unwrap().drop_rate = drop_rate;
+    }
+
+    /// Node-to-node requests sent so far, by path.
+    pub fn messages(&self) -> BTreeMap<String, u64> {
+        self.network.state.lock().unwrap().sent.clone()
+    }
+
+    /// Lets the nodes send the peers what they decided and apply what they
+    /// learned, as both happen in the background.
+    pub async fn settle(&self) {
+        for _ in 0..10_000 {
+            if settled(&self.nodes) {
+                return;
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    pub async fn ledgers(&self) -> Vec<Ledger> {
+        let mut ledgers = Vec::with_capacity(self.nodes.len());
+        for state in &self.nodes {
+            ledgers.push(state.ledger.to_map());
+        }
+        ledgers
+    }
+
+    /// No two nodes may have learned different values for the same instance.
+    pub async fn check_agreement(&self) -> Result<(), String> {
+        let mut learned: BTreeMap<u64, Vec<(u64, Value)>> = BTreeMap::new();
+        for (state, ledger) in self.nodes.iter().zip(self.ledgers().await) {
+            for (instance, value) in ledger {
+                learned.entry(instance).or_default().push((state.node.id, value));
+            }
+        }
+
+        for (instance, values) in learned {
+            if values.iter().any(|(_, value)| *value != values[0].1) {
+                return Err(format!("instance {} learned different values: {:?}", instance, values));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every value a client got an OK for must have been learned somewhere.
+    pub async fn check_learned(&self, acknowledged: &[Value]) -> Result<(), String> {
+        let ledgers = self.ledgers().await;
+        for value in acknowledged {
+            if !ledgers.iter().any(|ledger| ledger.values().any(|learned| learned == value)) {
+                return Err(format!("acknowledged value {:?} was never learned", value));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The seeds a scenario of up to `count` seeds should run: just
+/// `PAXOS_SIM_SEED` when replaying, the first `PAXOS_SIM_SEEDS` for a
+/// longer sweep, otherwise the first [`QUICK_SEEDS`].
+pub fn seeds(count: u64) -> Vec<u64> {
+    if let Ok(seed) = std::env::var("PAXOS_SIM_SEED") {
+        return vec![seed.parse().expect("PAXOS_SIM_SEED must be a number")];
+    }
+    let sweep = match std::env::var("PAXOS_SIM_SEEDS") {
+        Ok(seeds) => seeds.parse().expect("PAXOS_SIM_SEEDS must be a number"),
+        Err(_) => QUICK_SEEDS,
+    };
+    (0..count.min(sweep)).collect()
+}
+
+/// Runs `scenario` once per seed, each on a fresh single-threaded runtime,
+/// and panics with the replay seed on the first failure.
+pub fn run<F, Fut>(count: u64, scenario: F)
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    for seed in seeds(count) {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        if let Err(e) = runtime.block_on(scenario(seed)) {
+            panic!("simulation failed with seed {}: {}\nreplay with PAXOS_SIM_SEED={}", seed, e, seed);
+        }
+    }
+}
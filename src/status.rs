@@ -0,0 +1,186 @@
+//! How far a node got: its leadership epoch and its commit index.
+//!
+//! The epoch is the highest ballot the node promised, which only grows and
+//! changes whenever a proposer takes over with phase 1; a proposer that
+//! prepared ahead keeps it for as long as its range lasts. The commit index
+//! is the last instance up to which every instance is learned, so nothing
+//! before it can still change. `GET /status` shows both, and every write
+//! answers with them too, as the `X-Paxos-Epoch` and `X-Paxos-Commit-Index`
+//! headers, from the group the write went to: a client can tell whether a
+//! leader changed between two writes, and how far a node must have come
+//! before it shows them.
+//!
+//! `GET /state` is the whole of what the node knows, for scripts and
+//! dashboards: the status, the peers it knows, what its acceptor holds,
+//! who it takes for the leader, how far it applied and how big its ledger
+//! is.
+//!
+//! `GET /wait?index=N` holds the client until the node applied instance `N`
+//! to its KV store, or `timeout` milliseconds passed, and answers with the
+//! status then: a workflow that wrote through one node can wait for a
+//! replica to show the write before it reads there.
+
+use std::{sync::Mutex, time::Duration};
+use axum::{
+    extract::State,
+    http::{HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+
+use crate::{AppState, Id, Node, ProposalId, groups, input::{Json, Query}};
+
+pub const EPOCH_HEADER: &str = "x-paxos-epoch";
+pub const COMMIT_INDEX_HEADER: &str = "x-paxos-commit-index";
+
+/// How long `GET /wait` waits when the client doesn't say.
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The longest `GET /wait` waits whatever the client says.
+pub const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// The highest ballot this node promised.
+#[derive(Debug, Default)]
+pub struct Epoch {
+    highest: Mutex<ProposalId>,
+}
+
+impl Epoch {
+    pub fn observe(&self, id: ProposalId) {
+        let mut highest = self.highest.lock().unwrap();
+        *highest = (*highest).max(id);
+    }
+
+    pub fn get(&self) -> ProposalId {
+        *self.highest.lock().unwrap()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Status {
+    pub node: Id,
+    pub epoch: ProposalId,
+    pub commit_index: u64,
+    /// The highest instance learned, which may lie past a gap.
+    pub last_learned: u64,
+}
+
+pub fn of(state: &AppState) -> Status {
+    Status {
+        node: state.node.id,
+        epoch: state.epoch.get(),
+        commit_index: state.ledger.chained(),
+        last_learned: state.ledger.last().unwrap_or(0),
+    }
+}
+
+pub async fn get_status(State(state): State<AppState>) -> Json<Status> {
+    Json(of(&state))
+}
+
+/// A peer as this node sees it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Member {
+    #[serde(flatten)]
+    pub node: Node,
+    /// The protocol version the two of them speak; see `version`.
+    pub protocol: u32,
+    /// It told us it was shutting down, and hasn't connected since.
+    pub departed: bool,
+}
+
+/// What the acceptor holds for instances not learned yet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AcceptorSummary {
+    /// The highest ballot it promised for those; `status.epoch` is the
+    /// highest it ever did.
+    pub promised: ProposalId,
+    /// Instances it promised or accepted in.
+    pub slots: usize,
+    /// Instances it accepted a value in.
+    pub accepted: usize,
+    /// Promises for ranges of instances; see `proposer`.
+    pub ranges: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeState {
+    pub node: Node,
+    pub members: Vec<Member>,
+    pub acceptor: AcceptorSummary,
+    /// The node whose ballot this one promised last, that is the last
+    /// proposer it knows took over with phase 1; none before any did.
+    pub leader: Option<Id>,
+    /// As `GET /status` answers it.
+    pub status: Status,
+    /// Every instance up to this one is applied to the KV store.
+    pub applied_index: u64,
+    /// Instances learned.
+    pub ledger_size: usize,
+}
+
+pub async fn state_of(state: &AppState) -> NodeState {
+    let departed = state.departed.lock().await.clone();
+    let members = state.nodes.snapshot().iter()
+        .map(|node| Member { node: node.clone(), protocol: state.versions.of(node.id), departed: departed.contains(&node.id) })
+        .collect();
+    let acceptor = {
+        let acceptor = state.acceptor.lock().await;
+        AcceptorSummary {
+            promised: acceptor.highest(),
+            slots: acceptor.slots.len(),
+            accepted: acceptor.slots.values().filter(|slot| slot.accepted_proposal.is_some()).count(),
+            ranges: acceptor.ranges.len(),
+        }
+    };
+    let status = of(state);
+
+    NodeState {
+        node: state.node.clone(),
+        members,
+        acceptor,
+        leader: (status.epoch != ProposalId::default()).then_some(status.epoch.node_id),
+        status,
+        applied_index: state.applier.index(),
+        ledger_size: state.ledger.len(),
+    }
+}
+
+pub async fn get_state(State(state): State<AppState>) -> Json<NodeState> {
+    Json(state_of(&state).await)
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct WaitQuery {
+    /// The instance to wait for.
+    pub index: u64,
+    /// How long to wait, in milliseconds.
+    pub timeout: Option<u64>,
+    /// The group the instance is in, if not the node's own; see `groups`.
+    pub group: Option<String>,
+}
+
+pub async fn get_wait(State(state): State<AppState>, Query(query): Query<WaitQuery>) -> Response {
+    let group = match groups::named(&state, query.group.as_deref()) {
+        Ok(group) => group,
+        Err(refusal) => return refusal.into_response(),
+    };
+    let timeout = query.timeout.map_or(WAIT_TIMEOUT, Duration::from_millis).min(MAX_WAIT);
+
+    match tokio::time::timeout(timeout, group.applier.applied(query.index)).await {
+        Ok(()) => stamped(group, Json(of(group))),
+        Err(_) => {
+            let refusal = format!("Node {} didn't apply instance {} within {} ms!", state.node.id, query.index, timeout.as_millis());
+            stamped(group, (StatusCode::GATEWAY_TIMEOUT, refusal))
+        },
+    }
+}
+
+/// `reply` to a write that went to `state`, with its epoch and commit index.
+pub fn stamped(state: &AppState, reply: impl IntoResponse) -> Response {
+    let status = of(state);
+    let headers = [
+        (HeaderName::from_static(EPOCH_HEADER), format!("{}.{}", status.epoch.round, status.epoch.node_id)),
+        (HeaderName::from_static(COMMIT_INDEX_HEADER), status.commit_index.to_string()),
+    ];
+    (headers, reply).into_response()
+}
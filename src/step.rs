@@ -0,0 +1,142 @@
+//! Step mode, for walking through the protocol by hand.
+//!
+//! A node started with `--step` stops before each phase of every proposal it
+//! runs (prepare, accept, learn) until an operator calls `POST /admin/step`.
+//! `GET /admin/step` shows the phase that is waiting, so a class can look at
+//! what is about to be sent before letting it go. Proposals running at once
+//! wait in line, and each step releases the one that has waited longest.
+
+use std::{collections::VecDeque, sync::Mutex};
+use axum::{
+    http::StatusCode,
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+use tokio::sync::oneshot;
+
+use crate::{AppState, Ballot, ProposalId, Value, input::Json};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Prepare,
+    Accept,
+    Learn,
+}
+
+/// A phase held back until the next step.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pending {
+    pub phase: Phase,
+    pub instance: u64,
+    /// Not picked yet when a prepare is waiting.
+    pub id: Option<ProposalId>,
+    pub value: Option<Value>,
+}
+
+impl Pending {
+    pub fn prepare(instance: u64, value: &Value) -> Self {
+        Self { phase: Phase::Prepare, instance, id: None, value: Some(value.clone()) }
+    }
+
+    pub fn ballot(phase: Phase, ballot: &Ballot) -> Self {
+        Self { phase, instance: ballot.instance, id: Some(ballot.id), value: ballot.value.clone() }
+    }
+}
+
+/// Proposals waiting for a step, oldest first.
+#[derive(Debug, Default)]
+pub struct Stepper {
+    waiting: Mutex<VecDeque<(Pending, oneshot::Sender<()>)>>,
+}
+
+/// Takes the proposal out of the queue if it gives up while waiting (its
+/// client hung up), so the next step doesn't release a phase nobody saw.
+struct Waiting<'a> {
+    stepper: &'a Stepper,
+    released: oneshot::Receiver<()>,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.released.close();
+        self.stepper.waiting.lock().unwrap().retain(|(_, release)| !release.is_closed());
+    }
+}
+
+impl Stepper {
+    /// The phase the next step releases.
+    pub fn pending(&self) -> Option<Pending> {
+        self.waiting.lock().unwrap().front().map(|(pending, _)| pending.clone())
+    }
+
+    /// Phases waiting, the next one first.
+    pub fn queued(&self) -> Vec<Pending> {
+        self.waiting.lock().unwrap().iter().map(|(pending, _)| pending.clone()).collect()
+    }
+
+    pub async fn wait(&self, pending: Pending) {
+        let (release, released) = oneshot::channel();
+        self.waiting.lock().unwrap().push_back((pending, release));
+
+        let mut waiting = Waiting { stepper: self, released };
+        let _ = (&mut waiting.released).await;
+    }
+
+    /// Releases the phase that has waited longest, if there is one.
+    pub fn step(&self) -> Option<Pending> {
+        let mut waiting = self.waiting.lock().unwrap();
+        while let Some((pending, release)) = waiting.pop_front() {
+            if release.send(()).is_ok() {
+                return Some(pending);
+            }
+        }
+        None
+    }
+}
+
+/// Waits for the operator when the node runs in step mode.
+pub async fn gate(state: &AppState, pending: Pending) {
+    let Some(stepper) = &state.stepper else {
+        return;
+    };
+
+    println!("[step] Node {} holding {:?} of instance {} until /admin/step", state.node.id, pending.phase, pending.instance);
+    stepper.wait(pending).await;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StepStatus {
+    pub enabled: bool,
+    pub pending: Option<Pending>,
+}
+
+/// Only serves `/admin/step` on a node started with `--step`.
+pub async fn stepped(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if state.stepper.is_some() {
+        return next.run(request).await;
+    }
+    let refusal = format!("Node {} only serves {} with --step!", state.node.id, request.uri().path());
+    (StatusCode::FORBIDDEN, refusal).into_response()
+}
+
+pub async fn get_step(State(state): State<AppState>) -> (StatusCode, Json<StepStatus>) {
+    let pending = state.stepper.as_ref().and_then(|stepper| stepper.pending());
+    (StatusCode::OK, Json(StepStatus { enabled: state.stepper.is_some(), pending }))
+}
+
+pub async fn step(State(state): State<AppState>) -> (StatusCode, Json<StepStatus>) {
+    let Some(stepper) = &state.stepper else {
+        return (StatusCode::CONFLICT, Json(StepStatus { enabled: false, pending: None }));
+    };
+
+    match stepper.step() {
+        None => (StatusCode::CONFLICT, Json(StepStatus { enabled: true, pending: None })),
+        Some(pending) => {
+            println!("[/admin/step] Node {} released {:?} of instance {}", state.node.id, pending.phase, pending.instance);
+            (StatusCode::OK, Json(StepStatus { enabled: true, pending: Some(pending) }))
+        },
+    }
+}
@@ -0,0 +1,1069 @@
+//! Durable node state in a data directory.
+//!
+//! A node started with `--data-dir <dir>` keeps there:
+//!
+//! - `node.json`, its identity, so a directory can't be picked up by a node
+//!   with another id;
+//! - `wal/`, a write-ahead log with one line per promise, accept and learn,
+//!   and per block of rounds the proposer reserves, see
+//!   [`crate::proposer::Rounds`], each synced to disk before the node
+//!   answers for it, or sends a ballot under it. Entries logged
+//!   close together share one sync: whoever finds its entry not on disk yet
+//!   waits `--wal-group-delay-ms` for more, then syncs all of them, and the
+//!   others wait for that. A thread of its own writes and syncs it, so the
+//!   runtime never waits on the disk. It is split into
+//!   segments of about `--wal-segment-bytes`, each named after the first
+//!   entry it holds, and segments a snapshot fully covers are deleted;
+//! - `snapshot.json`, the ledger, KV store and acceptor as of some log
+//!   position, so a restart only replays the log after it. One is taken on
+//!   `POST /admin/snapshot`, and automatically every `--snapshot-every`
+//!   entries or `--snapshot-interval-ms` with something new in the log;
+//! - `intake.jsonl`, the client commands whose rounds are still under way,
+//!   proposed again on restart, see [`crate::intake`].
+//!
+//! On start the node loads the snapshot and replays the rest of the log on
+//! top. A last line cut short by a crash is dropped; anything else that
+//! doesn't parse stops the node, since guessing would risk breaking a
+//! promise. `paxos inspect --data-dir <dir>` reads the same files without
+//! starting a server.
+//!
+//! With `--encryption-key`, log entries and snapshots are sealed before
+//! they are written, see [`crate::encryption`].
+//!
+//! `paxos backup` puts the snapshot and the log after it into one [`Backup`]
+//! file, which is consistent even while the node runs: the snapshot is
+//! replaced atomically and read first, so the log read after it always
+//! picks up where it ends. With `--encryption-key` the file is sealed like
+//! the directory. `paxos restore` seeds a fresh directory from it.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}, mpsc},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use axum::{
+    http::StatusCode,
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+use tokio::sync::{Notify, oneshot};
+
+use crate::{
+    AppState, Ballot, Id, ProposalId, Value,
+    acceptor::RangePromise,
+    chain::Certificate,
+    chunked,
+    encryption::{Keyring, Purpose},
+    error::PaxosError,
+    history::now_micros,
+    input::Json,
+    intake::Intake,
+    kv::{Kv, StateMachine},
+    mmap::Mmap,
+    trace::Snapshot,
+};
+
+/// Bumped whenever the files change in a way older nodes can't read.
+/// Format 1 kept the whole log in `wal.jsonl`; opening such a directory
+/// moves it into the first segment.
+pub const FORMAT: u32 = 2;
+
+/// Default size at which the log moves on to a new segment.
+pub const SEGMENT_BYTES: u64 = 64 * 1024 * 1024;
+
+const IDENTITY: &str = "node.json";
+const WAL: &str = "wal";
+const LEGACY_WAL: &str = "wal.jsonl";
+const SNAPSHOT: &str = "snapshot.json";
+
+fn segment_path(dir: &Path, first_lsn: u64) -> PathBuf {
+    dir.join(WAL).join(format!("{:020}.jsonl", first_lsn))
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Identity {
+    pub id: Id,
+    pub format: u32,
+    /// Microseconds since the Unix epoch.
+    pub created: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Promised { instance: u64, id: ProposalId },
+    PromisedRange { range: RangePromise },
+    Accepted { ballot: Ballot },
+    Learned { instance: u64, value: Value },
+    Certified { certificate: Certificate },
+    /// The proposer may send ballots with rounds up to this one.
+    Reserved { round: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WalEntry {
+    /// Log sequence number, from 1.
+    pub lsn: u64,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotMeta {
+    /// Last log entry the snapshot covers.
+    pub lsn: u64,
+    pub instances: usize,
+    pub last_instance: Option<u64>,
+    /// Microseconds since the Unix epoch.
+    pub taken: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    pub meta: SnapshotMeta,
+    pub state: Snapshot,
+}
+
+/// Everything in a data directory, as read from disk.
+#[derive(Clone, Debug, Default)]
+pub struct DataDir {
+    pub identity: Option<Identity>,
+    pub snapshot: Option<SnapshotFile>,
+    /// The files of the log, oldest first.
+    pub segments: Vec<Segment>,
+    /// Log entries after the snapshot.
+    pub wal: Vec<WalEntry>,
+    /// Bytes of the last segment up to its last complete entry.
+    pub tail_len: u64,
+    /// Set when the log ends with an entry cut short, which is dropped.
+    pub torn: bool,
+    /// The keys the snapshot and the entries still on disk are sealed with.
+    pub keys: BTreeSet<String>,
+    /// Set when some of them aren't sealed.
+    pub plain: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub path: PathBuf,
+    pub first_lsn: u64,
+    pub bytes: u64,
+}
+
+/// The segments in `dir`, oldest first, counting a format 1 `wal.jsonl`
+/// as one that starts the log.
+fn segments(dir: &Path) -> io::Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+
+    let legacy = dir.join(LEGACY_WAL);
+    if let Ok(meta) = fs::metadata(&legacy) {
+        segments.push(Segment { path: legacy, first_lsn: 1, bytes: meta.len() });
+    }
+
+    let files = match fs::read_dir(dir.join(WAL)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(segments),
+        result => result?,
+    };
+    for file in files {
+        let file = file?;
+        let name = file.file_name();
+        let first_lsn = name.to_str()
+            .and_then(|name| name.strip_suffix(".jsonl"))
+            .and_then(|lsn| lsn.parse().ok());
+
+        if let Some(first_lsn) = first_lsn {
+            segments.push(Segment { path: file.path(), first_lsn, bytes: file.metadata()?.len() });
+        }
+    }
+
+    segments.sort_by_key(|segment| segment.first_lsn);
+    Ok(segments)
+}
+
+/// Log entries have to pick up right after the snapshot, with no gaps.
+fn check_continuity(covered: u64, wal: &[WalEntry]) -> Result<(), String> {
+    let mut lsn = covered;
+    for entry in wal {
+        if entry.lsn != lsn + 1 {
+            return Err(format!("the log jumps from lsn {} to {}", lsn, entry.lsn));
+        }
+        lsn = entry.lsn;
+    }
+    Ok(())
+}
+
+fn invalid(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+}
+
+/// Parses `path` straight from a map of it, so a big snapshot isn't held
+/// in memory both as text and as what it parses into.
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> io::Result<Option<T>> {
+    match Mmap::open(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+        Ok(map) => serde_json::from_slice(&map).map(Some).map_err(|e| invalid(path, e)),
+    }
+}
+
+/// Writes `path` through a temporary file, so a crash leaves either the old
+/// contents or the new ones.
+fn write_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    write_bytes(path, &serde_json::to_vec_pretty(value).unwrap())
+}
+
+fn write_bytes(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Reads the snapshot in `path`, opening it with `keys` if it is sealed,
+/// and notes in `data` which key that took.
+fn read_snapshot(path: &Path, keys: &Keyring, data: &mut DataDir) -> io::Result<Option<SnapshotFile>> {
+    let map = match Mmap::open(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        result => result?,
+    };
+    let opened = keys.open(Purpose::Snapshot, &map).map_err(|e| invalid(path, e))?;
+    data.note(opened.key);
+    serde_json::from_slice(&opened.bytes).map(Some).map_err(|e| invalid(path, e))
+}
+
+fn write_snapshot(path: &Path, snapshot: &SnapshotFile, keys: &Keyring) -> io::Result<()> {
+    write_bytes(path, &keys.seal(Purpose::Snapshot, serde_json::to_vec(snapshot).unwrap()))
+}
+
+/// `entry` as a line of the log, sealed with `keys`.
+fn wal_line(entry: &WalEntry, keys: &Keyring) -> Vec<u8> {
+    let mut line = keys.seal(Purpose::Wal, serde_json::to_vec(entry).unwrap());
+    line.push(b'\n');
+    line
+}
+
+/// How often to read a directory again when a running node purged the log
+/// between reading its snapshot and its segments.
+const READ_ATTEMPTS: usize = 3;
+
+impl DataDir {
+    /// Reads `dir`, which must not have anything sealed.
+    pub fn read(dir: &Path) -> io::Result<Self> {
+        Self::read_with_keys(dir, &Keyring::default())
+    }
+
+    /// Reads `dir`, opening whatever is sealed with `keys`.
+    pub fn read_with_keys(dir: &Path, keys: &Keyring) -> io::Result<Self> {
+        let mut attempt = 1;
+        loop {
+            let data = Self::read_once(dir, keys)?;
+            if attempt == READ_ATTEMPTS || data.check().is_ok() {
+                return Ok(data);
+            }
+            attempt += 1;
+        }
+    }
+
+    fn note(&mut self, key: Option<String>) {
+        match key {
+            Some(key) => {
+                self.keys.insert(key);
+            },
+            None => self.plain = true,
+        }
+    }
+
+    fn read_once(dir: &Path, keys: &Keyring) -> io::Result<Self> {
+        let identity: Option<Identity> = read_json(&dir.join(IDENTITY))?;
+        if let Some(identity) = &identity {
+            if identity.format > FORMAT {
+                return Err(invalid(dir, format!("format {} is newer than this build's {}", identity.format, FORMAT)));
+            }
+        }
+
+        let mut data = Self { identity, ..Self::default() };
+        data.snapshot = read_snapshot(&dir.join(SNAPSHOT), keys, &mut data)?;
+        let covered = data.snapshot.as_ref().map_or(0, |snapshot| snapshot.meta.lsn);
+        let segments = segments(dir)?;
+        let last_segment = segments.len().saturating_sub(1);
+
+        for (i, segment) in segments.iter().enumerate() {
+            let path = &segment.path;
+            let text = fs::read_to_string(path)?;
+
+            let mut offset = 0;
+            for line in text.split_inclusive('\n') {
+                let last = i == last_segment && offset + line.len() == text.len();
+                // A line that is sealed was written whole, so one that
+                // doesn't open wasn't cut short by a crash.
+                let opened = match keys.open(Purpose::Wal, line.trim_end().as_bytes()) {
+                    Ok(opened) => opened,
+                    Err(_) if last && !line.ends_with('\n') => {
+                        data.torn = true;
+                        break;
+                    },
+                    Err(e) => return Err(invalid(path, format!("byte {}: {}", offset, e))),
+                };
+                let entry = serde_json::from_slice::<WalEntry>(&opened.bytes);
+
+                match entry {
+                    Ok(entry) if line.ends_with('\n') => {
+                        data.note(opened.key);
+                        if entry.lsn > covered {
+                            data.wal.push(entry);
+                        }
+                    },
+                    // Only the last write can have been interrupted.
+                    _ if last => {
+                        data.torn = true;
+                        break;
+                    },
+                    Ok(_) => return Err(invalid(path, format!("byte {}: the entry is cut short", offset))),
+                    Err(e) => return Err(invalid(path, format!("byte {}: {}", offset, e))),
+                }
+                offset += line.len();
+            }
+            data.tail_len = offset as u64;
+        }
+
+        data.segments = segments;
+        Ok(data)
+    }
+
+    /// Makes sure no entry is missing between the snapshot and the end of
+    /// the log.
+    pub fn check(&self) -> Result<(), String> {
+        let covered = self.snapshot.as_ref().map_or(0, |snapshot| snapshot.meta.lsn);
+        check_continuity(covered, &self.wal)?;
+
+        match self.segments.first() {
+            Some(segment) if segment.first_lsn > covered + 1 => {
+                Err(format!("the log starts at lsn {}, after the snapshot's {}", segment.first_lsn, covered))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// The next log sequence number to hand out.
+    pub fn next_lsn(&self) -> u64 {
+        let snapshot = self.snapshot.as_ref().map_or(0, |snapshot| snapshot.meta.lsn);
+        self.wal.last().map_or(snapshot, |entry| entry.lsn) + 1
+    }
+
+    /// The state the node had when it stopped: the snapshot with the rest
+    /// of the log applied in order. The KV store gets what the ledger holds
+    /// up to its first gap; see `apply`.
+    pub fn recover(&self) -> Snapshot {
+        self.recover_with(None)
+    }
+
+    /// Like [`DataDir::recover`], applying the log with `machine`, if any;
+    /// see `kv`.
+    pub fn recover_with(&self, machine: Option<Arc<dyn StateMachine>>) -> Snapshot {
+        let mut state = self.snapshot.as_ref().map_or_else(Snapshot::default, |snapshot| snapshot.state.clone());
+        let mut kv = Kv { data: state.kv.into_iter().collect(), machine };
+        let applied = (1..).take_while(|instance| state.ledger.contains_key(instance)).count() as u64;
+
+        for entry in &self.wal {
+            match &entry.record {
+                Record::Promised { instance, id } => {
+                    state.promised = state.promised.max(*id);
+                    if !state.ledger.contains_key(instance) {
+                        let slot = state.acceptor.slots.entry(*instance).or_default();
+                        slot.last_ballot_number = slot.last_ballot_number.max(*id);
+                    }
+                },
+                Record::PromisedRange { range } => {
+                    state.promised = state.promised.max(range.id);
+                    state.acceptor.promise_range(*range);
+                },
+                Record::Accepted { ballot } => {
+                    state.promised = state.promised.max(ballot.id);
+                    if !state.ledger.contains_key(&ballot.instance) {
+                        let slot = state.acceptor.slots.entry(ballot.instance).or_default();
+                        slot.last_ballot_number = slot.last_ballot_number.max(ballot.id);
+                        slot.accepted_proposal = Some(ballot.clone());
+                    }
+                },
+                Record::Learned { instance, value } => {
+                    state.ledger.entry(*instance).or_insert_with(|| value.clone());
+                    state.acceptor.forget(*instance);
+                },
+                Record::Certified { certificate } => {
+                    state.certificates.insert(certificate.instance, certificate.clone());
+                },
+                Record::Reserved { round } => state.reserved = state.reserved.max(*round),
+            }
+        }
+
+        for value in (applied + 1..).map_while(|instance| state.ledger.get(&instance)) {
+            kv.apply(value);
+        }
+        state.kv = kv.data.into_iter().collect();
+        state
+    }
+}
+
+/// A data directory in a single file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Backup {
+    pub format: u32,
+    pub node: Id,
+    /// Microseconds since the Unix epoch.
+    pub taken: u64,
+    pub snapshot: Option<SnapshotFile>,
+    pub wal: Vec<WalEntry>,
+}
+
+impl Backup {
+    pub fn take(dir: &Path) -> io::Result<Self> {
+        Self::take_with_keys(dir, &Keyring::default())
+    }
+
+    /// Backs up `dir`, opening whatever is sealed with `keys`; see
+    /// [`Backup::to_bytes`] to seal the backup itself.
+    pub fn take_with_keys(dir: &Path, keys: &Keyring) -> io::Result<Self> {
+        let data = DataDir::read_with_keys(dir, keys)?;
+        let Some(identity) = data.identity else {
+            return Err(invalid(dir, "not a data directory"));
+        };
+
+        let backup = Self { format: identity.format, node: identity.id, taken: now_micros(), snapshot: data.snapshot, wal: data.wal };
+        backup.check().map_err(|e| invalid(dir, e))?;
+        Ok(backup)
+    }
+
+    /// The backup as a file, sealed with the current key of `keys` if
+    /// there is one, so it holds nothing the directory didn't.
+    pub fn to_bytes(&self, keys: &Keyring) -> Vec<u8> {
+        keys.seal(Purpose::Backup, serde_json::to_vec(self).unwrap())
+    }
+
+    /// What [`Backup::to_bytes`] made. One taken without a key reads
+    /// whatever `keys` holds, so it can seed a sealed directory.
+    pub fn from_bytes(bytes: &[u8], keys: &Keyring) -> Result<Self, String> {
+        let opened = keys.open_or_plain(Purpose::Backup, bytes, true)?;
+        serde_json::from_slice(&opened.bytes).map_err(|e| e.to_string())
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.format > FORMAT {
+            return Err(format!("format {} is newer than this build's {}", self.format, FORMAT));
+        }
+        check_continuity(self.snapshot.as_ref().map_or(0, |snapshot| snapshot.meta.lsn), &self.wal)
+    }
+
+    /// Writes the backup into `dir`, which must not hold a node already.
+    pub fn seed(&self, dir: &Path) -> io::Result<()> {
+        self.seed_with_keys(dir, &Keyring::default())
+    }
+
+    /// Writes the backup into `dir` sealed with `keys`.
+    pub fn seed_with_keys(&self, dir: &Path, keys: &Keyring) -> io::Result<()> {
+        self.check().map_err(|e| invalid(dir, e))?;
+        if dir.join(IDENTITY).exists() || dir.join(WAL).exists() || dir.join(LEGACY_WAL).exists() {
+            return Err(invalid(dir, "already holds a node, restore into an empty directory"));
+        }
+        fs::create_dir_all(dir.join(WAL))?;
+
+        if let Some(snapshot) = &self.snapshot {
+            write_snapshot(&dir.join(SNAPSHOT), snapshot, keys)?;
+        }
+
+        let covered = self.snapshot.as_ref().map_or(0, |snapshot| snapshot.meta.lsn);
+        let mut wal = File::create(segment_path(dir, covered + 1))?;
+        for entry in &self.wal {
+            wal.write_all(&wal_line(entry, keys))?;
+        }
+        wal.sync_all()?;
+
+        // Last, so that a restore that didn't finish isn't taken for a node.
+        write_json(&dir.join(IDENTITY), &Identity { id: self.node, format: FORMAT, created: now_micros() })
+    }
+}
+
+/// Where the next entry goes. Held while an entry is numbered and queued,
+/// so the writer gets them in order.
+#[derive(Debug)]
+struct Wal {
+    next_lsn: u64,
+    ops: Option<mpsc::Sender<Op>>,
+}
+
+/// What the writer thread is asked to do.
+#[derive(Debug)]
+enum Op {
+    Write { lsn: u64, line: Vec<u8> },
+    /// Sync everything written, and answer up to which entry.
+    Sync(oneshot::Sender<io::Result<u64>>),
+}
+
+/// How far the log is on disk, and whether someone is syncing more of it.
+#[derive(Debug, Default)]
+struct Synced {
+    lsn: u64,
+    syncing: bool,
+}
+
+#[derive(Debug)]
+pub struct Storage {
+    node: Id,
+    dir: PathBuf,
+    keys: Keyring,
+    wal: Mutex<Wal>,
+    writer: Option<JoinHandle<()>>,
+    /// First entry of every segment, the one being written last.
+    segments: Arc<Mutex<Vec<u64>>>,
+    synced: Mutex<Synced>,
+    /// Woken whenever a sync ends.
+    sync_done: Notify,
+    syncs: Arc<AtomicU64>,
+    uses_io_uring: bool,
+    intake: Intake,
+    last_snapshot: Mutex<Option<SnapshotMeta>>,
+    /// Held while a snapshot is written.
+    writing: Mutex<()>,
+    /// Microseconds since the Unix epoch; the age of a node without a
+    /// snapshot counts from here.
+    opened: u64,
+}
+
+/// Writes and syncs the log on a thread of its own, so no thread of the
+/// runtime ever waits for the disk. It takes whatever was queued since it
+/// last looked in one go: the entries in one write per segment, and one
+/// sync for everyone who asked for one meanwhile.
+struct Writer {
+    node: Id,
+    dir: PathBuf,
+    segment_bytes: u64,
+    file: File,
+    /// Size of the segment being written.
+    bytes: u64,
+    /// The last entry written.
+    written: u64,
+    segments: Arc<Mutex<Vec<u64>>>,
+    syncs: Arc<AtomicU64>,
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    ring: Option<crate::uring::Ring>,
+    /// Why a write or sync failed, if one did; nothing after it is durable,
+    /// so every sync fails from then on.
+    failed: Option<String>,
+}
+
+impl Writer {
+    fn run(mut self, ops: mpsc::Receiver<Op>) {
+        while let Ok(op) = ops.recv() {
+            let mut lines = Vec::new();
+            let mut waiting = Vec::new();
+            for op in std::iter::once(op).chain(ops.try_iter()) {
+                match op {
+                    Op::Write { lsn, line } => lines.push((lsn, line)),
+                    Op::Sync(reply) => waiting.push(reply),
+                }
+            }
+
+            if self.failed.is_none() {
+                match self.write(lines, !waiting.is_empty()) {
+                    Ok(()) if !waiting.is_empty() => {
+                        self.syncs.fetch_add(1, Ordering::SeqCst);
+                    },
+                    Ok(()) => {},
+                    Err(e) => {
+                        println!("[storage] Node {} failed to write its log: {}", self.node, e);
+                        self.failed = Some(e.to_string());
+                    },
+                }
+            }
+            for reply in waiting {
+                let _ = reply.send(match &self.failed {
+                    Some(e) => Err(io::Error::other(e.clone())),
+                    None => Ok(self.written),
+                });
+            }
+        }
+    }
+
+    /// Writes `lines` in order, moving on to a new segment where the one
+    /// being written is full, then syncs them if `sync`.
+    fn write(&mut self, lines: Vec<(u64, Vec<u8>)>, sync: bool) -> io::Result<()> {
+        let mut pending = Vec::new();
+        for (lsn, line) in lines {
+            if self.bytes + pending.len() as u64 >= self.segment_bytes {
+                // Syncs only ever cover the segment being written.
+                self.write_file(&pending, true)?;
+                pending.clear();
+                self.file = File::create(segment_path(&self.dir, lsn))?;
+                sync_dir(&self.dir.join(WAL))?;
+                self.bytes = 0;
+                self.segments.lock().unwrap().push(lsn);
+            }
+            self.written = lsn;
+            pending.extend_from_slice(&line);
+        }
+        self.write_file(&pending, sync)
+    }
+
+    /// Writes `data` at the end of the segment, in one submission with the
+    /// sync after it when there's a ring.
+    fn write_file(&mut self, data: &[u8], sync: bool) -> io::Result<()> {
+        self.bytes += data.len() as u64;
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(ring) = &mut self.ring {
+            return ring.write(&self.file, self.bytes - data.len() as u64, data, sync);
+        }
+        self.file.write_all(data)?;
+        if sync {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+impl Storage {
+    /// Opens the data directory of node `id`, creating it if needed, and
+    /// returns the state found there.
+    pub fn open(id: Id, dir: &Path, segment_bytes: u64) -> io::Result<(Self, Snapshot)> {
+        Self::open_with_keys(id, dir, segment_bytes, Keyring::default())
+    }
+
+    /// Like [`Storage::open`], sealing what it writes with the current key
+    /// of `keys` and opening what was sealed with any of them.
+    pub fn open_with_keys(id: Id, dir: &Path, segment_bytes: u64, keys: Keyring) -> io::Result<(Self, Snapshot)> {
+        Self::open_with(id, dir, segment_bytes, keys, None)
+    }
+
+    /// Like [`Storage::open_with_keys`], recovering the KV store with
+    /// `machine`, if any.
+    pub fn open_with(id: Id, dir: &Path, segment_bytes: u64, keys: Keyring, machine: Option<Arc<dyn StateMachine>>) -> io::Result<(Self, Snapshot)> {
+        fs::create_dir_all(dir.join(WAL))?;
+
+        if dir.join(LEGACY_WAL).exists() {
+            println!("[storage] Node {} moving its log into segments", id);
+            fs::rename(dir.join(LEGACY_WAL), segment_path(dir, 1))?;
+            sync_dir(&dir.join(WAL))?;
+            sync_dir(dir)?;
+        }
+
+        let data = DataDir::read_with_keys(dir, &keys)?;
+        data.check().map_err(|e| invalid(dir, e))?;
+
+        match &data.identity {
+            Some(identity) if identity.id != id => {
+                return Err(invalid(dir, format!("belongs to node {}, not {}", identity.id, id)));
+            },
+            Some(identity) if identity.format < FORMAT => {
+                write_json(&dir.join(IDENTITY), &Identity { format: FORMAT, ..identity.clone() })?;
+            },
+            Some(_) => {},
+            None => write_json(&dir.join(IDENTITY), &Identity { id, format: FORMAT, created: now_micros() })?,
+        }
+
+        let next_lsn = data.next_lsn();
+        let mut segments: Vec<u64> = data.segments.iter().map(|segment| segment.first_lsn).collect();
+        if segments.is_empty() {
+            segments.push(next_lsn);
+        }
+        let active = segment_path(dir, *segments.last().unwrap());
+
+        let mut file = OpenOptions::new().create(true).truncate(false).write(true).open(&active)?;
+        if data.torn {
+            println!("[storage] Node {} dropping an incomplete entry at the end of its log", id);
+            file.set_len(data.tail_len)?;
+            file.sync_all()?;
+        }
+        let bytes = io::Seek::seek(&mut file, io::SeekFrom::End(0))?;
+
+        let segments = Arc::new(Mutex::new(segments));
+        let syncs = Arc::new(AtomicU64::new(0));
+        let writer = Writer {
+            node: id,
+            dir: dir.to_path_buf(),
+            segment_bytes: segment_bytes.max(1),
+            file,
+            bytes,
+            written: next_lsn - 1,
+            segments: segments.clone(),
+            syncs: syncs.clone(),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            ring: crate::uring::Ring::new()
+                .inspect_err(|e| println!("[storage] Node {} writing its log without io_uring: {}", id, e))
+                .ok(),
+            failed: None,
+        };
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        let uses_io_uring = writer.ring.is_some();
+        #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+        let uses_io_uring = false;
+        let (ops, queued) = mpsc::channel();
+        let writer = thread::Builder::new().name(format!("wal-{}", id)).spawn(move || writer.run(queued))?;
+
+        let storage = Self {
+            node: id,
+            dir: dir.to_path_buf(),
+            keys,
+            wal: Mutex::new(Wal { next_lsn, ops: Some(ops) }),
+            writer: Some(writer),
+            segments,
+            synced: Mutex::new(Synced { lsn: next_lsn - 1, syncing: false }),
+            sync_done: Notify::new(),
+            syncs,
+            uses_io_uring,
+            intake: Intake::open(dir)?,
+            last_snapshot: Mutex::new(data.snapshot.as_ref().map(|snapshot| snapshot.meta.clone())),
+            writing: Mutex::new(()),
+            opened: now_micros(),
+        };
+        Ok((storage, data.recover_with(machine)))
+    }
+
+    /// Client commands taken and not finished yet.
+    pub fn intake(&self) -> &Intake {
+        &self.intake
+    }
+
+    /// Segments of the log on disk.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn segments(&self) -> usize {
+        self.segments.lock().unwrap().len()
+    }
+
+    pub fn last_snapshot(&self) -> Option<SnapshotMeta> {
+        self.last_snapshot.lock().unwrap().clone()
+    }
+
+    /// Log entries written since the last snapshot.
+    pub fn entries_since_snapshot(&self) -> u64 {
+        let covered = self.last_snapshot().map_or(0, |meta| meta.lsn);
+        self.position() - covered
+    }
+
+    /// Microseconds since the last snapshot, or since the node started.
+    pub fn snapshot_age(&self) -> u64 {
+        let since = self.last_snapshot().map_or(self.opened, |meta| meta.taken);
+        now_micros().saturating_sub(since)
+    }
+
+    /// Whether the log is written through io_uring.
+    pub fn uses_io_uring(&self) -> bool {
+        self.uses_io_uring
+    }
+
+    /// Syncs of the log since the node started.
+    pub fn syncs(&self) -> u64 {
+        self.syncs.load(Ordering::SeqCst)
+    }
+
+    /// Appends `record` and syncs it, returning its sequence number. Blocks
+    /// until it is on disk, so never call it on the runtime.
+    pub fn append(&self, record: Record) -> io::Result<u64> {
+        let lsn = self.write(record)?;
+        let upto = futures::executor::block_on(self.request_sync()?).map_err(|_| stopped())??;
+
+        let mut synced = self.synced.lock().unwrap();
+        synced.lsn = synced.lsn.max(upto);
+        Ok(lsn)
+    }
+
+    /// Queues `record` to be written, returning its sequence number to pass
+    /// to [`Storage::sync`]. A write that fails shows when it is synced.
+    pub fn write(&self, record: Record) -> io::Result<u64> {
+        let mut wal = self.wal.lock().unwrap();
+        let entry = WalEntry { lsn: wal.next_lsn, record };
+        let line = wal_line(&entry, &self.keys);
+        let ops = wal.ops.as_ref().ok_or_else(stopped)?;
+        ops.send(Op::Write { lsn: entry.lsn, line }).map_err(|_| stopped())?;
+        wal.next_lsn += 1;
+        Ok(entry.lsn)
+    }
+
+    /// Asks the writer to sync what it wrote; the answer says up to which
+    /// entry.
+    fn request_sync(&self) -> io::Result<oneshot::Receiver<io::Result<u64>>> {
+        let (reply, synced) = oneshot::channel();
+        let wal = self.wal.lock().unwrap();
+        let ops = wal.ops.as_ref().ok_or_else(stopped)?;
+        ops.send(Op::Sync(reply)).map_err(|_| stopped())?;
+        Ok(synced)
+    }
+
+    /// Waits until the log is on disk up to `lsn`. If no one is syncing it
+    /// yet, waits `delay` for more entries and syncs all of them.
+    pub async fn sync(&self, lsn: u64, delay: Duration) -> io::Result<()> {
+        loop {
+            let done = self.sync_done.notified();
+            tokio::pin!(done);
+            done.as_mut().enable();
+
+            let lead = {
+                let mut synced = self.synced.lock().unwrap();
+                if synced.lsn >= lsn {
+                    return Ok(());
+                }
+                !std::mem::replace(&mut synced.syncing, true)
+            };
+            if lead {
+                return self.sync_group(delay).await;
+            }
+            done.await;
+        }
+    }
+
+    async fn sync_group(&self, delay: Duration) -> io::Result<()> {
+        // Lets the others know even if this one is dropped halfway.
+        struct Syncing<'a>(&'a Storage);
+        impl Drop for Syncing<'_> {
+            fn drop(&mut self) {
+                self.0.synced.lock().unwrap().syncing = false;
+                self.0.sync_done.notify_waiters();
+            }
+        }
+        let _syncing = Syncing(self);
+
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        // Entries queued meanwhile go with this sync or the next.
+        let upto = self.request_sync()?.await.map_err(|_| stopped())??;
+
+        let mut synced = self.synced.lock().unwrap();
+        synced.lsn = synced.lsn.max(upto);
+        Ok(())
+    }
+
+    /// Deletes the segments whose every entry is at or before `lsn`, all but
+    /// the one being written.
+    fn purge(&self, lsn: u64) -> io::Result<usize> {
+        let mut segments = self.segments.lock().unwrap();
+        let covered = segments.windows(2).take_while(|pair| pair[1] - 1 <= lsn).count();
+
+        for first_lsn in segments.drain(..covered) {
+            fs::remove_file(segment_path(&self.dir, first_lsn))?;
+        }
+        if covered > 0 {
+            sync_dir(&self.dir.join(WAL))?;
+        }
+        Ok(covered)
+    }
+
+    /// The sequence number of the last entry written.
+    pub fn position(&self) -> u64 {
+        self.wal.lock().unwrap().next_lsn - 1
+    }
+
+    /// Writes `state`, a copy of everything logged up to `lsn`, as the
+    /// snapshot, and deletes the log it covers. Blocks on the disk, so it
+    /// runs off the runtime; one snapshot is written at a time, and one
+    /// older than the last is left out.
+    pub fn snapshot(&self, lsn: u64, state: Snapshot) -> io::Result<SnapshotMeta> {
+        let _writing = self.writing.lock().unwrap();
+        if let Some(last) = self.last_snapshot().filter(|last| last.lsn > lsn) {
+            return Ok(last);
+        }
+        let meta = SnapshotMeta {
+            lsn,
+            instances: state.ledger.len(),
+            last_instance: state.ledger.keys().max().copied(),
+            taken: now_micros(),
+        };
+
+        write_snapshot(&self.dir.join(SNAPSHOT), &SnapshotFile { meta: meta.clone(), state }, &self.keys)?;
+        *self.last_snapshot.lock().unwrap() = Some(meta.clone());
+
+        // The snapshot is on disk, so the log it covers can go; if that
+        // fails the next snapshot tries again.
+        match self.purge(meta.lsn) {
+            Ok(0) => {},
+            Ok(purged) => println!("[storage] Node {} deleted {} log segments up to lsn {}", self.node, purged, meta.lsn),
+            Err(e) => println!("[storage] Node {} failed to delete old log segments: {}", self.node, e),
+        }
+        Ok(meta)
+    }
+}
+
+impl Drop for Storage {
+    /// Lets the writer finish what was queued.
+    fn drop(&mut self) {
+        self.wal.get_mut().unwrap().ops = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the log's writer stopped")
+}
+
+/// Logs `record` before the node acts on it, when it has a data directory,
+/// and returns where it went; it is only safe to answer for it once
+/// [`durable`] says so, which may be after letting go of any locks.
+pub fn log(state: &AppState, record: Record) -> Result<Option<u64>, PaxosError> {
+    let Some(storage) = &state.storage else {
+        return Ok(None);
+    };
+
+    storage.write(record).map(Some).map_err(|e| {
+        println!("[storage] Node {} failed to write its log: {}", state.node.id, e);
+        PaxosError::Storage(e)
+    })
+}
+
+/// Waits for a record [`log`] wrote to be on disk, or passes on why it
+/// couldn't write it.
+pub async fn durable(state: &AppState, logged: Result<Option<u64>, PaxosError>) -> Result<(), PaxosError> {
+    let (Some(storage), Some(lsn)) = (&state.storage, logged?) else {
+        return Ok(());
+    };
+
+    let delay = state.settings.read().unwrap().wal_group_delay;
+    storage.sync(lsn, delay).await.map_err(|e| {
+        println!("[storage] Node {} failed to sync its log: {}", state.node.id, e);
+        PaxosError::Storage(e)
+    })
+}
+
+/// Puts what a data directory held back into a fresh node.
+pub async fn restore(state: &AppState, snapshot: Snapshot) {
+    let mut ledger = state.ledger.write().await;
+    state.applier.caught_up().await;
+    ledger.replace(snapshot.ledger.into_iter().collect());
+    ledger.restore_rewrites(snapshot.rewrites);
+    state.kv.lock().await.data = snapshot.kv.into_iter().collect();
+    state.applier.restart(state);
+    std::mem::drop(ledger);
+    // The proposer picks up past every ballot it may have sent, and every
+    // one this node promised, so it never sends one twice.
+    let promised = snapshot.acceptor.highest().max(snapshot.promised);
+    state.epoch.observe(promised);
+    state.rounds.restore(snapshot.reserved.max(promised.round));
+    *state.acceptor.lock().await = snapshot.acceptor;
+    state.certificates.replace(snapshot.certificates);
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotResult {
+    pub error: Option<String>,
+    pub snapshot: Option<SnapshotMeta>,
+}
+
+/// Snapshots the node's current state, if it has a data directory.
+pub async fn snapshot(state: &AppState) -> Option<io::Result<SnapshotMeta>> {
+    let storage = state.storage.as_ref()?;
+
+    // Handlers log while holding one of these, so nothing slips in between
+    // the copy and the log position it's stamped with. Only the copy is
+    // made under them; writing it out goes on without them.
+    let acceptor = state.acceptor.lock().await;
+    let ledger = state.ledger.write().await;
+    state.applier.caught_up().await;
+    let kv = state.kv.lock().await;
+    let snapshot = Snapshot {
+        ledger: ledger.to_map().into_iter().collect::<BTreeMap<_, _>>(),
+        kv: kv.data.clone().into_iter().collect(),
+        acceptor: acceptor.clone(),
+        certificates: state.certificates.to_map(),
+        promised: state.epoch.get(),
+        reserved: state.rounds.reserved(),
+        rewrites: ledger.all_rewrites(),
+    };
+    let lsn = storage.position();
+    std::mem::drop((kv, ledger, acceptor));
+
+    let writer = storage.clone();
+    let result = tokio::task::spawn_blocking(move || writer.snapshot(lsn, snapshot)).await.unwrap_or_else(|e| Err(io::Error::other(e)));
+
+    #[cfg(feature = "s3")]
+    if result.is_ok() {
+        tokio::spawn({
+            let (state, path) = (state.clone(), storage.dir.join(SNAPSHOT));
+            async move { crate::s3::upload_snapshot(state, &path).await }
+        });
+    }
+
+    Some(result)
+}
+
+/// How often [`run`] looks at the settings again.
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+
+/// Takes a snapshot whenever enough entries were logged, or enough time
+/// went by with something new in the log.
+pub async fn run(state: AppState) {
+    let Some(storage) = state.storage.clone() else {
+        return;
+    };
+
+    loop {
+        tokio::time::sleep(CHECK_EVERY).await;
+
+        let (every, interval) = {
+            let settings = state.settings.read().unwrap();
+            (settings.snapshot_every, settings.snapshot_interval)
+        };
+        let entries = storage.entries_since_snapshot();
+        let age = Duration::from_micros(storage.snapshot_age());
+
+        let due = (every > 0 && entries >= every) || (!interval.is_zero() && age >= interval && entries > 0);
+        if !due {
+            continue;
+        }
+
+        match snapshot(&state).await {
+            Some(Ok(meta)) => println!("[storage] Node {} took a snapshot at lsn {} after {} entries", state.node.id, meta.lsn, entries),
+            Some(Err(e)) => println!("[storage] Node {} failed to take a snapshot: {}", state.node.id, e),
+            None => return,
+        }
+    }
+}
+
+pub async fn take_snapshot(State(state): State<AppState>) -> (StatusCode, Json<SnapshotResult>) {
+    let Some(result) = snapshot(&state).await else {
+        let result = SnapshotResult { error: Some(String::from("Node has no data directory!")), snapshot: None };
+        return (StatusCode::CONFLICT, Json(result));
+    };
+
+    match result {
+        Ok(meta) => {
+            println!("[/admin/snapshot] Node {} took a snapshot at lsn {}", state.node.id, meta.lsn);
+            (StatusCode::OK, Json(SnapshotResult { error: None, snapshot: Some(meta) }))
+        },
+        Err(e) => {
+            println!("[/admin/snapshot] Node {} failed to take a snapshot: {}", state.node.id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(SnapshotResult { error: Some(e.to_string()), snapshot: None }))
+        },
+    }
+}
+
+/// The latest snapshot, in chunks, sent out of a map of the file.
+pub async fn get_snapshot(State(state): State<AppState>) -> Response {
+    let Some(storage) = &state.storage else {
+        return (StatusCode::CONFLICT, String::from("Node has no data directory!")).into_response();
+    };
+
+    // Snapshots are renamed into place, so this one stays whole while it
+    // is sent even if another is taken meanwhile.
+    match Mmap::open(&storage.dir().join(SNAPSHOT)) {
+        Ok(map) => chunked::respond(std::io::Cursor::new(map)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (StatusCode::NOT_FOUND, String::from("Node has no snapshot yet!")).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
@@ -0,0 +1,157 @@
+//! Per-key command streams, so a group proposes writes to unrelated keys
+//! side by side.
+//!
+//! A group's proposer runs one proposal at a time, on the instance after
+//! the last one learned, and the proposers of different nodes all fight
+//! over that one instance. With `--streams <n>` above 1, a group splits its
+//! log into `n` interleaved streams: instance `i` is in stream `(i - 1) %
+//! n`, and a KV command goes to the stream its key hashes to, by the hash
+//! `shards` uses. Each stream has a proposer task of its own, which only
+//! proposes in the stream's instances, from the first one above what the
+//! group has in order. Commands in different streams are proposed at once,
+//! and two nodes writing unrelated keys rarely want the same instance.
+//! Commands on one key all go through the same stream, one after the
+//! other, into ever higher instances, so they keep their order. Values
+//! that aren't KV commands, and expiries, go to stream 0.
+//!
+//! A stream with nothing to propose leaves holes in the log below the
+//! instances the busy ones decide, and the KV store applies in instance
+//! order. So every [`FILL_EVERY`], a node fills a hole that outlasted
+//! [`FILL_AFTER`] with an empty value, through the stream's own proposer,
+//! the way `takeover` does with a stuck instance. A proposal that loses
+//! its instance to a fill tries the stream's next one.
+//!
+//! A stream never prepares ahead, as a range would span the others.
+//! Commands on different keys can land in the log in another order than
+//! they were made in; each key's history is still linearizable, which is
+//! what makes the whole store so.
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::{
+    AppState, Value,
+    kv::Command,
+    proposer::ProposerHandle,
+    shards,
+    takeover::{self, Takeovers},
+};
+
+/// How often a node looks for holes the streams left.
+pub const FILL_EVERY: Duration = Duration::from_millis(20);
+
+/// How long a hole stays before it is filled.
+pub const FILL_AFTER: Duration = Duration::from_millis(100);
+
+/// The instances a proposer proposes in: the whole log, or one stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stream {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Default for Stream {
+    fn default() -> Self {
+        Self { index: 0, count: 1 }
+    }
+}
+
+impl Stream {
+    /// The stream of `count` that `instance` is in.
+    pub fn of(instance: u64, count: u64) -> u64 {
+        instance.saturating_sub(1) % count.max(1)
+    }
+
+    /// The instance to propose in next: the first one of the stream above
+    /// what the node has in order that it hasn't learned.
+    pub fn next_instance(&self, state: &AppState) -> u64 {
+        if self.count <= 1 {
+            return state.next_instance();
+        }
+        let from = state.ledger.chained() + 1;
+        let mut instance = from + (self.index + self.count - (from - 1) % self.count) % self.count;
+        while state.ledger.get(instance).is_some() {
+            instance += self.count;
+        }
+        instance
+    }
+}
+
+/// A group's streams, and the proposer of each.
+#[derive(Debug)]
+pub struct Streams {
+    proposers: Vec<ProposerHandle>,
+    holes: Takeovers,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl Streams {
+    /// `count` streams; 1 or less leaves the whole log to the group's own
+    /// proposer.
+    pub fn new(count: u64) -> Self {
+        let proposers = match count {
+            0 | 1 => Vec::new(),
+            count => (0..count).map(|index| ProposerHandle::for_stream(Stream { index, count })).collect(),
+        };
+        Self { proposers, holes: Takeovers::default() }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.proposers.len().max(1) as u64
+    }
+
+    /// The proposer for `instance`, which alone proposes in it.
+    pub fn proposer<'a>(&'a self, state: &'a AppState, instance: u64) -> &'a ProposerHandle {
+        match self.proposers.len() {
+            0 => &state.proposer,
+            count => &self.proposers[Stream::of(instance, count as u64) as usize],
+        }
+    }
+
+    /// The proposer `value` goes to.
+    pub fn route<'a>(&'a self, state: &'a AppState, value: &Value) -> &'a ProposerHandle {
+        let key = match Command::parse(value) {
+            _ if self.proposers.is_empty() => return &state.proposer,
+            Some(Command::Put { key, .. } | Command::Delete { key }) => key,
+            _ => return &self.proposers[0],
+        };
+        &self.proposers[shards::of(&key, self.proposers.len())]
+    }
+}
+
+/// Fills the holes that outlasted [`FILL_AFTER`] by `now`, and answers
+/// which it filled.
+pub async fn fill(state: &AppState, now: Instant) -> Vec<u64> {
+    let streams = &state.streams;
+    if streams.count() <= 1 || state.node.learner || state.pbft.is_enabled() || state.is_paused() || state.is_syncing() {
+        return Vec::new();
+    }
+
+    // Above the last instance learned, a proposer may well be at work.
+    let last = state.ledger.last().unwrap_or(0);
+    let mut holes = takeover::undecided(state).await;
+    holes.retain(|&instance| instance < last);
+
+    let mut filled = Vec::new();
+    for instance in streams.holes.overdue(&holes, now, FILL_AFTER) {
+        match streams.proposer(state, instance).take_over(state, instance).await {
+            Ok(_) => filled.push(instance),
+            Err(e) => println!("[streams] Node {} couldn't fill instance {}: {}", state.node.id, instance, e),
+        }
+    }
+    filled
+}
+
+/// Fills holes as long as the node runs.
+pub async fn run(state: AppState) {
+    loop {
+        tokio::time::sleep(FILL_EVERY).await;
+        fill(&state, Instant::now()).await;
+    }
+}
+
@@ -0,0 +1,127 @@
+//! How every member of a cluster is doing, as seen from each of them.
+//!
+//! `cluster-status` asks one node for the members it knows, then asks each
+//! member for its state: whether it answers, which node it takes for the
+//! leader, how far it learned and applied, and the hash its audit chain
+//! has at the last instance every member that answered learned, see
+//! `chain`. Members whose hashes differ there learned different values;
+//! the table marks them, the ones that take another leader than most do
+//! and the ones behind the others.
+
+use std::{collections::BTreeMap, fmt, net::SocketAddr};
+use futures::future::join_all;
+
+use crate::{Id, client::{Client, ClientError}, status::NodeState};
+
+/// One member, as it answered.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub id: Id,
+    pub node: String,
+    /// What it answered, or why it didn't.
+    pub state: Result<NodeState, String>,
+    /// Its chain's hash at [`Survey::at`], in hex.
+    pub hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Survey {
+    pub members: Vec<Member>,
+    /// The last instance every member that answered learned, where their
+    /// chains are compared.
+    pub at: u64,
+}
+
+/// The URL `addr` is reached at, from a client that reached the cluster at
+/// `host`: a node listening on every interface is reached where the client
+/// reached the first one.
+pub fn url(addr: SocketAddr, host: &str) -> String {
+    if addr.ip().is_unspecified() {
+        format!("http://{}:{}", host, addr.port())
+    } else {
+        format!("http://{}", addr)
+    }
+}
+
+/// Asks `client`'s node for the members, and each of them how it is doing.
+pub async fn run(client: &Client) -> Result<Survey, ClientError> {
+    let seed = client.state().await?;
+    let host = client.host();
+
+    let mut members = vec![(seed.node.id, client.clone())];
+    members.extend(seed.members.iter().map(|member| (member.node.id, Client::new(&url(member.node.addr, &host)))));
+    members.sort_by_key(|(id, _)| *id);
+
+    let states = join_all(members.iter().map(|(_, client)| client.state())).await;
+    let at = states.iter().flatten().map(|state| state.status.commit_index).min().unwrap_or(0);
+    let hashes = join_all(members.iter().zip(&states).map(|((_, client), state)| async move {
+        match state {
+            Ok(_) if at > 0 => client.link(at).await.ok().flatten().map(|link| link.hash),
+            _ => None,
+        }
+    })).await;
+
+    let members = members.into_iter().zip(states).zip(hashes)
+        .map(|(((id, client), state), hash)| Member { id, node: client.node().to_string(), state: state.map_err(|e| e.to_string()), hash })
+        .collect();
+    Ok(Survey { members, at })
+}
+
+impl Survey {
+    /// What most of the members that answered say, if they say anything.
+    fn majority<T: Ord + Clone>(&self, of: impl Fn(&Member) -> Option<T>) -> Option<T> {
+        let mut counts: BTreeMap<T, usize> = BTreeMap::new();
+        for value in self.members.iter().filter_map(of) {
+            *counts.entry(value).or_default() += 1;
+        }
+        counts.into_iter().max_by_key(|(_, count)| *count).map(|(value, _)| value)
+    }
+
+    fn furthest(&self) -> u64 {
+        self.members.iter().filter_map(|member| member.state.as_ref().ok()).map(|state| state.status.commit_index).max().unwrap_or(0)
+    }
+
+    /// What is off about `member`, compared to the others.
+    pub fn issues(&self, member: &Member) -> Vec<String> {
+        let Ok(state) = &member.state else {
+            return vec![String::from("unreachable")];
+        };
+        let mut issues = Vec::new();
+        let lag = self.furthest() - state.status.commit_index;
+        if lag > 0 {
+            issues.push(format!("{} behind", lag));
+        }
+        let leader = self.majority(|member| member.state.as_ref().ok()?.leader);
+        if state.leader.is_some() && state.leader != leader {
+            issues.push(String::from("other leader"));
+        }
+        let hash = self.majority(|member| member.hash.clone());
+        if member.hash.is_some() && member.hash != hash {
+            issues.push(format!("diverged by instance {}", self.at));
+        }
+        issues
+    }
+
+    /// Every member answered, and none diverged.
+    pub fn is_healthy(&self) -> bool {
+        self.members.iter().all(|member| member.state.is_ok() && !self.issues(member).iter().any(|issue| issue.starts_with("diverged")))
+    }
+}
+
+impl fmt::Display for Survey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>4}  {:<24} {:>6} {:>8} {:>8}  {:<16} ISSUES", "ID", "NODE", "LEADER", "COMMIT", "APPLIED", "CHAIN")?;
+        for member in &self.members {
+            let issues = self.issues(member).join(", ");
+            match &member.state {
+                Ok(state) => {
+                    let leader = state.leader.map_or(String::from("-"), |leader| leader.to_string());
+                    let hash = member.hash.as_deref().map_or("-", |hash| &hash[..hash.len().min(16)]);
+                    writeln!(f, "{:>4}  {:<24} {:>6} {:>8} {:>8}  {:<16} {}", member.id, member.node, leader, state.status.commit_index, state.applied_index, hash, issues)?;
+                },
+                Err(e) => writeln!(f, "{:>4}  {:<24} {:>6} {:>8} {:>8}  {:<16} {}: {}", member.id, member.node, "-", "-", "-", "-", issues, e)?,
+            }
+        }
+        writeln!(f, "Chains compared at instance {}.", self.at)
+    }
+}
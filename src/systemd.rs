@@ -0,0 +1,72 @@
+//! Readiness and watchdog notifications for nodes run as systemd services.
+//!
+//! With `Type=notify` systemd sets `NOTIFY_SOCKET`, and the node sends
+//! `READY=1` once it has recovered its state and listens, and `STOPPING=1`
+//! when it starts to drain. With `WatchdogSec=` it also sets
+//! `WATCHDOG_USEC`, and the node sends `WATCHDOG=1` twice per period, but
+//! only after it got hold of its acceptor and ledger: a node stuck on them
+//! answers nothing, so systemd should restart it.
+//!
+//! Outside systemd, or off unix, all of this does nothing.
+
+use std::time::Duration;
+
+use crate::AppState;
+
+/// Sends `message` to the service manager, if there is one.
+#[cfg(unix)]
+pub fn notify(message: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        let path = path.to_string_lossy();
+        // A leading `@` is an abstract socket, which only Linux has.
+        #[cfg(target_os = "linux")]
+        if let Some(name) = path.strip_prefix('@') {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            return socket.send_to_addr(message.as_bytes(), &addr);
+        }
+        socket.send_to(message.as_bytes(), &*path)
+    });
+
+    if let Err(e) = result {
+        println!("[systemd] Failed to notify {:?}: {}", message, e);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_message: &str) {}
+
+/// How often systemd wants to hear from us, if it watches us at all.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Pets the watchdog for as long as the node keeps making progress.
+pub async fn watchdog(state: AppState) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    println!("[systemd] Node {} petting the watchdog every {:?}", state.node.id, interval / 2);
+
+    loop {
+        tokio::time::sleep(interval / 2).await;
+
+        let acceptor = state.acceptor.lock().await;
+        let ledger = state.ledger.write().await;
+        std::mem::drop((ledger, acceptor));
+
+        notify("WATCHDOG=1");
+    }
+}
@@ -0,0 +1,155 @@
+//! Taking over an instance its proposer left undecided.
+//!
+//! A proposer that dies between its accepts and its learn leaves an
+//! instance some voters accepted a value in, and no one learned. Nothing
+//! else would ever decide it: proposers go on with the instances after the
+//! last one learned, and asking the voters for the log, as `apply` does
+//! for a gap, finds nothing to hand over. The KV store, which applies in
+//! instance order, would stay stuck behind it for good.
+//!
+//! Every second, each voter looks for instances below the last one it
+//! learned that it hasn't learned, and for those it accepted a value in
+//! without learning it. One that stays that way for
+//! `--stuck-instance-timeout-ms` is taken over: the node runs both phases
+//! for it under a ballot of its own, higher than any so far. Paxos forces
+//! the value any quorum may have chosen, or the one accepted under the
+//! highest ballot; with nothing accepted, the node proposes an empty value,
+//! which the KV store ignores. It then learns it and sends the learn out as
+//! for any other decision. A round refused for a higher ballot is run again
+//! over it, up to three times, before it waits for the next check.
+//!
+//! Voters wait longer the later they come in the list of voters, by up to
+//! the timeout again, so one of them usually takes an instance over before
+//! the others try. Two that do anyway only preempt each other, and Paxos
+//! keeps them from deciding two values. `paxos_instances_taken_over` in
+//! `GET /metrics` counts the instances this node took over.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Mutex, atomic::{AtomicU64, Ordering}},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::{
+    AppState, Ballot, ProposalId, Value,
+    chain,
+    error::PaxosError,
+    handlers,
+    proposer::Proposer,
+};
+
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+
+/// Rounds a takeover runs before it waits for the next check.
+const ATTEMPTS: usize = 3;
+
+/// Gaps below the last instance learned a check looks at, at most.
+const MAX_GAPS: usize = 64;
+
+/// The instances a node found undecided, and since when.
+#[derive(Debug, Default)]
+pub struct Takeovers {
+    seen: Mutex<BTreeMap<u64, Instant>>,
+    taken: AtomicU64,
+}
+
+impl Takeovers {
+    /// Notes which instances are undecided at `now`, and answers those that
+    /// were at least `after` ago too.
+    pub(crate) fn overdue(&self, undecided: &BTreeSet<u64>, now: Instant, after: Duration) -> Vec<u64> {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|instance, _| undecided.contains(instance));
+        undecided.iter()
+            .filter(|&&instance| now.duration_since(*seen.entry(instance).or_insert(now)) >= after)
+            .copied()
+            .collect()
+    }
+
+    /// Instances this node took over.
+    pub fn taken(&self) -> u64 {
+        self.taken.load(Ordering::SeqCst)
+    }
+}
+
+/// Instances this node knows were proposed and doesn't know the value of.
+pub async fn undecided(state: &AppState) -> BTreeSet<u64> {
+    let last = state.ledger.last().unwrap_or(0);
+    let mut undecided: BTreeSet<u64> = (state.ledger.chained() + 1..last)
+        .filter(|&instance| state.ledger.get(instance).is_none())
+        .take(MAX_GAPS)
+        .collect();
+
+    let acceptor = state.acceptor.lock().await;
+    undecided.extend(acceptor.slots.iter()
+        .filter(|(_, slot)| slot.accepted_proposal.is_some())
+        .map(|(instance, _)| *instance)
+        .filter(|&instance| state.ledger.get(instance).is_none()));
+    undecided
+}
+
+/// How long this node leaves an instance undecided before it takes it
+/// over: the timeout, and a share of it again for each voter ahead of it.
+fn patience(state: &AppState, timeout: Duration) -> Duration {
+    let voters = state.voters();
+    let ahead = voters.iter().filter(|node| node.id < state.node.id).count();
+    timeout + timeout.mul_f64(ahead as f64 / voters.len().max(1) as f64)
+}
+
+/// Takes over the instances that stayed undecided for too long by `now`,
+/// and answers which ones it decided.
+pub async fn check(state: &AppState, now: Instant) -> Vec<u64> {
+    let timeout = state.settings.read().unwrap().stuck_instance_timeout;
+    if timeout.is_zero() || state.node.learner || state.pbft.is_enabled() || state.is_paused() || state.is_syncing() {
+        return Vec::new();
+    }
+
+    let overdue = state.takeovers.overdue(&undecided(state).await, now, patience(state, timeout));
+    let mut decided = Vec::new();
+    for instance in overdue {
+        println!("[takeover] Node {} finds instance {} still undecided, taking it over", state.node.id, instance);
+        match state.streams.proposer(state, instance).take_over(state, instance).await {
+            Ok(ballot) => {
+                println!("[takeover] Node {} decided {:?} for instance {}", state.node.id, ballot.value, instance);
+                decided.push(instance);
+            },
+            Err(e) => println!("[takeover] Node {} couldn't take over instance {}: {}", state.node.id, instance, e),
+        }
+    }
+    decided
+}
+
+/// Both phases for `instance`, run by the proposer task, which alone owns
+/// `proposer`.
+pub(crate) async fn decide(state: &AppState, proposer: &mut Proposer, instance: u64) -> Result<Ballot, PaxosError> {
+    if let Some(value) = state.ledger.get(instance) {
+        return Ok(Ballot { instance, id: ProposalId::default(), value: Some(value) });
+    }
+
+    // A refusal names the ballot to beat, so the next attempt can.
+    let mut attempt = 1;
+    let (ballot, certificate) = loop {
+        let result = match proposer.prepare(state, instance, Value::new()).await {
+            Ok(ballot) => proposer.propose(state, &ballot).await.map(|certificate| (ballot, certificate)),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(decided) => break decided,
+            Err(e) if attempt == ATTEMPTS => return Err(e),
+            Err(_) => attempt += 1,
+        }
+    };
+    handlers::learn(state, &ballot).await;
+    chain::certify(state, certificate).await;
+    state.learns.push(state, ballot.clone(), false);
+    state.takeovers.taken.fetch_add(1, Ordering::SeqCst);
+    Ok(ballot)
+}
+
+/// Looks for stuck instances every second, as long as the node runs.
+pub async fn run(state: AppState) {
+    loop {
+        tokio::time::sleep(CHECK_EVERY).await;
+        check(&state, Instant::now()).await;
+    }
+}
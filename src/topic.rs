@@ -0,0 +1,219 @@
+//! Topics, a small message bus on the log.
+//!
+//! `POST /topics/<topic>` publishes its body to `topic`, and answers the
+//! message's offset in the topic once it is committed: offsets count from
+//! 0 in each topic, in log order, the same on every node.
+//! `GET /topics/<topic>?from=<offset>` answers the messages from `from` on,
+//! and waits up to `timeout_ms` for one when there is none yet, so a
+//! subscriber follows a topic by asking again from the `next` it was
+//! answered, and never sees another topic's messages.
+//!
+//! Each publish is a [`Command::Publish`] in the node's own log, and the
+//! messages are kept in the KV store under [`PREFIX`], which makes them
+//! replicated and snapshotted with everything else. A topic keeps its last
+//! [`RETAIN`] messages; a subscriber whose `from` is below `oldest` fell
+//! behind and missed some.
+//!
+//! `?since=<index>` reads by commit index instead, like a watch: the
+//! messages committed after instance `since`, out of the changes the node
+//! keeps (see `watch`), and `index`, the instance to read from next. A
+//! `since` further back than those gets a `410`.
+
+use std::time::Duration;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+use tokio::time::Instant;
+
+use crate::{
+    AppState,
+    acl::{self, Op},
+    chunked::Upload,
+    input::{Json, Path, Query},
+    intake,
+    kv::{self, Change, Command, Kv},
+    shutdown,
+};
+
+/// Where topics are kept in the KV store.
+pub const PREFIX: &str = "__topic/";
+
+/// Messages a topic keeps.
+pub const RETAIN: u64 = 10_000;
+
+/// Most messages a single read answers.
+const PAGE: usize = 1000;
+
+/// How long a read waits for a message, unless told otherwise.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_WAIT: Duration = Duration::from_secs(300);
+
+fn key(topic: &str) -> String {
+    format!("{}{}", PREFIX, topic)
+}
+
+fn message_key(topic: &str, offset: u64) -> String {
+    format!("{}{}/{}", PREFIX, topic, offset)
+}
+
+/// The offset the next message published to `topic` gets.
+pub fn next(kv: &Kv, topic: &str) -> u64 {
+    kv.get(&key(topic)).and_then(|next| next.parse().ok()).unwrap_or(0)
+}
+
+/// Message `offset` of `topic`, if the topic still keeps it.
+pub fn get(kv: &Kv, topic: &str, offset: u64) -> Option<Message> {
+    serde_json::from_str(kv.get(&message_key(topic, offset))?).ok()
+}
+
+/// Applies message `id` published to `topic` to `kv`; this is how every
+/// node numbers them. Answers what it changed.
+pub(crate) fn apply(kv: &mut Kv, topic: &str, id: String, message: String) -> Vec<Change> {
+    let offset = next(kv, topic);
+    let stored = serde_json::to_string(&Message { offset, id, message }).unwrap();
+    let mut changes = Vec::new();
+    let mut set = |key: String, after: Option<String>| {
+        let before = match &after {
+            Some(value) => kv.data.insert(key.clone(), value.clone()),
+            None => kv.data.remove(&key),
+        };
+        changes.push(Change { key, before, after });
+    };
+
+    set(key(topic), Some((offset + 1).to_string()));
+    set(message_key(topic, offset), Some(stored));
+    if offset >= RETAIN {
+        set(message_key(topic, offset - RETAIN), None);
+    }
+    changes
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Message {
+    pub offset: u64,
+    /// Who published it and when, unique to each publish.
+    pub id: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Page {
+    pub topic: String,
+    /// The oldest message the topic still keeps.
+    pub oldest: u64,
+    /// Where to read from next.
+    pub next: u64,
+    pub messages: Vec<Message>,
+    /// With `since`, the instance the messages go up to; read from here
+    /// next.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u64>,
+}
+
+/// Up to `limit` messages of `topic` from `from` on.
+pub fn read(kv: &Kv, topic: &str, from: u64, limit: usize) -> Page {
+    let end = next(kv, topic);
+    let oldest = end.saturating_sub(RETAIN);
+    let messages: Vec<Message> = (from.max(oldest)..end)
+        .take(limit)
+        .filter_map(|offset| get(kv, topic, offset))
+        .collect();
+    let next = messages.last().map_or(from.max(oldest).min(end), |message| message.offset + 1);
+    Page { topic: topic.to_string(), oldest, next, messages, index: None }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Published {
+    pub topic: String,
+    pub offset: u64,
+}
+
+pub async fn publish(State(state): State<AppState>, Path(topic): Path<String>, headers: HeaderMap, Upload(message): Upload) -> Response {
+    if let Err(refusal) = acl::authorize(&state, &headers, Op::Write, &key(&topic)).await {
+        return refusal.into_response();
+    }
+    if let Some(refusal) = kv::refusal(&state) {
+        return refusal.into_response();
+    }
+    let Some(_proposal) = state.shutdown.enter() else {
+        return shutdown::refuse().into_response();
+    };
+
+    let id = format!("{}-{}", state.node.id, state.clock.now());
+    let command = Command::Publish { topic: topic.clone(), id: id.clone(), message };
+    let instance = match intake::submit(&state, command.encode()).await {
+        Ok(instance) => instance,
+        Err(e) => return e.into_response(),
+    };
+    state.applier.applied(instance).await;
+
+    // Others may have published since, so look back for ours.
+    let kv = state.kv.lock().await;
+    let end = next(&kv, &topic);
+    let offset = (end.saturating_sub(RETAIN)..end).rev().find(|offset| get(&kv, &topic, *offset).is_some_and(|message| message.id == id));
+    match offset {
+        Some(offset) => Json(Published { topic, offset }).into_response(),
+        None => (StatusCode::GONE, format!("Message {} was published to {} and already dropped!", id, topic)).into_response(),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReadQuery {
+    pub from: Option<u64>,
+    pub since: Option<u64>,
+    pub limit: Option<usize>,
+    pub timeout_ms: Option<u64>,
+}
+
+pub async fn get_topic(State(state): State<AppState>, Path(topic): Path<String>, Query(query): Query<ReadQuery>, headers: HeaderMap) -> Response {
+    if let Err(refusal) = acl::authorize(&state, &headers, Op::Read, &key(&topic)).await {
+        return refusal.into_response();
+    }
+    let from = query.from.unwrap_or(0);
+    let limit = query.limit.unwrap_or(PAGE).min(PAGE);
+    let timeout = query.timeout_ms.map_or(WAIT_TIMEOUT, Duration::from_millis).min(MAX_WAIT);
+    let deadline = Instant::now() + timeout;
+    if let Some(since) = query.since {
+        return replay(&state, &topic, since, deadline).await;
+    }
+
+    loop {
+        let page = read(&*state.kv.lock().await, &topic, from, limit);
+        if !page.messages.is_empty() || Instant::now() >= deadline {
+            return Json(page).into_response();
+        }
+        let _ = tokio::time::timeout_at(deadline, state.applier.applied(state.applier.index() + 1)).await;
+    }
+}
+
+/// The messages committed to `topic` after instance `since`, waiting for
+/// one until `deadline`.
+async fn replay(state: &AppState, topic: &str, since: u64, deadline: Instant) -> Response {
+    let prefix = format!("{}{}/", PREFIX, topic);
+    loop {
+        let changes = match state.watches.since(since, &prefix) {
+            Ok(changes) => changes,
+            Err(from) => {
+                let refusal = format!("Messages after instance {} are no longer kept, read from {} on!", since, from);
+                return (StatusCode::GONE, refusal).into_response();
+            },
+        };
+        // A topic named like one of this one's messages shares the prefix.
+        let messages: Vec<Message> = changes.events.iter()
+            .filter_map(|event| {
+                let message: Message = serde_json::from_str(event.change.after.as_deref()?).ok()?;
+                (event.change.key == message_key(topic, message.offset)).then_some(message)
+            })
+            .collect();
+        if !messages.is_empty() || Instant::now() >= deadline {
+            let end = next(&*state.kv.lock().await, topic);
+            let next = messages.last().map_or(end, |message| message.offset + 1);
+            let page = Page { topic: topic.to_string(), oldest: end.saturating_sub(RETAIN), next, messages, index: Some(changes.index) };
+            return Json(page).into_response();
+        }
+        let _ = tokio::time::timeout_at(deadline, state.applier.applied(changes.index + 1)).await;
+    }
+}
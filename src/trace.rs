@@ -0,0 +1,235 @@
+//! Message traces, and replaying them offline.
+//!
+//! A node started with `--trace <file>` appends one JSON line per message
+//! its acceptor and learner handle (prepares and accepts along with the
+//! reply they got, learns), and every [`CHECKPOINT_EVERY`] messages a
+//! snapshot of its ledger, KV store and acceptor. While tracing, those
+//! handlers run one at a time, so the order in the file is the order in
+//! which they changed the state.
+//!
+//! [`replay`] feeds the messages to a fresh node in the same order and
+//! checks every reply and every snapshot against the trace, which turns a
+//! trace attached to a bug report into a reproduction that needs no cluster.
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+};
+use futures::future::BoxFuture;
+use serde::{Serialize, Deserialize};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::{
+    AppState, Ballot, Id, Node, ProposalId, Value,
+    acceptor::Acceptor,
+    chain::{Certificate, Rewrite},
+    handlers,
+    transport::{Reply, Transport},
+    wire::{Accepted, Promise},
+};
+
+/// Messages between two snapshots.
+pub const CHECKPOINT_EVERY: u64 = 32;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    pub ledger: BTreeMap<u64, Value>,
+    pub kv: BTreeMap<String, String>,
+    pub acceptor: Acceptor,
+    /// Only the node that kept them can have them, so replays ignore them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub certificates: BTreeMap<u64, Certificate>,
+    /// The highest ballot the node promised, which the acceptor forgets for
+    /// instances once they are learned.
+    #[serde(default)]
+    pub promised: ProposalId,
+    /// The highest round the node's proposers reserved; see `proposer`.
+    #[serde(default)]
+    pub reserved: u64,
+    /// What repairs replaced; see `chain`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rewrites: BTreeMap<u64, Vec<Rewrite>>,
+}
+
+impl Snapshot {
+    pub async fn take(state: &AppState) -> Self {
+        let ledger = state.ledger.to_map().into_iter().collect();
+        state.applier.caught_up().await;
+        let kv = state.kv.lock().await.data.clone().into_iter().collect();
+        let acceptor = state.acceptor.lock().await.clone();
+        Self {
+            ledger,
+            kv,
+            acceptor,
+            certificates: state.certificates.to_map(),
+            promised: state.epoch.get(),
+            reserved: state.rounds.reserved(),
+            rewrites: state.ledger.all_rewrites(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    Prepare { ballot: Ballot, reply: Promise },
+    Accept { ballot: Ballot, reply: Accepted },
+    Learn { ballot: Ballot },
+    Checkpoint { snapshot: Snapshot },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub node: Id,
+    pub seq: u64,
+    pub step: Step,
+}
+
+#[derive(Debug)]
+struct Writer {
+    file: File,
+    seq: u64,
+    steps: u64,
+}
+
+#[derive(Debug)]
+pub struct Trace {
+    node: Id,
+    writer: Mutex<Writer>,
+}
+
+/// Held by a handler from before it looks at the state until its step is
+/// recorded.
+pub struct TraceGuard<'a> {
+    node: Id,
+    writer: MutexGuard<'a, Writer>,
+}
+
+impl Trace {
+    pub fn open(node: Id, path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { node, writer: Mutex::new(Writer { file, seq: 0, steps: 0 }) })
+    }
+
+    pub async fn sync(&self) -> io::Result<()> {
+        self.writer.lock().await.file.sync_all()
+    }
+}
+
+pub async fn begin(state: &AppState) -> Option<TraceGuard<'_>> {
+    match &state.trace {
+        None => None,
+        Some(trace) => Some(TraceGuard { node: trace.node, writer: trace.writer.lock().await }),
+    }
+}
+
+impl TraceGuard<'_> {
+    pub async fn record(&mut self, state: &AppState, step: Step) {
+        self.write(step);
+
+        self.writer.steps += 1;
+        if self.writer.steps.is_multiple_of(CHECKPOINT_EVERY) {
+            let snapshot = Snapshot::take(state).await;
+            self.write(Step::Checkpoint { snapshot });
+        }
+    }
+
+    fn write(&mut self, step: Step) {
+        let entry = Entry { node: self.node, seq: self.writer.seq, step };
+        self.writer.seq += 1;
+
+        let mut line = serde_json::to_string(&entry).unwrap();
+        line.push('\n');
+
+        if let Err(e) = self.writer.file.write_all(line.as_bytes()) {
+            println!("[trace] Node {} failed to record step {}: {}", self.node, entry.seq, e);
+        }
+    }
+}
+
+pub fn read_entries(path: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Replays never talk to anybody.
+#[derive(Debug)]
+struct Offline;
+
+impl Transport for Offline {
+    fn post(&self, addr: SocketAddr, path: &str, _body: String) -> BoxFuture<'static, Result<Reply, String>> {
+        let error = format!("replay: {} to {} is not part of the trace", path, addr);
+        Box::pin(async move { Err(error) })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReplaySummary {
+    pub steps: usize,
+    pub checkpoints: usize,
+    pub snapshot: Option<Snapshot>,
+}
+
+/// Runs the trace through a fresh node and stops at the first step whose
+/// outcome differs from the recorded one.
+pub async fn replay(entries: &[Entry]) -> Result<ReplaySummary, String> {
+    let node = entries.first().map_or(0, |entry| entry.node);
+    let state = AppState::new(Node::new(node, SocketAddr::from(([0, 0, 0, 0], 0))), Arc::new(Offline));
+
+    let mut summary = ReplaySummary::default();
+    for entry in entries {
+        match &entry.step {
+            Step::Prepare { ballot, reply } => {
+                let (_, replayed) = handlers::promise(&state, ballot).await;
+                if &replayed != reply {
+                    return Err(format!("step {}: prepare {:?} got {:?}, the trace has {:?}", entry.seq, ballot, replayed, reply));
+                }
+            },
+            Step::Accept { ballot, reply } => {
+                let (_, replayed) = handlers::accept(&state, ballot).await;
+                if &replayed != reply {
+                    return Err(format!("step {}: accept {:?} got {:?}, the trace has {:?}", entry.seq, ballot, replayed, reply));
+                }
+            },
+            Step::Learn { ballot } => {
+                handlers::learn_value(&state, ballot).await;
+            },
+            Step::Checkpoint { snapshot } => {
+                let replayed = Snapshot::take(&state).await;
+                if let Some(part) = difference(&replayed, snapshot) {
+                    return Err(format!("step {}: the {} differs from the recorded one", entry.seq, part));
+                }
+                summary.checkpoints += 1;
+                continue;
+            },
+        }
+        summary.steps += 1;
+    }
+
+    summary.snapshot = Some(Snapshot::take(&state).await);
+    Ok(summary)
+}
+
+fn difference(replayed: &Snapshot, recorded: &Snapshot) -> Option<&'static str> {
+    if replayed.ledger != recorded.ledger {
+        Some("ledger")
+    } else if replayed.kv != recorded.kv {
+        Some("KV store")
+    } else if replayed.acceptor != recorded.acceptor {
+        Some("acceptor state")
+    } else {
+        None
+    }
+}
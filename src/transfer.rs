@@ -0,0 +1,90 @@
+//! Handing a brand-new node the cluster's state before it votes.
+//!
+//! A node that joins with nothing learned would otherwise vote right away,
+//! and take its part in quorums while its ledger and KV store are empty: its
+//! promises tell a proposer nothing, and its reads answer nothing until the
+//! learns trickle in. So when a node that never learned or promised anything
+//! `/connect`s to a peer, it first asks that peer, through `POST
+//! /admin/transfer`, for a snapshot of everything it learned and the KV
+//! store that goes with it, takes it in, and then fetches the log learned
+//! since the snapshot the way a learner does, until it has all of it; see
+//! `replica`. Only then does it vote.
+//!
+//! Until it does, it turns away prepares and accepts with a `503`, writes
+//! sent to it are forwarded to a voter, and writes forwarded to it are
+//! turned away, so no one waits on it. The peer's promises are its own and
+//! aren't transferred, nor are its certificates. A node with a data
+//! directory snapshots what it took in.
+
+use std::{collections::BTreeMap, sync::atomic::Ordering};
+use axum::{extract::State, http::StatusCode};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    AppState, Node, ProposalId, Value,
+    input::Json,
+    replica::{self, MAX_ENTRIES},
+    storage,
+    trace::Snapshot,
+    transport::post_json,
+};
+
+/// What a peer hands a node that joins.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Transfer {
+    pub ledger: BTreeMap<u64, Value>,
+    pub kv: BTreeMap<String, String>,
+}
+
+pub async fn get_transfer(State(state): State<AppState>) -> Json<Transfer> {
+    // Under the ledger's writer, with the KV store caught up, the two match.
+    let ledger = state.ledger.write().await;
+    state.applier.caught_up().await;
+    let kv = state.kv.lock().await.data.clone().into_iter().collect();
+    let transfer = Transfer { ledger: ledger.to_map().into_iter().collect(), kv };
+    std::mem::drop(ledger);
+
+    println!("[transfer] Node {} hands over {} instances", state.node.id, transfer.ledger.len());
+    Json(transfer)
+}
+
+/// Whether this node never learned or promised anything.
+pub async fn is_fresh(state: &AppState) -> bool {
+    state.ledger.is_empty() && state.acceptor.lock().await.highest() == ProposalId::default()
+}
+
+/// The 503 a node taking in the cluster's state turns votes away with.
+pub fn refuse(state: &AppState) -> (StatusCode, String) {
+    (StatusCode::SERVICE_UNAVAILABLE, format!("Node {} is still taking in the cluster's state, it doesn't vote yet!", state.node.id))
+}
+
+/// Keeps the node from voting until it has the state of `peer`, which it
+/// just joined, and the log learned since.
+pub fn join(state: &AppState, peer: Node) {
+    state.syncing.store(true, Ordering::SeqCst);
+    tokio::spawn(take_in(state.clone(), peer));
+}
+
+async fn take_in(state: AppState, peer: Node) {
+    let transfer = loop {
+        match post_json(state.transport.as_ref(), peer.addr, "/admin/transfer", &()).await {
+            Ok(reply) if !reply.is_error() => match reply.json::<Transfer>() {
+                Ok(transfer) => break transfer,
+                Err(e) => println!("[transfer] Node {} got a bad transfer from node {}: {}", state.node.id, peer.id, e),
+            },
+            _ => println!("[transfer] Node {} couldn't get the state of node {}, trying again", state.node.id, peer.id),
+        }
+        tokio::time::sleep(replica::CATCH_UP_EVERY).await;
+    };
+
+    let instances = transfer.ledger.len();
+    let snapshot = Snapshot { ledger: transfer.ledger, kv: transfer.kv, ..Snapshot::default() };
+    storage::restore(&state, snapshot).await;
+    while replica::catch_up(&state).await >= MAX_ENTRIES as usize {}
+    if let Some(Err(e)) = storage::snapshot(&state).await {
+        println!("[transfer] Node {} couldn't snapshot the state it took in: {}", state.node.id, e);
+    }
+
+    state.syncing.store(false, Ordering::SeqCst);
+    println!("[transfer] Node {} took in {} instances from node {}, and votes from now on", state.node.id, instances, peer.id);
+}
@@ -0,0 +1,94 @@
+use std::{fmt::Debug, net::SocketAddr, sync::Arc};
+use axum::http::StatusCode;
+use futures::future::BoxFuture;
+use reqwest::{Client, header::CONTENT_TYPE};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    Id,
+    dedup::{MESSAGE_ID_HEADER, MessageIds},
+    hlc::{HLC_HEADER, Hlc},
+    secrets::{ClusterToken, TOKEN_HEADER},
+};
+
+/// Set on every node-to-node request so the receiver knows who is talking.
+pub const NODE_ID_HEADER: &str = "x-paxos-node-id";
+
+/// What came back from a peer: the HTTP status and the raw body.
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl Reply {
+    pub fn is_error(&self) -> bool {
+        self.status.is_client_error() || self.status.is_server_error()
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, String> {
+        serde_json::from_str(&self.body).map_err(|e| e.to_string())
+    }
+}
+
+/// Everything a node sends to another node goes through a transport, so the
+/// same handlers can run over real HTTP or inside the simulator.
+pub trait Transport: Debug + Send + Sync {
+    fn post(&self, addr: SocketAddr, path: &str, body: String) -> BoxFuture<'static, Result<Reply, String>>;
+}
+
+pub async fn post_json<T: Serialize>(
+    transport: &dyn Transport,
+    addr: SocketAddr,
+    path: &str,
+    payload: &T,
+) -> Result<Reply, String> {
+    let body = serde_json::to_string(payload).map_err(|e| e.to_string())?;
+    transport.post(addr, path, body).await
+}
+
+#[derive(Debug)]
+pub struct HttpTransport {
+    node_id: Id,
+    client: Client,
+    token: Arc<ClusterToken>,
+    clock: Arc<Hlc>,
+    ids: MessageIds,
+}
+
+impl HttpTransport {
+    pub fn new(node_id: Id) -> Self {
+        Self { node_id, client: Client::new(), token: Arc::default(), clock: Arc::default(), ids: MessageIds::default() }
+    }
+
+    /// Sends whatever token `token` holds at the time along with every request.
+    pub fn with_token(self, token: Arc<ClusterToken>) -> Self {
+        Self { token, ..self }
+    }
+
+    /// Sends a reading of `clock` along with every request; see `hlc`.
+    pub fn with_clock(self, clock: Arc<Hlc>) -> Self {
+        Self { clock, ..self }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn post(&self, addr: SocketAddr, path: &str, body: String) -> BoxFuture<'static, Result<Reply, String>> {
+        let mut req = self.client.post(format!("http://{}{}", addr, path))
+            .header(CONTENT_TYPE, "application/json")
+            .header(NODE_ID_HEADER, self.node_id)
+            .header(HLC_HEADER, self.clock.now().to_string())
+            .header(MESSAGE_ID_HEADER, self.ids.next())
+            .body(body);
+        if let Some(token) = self.token.current() {
+            req = req.header(TOKEN_HEADER, token);
+        }
+
+        Box::pin(async move {
+            let res = req.send().await.map_err(|e| e.to_string())?;
+            let status = StatusCode::from_u16(res.status().as_u16()).map_err(|e| e.to_string())?;
+            let body = res.text().await.map_err(|e| e.to_string())?;
+            Ok(Reply { status, body })
+        })
+    }
+}
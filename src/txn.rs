@@ -0,0 +1,329 @@
+//! Transactions over keys in more than one group, by Paxos Commit.
+//!
+//! With `--shards`, a write runs a round in the group of its key only, so
+//! no one proposal can make writes to keys in two groups atomic. `POST
+//! /txn` takes a list of puts and deletes and commits all of them or none.
+//! It runs two-phase commit with each group as a participant, the way Paxos
+//! Commit does: a group's vote is itself a value in the group's log, so no
+//! one node holds the outcome, and any node can finish a transaction its
+//! coordinator left.
+//!
+//! The node that takes the request coordinates. It proposes a
+//! [`Command::Prepare`] with the writes to each group involved. Applying
+//! it, a group votes prepared and locks the keys, unless another
+//! transaction holds one of them, and refuses if so. The first vote in a
+//! group's log is the one that counts, so every replica comes to the same.
+//! The transaction commits if every group voted prepared, and the
+//! coordinator proposes a [`Command::Decide`] to each, which applies the
+//! writes or drops them, and releases the locks.
+//!
+//! A transaction still prepared after [`RECOVER_AFTER`] is finished by any
+//! node that sees it: the node proposes a [`Command::Refuse`] to each group
+//! without a vote, which counts against it unless the prepare got there
+//! first, reads the votes and decides the same way. Two nodes finishing the
+//! same transaction read the same votes and decide the same. A group keeps
+//! what it voted under `__txn/` after the transaction ends, so a prepare
+//! that comes late finds the vote cast.
+//!
+//! Locks only hold back other transactions: a plain `/kv` write to a
+//! locked key goes through, and a commit writes over it.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::Duration,
+};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::future::join_all;
+use serde::{Serialize, Deserialize};
+use tokio::time::Instant;
+
+use crate::{
+    AppState,
+    acl::{self, Op},
+    input::Json,
+    intake,
+    kv::{self, Change, Command, Kv},
+    schema,
+    shards,
+    shutdown,
+};
+
+/// Where a group keeps its votes, by transaction.
+pub const PREFIX: &str = "__txn/";
+
+/// Where a group keeps its locks, by key; no transaction id has a `/`.
+const LOCKS: &str = "__txn/lock/";
+
+/// How long a transaction stays prepared before any node finishes it.
+pub const RECOVER_AFTER: Duration = Duration::from_secs(5);
+
+const RECOVER_EVERY: Duration = Duration::from_secs(1);
+
+/// A group's vote on a transaction, and then how it ended there.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Vote {
+    Prepared,
+    Refused,
+    Committed,
+    Aborted,
+}
+
+/// What a group keeps of a transaction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    pub vote: Vote,
+    /// The writes to this group.
+    pub ops: Vec<Command>,
+    /// Every group the transaction writes to, by shard.
+    pub groups: Vec<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Transaction {
+    pub ops: Vec<Command>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub txn: String,
+    pub committed: bool,
+}
+
+fn record_key(txn: &str) -> String {
+    format!("{}{}", PREFIX, txn)
+}
+
+fn lock_key(key: &str) -> String {
+    format!("{}{}", LOCKS, key)
+}
+
+/// The key a write of a transaction is on; none for anything else.
+fn key(op: &Command) -> Option<&str> {
+    match op {
+        Command::Put { key, .. } | Command::Delete { key } => Some(key),
+        _ => None,
+    }
+}
+
+/// What `kv` keeps of `txn`.
+pub fn record(kv: &Kv, txn: &str) -> Option<Record> {
+    serde_json::from_str(kv.get(&record_key(txn))?).ok()
+}
+
+fn save(kv: &mut Kv, txn: &str, record: &Record) {
+    kv.data.insert(record_key(txn), serde_json::to_string(record).unwrap());
+}
+
+/// Applies a transaction's command to `kv`; this is how every replica of
+/// a group votes and decides. Answers what the writes changed.
+pub(crate) fn apply(kv: &mut Kv, command: Command) -> Vec<Change> {
+    match command {
+        Command::Prepare { txn, ops, groups } => {
+            if record(kv, &txn).is_some() {
+                return Vec::new();
+            }
+            let free = ops.iter().filter_map(key).all(|key| !kv.data.contains_key(&lock_key(key)));
+            if free {
+                for key in ops.iter().filter_map(key) {
+                    kv.data.insert(lock_key(key), txn.clone());
+                }
+            }
+            let vote = if free { Vote::Prepared } else { Vote::Refused };
+            save(kv, &txn, &Record { vote, ops, groups });
+            Vec::new()
+        },
+        Command::Refuse { txn } => {
+            if record(kv, &txn).is_none() {
+                save(kv, &txn, &Record { vote: Vote::Refused, ops: Vec::new(), groups: Vec::new() });
+            }
+            Vec::new()
+        },
+        Command::Decide { txn, commit } => {
+            let Some(mut record) = record(kv, &txn).filter(|record| record.vote == Vote::Prepared) else {
+                return Vec::new();
+            };
+            for key in record.ops.iter().filter_map(key) {
+                kv.data.remove(&lock_key(key));
+            }
+            let mut changes = Vec::new();
+            if commit {
+                for op in record.ops.iter().filter(|op| key(op).is_some()) {
+                    changes.extend(kv.apply_changes(&op.encode()));
+                }
+            }
+            record.vote = if commit { Vote::Committed } else { Vote::Aborted };
+            save(kv, &txn, &record);
+            changes
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// The group that holds shard `index`: the node's own without shards.
+fn group(state: &AppState, index: usize) -> &AppState {
+    state.groups.shard(index).unwrap_or(state)
+}
+
+/// Proposes `command` to `group`, and waits until the node applied it.
+async fn propose(group: &AppState, command: Command) -> Result<(), String> {
+    let instance = intake::submit(group, command.encode()).await?;
+    group.applier.applied(instance).await;
+    Ok(())
+}
+
+/// Collects the vote of every group in `groups` on `txn`, votes against
+/// it where there is none, and decides it in each. Answers whether it
+/// committed.
+async fn finish(state: &AppState, txn: &str, groups: &[usize]) -> Result<bool, String> {
+    let mut votes = Vec::with_capacity(groups.len());
+    for &index in groups {
+        let group = group(state, index);
+        let mut vote = record(&*group.kv.lock().await, txn).map(|record| record.vote);
+        if vote.is_none() {
+            propose(group, Command::Refuse { txn: txn.to_string() }).await?;
+            vote = record(&*group.kv.lock().await, txn).map(|record| record.vote);
+        }
+        votes.push(vote.unwrap_or(Vote::Refused));
+    }
+
+    let commit = votes.iter().all(|vote| matches!(vote, Vote::Prepared | Vote::Committed));
+    let decisions = groups.iter().zip(&votes)
+        .filter(|(_, vote)| **vote == Vote::Prepared)
+        .map(|(&index, _)| propose(group(state, index), Command::Decide { txn: txn.to_string(), commit }));
+    join_all(decisions).await.into_iter().collect::<Result<(), String>>()?;
+    Ok(commit)
+}
+
+/// Runs a transaction of `ops` to its end, with this node coordinating.
+pub async fn commit(state: &AppState, ops: Vec<Command>) -> Result<Outcome, String> {
+    let txn = format!("{}-{}", state.node.id, state.clock.now());
+    let count = state.groups.shard_count();
+    let mut writes: BTreeMap<usize, Vec<Command>> = BTreeMap::new();
+    for op in ops {
+        if let Some(index) = key(&op).map(|key| shards::of(key, count)) {
+            writes.entry(index).or_default().push(op);
+        }
+    }
+    let groups: Vec<usize> = writes.keys().copied().collect();
+
+    // A group the prepare didn't reach still gets a vote, in `finish`.
+    let prepares = writes.into_iter().map(|(index, ops)| {
+        propose(group(state, index), Command::Prepare { txn: txn.clone(), ops, groups: groups.clone() })
+    });
+    for result in join_all(prepares).await {
+        if let Err(e) = result {
+            println!("[txn] Node {} couldn't prepare transaction {}: {}", state.node.id, txn, e);
+        }
+    }
+
+    let committed = finish(state, &txn, &groups).await?;
+    Ok(Outcome { txn, committed })
+}
+
+/// Commits every write of the body, or none: `200` if it did, `409` if a
+/// key was held by another transaction.
+pub async fn post_txn(State(state): State<AppState>, headers: HeaderMap, Json(transaction): Json<Transaction>) -> Response {
+    if transaction.ops.is_empty() {
+        return (StatusCode::BAD_REQUEST, String::from("A transaction needs at least one write!")).into_response();
+    }
+    for op in &transaction.ops {
+        let admitted = match op {
+            Command::Put { key, value } => match acl::check(&state, &headers, Op::Write, key).await {
+                Ok(_) => schema::admit(&state, None, key, value).await,
+                Err(refusal) => Err(refusal),
+            },
+            Command::Delete { key } => acl::check(&state, &headers, Op::Delete, key).await.map(|_| ()),
+            _ => Err((StatusCode::BAD_REQUEST, String::from("A transaction only puts and deletes keys!"))),
+        };
+        if let Err(refusal) = admitted {
+            return refusal.into_response();
+        }
+    }
+
+    if let Some(refusal) = kv::refusal(&state) {
+        return refusal.into_response();
+    }
+    let Some(_proposal) = state.shutdown.enter() else {
+        return shutdown::refuse().into_response();
+    };
+
+    match commit(&state, transaction.ops).await {
+        Ok(outcome) if outcome.committed => Json(outcome).into_response(),
+        Ok(outcome) => (StatusCode::CONFLICT, Json(outcome)).into_response(),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, format!("Transaction left undecided, a node will finish it: {}!", e)).into_response(),
+    }
+}
+
+/// The transactions this node found prepared, and since when.
+#[derive(Debug, Default)]
+pub struct Recovery {
+    seen: Mutex<HashMap<String, Instant>>,
+}
+
+/// The transactions some group of the node's voted prepared on and hasn't
+/// decided, with the groups of each.
+async fn prepared(state: &AppState) -> BTreeMap<String, Vec<usize>> {
+    let mut prepared = BTreeMap::new();
+    for index in 0..state.groups.shard_count().max(1) {
+        let kv = group(state, index).kv.lock().await;
+        for (key, stored) in &kv.data {
+            let Some(txn) = key.strip_prefix(PREFIX).filter(|_| !key.starts_with(LOCKS)) else {
+                continue;
+            };
+            match serde_json::from_str::<Record>(stored) {
+                Ok(record) if record.vote == Vote::Prepared => {
+                    prepared.insert(txn.to_string(), record.groups);
+                },
+                _ => {},
+            }
+        }
+    }
+    prepared
+}
+
+impl Recovery {
+    /// Finishes the transactions left prepared for [`RECOVER_AFTER`] by
+    /// `now`, and answers which.
+    pub async fn check(&self, state: &AppState, now: Instant) -> Vec<String> {
+        if state.node.learner || state.is_paused() || state.is_syncing() {
+            return Vec::new();
+        }
+
+        let prepared = prepared(state).await;
+        let overdue: Vec<(String, Vec<usize>)> = {
+            let mut seen = self.seen.lock().unwrap();
+            seen.retain(|txn, _| prepared.contains_key(txn));
+            prepared.into_iter()
+                .filter(|(txn, _)| now.duration_since(*seen.entry(txn.clone()).or_insert(now)) >= RECOVER_AFTER)
+                .collect()
+        };
+
+        let mut finished = Vec::new();
+        for (txn, groups) in overdue {
+            println!("[txn] Node {} finds transaction {} left prepared, finishing it", state.node.id, txn);
+            match finish(state, &txn, &groups).await {
+                Ok(committed) => {
+                    println!("[txn] Node {} {} transaction {}", state.node.id, if committed { "committed" } else { "aborted" }, txn);
+                    finished.push(txn);
+                },
+                Err(e) => println!("[txn] Node {} couldn't finish transaction {}: {}", state.node.id, txn, e),
+            }
+        }
+        finished
+    }
+}
+
+/// Finishes what coordinators left, as long as the node runs.
+pub async fn run(state: AppState) {
+    let recovery = Recovery::default();
+    loop {
+        tokio::time::sleep(RECOVER_EVERY).await;
+        recovery.check(&state, Instant::now()).await;
+    }
+}
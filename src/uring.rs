@@ -0,0 +1,96 @@
+//! Writing and syncing the log through io_uring, on Linux with the
+//! `io-uring` feature.
+//!
+//! Every log write and sync is otherwise a `write` and an `fdatasync` of
+//! its own. A [`Ring`] hands the kernel a batch's write and the sync after
+//! it in one submission instead, linked so the sync only runs once the
+//! write is done, and waits for both with a single `io_uring_enter`. A
+//! kernel without io_uring, or a sandbox that forbids it, leaves the node on
+//! the portable writer.
+//!
+//! The ring belongs to the log's writer thread, see `storage`, so it is
+//! never shared and never waited on from the runtime.
+
+use std::{fs::File, io, os::unix::io::AsRawFd};
+use io_uring::{IoUring, opcode, squeue, types};
+
+const ENTRIES: u32 = 8;
+
+const WRITE: u64 = 1;
+const SYNC: u64 = 2;
+
+pub struct Ring {
+    ring: IoUring,
+}
+
+impl std::fmt::Debug for Ring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ring").field("entries", &ENTRIES).finish()
+    }
+}
+
+impl Ring {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self { ring: IoUring::new(ENTRIES)? })
+    }
+
+    /// Writes all of `data` into `file` at `offset`, then, if `sync`, syncs
+    /// it like [`File::sync_data`]. A write cut short cancels the sync
+    /// linked to it, so the rest goes again with another.
+    pub fn write(&mut self, file: &File, offset: u64, data: &[u8], sync: bool) -> io::Result<()> {
+        let fd = types::Fd(file.as_raw_fd());
+        let mut written = 0;
+        loop {
+            let rest = &data[written..];
+            let mut ops = Vec::with_capacity(2);
+            if !rest.is_empty() {
+                let write = opcode::Write::new(fd, rest.as_ptr(), rest.len().min(u32::MAX as usize) as u32)
+                    .offset(offset + written as u64)
+                    .build()
+                    .user_data(WRITE);
+                ops.push(if sync { write.flags(squeue::Flags::IO_LINK) } else { write });
+            }
+            if sync {
+                ops.push(opcode::Fsync::new(fd).flags(types::FsyncFlags::DATASYNC).build().user_data(SYNC));
+            }
+            if ops.is_empty() {
+                return Ok(());
+            }
+
+            // SAFETY: `rest` outlives the write, as we wait for it below
+            // before returning or touching `data` again.
+            unsafe { self.ring.submission().push_multiple(&ops) }.map_err(|_| io::Error::other("io_uring's submission queue is full"))?;
+            self.submit(ops.len())?;
+
+            let mut synced = !sync;
+            let mut cut_short = false;
+            for done in self.ring.completion().collect::<Vec<_>>() {
+                match (done.user_data(), done.result()) {
+                    (SYNC, res) if res == -libc::ECANCELED && cut_short => {},
+                    (_, res) if res < 0 => return Err(io::Error::from_raw_os_error(-res)),
+                    (WRITE, 0) => return Err(io::ErrorKind::WriteZero.into()),
+                    (WRITE, count) => {
+                        written += count as usize;
+                        cut_short = written < data.len();
+                    },
+                    _ => synced = true,
+                }
+            }
+            if written == data.len() && synced {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Submits what was pushed and waits for `count` completions.
+    fn submit(&mut self, count: usize) -> io::Result<()> {
+        loop {
+            match self.ring.submit_and_wait(count) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+                Ok(_) if self.ring.completion().len() < count => continue,
+                Ok(_) => return Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Protocol versions, so a cluster can be upgraded one node at a time.
+//!
+//! Nodes tell each other the newest protocol they speak in `/ping` and its
+//! reply, and each pair talks the older of the two. Messages stay readable
+//! both ways across versions: no message refuses fields it doesn't know,
+//! fields added after version 1 are `#[serde(default)]`, and endpoints
+//! added later are only called on peers that said they have them. A peer
+//! that never said is taken to speak [`MIN_PROTOCOL`].
+//!
+//! | version | adds |
+//! |---------|------|
+//! | 1 | everything up to read-only mode |
+//! | 2 | `/forward`, for read-only nodes |
+//! | 3 | `/handle-learns`, for batched learns |
+//! | 4 | decisions carried on accepts |
+//! | 5 | `/gossip-learns`, for learns passed on from peer to peer |
+//! | 6 | `/handle-prepare-range`, for preparing instances ahead |
+//! | 7 | `/handle-pre-vote`, for asking before preparing |
+//! | 8 | `/handle-ship`, for shipping the log to followers |
+
+use std::{collections::HashMap, sync::RwLock};
+
+use crate::Id;
+
+/// The newest protocol this build speaks.
+pub const PROTOCOL: u32 = 8;
+/// The oldest protocol this build can still talk to.
+pub const MIN_PROTOCOL: u32 = 1;
+/// Where `/forward` came in.
+pub const FORWARD: u32 = 2;
+/// Where `/handle-learns` came in.
+pub const LEARN_BATCH: u32 = 3;
+/// Where accepts started carrying decisions.
+pub const PIGGYBACK: u32 = 4;
+/// Where `/gossip-learns` came in.
+pub const GOSSIP: u32 = 5;
+/// Where `/handle-prepare-range` came in.
+pub const PREPARE_AHEAD: u32 = 6;
+/// Where `/handle-pre-vote` came in.
+pub const PRE_VOTE: u32 = 7;
+/// Where `/handle-ship` came in.
+pub const SHIP: u32 = 8;
+
+#[derive(Debug, Default)]
+pub struct Versions {
+    peers: RwLock<HashMap<Id, u32>>,
+}
+
+impl Versions {
+    /// Records the protocol `peer` speaks and returns the one to talk to it
+    /// in, or why the two can't talk at all.
+    pub fn negotiate(&self, peer: Id, theirs: Option<u32>) -> Result<u32, String> {
+        let theirs = theirs.unwrap_or(MIN_PROTOCOL);
+        if theirs < MIN_PROTOCOL {
+            return Err(format!("Node {} speaks protocol {}, but this one needs at least {}!", peer, theirs, MIN_PROTOCOL));
+        }
+
+        let version = theirs.min(PROTOCOL);
+        self.peers.write().unwrap().insert(peer, version);
+        Ok(version)
+    }
+
+    /// The protocol to talk to `peer` in.
+    pub fn of(&self, peer: Id) -> u32 {
+        self.peers.read().unwrap().get(&peer).copied().unwrap_or(MIN_PROTOCOL)
+    }
+
+    /// The newest protocol every node in `peers` speaks, us included.
+    pub fn common(&self, peers: impl IntoIterator<Item = Id>) -> u32 {
+        peers.into_iter().map(|peer| self.of(peer)).min().unwrap_or(PROTOCOL)
+    }
+}
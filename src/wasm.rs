@@ -0,0 +1,60 @@
+//! JavaScript bindings for the [`Playground`], for an in-browser playground
+//! driven by this crate:
+//!
+//! ```sh
+//! cargo build --lib --target wasm32-unknown-unknown --no-default-features --features wasm
+//! ```
+//!
+//! Envelopes and state cross into JavaScript as JSON strings, in the shapes
+//! the playground types serialize to.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Id, playground::Playground};
+
+#[wasm_bindgen]
+pub struct Cluster {
+    playground: Playground,
+}
+
+#[wasm_bindgen]
+impl Cluster {
+    #[wasm_bindgen(constructor)]
+    pub fn new(size: usize) -> Cluster {
+        Cluster { playground: Playground::new(size) }
+    }
+
+    pub fn propose(&mut self, node: Id, value: String) -> Result<(), JsValue> {
+        self.playground.propose(node, value).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// The delivered envelope, or `undefined` when `index` is out of range.
+    pub fn deliver(&mut self, index: usize) -> Option<String> {
+        self.playground.deliver(index).map(|envelope| serde_json::to_string(&envelope).unwrap())
+    }
+
+    #[wasm_bindgen(js_name = dropMessage)]
+    pub fn drop_message(&mut self, index: usize) -> Option<String> {
+        self.playground.drop_message(index).map(|envelope| serde_json::to_string(&envelope).unwrap())
+    }
+
+    pub fn run(&mut self, max_steps: usize) -> usize {
+        self.playground.run(max_steps)
+    }
+
+    #[wasm_bindgen(js_name = inFlight)]
+    pub fn in_flight(&self) -> String {
+        serde_json::to_string(&self.playground.in_flight).unwrap()
+    }
+
+    /// Nodes, messages in flight and outcomes so far.
+    pub fn state(&self) -> String {
+        serde_json::to_string(&self.playground).unwrap()
+    }
+
+    /// The agreement violation, if any.
+    #[wasm_bindgen(js_name = checkAgreement)]
+    pub fn check_agreement(&self) -> Option<String> {
+        self.playground.check_agreement().err()
+    }
+}
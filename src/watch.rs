@@ -0,0 +1,151 @@
+//! Watches on keys, that pick up where a client left off.
+//!
+//! `GET /watch?prefix=<p>&since=<index>` answers every change to a key
+//! under `prefix` applied after instance `since`, each with the key's value
+//! before and after, and `index`, the instance they go up to. With none
+//! yet, it waits up to `timeout_ms` for one. A client follows the store by
+//! asking again with `since` set to the `index` it was answered, so one
+//! that got disconnected replays what it missed before it sees anything
+//! new, and never loses a change in between. Without `since`, a watch
+//! starts from what is applied now.
+//!
+//! `group` watches one of the node's groups instead, shards included: with
+//! `--shards`, a watch names the shard of the keys it follows, as each has
+//! its own log and instances.
+//!
+//! Each group keeps the changes of its last instances, up to [`CAPACITY`]
+//! of them, as the apply task applies them; see `apply`. A watch from
+//! further back than that, or from before the node put a snapshot or a
+//! transfer in place, which skip the apply task, gets a `410` with the
+//! first instance it could watch from: the client has to read the keys
+//! again, then watch from there.
+
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, Deserialize};
+use tokio::time::Instant;
+
+use crate::{
+    AppState,
+    acl::{self, Op},
+    input::{Json, Query},
+    kv::Change,
+};
+
+/// Most changes a group keeps.
+pub const CAPACITY: usize = 4096;
+
+/// How long a watch waits for a change, unless told otherwise.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_WAIT: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Event {
+    pub instance: u64,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+#[derive(Debug)]
+struct History {
+    /// Every change of the instances from this one on is kept.
+    from: u64,
+    /// The last instance applied.
+    last: u64,
+    events: VecDeque<Event>,
+}
+
+/// The changes a group applied lately.
+#[derive(Debug)]
+pub struct Watches {
+    history: Mutex<History>,
+}
+
+impl Default for Watches {
+    fn default() -> Self {
+        Self { history: Mutex::new(History { from: 1, last: 0, events: VecDeque::new() }) }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Page {
+    /// The changes go up to this instance; watch from here next.
+    pub index: u64,
+    pub events: Vec<Event>,
+}
+
+impl Watches {
+    /// Keeps what applying `instance` changed.
+    pub fn record(&self, instance: u64, changes: &[Change]) {
+        let mut history = self.history.lock().unwrap();
+        if instance != history.last + 1 {
+            // Whatever came between was put in place some other way.
+            history.events.clear();
+            history.from = instance;
+        }
+        history.last = instance;
+        history.events.extend(changes.iter().map(|change| Event { instance, change: change.clone() }));
+        while history.events.len() > CAPACITY {
+            let dropped = history.events.pop_front().unwrap();
+            history.from = dropped.instance + 1;
+        }
+    }
+
+    /// The changes to keys under `prefix` after instance `since`, or the
+    /// first instance it could answer from if they are no longer kept.
+    pub fn since(&self, since: u64, prefix: &str) -> Result<Page, u64> {
+        let history = self.history.lock().unwrap();
+        if since + 1 < history.from {
+            return Err(history.from - 1);
+        }
+        let events = history.events.iter()
+            .filter(|event| event.instance > since && event.change.key.starts_with(prefix))
+            .cloned()
+            .collect();
+        Ok(Page { index: history.last.max(since), events })
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct WatchQuery {
+    #[serde(default)]
+    pub prefix: String,
+    pub since: Option<u64>,
+    pub timeout_ms: Option<u64>,
+    pub group: Option<String>,
+}
+
+pub async fn get_watch(State(state): State<AppState>, Query(query): Query<WatchQuery>, headers: HeaderMap) -> Response {
+    // Shards too: each has the changes of its own keys.
+    let group = match query.group.as_deref() {
+        None => &state,
+        Some(id) => match state.groups.get(id) {
+            Some(group) => group,
+            None => return (StatusCode::NOT_FOUND, format!("Node {} hosts no group {}!", state.node.id, id)).into_response(),
+        },
+    };
+    if let Err(refusal) = acl::authorize(&state, &headers, Op::Read, &query.prefix).await {
+        return refusal.into_response();
+    }
+    let since = query.since.unwrap_or_else(|| group.applier.index());
+    let timeout = query.timeout_ms.map_or(WAIT_TIMEOUT, Duration::from_millis).min(MAX_WAIT);
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let page = match group.watches.since(since, &query.prefix) {
+            Ok(page) => page,
+            Err(from) => {
+                let refusal = format!("Changes after instance {} are no longer kept, watch from {} on!", since, from);
+                return (StatusCode::GONE, refusal).into_response();
+            },
+        };
+        if !page.events.is_empty() || Instant::now() >= deadline {
+            return Json(page).into_response();
+        }
+        let _ = tokio::time::timeout_at(deadline, group.applier.applied(page.index + 1)).await;
+    }
+}
@@ -0,0 +1,201 @@
+//! What nodes send each other to join and to run the two phases.
+//!
+//! A node joins with a [`Join`] to `/ping` and is answered with the peer's
+//! own, a [`JoinAck`]. Phase 1 sends a [`Prepare`] to `/handle-prepare`,
+//! answered with a [`Promise`], and phase 2 an [`Accept`] to
+//! `/handle-accept`, answered with an [`Accepted`]. A decision goes out as
+//! a [`Learn`]. Whatever a node turns down it answers with a [`Nack`], in
+//! a promise or an accept reply of its own when that is what it turned
+//! down. Senders and handlers both go by these types.
+//!
+//! The JSON stays what nodes spoke before there were types for it, so
+//! they keep understanding each other: a join still carries its id, its
+//! address and its numbers as strings, and a promise or an accept reply
+//! carries `error` even when there is none.
+
+use std::net::SocketAddr;
+use serde::{Serialize, Deserialize};
+
+use crate::{AppState, Ballot, Id, Node, ProposalId, Value, learns::Committed, version};
+
+/// A node asking to join, or saying it is back.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Join {
+    #[serde(with = "text")]
+    pub id: Id,
+    #[serde(with = "text")]
+    pub addr: SocketAddr,
+    /// Missing from nodes older than protocol versions.
+    #[serde(default, with = "text::option", skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<u32>,
+    /// The multicast group the node gets learns on, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multicast: Option<String>,
+    /// The key the node signs consensus messages with, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Set on a node that only learns.
+    #[serde(default, with = "text", skip_serializing_if = "std::ops::Not::not")]
+    pub learner: bool,
+    /// The zone the node runs in, if it says.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    /// Its vote's weight; a weight of 0 counts as 1.
+    #[serde(default = "Node::one", with = "text", skip_serializing_if = "Node::is_one")]
+    pub weight: u32,
+}
+
+impl Join {
+    /// How `state` introduces itself.
+    pub fn of(state: &AppState) -> Self {
+        Self {
+            id: state.node.id,
+            addr: state.node.addr,
+            protocol: Some(version::PROTOCOL),
+            multicast: state.multicast.as_ref().map(|multicast| multicast.group().to_string()),
+            public_key: state.keys.public_hex(),
+            learner: state.node.learner,
+            zone: state.node.zone.clone(),
+            weight: state.node.weight,
+        }
+    }
+
+    /// The node that joined.
+    pub fn node(&self) -> Node {
+        Node { id: self.id, addr: self.addr, learner: self.learner, zone: self.zone.clone(), weight: self.weight.max(1) }
+    }
+}
+
+/// What a node answers a join it took: the same about itself.
+pub type JoinAck = Join;
+
+/// Phase 1a: the ballot to promise, with the proposer's value.
+pub type Prepare = Ballot;
+
+/// Phase 1b, or the acceptor turning the prepare down.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Promise {
+    pub error: Option<String>,
+    /// The highest proposal this acceptor accepted for the instance, if any.
+    pub value: Option<Ballot>,
+    /// On a NACK, the proposal that the acceptor already promised.
+    #[serde(default)]
+    pub promised: Option<ProposalId>,
+    /// Set when the acceptor already learned the instance.
+    #[serde(default)]
+    pub decided: Option<Value>,
+}
+
+impl Promise {
+    pub fn granted(value: Option<Ballot>) -> Self {
+        Self { error: None, value, promised: None, decided: None }
+    }
+
+    pub fn decided(value: Value) -> Self {
+        Self { decided: Some(value), ..Self::granted(None) }
+    }
+}
+
+/// Phase 2a: the ballot, and decisions the proposer had yet to tell the
+/// acceptor about. Older proposers send the bare ballot.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Accept {
+    #[serde(flatten)]
+    pub ballot: Ballot,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub committed: Vec<Committed>,
+}
+
+/// Phase 2b, or the acceptor turning the accept down.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Accepted {
+    pub error: Option<String>,
+    pub value: Option<Ballot>,
+    #[serde(default)]
+    pub promised: Option<ProposalId>,
+    /// The acceptor's signature over what it accepted, if it has a key;
+    /// see `chain`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ack: Option<String>,
+}
+
+impl Accepted {
+    pub fn granted(ballot: Ballot, ack: Option<String>) -> Self {
+        Self { error: None, value: Some(ballot), promised: None, ack }
+    }
+}
+
+/// A decision, to `/handle-learn`, or a batch of them in the order they
+/// were decided, to `/handle-learns`.
+pub type Learn = Ballot;
+
+/// Why a node turned a message down.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Nack {
+    pub error: String,
+    /// The ballot to beat, when a higher promise was the reason.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub promised: Option<ProposalId>,
+}
+
+impl Nack {
+    pub fn new(error: impl Into<String>) -> Self {
+        Self { error: error.into(), promised: None }
+    }
+
+    /// Turned down for `promised`, a higher ballot.
+    pub fn outbid(error: impl Into<String>, promised: ProposalId) -> Self {
+        Self { error: error.into(), promised: Some(promised) }
+    }
+}
+
+impl From<Nack> for Promise {
+    fn from(nack: Nack) -> Self {
+        Self { error: Some(nack.error), value: None, promised: nack.promised, decided: None }
+    }
+}
+
+impl From<Nack> for Accepted {
+    fn from(nack: Nack) -> Self {
+        Self { error: Some(nack.error), value: None, promised: nack.promised, ack: None }
+    }
+}
+
+/// A value that goes over the wire as a string.
+mod text {
+    use std::{fmt::Display, str::FromStr};
+    use serde::{Deserialize, Deserializer, Serializer, de};
+
+    pub fn serialize<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<T: Display, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => serializer.collect_str(value),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+        where
+            T: FromStr,
+            T::Err: Display,
+            D: Deserializer<'de>,
+        {
+            Option::<String>::deserialize(deserializer)?.map(|text| text.parse().map_err(de::Error::custom)).transpose()
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use axum::http::StatusCode;
+
+use paxos_from_scratch::{
+    AppState,
+    acl::{Grant, Op, Rule},
+    sim::{Sim, SimConfig},
+};
+
+mod common;
+use common::{cluster, send_as};
+
+fn guarded(sim: &Sim) -> AppState {
+    let mut state = sim.node(0).clone();
+    state.acl = true;
+    state
+}
+
+async fn grant(state: &AppState, by: Option<&str>, token: &str, client: &str, admin: bool, rules: &[(&str, &[Op])]) -> StatusCode {
+    let rules: Vec<Rule> = rules.iter().map(|(prefix, ops)| Rule { prefix: prefix.to_string(), ops: ops.to_vec() }).collect();
+    let grant = Grant { client: client.to_string(), admin, rules, quota: None };
+    let mut body = serde_json::to_value(&grant).unwrap();
+    body["token"] = token.into();
+    send_as(state, "POST", "/admin/acl", by, &body.to_string()).await.0
+}
+
+#[tokio::test]
+async fn clients_only_reach_the_prefixes_they_were_granted() {
+    let sim = cluster(0, 1);
+    let state = guarded(&sim);
+
+    assert_eq!(grant(&state, None, "root-token", "root", true, &[]).await, StatusCode::OK);
+    assert_eq!(grant(&state, Some("root-token"), "app-token", "app", false, &[("app.", &[Op::Read, Op::Write])]).await, StatusCode::OK);
+
+    assert_eq!(send_as(&state, "PUT", "/kv/app.a", None, "1").await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(send_as(&state, "PUT", "/kv/app.a", Some("wrong"), "1").await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(send_as(&state, "PUT", "/kv/app.a", Some("app-token"), "1").await.0, StatusCode::OK);
+    assert_eq!(send_as(&state, "GET", "/kv/app.a", Some("app-token"), "").await, (StatusCode::OK, String::from("1")));
+    assert_eq!(send_as(&state, "DELETE", "/kv/app.a", Some("app-token"), "").await.0, StatusCode::FORBIDDEN);
+    assert_eq!(send_as(&state, "PUT", "/kv/other.a", Some("app-token"), "1").await.0, StatusCode::FORBIDDEN);
+
+    // A raw proposal of a KV command is held to the same rules.
+    let command = r#"{"op":"put","key":"other.b","value":"1"}"#;
+    assert_eq!(send_as(&state, "POST", "/prepare", Some("app-token"), command).await.0, StatusCode::FORBIDDEN);
+    assert!(sim.node(0).kv.lock().await.get("other.b").is_none());
+}
+
+#[tokio::test]
+async fn the_table_is_only_written_by_an_admin() {
+    let sim = cluster(0, 1);
+    let state = guarded(&sim);
+
+    assert_eq!(grant(&state, None, "app-token", "app", false, &[("", &[Op::Write])]).await, StatusCode::BAD_REQUEST, "the first grant must be an admin's");
+    assert_eq!(grant(&state, None, "root-token", "root", true, &[]).await, StatusCode::OK);
+    assert_eq!(grant(&state, None, "app-token", "app", false, &[("", &[Op::Write])]).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(grant(&state, Some("root-token"), "app-token", "app", false, &[("", &[Op::Write])]).await, StatusCode::OK);
+    assert_eq!(grant(&state, Some("app-token"), "mine", "me", true, &[]).await, StatusCode::FORBIDDEN);
+
+    // Even a client that may write every key can't write a grant through the KV API.
+    let planted = serde_json::to_string(&Grant { client: String::from("me"), admin: true, rules: Vec::new(), quota: None }).unwrap();
+    assert_eq!(send_as(&state, "PUT", "/kv/__acl%2Fanything", Some("app-token"), &planted).await.0, StatusCode::BAD_REQUEST);
+    let command = serde_json::json!({ "op": "put", "key": "__acl/anything", "value": planted }).to_string();
+    assert_eq!(send_as(&state, "POST", "/prepare", Some("app-token"), &command).await.0, StatusCode::BAD_REQUEST);
+
+    assert_eq!(send_as(&state, "DELETE", "/admin/acl/root", Some("root-token"), "").await.0, StatusCode::CONFLICT, "the last admin must stay");
+    assert_eq!(send_as(&state, "DELETE", "/admin/acl/app", Some("root-token"), "").await.0, StatusCode::OK);
+    assert_eq!(send_as(&state, "PUT", "/kv/a", Some("app-token"), "1").await.0, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn grants_are_replicated_to_every_node() {
+    let sim = cluster(0, 3);
+    let first = guarded(&sim);
+    assert_eq!(grant(&first, None, "root-token", "root", true, &[("", &[Op::Read, Op::Write, Op::Delete])]).await, StatusCode::OK);
+    sim.settle().await;
+
+    let mut other = sim.node(2).clone();
+    other.acl = true;
+    assert_eq!(send_as(&other, "PUT", "/kv/k", Some("root-token"), "v").await.0, StatusCode::OK);
+    assert_eq!(send_as(&other, "PUT", "/kv/k", Some("nobody"), "v").await.0, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn only_an_admin_reads_what_shows_every_key() {
+    let sim = Sim::new(0, SimConfig { nodes: 1, groups: vec![String::from("orders")], ..SimConfig::default() });
+    let state = guarded(&sim);
+    assert_eq!(grant(&state, None, "root-token", "root", true, &[]).await, StatusCode::OK);
+    assert_eq!(grant(&state, Some("root-token"), "app-token", "app", false, &[("", &[Op::Read])]).await, StatusCode::OK);
+
+    for path in ["/ledger", "/events", "/admin/snapshot", "/admin/chain", "/admin/chain/verify", "/groups/orders/ledger", "/groups/orders/admin/chain"] {
+        assert_eq!(send_as(&state, "GET", path, None, "").await.0, StatusCode::UNAUTHORIZED, "{}", path);
+        assert_eq!(send_as(&state, "GET", path, Some("app-token"), "").await.0, StatusCode::FORBIDDEN, "{}", path);
+    }
+    let (status, ledger) = send_as(&state, "GET", "/ledger", Some("root-token"), "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(ledger.contains("__acl/"), "{}", ledger);
+    assert_eq!(send_as(&state, "GET", "/groups/orders/ledger", Some("root-token"), "").await.0, StatusCode::OK);
+}
@@ -0,0 +1,170 @@
+use std::{sync::{Arc, Mutex}, time::Duration};
+use paxos_from_scratch::{
+    Ballot, ProposalId, Value,
+    apply::{self, ApplyHook},
+    kv::{Change, Command},
+    sim::{self, Sim, SimConfig},
+    storage,
+    trace::Snapshot,
+};
+
+#[test]
+fn a_stuck_apply_holds_up_neither_learns_nor_accepts() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+        let node = sim.node(1);
+        let put = Command::Put { key: String::from("k"), value: String::from("v") }.encode();
+        let ballot = |instance: u64| serde_json::json!({ "instance": instance, "id": { "round": 1, "node_id": 1 }, "value": put }).to_string();
+
+        let kv = node.kv.lock().await;
+        if sim.request(1, "/handle-learn", &ballot(1)).await.is_error() || sim.request(1, "/handle-accept", &ballot(2)).await.is_error() {
+            return Err(String::from("the node stopped learning or accepting behind its apply"));
+        }
+        if (node.ledger.len(), node.applier.lag()) != (1, 1) {
+            return Err(format!("expected 1 learned and 1 to apply, got {} and {}", node.ledger.len(), node.applier.lag()));
+        }
+
+        std::mem::drop(kv);
+        node.applier.caught_up().await;
+        if node.kv.lock().await.get("k").map(String::as_str) != Some("v") || node.applier.lag() != 0 {
+            return Err(String::from("the value wasn't applied once the store was free"));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn a_write_is_applied_where_it_was_made_before_it_returns() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+        for i in 0..5 {
+            let key = format!("k{}", i);
+            if sim.put(0, &key, "v").await.is_error() {
+                return Err(format!("writing {} failed", key));
+            }
+            if sim.get(0, &format!("/kv/{}", key)).await.body != "v" {
+                return Err(format!("{} wasn't readable right after it was written", key));
+            }
+        }
+        Ok(())
+    });
+}
+
+fn put(key: &str, value: &str) -> String {
+    Command::Put { key: String::from(key), value: String::from(value) }.encode()
+}
+
+async fn learn(sim: &Sim, index: usize, instance: u64, value: &str) {
+    let ballot = Ballot { instance, id: ProposalId::default(), value: Some(value.to_string()) };
+    let reply = sim.request(index, "/handle-learn", &serde_json::to_string(&ballot).unwrap()).await;
+    assert!(!reply.is_error(), "{}", reply.body);
+}
+
+async fn value(sim: &Sim, index: usize, key: &str) -> Option<String> {
+    sim.node(index).kv.lock().await.get(key).cloned()
+}
+
+#[tokio::test]
+async fn a_value_learned_early_waits_for_the_one_before_it() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    learn(&sim, 0, 2, &put("a", "2")).await;
+    sim.settle().await;
+    assert_eq!(value(&sim, 0, "a").await, None);
+    assert_eq!(sim.node(0).applier.lag(), 1);
+    assert_eq!(sim.node(0).applier.gap(), Some(1));
+
+    learn(&sim, 0, 1, &put("a", "1")).await;
+    sim.settle().await;
+    assert_eq!(value(&sim, 0, "a").await.as_deref(), Some("2"));
+    assert_eq!(sim.node(0).applier.index(), 2);
+    assert_eq!(sim.node(0).applier.gap(), None);
+}
+
+#[tokio::test]
+async fn a_gap_that_persists_is_fetched_from_the_voters() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    sim.partition(&[&[0, 1], &[2]]);
+    for key in ["a", "b", "c"] {
+        assert!(!sim.put(0, key, "1").await.is_error());
+    }
+    sim.settle().await;
+    sim.heal();
+
+    let last = sim.node(0).ledger.get(3).unwrap();
+    learn(&sim, 2, 3, &last).await;
+    assert_eq!(sim.node(2).applier.gap(), Some(1));
+
+    tokio::spawn(apply::fill_gaps(sim.node(2).clone()));
+    tokio::time::timeout(Duration::from_secs(10), sim.node(2).applier.applied(3)).await.unwrap();
+    for key in ["a", "b", "c"] {
+        assert_eq!(value(&sim, 2, key).await.as_deref(), Some("1"));
+    }
+}
+
+#[tokio::test]
+async fn a_restored_node_holds_what_lies_past_a_gap() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    let snapshot = Snapshot {
+        ledger: [(1, put("a", "1")), (3, put("c", "3"))].into_iter().collect(),
+        kv: [(String::from("a"), String::from("1"))].into_iter().collect(),
+        ..Snapshot::default()
+    };
+    storage::restore(sim.node(0), snapshot).await;
+    assert_eq!(sim.node(0).applier.index(), 1);
+    assert_eq!(sim.node(0).applier.gap(), Some(2));
+
+    learn(&sim, 0, 2, &put("b", "2")).await;
+    sim.settle().await;
+    assert_eq!(value(&sim, 0, "b").await.as_deref(), Some("2"));
+    assert_eq!(value(&sim, 0, "c").await.as_deref(), Some("3"));
+    assert_eq!(sim.node(0).applier.lag(), 0);
+}
+
+/// The changes of each instance, in the order they were applied.
+type Applied = Vec<(u64, Vec<Change>)>;
+
+/// Keeps every change it is told about.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Applied>>);
+
+impl ApplyHook for Recorder {
+    fn applied(&self, instance: u64, _value: &Value, changes: &[Change]) {
+        self.0.lock().unwrap().push((instance, changes.to_vec()));
+    }
+}
+
+struct Panicking;
+
+impl ApplyHook for Panicking {
+    fn applied(&self, _instance: u64, _value: &Value, _changes: &[Change]) {
+        panic!("a hook went wrong");
+    }
+}
+
+fn change(key: &str, before: Option<&str>, after: Option<&str>) -> Change {
+    Change { key: String::from(key), before: before.map(String::from), after: after.map(String::from) }
+}
+
+#[tokio::test]
+async fn hooks_see_each_value_applied_with_what_it_changed() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    let recorder = Recorder::default();
+    sim.node(1).hooks.register(Panicking);
+    sim.node(1).hooks.register(recorder.clone());
+
+    learn(&sim, 1, 2, &put("a", "2")).await;
+    learn(&sim, 1, 1, &put("a", "1")).await;
+    learn(&sim, 1, 3, &Command::Delete { key: String::from("a") }.encode()).await;
+    learn(&sim, 1, 4, "not a command").await;
+    sim.settle().await;
+
+    let seen = recorder.0.lock().unwrap().clone();
+    assert_eq!(seen, vec![
+        (1, vec![change("a", None, Some("1"))]),
+        (2, vec![change("a", Some("1"), Some("2"))]),
+        (3, vec![change("a", Some("2"), None)]),
+        (4, vec![]),
+    ]);
+    assert_eq!(sim.node(1).applier.index(), 4, "the panicking hook stopped the node applying");
+    assert!(sim.node(0).hooks.is_empty());
+}
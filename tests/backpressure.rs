@@ -0,0 +1,43 @@
+use std::time::Duration;
+use paxos_from_scratch::backpressure::{Backpressure, Limits};
+
+#[tokio::test]
+async fn proposals_over_the_limit_queue_and_then_get_turned_away() {
+    let backpressure = Backpressure::default();
+    let limits = Limits { max_in_flight: 1, max_queued: 1 };
+
+    let running = backpressure.admit(limits).await.unwrap();
+    let waiting = backpressure.admit(limits);
+    tokio::pin!(waiting);
+    assert!(tokio::time::timeout(Duration::from_millis(50), &mut waiting).await.is_err());
+    assert_eq!((backpressure.running(), backpressure.queued()), (1, 1));
+
+    assert!(backpressure.admit(limits).await.is_none(), "the queue is full");
+
+    drop(running);
+    let next = waiting.await.unwrap();
+    assert_eq!((backpressure.running(), backpressure.queued()), (1, 0));
+    drop(next);
+    assert_eq!(backpressure.running(), 0);
+}
+
+#[tokio::test]
+async fn a_proposal_given_up_on_leaves_the_queue() {
+    let backpressure = Backpressure::default();
+    let limits = Limits { max_in_flight: 1, max_queued: 2 };
+
+    let running = backpressure.admit(limits).await.unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(50), backpressure.admit(limits)).await.is_err());
+    assert_eq!(backpressure.queued(), 0);
+
+    drop(running);
+    assert!(backpressure.admit(limits).await.is_some());
+}
+
+#[tokio::test]
+async fn no_limit_never_queues() {
+    let backpressure = Backpressure::default();
+    let slots: Vec<_> = futures::future::join_all((0..100).map(|_| backpressure.admit(Limits::default()))).await;
+    assert!(slots.iter().all(Option::is_some));
+    assert_eq!(backpressure.running(), 100);
+}
@@ -0,0 +1,79 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    AppState,
+    barrier::{self, Passed},
+    kv::{Command, Kv},
+    lease::Action,
+};
+
+mod common;
+use common::{cluster, send};
+
+async fn enter(state: &AppState, participant: &str, count: usize, timeout_ms: u64) -> (StatusCode, String) {
+    let body = serde_json::json!({ "participant": participant, "count": count, "timeout_ms": timeout_ms });
+    send(state, "POST", "/barriers/start/enter", &body.to_string()).await
+}
+
+/// Enters `participant` through `state` once it sees `before` waiting, so
+/// the entries don't race for one instance.
+async fn enter_after(state: &AppState, before: usize, participant: &str) -> (StatusCode, String) {
+    while barrier::get(&*state.kv.lock().await, "start").map_or(0, |barrier| barrier.waiting.len()) < before {
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    }
+    enter(state, participant, 3, 10_000).await
+}
+
+fn enter_command(participant: &str, lease: Option<&str>, at: u64) -> String {
+    let (participant, lease) = (participant.to_string(), lease.map(str::to_string));
+    Command::Enter { barrier: String::from("start"), participant, count: 2, lease, at }.encode()
+}
+
+#[tokio::test]
+async fn participants_on_every_node_pass_together() {
+    let sim = cluster(0, 3);
+    let result: Result<(), String> = async {
+        let (a, b, c) = tokio::join!(enter_after(sim.node(0), 0, "a"), enter_after(sim.node(1), 1, "b"), enter_after(sim.node(2), 2, "c"));
+        for (status, body) in [a, b, c] {
+            let passed: Passed = serde_json::from_str(&body).map_err(|_| format!("{}: {}", status, body))?;
+            if passed.generation != 1 || passed.participants.len() != 3 {
+                return Err(format!("the barrier let through {:?}", passed));
+            }
+        }
+
+        let (status, body) = enter(sim.node(0), "a", 2, 200).await;
+        if status != StatusCode::GATEWAY_TIMEOUT {
+            return Err(format!("a passed the next round alone: {}", body));
+        }
+        let (status, body) = enter(sim.node(0), "b", 3, 200).await;
+        if status != StatusCode::CONFLICT {
+            return Err(format!("b entered a round for 2 as one for 3: {}", body));
+        }
+        let (status, body) = enter(sim.node(0), "b", 2, 200).await;
+        let passed: Passed = serde_json::from_str(&body).map_err(|_| format!("{}: {}", status, body))?;
+        if passed.generation != 2 {
+            return Err(format!("the second round let through {:?}", passed));
+        }
+        Ok(())
+    }.await;
+    result.unwrap();
+}
+
+#[test]
+fn a_participant_leaves_with_its_lease() {
+    let mut kv = Kv::default();
+    let acquire = Action::Acquire { holder: String::from("a"), ttl_ms: 1 };
+    kv.apply(&Command::Lease { name: String::from("session"), at: 1_000, action: acquire }.encode());
+
+    kv.apply(&enter_command("a", Some("session"), 1_500));
+    assert!(barrier::get(&kv, "start").unwrap().waiting.contains_key("a"));
+    kv.apply(&enter_command("b", Some("session"), 1_500));
+    assert!(!barrier::get(&kv, "start").unwrap().waiting.contains_key("b"), "b doesn't hold the lease");
+
+    kv.apply(&enter_command("b", None, 2_000));
+    let barrier = barrier::get(&kv, "start").unwrap();
+    assert_eq!((barrier.generation, barrier.waiting.len()), (0, 1), "a's lease ran out at 2000, so b waits alone");
+    kv.apply(&enter_command("c", None, 2_000));
+    let barrier = barrier::get(&kv, "start").unwrap();
+    assert_eq!(barrier.generation, 1);
+    assert_eq!(barrier.passed.into_keys().collect::<Vec<_>>(), ["b", "c"]);
+}
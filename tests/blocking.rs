@@ -0,0 +1,29 @@
+#![cfg(feature = "blocking")]
+
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use paxos_from_scratch::{blocking::Client, client::ClientError, cluster::{ClusterBuilder, Network::Http}};
+
+#[test]
+fn a_blocking_client_reads_and_writes_without_a_runtime() {
+    // The nodes run on a runtime of their own, as they would in another process.
+    let runtime = Runtime::new().unwrap();
+    let cluster = runtime.block_on(ClusterBuilder::new().nodes(3).transport(Http).start()).unwrap();
+    let client = Client::new(&cluster.node(0).addr().unwrap().to_string());
+
+    client.put("x", "1").unwrap();
+    assert_eq!(client.get("x").unwrap().as_deref(), Some("1"));
+    assert_eq!(client.get("y").unwrap(), None);
+    let page = client.watch("x", Some(0), Duration::from_millis(100)).unwrap();
+    assert_eq!(page.events.len(), 1);
+    assert_eq!(client.state().unwrap().members.len(), 2);
+
+    client.delete("x").unwrap();
+    assert_eq!(client.get("x").unwrap(), None);
+}
+
+#[test]
+fn a_blocking_client_says_when_nothing_answers() {
+    let client = Client::new("localhost:1");
+    assert!(matches!(client.status(), Err(ClientError::Unreachable { .. })));
+}
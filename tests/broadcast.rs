@@ -0,0 +1,37 @@
+use futures::StreamExt;
+use paxos_from_scratch::{
+    broadcast::{AtomicBroadcast, Broadcast, Delivery},
+    sim::{self, Sim, SimConfig},
+};
+
+#[test]
+fn every_node_delivers_the_same_messages_in_the_same_order() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+        let nodes: Vec<Broadcast> = (0..sim.size()).map(|index| Broadcast::new(sim.node(index).clone())).collect();
+        let mut streams: Vec<_> = nodes.iter().map(|node| node.deliveries()).collect();
+
+        let mut instances = Vec::new();
+        for round in 0..6 {
+            let message = format!("m{}", round);
+            instances.push(nodes[round % nodes.len()].broadcast(message).await?);
+            sim.settle().await;
+        }
+        if nodes[0].broadcast(String::new()).await.is_ok() {
+            return Err(String::from("an empty message was broadcast"));
+        }
+
+        let mut delivered: Vec<Vec<Delivery>> = Vec::new();
+        for stream in &mut streams {
+            delivered.push(stream.by_ref().take(instances.len()).collect().await);
+        }
+        let order: Vec<u64> = delivered[0].iter().map(|delivery| delivery.instance).collect();
+        if order != instances {
+            return Err(format!("node 0 delivered instances {:?}, broadcasts got {:?}", order, instances));
+        }
+        if delivered.iter().any(|deliveries| *deliveries != delivered[0]) {
+            return Err(format!("the nodes delivered different orders: {:?}", delivered));
+        }
+        Ok(())
+    });
+}
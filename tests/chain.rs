@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    chain::{self, Audit, GENESIS, Proof, ProvedRead},
+    ed25519::Keypair,
+    hex,
+    sim::{self, Sim, SimConfig},
+};
+
+fn keypair(n: u8) -> Keypair {
+    Keypair::from_seed(&[n; 32])
+}
+
+/// A cluster that has chosen `count` values through node 0.
+async fn cluster(seed: u64, count: usize, signed: bool) -> Result<Sim, String> {
+    let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+    if signed {
+        for index in 0..sim.size() {
+            let node = sim.node(index);
+            node.keys.set_own(node.node.id, keypair(node.node.id as u8));
+            for peer in 1..=sim.size() as u64 {
+                node.keys.trust(peer, keypair(peer as u8).public());
+            }
+        }
+    }
+
+    for i in 0..count {
+        let reply = sim.put(0, &format!("k{}", i), &format!("v{}", i)).await;
+        if reply.is_error() {
+            return Err(format!("write {} failed: {}", i, reply.body));
+        }
+    }
+    sim.settle().await;
+    Ok(sim)
+}
+
+async fn audit(sim: &Sim, index: usize) -> (StatusCode, Audit) {
+    let reply = sim.get(index, "/admin/chain/verify").await;
+    let audit = reply.json().unwrap_or_else(|e| panic!("{}: {}", e, reply.body));
+    (reply.status, audit)
+}
+
+#[test]
+fn every_node_chains_the_same_history() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, 5, false).await?;
+
+        let mut heads = Vec::new();
+        for index in 0..sim.size() {
+            let ledger = &sim.node(index).ledger;
+            if ledger.chained() != ledger.last().unwrap_or(0) {
+                return Err(format!("node {} chained {} of {} instances", index, ledger.chained(), ledger.len()));
+            }
+
+            let mut prev = GENESIS;
+            for instance in 1..=ledger.chained() {
+                prev = chain::link(&prev, instance, &ledger.get(instance).unwrap());
+                if ledger.link(instance) != Some(prev) {
+                    return Err(format!("node {} chained instance {} to another hash", index, instance));
+                }
+            }
+            heads.push(prev);
+        }
+
+        if heads.windows(2).any(|pair| pair[0] != pair[1]) {
+            return Err(String::from("the nodes ended on different hashes"));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn a_peer_audits_with_the_certificates_the_proposer_kept() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, 5, false).await?;
+        let proposer = sim.node(0);
+        if proposer.certificates.len() as u64 != proposer.ledger.chained() || !sim.node(1).certificates.is_empty() {
+            return Err(String::from("only the proposer should have certified, and everything"));
+        }
+
+        let (status, audit) = audit(&sim, 1).await;
+        if status != StatusCode::OK || !audit.uncertified.is_empty() || audit.head.is_none() {
+            return Err(format!("the audit came back {}: {:?}", status, audit));
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn a_value_changed_after_the_fact_fails_the_audit() {
+    let sim = cluster(0, 3, false).await.unwrap();
+    let node = sim.node(0);
+    let before = node.ledger.link(3);
+    {
+        let mut ledger = node.ledger.write().await;
+        let mut values = ledger.to_map();
+        values.insert(2, String::from("forged"));
+        ledger.replace(values);
+    }
+
+    assert_ne!(node.ledger.link(3), before, "every hash after a changed value changes");
+    let (status, audit) = audit(&sim, 0).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(audit.invalid.iter().map(|invalid| invalid.instance).collect::<Vec<_>>(), vec![2]);
+}
+
+#[tokio::test]
+async fn signed_acks_hold_up_and_forged_ones_dont() {
+    let sim = cluster(0, 2, true).await.unwrap();
+    let node = sim.node(1);
+    let value = node.ledger.get(1).unwrap();
+    let certificate = sim.node(0).certificates.get(1).unwrap();
+    assert!(certificate.acks.iter().all(|ack| ack.signature.is_some()));
+    assert_eq!(certificate.check(&value, &node.keys), Ok(()));
+    assert_eq!(audit(&sim, 1).await.0, StatusCode::OK);
+
+    // Without the signatures of a quorum, the acks count for nothing.
+    let mut forged = certificate.clone();
+    for ack in forged.acks.iter_mut().skip(1) {
+        ack.signature = Some(hex::encode(&keypair(99).sign(&chain::ack_message(1, forged.id, &value))));
+    }
+    assert!(forged.check(&value, &node.keys).is_err());
+    assert!(certificate.check("something else", &node.keys).is_err());
+}
+
+fn public_keys(count: u64) -> BTreeMap<u64, [u8; 32]> {
+    (1..=count).map(|id| (id, keypair(id as u8).public())).collect()
+}
+
+#[tokio::test]
+async fn a_read_comes_with_a_proof_any_client_can_check() {
+    let sim = cluster(0, 3, true).await.unwrap();
+
+    // Node 1 kept no certificates, so it gets the one it needs from node 0.
+    let reply = sim.get(1, "/kv/k1?proof=true").await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    let read: ProvedRead = reply.json().unwrap();
+    assert_eq!((read.key.as_str(), read.value.as_str()), ("k1", "v1"));
+    assert!(read.proof.writes("k1", "v1") && !read.proof.writes("k1", "v2"));
+    assert!(read.proof.verify(&public_keys(3)).unwrap().len() >= 2);
+
+    let reply = sim.get(2, &format!("/proof/{}", read.proof.instance)).await;
+    assert_eq!(reply.json::<Proof>().unwrap(), read.proof);
+    assert_eq!(sim.get(1, "/proof/99").await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn a_proof_holds_only_with_a_quorum_of_the_keys_the_client_knows() {
+    let sim = cluster(0, 1, true).await.unwrap();
+    let proof: Proof = sim.get(0, "/proof/1").await.json().unwrap();
+    let keys = public_keys(3);
+    assert!(proof.verify(&keys).is_ok());
+
+    let mut changed = proof.clone();
+    changed.value = String::from(r#"{"op":"put","key":"k0","value":"forged"}"#);
+    assert!(changed.verify(&keys).is_err(), "the hash no longer covers the value");
+
+    // A node can't shrink the quorum by claiming fewer voters.
+    let mut shrunk = proof.clone();
+    shrunk.certificate.voters = 1;
+    shrunk.certificate.acks.truncate(1);
+    assert!(shrunk.verify(&keys).is_err());
+    assert!(proof.verify(&(1..=3).map(|id| (id, keypair(99).public())).collect()).is_err(), "signed with other keys");
+
+    let unsigned = cluster(0, 1, false).await.unwrap();
+    let proof: Proof = unsigned.get(0, "/proof/1").await.json().unwrap();
+    assert!(proof.verify(&keys).is_err(), "acks without signatures prove nothing");
+}
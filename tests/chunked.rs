@@ -0,0 +1,103 @@
+use std::sync::Arc;
+use axum::{body::Body, http::{Request, StatusCode, header}};
+
+use paxos_from_scratch::{
+    chunked::{self, CHUNK_BYTES, Decoder},
+    sim,
+    storage::{SEGMENT_BYTES, Storage},
+};
+
+mod common;
+use common::{call, cluster};
+
+fn upload(key: &str, body: Vec<u8>) -> Request<Body> {
+    Request::put(format!("/kv/{}", key)).header(header::CONTENT_TYPE, chunked::CONTENT_TYPE).body(Body::from(body)).unwrap()
+}
+
+#[test]
+fn a_value_sent_in_chunks_is_stored_and_read_back_in_chunks() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, 3);
+        let value: String = (0..3 * CHUNK_BYTES + 17).map(|i| char::from(b'a' + (i % 26) as u8)).collect();
+
+        let (status, _) = call(sim.node(0), upload("big", chunked::encode(value.as_bytes(), CHUNK_BYTES))).await;
+        if status != StatusCode::OK {
+            return Err(format!("the upload was answered with {}", status));
+        }
+        sim.settle().await;
+
+        for index in 0..sim.size() {
+            if sim.node(index).kv.lock().await.get("big") != Some(&value) {
+                return Err(format!("node {} doesn't have the whole value", index));
+            }
+        }
+
+        let request = Request::get("/kv/big").header(header::ACCEPT, chunked::CONTENT_TYPE).body(Body::empty()).unwrap();
+        let (_, body) = call(sim.node(1), request).await;
+        match chunked::decode(body, usize::MAX).await {
+            Ok(read) if read == value.as_bytes() => Ok(()),
+            Ok(read) => Err(format!("read back {} bytes of {}", read.len(), value.len())),
+            Err(e) => Err(format!("the answer didn't decode: {}", e)),
+        }
+    });
+}
+
+#[test]
+fn a_corrupt_chunk_is_refused_before_anything_is_proposed() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, 3);
+        let mut body = chunked::encode(&[b'x'; 1000], 100);
+        let last = body.len() - chunked::frame(&[]).len() - 1;
+        body[last] = b'y';
+
+        let (status, _) = call(sim.node(0), upload("k", body)).await;
+        if status != StatusCode::BAD_REQUEST {
+            return Err(format!("a corrupt upload was answered with {}", status));
+        }
+        match sim.node(0).ledger.len() {
+            0 => Ok(()),
+            learned => Err(format!("{} values were learned from it", learned)),
+        }
+    });
+}
+
+#[test]
+fn a_body_may_arrive_in_pieces_of_any_size() {
+    let body = chunked::encode(b"hello, world", 5);
+
+    let mut decoder = Decoder::default();
+    let mut data = Vec::new();
+    for byte in &body {
+        decoder.push(std::slice::from_ref(byte), &mut data).unwrap();
+    }
+    decoder.finish().unwrap();
+    assert_eq!(data, b"hello, world");
+
+    let mut decoder = Decoder::default();
+    decoder.push(&body[..body.len() - 1], &mut Vec::new()).unwrap();
+    assert!(decoder.finish().is_err(), "a body cut short must not pass");
+    assert!(Decoder::default().push(b"zz 0\n", &mut Vec::new()).is_err());
+}
+
+#[tokio::test]
+async fn the_snapshot_is_served_in_chunks() {
+    let dir = std::env::temp_dir().join(format!("paxos-chunked-snapshot-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+
+    let sim = cluster(0, 1);
+    let mut state = sim.node(0).clone();
+    state.storage = Some(Arc::new(storage));
+
+    let get = || Request::get("/admin/snapshot").body(Body::empty()).unwrap();
+    assert_eq!(call(&state, get()).await.0, StatusCode::NOT_FOUND);
+
+    state.ledger.write().await.insert(1, String::from("a"));
+    let (status, _) = call(&state, Request::post("/admin/snapshot").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = call(&state, get()).await;
+    assert_eq!(status, StatusCode::OK);
+    let served = chunked::decode(body, usize::MAX).await.unwrap();
+    assert_eq!(served, std::fs::read(dir.join("snapshot.json")).unwrap());
+}
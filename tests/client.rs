@@ -0,0 +1,48 @@
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use paxos_from_scratch::{
+    router,
+    client::{Client, ClientError},
+    sim::{Sim, SimConfig},
+};
+
+/// Serves node `index` of `sim` over HTTP, and answers a client of it.
+async fn serve(sim: &Sim, index: usize) -> Client {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = router(sim.node(index).clone()).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    Client::new(&addr.to_string())
+}
+
+#[tokio::test]
+async fn a_client_writes_reads_and_proposes_through_a_node() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    let client = serve(&sim, 0).await;
+    assert!(client.node().starts_with("http://127.0.0.1:"));
+
+    assert_eq!(client.get("x").await.unwrap(), None);
+    client.put("x", "1").await.unwrap();
+    assert_eq!(client.get("x").await.unwrap().as_deref(), Some("1"));
+    client.delete("x").await.unwrap();
+    assert_eq!(client.get("x").await.unwrap(), None);
+
+    client.propose("hello").await.unwrap();
+    let status = client.status().await.unwrap();
+    assert_eq!((status.node, status.commit_index), (1, 3));
+    assert_eq!(client.state().await.unwrap().ledger_size, 3);
+}
+
+#[tokio::test]
+async fn a_client_says_why_a_request_failed() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    let client = serve(&sim, 0).await;
+    sim.request(0, "/admin/pause", "").await;
+    match client.put("x", "1").await {
+        Err(ClientError::Refused { status, .. }) => assert_eq!(status.as_u16(), 503),
+        other => panic!("expected a refusal, got {:?}", other),
+    }
+
+    let nobody = Client::new("http://127.0.0.1:1");
+    assert!(matches!(nobody.status().await, Err(ClientError::Unreachable { .. })));
+}
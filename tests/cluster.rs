@@ -0,0 +1,35 @@
+use paxos_from_scratch::cluster::{ClusterBuilder, Network::{Http, InMemory}};
+
+#[tokio::test]
+async fn a_cluster_in_memory_replicates_writes() {
+    let cluster = ClusterBuilder::new().nodes(5).transport(InMemory).start().await.unwrap();
+    assert_eq!(cluster.nodes().iter().map(|node| node.id()).collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+
+    assert!(!cluster.node(0).put("x", "1").await.is_error());
+    cluster.settle().await;
+    for node in cluster.nodes() {
+        assert_eq!(node.get("/kv/x").await.body, "1", "node {}", node.id());
+    }
+    assert!(cluster.sim().unwrap().check_agreement().await.is_ok());
+}
+
+#[tokio::test]
+async fn a_cluster_in_memory_can_be_partitioned() {
+    let cluster = ClusterBuilder::new().nodes(3).seed(7).start().await.unwrap();
+    cluster.sim().unwrap().partition(&[&[0], &[1, 2]]);
+    assert!(cluster.node(0).put("x", "1").await.is_error(), "a minority wrote");
+    assert!(!cluster.node(1).put("x", "2").await.is_error());
+}
+
+#[tokio::test]
+async fn a_cluster_over_http_replicates_writes() {
+    let cluster = ClusterBuilder::new().nodes(3).learners(1).transport(Http).start().await.unwrap();
+    assert!(cluster.sim().is_none());
+    assert!(cluster.node(2).state().node.learner);
+
+    let client = cluster.node(0).client().unwrap();
+    client.put("x", "1").await.unwrap();
+    cluster.settle().await;
+    assert_eq!(cluster.node(2).client().unwrap().get("x").await.unwrap().as_deref(), Some("1"));
+    assert_eq!(client.state().await.unwrap().members.len(), 2);
+}
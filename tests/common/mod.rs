@@ -0,0 +1,50 @@
+//! What the integration tests share: a cluster to run against, and requests
+//! sent through a node's router the way a client would.
+
+// Each test binary uses a part of it.
+#![allow(dead_code)]
+
+use axum::{body::{Body, to_bytes}, http::{Request, StatusCode, request::Builder}};
+use tower::ServiceExt;
+use paxos_from_scratch::{
+    AppState, router,
+    sim::{Sim, SimConfig},
+};
+
+/// A cluster of `nodes` inside this process, its network seeded with `seed`.
+pub fn cluster(seed: u64, nodes: usize) -> Sim {
+    Sim::new(seed, SimConfig { nodes, ..SimConfig::default() })
+}
+
+/// A request with a JSON body, as clients send them.
+pub fn json(method: &str, uri: &str) -> Builder {
+    Request::builder().method(method).uri(uri).header("content-type", "application/json")
+}
+
+/// Answers `request` the way `state` would, leaving the body unread.
+pub async fn call(state: &AppState, request: Request<Body>) -> (StatusCode, Body) {
+    let response = router(state.clone()).oneshot(request).await.unwrap();
+    (response.status(), response.into_body())
+}
+
+/// Sends `request` with `body`, and answers the status and the body read
+/// as text.
+pub async fn send_request(state: &AppState, request: Builder, body: &str) -> (StatusCode, String) {
+    let (status, body) = call(state, request.body(Body::from(body.to_string())).unwrap()).await;
+    let bytes = to_bytes(body, usize::MAX).await.unwrap();
+    (status, String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Sends `body` as JSON to `uri`.
+pub async fn send(state: &AppState, method: &str, uri: &str, body: &str) -> (StatusCode, String) {
+    send_request(state, json(method, uri), body).await
+}
+
+/// Like [`send`], with `token` as the bearer token, if there is one.
+pub async fn send_as(state: &AppState, method: &str, uri: &str, token: Option<&str>, body: &str) -> (StatusCode, String) {
+    let mut request = json(method, uri);
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    send_request(state, request, body).await
+}
@@ -0,0 +1,23 @@
+use paxos_from_scratch::{crash, sim::{self, Sim, SimConfig}};
+
+#[test]
+fn the_crash_summary_skips_what_is_locked() {
+    sim::run(1, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+        sim.propose(0, "a").await;
+
+        let summary = crash::summary(sim.node(0));
+        for line in ["node: 1", "proposer round: 1", "learned: 1 instances, up to 1; next instance 2", "peers: [2, 3]"] {
+            if !summary.contains(line) {
+                return Err(format!("{:?} is missing from:\n{}", line, summary));
+            }
+        }
+
+        let _kv = sim.node(0).kv.lock().await;
+        let summary = crash::summary(sim.node(0));
+        if !summary.contains("kv: locked") {
+            return Err(format!("a held lock wasn't reported:\n{}", summary));
+        }
+        Ok(())
+    });
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::Ordering;
+use axum::http::StatusCode;
+
+use paxos_from_scratch::{
+    Ballot, ProposalId,
+    dedup::MESSAGE_ID_HEADER,
+    faults::{Direction, FaultRule},
+    sim::{self, Sim},
+    transport::NODE_ID_HEADER,
+};
+
+mod common;
+use common::{cluster, json, send_request};
+
+/// Sends `body` to `path` on node `index` as message `id` of node `peer`.
+async fn send(sim: &Sim, index: usize, peer: u64, id: &str, path: &str, body: String) -> StatusCode {
+    let request = json("POST", path).header(NODE_ID_HEADER, peer).header(MESSAGE_ID_HEADER, id);
+    send_request(sim.node(index), request, &body).await.0
+}
+
+fn learn(instance: u64) -> String {
+    serde_json::to_string(&Ballot { instance, id: ProposalId::default(), value: Some(format!("v{}", instance)) }).unwrap()
+}
+
+#[tokio::test]
+async fn a_message_seen_before_is_answered_without_handling_it() {
+    let sim = cluster(0, 3);
+    assert_eq!(send(&sim, 0, 2, "7.1", "/handle-learn", learn(1)).await, StatusCode::OK);
+    assert_eq!(send(&sim, 0, 2, "7.1", "/handle-learn", learn(2)).await, StatusCode::OK);
+    assert_eq!(sim.node(0).ledger.len(), 1, "the second message was handled");
+    assert_eq!(sim.node(0).dedup.suppressed(), 1);
+
+    // Ids are only unique to their sender.
+    assert_eq!(send(&sim, 0, 3, "7.1", "/handle-learn", learn(2)).await, StatusCode::OK);
+    assert_eq!(sim.node(0).ledger.len(), 2);
+}
+
+#[tokio::test]
+async fn a_message_that_failed_is_handled_again() {
+    let sim = cluster(0, 3);
+    let prepare = serde_json::json!({ "instance": 1, "id": { "round": 1, "node_id": 2 } }).to_string();
+
+    sim.node(0).syncing.store(true, Ordering::SeqCst);
+    assert_eq!(send(&sim, 0, 2, "7.1", "/handle-prepare", prepare.clone()).await, StatusCode::SERVICE_UNAVAILABLE);
+    sim.node(0).syncing.store(false, Ordering::SeqCst);
+    assert_eq!(send(&sim, 0, 2, "7.1", "/handle-prepare", prepare).await, StatusCode::OK);
+    assert_eq!(sim.node(0).dedup.suppressed(), 0);
+}
+
+#[test]
+fn duplicated_messages_are_suppressed() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, 3);
+        let rule = FaultRule { direction: Direction::Inbound, duplicate: 100.0, ..FaultRule::default() };
+        sim.node(1).faults.add(rule);
+
+        for key in ["a", "b", "c"] {
+            if sim.put(0, key, "1").await.is_error() {
+                return Err(format!("the write of {} failed", key));
+            }
+        }
+        sim.settle().await;
+        sim.check_agreement().await?;
+
+        if sim.node(1).dedup.suppressed() == 0 {
+            return Err(String::from("node 2 handled every duplicate again"));
+        }
+        if !sim.get(1, "/metrics").await.body.contains("paxos_duplicates_suppressed") {
+            return Err(String::from("the metrics don't count suppressed duplicates"));
+        }
+        Ok(())
+    });
+}
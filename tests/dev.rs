@@ -0,0 +1,42 @@
+use std::{net::TcpListener, path::{Path, PathBuf}};
+use paxos_from_scratch::{dev::DevCluster, survey};
+
+const PROGRAM: &str = env!("CARGO_BIN_EXE_paxos-from-scratch");
+
+/// A port nothing listens on, and likely nothing on the few after it.
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+#[tokio::test]
+async fn a_dev_cluster_starts_connected_nodes() {
+    let cluster = DevCluster { nodes: 3, base_port: free_port(), data_dir: None, args: Vec::new() };
+    let mut running = cluster.start(Path::new(PROGRAM)).await.unwrap();
+
+    let survey = survey::run(&running.client(0)).await.unwrap();
+    assert_eq!(survey.members.iter().map(|member| member.id).collect::<Vec<_>>(), [1, 2, 3]);
+    assert!(survey.members.iter().all(|member| member.state.is_ok()), "{}", survey);
+
+    running.client(0).put("x", "1").await.unwrap();
+    assert_eq!(running.client(2).get("x").await.unwrap().as_deref(), Some("1"));
+    running.stop().await;
+    assert!(running.client(0).status().await.is_err(), "node 1 still answers");
+}
+
+#[test]
+fn each_node_gets_its_id_port_and_data_directory() {
+    let cluster = DevCluster { nodes: 3, base_port: 4000, data_dir: Some(PathBuf::from("data")), args: vec![String::from("--shards"), String::from("2")] };
+    assert_eq!(cluster.members(), [(1, 4000), (2, 4001), (3, 4002)]);
+
+    let command = cluster.command(Path::new("paxos"), 2, 4001);
+    let args: Vec<_> = command.as_std().get_args().map(|arg| arg.to_str().unwrap()).collect();
+    assert_eq!(args, ["--id", "2", "--port", "4001", "--data-dir", "data/2", "--shards", "2"]);
+}
+
+#[tokio::test]
+async fn a_dev_cluster_has_to_fit_on_the_ports() {
+    let cluster = DevCluster { nodes: 0, base_port: 4000, data_dir: None, args: Vec::new() };
+    assert!(cluster.start(Path::new(PROGRAM)).await.is_err());
+    let cluster = DevCluster { nodes: 2, base_port: u16::MAX, data_dir: None, args: Vec::new() };
+    assert!(cluster.start(Path::new(PROGRAM)).await.is_err());
+}
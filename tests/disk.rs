@@ -0,0 +1,41 @@
+use paxos_from_scratch::{
+    disk::{self, Disk},
+    sim::{self, Sim, SimConfig, DEFAULT_SEEDS},
+};
+
+#[test]
+fn a_node_goes_degraded_under_the_threshold_and_back_above_it() {
+    let disk = Disk::default();
+    assert_eq!(disk.update(100, 0), None, "no threshold, never low");
+    assert_eq!(disk.update(100, 200), Some(true));
+    assert_eq!(disk.update(150, 200), None);
+    assert!(disk.is_low());
+    assert_eq!(disk.update(300, 200), Some(false));
+    assert_eq!(disk.free(), 300);
+
+    assert!(disk::free_bytes(&std::env::temp_dir()).unwrap() > 0);
+}
+
+#[test]
+fn nodes_low_on_space_refuse_to_vote_until_it_is_freed() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+
+        for node in [1, 2] {
+            sim.node(node).disk.update(0, 1);
+        }
+        let reply = sim.propose(1, "full").await;
+        if reply.body != disk::LOW_ON_SPACE {
+            return Err(format!("a node low on space took a proposal: {:?}", reply));
+        }
+        if !sim.propose(0, "no quorum").await.is_error() {
+            return Err(String::from("a value was decided without a quorum"));
+        }
+
+        sim.node(1).disk.update(1, 1);
+        if sim.propose(0, "freed").await.is_error() {
+            return Err(String::from("the cluster didn't decide once space was freed"));
+        }
+        sim.check_agreement().await
+    });
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    AppState,
+    election::{Leader, Successions},
+};
+
+mod common;
+use common::{cluster, send};
+
+async fn campaign(state: &AppState, candidate: &str, timeout_ms: u64) -> (StatusCode, String) {
+    let body = serde_json::json!({ "candidate": candidate, "ttl_ms": 60_000, "value": format!("{}:8080", candidate), "timeout_ms": timeout_ms });
+    send(state, "POST", "/elections/db/campaign", &body.to_string()).await
+}
+
+fn leader((status, body): (StatusCode, String)) -> Result<Leader, String> {
+    serde_json::from_str(&body).map_err(|_| format!("{}: {}", status, body))
+}
+
+#[tokio::test]
+async fn a_candidate_leads_once_the_leader_resigns() {
+    let sim = cluster(0, 3);
+
+    let elected = leader(campaign(sim.node(0), "a", 1_000).await).unwrap();
+    assert_eq!((elected.leader.as_str(), elected.value.as_deref()), ("a", Some("a:8080")));
+    assert_eq!(campaign(sim.node(0), "b", 100).await.0, StatusCode::GATEWAY_TIMEOUT, "a still leads");
+    sim.settle().await;
+    assert_eq!(leader(send(sim.node(1), "GET", "/elections/db/observe", "").await).unwrap().leader, "a");
+
+    let resign = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        send(sim.node(0), "POST", "/elections/db/resign", r#"{"candidate":"a"}"#).await
+    };
+    let (observed, elected, resigned) = tokio::join!(
+        send(sim.node(2), "GET", "/elections/db/observe?leader=a&timeout_ms=10000", ""),
+        campaign(sim.node(1), "b", 10_000),
+        resign,
+    );
+    assert_eq!(resigned.0, StatusCode::OK);
+    assert_eq!(leader(elected).unwrap().value.as_deref(), Some("b:8080"));
+    // Whoever observes sees a go, whether b took over yet or not.
+    assert!(observed.0 == StatusCode::NOT_FOUND || leader(observed).unwrap().leader == "b");
+}
+
+#[tokio::test]
+async fn only_the_leader_can_resign() {
+    let sim = cluster(0, 1);
+    leader(campaign(sim.node(0), "a", 1_000).await).unwrap();
+    assert_eq!(send(sim.node(0), "POST", "/elections/db/resign", r#"{"candidate":"b"}"#).await.0, StatusCode::CONFLICT);
+
+    let renewed = leader(campaign(sim.node(0), "a", 1_000).await).unwrap();
+    assert_eq!(renewed.leader, "a", "campaigning again keeps the lead");
+}
+
+#[tokio::test]
+async fn an_observer_resumes_from_a_commit_index() {
+    let sim = cluster(0, 1);
+    leader(campaign(sim.node(0), "a", 1_000).await).unwrap();
+    let since = sim.node(0).applier.index();
+    leader(campaign(sim.node(0), "a", 1_000).await).unwrap();
+    send(sim.node(0), "POST", "/elections/db/resign", r#"{"candidate":"a"}"#).await;
+    leader(campaign(sim.node(0), "b", 1_000).await).unwrap();
+
+    let (status, body) = send(sim.node(0), "GET", &format!("/elections/db/observe?since={}&timeout_ms=50", since), "").await;
+    let observed: Successions = serde_json::from_str(&body).unwrap_or_else(|_| panic!("{}: {}", status, body));
+    let leaders: Vec<_> = observed.changes.iter().map(|change| change.leader.as_ref().map(|leader| (leader.leader.as_str(), leader.value.as_deref()))).collect();
+    assert_eq!(leaders, [None, Some(("b", None)), Some(("b", Some("b:8080")))], "a renewal is no change");
+    assert_eq!(observed.index, sim.node(0).applier.index());
+}
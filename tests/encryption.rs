@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+use paxos_from_scratch::{
+    encryption::Keyring,
+    hex,
+    storage::{Backup, DataDir, Record, SEGMENT_BYTES, Storage},
+};
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("paxos-encryption-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn keys(ids: &[&str]) -> Keyring {
+    let lines: Vec<String> = ids.iter().map(|id| format!("{} {}", id, hex::encode(&[id.as_bytes()[0]; 32]))).collect();
+    Keyring::parse(&lines.join("\n")).unwrap()
+}
+
+fn learned(instance: u64, value: &str) -> Record {
+    Record::Learned { instance, value: value.to_string() }
+}
+
+/// Everything in `dir`, as one string.
+fn contents(dir: &Path) -> String {
+    let mut text = String::new();
+    for entry in walk(dir) {
+        text.push_str(&String::from_utf8_lossy(&std::fs::read(entry).unwrap()));
+    }
+    text
+}
+
+fn walk(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir).unwrap().flat_map(|entry| {
+        let path = entry.unwrap().path();
+        if path.is_dir() { walk(&path) } else { vec![path] }
+    }).collect()
+}
+
+#[test]
+fn a_sealed_data_dir_keeps_nothing_in_the_clear() {
+    let dir = data_dir("sealed");
+    let (storage, _) = Storage::open_with_keys(1, &dir, SEGMENT_BYTES, keys(&["a"])).unwrap();
+    storage.append(learned(1, "secret-snapshotted")).unwrap();
+    storage.snapshot(storage.position(), DataDir::read_with_keys(&dir, &keys(&["a"])).unwrap().recover()).unwrap();
+    storage.append(learned(2, "secret-logged")).unwrap();
+    drop(storage);
+
+    assert!(!contents(&dir).contains("secret"), "a value was written in the clear");
+    assert!(DataDir::read(&dir).is_err(), "a sealed directory must not be read without its key");
+
+    let (_, recovered) = Storage::open_with_keys(1, &dir, SEGMENT_BYTES, keys(&["a"])).unwrap();
+    assert_eq!(recovered.ledger.get(&1).map(String::as_str), Some("secret-snapshotted"));
+    assert_eq!(recovered.ledger.get(&2).map(String::as_str), Some("secret-logged"));
+}
+
+#[test]
+fn a_new_key_takes_over_without_rewriting_what_the_old_one_sealed() {
+    // A segment per entry, so a snapshot can delete all of a's.
+    let dir = data_dir("rotate");
+    let (storage, _) = Storage::open_with_keys(1, &dir, 1, keys(&["a"])).unwrap();
+    storage.append(learned(1, "old")).unwrap();
+    drop(storage);
+
+    let (storage, _) = Storage::open_with_keys(1, &dir, 1, keys(&["a", "b"])).unwrap();
+    storage.append(learned(2, "new")).unwrap();
+    let data = DataDir::read_with_keys(&dir, &keys(&["a", "b"])).unwrap();
+    assert_eq!(data.keys.iter().map(String::as_str).collect::<Vec<_>>(), ["a", "b"]);
+    assert!(DataDir::read_with_keys(&dir, &keys(&["b"])).is_err(), "entries sealed with a are still there");
+
+    // Once a snapshot covers them, a is no longer needed.
+    storage.snapshot(storage.position(), data.recover()).unwrap();
+    drop(storage);
+    let (_, recovered) = Storage::open_with_keys(1, &dir, 1, keys(&["b"])).unwrap();
+    assert_eq!(recovered.ledger.len(), 2);
+}
+
+#[test]
+fn a_plain_data_dir_carries_on_sealed_only_when_migrating() {
+    let dir = data_dir("plain");
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    storage.append(learned(1, "plain")).unwrap();
+    drop(storage);
+
+    let e = Storage::open_with_keys(1, &dir, SEGMENT_BYTES, keys(&["a"])).unwrap_err();
+    assert!(e.to_string().contains("isn't sealed"), "{}", e);
+
+    let (storage, _) = Storage::open_with_keys(1, &dir, SEGMENT_BYTES, keys(&["a"]).migrating(true)).unwrap();
+    storage.append(learned(2, "sealed")).unwrap();
+    drop(storage);
+
+    let data = DataDir::read_with_keys(&dir, &keys(&["a"]).migrating(true)).unwrap();
+    assert!(data.plain && data.keys.contains("a"));
+    assert_eq!(data.recover().ledger.len(), 2);
+    assert!(DataDir::read_with_keys(&dir, &keys(&["a"])).is_err(), "entries in the clear were taken without migrating");
+}
+
+#[test]
+fn a_changed_entry_stops_the_node_rather_than_being_dropped() {
+    let dir = data_dir("changed");
+    let (storage, _) = Storage::open_with_keys(1, &dir, SEGMENT_BYTES, keys(&["a"])).unwrap();
+    storage.append(learned(1, "v")).unwrap();
+    drop(storage);
+
+    // The only entry is also the last one, which a crash could have cut short.
+    let segment = walk(&dir.join("wal")).pop().unwrap();
+    let mut line = std::fs::read_to_string(&segment).unwrap();
+    let at = line.len() - 10;
+    let flipped = if &line[at..at + 1] == "0" { "1" } else { "0" };
+    line.replace_range(at..at + 1, flipped);
+    std::fs::write(&segment, line).unwrap();
+
+    let e = Storage::open_with_keys(1, &dir, SEGMENT_BYTES, keys(&["a"])).unwrap_err();
+    assert!(e.to_string().contains("doesn't open"), "{}", e);
+}
+
+#[test]
+fn a_backup_restores_sealed_under_the_current_key() {
+    let dir = data_dir("backup");
+    let (storage, _) = Storage::open_with_keys(1, &dir, SEGMENT_BYTES, keys(&["a"])).unwrap();
+    storage.append(learned(1, "v")).unwrap();
+    drop(storage);
+
+    let backup = Backup::take_with_keys(&dir, &keys(&["a"])).unwrap();
+    let file = backup.to_bytes(&keys(&["a"]));
+    assert!(!String::from_utf8_lossy(&file).contains("\"wal\""), "the backup was written in the clear");
+    assert!(Backup::from_bytes(&file, &keys(&["b"])).is_err());
+    let backup = Backup::from_bytes(&file, &keys(&["a"])).unwrap();
+
+    let restored = data_dir("backup-restored");
+    backup.seed_with_keys(&restored, &keys(&["b"])).unwrap();
+
+    let data = DataDir::read_with_keys(&restored, &keys(&["b"])).unwrap();
+    assert_eq!(data.keys.iter().map(String::as_str).collect::<Vec<_>>(), ["b"]);
+    assert_eq!(data.recover().ledger.get(&1).map(String::as_str), Some("v"));
+}
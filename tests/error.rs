@@ -0,0 +1,35 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    ProposalId,
+    error::PaxosError,
+    sim::{self, DEFAULT_SEEDS, Sim, SimConfig},
+};
+
+#[test]
+fn each_error_answers_with_its_own_status() {
+    let preempted = PaxosError::Preempted(ProposalId { round: 7, node_id: 2 });
+    assert_eq!(preempted.refusal(), (StatusCode::CONFLICT, String::from("Proposal was preempted by ballot 7.2!")));
+    assert_eq!(PaxosError::NoQuorum { phase: "accepted" }.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(PaxosError::Peer { status: StatusCode::FORBIDDEN, message: String::from("No!") }.refusal(), (StatusCode::FORBIDDEN, String::from("No!")));
+
+    let storage = PaxosError::from(std::io::Error::other("disk on fire"));
+    assert_eq!(storage.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(String::from(storage), "Failed to persist: disk on fire");
+}
+
+#[test]
+fn a_proposal_cut_off_from_a_quorum_is_unavailable() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+        sim.partition(&[&[0], &[1, 2]]);
+
+        let reply = sim.propose(0, "alone").await;
+        if reply.status != StatusCode::SERVICE_UNAVAILABLE {
+            return Err(format!("expected a 503, got {}: {}", reply.status, reply.body));
+        }
+        if sim.put(1, "k", "v").await.is_error() {
+            return Err(String::from("the majority failed to decide"));
+        }
+        Ok(())
+    });
+}
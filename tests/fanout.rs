@@ -0,0 +1,94 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use axum::http::StatusCode;
+use futures::future::BoxFuture;
+use paxos_from_scratch::{
+    AppState, Node,
+    fanout::{self, Limits},
+    transport::{Reply, Transport},
+};
+
+/// Answers every request after a while, keeping track of the most it ever
+/// had out at once, overall and to the first peer.
+#[derive(Clone, Debug, Default)]
+struct Counting {
+    counts: Arc<Mutex<[(usize, usize); 2]>>,
+}
+
+impl Counting {
+    fn most(&self) -> (usize, usize) {
+        let counts = self.counts.lock().unwrap();
+        (counts[0].1, counts[1].1)
+    }
+}
+
+fn first_peer() -> SocketAddr {
+    "127.0.0.1:3002".parse().unwrap()
+}
+
+impl Transport for Counting {
+    fn post(&self, addr: SocketAddr, _path: &str, _body: String) -> BoxFuture<'static, Result<Reply, String>> {
+        let counts = self.counts.clone();
+        let tracked = move |change: isize| {
+            let mut counts = counts.lock().unwrap();
+            for (i, (now, most)) in counts.iter_mut().enumerate() {
+                if i == 0 || addr == first_peer() {
+                    *now = now.saturating_add_signed(change);
+                    *most = (*most).max(*now);
+                }
+            }
+        };
+        Box::pin(async move {
+            tracked(1);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            tracked(-1);
+            Ok(Reply { status: StatusCode::OK, body: String::new() })
+        })
+    }
+}
+
+fn node(port: u16) -> Node {
+    Node::new(port as u64, format!("127.0.0.1:{}", port).parse().unwrap())
+}
+
+fn state(counting: &Counting, limits: Limits) -> AppState {
+    let state = AppState::new(node(3001), Arc::new(counting.clone()));
+    state.settings.write().unwrap().fan_out = limits;
+    state
+}
+
+#[tokio::test]
+async fn a_round_sends_no_more_than_its_limit_at_once() {
+    let counting = Counting::default();
+    let state = state(&counting, Limits { per_round: 3, per_peer: 0 });
+    let peers: Vec<Node> = (3002..3012).map(node).collect();
+
+    let replies = fanout::post_all(&state, &peers, "/ping", &()).await;
+    assert_eq!(replies.len(), 10);
+    assert!(replies.iter().all(Result::is_ok));
+    assert_eq!(counting.most().0, 3);
+}
+
+#[tokio::test]
+async fn a_peer_gets_no_more_than_its_limit_across_rounds() {
+    let counting = Counting::default();
+    let state = state(&counting, Limits { per_round: 0, per_peer: 2 });
+    let peers = [node(3002), node(3003)];
+
+    let rounds = (0..5).map(|_| fanout::post_all(&state, &peers, "/ping", &()));
+    futures::future::join_all(rounds).await;
+    assert_eq!(counting.most(), (4, 2), "two to each of the two peers");
+}
+
+#[tokio::test]
+async fn no_limit_sends_everything_at_once() {
+    let counting = Counting::default();
+    let state = state(&counting, Limits::default());
+    let peers: Vec<Node> = (3002..3012).map(node).collect();
+
+    fanout::post_all(&state, &peers, "/ping", &()).await;
+    assert_eq!(counting.most().0, 10);
+}
@@ -0,0 +1,60 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    groups,
+    sim::{Sim, SimConfig},
+};
+
+fn hosting(groups: &[&str]) -> Sim {
+    Sim::new(0, SimConfig { nodes: 3, groups: groups.iter().map(|id| id.to_string()).collect(), ..SimConfig::default() })
+}
+
+#[test]
+fn a_path_says_which_group_it_is_for() {
+    assert_eq!(groups::split_path("/groups/shard-2/handle-accept"), (Some("shard-2"), "/handle-accept"));
+    assert_eq!(groups::split_path(&format!("{}/admin/chain", groups::prefix("orders"))), (Some("orders"), "/admin/chain"));
+    assert_eq!(groups::split_path("/handle-accept"), (None, "/handle-accept"));
+    assert_eq!(groups::split_path("/groups/a b/handle-accept"), (None, "/groups/a b/handle-accept"));
+
+    assert!(groups::is_valid("orders") && groups::is_valid("eu-west.1"));
+    assert!(!groups::is_valid("shard-1"), "shard ids are taken");
+    assert!(!groups::is_valid("") && !groups::is_valid("a/b"));
+}
+
+#[tokio::test]
+async fn a_named_group_keeps_a_key_space_and_a_log_of_its_own() {
+    let sim = hosting(&["orders", "users"]);
+
+    let reply = sim.put(0, "x?group=orders", "1").await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    assert_eq!(sim.put(0, "x", "2").await.status, StatusCode::OK);
+    sim.settle().await;
+
+    assert_eq!(sim.get(2, "/kv/x?group=orders").await.body, "1");
+    assert_eq!(sim.get(2, "/kv/x").await.body, "2");
+    assert_eq!(sim.get(2, "/kv/x?group=users").await.status, StatusCode::NOT_FOUND);
+    assert_eq!(sim.get(2, "/kv/x?group=billing").await.status, StatusCode::NOT_FOUND);
+    assert_eq!(sim.get(2, "/kv/x?group=shard-0").await.status, StatusCode::NOT_FOUND, "shards are picked by hash");
+
+    for index in 0..sim.size() {
+        let node = sim.node(index);
+        let orders = node.groups.get("orders").unwrap();
+        assert_eq!((node.ledger.len(), orders.ledger.len(), node.groups.get("users").unwrap().ledger.len()), (1, 1, 0));
+    }
+
+    // Every message of the group says which group it is for.
+    let messages = sim.messages();
+    assert!(messages.keys().any(|path| path == "/groups/orders/handle-accept"), "{:?}", messages);
+    assert!(!messages.keys().any(|path| path.starts_with("/groups/users/")), "{:?}", messages);
+}
+
+#[tokio::test]
+async fn metrics_are_labelled_by_group() {
+    let sim = hosting(&["orders"]);
+    assert_eq!(sim.put(0, "a?group=orders", "1").await.status, StatusCode::OK);
+    sim.settle().await;
+
+    let metrics = sim.get(1, "/metrics").await.body;
+    assert!(metrics.contains("paxos_group_learned_instances{group=\"orders\"} 1"), "{}", metrics);
+    assert!(metrics.contains("paxos_group_keys{group=\"orders\"} 1"), "{}", metrics);
+    assert!(metrics.contains("paxos_learned_instances 0"), "{}", metrics);
+}
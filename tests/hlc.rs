@@ -0,0 +1,111 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    hlc::{Hlc, MAX_DRIFT, Skew, Timestamp},
+    lease::{self, Lease},
+    ledger::Entry,
+    sim::{self, Sim, SimConfig},
+};
+
+fn at(wall: u64, logical: u32) -> Timestamp {
+    Timestamp { wall, logical }
+}
+
+#[test]
+fn the_clock_never_goes_back() {
+    let clock = Hlc::default();
+    assert_eq!(clock.tick(100), at(100, 0));
+    assert_eq!(clock.tick(100), at(100, 1));
+    assert_eq!(clock.tick(90), at(100, 2), "the wall clock went back");
+    assert_eq!(clock.tick(120), at(120, 0));
+}
+
+#[test]
+fn a_peer_clock_ahead_moves_this_one_past_it() {
+    let clock = Hlc::default();
+    clock.tick(100);
+    assert_eq!(clock.receive_at(100, at(150, 4)), Some(at(150, 5)));
+    assert_eq!(clock.receive_at(110, at(120, 0)), Some(at(150, 6)), "an older message leaves it be");
+    assert_eq!(clock.receive_at(200, at(150, 9)), Some(at(200, 0)));
+
+    let far = 200 + MAX_DRIFT.as_micros() as u64 + 1;
+    assert_eq!(clock.receive_at(200, at(far, 0)), None);
+    assert_eq!(clock.last(), at(200, 0));
+}
+
+#[test]
+fn a_timestamp_reads_back_as_written() {
+    let stamp = at(1_700_000_000_000_000, 3);
+    assert_eq!(stamp.to_string(), "1700000000000000.3");
+    assert_eq!(stamp.to_string().parse::<Timestamp>(), Ok(stamp));
+    assert!("17".parse::<Timestamp>().is_err());
+}
+
+#[test]
+fn a_peer_stamps_an_entry_after_its_proposer_did() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+        for key in ["a", "b", "c"] {
+            if sim.put(0, key, "1").await.is_error() {
+                return Err(format!("the write of {} failed", key));
+            }
+        }
+        sim.settle().await;
+
+        for instance in 1..=3 {
+            let proposer = sim.node(0).ledger.stamp(instance).ok_or("node 0 stamped nothing")?;
+            for index in 1..sim.size() {
+                let stamp = sim.node(index).ledger.stamp(instance).ok_or(format!("node {} stamped nothing", index))?;
+                if stamp <= proposer {
+                    return Err(format!("node {} stamped instance {} at {}, before its proposer at {}", index, instance, stamp, proposer));
+                }
+            }
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn the_ledger_shows_the_stamps() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    for key in ["a", "b", "c"] {
+        assert_eq!(sim.put(0, key, "1").await.status, StatusCode::OK);
+    }
+    sim.settle().await;
+
+    let entries: Vec<Entry> = sim.get(1, "/ledger?from=2").await.json().unwrap();
+    assert_eq!(entries.iter().map(|entry| entry.instance).collect::<Vec<_>>(), vec![2, 3]);
+    assert!(entries.iter().all(|entry| entry.hlc.is_some()), "{:?}", entries);
+    assert!(entries[0].value.contains("\"b\""), "{}", entries[0].value);
+}
+
+#[test]
+fn a_skewed_clock_reads_ahead_but_never_goes_back() {
+    let clock = Hlc::default();
+    let before = clock.now();
+    clock.set_skew(10_000_000);
+    assert!(clock.now().wall >= before.wall + 10_000_000);
+
+    clock.set_skew(0);
+    let held = clock.now();
+    assert!(held.wall >= before.wall + 10_000_000, "the clock went back with the skew");
+}
+
+#[tokio::test]
+async fn a_node_whose_clock_runs_ahead_sees_a_lease_run_out_first() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    let taken: Lease = sim.request(1, "/leases/lock", r#"{"holder":"a","ttl_ms":1000}"#).await.json().unwrap();
+    sim.settle().await;
+
+    let skew: Skew = sim.request(0, "/admin/clock-skew", r#"{"skew_ms":5000}"#).await.json().unwrap();
+    assert_eq!(sim.get(0, "/admin/clock-skew").await.json::<Skew>().unwrap(), skew);
+    assert_eq!(sim.get(0, "/leases/lock").await.status, StatusCode::NOT_FOUND, "node 0 is 5 seconds ahead");
+
+    // b takes a lease a still holds on its own clock; the log keeps every
+    // node agreeing on who holds it, whatever a believes.
+    let stolen: Lease = sim.request(0, "/leases/lock", r#"{"holder":"b","ttl_ms":1000}"#).await.json().unwrap();
+    assert!(stolen.expires > taken.expires);
+    sim.settle().await;
+    for index in 0..sim.size() {
+        assert_eq!(lease::get(&*sim.node(index).kv.lock().await, "lock").unwrap().holder, "b");
+    }
+}
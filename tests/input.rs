@@ -0,0 +1,28 @@
+use axum::http::{Request, StatusCode};
+use paxos_from_scratch::input::Invalid;
+
+mod common;
+use common::{cluster, json, send_request};
+
+#[tokio::test]
+async fn malformed_input_is_a_400_that_says_why() {
+    let sim = cluster(0, 1);
+    let state = sim.node(0);
+
+    let malformed = [
+        (json("POST", "/admin/faults"), "{\"drop\": "),
+        (json("POST", "/admin/faults"), "{\"drop\": \"lots\"}"),
+        (Request::builder().method("POST").uri("/admin/partition"), "{\"groups\": [[1]]}"),
+        (Request::builder().method("GET").uri("/ledger?from=first"), ""),
+        (Request::builder().method("DELETE").uri("/admin/faults/one"), ""),
+        (Request::builder().method("POST").uri("/connect"), "not-a-port"),
+    ];
+    for (request, body) in malformed {
+        let (status, reply) = send_request(state, request, body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", reply);
+        let invalid: Invalid = serde_json::from_str(&reply).unwrap_or_else(|_| panic!("not a structured error: {}", reply));
+        assert!(!invalid.error.is_empty());
+    }
+
+    assert_eq!(send_request(state, Request::builder().method("GET").uri("/ledger?from=1"), "").await.0, StatusCode::OK);
+}
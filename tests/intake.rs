@@ -0,0 +1,63 @@
+use std::{io::Write, path::PathBuf, sync::Arc};
+use axum::{body::Body, http::{Request, StatusCode}};
+use tower::ServiceExt;
+
+use paxos_from_scratch::{
+    intake::Intake,
+    router,
+    sim::{Sim, SimConfig},
+    storage::{SEGMENT_BYTES, Storage},
+};
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("paxos-intake-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn commands_not_finished_are_there_after_a_restart() {
+    let dir = data_dir("restart");
+    let intake = Intake::open(&dir).unwrap();
+    let a = intake.push(&String::from("a")).unwrap();
+    intake.push(&String::from("b")).unwrap();
+    intake.push(&String::from("c")).unwrap();
+    intake.done(a).unwrap();
+    assert_eq!(intake.pending(), 2);
+    drop(intake);
+
+    // A crash halfway through taking a fourth.
+    let mut file = std::fs::OpenOptions::new().append(true).open(dir.join("intake.jsonl")).unwrap();
+    file.write_all(br#"{"id":4,"val"#).unwrap();
+    drop(file);
+
+    let intake = Intake::open(&dir).unwrap();
+    let recovered: Vec<String> = intake.take_recovered().into_iter().map(|(_, value)| value).collect();
+    assert_eq!(recovered, vec!["b", "c"]);
+    assert!(intake.take_recovered().is_empty(), "only the first call gets them");
+
+    let d = intake.push(&String::from("d")).unwrap();
+    assert!(d > 3, "a new command must not reuse an old id");
+}
+
+#[tokio::test]
+async fn a_finished_write_is_crossed_off() {
+    let dir = data_dir("finished");
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+
+    let sim = Sim::new(0, SimConfig { nodes: 1, ..SimConfig::default() });
+    let mut state = sim.node(0).clone();
+    state.storage = Some(Arc::new(storage));
+
+    let put = Request::put("/kv/k").body(Body::from("v")).unwrap();
+    let response = router(state.clone()).oneshot(put).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let storage = state.storage.as_ref().unwrap();
+    assert_eq!(storage.intake().pending(), 0);
+    drop(state);
+    drop(sim);
+
+    assert!(Intake::open(&dir).unwrap().take_recovered().is_empty());
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use proptest::prelude::*;
+
+use paxos_from_scratch::{
+    Ballot, ProposalId,
+    acceptor::Acceptor,
+    proposer::{Proposer, Round},
+};
+
+const PROPOSERS: usize = 3;
+const ACCEPTORS: usize = 3;
+const INSTANCE: u64 = 1;
+
+/// One step of a schedule: a proposer starting a new round, or the network
+/// delivering (or losing) one of the messages in flight, picked by index.
+#[derive(Clone, Debug)]
+enum Step {
+    Start(usize),
+    Deliver(usize),
+    Drop(usize),
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        1 => (0..PROPOSERS).prop_map(Step::Start),
+        6 => any::<usize>().prop_map(Step::Deliver),
+        1 => any::<usize>().prop_map(Step::Drop),
+    ]
+}
+
+#[derive(Clone, Debug)]
+enum Message {
+    Prepare { from: usize, to: usize, ballot: Ballot },
+    Promise { to: usize, from: usize, id: ProposalId, accepted: Option<Ballot> },
+    Accept { from: usize, to: usize, ballot: Ballot },
+    Accepted { to: usize, from: usize, id: ProposalId },
+    Rejected { to: usize, promised: ProposalId },
+}
+
+#[derive(Default)]
+struct ProposerNode {
+    proposer: Proposer,
+    round: Option<Round>,
+    /// What the promises for the round in flight reported, kept apart from
+    /// `Round` to check its choice against.
+    seen: Vec<Option<Ballot>>,
+    proposed: bool,
+    issued: Vec<ProposalId>,
+}
+
+struct Cluster {
+    proposers: Vec<ProposerNode>,
+    acceptors: Vec<Acceptor>,
+    in_flight: Vec<Message>,
+    /// Ballots accepted by a quorum, by proposal.
+    chosen: HashMap<ProposalId, Ballot>,
+}
+
+impl Cluster {
+    fn new() -> Self {
+        Self {
+            proposers: (0..PROPOSERS).map(|_| ProposerNode::default()).collect(),
+            acceptors: vec![Acceptor::default(); ACCEPTORS],
+            in_flight: Vec::new(),
+            chosen: HashMap::new(),
+        }
+    }
+
+    fn promised(&self, acceptor: usize) -> ProposalId {
+        self.acceptors[acceptor].slots.get(&INSTANCE).map_or_else(ProposalId::default, |slot| slot.last_ballot_number)
+    }
+
+    fn run(&mut self, step: Step) {
+        match step {
+            Step::Start(p) => self.start(p),
+            Step::Deliver(i) if !self.in_flight.is_empty() => {
+                let message = self.in_flight.remove(i % self.in_flight.len());
+                self.deliver(message);
+            },
+            Step::Drop(i) if !self.in_flight.is_empty() => {
+                self.in_flight.remove(i % self.in_flight.len());
+            },
+            _ => {},
+        }
+    }
+
+    fn start(&mut self, p: usize) {
+        let node = &mut self.proposers[p];
+        let id = node.proposer.next_proposal_id(p as u64 + 1);
+        let round = Round::new(INSTANCE, id, format!("v{}-{}", p, id.round), ACCEPTORS);
+
+        if let Some(last) = node.issued.last() {
+            assert!(id > *last, "proposer {} reissued {:?} after {:?}", p, id, last);
+        }
+        node.issued.push(id);
+
+        for to in 0..ACCEPTORS {
+            self.in_flight.push(Message::Prepare { from: p, to, ballot: round.prepare() });
+        }
+        node.round = Some(round);
+        node.seen.clear();
+        node.proposed = false;
+    }
+
+    fn deliver(&mut self, message: Message) {
+        match message {
+            Message::Prepare { from, to, ballot } => {
+                let before = self.promised(to);
+                let reply = match self.acceptors[to].prepare(&ballot) {
+                    Ok(accepted) => {
+                        assert!(ballot.id > before, "acceptor {} promised {:?} over {:?}", to, ballot.id, before);
+                        Message::Promise { to: from, from: to, id: ballot.id, accepted }
+                    },
+                    Err(promised) => {
+                        assert!(ballot.id <= before);
+                        Message::Rejected { to: from, promised }
+                    },
+                };
+                assert!(self.promised(to) >= before, "acceptor {} went back on its promise", to);
+                self.in_flight.push(reply);
+            },
+            Message::Accept { from, to, ballot } => {
+                let before = self.promised(to);
+                let reply = match self.acceptors[to].accept(&ballot) {
+                    Ok(()) => {
+                        assert!(ballot.id >= before, "acceptor {} accepted {:?} below its promise {:?}", to, ballot.id, before);
+                        Message::Accepted { to: from, from: to, id: ballot.id }
+                    },
+                    Err(promised) => {
+                        assert!(ballot.id < before);
+                        Message::Rejected { to: from, promised }
+                    },
+                };
+                assert!(self.promised(to) >= before, "acceptor {} went back on its promise", to);
+                self.in_flight.push(reply);
+            },
+            Message::Promise { to, from, id, accepted } => {
+                let node = &mut self.proposers[to];
+                if node.proposed {
+                    return;
+                }
+                let Some(round) = node.round.as_mut().filter(|round| round.id == id) else {
+                    return;
+                };
+
+                node.seen.push(accepted.clone());
+                round.promise(from as u64 + 1, accepted, None);
+
+                let Some(proposal) = round.proposal() else {
+                    return;
+                };
+
+                let highest = node.seen.iter().flatten().max_by_key(|ballot| ballot.id);
+                if let Some(highest) = highest {
+                    assert_eq!(proposal.value, highest.value, "proposer {} ignored the highest accepted ballot", to);
+                }
+
+                node.proposed = true;
+                for acceptor in 0..ACCEPTORS {
+                    self.in_flight.push(Message::Accept { from: to, to: acceptor, ballot: proposal.clone() });
+                }
+            },
+            Message::Accepted { to, from, id } => {
+                let node = &mut self.proposers[to];
+                let Some(round) = node.round.as_mut().filter(|round| round.id == id) else {
+                    return;
+                };
+                round.accept(from as u64 + 1);
+
+                if round.is_chosen() {
+                    self.chosen.insert(id, round.proposal().unwrap());
+                }
+            },
+            Message::Rejected { to, promised } => {
+                let node = &mut self.proposers[to];
+                let round = node.proposer.round;
+                node.proposer.observe(Some(promised));
+                assert!(node.proposer.round >= round);
+                assert!(node.proposer.round >= promised.round);
+            },
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn invariants_hold_over_random_interleavings(steps in prop::collection::vec(step(), 1..200)) {
+        let mut cluster = Cluster::new();
+        for step in steps {
+            cluster.run(step);
+        }
+
+        let mut chosen = cluster.chosen.values().map(|ballot| ballot.value.clone());
+        if let Some(first) = chosen.next() {
+            prop_assert!(chosen.all(|value| value == first), "more than one value chosen: {:?}", cluster.chosen);
+        }
+    }
+
+    #[test]
+    fn acceptor_never_accepts_below_its_promise(
+        requests in prop::collection::vec((any::<bool>(), 0..2u64, 0..8u64, 1..4u64), 1..100)
+    ) {
+        let mut acceptor = Acceptor::default();
+        let mut promised: HashMap<u64, ProposalId> = HashMap::new();
+        let mut accepted: HashMap<u64, Ballot> = HashMap::new();
+
+        for (is_prepare, instance, round, node_id) in requests {
+            let id = ProposalId { round, node_id };
+            let before = promised.get(&instance).copied().unwrap_or_default();
+
+            if is_prepare {
+                let ballot = Ballot { instance, id, value: None };
+                match acceptor.prepare(&ballot) {
+                    Ok(previous) => {
+                        prop_assert!(id > before);
+                        prop_assert_eq!(previous.as_ref(), accepted.get(&instance));
+                        promised.insert(instance, id);
+                    },
+                    Err(higher) => {
+                        prop_assert!(id <= before);
+                        prop_assert_eq!(higher, before);
+                    },
+                }
+            } else {
+                let ballot = Ballot { instance, id, value: Some(format!("{:?}", id)) };
+                match acceptor.accept(&ballot) {
+                    Ok(()) => {
+                        prop_assert!(id >= before);
+                        promised.insert(instance, id);
+                        accepted.insert(instance, ballot);
+                    },
+                    Err(higher) => {
+                        prop_assert!(id < before);
+                        prop_assert_eq!(higher, before);
+                    },
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_proposes_the_highest_accepted_value(
+        promises in prop::collection::vec(prop::option::of((0..8u64, 1..4u64)), 1..6),
+        voters in 1..6usize,
+    ) {
+        let id = ProposalId { round: 9, node_id: 1 };
+        let mut round = Round::new(INSTANCE, id, String::from("own"), voters);
+
+        for (from, promise) in promises.iter().enumerate() {
+            let accepted = promise.map(|(round, node_id)| {
+                let id = ProposalId { round, node_id };
+                Ballot { instance: INSTANCE, id, value: Some(format!("{:?}", id)) }
+            });
+            round.promise(from as u64 + 1, accepted, None);
+        }
+
+        let quorum = voters / 2 + 1;
+        let proposal = round.proposal();
+        prop_assert_eq!(proposal.is_some(), promises.len() >= quorum);
+
+        if let Some(proposal) = proposal {
+            let highest = promises.iter().flatten().max().map(|&(round, node_id)| ProposalId { round, node_id });
+            let expected = highest.map_or(String::from("own"), |id| format!("{:?}", id));
+            prop_assert_eq!(proposal.id, id);
+            prop_assert_eq!(proposal.value, Some(expected));
+        }
+    }
+}
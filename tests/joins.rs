@@ -0,0 +1,89 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, atomic::{AtomicBool, Ordering}},
+    time::Duration,
+};
+use axum::{body::Body, http::{Request, StatusCode}};
+use futures::future::BoxFuture;
+use tokio::time::Instant;
+use tower::ServiceExt;
+use paxos_from_scratch::{
+    AppState, Node,
+    joins::{self, MAX_BACKOFF, Waiting},
+    router,
+    transport::{Reply, Transport},
+    version,
+};
+
+/// A peer on port 3002 that answers pings once it is up.
+#[derive(Clone, Debug, Default)]
+struct Peer {
+    up: Arc<AtomicBool>,
+}
+
+impl Transport for Peer {
+    fn post(&self, addr: SocketAddr, path: &str, _body: String) -> BoxFuture<'static, Result<Reply, String>> {
+        let reply = match (self.up.load(Ordering::SeqCst), addr.port(), path) {
+            (true, 3002, "/ping") => Ok(Reply {
+                status: StatusCode::OK,
+                body: format!(r#"{{"id":"2","addr":"127.0.0.1:3002","protocol":"{}"}}"#, version::PROTOCOL),
+            }),
+            (true, 3002, _) => Ok(Reply { status: StatusCode::OK, body: String::new() }),
+            _ => Err(format!("connection refused by {}", addr)),
+        };
+        Box::pin(async move { reply })
+    }
+}
+
+async fn connect(state: &AppState, port: &str) -> (StatusCode, String) {
+    let request = Request::builder().method("POST").uri("/connect").body(Body::from(port.to_string())).unwrap();
+    let response = router(state.clone()).oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn a_join_to_a_peer_not_up_yet_waits_for_it() {
+    let peer = Peer::default();
+    let state = AppState::new(Node::new(1, "127.0.0.1:3001".parse().unwrap()), Arc::new(peer.clone()));
+
+    let (status, reply) = connect(&state, "3002").await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", reply);
+    assert!(state.nodes.snapshot().is_empty());
+    let waiting: Vec<Waiting> = state.joins.waiting();
+    assert_eq!(waiting.iter().map(|waiting| (waiting.port, waiting.attempts)).collect::<Vec<_>>(), [(3002, 1)]);
+
+    // Nothing is due before the backoff ran out, and an attempt that fails
+    // waits longer.
+    let now = Instant::now();
+    assert!(joins::retry(&state, now).await.is_empty());
+    assert!(joins::retry(&state, now + joins::backoff(1)).await.is_empty());
+    assert_eq!(state.joins.waiting()[0].attempts, 2);
+    assert!(state.joins.due(now + joins::backoff(1) + joins::backoff(1)).is_empty());
+
+    peer.up.store(true, Ordering::SeqCst);
+    assert_eq!(joins::retry(&state, now + Duration::from_secs(60)).await, [3002]);
+    assert!(state.joins.waiting().is_empty());
+    assert_eq!(state.nodes.snapshot().iter().map(|node| node.id).collect::<Vec<_>>(), [2]);
+}
+
+#[tokio::test]
+async fn connecting_directly_stops_the_retries() {
+    let peer = Peer::default();
+    let state = AppState::new(Node::new(1, "127.0.0.1:3001".parse().unwrap()), Arc::new(peer.clone()));
+
+    assert_eq!(connect(&state, "3002").await.0, StatusCode::ACCEPTED);
+    peer.up.store(true, Ordering::SeqCst);
+    assert_eq!(connect(&state, "3002").await.0, StatusCode::OK);
+    assert!(state.joins.waiting().is_empty());
+}
+
+#[test]
+fn the_backoff_doubles_up_to_a_cap() {
+    assert_eq!(joins::backoff(1), Duration::from_millis(500));
+    assert_eq!(joins::backoff(2), Duration::from_secs(1));
+    assert_eq!(joins::backoff(4), Duration::from_secs(4));
+    assert_eq!(joins::backoff(8), MAX_BACKOFF);
+    assert_eq!(joins::backoff(u32::MAX), MAX_BACKOFF);
+}
@@ -0,0 +1,185 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use axum::http::StatusCode;
+use futures::future::BoxFuture;
+use paxos_from_scratch::{
+    AppState, Ballot, Node, ProposalId,
+    learns::{Batching, Committed},
+    sim::{self, Sim, SimConfig},
+    transport::{Reply, Transport},
+    version::{MIN_PROTOCOL, PROTOCOL},
+};
+
+/// Answers everything, keeping each request's port, path and number of
+/// ballots.
+#[derive(Clone, Debug, Default)]
+struct Recording {
+    sent: Arc<Mutex<Vec<(u16, String, usize)>>>,
+}
+
+impl Transport for Recording {
+    fn post(&self, addr: SocketAddr, path: &str, body: String) -> BoxFuture<'static, Result<Reply, String>> {
+        let ballots = serde_json::from_str::<Vec<Ballot>>(&body).map_or(1, |ballots| ballots.len());
+        self.sent.lock().unwrap().push((addr.port(), path.to_string(), ballots));
+        Box::pin(async { Ok(Reply { status: StatusCode::OK, body: String::new() }) })
+    }
+}
+
+fn node(port: u16) -> Node {
+    Node::new(port as u64, format!("127.0.0.1:{}", port).parse().unwrap())
+}
+
+fn ballot(instance: u64) -> Ballot {
+    Ballot { instance, id: ProposalId { round: 1, node_id: 3001 }, value: Some(format!("v{}", instance)) }
+}
+
+fn cluster(recording: &Recording, batching: Batching) -> AppState {
+    let state = AppState::new(node(3001), Arc::new(recording.clone()));
+    state.settings.write().unwrap().learn_batching = batching;
+    state.nodes.update(|nodes| nodes.extend([node(3002), node(3003)]));
+    state.versions.negotiate(3002, Some(PROTOCOL)).unwrap();
+    state.versions.negotiate(3003, Some(MIN_PROTOCOL)).unwrap();
+    state
+}
+
+async fn sent_once_settled(state: &AppState, recording: &Recording) -> Vec<(u16, String, usize)> {
+    while state.learns.pending() > 0 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    let mut sent = recording.sent.lock().unwrap().clone();
+    sent.sort();
+    sent
+}
+
+#[tokio::test]
+async fn decisions_close_together_go_out_as_one_batch() {
+    let recording = Recording::default();
+    let state = cluster(&recording, Batching { max_delay: Duration::from_millis(50), max_batch: 0, adaptive: false });
+
+    for instance in 1..=3 {
+        state.learns.push(&state, ballot(instance), false);
+    }
+
+    let learn = |port, path: &str, ballots| (port, path.to_string(), ballots);
+    assert_eq!(sent_once_settled(&state, &recording).await, [
+        learn(3002, "/handle-learns", 3),
+        learn(3003, "/handle-learn", 1),
+        learn(3003, "/handle-learn", 1),
+        learn(3003, "/handle-learn", 1),
+    ], "one batch to the new peer, one at a time to the old one");
+}
+
+#[tokio::test]
+async fn a_full_batch_goes_out_without_waiting() {
+    let recording = Recording::default();
+    let state = cluster(&recording, Batching { max_delay: Duration::from_secs(60), max_batch: 2, adaptive: false });
+
+    for instance in 1..=2 {
+        state.learns.push(&state, ballot(instance), false);
+    }
+
+    let sent = tokio::time::timeout(Duration::from_secs(5), sent_once_settled(&state, &recording)).await.unwrap();
+    assert!(sent.contains(&(3002, String::from("/handle-learns"), 2)), "{:?}", sent);
+}
+
+#[tokio::test]
+async fn decisions_everyone_accepted_go_along_with_the_next_accept() {
+    let recording = Recording::default();
+    let state = cluster(&recording, Batching { max_delay: Duration::from_secs(60), max_batch: 0, adaptive: false });
+    state.learns.push(&state, ballot(1), true);
+    state.learns.push(&state, ballot(2), false);
+
+    assert!(state.learns.piggyback(&state, [3002, 3003]).is_empty(), "3003 wouldn't read it");
+
+    state.versions.negotiate(3003, Some(PROTOCOL)).unwrap();
+    let committed = state.learns.piggyback(&state, [3002, 3003]);
+    assert_eq!(committed, [Committed { instance: 1, id: ballot(1).id }]);
+    assert_eq!(state.learns.len(), 1, "the one not everyone accepted still needs a learn");
+    assert_eq!(state.learns.pending(), 2);
+
+    state.learns.sent(committed.len());
+    assert_eq!(state.learns.pending(), 1);
+}
+
+#[test]
+fn acceptors_learn_what_they_accepted_from_the_next_accept() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+        let accept = |instance: u64, round: u64, committed: &str| format!(
+            r#"{{"instance":{},"id":{{"round":{},"node_id":1}},"value":"v{}","committed":[{}]}}"#, instance, round, instance, committed,
+        );
+
+        sim.request(1, "/handle-accept", &accept(1, 1, "")).await;
+        sim.request(1, "/handle-accept", &accept(2, 1, "")).await;
+        let committed = r#"{"instance":1,"id":{"round":1,"node_id":1}},{"instance":2,"id":{"round":2,"node_id":1}}"#;
+        if sim.request(1, "/handle-accept", &accept(3, 1, committed)).await.is_error() {
+            return Err(String::from("the accept carrying decisions was refused"));
+        }
+
+        let ledger = sim.node(1).ledger.to_map();
+        if ledger.get(&1).map(String::as_str) != Some("v1") {
+            return Err(format!("instance 1 wasn't learned from the accept: {:?}", ledger));
+        }
+        if ledger.contains_key(&2) {
+            return Err(String::from("instance 2 was learned though the node accepted it under another proposal"));
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn the_wait_follows_the_pace_of_decisions() {
+    let recording = Recording::default();
+    let adaptive = Batching { max_delay: Duration::from_millis(10), max_batch: 0, adaptive: true };
+    let state = cluster(&recording, adaptive);
+    assert_eq!(state.learns.window(adaptive), Duration::ZERO, "nothing to wait for before any decision");
+
+    state.learns.push(&state, ballot(1), false);
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    state.learns.push(&state, ballot(2), false);
+    assert_eq!(state.learns.window(adaptive), Duration::ZERO, "decisions further apart than the delay don't wait");
+
+    for instance in 3..=40 {
+        state.learns.push(&state, ballot(instance), false);
+    }
+    assert_eq!(state.learns.window(adaptive), adaptive.max_delay, "a burst waits the whole delay");
+
+    let fixed = Batching { adaptive: false, ..adaptive };
+    assert_eq!(state.learns.window(fixed), fixed.max_delay);
+}
+
+#[test]
+fn gossip_reaches_the_cluster_while_the_proposer_tells_only_a_few() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 9, ..SimConfig::default() });
+        for index in 0..sim.size() {
+            sim.node(index).settings.write().unwrap().learn_gossip = 2;
+        }
+
+        let mut acknowledged = Vec::new();
+        for i in 0..3 {
+            let value = format!("v{}", i);
+            if !sim.propose(0, &value).await.is_error() {
+                acknowledged.push(value);
+            }
+        }
+        sim.settle().await;
+
+        let gossip = sim.messages().get("/gossip-learns").copied().unwrap_or(0);
+        if sim.messages().contains_key("/handle-learns") || gossip == 0 {
+            return Err(format!("learns weren't gossiped: {:?}", sim.messages()));
+        }
+        sim.check_agreement().await?;
+
+        // Gossip may miss a peer now and then; it finds out on its next
+        // prepare, as with a lost learn.
+        let learned = sim.ledgers().await.iter().filter(|ledger| ledger.len() == acknowledged.len()).count();
+        if learned < sim.size() / 2 + 1 {
+            return Err(format!("only {} of {} nodes learned everything", learned, sim.size()));
+        }
+        Ok(())
+    });
+}
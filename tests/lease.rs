@@ -0,0 +1,106 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    kv::{Command, Kv},
+    lease::{self, Action, Lease},
+    sim,
+};
+
+mod common;
+use common::{cluster, send};
+
+fn acquire(holder: &str, ttl_ms: u64) -> String {
+    serde_json::json!({ "holder": holder, "ttl_ms": ttl_ms }).to_string()
+}
+
+fn command(name: &str, at: u64, action: Action) -> String {
+    Command::Lease { name: name.to_string(), at, action }.encode()
+}
+
+#[test]
+fn a_lease_is_held_by_one_holder_until_released() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, 3);
+        let (status, body) = send(sim.node(0), "POST", "/leases/lock", &acquire("a", 60_000)).await;
+        if status != StatusCode::OK {
+            return Err(format!("a couldn't take the lease: {}", body));
+        }
+        let taken: Lease = serde_json::from_str(&body).map_err(|e| e.to_string())?;
+        if send(sim.node(0), "POST", "/leases/lock", &acquire("b", 60_000)).await.0 != StatusCode::CONFLICT {
+            return Err(String::from("b took a lease a holds"));
+        }
+        if send(sim.node(0), "POST", "/leases/lock/renew", r#"{"holder":"b"}"#).await.0 != StatusCode::CONFLICT {
+            return Err(String::from("b renewed a lease a holds"));
+        }
+
+        let (status, body) = send(sim.node(0), "POST", "/leases/lock/renew", r#"{"holder":"a"}"#).await;
+        let renewed: Lease = serde_json::from_str(&body).map_err(|_| format!("{}: {}", status, body))?;
+        if renewed.expires < taken.expires {
+            return Err(String::from("the renewal didn't extend the lease"));
+        }
+        sim.settle().await;
+        for index in 0..sim.size() {
+            let (_, body) = send(sim.node(index), "GET", "/leases/lock", "").await;
+            if serde_json::from_str::<Lease>(&body).map(|lease| lease.holder).ok().as_deref() != Some("a") {
+                return Err(format!("node {} doesn't know a holds the lease: {}", index, body));
+            }
+        }
+
+        if send(sim.node(0), "DELETE", "/leases/lock?holder=b", "").await.0 != StatusCode::CONFLICT {
+            return Err(String::from("b released a lease a holds"));
+        }
+        if send(sim.node(0), "DELETE", "/leases/lock?holder=a", "").await.0 != StatusCode::OK {
+            return Err(String::from("a couldn't release its lease"));
+        }
+        if send(sim.node(0), "POST", "/leases/lock", &acquire("b", 60_000)).await.0 != StatusCode::OK {
+            return Err(String::from("b couldn't take the released lease"));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn an_expired_lease_is_revoked_with_its_keys() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, 3);
+        send(sim.node(0), "POST", "/leases/leader", &acquire("a", 60_000)).await;
+        let (status, body) = send(sim.node(0), "PUT", "/leases/leader/keys/address?holder=a", "10.0.0.1").await;
+        let lease: Lease = serde_json::from_str(&body).map_err(|_| format!("{}: {}", status, body))?;
+        if lease.keys.get("address").map(String::as_str) != Some("10.0.0.1") {
+            return Err(String::from("the key wasn't set with the lease"));
+        }
+        if send(sim.node(0), "PUT", "/leases/leader/keys/address?holder=b", "10.0.0.2").await.0 != StatusCode::CONFLICT {
+            return Err(String::from("b set a key on a lease a holds"));
+        }
+
+        let node = sim.node(0);
+        sim.settle().await;
+        if lease::sweep(node, lease.expires - 1).await != 0 {
+            return Err(String::from("a live lease was expired"));
+        }
+        if lease::sweep(node, lease.expires).await != 1 {
+            return Err(String::from("the lease wasn't expired"));
+        }
+        sim.settle().await;
+        for index in 0..sim.size() {
+            if let Some(lease) = lease::get(&*sim.node(index).kv.lock().await, "leader") {
+                return Err(format!("node {} still has the lease, keys and all: {:?}", index, lease));
+            }
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn every_node_decides_by_the_time_in_the_command() {
+    let mut kv = Kv::default();
+    kv.apply(&command("lock", 1_000, Action::Acquire { holder: String::from("a"), ttl_ms: 1 }));
+    kv.apply(&command("lock", 1_500, Action::Acquire { holder: String::from("b"), ttl_ms: 1 }));
+    assert_eq!(lease::get(&kv, "lock").unwrap().holder, "a", "a's lease ran until 2000");
+
+    kv.apply(&command("lock", 1_900, Action::Expire));
+    assert!(lease::get(&kv, "lock").is_some(), "an expiry proposed early does nothing");
+    kv.apply(&command("lock", 2_000, Action::Renew { holder: String::from("a") }));
+    kv.apply(&command("lock", 2_000, Action::Acquire { holder: String::from("b"), ttl_ms: 1 }));
+    let lease = lease::get(&kv, "lock").unwrap();
+    assert_eq!((lease.holder.as_str(), lease.expires), ("b", 3_000), "a ran out, so b takes it");
+}
@@ -0,0 +1,87 @@
+use paxos_from_scratch::history::{self, Event, EventType, Function};
+
+fn event(node: u64, op: u64, kind: EventType, f: Function, value: Option<&str>, time: u64) -> Event {
+    Event { node, op, kind, f, key: String::from("x"), value: value.map(str::to_string), time }
+}
+
+fn write(node: u64, op: u64, value: &str, call: u64, ret: u64) -> [Event; 2] {
+    [
+        event(node, op, EventType::Invoke, Function::Write, Some(value), call),
+        event(node, op, EventType::Ok, Function::Write, Some(value), ret),
+    ]
+}
+
+fn read(node: u64, op: u64, value: Option<&str>, call: u64, ret: u64) -> [Event; 2] {
+    [
+        event(node, op, EventType::Invoke, Function::Read, None, call),
+        event(node, op, EventType::Ok, Function::Read, value, ret),
+    ]
+}
+
+#[test]
+fn sequential_history_is_linearizable() {
+    let events = [write(1, 1, "a", 0, 10), read(2, 1, Some("a"), 20, 30)].concat();
+    assert!(history::check(&events).is_ok());
+}
+
+#[test]
+fn stale_read_after_acknowledged_write_is_not() {
+    let events = [
+        write(1, 1, "a", 0, 10),
+        write(1, 2, "b", 20, 30),
+        read(2, 1, Some("a"), 40, 50),
+    ].concat();
+
+    let violations = history::check(&events).unwrap_err();
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].key, "x");
+}
+
+#[test]
+fn concurrent_operations_may_take_effect_in_either_order() {
+    let events = [
+        write(1, 1, "a", 0, 100),
+        write(2, 1, "b", 10, 90),
+        read(3, 1, Some("a"), 95, 110),
+        read(3, 2, Some("a"), 120, 130),
+    ].concat();
+    assert!(history::check(&events).is_ok());
+}
+
+#[test]
+fn unknown_outcome_write_may_or_may_not_have_happened() {
+    let mut events = vec![
+        event(1, 1, EventType::Invoke, Function::Write, Some("a"), 0),
+        event(1, 1, EventType::Info, Function::Write, Some("a"), 10),
+    ];
+    events.extend(read(2, 1, None, 20, 30));
+    events.extend(read(2, 2, Some("a"), 40, 50));
+    assert!(history::check(&events).is_ok());
+
+    // Once observed it has happened, so the register can't go back to empty.
+    events.extend(read(2, 3, None, 60, 70));
+    assert!(history::check(&events).is_err());
+}
+
+#[test]
+fn failed_writes_are_never_visible() {
+    let mut events = vec![
+        event(1, 1, EventType::Invoke, Function::Write, Some("a"), 0),
+        event(1, 1, EventType::Fail, Function::Write, Some("a"), 10),
+    ];
+    events.extend(read(2, 1, Some("a"), 20, 30));
+    assert!(history::check(&events).is_err());
+}
+
+#[test]
+fn deletes_empty_the_register() {
+    let events = [
+        write(1, 1, "a", 0, 10),
+        [
+            event(1, 2, EventType::Invoke, Function::Delete, None, 20),
+            event(1, 2, EventType::Ok, Function::Delete, None, 30),
+        ],
+        read(2, 1, None, 40, 50),
+    ].concat();
+    assert!(history::check(&events).is_ok());
+}
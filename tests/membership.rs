@@ -0,0 +1,22 @@
+use paxos_from_scratch::{Node, membership::Membership};
+
+fn node(id: u64) -> Node {
+    Node::new(id, format!("127.0.0.1:{}", 3000 + id).parse().unwrap())
+}
+
+#[test]
+fn snapshots_keep_the_peers_they_were_taken_with() {
+    let membership = Membership::new(vec![node(2)]);
+    let before = membership.snapshot();
+
+    let joined = membership.update(|nodes| {
+        nodes.push(node(3));
+        nodes.len()
+    });
+    assert_eq!(joined, 2);
+
+    assert_eq!(before.iter().map(|node| node.id).collect::<Vec<_>>(), [2]);
+    assert_eq!(membership.snapshot().iter().map(|node| node.id).collect::<Vec<_>>(), [2, 3]);
+    assert_eq!(membership.at(node(3).addr), Some(3));
+    assert_eq!(membership.at(node(4).addr), None);
+}
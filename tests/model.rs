@@ -0,0 +1,36 @@
+#![cfg(feature = "model-check")]
+
+use paxos_from_scratch::model::ModelConfig;
+use stateright::{Checker, Model, UniformChooser};
+
+fn check(config: ModelConfig) {
+    let checker = config.into_model().checker().spawn_bfs().join();
+    checker.assert_properties();
+}
+
+#[test]
+fn single_proposer_is_safe_and_live() {
+    check(ModelConfig { nodes: 3, proposers: 1, attempts: 1 });
+}
+
+#[test]
+fn competing_proposers_agree() {
+    check(ModelConfig { nodes: 3, proposers: 2, attempts: 1 });
+}
+
+#[test]
+fn a_retry_agrees_with_the_attempt_it_preempted() {
+    check(ModelConfig { nodes: 3, proposers: 1, attempts: 2 });
+}
+
+/// Too many interleavings to enumerate them all, so walk random ones instead.
+#[test]
+fn retrying_proposers_agree() {
+    let checker = ModelConfig { nodes: 3, proposers: 2, attempts: 2 }
+        .into_model()
+        .checker()
+        .target_state_count(500_000)
+        .spawn_simulation(0, UniformChooser)
+        .join();
+    checker.assert_properties();
+}
@@ -0,0 +1,40 @@
+use std::{net::{Ipv4Addr, SocketAddrV4}, sync::Arc, time::Duration};
+
+use paxos_from_scratch::{
+    Ballot, ProposalId,
+    multicast::{self, MAX_DATAGRAM_BYTES, Multicast},
+    sim::{Sim, SimConfig},
+};
+
+fn ballot(instance: u64, value: String) -> Ballot {
+    Ballot { instance, id: ProposalId { round: 1, node_id: 1 }, value: Some(value) }
+}
+
+#[tokio::test]
+async fn a_batch_reaches_the_group_and_a_big_one_falls_back() {
+    let group = SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 1), 40000 + (std::process::id() % 20000) as u16);
+    let sim = Sim::new(0, SimConfig { nodes: 2, ..SimConfig::default() });
+    let (mut sender, mut receiver) = (sim.node(0).clone(), sim.node(1).clone());
+    sender.multicast = Some(Arc::new(Multicast::join(group, Ipv4Addr::LOCALHOST).unwrap()));
+    receiver.multicast = Some(Arc::new(Multicast::join(group, Ipv4Addr::LOCALHOST).unwrap()));
+    tokio::spawn(multicast::listen(receiver.clone()));
+
+    let listeners = sender.multicast.as_ref().unwrap();
+    listeners.heard(receiver.node.id, Some("239.255.0.9:1"));
+    assert!(multicast::send(&sender, &[ballot(1, String::from("v"))]).await.is_empty(), "a peer on another group gets nothing");
+
+    listeners.heard(receiver.node.id, Some(&group.to_string()));
+    let reached = multicast::send(&sender, &[ballot(1, String::from("v"))]).await;
+    assert!(reached.contains(&receiver.node.id));
+
+    let big = ballot(2, "x".repeat(MAX_DATAGRAM_BYTES));
+    assert!(multicast::send(&sender, &[big]).await.is_empty(), "a batch too big for a datagram goes over HTTP");
+
+    for _ in 0..200 {
+        if receiver.ledger.get(1).is_some() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("the receiver never learned the multicast value");
+}
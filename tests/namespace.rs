@@ -0,0 +1,96 @@
+use axum::http::StatusCode;
+
+use paxos_from_scratch::{
+    AppState,
+    namespace::{self, Namespace},
+};
+
+mod common;
+use common::{cluster, send};
+
+async fn create(state: &AppState, name: &str, retention_ms: Option<u64>) -> StatusCode {
+    let namespace = Namespace { name: name.to_string(), retention_ms, script: None };
+    send(state, "POST", "/admin/namespaces", &serde_json::to_string(&namespace).unwrap()).await.0
+}
+
+fn micros() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_micros() as u64
+}
+
+#[tokio::test]
+async fn namespaces_dont_see_each_others_keys() {
+    let sim = cluster(0, 1);
+    let state = sim.node(0).clone();
+
+    assert_eq!(send(&state, "PUT", "/ns/a/kv/k", "1").await.0, StatusCode::NOT_FOUND, "a namespace must be made first");
+    assert_eq!(create(&state, "a", None).await, StatusCode::OK);
+    assert_eq!(create(&state, "b", None).await, StatusCode::OK);
+    assert_eq!(create(&state, "no/slash", None).await, StatusCode::BAD_REQUEST);
+
+    assert_eq!(send(&state, "PUT", "/ns/a/kv/k", "1").await.0, StatusCode::OK);
+    assert_eq!(send(&state, "PUT", "/kv/k", "default").await.0, StatusCode::OK);
+    assert_eq!(send(&state, "GET", "/ns/a/kv/k", "").await, (StatusCode::OK, String::from("1")));
+    assert_eq!(send(&state, "GET", "/ns/b/kv/k", "").await.0, StatusCode::NOT_FOUND);
+    assert_eq!(send(&state, "GET", "/kv/k", "").await, (StatusCode::OK, String::from("default")));
+
+    // The KV API can't reach into a namespace, nor expire one.
+    assert_eq!(send(&state, "GET", "/kv/__ns%2Fa%2Fk", "").await.0, StatusCode::BAD_REQUEST);
+    assert_eq!(send(&state, "PUT", "/kv/__ns%2Fb%2Fk", "planted").await.0, StatusCode::BAD_REQUEST);
+    let expire = r#"{"op":"expire","namespace":"a","before":18446744073709551615}"#;
+    assert_eq!(send(&state, "POST", "/prepare", expire).await.0, StatusCode::BAD_REQUEST);
+
+    assert_eq!(send(&state, "DELETE", "/ns/a/kv/k", "").await.0, StatusCode::OK);
+    assert_eq!(send(&state, "GET", "/ns/a/kv/k", "").await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn dropping_a_namespace_drops_its_keys_on_every_node() {
+    let sim = cluster(0, 3);
+    let state = sim.node(0).clone();
+    assert_eq!(create(&state, "a", None).await, StatusCode::OK);
+    assert_eq!(send(&state, "PUT", "/ns/a/kv/k", "1").await.0, StatusCode::OK);
+    assert_eq!(send(&state, "DELETE", "/admin/namespaces/a", "").await.0, StatusCode::OK);
+    assert_eq!(send(&state, "DELETE", "/admin/namespaces/a", "").await.0, StatusCode::NOT_FOUND);
+    sim.settle().await;
+
+    for i in 0..3 {
+        let kv = sim.node(i).kv.lock().await;
+        assert!(kv.data.keys().all(|key| !key.starts_with(namespace::PREFIX)), "node {} kept {:?}", i, kv.data);
+    }
+}
+
+#[tokio::test]
+async fn keys_past_their_retention_are_swept_everywhere() {
+    let sim = cluster(0, 3);
+    let state = sim.node(0).clone();
+    assert_eq!(create(&state, "short", Some(60_000)).await, StatusCode::OK);
+    assert_eq!(create(&state, "forever", None).await, StatusCode::OK);
+    assert_eq!(send(&state, "PUT", "/ns/short/kv/k", "1").await.0, StatusCode::OK);
+    assert_eq!(send(&state, "PUT", "/ns/forever/kv/k", "1").await.0, StatusCode::OK);
+
+    assert_eq!(namespace::sweep(&state, micros()).await, 0, "nothing is a minute old yet");
+    assert_eq!(namespace::sweep(&state, micros() + 120_000_000).await, 1);
+    sim.settle().await;
+
+    for i in 0..3 {
+        let kv = sim.node(i).kv.lock().await;
+        assert!(kv.get(&namespace::key("short", "k")).is_none(), "node {} kept an expired key", i);
+        assert!(kv.get(&namespace::key("forever", "k")).is_some(), "node {} expired a key it should keep", i);
+    }
+}
+
+#[tokio::test]
+async fn metrics_show_each_namespace() {
+    let sim = cluster(0, 1);
+    let state = sim.node(0).clone();
+    assert_eq!(create(&state, "a", Some(1_500)).await, StatusCode::OK);
+    assert_eq!(create(&state, "b", None).await, StatusCode::OK);
+    assert_eq!(send(&state, "PUT", "/ns/a/kv/x", "1").await.0, StatusCode::OK);
+    assert_eq!(send(&state, "PUT", "/ns/a/kv/y", "2").await.0, StatusCode::OK);
+
+    let (_, metrics) = send(&state, "GET", "/metrics", "").await;
+    assert!(metrics.contains("paxos_namespace_keys{namespace=\"a\"} 2"), "{}", metrics);
+    assert!(metrics.contains("paxos_namespace_keys{namespace=\"b\"} 0"), "{}", metrics);
+    assert!(metrics.contains("paxos_namespace_retention_seconds{namespace=\"a\"} 1.5"), "{}", metrics);
+    assert!(!metrics.contains("paxos_namespace_retention_seconds{namespace=\"b\"}"), "{}", metrics);
+}
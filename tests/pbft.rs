@@ -0,0 +1,113 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    Ballot, ProposalId,
+    pbft::{self, Phase, PrePrepare, Vote},
+    sim::{self, Sim, SimConfig},
+};
+
+fn byzantine_cluster(seed: u64) -> Sim {
+    let sim = Sim::new(seed, SimConfig { nodes: 4, ..SimConfig::default() });
+    for i in 0..sim.size() {
+        sim.node(i).pbft.enable(0);
+    }
+    sim
+}
+
+/// Lets the replicas trade their votes, which they do in the background.
+async fn exchange() {
+    for _ in 0..2_000 {
+        tokio::task::yield_now().await;
+    }
+}
+
+#[test]
+fn quorums_of_two_share_an_honest_replica() {
+    for n in 1..20 {
+        let (f, q) = (pbft::faulty(n), pbft::quorum(n));
+        assert!(2 * q > n + f, "{} nodes, quorums of {}", n, q);
+        assert!(q <= n - f, "{} nodes can't decide with {} of them faulty", n, f);
+    }
+    assert_eq!((pbft::faulty(4), pbft::quorum(4)), (1, 3));
+}
+
+#[test]
+fn commands_are_decided_in_the_same_order_everywhere() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = byzantine_cluster(seed);
+        let values: Vec<String> = (0..3).map(|i| format!("v{}", i)).collect();
+        for (i, value) in values.iter().enumerate() {
+            let reply = sim.propose(i, value).await;
+            if reply.is_error() {
+                return Err(format!("{} wasn't decided: {}", value, reply.body));
+            }
+        }
+        exchange().await;
+
+        for (i, ledger) in sim.ledgers().await.iter().enumerate() {
+            let decided: Vec<&String> = (1..=3).filter_map(|seq| ledger.get(&seq)).collect();
+            if decided != values.iter().collect::<Vec<_>>() {
+                return Err(format!("node {} decided {:?}", i + 1, decided));
+            }
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn a_primary_that_equivocates_gets_no_two_decisions() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = byzantine_cluster(seed);
+        let primary = sim.node(0);
+
+        // Node 1, the primary, tells nodes 2 and 3 one command and node 4
+        // another for the same number, and votes for whatever each was told.
+        for (i, value) in [(1, "a"), (2, "a"), (3, "b")] {
+            let addr = sim.node(i).node.addr;
+            let message = PrePrepare { view: 0, seq: 1, value: value.to_string() };
+            primary.transport.post(addr, "/pbft/pre-prepare", serde_json::to_string(&message).unwrap()).await?;
+            for phase in [Phase::Prepare, Phase::Commit] {
+                let vote = Vote { phase, view: 0, seq: 1, digest: pbft::digest(&value.to_string()) };
+                primary.transport.post(addr, "/pbft/vote", serde_json::to_string(&vote).unwrap()).await?;
+            }
+        }
+        exchange().await;
+
+        sim.check_agreement().await?;
+        let ledgers = sim.ledgers().await;
+        if ledgers[3].contains_key(&1) {
+            return Err(format!("node 4 decided {:?} with a single honest vote", ledgers[3][&1]));
+        }
+        if ledgers[1].get(&1).map(String::as_str) != Some("a") || ledgers[2].get(&1).map(String::as_str) != Some("a") {
+            return Err(format!("nodes 2 and 3 had a quorum for a but decided {:?} and {:?}", ledgers[1].get(&1), ledgers[2].get(&1)));
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn only_the_primary_gives_out_numbers() {
+    let sim = byzantine_cluster(0);
+    let message = PrePrepare { view: 0, seq: 1, value: String::from("forged") };
+    let reply = sim.node(1).transport.post(sim.node(2).node.addr, "/pbft/pre-prepare", serde_json::to_string(&message).unwrap()).await.unwrap();
+    assert_eq!(reply.status, StatusCode::CONFLICT);
+    assert!(reply.body.contains("isn't the primary"), "{}", reply.body);
+}
+
+#[tokio::test]
+async fn paxos_messages_are_turned_away() {
+    let sim = byzantine_cluster(0);
+    let ballot = Ballot { instance: 1, id: ProposalId { round: 1, node_id: 2 }, value: Some(String::from("lie")) };
+    let reply = sim.node(1).transport.post(sim.node(2).node.addr, "/handle-learn", serde_json::to_string(&ballot).unwrap()).await.unwrap();
+    assert_eq!(reply.status, StatusCode::CONFLICT);
+    assert!(sim.node(2).ledger.get(1).is_none());
+}
+
+#[test]
+fn a_node_refuses_to_run_pbft_without_a_signing_key() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_paxos-from-scratch"))
+        .args(["--id", "1", "--port", "0", "--byzantine"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--byzantine needs --signing-key"));
+}
@@ -0,0 +1,80 @@
+use proptest::prelude::*;
+
+use paxos_from_scratch::playground::{Message, Outcome, Playground};
+
+const NODES: usize = 3;
+
+#[derive(Clone, Debug)]
+enum Step {
+    Propose(u64),
+    Deliver(usize),
+    Drop(usize),
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        1 => (1..=NODES as u64).prop_map(Step::Propose),
+        8 => any::<usize>().prop_map(Step::Deliver),
+        1 => any::<usize>().prop_map(Step::Drop),
+    ]
+}
+
+#[test]
+fn a_lone_proposal_is_chosen_everywhere() {
+    let mut playground = Playground::new(NODES);
+    playground.propose(1, String::from("a")).unwrap();
+    playground.run(1000);
+
+    assert_eq!(playground.outcomes, [Outcome::Chosen { node: 1, instance: 1, value: String::from("a") }]);
+    for node in &playground.nodes {
+        assert_eq!(node.ledger.get(&1).map(String::as_str), Some("a"), "node {}", node.id);
+    }
+}
+
+#[test]
+fn a_late_proposer_adopts_the_chosen_value_and_moves_on() {
+    let mut playground = Playground::new(NODES);
+    playground.propose(1, String::from("a")).unwrap();
+
+    // Only node 1 hears that "a" was chosen.
+    while let Some(envelope) = playground.in_flight.first().cloned() {
+        if matches!(envelope.message, Message::Learn { .. }) && envelope.to != 1 {
+            playground.drop_message(0);
+        } else {
+            playground.deliver(0);
+        }
+    }
+
+    playground.propose(2, String::from("b")).unwrap();
+    playground.run(1000);
+
+    playground.check_agreement().unwrap();
+    assert_eq!(playground.outcomes.last(), Some(&Outcome::Chosen { node: 2, instance: 2, value: String::from("b") }));
+    assert_eq!(playground.nodes[1].ledger.get(&1).map(String::as_str), Some("a"));
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn any_schedule_agrees(steps in prop::collection::vec(step(), 1..300)) {
+        let mut playground = Playground::new(NODES);
+        for (i, step) in steps.into_iter().enumerate() {
+            match step {
+                Step::Propose(node) => {
+                    let _ = playground.propose(node, format!("v{}", i));
+                },
+                Step::Deliver(index) if !playground.in_flight.is_empty() => {
+                    playground.deliver(index % playground.in_flight.len());
+                },
+                Step::Drop(index) if !playground.in_flight.is_empty() => {
+                    playground.drop_message(index % playground.in_flight.len());
+                },
+                _ => {},
+            }
+        }
+        playground.run(10_000);
+
+        prop_assert!(playground.check_agreement().is_ok(), "{:?}", playground.check_agreement());
+    }
+}
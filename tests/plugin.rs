@@ -0,0 +1,91 @@
+#![cfg(feature = "plugins")]
+
+use std::sync::Arc;
+use paxos_from_scratch::{
+    kv::{Change, Command, Kv},
+    plugin::Plugin,
+};
+
+/// Puts each value under itself as a key, then runs `then`.
+fn echo(then: &str) -> String {
+    format!(r#"
+        (module
+          (import "env" "put" (func $put (param i32 i32 i32 i32)))
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) i32.const 1024)
+          (func (export "apply") (param i32 i32)
+            (call $put (local.get 0) (local.get 1) (local.get 0) (local.get 1))
+            {}))
+    "#, then)
+}
+
+/// Puts each value under `src`, then copies `src` to `dst`.
+const COPY: &str = r#"
+    (module
+      (import "env" "get" (func $get (param i32 i32 i32 i32) (result i32)))
+      (import "env" "put" (func $put (param i32 i32 i32 i32)))
+      (memory (export "memory") 1)
+      (data (i32.const 0) "src")
+      (data (i32.const 8) "dst")
+      (func (export "alloc") (param i32) (result i32) i32.const 1024)
+      (func (export "apply") (param i32 i32) (local $len i32)
+        (call $put (i32.const 0) (i32.const 3) (local.get 0) (local.get 1))
+        (local.set $len (call $get (i32.const 0) (i32.const 3) (i32.const 16) (i32.const 512)))
+        (call $put (i32.const 8) (i32.const 3) (i32.const 16) (local.get $len))))
+"#;
+
+fn kv(module: &str) -> Kv {
+    Kv::with_machine(Some(Arc::new(Plugin::new("test", module.as_bytes()).unwrap())))
+}
+
+#[test]
+fn a_plugin_decides_what_a_value_does() {
+    let mut kv = kv(&echo(""));
+    let changes = kv.apply_changes("hello");
+    assert_eq!(changes, vec![Change { key: String::from("hello"), before: None, after: Some(String::from("hello")) }]);
+
+    // A KV command is just another value to the plugin.
+    let put = Command::Put { key: String::from("k"), value: String::from("v") }.encode();
+    kv.apply(&put);
+    assert_eq!(kv.get("k"), None);
+    assert_eq!(kv.get(&put), Some(&put));
+}
+
+#[test]
+fn a_plugin_reads_its_own_writes() {
+    let mut kv = kv(COPY);
+    kv.apply("one");
+    kv.apply("two");
+    assert_eq!((kv.get("src").map(String::as_str), kv.get("dst").map(String::as_str)), (Some("two"), Some("two")));
+}
+
+#[test]
+fn a_value_that_traps_or_runs_out_of_fuel_changes_nothing() {
+    for then in ["unreachable", "(loop $forever (br $forever))"] {
+        let mut kv = kv(&echo(then));
+        assert_eq!(kv.apply_changes("hello"), Vec::new(), "{}", then);
+        assert!(kv.data.is_empty(), "{}", then);
+    }
+}
+
+#[test]
+fn reserved_keys_stay_the_nodes_own() {
+    let mut kv = kv(&echo(""));
+    let grant = Command::Put { key: String::from("__acl/token"), value: String::from("{}") }.encode();
+    kv.apply(&grant);
+    assert_eq!(kv.data.len(), 1);
+    assert_eq!(kv.get("__acl/token").map(String::as_str), Some("{}"));
+}
+
+#[test]
+fn a_module_that_imports_anything_else_is_refused() {
+    let clock = r#"
+        (module
+          (import "wasi_snapshot_preview1" "clock_time_get" (func (param i32 i64 i32) (result i32)))
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) i32.const 0)
+          (func (export "apply") (param i32 i32)))
+    "#;
+    assert!(Plugin::new("clock", clock.as_bytes()).is_err());
+    assert!(Plugin::new("nothing", b"(module)").is_err());
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    prevote::{PreVote, PreVoteReply},
+    sim::{self, Sim, SimConfig},
+};
+
+const LEASE: Duration = Duration::from_secs(60);
+
+/// A cluster preparing ten instances ahead, with pre-votes on or off.
+fn cluster(seed: u64, lease: Duration) -> Sim {
+    let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+    for index in 0..sim.size() {
+        let mut settings = sim.node(index).settings.write().unwrap();
+        settings.prepare_ahead = 10;
+        settings.pre_vote_lease = lease;
+    }
+    sim
+}
+
+#[test]
+fn a_voter_turns_down_others_while_it_follows_a_proposer() {
+    let prevote = PreVote::default();
+    assert_eq!(prevote.answer(2, LEASE), PreVoteReply { granted: true, leader: None }, "it follows no one yet");
+
+    prevote.heard(1);
+    assert_eq!(prevote.answer(2, LEASE), PreVoteReply { granted: false, leader: Some(1) });
+    assert!(prevote.answer(1, LEASE).granted, "the proposer it follows may prepare again");
+    assert!(prevote.answer(2, Duration::ZERO).granted, "the lease ran out");
+}
+
+#[test]
+fn a_write_elsewhere_goes_to_the_proposer_instead_of_preempting_it() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, LEASE);
+        for (index, key) in [(0, "a"), (1, "b"), (2, "c"), (0, "d")] {
+            let reply = sim.put(index, key, "1").await;
+            if reply.status != StatusCode::OK {
+                return Err(format!("the write of {} through node {} failed: {}", key, index, reply.body));
+            }
+        }
+        sim.settle().await;
+        sim.check_agreement().await?;
+
+        let messages = sim.messages();
+        if messages.get("/handle-prepare-range") != Some(&3) || messages.contains_key("/handle-prepare") {
+            return Err(format!("node 0 had to prepare again: {:?}", messages));
+        }
+        if sim.node(1).proposer.round() != 0 || sim.node(2).proposer.round() != 0 {
+            return Err(String::from("a node that lost the pre-vote prepared anyway"));
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn without_a_lease_every_node_prepares_for_itself() {
+    let sim = cluster(0, Duration::ZERO);
+    for index in 0..sim.size() {
+        let reply = sim.put(index, &format!("k{}", index), "1").await;
+        assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    }
+    assert!(sim.messages().get("/handle-prepare-range") > Some(&3), "{:?}", sim.messages());
+    assert!(!sim.messages().contains_key("/handle-pre-vote"));
+}
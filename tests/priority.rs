@@ -0,0 +1,65 @@
+use std::time::Duration;
+use axum::{body::Body, http::{Request, StatusCode}};
+use tower::ServiceExt;
+use paxos_from_scratch::{
+    backpressure::{Backpressure, Limits},
+    priority::{self, Lane, URGENT_RUN},
+    router,
+    sim::{Sim, SimConfig},
+};
+
+#[tokio::test]
+async fn urgent_commands_go_first_without_starving_the_rest() {
+    let (senders, mut lanes) = priority::lanes();
+    for i in 0..3 {
+        senders.send(Lane::Normal, format!("normal {}", i)).unwrap();
+    }
+    for i in 0..URGENT_RUN + 2 {
+        senders.send(Lane::Urgent, format!("urgent {}", i)).unwrap();
+    }
+
+    let mut order = Vec::new();
+    for _ in 0..URGENT_RUN + 5 {
+        order.push(lanes.recv().await.unwrap());
+    }
+    let mut expected: Vec<String> = (0..URGENT_RUN).map(|i| format!("urgent {}", i)).collect();
+    expected.extend([String::from("normal 0"), format!("urgent {}", URGENT_RUN), format!("urgent {}", URGENT_RUN + 1)]);
+    expected.extend([String::from("normal 1"), String::from("normal 2")]);
+    assert_eq!(order, expected);
+
+    drop(senders);
+    assert_eq!(lanes.recv().await, None);
+}
+
+#[tokio::test]
+async fn an_urgent_proposal_takes_a_free_slot_ahead_of_the_queue() {
+    let backpressure = Backpressure::default();
+    let limits = Limits { max_in_flight: 1, max_queued: 1 };
+
+    let running = backpressure.admit(limits).await.unwrap();
+    let waiting = backpressure.admit(limits);
+    tokio::pin!(waiting);
+    assert!(tokio::time::timeout(Duration::from_millis(50), &mut waiting).await.is_err());
+
+    drop(running);
+    let urgent = priority::scope(Lane::Urgent, backpressure.admit(limits)).await;
+    assert!(urgent.is_some(), "the urgent proposal went first");
+    assert!(tokio::time::timeout(Duration::from_millis(50), &mut waiting).await.is_err(), "the queued one still waits");
+
+    drop(urgent);
+    assert!(waiting.await.is_some());
+}
+
+#[tokio::test]
+async fn a_client_picks_its_lane_with_a_header() {
+    let sim = Sim::new(0, SimConfig { nodes: 1, ..SimConfig::default() });
+    let write = |priority: &str| {
+        let request = Request::builder().method("PUT").uri("/kv/lock").header(priority::PRIORITY_HEADER, priority);
+        router(sim.node(0).clone()).oneshot(request.body(Body::from("released")).unwrap())
+    };
+
+    assert_eq!(write("urgent").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(write("Normal").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(write("whenever").await.unwrap().status(), StatusCode::BAD_REQUEST);
+    assert_eq!(sim.node(0).kv.lock().await.get("lock").map(String::as_str), Some("released"));
+}
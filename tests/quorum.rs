@@ -0,0 +1,140 @@
+use std::collections::BTreeSet;
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    Node,
+    quorum::Quorum,
+    sim::{self, Sim, SimConfig},
+};
+
+fn ids(ids: &[u64]) -> BTreeSet<u64> {
+    ids.iter().copied().collect()
+}
+
+/// Five voters in zone a, and two each in b and c.
+fn zones() -> Vec<String> {
+    ["a", "a", "a", "a", "a", "b", "b", "c", "c"].iter().map(|zone| zone.to_string()).collect()
+}
+
+fn voters() -> Vec<Node> {
+    zones().into_iter().enumerate()
+        .map(|(i, zone)| Node { zone: Some(zone), ..Node::new(i as u64 + 1, ([127, 0, 0, 1], 3000 + i as u16).into()) })
+        .collect()
+}
+
+#[test]
+fn without_zones_a_quorum_is_a_majority() {
+    let nodes: Vec<Node> = (1..=5).map(|id| Node::new(id, ([127, 0, 0, 1], 3000 + id as u16).into())).collect();
+    let quorum = Quorum::of(&nodes);
+    assert_eq!(quorum, Quorum::Majority(5));
+    assert!(quorum.is_met(&ids(&[1, 4, 5])));
+    assert!(!quorum.is_met(&ids(&[1, 2])));
+    assert_eq!(quorum.nearby(1), None);
+}
+
+#[test]
+fn with_zones_a_quorum_is_a_majority_of_zone_majorities() {
+    let quorum = Quorum::of(&voters());
+    assert_eq!(quorum.zones(), vec![vec![1, 2, 3, 4, 5], vec![6, 7], vec![8, 9]]);
+
+    assert!(quorum.is_met(&ids(&[6, 7, 8, 9])), "zones b and c, without a");
+    assert!(quorum.is_met(&ids(&[1, 2, 3, 6, 7])));
+    assert!(!quorum.is_met(&ids(&[1, 2, 3, 4, 5])), "a majority of the voters, but in one zone");
+    assert!(!quorum.is_met(&ids(&[1, 2, 3, 6, 8])), "one voter of b and of c is no majority of them");
+
+    // Node 6 asks its own zone and a majority of the next one first.
+    assert_eq!(quorum.nearby(6), Some(ids(&[1, 2, 3, 6, 7])));
+}
+
+#[test]
+fn a_voter_without_a_zone_is_a_zone_of_its_own() {
+    let mut nodes = voters();
+    nodes.truncate(7);
+    nodes[6].zone = None;
+    let quorum = Quorum::of(&nodes);
+    assert_eq!(quorum.zones(), vec![vec![1, 2, 3, 4, 5], vec![6], vec![7]]);
+    assert!(quorum.is_met(&ids(&[6, 7])));
+}
+
+#[test]
+fn the_cluster_survives_losing_its_biggest_zone() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 9, zones: zones(), ..SimConfig::default() });
+        sim.partition(&[&[5, 6, 7, 8]]);
+
+        let reply = sim.put(5, "x", "1").await;
+        if reply.status != StatusCode::OK {
+            return Err(format!("the write failed without zone a: {}", reply.body));
+        }
+        sim.settle().await;
+        sim.check_agreement().await?;
+
+        // Zone a alone holds most of the voters, and still can't decide.
+        let reply = sim.put(0, "y", "1").await;
+        if !reply.is_error() {
+            return Err(String::from("zone a decided on its own"));
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn a_zoned_round_stays_near_home_while_it_can() {
+    let sim = Sim::new(0, SimConfig { nodes: 9, zones: zones(), ..SimConfig::default() });
+    let reply = sim.put(5, "x", "1").await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+
+    let messages = sim.messages();
+    assert_eq!(messages.get("/handle-accept"), Some(&5), "{:?}", messages);
+    let certificate = sim.node(5).certificates.get(1).unwrap();
+    assert_eq!((certificate.voters, certificate.zones.len()), (9, 3));
+}
+
+#[test]
+fn a_weighted_quorum_is_more_than_half_the_votes() {
+    let mut nodes: Vec<Node> = (1..=4).map(|id| Node::new(id, ([127, 0, 0, 1], 3000 + id as u16).into())).collect();
+    nodes[0].weight = 2;
+    let quorum = Quorum::of(&nodes);
+    assert_eq!(quorum.weights(), [(1, 2)].into_iter().collect());
+    assert_eq!(quorum.to_string(), "more than half of 5 votes");
+
+    assert!(quorum.is_met(&ids(&[1, 2])), "3 votes of 5");
+    assert!(quorum.is_met(&ids(&[2, 3, 4])));
+    assert!(!quorum.is_met(&ids(&[1])));
+    assert!(!quorum.is_met(&ids(&[3, 4])));
+}
+
+#[test]
+fn weights_count_within_each_zone() {
+    let mut nodes = voters();
+    nodes[5].weight = 3;
+    let quorum = Quorum::of(&nodes);
+    assert!(quorum.is_met(&ids(&[6, 8, 9])), "node 6 outweighs node 7 in zone b");
+    assert!(!quorum.is_met(&ids(&[7, 8, 9])));
+    assert_eq!(quorum.nearby(8), Some(ids(&[1, 2, 3, 8, 9])));
+}
+
+#[test]
+fn a_heavy_voter_breaks_the_tie_between_two_halves() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 4, weights: vec![2, 1, 1, 1], ..SimConfig::default() });
+        sim.partition(&[&[0, 1], &[2, 3]]);
+
+        let reply = sim.put(0, "x", "1").await;
+        if reply.status != StatusCode::OK {
+            return Err(format!("the half with the heavy voter couldn't write: {}", reply.body));
+        }
+        let reply = sim.put(2, "y", "1").await;
+        if !reply.is_error() {
+            return Err(String::from("the other half decided with 2 votes of 5"));
+        }
+
+        sim.heal();
+        sim.settle().await;
+        sim.check_agreement().await?;
+        let certificate = sim.node(0).certificates.get(1).ok_or("node 0 kept no certificate")?;
+        if certificate.weights.get(&1) != Some(&2) {
+            return Err(format!("the certificate lost the weights: {:?}", certificate));
+        }
+        Ok(())
+    });
+}
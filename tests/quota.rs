@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+use axum::http::StatusCode;
+
+use paxos_from_scratch::{
+    AppState,
+    acl::{Grant, NewGrant, Op, Rule},
+    quota::{ClientUsage, Over, Quota, Quotas, Usage},
+    sim::Sim,
+};
+
+mod common;
+use common::{cluster, send_as};
+
+fn quota(ops_per_sec: f64, bytes_per_sec: f64, over: Over) -> Quota {
+    Quota { ops_per_sec, bytes_per_sec, burst_secs: 1.0, over }
+}
+
+/// A node with ACLs on, an admin `root` and a client `app` under `quota`.
+async fn node(sim: &Sim, quota: Quota) -> AppState {
+    let mut state = sim.node(0).clone();
+    state.acl = true;
+    let grants = [
+        NewGrant { token: String::from("root-token"), grant: Grant { client: String::from("root"), admin: true, rules: Vec::new(), quota: None } },
+        NewGrant {
+            token: String::from("app-token"),
+            grant: Grant { client: String::from("app"), admin: false, rules: vec![Rule { prefix: String::new(), ops: vec![Op::Write] }], quota: Some(quota) },
+        },
+    ];
+    for grant in grants {
+        let (status, body) = send_as(&state, "POST", "/admin/acl", Some("root-token"), &serde_json::to_string(&grant).unwrap()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    state
+}
+
+async fn usage(state: &AppState) -> Vec<ClientUsage> {
+    let (status, body) = send_as(state, "GET", "/admin/quotas", Some("root-token"), "").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[test]
+fn a_bucket_refills_at_the_quota_rate() {
+    let quotas = Quotas::default();
+    let quota = quota(2.0, 0.0, Over::Reject);
+    let start = Instant::now();
+
+    assert_eq!(quotas.take("a", &quota, 10, start), Ok(()));
+    assert_eq!(quotas.take("a", &quota, 10, start), Ok(()));
+    assert_eq!(quotas.take("a", &quota, 10, start), Err(Duration::from_millis(500)));
+    assert_eq!(quotas.take("b", &quota, 10, start), Ok(()), "each client has a bucket of its own");
+    assert_eq!(quotas.take("a", &quota, 10, start + Duration::from_millis(500)), Ok(()));
+}
+
+#[tokio::test]
+async fn writes_over_the_quota_are_rejected_and_counted() {
+    let sim = cluster(0, 1);
+    let state = node(&sim, quota(1.0, 0.0, Over::Reject)).await;
+
+    assert_eq!(send_as(&state, "PUT", "/kv/a", Some("app-token"), "1").await.0, StatusCode::OK);
+    assert_eq!(send_as(&state, "PUT", "/kv/b", Some("app-token"), "1").await.0, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(send_as(&state, "POST", "/prepare", Some("app-token"), "raw").await.0, StatusCode::TOO_MANY_REQUESTS);
+    assert!(sim.node(0).kv.lock().await.get("b").is_none());
+
+    let usage = usage(&state).await;
+    let app = usage.iter().find(|client| client.client == "app").unwrap();
+    assert_eq!(app.usage, Usage { ops: 1, bytes: r#"{"op":"put","key":"a","value":"1"}"#.len() as u64, rejected: 2, throttled: 0 });
+    let root = usage.iter().find(|client| client.client == "root").unwrap();
+    assert_eq!((root.quota, root.usage), (None, Usage::default()));
+}
+
+#[tokio::test]
+async fn a_big_write_spends_the_byte_quota_ahead() {
+    let sim = cluster(0, 1);
+    let state = node(&sim, quota(0.0, 100.0, Over::Reject)).await;
+
+    // Bigger than the bucket, so it waits for a full one and leaves it owing.
+    assert_eq!(send_as(&state, "PUT", "/kv/a", Some("app-token"), &"x".repeat(500)).await.0, StatusCode::OK);
+    let (status, body) = send_as(&state, "PUT", "/kv/b", Some("app-token"), "1").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(body.contains("retry in 5s"), "{}", body);
+}
+
+#[tokio::test]
+async fn throttled_writes_wait_their_turn() {
+    let sim = cluster(0, 1);
+    let state = node(&sim, Quota { burst_secs: 0.05, ..quota(20.0, 0.0, Over::Throttle) }).await;
+
+    let start = Instant::now();
+    for key in ["a", "b", "c"] {
+        assert_eq!(send_as(&state, "PUT", &format!("/kv/{}", key), Some("app-token"), "1").await.0, StatusCode::OK);
+    }
+    assert!(start.elapsed() >= Duration::from_millis(90), "three writes at 20 a second took {:?}", start.elapsed());
+
+    let usage = usage(&state).await;
+    let app = usage.iter().find(|client| client.client == "app").unwrap();
+    assert_eq!((app.usage.ops, app.usage.rejected, app.usage.throttled), (3, 0, 2));
+}
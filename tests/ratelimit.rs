@@ -0,0 +1,122 @@
+use std::{net::IpAddr, time::{Duration, Instant}};
+use axum::{body::Body, http::{Request, StatusCode}};
+use tower::ServiceExt;
+use paxos_from_scratch::{
+    ratelimit::{Limit, RateLimiter, RateLimits},
+    router,
+    sim::{Sim, SimConfig},
+    transport::NODE_ID_HEADER,
+};
+
+fn ip(last: u8) -> Option<IpAddr> {
+    Some(IpAddr::from([10, 0, 0, last]))
+}
+
+#[test]
+fn a_client_gets_its_burst_then_its_rate() {
+    let limiter = RateLimiter::default();
+    let limits = RateLimits { client: Limit { rate: 2.0, burst: 3.0 }, ..RateLimits::default() };
+    let now = Instant::now();
+
+    for _ in 0..3 {
+        assert_eq!(limiter.check(limits, ip(1), now), Ok(()));
+    }
+    assert_eq!(limiter.check(limits, ip(1), now), Err(Duration::from_millis(500)));
+    assert_eq!(limiter.check(limits, ip(2), now), Ok(()), "another client has a bucket of its own");
+    assert_eq!(limiter.check(limits, None, now), Ok(()), "an unknown client only counts globally");
+
+    assert_eq!(limiter.check(limits, ip(1), now + Duration::from_millis(500)), Ok(()));
+    assert!(limiter.check(limits, ip(1), now + Duration::from_millis(500)).is_err());
+}
+
+#[test]
+fn the_global_limit_applies_to_all_clients_together() {
+    let limiter = RateLimiter::default();
+    let limits = RateLimits { client: Limit { rate: 100.0, burst: 10.0 }, global: Limit { rate: 1.0, burst: 2.0 } };
+    let now = Instant::now();
+
+    assert_eq!(limiter.check(limits, ip(1), now), Ok(()));
+    assert_eq!(limiter.check(limits, ip(2), now), Ok(()));
+    assert_eq!(limiter.check(limits, ip(3), now), Err(Duration::from_secs(1)));
+    assert_eq!(limiter.check(limits, None, now), Err(Duration::from_secs(1)));
+    assert_eq!(limiter.check(limits, ip(3), now + Duration::from_secs(1)), Ok(()));
+}
+
+#[test]
+fn no_rate_means_no_limit() {
+    let limiter = RateLimiter::default();
+    let now = Instant::now();
+    for _ in 0..1000 {
+        assert_eq!(limiter.check(RateLimits::default(), ip(1), now), Ok(()));
+    }
+}
+
+#[test]
+fn each_peer_has_a_bucket_for_consensus_messages() {
+    let limiter = RateLimiter::default();
+    let limit = Limit { rate: 10.0, burst: 2.0 };
+    let now = Instant::now();
+
+    assert_eq!(limiter.check_peer(limit, Some(2), now), Ok(()));
+    assert_eq!(limiter.check_peer(limit, Some(2), now), Ok(()));
+    assert_eq!(limiter.check_peer(limit, Some(2), now), Err(Duration::from_millis(100)));
+    assert_eq!(limiter.check_peer(limit, Some(3), now), Ok(()), "another peer has a bucket of its own");
+    assert_eq!(limiter.check_peer(limit, Some(2), now + Duration::from_millis(100)), Ok(()));
+    assert_eq!(limiter.throttled_peers(), [(Some(2), 1), (Some(3), 0)]);
+
+    assert_eq!(limiter.check_peer(Limit::default(), Some(2), now), Ok(()), "no rate means no limit");
+}
+
+#[tokio::test]
+async fn a_throttled_peer_gets_a_nack_and_shows_in_the_metrics() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    sim.node(2).settings.write().unwrap().peer_rate_limit = Limit { rate: 0.001, burst: 1.0 };
+
+    // Node 3 takes node 1's first prepare and NACKs the rest, and the other
+    // two still make a majority.
+    for key in ["a", "b"] {
+        let reply = sim.put(0, key, "1").await;
+        assert!(!reply.is_error(), "{}", reply.body);
+    }
+
+    let metrics = sim.get(2, "/metrics").await.body;
+    let line = metrics.lines().find(|line| line.starts_with("paxos_peer_throttled{peer=\"1\"}")).unwrap_or_else(|| panic!("{}", metrics));
+    assert!(line.split(' ').nth(1).unwrap().parse::<u64>().unwrap() >= 2, "{}", line);
+    assert!(!metrics.contains("paxos_peer_throttled{peer=\"2\"}"), "{}", metrics);
+}
+
+#[tokio::test]
+async fn ids_of_no_member_share_a_bucket() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    sim.node(2).settings.write().unwrap().peer_rate_limit = Limit { rate: 0.001, burst: 1.0 };
+
+    let mut statuses = Vec::new();
+    for id in [98, 99] {
+        let request = Request::post("/handle-prepare").header(NODE_ID_HEADER, id.to_string()).header("content-type", "application/json").body(Body::from("{}")).unwrap();
+        statuses.push(router(sim.node(2).clone()).oneshot(request).await.unwrap().status());
+    }
+    assert_ne!(statuses[0], StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(statuses[1], StatusCode::TOO_MANY_REQUESTS, "node 99 got a bucket of its own");
+
+    let metrics = sim.get(2, "/metrics").await.body;
+    assert!(metrics.contains("paxos_peer_throttled{peer=\"unknown\"} 1"), "{}", metrics);
+    assert!(!metrics.contains("peer=\"99\""), "{}", metrics);
+}
+
+#[test]
+fn a_crowd_of_clients_is_forgotten_least_recent_first() {
+    let limiter = RateLimiter::default();
+    let limits = RateLimits { client: Limit { rate: 0.001, burst: 1.0 }, ..RateLimits::default() };
+    let start = Instant::now();
+
+    // Every client empties its bucket, so none is full again when the table
+    // fills up and the ones seen first have to go.
+    for n in 0..10_000u32 {
+        let client = Some(IpAddr::from((0x0a00_0000 + n).to_be_bytes()));
+        assert_eq!(limiter.check(limits, client, start + Duration::from_micros(n as u64)), Ok(()));
+    }
+    let later = start + Duration::from_secs(1);
+    assert_eq!(limiter.check(limits, Some(IpAddr::from([10, 1, 0, 0])), later), Ok(()), "a new client after the sweep");
+    assert_eq!(limiter.check(limits, Some(IpAddr::from([10, 0, 0, 0])), later), Ok(()), "the first client was forgotten");
+    assert!(limiter.check(limits, Some(IpAddr::from([10, 0, 39, 15])), later).is_err(), "the last client is still limited");
+}
@@ -0,0 +1,131 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    chain::{self, Link},
+    consistency::CheckReport,
+    ed25519::Keypair,
+    events::{EventsPage, Transition},
+    kv::Command,
+    repair::RepairRequest,
+    sim::{self, Sim, SimConfig},
+    transport::post_json,
+};
+
+/// A cluster whose nodes sign, and know each other's keys, that wrote `a`
+/// and `b`, where node 3 learned `a` as something else, behind the
+/// protocol's back.
+async fn diverged(seed: u64) -> Result<Sim, String> {
+    let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+    for index in 0..sim.size() {
+        let node = sim.node(index);
+        node.keys.set_own(node.node.id, Keypair::from_seed(&[node.node.id as u8; 32]));
+        for peer in 1..=sim.size() as u64 {
+            node.keys.trust(peer, Keypair::from_seed(&[peer as u8; 32]).public());
+        }
+    }
+    for key in ["a", "b"] {
+        if sim.put(0, key, "1").await.is_error() {
+            return Err(format!("the write of {} failed", key));
+        }
+    }
+    sim.settle().await;
+
+    let forged = Command::Put { key: String::from("a"), value: String::from("forged") }.encode();
+    sim.node(2).ledger.write().await.insert(1, forged.clone());
+    sim.node(2).kv.lock().await.apply(&forged);
+    Ok(sim)
+}
+
+#[test]
+fn the_check_repairs_a_node_from_the_quorum() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = diverged(seed).await?;
+
+        let check = sim.get(0, "/admin/consistency-check").await;
+        let report: CheckReport = check.json()?;
+        if check.status != StatusCode::CONFLICT || report.conflicts.len() != 1 || report.conflicts[0].repaired != [3] {
+            return Err(format!("expected node 3 repaired, got {} {:?}", check.status, report.conflicts));
+        }
+
+        if sim.node(2).ledger.get(1) != sim.node(0).ledger.get(1) {
+            return Err(String::from("node 3 kept the forged value"));
+        }
+        if sim.node(2).kv.lock().await.get("a").map(String::as_str) != Some("1") {
+            return Err(String::from("node 3's KV store kept the forged value"));
+        }
+        if sim.node(2).ledger.chained() != 2 {
+            return Err(String::from("node 3 didn't chain the repaired instance again"));
+        }
+
+        let check = sim.get(1, "/admin/consistency-check").await;
+        if check.status != StatusCode::OK {
+            return Err(format!("the cluster still diverges after the repair: {}", check.body));
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn a_node_records_what_it_was_repaired_from() {
+    let sim = diverged(0).await.unwrap();
+    // Node 3 checks itself, and repairs its own ledger.
+    let check = sim.get(2, "/admin/consistency-check").await;
+    assert_eq!(check.status, StatusCode::CONFLICT);
+
+    let page: EventsPage = sim.get(2, "/events").await.json().unwrap();
+    let repaired = page.events.iter().find_map(|event| match &event.transition {
+        Transition::Repaired { instance, was, .. } => Some((*instance, was.clone())),
+        _ => None,
+    });
+    let (instance, was) = repaired.expect("no repaired event");
+    assert_eq!(instance, 1);
+    assert!(was.contains("forged"), "{}", was);
+}
+
+#[tokio::test]
+async fn without_a_quorum_nothing_is_repaired() {
+    let sim = diverged(0).await.unwrap();
+    let other = Command::Put { key: String::from("a"), value: String::from("other") }.encode();
+    sim.node(1).ledger.write().await.insert(1, other);
+
+    let report: CheckReport = sim.get(0, "/admin/consistency-check").await.json().unwrap();
+    assert_eq!(report.conflicts[0].repaired, Vec::<u64>::new());
+    assert!(sim.node(2).ledger.get(1).unwrap().contains("forged"));
+}
+
+#[tokio::test]
+async fn a_repair_is_chained_with_what_it_replaced() {
+    let sim = diverged(0).await.unwrap();
+    let forged = sim.node(2).ledger.get(1).unwrap();
+    let hash = Link::of(sim.node(2), 1).unwrap().hash;
+    assert_eq!(sim.get(0, "/admin/consistency-check").await.status, StatusCode::CONFLICT);
+
+    let link = Link::of(sim.node(2), 1).unwrap();
+    assert_eq!(link.hash, Link::of(sim.node(0), 1).unwrap().hash);
+    assert_eq!(link.rewrites.len(), 1);
+    assert_eq!(link.rewrites[0].was, chain::value_hash(&forged));
+    assert_eq!(link.rewrites[0].hash, hash);
+    assert_eq!(link.rewrites[0].by, 1);
+}
+
+#[tokio::test]
+async fn a_node_only_takes_a_repair_a_quorum_learned() {
+    let sim = diverged(0).await.unwrap();
+    let other = Command::Put { key: String::from("a"), value: String::from("other") }.encode();
+    let request = RepairRequest { instance: 1, value: other };
+    let reply = post_json(sim.node(0).transport.as_ref(), sim.node(1).node.addr, "/admin/repair", &request).await.unwrap();
+    assert_eq!(reply.status, StatusCode::CONFLICT, "{}", reply.body);
+    assert_eq!(sim.node(1).ledger.get(1), sim.node(0).ledger.get(1));
+}
+
+#[tokio::test]
+async fn a_node_without_a_key_takes_no_repair() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    assert!(!sim.put(0, "a", "1").await.is_error());
+    sim.settle().await;
+    let forged = Command::Put { key: String::from("a"), value: String::from("forged") }.encode();
+    sim.node(2).ledger.write().await.insert(1, forged);
+
+    let report: CheckReport = sim.get(0, "/admin/consistency-check").await.json().unwrap();
+    assert_eq!(report.conflicts[0].repaired, Vec::<u64>::new());
+    assert!(sim.node(2).ledger.get(1).unwrap().contains("forged"));
+}
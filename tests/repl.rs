@@ -0,0 +1,65 @@
+use std::{net::SocketAddr, time::Duration};
+use tokio::net::TcpListener;
+use paxos_from_scratch::{
+    router,
+    client::Client,
+    repl::{self, Session, Step},
+    sim::{Sim, SimConfig},
+};
+
+/// Serves the first node of `sim` over HTTP.
+async fn serve(sim: &Sim) -> Client {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = router(sim.node(0).clone()).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    Client::new(&addr.to_string())
+}
+
+fn printed(step: Result<Step, String>) -> String {
+    match step {
+        Ok(Step::Print(text)) => text,
+        other => panic!("expected something printed, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn a_session_writes_reads_and_watches_keys() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    let mut session = Session::new(serve(&sim).await);
+    session.watch_timeout = Duration::from_millis(100);
+
+    assert_eq!(printed(session.eval("put greeting hello world").await), "OK");
+    assert_eq!(printed(session.eval("get greeting").await), "hello world");
+    assert_eq!(printed(session.eval("get missing").await), "Key missing not found");
+
+    // The first watch starts from now, the next one from where it stopped.
+    assert!(printed(session.eval("watch greet").await).starts_with("No changes up to instance"));
+    assert_eq!(printed(session.eval("delete greeting").await), "OK");
+    let changes = printed(session.eval("watch greet").await);
+    assert!(changes.contains("greeting: hello world -> (none)"), "{}", changes);
+
+    let members = printed(session.eval("members").await);
+    assert_eq!(members.lines().count(), 3, "{}", members);
+    assert!(members.lines().next().unwrap().contains("(this node)"), "{}", members);
+    assert_eq!(session.eval("exit").await, Ok(Step::Exit));
+}
+
+#[tokio::test]
+async fn a_session_explains_what_it_cannot_run() {
+    let mut session = Session::new(Client::new("localhost:1"));
+    assert_eq!(session.eval("put onlykey").await, Err(String::from("Usage: put <key> <value>")));
+    assert_eq!(session.eval("get").await, Err(String::from("Usage: get <key>")));
+    assert_eq!(session.eval("frobnicate").await, Err(String::from("No command frobnicate; try help")));
+    assert!(session.eval("get x").await.unwrap_err().starts_with("Failed to reach http://localhost:1"));
+
+    assert_eq!(printed(session.eval("node localhost:2").await), "Talking to http://localhost:2");
+    assert_eq!(session.client.node(), "http://localhost:2");
+}
+
+#[test]
+fn tab_completes_command_names() {
+    assert_eq!(repl::complete("le", 2), (0, vec![String::from("leader")]));
+    assert_eq!(repl::complete("p", 1), (0, vec![String::from("put"), String::from("propose")]));
+    assert_eq!(repl::complete("get le", 6), (6, Vec::<String>::new()));
+}
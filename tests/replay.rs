@@ -0,0 +1,43 @@
+use paxos_from_scratch::{
+    Ballot, ProposalId,
+    trace::{self, Entry, Step},
+    wire::{Accepted, Promise},
+};
+
+fn ballot(round: u64, value: Option<&str>) -> Ballot {
+    Ballot { instance: 1, id: ProposalId { round, node_id: 1 }, value: value.map(str::to_string) }
+}
+
+fn trace() -> Vec<Entry> {
+    let promise = Promise::granted(None);
+    let accepted = Accepted::granted(ballot(1, Some("a")), None);
+    let steps = [
+        Step::Prepare { ballot: ballot(1, None), reply: promise },
+        Step::Accept { ballot: ballot(1, Some("a")), reply: accepted },
+        Step::Learn { ballot: ballot(1, Some("a")) },
+    ];
+
+    steps.into_iter()
+        .enumerate()
+        .map(|(seq, step)| Entry { node: 1, seq: seq as u64, step })
+        .collect()
+}
+
+#[tokio::test]
+async fn replaying_a_trace_rebuilds_the_state() {
+    let summary = trace::replay(&trace()).await.unwrap();
+    assert_eq!(summary.steps, 3);
+    assert_eq!(summary.snapshot.unwrap().ledger.get(&1).map(String::as_str), Some("a"));
+}
+
+#[tokio::test]
+async fn replay_stops_where_the_trace_diverges() {
+    let mut entries = trace();
+
+    // An acceptor that already promised round 1 can't promise it again.
+    let Step::Prepare { ballot, reply } = entries[0].step.clone() else { unreachable!() };
+    entries.insert(1, Entry { node: 1, seq: 1, step: Step::Prepare { ballot, reply } });
+
+    let error = trace::replay(&entries).await.unwrap_err();
+    assert!(error.starts_with("step 1:"), "{}", error);
+}
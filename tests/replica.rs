@@ -0,0 +1,84 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    Ballot, ProposalId,
+    replica,
+    sim::{self, Sim, SimConfig},
+};
+
+/// Three voters and a learner, node 3.
+fn cluster(seed: u64) -> Sim {
+    Sim::new(seed, SimConfig { nodes: 4, learners: 1, ..SimConfig::default() })
+}
+
+#[test]
+fn a_learner_reads_what_the_voters_chose_without_a_vote() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed);
+        for i in 0..5 {
+            let reply = sim.put(0, &format!("k{}", i), &format!("v{}", i)).await;
+            if reply.is_error() {
+                return Err(format!("write {} failed: {}", i, reply.body));
+            }
+        }
+        sim.settle().await;
+        sim.check_agreement().await?;
+
+        let learner = sim.node(3);
+        if !learner.acceptor.lock().await.slots.is_empty() {
+            return Err(String::from("the learner accepted a value"));
+        }
+        if sim.node(0).certificates.get(1).map(|certificate| certificate.voters) != Some(3) {
+            return Err(String::from("the learner counted towards the quorum"));
+        }
+        for i in 0..5 {
+            let reply = sim.get(3, &format!("/kv/k{}", i)).await;
+            if reply.body != format!("v{}", i) {
+                return Err(format!("the learner read {:?} for k{}", reply.body, i));
+            }
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn a_learner_turns_away_prepares_and_accepts() {
+    let sim = cluster(0);
+    let ballot = Ballot { instance: 1, id: ProposalId { round: 1, node_id: 1 }, value: None };
+    let body = serde_json::to_string(&ballot).unwrap();
+
+    let reply = sim.request(3, "/handle-prepare", &body).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN, "{}", reply.body);
+    assert_eq!(sim.request(0, "/handle-prepare", &body).await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn a_write_to_a_learner_is_proposed_by_a_voter() {
+    let sim = cluster(0);
+    let reply = sim.put(3, "x", "1").await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    sim.settle().await;
+
+    assert_eq!(sim.node(3).ledger.len(), 1);
+    assert!(sim.node(3).certificates.is_empty(), "the learner proposed it itself");
+    assert_eq!(sim.get(0, "/kv/x").await.body, "1");
+}
+
+#[tokio::test]
+async fn a_learner_that_missed_the_learns_catches_up_from_a_voter() {
+    let sim = cluster(0);
+    sim.partition(&[&[0, 1, 2]]);
+    for i in 0..3 {
+        let reply = sim.put(0, &format!("k{}", i), "v").await;
+        assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    }
+    sim.settle().await;
+    let learner = sim.node(3);
+    assert!(learner.ledger.is_empty());
+
+    sim.heal();
+    assert_eq!(replica::catch_up(learner).await, 3);
+    assert_eq!(replica::catch_up(learner).await, 0, "nothing is left to fetch");
+    sim.settle().await;
+    assert_eq!(learner.ledger.to_map(), sim.node(0).ledger.to_map());
+    assert_eq!(sim.get(3, "/kv/k2").await.body, "v");
+}
@@ -0,0 +1,102 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+use paxos_from_scratch::{
+    kv::Command,
+    schema::{self, Schema},
+    sim,
+};
+
+mod common;
+use common::{cluster, send};
+
+#[test]
+fn a_value_is_checked_against_the_schema() {
+    let user = json!({
+        "type": "object",
+        "title": "A user",
+        "required": ["name"],
+        "properties": {
+            "name": { "type": "string", "minLength": 1 },
+            "age": { "type": "integer", "minimum": 0 },
+            "tags": { "type": "array", "items": { "enum": ["admin", "guest"] }, "maxItems": 2 }
+        },
+        "additionalProperties": false
+    });
+    assert_eq!(schema::check(&user), Ok(()));
+
+    assert_eq!(schema::validate(&user, &json!({ "name": "ada", "age": 36, "tags": ["admin"] })), Ok(()));
+    assert_eq!(schema::validate(&user, &json!({ "age": 36 })), Err(String::from("the value lacks name")));
+    assert_eq!(schema::validate(&user, &json!({ "name": "ada", "age": 3.5 })), Err(String::from("/age is number, not integer")));
+    assert_eq!(schema::validate(&user, &json!({ "name": "ada", "tags": ["root"] })), Err(String::from("/tags/0 isn't one of the values allowed")));
+    assert_eq!(schema::validate(&user, &json!({ "name": "ada", "email": "a@b" })), Err(String::from("/email isn't allowed at all")));
+    assert_eq!(schema::validate(&user, &json!("ada")), Err(String::from("the value is string, not object")));
+}
+
+#[test]
+fn a_schema_with_keywords_it_cant_check_is_refused() {
+    assert!(schema::check(&json!({ "type": "string", "pattern": "^a" })).is_err());
+    assert!(schema::check(&json!({ "type": "text" })).is_err());
+    assert!(schema::check(&json!({ "properties": { "a": { "minLength": -1 } } })).is_err());
+    assert!(schema::check(&json!(42)).is_err());
+    assert_eq!(schema::check(&json!(true)), Ok(()));
+}
+
+fn register(namespace: Option<&str>, prefix: &str) -> String {
+    let schema = json!({ "type": "object", "required": ["name"] });
+    serde_json::to_string(&Schema { namespace: namespace.map(String::from), prefix: prefix.to_string(), schema }).unwrap()
+}
+
+#[test]
+fn a_write_that_breaks_the_schema_never_enters_consensus() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, 3);
+        if sim.request(0, "/admin/schemas", &register(None, "users.")).await.is_error() {
+            return Err(String::from("the schema wasn't registered"));
+        }
+        sim.settle().await;
+        let learned = sim.node(1).ledger.len();
+
+        let refused = sim.put(1, "users.ada", "{\"age\": 36}").await;
+        if refused.status != StatusCode::UNPROCESSABLE_ENTITY {
+            return Err(format!("expected a 422, got {} {}", refused.status, refused.body));
+        }
+        let raw = Command::Put { key: String::from("users.ada"), value: String::from("not json") }.encode();
+        if sim.propose(1, &raw).await.status != StatusCode::UNPROCESSABLE_ENTITY {
+            return Err(String::from("a raw proposal got around the schema"));
+        }
+        if sim.node(1).ledger.len() != learned {
+            return Err(String::from("a refused value was proposed"));
+        }
+
+        if sim.put(1, "users.ada", "{\"name\": \"ada\"}").await.is_error() || sim.put(1, "groups.x", "anything").await.is_error() {
+            return Err(String::from("a valid write was refused"));
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn a_namespace_has_schemas_of_its_own() {
+    let sim = cluster(0, 3);
+    assert!(!sim.request(0, "/admin/namespaces", "{\"name\": \"app\"}").await.is_error());
+    assert!(!sim.request(0, "/admin/schemas", &register(Some("app"), "")).await.is_error());
+
+    assert_eq!(send(sim.node(0), "PUT", "/ns/app/kv/k", "[]").await.0, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(send(sim.node(0), "PUT", "/ns/app/kv/k", "{\"name\": 1}").await.0, StatusCode::OK);
+    assert_eq!(send(sim.node(0), "PUT", "/kv/k", "[]").await.0, StatusCode::OK, "the default namespace has no schema");
+
+    let schemas: Vec<Schema> = sim.get(0, "/admin/schemas").await.json().unwrap();
+    assert_eq!(schemas.len(), 1);
+    assert_eq!(send(sim.node(0), "DELETE", "/admin/schemas?namespace=app&prefix=", "").await.0, StatusCode::OK);
+    assert_eq!(send(sim.node(0), "PUT", "/ns/app/kv/k", "[]").await.0, StatusCode::OK);
+    assert_eq!(send(sim.node(0), "DELETE", "/admin/schemas?namespace=app&prefix=", "").await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn schemas_are_reserved_keys() {
+    let sim = cluster(0, 3);
+    let planted = Command::Put { key: schema::key(None, "users."), value: String::from("true") }.encode();
+    assert_eq!(sim.propose(0, &planted).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(sim.request(0, "/admin/schemas", "{\"prefix\": \"a\", \"schema\": {\"format\": \"email\"}}").await.status, StatusCode::BAD_REQUEST);
+}
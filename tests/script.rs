@@ -0,0 +1,64 @@
+#![cfg(feature = "scripting")]
+
+use axum::http::StatusCode;
+
+use paxos_from_scratch::{
+    AppState,
+    namespace::Namespace,
+};
+
+mod common;
+use common::{cluster, send};
+
+async fn create(state: &AppState, name: &str, script: &str) -> StatusCode {
+    let namespace = Namespace { name: name.to_string(), retention_ms: None, script: Some(script.to_string()) };
+    send(state, "POST", "/admin/namespaces", &serde_json::to_string(&namespace).unwrap()).await.0
+}
+
+const POLICY: &str = r#"
+    if op == "delete" {
+        if key == "keep" { throw "keep stays" }
+    } else if !key.starts_with("user.") {
+        false
+    } else {
+        value.to_upper()
+    }
+"#;
+
+#[tokio::test]
+async fn a_script_checks_and_rewrites_writes() {
+    let sim = cluster(0, 1);
+    let state = sim.node(0).clone();
+    assert_eq!(create(&state, "users", POLICY).await, StatusCode::OK);
+
+    assert_eq!(send(&state, "PUT", "/ns/users/kv/user.ada", "lovelace").await.0, StatusCode::OK);
+    assert_eq!(send(&state, "GET", "/ns/users/kv/user.ada", "").await, (StatusCode::OK, String::from("LOVELACE")));
+
+    let (status, body) = send(&state, "PUT", "/ns/users/kv/ada", "lovelace").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(send(&state, "GET", "/ns/users/kv/ada", "").await.0, StatusCode::NOT_FOUND);
+
+    assert_eq!(send(&state, "PUT", "/ns/users/kv/keep", "").await.0, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, body) = send(&state, "DELETE", "/ns/users/kv/keep", "").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body.contains("keep stays"), "{}", body);
+    assert_eq!(send(&state, "DELETE", "/ns/users/kv/user.ada", "").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn a_script_that_runs_too_long_turns_the_write_away() {
+    let sim = cluster(0, 1);
+    let state = sim.node(0).clone();
+    assert_eq!(create(&state, "spin", "loop {}").await, StatusCode::OK);
+
+    assert_eq!(send(&state, "PUT", "/ns/spin/kv/k", "v").await.0, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(state.ledger.len(), 1, "only the namespace was proposed");
+}
+
+#[tokio::test]
+async fn a_script_that_doesnt_compile_is_refused() {
+    let sim = cluster(0, 1);
+    let state = sim.node(0).clone();
+    assert_eq!(create(&state, "broken", "if {").await, StatusCode::BAD_REQUEST);
+    assert_eq!(send(&state, "PUT", "/ns/broken/kv/k", "v").await.0, StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,122 @@
+use std::{path::PathBuf, sync::Arc, time::{Duration, Instant}};
+use axum::{body::Body, http::{Request, StatusCode}};
+use tower::ServiceExt;
+
+use paxos_from_scratch::{
+    AppState, router,
+    secrets::{ClusterToken, TOKEN_HEADER},
+    step::Stepper,
+    sim::{Sim, SimConfig},
+};
+
+fn token_file(name: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("paxos-secrets-{}-{}", name, std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    path
+}
+
+async fn status(state: &AppState, path: &str, token: Option<&str>) -> StatusCode {
+    let mut request = Request::builder().method("POST").uri(path).header("content-type", "application/json");
+    if let Some(token) = token {
+        request = request.header(TOKEN_HEADER, token);
+    }
+    router(state.clone()).oneshot(request.body(Body::from("1")).unwrap()).await.unwrap().status()
+}
+
+#[test]
+fn a_rotated_token_is_taken_until_the_grace_window_ends() {
+    let path = token_file("rotate", "# the cluster\nold\n");
+    let token = ClusterToken::load(&path).unwrap();
+    let start = Instant::now();
+    assert_eq!(token.current().as_deref(), Some("old"));
+    assert!(!token.reload(Duration::from_secs(60), start).unwrap(), "nothing changed");
+
+    // Both are taken once the new one is listed, and the old one still sent.
+    std::fs::write(&path, "old\nnew\n").unwrap();
+    assert!(token.reload(Duration::from_secs(60), start).unwrap());
+    assert_eq!(token.current().as_deref(), Some("old"));
+    assert!(token.accepts("old", start) && token.accepts("new", start));
+
+    std::fs::write(&path, "new\n").unwrap();
+    assert!(token.reload(Duration::from_secs(60), start).unwrap());
+    assert_eq!(token.current().as_deref(), Some("new"));
+    assert!(token.accepts("old", start + Duration::from_secs(59)), "the old token is still in its grace window");
+    assert!(!token.accepts("old", start + Duration::from_secs(60)));
+    assert!(!token.accepts("other", start));
+}
+
+#[test]
+fn a_file_that_cant_be_read_keeps_the_tokens_there_were() {
+    let path = token_file("broken", "old\n");
+    let token = ClusterToken::load(&path).unwrap();
+
+    std::fs::write(&path, "\n# nothing yet\n").unwrap();
+    assert!(token.reload(Duration::ZERO, Instant::now()).is_err());
+    std::fs::remove_file(&path).unwrap();
+    assert!(token.reload(Duration::ZERO, Instant::now()).is_err());
+    assert!(token.accepts("old", Instant::now()));
+}
+
+#[tokio::test]
+async fn peer_endpoints_need_the_token_and_client_ones_dont() {
+    let sim = Sim::new(0, SimConfig { nodes: 1, ..SimConfig::default() });
+    let mut state = sim.node(0).clone();
+    state.cluster_token = Arc::new(ClusterToken::load(&token_file("routes", "secret\n")).unwrap());
+
+    for path in ["/handle-learn", "/forward", "/ping", "/admin/ledger-digest"] {
+        assert_eq!(status(&state, path, None).await, StatusCode::UNAUTHORIZED, "{}", path);
+        assert_eq!(status(&state, path, Some("wrong")).await, StatusCode::UNAUTHORIZED, "{}", path);
+        assert_ne!(status(&state, path, Some("secret")).await, StatusCode::UNAUTHORIZED, "{}", path);
+    }
+    assert_eq!(status(&state, "/prepare", None).await, StatusCode::OK);
+}
+
+async fn admin(state: &AppState, method: &str, path: &str, bearer: Option<&str>) -> StatusCode {
+    let mut request = Request::builder().method(method).uri(path).header("content-type", "application/json");
+    if let Some(token) = bearer {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    router(state.clone()).oneshot(request.body(Body::from("{}")).unwrap()).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn admin_endpoints_need_an_admin_token() {
+    let sim = Sim::new(0, SimConfig { nodes: 1, ..SimConfig::default() });
+    let mut state = sim.node(0).clone();
+    state.admin_token = Arc::new(ClusterToken::load(&token_file("admin", "ops\n")).unwrap());
+    state.cluster_token = Arc::new(ClusterToken::load(&token_file("admin-cluster", "secret\n")).unwrap());
+
+    for (method, path) in [("GET", "/admin/groups"), ("GET", "/admin/consistency-check"), ("POST", "/admin/clock-skew"), ("GET", "/admin/shipping"), ("GET", "/ledger")] {
+        assert_eq!(admin(&state, method, path, None).await, StatusCode::UNAUTHORIZED, "{}", path);
+        assert_eq!(admin(&state, method, path, Some("secret")).await, StatusCode::UNAUTHORIZED, "{} takes the cluster token", path);
+        assert_ne!(admin(&state, method, path, Some("ops")).await, StatusCode::UNAUTHORIZED, "{}", path);
+    }
+    assert_ne!(status(&state, "/admin/ledger-digest", Some("secret")).await, StatusCode::UNAUTHORIZED, "peers still reach theirs");
+}
+
+#[tokio::test]
+async fn debugging_endpoints_need_the_flag() {
+    let sim = Sim::new(0, SimConfig { nodes: 1, ..SimConfig::default() });
+    let mut state = sim.node(0).clone();
+    state.debug_admin = false;
+
+    for (method, path) in [("POST", "/admin/clock-skew"), ("POST", "/admin/faults"), ("POST", "/admin/partition"), ("POST", "/admin/heal")] {
+        assert_eq!(admin(&state, method, path, None).await, StatusCode::FORBIDDEN, "{}", path);
+    }
+    assert_eq!(admin(&state, "GET", "/admin/groups", None).await, StatusCode::OK);
+    assert_eq!(admin(&state, "POST", "/admin/pause", None).await, StatusCode::OK, "pausing isn't debugging");
+    assert!(state.paused.load(std::sync::atomic::Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn stepping_needs_a_stepped_node() {
+    let sim = Sim::new(0, SimConfig { nodes: 1, ..SimConfig::default() });
+    let mut state = sim.node(0).clone();
+    assert!(state.debug_admin);
+    assert_eq!(admin(&state, "GET", "/admin/step", None).await, StatusCode::FORBIDDEN, "--debug-admin doesn't open it");
+
+    state.debug_admin = false;
+    state.stepper = Some(Arc::new(Stepper::default()));
+    assert_eq!(admin(&state, "GET", "/admin/step", None).await, StatusCode::OK);
+    assert_eq!(admin(&state, "POST", "/admin/faults", None).await, StatusCode::FORBIDDEN, "--step doesn't open the rest");
+}
@@ -0,0 +1,98 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    ed25519::Keypair,
+    kv::Command,
+    groups::{self, GroupStatus},
+    shards,
+    sim::{self, Sim, SimConfig},
+};
+
+fn sharded(seed: u64, count: usize) -> Sim {
+    Sim::new(seed, SimConfig { nodes: 3, shards: count, ..SimConfig::default() })
+}
+
+#[test]
+fn keys_hash_to_every_shard() {
+    assert_eq!(shards::of("anything", 1), 0);
+    let spread: std::collections::BTreeSet<usize> = (0..64).map(|i| shards::of(&format!("k{}", i), 4)).collect();
+    assert_eq!(spread.len(), 4, "64 keys should land in every one of 4 groups");
+}
+
+#[test]
+fn each_group_chooses_only_its_own_keys() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = sharded(seed, 3);
+        for i in 0..12 {
+            let reply = sim.put(0, &format!("k{}", i), &format!("v{}", i)).await;
+            if reply.is_error() {
+                return Err(format!("write {} failed: {}", i, reply.body));
+            }
+        }
+        sim.settle().await;
+        sim.check_agreement().await?;
+
+        for index in 0..sim.size() {
+            let node = sim.node(index);
+            if !node.ledger.is_empty() {
+                return Err(format!("node {} chose keys in its own group", index));
+            }
+            for (shard, group) in node.groups.shards().enumerate() {
+                for instance in 1..=group.ledger.last().unwrap_or(0) {
+                    let Some(Command::Put { key, .. }) = group.ledger.get(instance).and_then(|value| Command::parse(&value)) else {
+                        return Err(format!("node {} has a gap at instance {} of group {}", index, instance, shard));
+                    };
+                    if shards::of(&key, 3) != shard {
+                        return Err(format!("node {} chose {} in group {}", index, key, shard));
+                    }
+                }
+                if group.ledger.to_map() != sim.node(0).groups.shard(shard).unwrap().ledger.to_map() {
+                    return Err(format!("node {} learned another log for group {}", index, shard));
+                }
+            }
+        }
+
+        for i in 0..12 {
+            let reply = sim.get(2, &format!("/kv/k{}", i)).await;
+            if reply.body != format!("v{}", i) {
+                return Err(format!("node 2 read {:?} for k{}", reply.body, i));
+            }
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn signed_messages_reach_the_group_they_were_signed_for() {
+    let sim = sharded(0, 2);
+    for index in 0..sim.size() {
+        let node = sim.node(index);
+        node.keys.set_own(node.node.id, Keypair::from_seed(&[node.node.id as u8; 32]));
+        for peer in 1..=sim.size() as u64 {
+            node.keys.trust(peer, Keypair::from_seed(&[peer as u8; 32]).public());
+        }
+    }
+
+    for key in ["a", "b", "c", "d"] {
+        let reply = sim.put(0, key, "1").await;
+        assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    }
+    sim.settle().await;
+
+    let status: Vec<GroupStatus> = sim.get(1, "/admin/groups").await.json().unwrap();
+    assert_eq!(status.iter().map(|group| group.id.as_str()).collect::<Vec<_>>(), vec!["shard-0", "shard-1"]);
+    assert_eq!(status.iter().map(|shard| shard.keys).sum::<usize>(), 4);
+    assert_eq!(status.iter().map(|shard| shard.instances).sum::<usize>(), 4);
+    let messages = sim.messages();
+    assert!(messages.keys().all(|path| groups::split_path(path).0.is_some()), "{:?}", messages);
+}
+
+#[test]
+fn a_data_directory_keeps_the_count_it_was_split_with() {
+    let dir = std::env::temp_dir().join(format!("paxos-shards-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    shards::check_count(&dir, 4).unwrap();
+    shards::check_count(&dir, 4).unwrap();
+    assert!(shards::check_count(&dir, 2).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
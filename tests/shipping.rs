@@ -0,0 +1,82 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    shipping::Progress,
+    sim::{self, Sim, SimConfig},
+};
+use std::collections::BTreeMap;
+
+fn cluster(seed: u64) -> Sim {
+    let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+    for index in 0..sim.size() {
+        sim.node(index).settings.write().unwrap().log_shipping = true;
+    }
+    sim
+}
+
+#[test]
+fn decisions_are_shipped_to_every_follower() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed);
+        for key in ["a", "b", "c"] {
+            if sim.put(0, key, "1").await.is_error() {
+                return Err(format!("the write of {} failed", key));
+            }
+        }
+        sim.settle().await;
+        sim.check_agreement().await?;
+
+        let messages = sim.messages();
+        if messages.contains_key("/handle-learns") || !messages.contains_key("/handle-ship") {
+            return Err(format!("the decisions weren't shipped: {:?}", messages));
+        }
+        for index in 1..sim.size() {
+            if sim.node(index).ledger.chained() < 2 {
+                return Err(format!("node {} is behind", index + 1));
+            }
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn the_leader_keeps_each_followers_progress() {
+    let sim = cluster(0);
+    for key in ["a", "b", "c"] {
+        assert_eq!(sim.put(0, key, "1").await.status, StatusCode::OK);
+    }
+    sim.settle().await;
+
+    let progress: BTreeMap<u64, Progress> = sim.get(0, "/admin/shipping").await.json().unwrap();
+    let expected = Progress { next: 4, matched: 3 };
+    assert_eq!(progress, [(2, expected), (3, expected)].into_iter().collect());
+}
+
+#[tokio::test]
+async fn a_follower_that_missed_shipments_gets_them_with_the_next() {
+    let sim = cluster(0);
+    sim.partition(&[&[0, 1]]);
+    for key in ["a", "b", "c"] {
+        assert_eq!(sim.put(0, key, "1").await.status, StatusCode::OK);
+    }
+    sim.settle().await;
+    assert_eq!(sim.node(2).ledger.len(), 0);
+    assert_eq!(sim.node(0).shipper.progress()[&3].matched, 0);
+
+    sim.heal();
+    assert_eq!(sim.put(0, "d", "1").await.status, StatusCode::OK);
+    sim.settle().await;
+    assert_eq!(sim.node(2).ledger.chained(), 4);
+    assert_eq!(sim.node(0).shipper.progress()[&3], Progress { next: 5, matched: 4 });
+}
+
+#[tokio::test]
+async fn a_follower_on_an_older_protocol_still_gets_learns() {
+    let sim = cluster(0);
+    sim.node(0).versions.negotiate(3, Some(7)).unwrap();
+    assert_eq!(sim.put(0, "a", "1").await.status, StatusCode::OK);
+    sim.settle().await;
+
+    assert_eq!(sim.node(2).ledger.len(), 1);
+    assert!(sim.messages().contains_key("/handle-learns"), "{:?}", sim.messages());
+    assert!(!sim.node(0).shipper.progress().contains_key(&3));
+}
@@ -0,0 +1,132 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    Ballot, ProposalId,
+    ed25519::{self, Keypair},
+    hex,
+    signing::{self, Envelope, Keys},
+    sim::{self, Sim, SimConfig},
+};
+
+#[test]
+fn signatures_match_the_rfc_vectors() {
+    let vectors = [
+        (
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        ),
+        (
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        ),
+    ];
+
+    for (seed, public, message, signature) in vectors {
+        let keypair = Keypair::from_seed(&hex::decode(seed).unwrap());
+        assert_eq!(hex::encode(&keypair.public()), public);
+
+        let message: Vec<u8> = (0..message.len() / 2).map(|i| u8::from_str_radix(&message[2 * i..2 * i + 2], 16).unwrap()).collect();
+        let signed = keypair.sign(&message);
+        assert_eq!(hex::encode(&signed), signature);
+        assert!(ed25519::verify(&keypair.public(), &message, &signed));
+
+        let mut forged = signed;
+        forged[0] ^= 1;
+        assert!(!ed25519::verify(&keypair.public(), &message, &forged));
+        assert!(!ed25519::verify(&keypair.public(), b"something else", &signed));
+    }
+}
+
+fn keypair(n: u8) -> Keypair {
+    Keypair::from_seed(&[n; 32])
+}
+
+/// Every node signing, with every other node's key.
+fn signed_cluster(seed: u64) -> Sim {
+    let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+    for index in 0..sim.size() {
+        let node = sim.node(index);
+        node.keys.set_own(node.node.id, keypair(node.node.id as u8));
+        for peer in 1..=sim.size() as u64 {
+            node.keys.trust(peer, keypair(peer as u8).public());
+        }
+    }
+    sim
+}
+
+#[test]
+fn signed_rounds_are_decided_as_before() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = signed_cluster(seed);
+        let values: Vec<String> = (0..3).map(|i| format!("v{}", i)).collect();
+        for value in &values {
+            let reply = sim.propose(0, value).await;
+            if reply.is_error() {
+                return Err(format!("{} wasn't chosen: {}", value, reply.body));
+            }
+        }
+        sim.settle().await;
+        sim.check_agreement().await?;
+        sim.check_learned(&values).await
+    });
+}
+
+#[test]
+fn a_vote_not_signed_by_its_node_is_refused() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = signed_cluster(seed);
+        let ballot = Ballot { instance: 1, id: ProposalId { round: 9, node_id: 2 }, value: Some(String::from("forged")) };
+        let body = serde_json::to_string(&ballot).unwrap();
+
+        let rogue = keypair(99);
+        let sent = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_micros() as u64;
+        let signature = hex::encode(&rogue.sign(&signing::request_message("/handle-accept", sent, 0, &body)));
+        let forged = Envelope { from: 2, sent, nonce: 0, body: body.clone(), signature };
+        for attempt in [serde_json::to_string(&forged).unwrap(), body] {
+            let reply = sim.request(0, "/handle-accept", &attempt).await;
+            if reply.status != StatusCode::UNAUTHORIZED {
+                return Err(format!("a forged accept was answered with {}", reply.status));
+            }
+        }
+        let accepted = sim.node(0).acceptor.lock().await.slots.get(&1).cloned();
+        match accepted {
+            None => Ok(()),
+            Some(slot) => Err(format!("the forged accept got through: {:?}", slot)),
+        }
+    });
+}
+
+#[test]
+fn a_node_only_takes_the_keys_it_was_given() {
+    let keys = Keys::default();
+    keys.set_own(1, keypair(1));
+    let (given, other) = (hex::encode(&keypair(5).public()), hex::encode(&keypair(6).public()));
+    keys.trust(5, keypair(5).public());
+    keys.heard(5, Some(&given)).unwrap();
+    assert!(keys.heard(5, Some(&other)).is_err(), "another key for the same node must be refused");
+    assert!(keys.heard(6, Some(&other)).is_err(), "a key that wasn't given must be refused");
+    assert!(keys.heard(5, Some("not hex")).is_err());
+    assert_eq!(keys.of(6), None);
+}
+
+#[test]
+fn a_signed_request_is_taken_once_and_only_while_fresh() {
+    let (sender, receiver) = (Keys::default(), Keys::default());
+    sender.set_own(2, keypair(2));
+    receiver.trust(2, keypair(2).public());
+
+    let sealed = sender.seal_request("/handle-accept", String::from("{}")).unwrap();
+    assert!(receiver.check_request(&sealed, "/handle-accept"));
+    assert!(!receiver.check_request(&sealed, "/handle-accept"), "a replayed request was taken");
+    let next = sender.seal_request("/handle-accept", String::from("{}")).unwrap();
+    assert!(!receiver.check_request(&next, "/handle-learn"), "a request passed for another path");
+    assert!(receiver.check_request(&next, "/handle-accept"));
+
+    let sent = next.sent - 2 * signing::MAX_AGE.as_micros() as u64;
+    let message = signing::request_message("/handle-accept", sent, 7, "{}");
+    let stale = Envelope { sent, nonce: 7, signature: hex::encode(&keypair(2).sign(&message)), ..next };
+    assert!(!receiver.check_request(&stale, "/handle-accept"), "a stale request was taken");
+}
@@ -0,0 +1,339 @@
+use paxos_from_scratch::{
+    Value,
+    consistency::CheckReport,
+    events::{EventsPage, Transition},
+    rng::Rng,
+    sim::{self, Sim, SimConfig, DEFAULT_SEEDS},
+};
+
+/// Each client proposes `ops` distinct values against random nodes, retrying
+/// a few times on failure, and returns the values that were acknowledged.
+async fn run_clients(sim: &Sim, clients: u64, ops: u64) -> Vec<Value> {
+    let workers = (0..clients).map(|client| {
+        let sim = sim.clone();
+        async move {
+            let mut rng = Rng::new(sim.seed ^ (client + 1).wrapping_mul(0x5851_f42d_4c95_7f2d));
+            let mut acknowledged = Vec::new();
+
+            for op in 0..ops {
+                let value = format!("c{}-{}", client, op);
+                for _ in 0..8 {
+                    let node = rng.below(sim.size() as u64) as usize;
+                    if !sim.propose(node, &value).await.is_error() {
+                        acknowledged.push(value.clone());
+                        break;
+                    }
+                }
+            }
+
+            acknowledged
+        }
+    });
+
+    futures::future::join_all(workers).await.into_iter().flatten().collect()
+}
+
+#[test]
+fn agreement_with_message_loss_and_reordering() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, drop_rate: 0.1, max_delay: 8, ..SimConfig::default() });
+
+        let acknowledged = run_clients(&sim, 3, 5).await;
+
+        sim.check_agreement().await?;
+        sim.check_learned(&acknowledged).await
+    });
+}
+
+#[test]
+fn a_leader_that_prepared_ahead_only_sends_accepts() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+        for index in 0..sim.size() {
+            sim.node(index).settings.write().unwrap().prepare_ahead = 100;
+        }
+
+        let values: Vec<Value> = (0..5).map(|i| format!("v{}", i)).collect();
+        for value in &values {
+            if sim.propose(0, value).await.is_error() {
+                return Err(format!("{} wasn't chosen", value));
+            }
+        }
+        sim.settle().await;
+
+        let messages = sim.messages();
+        if messages.contains_key("/handle-prepare") || messages.get("/handle-prepare-range").copied() != Some(sim.size() as u64) {
+            return Err(format!("phase 1 didn't run once for all of them: {:?}", messages));
+        }
+        sim.check_agreement().await?;
+        sim.check_learned(&values).await
+    });
+}
+
+#[test]
+fn agreement_while_every_node_prepares_ahead() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, drop_rate: 0.1, max_delay: 8, ..SimConfig::default() });
+        for index in 0..sim.size() {
+            sim.node(index).settings.write().unwrap().prepare_ahead = 4;
+        }
+
+        let acknowledged = run_clients(&sim, 3, 5).await;
+
+        sim.check_agreement().await?;
+        sim.check_learned(&acknowledged).await
+    });
+}
+
+#[test]
+fn minority_cannot_decide_until_healed() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 5, ..SimConfig::default() });
+
+        sim.partition(&[&[0, 1], &[2, 3, 4]]);
+
+        if !sim.propose(0, "minority").await.is_error() {
+            return Err(String::from("minority side decided a value"));
+        }
+
+        if sim.propose(2, "majority").await.is_error() {
+            return Err(String::from("majority side failed to decide"));
+        }
+
+        sim.heal();
+
+        if sim.propose(0, "healed").await.is_error() {
+            return Err(String::from("healed node failed to decide"));
+        }
+
+        sim.check_agreement().await?;
+        sim.check_learned(&[String::from("majority"), String::from("healed")]).await
+    });
+}
+
+#[test]
+fn agreement_under_shifting_partitions() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 5, drop_rate: 0.05, max_delay: 8, ..SimConfig::default() });
+
+        let nemesis = {
+            let sim = sim.clone();
+            async move {
+                let mut rng = Rng::new(!sim.seed);
+                for _ in 0..10 {
+                    for _ in 0..rng.below(200) {
+                        tokio::task::yield_now().await;
+                    }
+
+                    let split = 1 + rng.below(sim.size() as u64 - 1) as usize;
+                    let mut order: Vec<usize> = (0..sim.size()).collect();
+                    for i in (1..order.len()).rev() {
+                        order.swap(i, rng.below(i as u64 + 1) as usize);
+                    }
+                    sim.partition(&[&order[..split], &order[split..]]);
+                }
+                sim.heal();
+            }
+        };
+
+        let (acknowledged, _) = futures::join!(run_clients(&sim, 4, 5), nemesis);
+
+        sim.check_agreement().await?;
+        sim.check_learned(&acknowledged).await
+    });
+}
+
+#[test]
+fn agreement_with_injected_faults() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+
+        let rule = r#"{"direction": "both", "drop": 10, "duplicate": 20}"#;
+        for node in 0..sim.size() {
+            if sim.request(node, "/admin/faults", rule).await.is_error() {
+                return Err(String::from("installing a fault rule failed"));
+            }
+        }
+
+        let acknowledged = run_clients(&sim, 3, 5).await;
+
+        sim.check_agreement().await?;
+        sim.check_learned(&acknowledged).await
+    });
+}
+
+#[test]
+fn admin_partition_splits_and_heals_the_cluster() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 5, ..SimConfig::default() });
+
+        if sim.request(0, "/admin/partition", r#"{"groups": [[1, 2], [3, 4, 5]]}"#).await.is_error() {
+            return Err(String::from("installing the partition failed"));
+        }
+
+        if !sim.propose(1, "minority").await.is_error() {
+            return Err(String::from("minority side decided a value"));
+        }
+
+        if sim.propose(4, "majority").await.is_error() {
+            return Err(String::from("majority side failed to decide"));
+        }
+
+        // Healing through a node on the other side must still reach everyone.
+        if sim.request(3, "/admin/heal", "").await.is_error() {
+            return Err(String::from("healing the partition failed"));
+        }
+
+        if sim.propose(1, "healed").await.is_error() {
+            return Err(String::from("healed node failed to decide"));
+        }
+
+        sim.check_agreement().await?;
+        sim.check_learned(&[String::from("majority"), String::from("healed")]).await
+    });
+}
+
+#[test]
+fn consistency_check_reports_divergent_instances() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig::default());
+        run_clients(&sim, 2, 3).await;
+
+        let healthy = sim.get(0, "/admin/consistency-check").await;
+        if healthy.is_error() {
+            return Err(format!("healthy cluster failed the check: {}", healthy.body));
+        }
+
+        // Nothing in the protocol can do this, so do it behind its back.
+        sim.node(2).ledger.write().await.insert(1, String::from("forged"));
+
+        let check = sim.get(1, "/admin/consistency-check").await;
+        let report: CheckReport = check.json().map_err(|e| format!("bad report: {}", e))?;
+        let instances: Vec<u64> = report.conflicts.iter().map(|conflict| conflict.instance).collect();
+
+        if check.status.as_u16() != 409 || instances != [1] {
+            return Err(format!("expected a conflict on instance 1, got {} {:?}", check.status, instances));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn events_follow_the_phases_of_a_round() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig::default());
+        if sim.propose(0, "v").await.is_error() {
+            return Err(String::from("proposal failed on a healthy cluster"));
+        }
+
+        let page: EventsPage = sim.get(0, "/events").await.json()?;
+        let kinds: Vec<&str> = page.events.iter()
+            .filter_map(|event| match event.transition {
+                Transition::PrepareSent { .. } => Some("prepare"),
+                Transition::ValueAdopted { own: true, .. } => Some("adopt"),
+                Transition::QuorumReached { .. } => Some("quorum"),
+                Transition::Learned { .. } => Some("learn"),
+                _ => None,
+            })
+            .collect();
+        if kinds != ["prepare", "adopt", "quorum", "learn"] {
+            return Err(format!("proposer recorded {:?}", kinds));
+        }
+
+        let since = page.events[1].seq;
+        let later: EventsPage = sim.get(0, &format!("/events?since={}", since)).await.json()?;
+        if later.events.first().map(|event| event.seq) != Some(since + 1) {
+            return Err(format!("events after {} started at {:?}", since, later.events.first()));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn a_draining_node_refuses_new_proposals() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig::default());
+        sim.node(0).shutdown.refuse_new();
+
+        let refused = sim.propose(0, "late").await;
+        if refused.status.as_u16() != 503 {
+            return Err(format!("draining node answered {} {}", refused.status, refused.body));
+        }
+
+        // Its acceptor still takes part in everyone else's rounds.
+        let accepted = sim.propose(1, "v").await;
+        if accepted.is_error() {
+            return Err(format!("proposal next to a draining node failed: {}", accepted.body));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn paused_nodes_neither_vote_nor_propose_but_keep_learning() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+
+        for node in [1, 2] {
+            if sim.request(node, "/admin/pause", "").await.is_error() {
+                return Err(String::from("pausing a node failed"));
+            }
+        }
+        if !sim.propose(1, "paused").await.is_error() {
+            return Err(String::from("a paused node proposed"));
+        }
+        if !sim.propose(0, "no quorum").await.is_error() {
+            return Err(String::from("a value was decided without a quorum"));
+        }
+
+        if sim.request(1, "/admin/resume", "").await.is_error() {
+            return Err(String::from("resuming a node failed"));
+        }
+        if sim.propose(0, "resumed").await.is_error() {
+            return Err(String::from("the resumed majority failed to decide"));
+        }
+
+        sim.check_agreement().await?;
+        sim.settle().await;
+        if sim.ledgers().await.iter().any(|ledger| !ledger.values().any(|value| value == "resumed")) {
+            return Err(String::from("a node missed the value, paused or not"));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn read_only_nodes_reject_or_forward_writes() {
+    sim::run(DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+
+        sim.request(0, "/admin/read-only", r#"{"mode": "reject"}"#).await;
+        if !sim.propose(0, "rejected").await.is_error() {
+            return Err(String::from("a read-only node took a write"));
+        }
+
+        sim.request(0, "/admin/read-only", r#"{"mode": "forward"}"#).await;
+        sim.request(1, "/admin/read-only", r#"{"mode": "forward"}"#).await;
+        if sim.propose(0, "forwarded").await.is_error() {
+            return Err(String::from("no peer took the forwarded write"));
+        }
+
+        sim.request(2, "/admin/read-only", r#"{"mode": "reject"}"#).await;
+        if !sim.propose(0, "nowhere").await.is_error() {
+            return Err(String::from("a write went through with every node read-only"));
+        }
+
+        sim.check_agreement().await?;
+        sim.check_learned(&[String::from("forwarded")]).await
+    });
+}
+
+#[test]
+fn a_cluster_needs_a_node_and_fits_any_number() {
+    assert!(SimConfig { nodes: 0, ..SimConfig::default() }.check().is_err());
+    assert!(SimConfig { nodes: 1, ..SimConfig::default() }.check().is_ok());
+
+    let sim = Sim::new(0, SimConfig { nodes: 300, ..SimConfig::default() });
+    let addrs: std::collections::HashSet<_> = (0..sim.size()).map(|i| sim.node(i).node.addr).collect();
+    assert_eq!(addrs.len(), 300);
+}
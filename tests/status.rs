@@ -0,0 +1,107 @@
+use axum::{body::Body, http::{Request, StatusCode}};
+use tower::ServiceExt;
+
+use paxos_from_scratch::{
+    ProposalId, router,
+    sim::{self, Sim, SimConfig},
+    status::{COMMIT_INDEX_HEADER, EPOCH_HEADER, NodeState, Status},
+};
+
+#[tokio::test]
+async fn status_shows_the_epoch_and_the_commit_index() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    let status: Status = sim.get(0, "/status").await.json().unwrap();
+    assert_eq!((status.epoch, status.commit_index, status.last_learned), (ProposalId::default(), 0, 0));
+
+    for key in ["a", "b", "c"] {
+        let reply = sim.put(0, key, "1").await;
+        assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    }
+    sim.settle().await;
+
+    let status: Status = sim.get(1, "/status").await.json().unwrap();
+    assert_eq!((status.node, status.commit_index, status.last_learned), (2, 3, 3));
+    assert_eq!(status.epoch.node_id, 1, "node 1 promised node 0's ballot");
+}
+
+#[tokio::test]
+async fn state_shows_what_the_node_knows() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    let state: NodeState = sim.get(1, "/state").await.json().unwrap();
+    assert_eq!((state.leader, state.applied_index, state.ledger_size), (None, 0, 0));
+
+    for key in ["a", "b"] {
+        assert!(!sim.put(0, key, "1").await.is_error());
+    }
+    sim.settle().await;
+    sim.node(1).applier.applied(2).await;
+
+    let reply = sim.get(1, "/state").await;
+    let state: NodeState = reply.json().unwrap_or_else(|e| panic!("{}: {}", e, reply.body));
+    assert_eq!(state.node.id, 2);
+    assert_eq!(state.members.iter().map(|member| (member.node.id, member.departed)).collect::<Vec<_>>(), [(1, false), (3, false)]);
+    assert_eq!(state.leader, Some(1), "node 0 took over with phase 1");
+    assert_eq!((state.status.commit_index, state.applied_index, state.ledger_size), (2, 2, 2));
+    assert_eq!(state.status.epoch, sim.node(1).epoch.get());
+    assert_eq!((state.acceptor.slots, state.acceptor.accepted), (0, 0), "learned instances are forgotten");
+}
+
+#[tokio::test]
+async fn a_write_answers_with_the_epoch_and_the_commit_index() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    let request = Request::put("/kv/x").body(Body::from("1")).unwrap();
+    let response = router(sim.node(0).clone()).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let headers = response.headers();
+    let epoch = sim.node(0).epoch.get();
+    assert_eq!(headers[EPOCH_HEADER], format!("{}.{}", epoch.round, epoch.node_id).as_str());
+    assert_eq!(headers[COMMIT_INDEX_HEADER], "1");
+}
+
+#[test]
+fn the_epoch_grows_when_another_node_takes_over() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+        if sim.put(0, "a", "1").await.is_error() {
+            return Err(String::from("the first write failed"));
+        }
+        let before = sim.node(2).epoch.get();
+
+        if sim.put(1, "b", "1").await.is_error() {
+            return Err(String::from("the second write failed"));
+        }
+        sim.settle().await;
+        let after = sim.node(2).epoch.get();
+        if after <= before || after.node_id != 2 {
+            return Err(format!("node 2 went from epoch {:?} to {:?}", before, after));
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn wait_answers_once_the_replica_applied_the_instance() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    let writes = async {
+        for key in ["a", "b"] {
+            assert_eq!(sim.put(0, key, "1").await.status, StatusCode::OK);
+        }
+    };
+    let (reply, ()) = tokio::join!(sim.get(2, "/wait?index=2&timeout=5000"), writes);
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    let status: Status = reply.json().unwrap();
+    assert!(status.commit_index >= 2, "{:?}", status);
+    assert!(sim.node(2).kv.lock().await.get("b").is_some());
+
+    let reply = sim.get(2, "/wait?index=1").await;
+    assert_eq!(reply.status, StatusCode::OK, "instance 1 is long applied");
+}
+
+#[tokio::test]
+async fn wait_gives_up_after_its_timeout() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    let reply = sim.get(1, "/wait?index=1&timeout=50").await;
+    assert_eq!(reply.status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(reply.body, "Node 2 didn't apply instance 1 within 50 ms!");
+}
@@ -0,0 +1,47 @@
+use std::{sync::Arc, time::Duration};
+use paxos_from_scratch::step::{Pending, Stepper};
+
+fn prepare(instance: u64) -> Pending {
+    Pending::prepare(instance, &instance.to_string())
+}
+
+async fn queue(stepper: &Arc<Stepper>, instance: u64) -> tokio::task::JoinHandle<()> {
+    let waiter = stepper.clone();
+    let handle = tokio::spawn(async move { waiter.wait(prepare(instance)).await });
+    while !stepper.queued().contains(&prepare(instance)) {
+        tokio::task::yield_now().await;
+    }
+    handle
+}
+
+#[tokio::test]
+async fn each_step_releases_the_phase_waiting_longest() {
+    let stepper = Arc::new(Stepper::default());
+    let first = queue(&stepper, 1).await;
+    let second = queue(&stepper, 2).await;
+    assert_eq!(stepper.pending(), Some(prepare(1)));
+
+    assert_eq!(stepper.step(), Some(prepare(1)));
+    tokio::time::timeout(Duration::from_secs(1), first).await.unwrap().unwrap();
+    assert!(!second.is_finished());
+    assert_eq!(stepper.pending(), Some(prepare(2)));
+
+    assert_eq!(stepper.step(), Some(prepare(2)));
+    tokio::time::timeout(Duration::from_secs(1), second).await.unwrap().unwrap();
+    assert_eq!(stepper.step(), None);
+}
+
+#[tokio::test]
+async fn a_phase_given_up_leaves_the_others_waiting() {
+    let stepper = Arc::new(Stepper::default());
+    let first = queue(&stepper, 1).await;
+    let second = queue(&stepper, 2).await;
+
+    second.abort();
+    let _ = second.await;
+    assert_eq!(stepper.queued(), vec![prepare(1)]);
+
+    assert_eq!(stepper.step(), Some(prepare(1)));
+    tokio::time::timeout(Duration::from_secs(1), first).await.unwrap().unwrap();
+    assert_eq!(stepper.pending(), None);
+}
@@ -0,0 +1,366 @@
+use std::{fs::OpenOptions, io::Write, path::PathBuf, sync::Arc};
+use axum::{body::Body, http::{Request, StatusCode}};
+use tower::ServiceExt;
+use paxos_from_scratch::{
+    Ballot, ProposalId,
+    acceptor::RangePromise,
+    mmap::Mmap,
+    proposer::RESERVE,
+    router, shutdown,
+    sim::{Sim, SimConfig},
+    storage::{self, Backup, DataDir, FORMAT, Record, SEGMENT_BYTES, Storage},
+    trace::Snapshot,
+};
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("paxos-storage-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn ballot(instance: u64, round: u64, value: &str) -> Ballot {
+    Ballot { instance, id: ProposalId { round, node_id: 2 }, value: Some(value.to_string()) }
+}
+
+#[test]
+fn a_reopened_data_dir_keeps_promises_and_learned_values() {
+    let dir = data_dir("reopen");
+    let (storage, recovered) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    assert_eq!(recovered, Snapshot::default());
+
+    storage.append(Record::Learned { instance: 1, value: String::from(r#"{"op":"put","key":"k","value":"v"}"#) }).unwrap();
+    storage.append(Record::Promised { instance: 2, id: ProposalId { round: 3, node_id: 2 } }).unwrap();
+    storage.append(Record::Accepted { ballot: ballot(2, 3, "b") }).unwrap();
+    storage.append(Record::Promised { instance: 3, id: ProposalId { round: 4, node_id: 2 } }).unwrap();
+    drop(storage);
+
+    let (_, recovered) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    assert_eq!(recovered.ledger.get(&1).map(String::as_str), Some(r#"{"op":"put","key":"k","value":"v"}"#));
+    assert_eq!(recovered.kv.get("k").map(String::as_str), Some("v"));
+    assert_eq!(recovered.acceptor.slots[&2].accepted_proposal, Some(ballot(2, 3, "b")));
+    assert_eq!(recovered.acceptor.slots[&3].last_ballot_number, ProposalId { round: 4, node_id: 2 });
+
+    assert!(Storage::open(2, &dir, SEGMENT_BYTES).is_err(), "another node must not pick up the directory");
+}
+
+#[test]
+fn a_reopened_data_dir_keeps_the_rounds_reserved_and_the_highest_promise() {
+    let dir = data_dir("rounds");
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    storage.append(Record::Reserved { round: 1000 }).unwrap();
+    storage.append(Record::Promised { instance: 1, id: ProposalId { round: 5000, node_id: 2 } }).unwrap();
+    storage.append(Record::Learned { instance: 1, value: String::from("a") }).unwrap();
+    drop(storage);
+
+    let (storage, recovered) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    assert!(recovered.acceptor.slots.is_empty());
+    assert_eq!((recovered.reserved, recovered.promised), (1000, ProposalId { round: 5000, node_id: 2 }));
+
+    storage.snapshot(storage.position(), recovered.clone()).unwrap();
+    drop(storage);
+    let (_, snapshotted) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    assert_eq!((snapshotted.reserved, snapshotted.promised), (recovered.reserved, recovered.promised), "a snapshot must keep them");
+}
+
+#[tokio::test]
+async fn a_restarted_node_never_reuses_a_ballot() {
+    let dir = data_dir("ballots");
+    let put = |state| async move {
+        let response = router(state).oneshot(Request::put("/kv/k").body(Body::from("v")).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    };
+
+    let sim = Sim::new(0, SimConfig { nodes: 1, ..SimConfig::default() });
+    let mut state = sim.node(0).clone();
+    state.storage = Some(Arc::new(Storage::open(1, &dir, SEGMENT_BYTES).unwrap().0));
+    put(state.clone()).await;
+    let before = state.proposer.round();
+    assert!(before >= 1);
+    drop((state, sim));
+
+    let sim = Sim::new(0, SimConfig { nodes: 1, ..SimConfig::default() });
+    let mut state = sim.node(0).clone();
+    let (restarted, recovered) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    assert_eq!(recovered.reserved, RESERVE);
+    state.storage = Some(Arc::new(restarted));
+    storage::restore(&state, recovered).await;
+    put(state.clone()).await;
+    assert!(state.proposer.round() > RESERVE, "round {} may have been used before the restart", state.proposer.round());
+}
+
+#[tokio::test]
+async fn shutting_down_syncs_the_log() {
+    let dir = data_dir("shutdown");
+    let sim = Sim::new(0, SimConfig { nodes: 1, ..SimConfig::default() });
+    let mut state = sim.node(0).clone();
+    let storage = Arc::new(Storage::open(1, &dir, SEGMENT_BYTES).unwrap().0);
+    state.storage = Some(storage.clone());
+
+    storage.write(Record::Learned { instance: 1, value: String::from("v") }).unwrap();
+    let syncs = storage.syncs();
+    shutdown::run(state).await;
+    assert_eq!(storage.syncs(), syncs + 1);
+}
+
+#[test]
+fn a_range_promised_ahead_holds_after_a_restart() {
+    let dir = data_dir("range");
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    let range = RangePromise { from: 5, to: 105, id: ProposalId { round: 7, node_id: 2 } };
+    storage.append(Record::PromisedRange { range }).unwrap();
+    drop(storage);
+
+    let (_, recovered) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    let mut acceptor = recovered.acceptor;
+    assert_eq!(acceptor.promised(50), range.id);
+    assert_eq!(acceptor.promised(105), ProposalId::default());
+    assert_eq!(acceptor.accept(&ballot(60, 6, "old")), Err(range.id), "an older proposal anywhere in the range is refused");
+    assert!(acceptor.accept(&ballot(200, 6, "after")).is_ok());
+}
+
+#[test]
+fn a_snapshot_covers_the_log_before_it() {
+    let dir = data_dir("snapshot");
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+
+    storage.append(Record::Learned { instance: 1, value: String::from("a") }).unwrap();
+    let mut snapshot = Snapshot::default();
+    snapshot.ledger.insert(1, String::from("a"));
+    let meta = storage.snapshot(storage.position(), snapshot).unwrap();
+    assert_eq!(meta.lsn, 1);
+
+    storage.append(Record::Learned { instance: 2, value: String::from("b") }).unwrap();
+    drop(storage);
+
+    let data = DataDir::read(&dir).unwrap();
+    assert_eq!(data.wal.iter().map(|entry| entry.lsn).collect::<Vec<_>>(), vec![2]);
+    assert_eq!(data.next_lsn(), 3);
+    assert_eq!(data.recover().ledger.len(), 2);
+}
+
+#[test]
+fn a_snapshot_older_than_the_last_is_left_out() {
+    let dir = data_dir("older");
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    for instance in 1..=2 {
+        storage.append(Record::Learned { instance, value: String::from("a") }).unwrap();
+    }
+
+    let mut newer = Snapshot::default();
+    newer.ledger.extend([(1, String::from("a")), (2, String::from("a"))]);
+    assert_eq!(storage.snapshot(2, newer).unwrap().lsn, 2);
+    // Copied before the one above, and written after it.
+    assert_eq!(storage.snapshot(1, Snapshot::default()).unwrap().lsn, 2);
+    drop(storage);
+    assert_eq!(DataDir::read(&dir).unwrap().recover().ledger.len(), 2);
+}
+
+#[tokio::test]
+async fn a_node_snapshots_what_it_applied_off_the_runtime() {
+    let dir = data_dir("node");
+    let sim = Sim::new(0, SimConfig { nodes: 1, ..SimConfig::default() });
+    let mut state = sim.node(0).clone();
+    state.storage = Some(Arc::new(Storage::open(1, &dir, SEGMENT_BYTES).unwrap().0));
+    let response = router(state.clone()).oneshot(Request::put("/kv/k").body(Body::from("v")).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let meta = storage::snapshot(&state).await.unwrap().unwrap();
+    assert_eq!(meta.lsn, state.storage.as_ref().unwrap().position());
+    drop((state, sim));
+    let (_, recovered) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    assert_eq!(recovered.kv.get("k").map(String::as_str), Some("v"));
+}
+
+#[test]
+fn an_entry_cut_short_is_dropped() {
+    let dir = data_dir("torn");
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    storage.append(Record::Learned { instance: 1, value: String::from("a") }).unwrap();
+    drop(storage);
+
+    let mut wal = OpenOptions::new().append(true).open(dir.join("wal").join(format!("{:020}.jsonl", 1))).unwrap();
+    wal.write_all(br#"{"lsn":2,"type":"lea"#).unwrap();
+    assert!(DataDir::read(&dir).unwrap().torn);
+
+    let (storage, recovered) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    assert_eq!(recovered.ledger.len(), 1);
+    assert_eq!(storage.append(Record::Learned { instance: 2, value: String::from("b") }).unwrap(), 2);
+    drop(storage);
+
+    let data = DataDir::read(&dir).unwrap();
+    assert!(!data.torn);
+    assert_eq!(data.recover().ledger.len(), 2);
+}
+
+#[test]
+fn a_backup_restores_into_an_empty_directory_only() {
+    let dir = data_dir("backup");
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    storage.append(Record::Learned { instance: 1, value: String::from("a") }).unwrap();
+    let mut snapshot = Snapshot::default();
+    snapshot.ledger.insert(1, String::from("a"));
+    storage.snapshot(storage.position(), snapshot).unwrap();
+    storage.append(Record::Accepted { ballot: ballot(2, 1, "b") }).unwrap();
+    drop(storage);
+
+    let backup = Backup::take(&dir).unwrap();
+    assert_eq!((backup.node, backup.wal.len()), (1, 1));
+    assert!(backup.seed(&dir).is_err(), "restoring over a node must fail");
+
+    let restored = data_dir("restored");
+    backup.seed(&restored).unwrap();
+    let (_, recovered) = Storage::open(1, &restored, SEGMENT_BYTES).unwrap();
+    assert_eq!(recovered, DataDir::read(&dir).unwrap().recover());
+
+    let newer = Backup { format: FORMAT + 1, ..backup };
+    assert!(newer.seed(&data_dir("newer")).is_err());
+}
+
+#[test]
+fn the_log_since_the_last_snapshot_is_counted_across_restarts() {
+    let dir = data_dir("since");
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    assert_eq!((storage.entries_since_snapshot(), storage.last_snapshot()), (0, None));
+
+    for instance in 1..=3 {
+        storage.append(Record::Learned { instance, value: String::from("a") }).unwrap();
+    }
+    assert_eq!(storage.entries_since_snapshot(), 3);
+
+    storage.snapshot(storage.position(), Snapshot::default()).unwrap();
+    storage.append(Record::Learned { instance: 4, value: String::from("a") }).unwrap();
+    drop(storage);
+
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    assert_eq!(storage.last_snapshot().map(|meta| meta.lsn), Some(3));
+    assert_eq!(storage.entries_since_snapshot(), 1);
+}
+
+#[test]
+fn segments_a_snapshot_covers_are_deleted() {
+    let dir = data_dir("segments");
+    // Every entry gets a segment of its own.
+    let (storage, _) = Storage::open(1, &dir, 1).unwrap();
+    for instance in 1..=4 {
+        storage.append(Record::Learned { instance, value: String::from("a") }).unwrap();
+    }
+    assert_eq!(storage.segments(), 4);
+
+    let mut snapshot = Snapshot::default();
+    for instance in 1..=4 {
+        snapshot.ledger.insert(instance, String::from("a"));
+    }
+    storage.snapshot(storage.position(), snapshot).unwrap();
+    assert_eq!(storage.segments(), 1);
+    storage.append(Record::Learned { instance: 5, value: String::from("b") }).unwrap();
+    drop(storage);
+
+    let data = DataDir::read(&dir).unwrap();
+    assert_eq!(data.segments.iter().map(|segment| segment.first_lsn).collect::<Vec<_>>(), vec![4, 5]);
+    assert_eq!(data.recover().ledger.len(), 5);
+}
+
+#[test]
+fn a_log_from_before_segments_is_moved_into_the_first_one() {
+    let dir = data_dir("legacy");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("node.json"), r#"{"id":1,"format":1,"created":0}"#).unwrap();
+    std::fs::write(dir.join("wal.jsonl"), "{\"lsn\":1,\"type\":\"learned\",\"instance\":1,\"value\":\"a\"}\n").unwrap();
+    assert_eq!(DataDir::read(&dir).unwrap().recover().ledger.len(), 1);
+
+    let (storage, recovered) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    assert_eq!(recovered.ledger.len(), 1);
+    assert_eq!(storage.append(Record::Learned { instance: 2, value: String::from("b") }).unwrap(), 2);
+    drop(storage);
+
+    let data = DataDir::read(&dir).unwrap();
+    assert!(!dir.join("wal.jsonl").exists());
+    assert_eq!(data.identity.as_ref().map(|identity| identity.format), Some(FORMAT));
+    assert_eq!(data.recover().ledger.len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn entries_written_together_share_a_sync() {
+    let dir = data_dir("group");
+    let (storage, _) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    let storage = std::sync::Arc::new(storage);
+
+    let writes = (1..=20).map(|instance| {
+        let storage = storage.clone();
+        tokio::spawn(async move {
+            let lsn = storage.write(Record::Learned { instance, value: instance.to_string() })?;
+            storage.sync(lsn, std::time::Duration::from_millis(5)).await
+        })
+    });
+    for write in futures::future::join_all(writes).await {
+        write.unwrap().unwrap();
+    }
+    assert!(storage.syncs() < 20, "{} syncs for 20 entries", storage.syncs());
+
+    // Already on disk, so no sync needed.
+    let syncs = storage.syncs();
+    storage.sync(1, std::time::Duration::ZERO).await.unwrap();
+    assert_eq!(storage.syncs(), syncs);
+    drop(storage);
+
+    let (_, recovered) = Storage::open(1, &dir, SEGMENT_BYTES).unwrap();
+    assert_eq!(recovered.ledger.len(), 20);
+}
+
+#[tokio::test]
+async fn entries_queued_for_the_writer_reach_the_log() {
+    let dir = data_dir("writer");
+    let (storage, _) = Storage::open(1, &dir, 64).unwrap();
+    let storage = Arc::new(storage);
+
+    let mut lsn = 0;
+    for instance in 1..=20 {
+        lsn = storage.write(Record::Learned { instance, value: instance.to_string() }).unwrap();
+    }
+    storage.sync(lsn, std::time::Duration::ZERO).await.unwrap();
+    assert!(storage.segments() > 1);
+
+    // Entries never synced still reach the file once the storage closes.
+    storage.write(Record::Learned { instance: 21, value: String::from("21") }).unwrap();
+    drop(storage);
+    let (_, recovered) = Storage::open(1, &dir, 64).unwrap();
+    assert_eq!(recovered.ledger.len(), 21);
+}
+
+#[test]
+fn a_mapped_file_reads_like_the_file() {
+    let dir = data_dir("mmap");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("full"), b"{\"a\":1}").unwrap();
+    std::fs::write(dir.join("empty"), b"").unwrap();
+
+    assert_eq!(&*Mmap::open(&dir.join("full")).unwrap(), b"{\"a\":1}");
+    assert!(Mmap::open(&dir.join("empty")).unwrap().is_empty());
+    assert!(Mmap::open(&dir.join("missing")).is_err());
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[test]
+fn a_log_written_through_io_uring_reads_back() {
+    let dir = data_dir("uring");
+    let (storage, _) = Storage::open(1, &dir, 256).unwrap();
+    if !storage.uses_io_uring() {
+        println!("io_uring isn't available here, so this ran on the portable writer");
+    }
+
+    for instance in 1..=20 {
+        storage.append(Record::Learned { instance, value: instance.to_string() }).unwrap();
+    }
+    // Queued together, they go in one submission with a single sync.
+    let syncs = storage.syncs();
+    for instance in 21..=30 {
+        storage.write(Record::Learned { instance, value: instance.to_string() }).unwrap();
+    }
+    storage.append(Record::Learned { instance: 31, value: String::from("31") }).unwrap();
+    assert!(storage.syncs() - syncs <= 2, "{} syncs for one batch", storage.syncs() - syncs);
+    drop(storage);
+
+    let (_, recovered) = Storage::open(1, &dir, 256).unwrap();
+    assert_eq!(recovered.ledger.len(), 31);
+    assert_eq!(recovered.ledger[&20], "20");
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+use tokio::time::Instant;
+use paxos_from_scratch::{
+    shards,
+    sim::{self, Sim, SimConfig},
+    streams::{self, FILL_AFTER, Stream},
+};
+
+fn cluster(seed: u64, streams: u64) -> Sim {
+    Sim::new(seed, SimConfig { nodes: 3, streams, ..SimConfig::default() })
+}
+
+fn ballot(instance: u64, value: &str) -> String {
+    serde_json::json!({ "instance": instance, "id": { "round": 1, "node_id": 3 }, "value": value }).to_string()
+}
+
+/// The instance a write was stored at, from its reply.
+fn instance(body: &str) -> u64 {
+    body.trim_end_matches('!').rsplit(' ').next().unwrap().parse().unwrap()
+}
+
+/// Waits until every node has applied `expected`.
+async fn everywhere(sim: &Sim, expected: &[(String, &str)]) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            sim.settle().await;
+            let mut applied = true;
+            for index in 0..sim.size() {
+                let kv = sim.node(index).kv.lock().await;
+                applied &= expected.iter().all(|(key, value)| kv.get(key).map(String::as_str) == Some(*value));
+            }
+            if applied {
+                break;
+            }
+            tokio::time::sleep(streams::FILL_EVERY).await;
+        }
+    }).await.expect("every node applies every write");
+}
+
+#[test]
+fn a_stream_takes_every_nth_instance_it_has_not_learned() {
+    assert_eq!((1..=7).map(|instance| Stream::of(instance, 3)).collect::<Vec<_>>(), vec![0, 1, 2, 0, 1, 2, 0]);
+    assert_eq!(Stream::of(5, 1), 0);
+
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, 3);
+        let node = sim.node(0);
+        let next = |index| Stream { index, count: 3 }.next_instance(node);
+        if (next(0), next(1), next(2)) != (1, 2, 3) {
+            return Err(format!("a fresh log starts the streams at {:?}", (next(0), next(1), next(2))));
+        }
+        for instance in [1, 2, 4] {
+            sim.request(0, "/handle-learn", &ballot(instance, "")).await;
+        }
+        if (next(0), next(1), next(2)) != (7, 5, 3) {
+            return Err(format!("expected the streams to go on at 7, 5 and 3, got {:?}", (next(0), next(1), next(2))));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn a_hole_a_quiet_stream_left_is_filled_with_an_empty_value() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, 2);
+        if sim.request(0, "/handle-learn", &ballot(2, "after")).await.is_error() {
+            return Err(String::from("the learn was refused"));
+        }
+        let node = sim.node(0);
+        let now = Instant::now();
+        if !streams::fill(node, now).await.is_empty() {
+            return Err(String::from("the hole was filled before its time"));
+        }
+        if streams::fill(node, now + FILL_AFTER * 2).await != vec![1] {
+            return Err(String::from("the hole wasn't filled"));
+        }
+        node.applier.applied(2).await;
+        if node.ledger.get(1).as_deref() != Some("") {
+            return Err(format!("expected an empty value in instance 1, got {:?}", node.ledger.get(1)));
+        }
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn writes_to_unrelated_keys_are_proposed_side_by_side() {
+    let sim = cluster(0, 4);
+    for index in 0..sim.size() {
+        tokio::spawn(streams::run(sim.node(index).clone()));
+    }
+
+    let keys: Vec<String> = (0..8).map(|i| format!("key-{}", i)).collect();
+    let writes = keys.iter().enumerate().map(|(i, key)| sim.put(i % 2, key, "value"));
+    let mut stored = Vec::new();
+    for reply in futures::future::join_all(writes).await {
+        assert!(!reply.is_error(), "{}", reply.body);
+        stored.push(instance(&reply.body));
+    }
+    for (key, &instance) in keys.iter().zip(&stored) {
+        assert_eq!(Stream::of(instance, 4), shards::of(key, 4) as u64, "{} went to another stream", key);
+    }
+
+    everywhere(&sim, &keys.iter().map(|key| (key.clone(), "value")).collect::<Vec<_>>()).await;
+    sim.check_agreement().await.unwrap();
+}
+
+#[tokio::test]
+async fn writes_to_one_key_keep_their_order() {
+    let sim = cluster(0, 4);
+    for index in 0..sim.size() {
+        tokio::spawn(streams::run(sim.node(index).clone()));
+    }
+
+    let mut last = 0;
+    for i in 0..6 {
+        let reply = sim.put(i % 3, "counter", &i.to_string()).await;
+        assert!(!reply.is_error(), "{}", reply.body);
+        let stored = instance(&reply.body);
+        assert!(stored > last, "write {} went to instance {}, not above {}", i, stored, last);
+        assert_eq!(Stream::of(stored, 4), shards::of("counter", 4) as u64);
+        last = stored;
+        // The write returns once its node applied it, holes below and all.
+        assert_eq!(sim.node(i % 3).kv.lock().await.get("counter"), Some(&i.to_string()));
+    }
+    everywhere(&sim, &[(String::from("counter"), "5")]).await;
+}
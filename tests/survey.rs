@@ -0,0 +1,58 @@
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use paxos_from_scratch::{
+    router,
+    client::Client,
+    sim::{Sim, SimConfig},
+    survey,
+};
+
+/// Serves every node of `sim` over HTTP, and has the first one know the
+/// others at the addresses they are served at.
+async fn serve(sim: &Sim) -> Client {
+    let mut addrs = Vec::new();
+    for index in 0..sim.size() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        addrs.push(listener.local_addr().unwrap());
+        let app = router(sim.node(index).clone()).into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    }
+    sim.node(0).nodes.update(|nodes| {
+        for node in nodes.iter_mut() {
+            node.addr = addrs[node.id as usize - 1];
+        }
+    });
+    Client::new(&addrs[0].to_string())
+}
+
+#[tokio::test]
+async fn cluster_status_shows_who_lags_and_who_diverged() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    for key in ["a", "b"] {
+        assert!(!sim.put(0, key, "1").await.is_error());
+    }
+    sim.settle().await;
+    sim.partition(&[&[0, 1], &[2]]);
+    assert!(!sim.put(0, "c", "1").await.is_error());
+    sim.settle().await;
+    let client = serve(&sim).await;
+
+    let status = survey::run(&client).await.unwrap();
+    assert_eq!(status.members.iter().map(|member| member.id).collect::<Vec<_>>(), [1, 2, 3]);
+    assert_eq!(status.at, 2, "node 3 missed the last write");
+    assert!(status.issues(&status.members[0]).is_empty());
+    assert_eq!(status.issues(&status.members[2]), ["1 behind"]);
+    assert!(status.is_healthy());
+
+    sim.node(1).ledger.write().await.overwrite(1, String::from("forged"), 2);
+    let status = survey::run(&client).await.unwrap();
+    assert_eq!(status.issues(&status.members[1]), ["diverged by instance 2"]);
+    assert!(!status.is_healthy());
+    assert!(status.to_string().contains("diverged by instance 2"), "{}", status);
+}
+
+#[test]
+fn a_node_on_every_interface_is_reached_where_the_first_one_was() {
+    assert_eq!(survey::url("0.0.0.0:3001".parse().unwrap(), "db1"), "http://db1:3001");
+    assert_eq!(survey::url("10.0.0.2:3001".parse().unwrap(), "db1"), "http://10.0.0.2:3001");
+}
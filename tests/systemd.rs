@@ -0,0 +1,28 @@
+#![cfg(unix)]
+
+use std::{os::unix::net::UnixDatagram, time::Duration};
+use paxos_from_scratch::systemd;
+
+// One test, since it sets process-wide variables.
+#[test]
+fn notifications_reach_the_socket_systemd_gives() {
+    let path = std::env::temp_dir().join(format!("paxos-notify-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+    std::env::set_var("NOTIFY_SOCKET", &path);
+    systemd::notify("READY=1");
+    let mut buf = [0; 64];
+    let len = socket.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"READY=1");
+
+    std::env::set_var("WATCHDOG_USEC", "3000000");
+    assert_eq!(systemd::watchdog_interval(), Some(Duration::from_secs(3)));
+    std::env::set_var("WATCHDOG_PID", "1");
+    assert_eq!(systemd::watchdog_interval(), None, "the watchdog is meant for another process");
+    std::env::set_var("WATCHDOG_PID", std::process::id().to_string());
+    assert_eq!(systemd::watchdog_interval(), Some(Duration::from_secs(3)));
+
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+use tokio::time::Instant;
+use paxos_from_scratch::{
+    sim::{self, Sim, SimConfig},
+    takeover,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn cluster(seed: u64, timeout: Duration) -> Sim {
+    let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+    for index in 0..sim.size() {
+        sim.node(index).settings.write().unwrap().stuck_instance_timeout = timeout;
+    }
+    sim
+}
+
+fn ballot(instance: u64, round: u64, value: &str) -> String {
+    serde_json::json!({ "instance": instance, "id": { "round": round, "node_id": 3 }, "value": value }).to_string()
+}
+
+#[test]
+fn a_value_its_proposer_left_behind_is_decided_by_a_voter() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, TIMEOUT);
+        // Node 3's proposer got its value chosen, and died before the learn.
+        for index in [1, 2] {
+            if sim.request(index, "/handle-accept", &ballot(1, 1, "orphan")).await.is_error() {
+                return Err(String::from("the accept was refused"));
+            }
+        }
+        let node = sim.node(1);
+        let now = Instant::now();
+        if !takeover::check(node, now).await.is_empty() {
+            return Err(String::from("the instance was taken over before its timeout"));
+        }
+
+        let decided = takeover::check(node, now + TIMEOUT * 2).await;
+        if decided != vec![1] {
+            return Err(format!("expected instance 1 taken over, got {:?}", decided));
+        }
+        sim.settle().await;
+        for index in 0..sim.size() {
+            if sim.node(index).ledger.get(1).as_deref() != Some("orphan") {
+                return Err(format!("node {} learned {:?}, not the chosen value", index, sim.node(index).ledger.get(1)));
+            }
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn a_gap_no_one_accepted_anything_in_is_filled_with_an_empty_value() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, TIMEOUT);
+        if sim.request(0, "/handle-learn", &ballot(2, 1, "after")).await.is_error() {
+            return Err(String::from("the learn was refused"));
+        }
+        let node = sim.node(0);
+        let now = Instant::now();
+        takeover::check(node, now).await;
+        if takeover::check(node, now + TIMEOUT * 2).await != vec![1] {
+            return Err(String::from("the gap wasn't taken over"));
+        }
+        node.applier.applied(2).await;
+        if node.ledger.get(1).as_deref() != Some("") || node.takeovers.taken() != 1 {
+            return Err(format!("expected an empty value in instance 1, got {:?}", node.ledger.get(1)));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn voters_later_in_the_list_wait_longer_and_a_zero_timeout_never_takes_over() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = cluster(seed, TIMEOUT);
+        for index in 0..sim.size() {
+            sim.request(index, "/handle-accept", &ballot(1, 1, "orphan")).await;
+        }
+        let now = Instant::now();
+        for index in 0..sim.size() {
+            takeover::check(sim.node(index), now).await;
+        }
+        // Past the first voter's patience, short of the others'.
+        let later = now + TIMEOUT + TIMEOUT / 6;
+        let mut taken = Vec::new();
+        for index in (0..sim.size()).rev() {
+            if !takeover::check(sim.node(index), later).await.is_empty() {
+                taken.push(index);
+            }
+        }
+        if taken != vec![0] {
+            return Err(format!("expected only the first voter to take over, got {:?}", taken));
+        }
+
+        let quiet = cluster(seed, Duration::ZERO);
+        quiet.request(0, "/handle-accept", &ballot(1, 1, "orphan")).await;
+        takeover::check(quiet.node(0), now).await;
+        if !takeover::check(quiet.node(0), now + TIMEOUT * 100).await.is_empty() {
+            return Err(String::from("a zero timeout took an instance over"));
+        }
+        Ok(())
+    });
+}
@@ -0,0 +1,72 @@
+use paxos_from_scratch::{
+    AppState,
+    kv::{Command, Kv},
+    topic::{self, Page, Published, RETAIN},
+};
+
+mod common;
+use common::{cluster, send};
+
+async fn publish(state: &AppState, topic: &str, message: &str) -> Published {
+    let (status, body) = send(state, "POST", &format!("/topics/{}", topic), message).await;
+    serde_json::from_str(&body).unwrap_or_else(|_| panic!("{}: {}", status, body))
+}
+
+async fn read(state: &AppState, topic: &str, from: u64) -> Page {
+    let (status, body) = send(state, "GET", &format!("/topics/{}?from={}&timeout_ms=50", topic, from), "").await;
+    serde_json::from_str(&body).unwrap_or_else(|_| panic!("{}: {}", status, body))
+}
+
+#[tokio::test]
+async fn subscribers_read_only_their_topic_in_order() {
+    let sim = cluster(0, 3);
+    let publishes = [("orders", "o1", 0), ("audit", "a1", 0), ("orders", "o2", 1), ("orders", "o3", 2)];
+    for (index, (topic, message, offset)) in publishes.into_iter().enumerate() {
+        assert_eq!(publish(sim.node(index % sim.size()), topic, message).await.offset, offset);
+        sim.settle().await;
+    }
+
+    let page = read(sim.node(2), "orders", 1).await;
+    let messages: Vec<(u64, &str)> = page.messages.iter().map(|message| (message.offset, message.message.as_str())).collect();
+    assert_eq!(messages, [(1, "o2"), (2, "o3")]);
+    assert_eq!(page.next, 3);
+
+    let waiting = read(sim.node(2), "orders", page.next);
+    let (page, _) = tokio::join!(waiting, async {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        publish(sim.node(0), "audit", "a2").await
+    });
+    assert!(page.messages.is_empty(), "a publish to another topic woke a reader up with it");
+}
+
+#[test]
+fn a_topic_keeps_its_last_messages() {
+    let mut kv = Kv::default();
+    for i in 0..=RETAIN {
+        kv.apply(&Command::Publish { topic: String::from("t"), id: i.to_string(), message: format!("m{}", i) }.encode());
+    }
+    let page = topic::read(&kv, "t", 0, 2);
+    assert_eq!(page.oldest, 1);
+    assert_eq!(page.messages.iter().map(|message| message.offset).collect::<Vec<_>>(), [1, 2], "a reader that fell behind goes on from the oldest");
+    assert!(topic::get(&kv, "t", 0).is_none());
+}
+
+#[tokio::test]
+async fn a_subscriber_resumes_from_a_commit_index() {
+    let sim = cluster(0, 1);
+    publish(sim.node(0), "orders", "o1").await;
+    let since = sim.node(0).applier.index();
+    publish(sim.node(0), "orders", "o2").await;
+    publish(sim.node(0), "orders%2Fx", "other").await;
+    publish(sim.node(0), "orders", "o3").await;
+
+    let (status, body) = send(sim.node(0), "GET", &format!("/topics/orders?since={}&timeout_ms=50", since), "").await;
+    let page: Page = serde_json::from_str(&body).unwrap_or_else(|_| panic!("{}: {}", status, body));
+    let messages: Vec<(u64, &str)> = page.messages.iter().map(|message| (message.offset, message.message.as_str())).collect();
+    assert_eq!(messages, [(1, "o2"), (2, "o3")]);
+    assert_eq!((page.next, page.index), (3, Some(sim.node(0).applier.index())));
+
+    let (status, body) = send(sim.node(0), "GET", &format!("/topics/orders?since={}&timeout_ms=50", page.index.unwrap()), "").await;
+    let page: Page = serde_json::from_str(&body).unwrap_or_else(|_| panic!("{}: {}", status, body));
+    assert!(page.messages.is_empty());
+}
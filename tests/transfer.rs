@@ -0,0 +1,68 @@
+use std::sync::atomic::Ordering;
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    Ballot, ProposalId,
+    sim::{Sim, SimConfig},
+    transfer,
+};
+
+/// Three nodes that wrote without node 3, which never heard of any of it.
+async fn cluster() -> Sim {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    sim.partition(&[&[0, 1]]);
+    for i in 0..5 {
+        let reply = sim.put(0, &format!("k{}", i), &format!("v{}", i)).await;
+        assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    }
+    sim.heal();
+    sim
+}
+
+async fn joined(sim: &Sim, index: usize) {
+    for _ in 0..10_000 {
+        if !sim.node(index).is_syncing() {
+            return;
+        }
+        tokio::task::yield_now().await;
+    }
+    panic!("node {} never finished taking in the cluster's state", index + 1);
+}
+
+#[tokio::test]
+async fn a_fresh_node_takes_in_the_cluster_state_before_it_votes() {
+    let sim = cluster().await;
+    let fresh = sim.node(2);
+    assert!(transfer::is_fresh(fresh).await);
+
+    transfer::join(fresh, sim.node(0).node.clone());
+    assert!(fresh.is_syncing(), "it doesn't vote from the start");
+    joined(&sim, 2).await;
+
+    assert_eq!(fresh.ledger.len(), 5);
+    assert_eq!(fresh.ledger.chained(), 5);
+    for i in 0..5 {
+        assert_eq!(sim.get(2, &format!("/kv/k{}", i)).await.body, format!("v{}", i));
+    }
+    assert!(!transfer::is_fresh(fresh).await);
+    sim.check_agreement().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_node_taking_in_state_turns_away_votes_and_forwards_writes() {
+    let sim = cluster().await;
+    sim.node(2).syncing.store(true, Ordering::SeqCst);
+
+    let ballot = Ballot { instance: 6, id: ProposalId { round: 9, node_id: 1 }, value: None };
+    let reply = sim.request(2, "/handle-prepare", &serde_json::to_string(&ballot).unwrap()).await;
+    assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE, "{}", reply.body);
+
+    let reply = sim.put(2, "x", "1").await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    assert_eq!(sim.node(2).proposer.round(), 0, "node 3 proposed while it doesn't vote");
+}
+
+#[tokio::test]
+async fn a_node_that_learned_anything_is_not_fresh() {
+    let sim = cluster().await;
+    assert!(!transfer::is_fresh(sim.node(1)).await);
+}
@@ -0,0 +1,145 @@
+use axum::http::StatusCode;
+use tokio::time::Instant;
+use paxos_from_scratch::{
+    intake,
+    kv::Command,
+    shards,
+    sim::{self, Sim, SimConfig},
+    txn::{self, Outcome, Recovery, Vote, RECOVER_AFTER},
+};
+
+fn sharded(seed: u64) -> Sim {
+    Sim::new(seed, SimConfig { nodes: 3, shards: 2, ..SimConfig::default() })
+}
+
+/// A key in each of the two shards.
+fn keys() -> (String, String) {
+    let key = |shard| (0..).map(|i| format!("k{}", i)).find(|key| shards::of(key, 2) == shard).unwrap();
+    (key(0), key(1))
+}
+
+fn put(key: &str, value: &str) -> Command {
+    Command::Put { key: key.to_string(), value: value.to_string() }
+}
+
+fn body(ops: &[Command]) -> String {
+    serde_json::json!({ "ops": ops }).to_string()
+}
+
+/// Leaves transaction `txn` prepared in the shards of `ops`, as a
+/// coordinator that died before deciding would.
+async fn prepare(sim: &Sim, txn: &str, ops: &[Command], groups: Vec<usize>) -> Result<(), String> {
+    for (index, op) in ops.iter().enumerate() {
+        let group = sim.node(0).groups.shard(groups[index]).unwrap();
+        let prepare = Command::Prepare { txn: txn.to_string(), ops: vec![op.clone()], groups: groups.clone() };
+        let instance = intake::submit(group, prepare.encode()).await?;
+        group.applier.applied(instance).await;
+    }
+    sim.settle().await;
+    Ok(())
+}
+
+/// What every node's shard holds for `key`.
+async fn everywhere(sim: &Sim, key: &str) -> Vec<Option<String>> {
+    sim.settle().await;
+    let mut values = Vec::new();
+    for index in 0..sim.size() {
+        let group = shards::route(sim.node(index), key);
+        values.push(group.kv.lock().await.get(key).cloned());
+    }
+    values
+}
+
+#[test]
+fn a_transaction_commits_writes_to_every_shard() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = sharded(seed);
+        let (a, b) = keys();
+        let reply = sim.request(1, "/txn", &body(&[put(&a, "1"), put(&b, "2")])).await;
+        let outcome: Outcome = reply.json()?;
+        if reply.status != StatusCode::OK || !outcome.committed {
+            return Err(format!("the transaction didn't commit: {}", reply.body));
+        }
+        if everywhere(&sim, &a).await != vec![Some(String::from("1")); 3] || everywhere(&sim, &b).await != vec![Some(String::from("2")); 3] {
+            return Err(String::from("not every node applied both writes"));
+        }
+        let group = shards::route(sim.node(0), &a);
+        if txn::record(&*group.kv.lock().await, &outcome.txn).map(|record| record.vote) != Some(Vote::Committed) {
+            return Err(String::from("the shard didn't record the commit"));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn a_transaction_touching_a_locked_key_writes_nothing() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = sharded(seed);
+        let (a, b) = keys();
+        prepare(&sim, "9-held", &[put(&a, "held")], vec![0, 1]).await?;
+
+        let reply = sim.request(0, "/txn", &body(&[put(&a, "1"), put(&b, "2")])).await;
+        if reply.status != StatusCode::CONFLICT || reply.json::<Outcome>()?.committed {
+            return Err(format!("expected the transaction aborted, got {}: {}", reply.status, reply.body));
+        }
+        if everywhere(&sim, &b).await.iter().any(Option::is_some) {
+            return Err(String::from("the other shard applied its write anyway"));
+        }
+
+        let reserved = sim.put(0, &format!("{}9-held", txn::PREFIX.replace('/', "%2F")), "forged").await;
+        if reserved.status != StatusCode::BAD_REQUEST {
+            return Err(format!("a vote was writable over /kv: {}", reserved.status));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn a_transaction_its_coordinator_left_is_aborted_unless_every_shard_prepared() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = sharded(seed);
+        let (a, b) = keys();
+        // Only the first shard heard of it before the coordinator died.
+        prepare(&sim, "9-half", &[put(&a, "half")], vec![0, 1]).await?;
+
+        let recovery = Recovery::default();
+        let node = sim.node(2);
+        let now = Instant::now();
+        if !recovery.check(node, now).await.is_empty() {
+            return Err(String::from("the transaction was finished before its time"));
+        }
+        if recovery.check(node, now + RECOVER_AFTER * 2).await != vec![String::from("9-half")] {
+            return Err(String::from("the transaction wasn't finished"));
+        }
+        if everywhere(&sim, &a).await.iter().any(Option::is_some) {
+            return Err(String::from("an aborted write was applied"));
+        }
+
+        // The lock is gone with it.
+        let reply = sim.request(0, "/txn", &body(&[put(&a, "1"), put(&b, "2")])).await;
+        if reply.status != StatusCode::OK {
+            return Err(format!("the key stayed locked: {}", reply.body));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn a_transaction_every_shard_prepared_is_committed_by_recovery() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = sharded(seed);
+        let (a, b) = keys();
+        prepare(&sim, "9-whole", &[put(&a, "1"), put(&b, "2")], vec![0, 1]).await?;
+
+        let recovery = Recovery::default();
+        let now = Instant::now();
+        recovery.check(sim.node(1), now).await;
+        if recovery.check(sim.node(1), now + RECOVER_AFTER * 2).await != vec![String::from("9-whole")] {
+            return Err(String::from("the transaction wasn't finished"));
+        }
+        if everywhere(&sim, &a).await != vec![Some(String::from("1")); 3] || everywhere(&sim, &b).await != vec![Some(String::from("2")); 3] {
+            return Err(String::from("recovery didn't commit both writes"));
+        }
+        Ok(())
+    });
+}
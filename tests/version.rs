@@ -0,0 +1,59 @@
+use paxos_from_scratch::{
+    Ballot,
+    sim::{self, Sim, SimConfig},
+    version::{MIN_PROTOCOL, PROTOCOL, Versions},
+    wire::{Accepted, Join, Promise},
+};
+
+#[test]
+fn peers_talk_the_older_of_their_protocols() {
+    let versions = Versions::default();
+    assert_eq!(versions.of(2), MIN_PROTOCOL, "a peer that never said is taken to be the oldest");
+
+    assert_eq!(versions.negotiate(2, Some(PROTOCOL + 1)), Ok(PROTOCOL));
+    assert_eq!(versions.negotiate(3, None), Ok(MIN_PROTOCOL));
+    assert!(versions.negotiate(4, Some(MIN_PROTOCOL - 1)).is_err());
+
+    assert_eq!(versions.of(2), PROTOCOL);
+    assert_eq!(versions.common([2]), PROTOCOL);
+    assert_eq!(versions.common([2, 3]), MIN_PROTOCOL);
+}
+
+#[test]
+fn messages_from_other_versions_still_parse() {
+    // From a node that predates protocol versions.
+    let ping: Join = serde_json::from_str(r#"{"id":"2","addr":"127.0.0.1:3001"}"#).unwrap();
+    assert_eq!((ping.protocol, ping.node().weight), (None, 1));
+
+    let prepare: Promise = serde_json::from_str(r#"{"error":null,"value":null}"#).unwrap();
+    assert_eq!((prepare.promised, prepare.decided), (None, None));
+    let accept: Accepted = serde_json::from_str(r#"{"error":null,"value":null}"#).unwrap();
+    assert_eq!(accept.promised, None);
+
+    // From a newer node, with fields this one doesn't know.
+    let ballot: Ballot = serde_json::from_str(r#"{"instance":1,"id":{"round":1,"node_id":2},"value":"a","epoch":3}"#).unwrap();
+    assert_eq!(ballot.instance, 1);
+    let ping: Join = serde_json::from_str(r#"{"id":"2","addr":"127.0.0.1:3001","protocol":"9","zone":"b"}"#).unwrap();
+    assert_eq!(ping.protocol, Some(9));
+}
+
+#[test]
+fn writes_are_only_forwarded_to_peers_that_have_forward() {
+    sim::run(sim::DEFAULT_SEEDS, |seed| async move {
+        let sim = Sim::new(seed, SimConfig { nodes: 3, ..SimConfig::default() });
+        for peer in [2, 3] {
+            sim.node(0).versions.negotiate(peer, Some(MIN_PROTOCOL)).unwrap();
+        }
+
+        sim.request(0, "/admin/read-only", r#"{"mode": "forward"}"#).await;
+        if !sim.propose(0, "old peers").await.is_error() {
+            return Err(String::from("a write was forwarded to a peer too old for it"));
+        }
+
+        sim.node(0).versions.negotiate(3, Some(PROTOCOL)).unwrap();
+        if sim.propose(0, "upgraded").await.is_error() {
+            return Err(String::from("the upgraded peer didn't take the write"));
+        }
+        sim.check_agreement().await
+    });
+}
@@ -0,0 +1,51 @@
+use paxos_from_scratch::{
+    kv::Change,
+    sim::{Sim, SimConfig},
+    watch::{Page, Watches, CAPACITY},
+};
+
+fn change(key: &str, after: &str) -> Change {
+    Change { key: key.to_string(), before: None, after: Some(after.to_string()) }
+}
+
+async fn watch(sim: &Sim, index: usize, since: u64) -> Page {
+    let reply = sim.get(index, &format!("/watch?prefix=a&since={}&timeout_ms=2000", since)).await;
+    reply.json().unwrap_or_else(|e| panic!("{}: {}", e, reply.body))
+}
+
+#[tokio::test]
+async fn a_watch_replays_what_it_missed_then_waits() {
+    let sim = Sim::new(0, SimConfig { nodes: 3, ..SimConfig::default() });
+    for (key, value) in [("a1", "1"), ("b", "2"), ("a2", "3")] {
+        assert!(!sim.put(0, key, value).await.is_error());
+        sim.settle().await;
+    }
+
+    let page = watch(&sim, 1, 0).await;
+    let keys: Vec<(u64, &str)> = page.events.iter().map(|event| (event.instance, event.change.key.as_str())).collect();
+    assert_eq!(keys, [(1, "a1"), (3, "a2")]);
+    assert_eq!(page.index, 3);
+
+    let (page, _) = tokio::join!(watch(&sim, 1, page.index), async {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        sim.put(0, "b", "4").await;
+        sim.put(0, "a1", "5").await
+    });
+    assert_eq!(page.events.len(), 1, "the watch answers as soon as a key under its prefix changed");
+    assert_eq!((page.events[0].instance, page.events[0].change.before.as_deref()), (5, Some("1")));
+}
+
+#[test]
+fn a_watch_from_further_back_than_kept_is_gone() {
+    let watches = Watches::default();
+    for instance in 1..=CAPACITY as u64 + 1 {
+        watches.record(instance, &[change("a", &instance.to_string())]);
+    }
+    assert_eq!(watches.since(0, "a"), Err(1));
+    assert_eq!(watches.since(1, "a").unwrap().events.len(), CAPACITY);
+
+    // A snapshot put instances in place without the apply task.
+    watches.record(CAPACITY as u64 + 10, &[change("a", "x")]);
+    assert_eq!(watches.since(CAPACITY as u64 + 1, "a"), Err(CAPACITY as u64 + 9));
+    assert_eq!(watches.since(CAPACITY as u64 + 9, "a").unwrap().events.len(), 1);
+}
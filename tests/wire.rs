@@ -0,0 +1,49 @@
+use axum::http::StatusCode;
+use paxos_from_scratch::{
+    Node, ProposalId,
+    sim::{Sim, SimConfig},
+    wire::{Join, Nack, Promise},
+};
+
+#[test]
+fn a_join_goes_over_the_wire_as_strings() {
+    let join = Join {
+        id: 2,
+        addr: "127.0.0.1:3002".parse().unwrap(),
+        protocol: Some(8),
+        multicast: None,
+        public_key: None,
+        learner: true,
+        zone: None,
+        weight: 3,
+    };
+    let json = serde_json::to_string(&join).unwrap();
+    assert_eq!(json, r#"{"id":"2","addr":"127.0.0.1:3002","protocol":"8","learner":"true","weight":"3"}"#);
+    assert_eq!(serde_json::from_str::<Join>(&json).unwrap(), join);
+
+    let voter: Join = serde_json::from_str(r#"{"id":"3","addr":"127.0.0.1:3003","weight":"0"}"#).unwrap();
+    let Node { id, learner, weight, .. } = voter.node();
+    assert_eq!((id, learner, weight), (3, false, 1));
+    assert!(serde_json::from_str::<Join>(r#"{"id":"three","addr":"127.0.0.1:3003"}"#).is_err());
+}
+
+#[test]
+fn a_nack_keeps_the_ballot_to_beat() {
+    let promised = ProposalId { round: 4, node_id: 1 };
+    let promise = Promise::from(Nack::outbid("Too late!", promised));
+    assert_eq!((promise.error.as_deref(), promise.promised), (Some("Too late!"), Some(promised)));
+
+    let json = serde_json::to_string(&Promise::granted(None)).unwrap();
+    assert!(json.contains(r#""error":null"#), "older nodes expect `error` in every promise: {}", json);
+}
+
+#[tokio::test]
+async fn a_refused_join_answers_a_nack() {
+    let sim = Sim::new(0, SimConfig { nodes: 2, ..SimConfig::default() });
+    let reply = sim.request(0, "/ping", r#"{"id":"1","addr":"10.0.0.1:3000"}"#).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+    assert_eq!(reply.json::<Nack>().unwrap(), Nack::new("You can't connect in the same node!"));
+
+    let reply = sim.request(0, "/ping", r#"{"id":"2","addr":"10.0.0.2:3000"}"#).await;
+    assert_eq!(reply.json::<Nack>().unwrap().error, "You're already connected in this node!");
+}